@@ -0,0 +1,162 @@
+/* The C API of libneksisc, declared from src/capi.rs. Regenerate with cbindgen, see cbindgen.toml and docs/tools/c-api.md. */
+
+#ifndef NEKSIS_H
+#define NEKSIS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define NEKSIS_OK 0
+
+#define NEKSIS_ERROR -1
+
+#define NEKSIS_LIMIT_EXCEEDED -2
+
+typedef enum NeksisType {
+  NEKSIS_TYPE_NULL,
+  NEKSIS_TYPE_INT,
+  NEKSIS_TYPE_FLOAT,
+  NEKSIS_TYPE_BOOL,
+  NEKSIS_TYPE_STRING,
+} NeksisType;
+
+typedef struct NeksisEngine NeksisEngine;
+
+typedef union NeksisData {
+  int64_t int_value;
+  double float_value;
+  bool bool_value;
+  /* NUL-terminated UTF-8 */
+  const char *string_value;
+} NeksisData;
+
+typedef struct NeksisValue {
+  /* A `NeksisType` */
+  int tag;
+  NeksisData data;
+} NeksisValue;
+
+/*
+ Bounds on each later load or call. A field left at 0 sets no limit, or
+ the default depth for `max_call_depth`.
+ */
+typedef struct NeksisLimits {
+  uint64_t max_instructions;
+  size_t max_heap_bytes;
+  size_t max_call_depth;
+  uint64_t max_wall_time_ms;
+} NeksisLimits;
+
+/*
+ A native function registered with `neksis_register`. It stores its result
+ in `*result` and returns `NEKSIS_OK`, or returns `NEKSIS_ERROR` with an
+ optional message string in `*result`; either way, strings it returns are
+ copied before it is called again.
+ */
+typedef int (*NeksisCallback)(void *user_data, const NeksisValue *args, size_t argc, NeksisValue *result);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Create an engine, or return NULL if that fails. Free it with
+ `neksis_engine_free`.
+ */
+NeksisEngine *neksis_engine_new(void);
+
+/*
+ # Safety
+
+ `engine` must come from `neksis_engine_new` and not have been freed, or be NULL.
+ */
+void neksis_engine_free(NeksisEngine *engine);
+
+/*
+ Compile and run a NUL-terminated program after everything the engine has
+ run before.
+
+ # Safety
+
+ `engine` must be a live engine and `source` NULL or a NUL-terminated string.
+ */
+int neksis_load_source(NeksisEngine *engine, const char *source);
+
+/*
+ Run `length` bytes written by `neksis build --emit-bytecode`.
+
+ # Safety
+
+ `engine` must be a live engine and `data` NULL or readable for `length` bytes.
+ */
+int neksis_load_bytecode(NeksisEngine *engine, const uint8_t *data, size_t length);
+
+/*
+ Call the Neksis function `name` with `argc` arguments, storing its result
+ in `*result` unless `result` is NULL.
+
+ # Safety
+
+ `engine` must be a live engine, `name` a NUL-terminated string, `args`
+ readable for `argc` values and `result` NULL or writable.
+ */
+int neksis_call(NeksisEngine *engine,
+                const char *name,
+                const NeksisValue *args,
+                size_t argc,
+                NeksisValue *result);
+
+/*
+ Make `callback` callable from Neksis as `name(...)`. `user_data` is passed
+ back to it unchanged. An error it reports can be caught with try/catch.
+
+ # Safety
+
+ `engine` must be a live engine and `name` a NUL-terminated string.
+ `callback` must stay callable with `user_data` for as long as the engine lives.
+ */
+int neksis_register(NeksisEngine *engine,
+                    const char *name,
+                    NeksisCallback callback,
+                    void *user_data);
+
+/*
+ Let scripts use `permission` ("read", "write", "exit" or "ffi") on
+ `scope`, a path that includes everything under it. A NULL `scope` grants
+ it without limits. New engines have no permissions.
+
+ # Safety
+
+ `engine` must be a live engine, `permission` a NUL-terminated string and
+ `scope` NULL or a NUL-terminated string.
+ */
+int neksis_allow(NeksisEngine *engine, const char *permission, const char *scope);
+
+/*
+ Bound the instructions, heap, call depth and time of every later load and
+ call; each gets the whole budget to itself. A NULL `limits` removes them.
+
+ # Safety
+
+ `engine` must be a live engine and `limits` NULL or readable.
+ */
+int neksis_set_limits(NeksisEngine *engine, const NeksisLimits *limits);
+
+/*
+ The message of the latest call on `engine` that failed, or NULL if none
+ has. It stays valid until another call fails.
+
+ # Safety
+
+ `engine` must be a live engine or NULL.
+ */
+const char *neksis_last_error(const NeksisEngine *engine);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NEKSIS_H */
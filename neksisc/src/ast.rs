@@ -130,6 +130,8 @@ pub struct ReallocExpression {
 #[derive(Debug, Clone, PartialEq)]
 pub struct TryCatchExpression {
     pub try_block: Box<Expression>,
+    /// Receives the error message in the catch block, as in `catch e { ... }`
+    pub catch_variable: Option<String>,
     pub catch_block: Box<Expression>,
 }
 
@@ -250,12 +252,16 @@ pub enum Statement {
     // Add missing variant
     GenericFunction(GenericFunctionStatement),
     Class(ClassStatement),
+    Extern(ExternStatement),
+    PythonImport(PythonImportStatement),
     // Add missing variants for type inference and borrow checker
     LetStatement { name: String, value: Box<Expression>, var_type: Option<Type> },
     AssignmentStatement { name: String, value: Box<Expression> },
     FunctionStatement { name: String, parameters: Vec<Parameter>, return_type: Option<Type>, body: Box<Expression> },
     ReturnStatement { value: Option<Box<Expression>> },
     ExpressionStatement { expression: Box<Expression> },
+    // Line of the statement that follows, only emitted when the parser tracks lines
+    SourceLine(usize),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -635,6 +641,33 @@ pub struct LambdaExpression {
     pub body: Box<Expression>,
 } 
 
+/// `extern "C" lib "libm.so.6" { fn cos(x: Float) -> Float; }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternStatement {
+    pub library: String,
+    pub functions: Vec<ExternFunction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternFunction {
+    pub name: String,
+    pub signature: crate::ffi::FFISignature,
+}
+
+/// `import python "os.path" as path;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PythonImportStatement {
+    pub module: String,
+    pub alias: Option<String>,
+}
+
+impl PythonImportStatement {
+    /// The name the program uses for the module; as in Python, `import python "os.path"` binds `os`
+    pub fn binding(&self) -> &str {
+        self.alias.as_deref().unwrap_or_else(|| self.module.split('.').next().unwrap_or(&self.module))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ClassStatement {
     pub name: String,
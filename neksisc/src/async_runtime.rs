@@ -6,10 +6,33 @@ use crate::vm::VMValue;
 
 pub type TaskId = usize;
 
+/// A task result as it crosses back from a worker thread. VM arrays and
+/// objects are not thread-safe, so tasks can only produce scalar values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskValue {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Null,
+}
+
+impl From<TaskValue> for VMValue {
+    fn from(value: TaskValue) -> Self {
+        match value {
+            TaskValue::Int(i) => VMValue::Int(i),
+            TaskValue::Float(f) => VMValue::Float(f),
+            TaskValue::String(s) => VMValue::String(s),
+            TaskValue::Bool(b) => VMValue::Bool(b),
+            TaskValue::Null => VMValue::Null,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum AsyncMessage {
     SpawnTask(Task),
-    CompleteTask(TaskId, VMValue),
+    CompleteTask(TaskId, TaskValue),
     FailTask(TaskId, String),
     Shutdown,
 }
@@ -33,7 +56,7 @@ pub enum TaskPriority {
 
 pub struct AsyncRuntime {
     task_queue: Arc<Mutex<VecDeque<Task>>>,
-    completed_tasks: Arc<Mutex<std::collections::HashMap<TaskId, VMValue>>>,
+    completed_tasks: Arc<Mutex<std::collections::HashMap<TaskId, TaskValue>>>,
     failed_tasks: Arc<Mutex<std::collections::HashMap<TaskId, String>>>,
     worker_handles: Vec<thread::JoinHandle<()>>,
     #[allow(dead_code)]
@@ -85,7 +108,7 @@ impl AsyncRuntime {
     fn worker_loop(
         worker_id: usize,
         task_queue: Arc<Mutex<VecDeque<Task>>>,
-        completed_tasks: Arc<Mutex<std::collections::HashMap<TaskId, VMValue>>>,
+        completed_tasks: Arc<Mutex<std::collections::HashMap<TaskId, TaskValue>>>,
         failed_tasks: Arc<Mutex<std::collections::HashMap<TaskId, String>>>,
         is_running: Arc<Mutex<bool>>,
     ) {
@@ -122,28 +145,28 @@ impl AsyncRuntime {
         println!("Worker {} shutting down", worker_id);
     }
     
-    fn execute_task(task: &Task) -> Result<VMValue, String> {
+    fn execute_task(task: &Task) -> Result<TaskValue, String> {
         // For now, simulate different types of async work
         match task.name.as_str() {
             "fibonacci" => {
                 // CPU-intensive task
                 thread::sleep(Duration::from_millis(50)); // Simulate work
-                Ok(VMValue::Int(Self::fibonacci(20)))
+                Ok(TaskValue::Int(Self::fibonacci(20)))
             }
             "io_operation" => {
                 // I/O intensive task
                 thread::sleep(Duration::from_millis(100)); // Simulate I/O
-                Ok(VMValue::String("I/O completed".to_string()))
+                Ok(TaskValue::String("I/O completed".to_string()))
             }
             "network_request" => {
                 // Network operation
                 thread::sleep(Duration::from_millis(200)); // Simulate network delay
-                Ok(VMValue::String("Network response".to_string()))
+                Ok(TaskValue::String("Network response".to_string()))
             }
             _ => {
                 // Generic async task
                 thread::sleep(Duration::from_millis(25));
-                Ok(VMValue::String(format!("Task {} completed", task.name)))
+                Ok(TaskValue::String(format!("Task {} completed", task.name)))
             }
         }
     }
@@ -188,7 +211,7 @@ impl AsyncRuntime {
         loop {
             // Check if task completed successfully
             if let Some(result) = self.completed_tasks.lock().unwrap().remove(&task_id) {
-                return Ok(result);
+                return Ok(result.into());
             }
             
             // Check if task failed
@@ -203,7 +226,7 @@ impl AsyncRuntime {
     
     pub fn try_get_result(&self, task_id: TaskId) -> Option<Result<VMValue, String>> {
         if let Some(result) = self.completed_tasks.lock().unwrap().remove(&task_id) {
-            Some(Ok(result))
+            Some(Ok(result.into()))
         } else if let Some(error) = self.failed_tasks.lock().unwrap().remove(&task_id) {
             Some(Err(error))
         } else {
@@ -0,0 +1,337 @@
+// Benchmark Harness for Neksis
+//
+// Runs functions annotated with `@bench` with a warmup phase, picks an
+// iteration count per sample from the warmup timing, and reports mean,
+// median, standard deviation and outliers. Results are stored in a baseline
+// file so later runs can report regressions and improvements.
+
+use crate::error::CompilerError;
+use crate::permissions::Permissions;
+use crate::test_framework::{find_source_files, load_annotated_functions, LoadError};
+use crate::vm::{BytecodeInstruction, OutputCapture, VM};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub warmup: Duration,
+    pub measurement: Duration,
+    pub samples: usize,
+    pub filter: Option<String>,
+    /// Relative change of the median below which a result counts as noise
+    pub noise_threshold: f64,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            warmup: Duration::from_millis(500),
+            measurement: Duration::from_secs(2),
+            samples: 50,
+            filter: None,
+            noise_threshold: 0.05,
+        }
+    }
+}
+
+/// A single `@bench` function found during discovery
+#[derive(Debug, Clone)]
+pub struct BenchCase {
+    pub name: String,
+    pub file: PathBuf,
+    pub line: usize,
+    program: Arc<Vec<BytecodeInstruction>>,
+}
+
+impl BenchCase {
+    pub fn qualified_name(&self) -> String {
+        format!("{}::{}", self.file.display(), self.name)
+    }
+}
+
+/// Summary statistics over per-iteration times, in nanoseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Statistics {
+    pub mean: f64,
+    pub median: f64,
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Samples outside the 1.5×IQR Tukey fences
+    pub mild_outliers: usize,
+    /// Samples outside the 3×IQR Tukey fences
+    pub severe_outliers: usize,
+}
+
+impl Statistics {
+    pub fn from_samples(samples: &[f64]) -> Self {
+        if samples.is_empty() {
+            return Self { mean: 0.0, median: 0.0, stddev: 0.0, min: 0.0, max: 0.0, mild_outliers: 0, severe_outliers: 0 };
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        let n = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / n;
+        let variance = if sorted.len() > 1 {
+            sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)
+        } else {
+            0.0
+        };
+
+        let q1 = percentile(&sorted, 0.25);
+        let q3 = percentile(&sorted, 0.75);
+        let iqr = q3 - q1;
+        let outside = |k: f64| sorted.iter().filter(|&&x| x < q1 - k * iqr || x > q3 + k * iqr).count();
+        let severe_outliers = outside(3.0);
+
+        Self {
+            mean,
+            median: percentile(&sorted, 0.5),
+            stddev: variance.sqrt(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mild_outliers: outside(1.5) - severe_outliers,
+            severe_outliers,
+        }
+    }
+}
+
+// Linear interpolation between closest ranks of an already sorted slice
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    let rank = fraction * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub case: BenchCase,
+    pub samples: usize,
+    pub iterations_per_sample: u64,
+    pub statistics: Statistics,
+}
+
+/// How a result compares with the stored baseline
+#[derive(Debug, Clone, PartialEq)]
+pub enum Comparison {
+    New,
+    NoChange(f64),
+    Improved(f64),
+    Regressed(f64),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Baseline {
+    pub benchmarks: BTreeMap<String, Statistics>,
+}
+
+impl Baseline {
+    /// Baselines live in `.neksis/bench/<name>.json` relative to the project
+    pub fn path_for(name: &str) -> PathBuf {
+        Path::new(".neksis").join("bench").join(format!("{}.json", name))
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>, CompilerError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)
+            .map_err(|e| CompilerError::io_error(&format!("Failed to read baseline '{}': {}", path.display(), e)))?;
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| CompilerError::config_error(&format!("Invalid baseline '{}': {}", path.display(), e)))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CompilerError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| CompilerError::io_error(&format!("Failed to create '{}': {}", parent.display(), e)))?;
+        }
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| CompilerError::internal_error(&format!("Failed to serialize baseline: {}", e)))?;
+        fs::write(path, content)
+            .map_err(|e| CompilerError::io_error(&format!("Failed to write baseline '{}': {}", path.display(), e)))
+    }
+
+    pub fn record(&mut self, result: &BenchResult) {
+        self.benchmarks.insert(result.case.qualified_name(), result.statistics.clone());
+    }
+
+    /// Compare medians, treating changes within `noise_threshold` as noise
+    pub fn compare(&self, result: &BenchResult, noise_threshold: f64) -> Comparison {
+        let Some(previous) = self.benchmarks.get(&result.case.qualified_name()) else {
+            return Comparison::New;
+        };
+        if previous.median <= 0.0 {
+            return Comparison::New;
+        }
+        let change = (result.statistics.median - previous.median) / previous.median;
+        if change.abs() < noise_threshold {
+            Comparison::NoChange(change)
+        } else if change < 0.0 {
+            Comparison::Improved(change)
+        } else {
+            Comparison::Regressed(change)
+        }
+    }
+}
+
+pub struct BenchRunner {
+    config: BenchConfig,
+}
+
+impl BenchRunner {
+    pub fn new(config: BenchConfig) -> Self {
+        Self { config }
+    }
+
+    /// Find every `@bench` function in the `.nx` files under `paths`
+    pub fn discover(&self, paths: &[PathBuf]) -> Result<(Vec<BenchCase>, Vec<LoadError>), CompilerError> {
+        let mut cases = Vec::new();
+        let mut load_errors = Vec::new();
+        for file in find_source_files(paths)? {
+            let source = fs::read_to_string(&file)
+                .map_err(|e| CompilerError::io_error(&format!("Failed to read '{}': {}", file.display(), e)))?;
+            if !source.contains("@bench") {
+                continue;
+            }
+            let program = match load_annotated_functions(&file, &source, &["bench"]) {
+                Ok(program) => program,
+                Err(message) => {
+                    load_errors.push(LoadError { file, message });
+                    continue;
+                }
+            };
+            for (function, line) in program.functions {
+                if !function.parameters.is_empty() {
+                    load_errors.push(LoadError {
+                        file: file.clone(),
+                        message: format!("benchmark function '{}' must not take parameters", function.name),
+                    });
+                    continue;
+                }
+                let case = BenchCase {
+                    name: function.name,
+                    file: file.clone(),
+                    line,
+                    program: Arc::clone(&program.instructions),
+                };
+                if self.config.filter.as_ref().is_none_or(|filter| case.qualified_name().contains(filter.as_str())) {
+                    cases.push(case);
+                }
+            }
+        }
+        Ok((cases, load_errors))
+    }
+
+    pub fn run_case(&self, case: &BenchCase) -> Result<BenchResult, String> {
+        // Benchmarks usually do not print, but if they do the output is discarded
+        let capture = OutputCapture::new();
+        let mut vm = VM::new();
+        vm.set_permissions(Permissions::all());
+        vm.set_output_capture(capture.clone());
+        vm.load_instructions((*case.program).clone());
+        vm.run()?;
+
+        // Warm up, and use the warmup timing to estimate the cost of one iteration
+        let warmup_start = Instant::now();
+        let mut warmup_iterations: u64 = 0;
+        while warmup_iterations == 0 || warmup_start.elapsed() < self.config.warmup {
+            vm.call(&case.name, Vec::new())?;
+            warmup_iterations += 1;
+        }
+        let per_iteration = warmup_start.elapsed().as_secs_f64() / warmup_iterations as f64;
+
+        let samples = self.config.samples.max(2);
+        let sample_budget = self.config.measurement.as_secs_f64() / samples as f64;
+        let iterations_per_sample = ((sample_budget / per_iteration.max(1e-9)) as u64).max(1);
+
+        let mut per_iteration_ns = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            let start = Instant::now();
+            for _ in 0..iterations_per_sample {
+                vm.call(&case.name, Vec::new())?;
+            }
+            per_iteration_ns.push(start.elapsed().as_nanos() as f64 / iterations_per_sample as f64);
+            capture.stdout.lock().unwrap().clear();
+            capture.stderr.lock().unwrap().clear();
+        }
+
+        Ok(BenchResult {
+            case: case.clone(),
+            samples,
+            iterations_per_sample,
+            statistics: Statistics::from_samples(&per_iteration_ns),
+        })
+    }
+}
+
+/// Format nanoseconds with a readable unit
+pub fn format_nanos(nanos: f64) -> String {
+    if nanos < 1_000.0 {
+        format!("{:.1} ns", nanos)
+    } else if nanos < 1_000_000.0 {
+        format!("{:.2} µs", nanos / 1_000.0)
+    } else if nanos < 1_000_000_000.0 {
+        format!("{:.2} ms", nanos / 1_000_000.0)
+    } else {
+        format!("{:.2} s", nanos / 1_000_000_000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statistics() {
+        let stats = Statistics::from_samples(&[10.0, 11.0, 12.0, 13.0, 14.0, 100.0]);
+        assert_eq!(stats.median, 12.5);
+        assert_eq!(stats.min, 10.0);
+        assert_eq!(stats.max, 100.0);
+        assert!((stats.mean - 26.666).abs() < 0.01);
+        assert_eq!(stats.mild_outliers + stats.severe_outliers, 1);
+    }
+
+    #[test]
+    fn test_discover_run_and_compare() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("fib_bench.nx"),
+            "fn fib(n: Int) -> Int {\n    if n <= 1 {\n        return n;\n    }\n    return fib(n - 1) + fib(n - 2);\n}\n\n@bench\nfn fib_10() {\n    fib(10);\n}\n",
+        )
+        .unwrap();
+
+        let runner = BenchRunner::new(BenchConfig {
+            warmup: Duration::from_millis(10),
+            measurement: Duration::from_millis(20),
+            samples: 5,
+            ..BenchConfig::default()
+        });
+        let (cases, load_errors) = runner.discover(&[dir.path().to_path_buf()]).unwrap();
+        assert!(load_errors.is_empty());
+        assert_eq!(cases.len(), 1);
+        assert_eq!(cases[0].line, 9);
+
+        let result = runner.run_case(&cases[0]).unwrap();
+        assert!(result.statistics.median > 0.0);
+
+        let mut baseline = Baseline::default();
+        assert_eq!(baseline.compare(&result, 0.05), Comparison::New);
+        baseline.record(&result);
+        let mut slower = result.clone();
+        slower.statistics.median *= 2.0;
+        assert!(matches!(baseline.compare(&slower, 0.05), Comparison::Regressed(change) if (change - 1.0).abs() < 1e-9));
+        assert!(matches!(baseline.compare(&result, 0.05), Comparison::NoChange(_)));
+
+        let path = dir.path().join("baseline.json");
+        baseline.save(&path).unwrap();
+        assert_eq!(Baseline::load(&path).unwrap().unwrap().benchmarks.len(), 1);
+    }
+}
@@ -0,0 +1,1245 @@
+// C Header Bindings
+//
+// `neksis bindgen` turns the declarations in a C header into an `extern "C"`
+// block. The preprocessor is not run: `#include`s are not followed and macros
+// are reported rather than expanded. Functions, structs, enums, typedefs and
+// pointers are understood; a declaration outside that subset is skipped with a
+// warning, so one variadic function does not cost the rest of the header.
+
+use crate::ffi::{CallingConvention, FFIParameter, FFISignature, FFIType, ParameterDirection};
+use crate::lexer::{Lexer, Token};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::os::raw::c_long;
+
+/// Bindings generated from a header
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    /// Neksis source: the enum constants followed by the extern block
+    pub source: String,
+    pub functions: usize,
+    pub structs: usize,
+    pub constants: usize,
+    /// Declarations that were left out, and why
+    pub warnings: Vec<Warning>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub struct Bindgen {
+    library: String,
+}
+
+impl Bindgen {
+    /// Bindings whose functions are loaded from `library`
+    pub fn new(library: &str) -> Self {
+        Self { library: library.to_string() }
+    }
+
+    pub fn generate(&self, header: &str) -> Bindings {
+        let mut warnings = Vec::new();
+        let tokens = tokenize(header, &mut warnings);
+        let mut parser = HeaderParser::new(tokens);
+        parser.parse();
+        warnings.append(&mut parser.warnings);
+
+        let mut emitter = Emitter { typedefs: &parser.typedefs, structs: HashMap::new(), warnings: Vec::new() };
+        let (constants, structs, functions) = emitter.emit(&parser.items);
+        warnings.append(&mut emitter.warnings);
+        warnings.sort_by_key(|warning| warning.line);
+
+        let mut source = format!("// Generated by neksis bindgen for {}\n\n", self.library);
+        if !constants.is_empty() {
+            source.push_str(&constants.join("\n"));
+            source.push_str("\n\n");
+        }
+        // Structs only refer to earlier structs, so they can all go before the functions
+        source.push_str(&format!("extern \"C\" lib \"{}\" {{\n", self.library));
+        source.push_str(&structs.join("\n"));
+        if !structs.is_empty() && !functions.is_empty() {
+            source.push('\n');
+        }
+        source.push_str(&functions.concat());
+        source.push_str("}\n");
+        Bindings { source, functions: functions.len(), structs: structs.len(), constants: constants.len(), warnings }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Number(i64),
+    Text,
+    Punct(&'static str),
+    Other(char),
+}
+
+#[derive(Debug, Clone)]
+struct CToken {
+    tok: Tok,
+    line: usize,
+}
+
+const PUNCTUATION: [&str; 27] = [
+    "...", "<<", ">>", "(", ")", "{", "}", "[", "]", ";", ",", "*", "=", ":", "+", "-", "~", "!", "|", "&", "^",
+    "/", "%", "<", ">", "?", ".",
+];
+
+/// Split a header into tokens, dropping comments and preprocessor directives
+fn tokenize(source: &str, warnings: &mut Vec<Warning>) -> Vec<CToken> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut line_start = true;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            line_start = true;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i = skip_block_comment(&chars, i, &mut line);
+        } else if c == '#' && line_start {
+            // A directive runs to the end of the line, including continuations
+            let directive_line = line;
+            let mut text = String::new();
+            i += 1;
+            while i < chars.len() && chars[i] != '\n' {
+                if chars[i] == '\\' && chars.get(i + 1) == Some(&'\n') {
+                    line += 1;
+                    i += 2;
+                } else if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                    i = skip_block_comment(&chars, i, &mut line);
+                } else if chars[i] == '/' && chars.get(i + 1) == Some(&'/') {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                } else {
+                    text.push(chars[i]);
+                    i += 1;
+                }
+            }
+            directive(&text, directive_line, warnings);
+        } else {
+            line_start = false;
+            let start = i;
+            let tok = if c.is_alphabetic() || c == '_' {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                Tok::Ident(chars[start..i].iter().collect())
+            } else if c.is_ascii_digit() {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '.') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                match parse_integer(&text) {
+                    Some(value) => Tok::Number(value),
+                    None => Tok::Other(c),
+                }
+            } else if c == '"' || c == '\'' {
+                i += 1;
+                while i < chars.len() && chars[i] != c && chars[i] != '\n' {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                i += 1;
+                let body: String = chars[start + 1..(i - 1).min(chars.len())].iter().collect();
+                if c == '"' {
+                    Tok::Text
+                } else {
+                    match character_value(&body) {
+                        Some(value) => Tok::Number(value),
+                        None => Tok::Other('\''),
+                    }
+                }
+            } else if let Some(punct) = PUNCTUATION.iter().find(|p| p.chars().enumerate().all(|(k, pc)| chars.get(i + k) == Some(&pc))) {
+                i += punct.len();
+                Tok::Punct(punct)
+            } else {
+                i += 1;
+                Tok::Other(c)
+            };
+            tokens.push(CToken { tok, line });
+        }
+    }
+    tokens
+}
+
+fn skip_block_comment(chars: &[char], mut i: usize, line: &mut usize) -> usize {
+    i += 2;
+    while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+        if chars[i] == '\n' {
+            *line += 1;
+        }
+        i += 1;
+    }
+    i + 2
+}
+
+/// Macros cannot be expressed in an extern block, so each one with a body is reported
+fn directive(text: &str, line: usize, warnings: &mut Vec<Warning>) {
+    let Some(rest) = text.trim_start().strip_prefix("define") else {
+        return;
+    };
+    if !rest.starts_with(char::is_whitespace) {
+        return;
+    }
+    let rest = rest.trim_start();
+    let name: String = rest.chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+    let body = &rest[name.len()..];
+    // `#define HEADER_H` guards carry no value worth reporting
+    let message = if body.starts_with('(') {
+        format!("function-like macro {}() is not supported", name)
+    } else if !body.trim().is_empty() {
+        format!("macro {} is not supported; declare its value by hand", name)
+    } else {
+        return;
+    };
+    warnings.push(Warning { line, message });
+}
+
+fn parse_integer(text: &str) -> Option<i64> {
+    let digits = text.trim_end_matches(['u', 'U', 'l', 'L']);
+    if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8).ok()
+    } else {
+        digits.parse().ok()
+    }
+}
+
+fn character_value(body: &str) -> Option<i64> {
+    let mut chars = body.chars();
+    let value = match (chars.next()?, chars.next()) {
+        ('\\', Some('n')) => '\n',
+        ('\\', Some('t')) => '\t',
+        ('\\', Some('r')) => '\r',
+        ('\\', Some('0')) => '\0',
+        ('\\', Some(escaped)) => escaped,
+        (c, None) => c,
+        _ => return None,
+    };
+    Some(value as i64)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CType {
+    Void,
+    /// Plain `char`, which becomes a String behind a pointer
+    Char,
+    Scalar(FFIType),
+    Enum,
+    Struct(String),
+    Union(String),
+    /// A typedef name, resolved when the bindings are written
+    Named(String),
+    Pointer(Box<CType>),
+    Array(Box<CType>),
+    Function(Box<CFunction>),
+    Unsupported(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CFunction {
+    parameters: Vec<(Option<String>, CType)>,
+    return_type: CType,
+    variadic: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: Option<String>,
+    ty: CType,
+    bitfield: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Constant { name: String, value: i64, line: usize },
+    Struct { name: String, fields: Vec<Field>, line: usize },
+    Function { name: String, function: CFunction, line: usize },
+}
+
+struct Specifiers {
+    ty: CType,
+    typedef: bool,
+}
+
+struct HeaderParser {
+    tokens: Vec<CToken>,
+    position: usize,
+    items: Vec<Item>,
+    typedefs: HashMap<String, CType>,
+    constants: HashMap<String, i64>,
+    /// Names given to structs declared without a tag
+    anonymous: HashSet<String>,
+    warnings: Vec<Warning>,
+}
+
+impl HeaderParser {
+    fn new(tokens: Vec<CToken>) -> Self {
+        Self {
+            tokens,
+            position: 0,
+            items: Vec::new(),
+            typedefs: HashMap::new(),
+            constants: HashMap::new(),
+            anonymous: HashSet::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn parse(&mut self) {
+        while self.position < self.tokens.len() {
+            // `extern "C" {` wraps headers shared with C++; its braces are transparent
+            if self.check_ident("extern") && matches!(self.peek_at(1), Some(Tok::Text)) {
+                self.position += 2;
+                self.eat("{");
+                continue;
+            }
+            if self.eat(";") || self.eat("}") {
+                continue;
+            }
+            let start = self.position;
+            let line = self.line();
+            let (items, typedefs) = (self.items.len(), self.typedefs.clone());
+            if let Err(message) = self.declaration() {
+                self.warn(line, format!("skipped a declaration: {}", message));
+                self.items.truncate(items);
+                self.typedefs = typedefs;
+                self.position = start;
+                self.skip_declaration();
+            }
+        }
+    }
+
+    fn declaration(&mut self) -> Result<(), String> {
+        let line = self.line();
+        let specifiers = self.specifiers()?;
+        if self.eat(";") {
+            return Ok(());
+        }
+        let mut base = specifiers.ty;
+        loop {
+            let (name, ty) = self.declarator(base.clone())?;
+            let name = name.ok_or_else(|| format!("expected a name at line {}", self.line()))?;
+            if specifiers.typedef {
+                // Later declarators, as in `typedef struct { ... } Point, *PointRef;`, use the new name
+                if self.typedef(name.clone(), ty) {
+                    base = CType::Struct(name);
+                }
+            } else if let CType::Function(function) = ty {
+                if self.check("{") {
+                    self.skip_balanced();
+                    self.warn(line, format!("{} is defined in the header, so the library may not export it", name));
+                    return Ok(());
+                }
+                self.items.push(Item::Function { name, function: *function, line });
+            } else {
+                self.warn(line, format!("global variable {} is not supported", name));
+            }
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect(";")
+    }
+
+    /// Record a typedef, returning whether it named an anonymous struct
+    fn typedef(&mut self, name: String, ty: CType) -> bool {
+        // `typedef struct { ... } Point;` names the struct after the typedef
+        if let CType::Struct(tag) = &ty {
+            if self.anonymous.remove(tag) {
+                for item in self.items.iter_mut() {
+                    if let Item::Struct { name: struct_name, .. } = item {
+                        if struct_name == tag {
+                            *struct_name = name.clone();
+                        }
+                    }
+                }
+                self.typedefs.insert(name.clone(), CType::Struct(name));
+                return true;
+            }
+        }
+        self.typedefs.insert(name, ty);
+        false
+    }
+
+    fn specifiers(&mut self) -> Result<Specifiers, String> {
+        let mut typedef = false;
+        let mut base = None;
+        let mut words = Vec::new();
+        while let Some(Tok::Ident(word)) = self.peek().cloned() {
+            match word.as_str() {
+                "typedef" => typedef = true,
+                "const" | "volatile" | "extern" | "static" | "inline" | "__inline" | "__inline__" | "register"
+                | "restrict" | "__restrict" | "__extension__" | "_Noreturn" => {}
+                "__attribute__" | "__declspec" | "__asm__" | "__asm" | "_Alignas" => {
+                    self.skip_attributes();
+                    continue;
+                }
+                "void" | "char" | "short" | "int" | "long" | "float" | "double" | "signed" | "unsigned" | "_Bool"
+                | "bool" | "_Complex" | "__int128" => words.push(word),
+                "struct" | "union" => {
+                    self.position += 1;
+                    base = Some(self.record(word == "union")?);
+                    continue;
+                }
+                "enum" => {
+                    self.position += 1;
+                    base = Some(self.enumeration()?);
+                    continue;
+                }
+                _ if base.is_none() && words.is_empty() => base = Some(CType::Named(word)),
+                _ => break,
+            }
+            self.position += 1;
+        }
+        let ty = match base {
+            Some(ty) => ty,
+            None if words.is_empty() => return Err(format!("expected a type at line {}", self.line())),
+            None => builtin(&words),
+        };
+        Ok(Specifiers { ty, typedef })
+    }
+
+    fn record(&mut self, union: bool) -> Result<CType, String> {
+        let line = self.line();
+        self.skip_attributes();
+        let tag = self.identifier();
+        if !self.check("{") {
+            let tag = tag.ok_or_else(|| format!("expected a struct name at line {}", line))?;
+            return Ok(if union { CType::Union(tag) } else { CType::Struct(tag) });
+        }
+        let name = tag.unwrap_or_else(|| {
+            let name = format!("Anonymous{}", self.anonymous.len() + 1);
+            self.anonymous.insert(name.clone());
+            name
+        });
+        if union {
+            self.skip_balanced();
+            self.warn(line, format!("union {} is not supported", name));
+            return Ok(CType::Union(name));
+        }
+
+        self.expect("{")?;
+        let mut fields = Vec::new();
+        while !self.eat("}") {
+            if self.position >= self.tokens.len() {
+                return Err(format!("struct {} is not closed", name));
+            }
+            let specifiers = self.specifiers()?;
+            if self.eat(";") {
+                // A C11 anonymous member
+                fields.push(Field { name: None, ty: specifiers.ty, bitfield: false });
+                continue;
+            }
+            loop {
+                let (field, ty) = self.declarator(specifiers.ty.clone())?;
+                let bitfield = self.eat(":");
+                if bitfield {
+                    self.expression()?;
+                }
+                fields.push(Field { name: field, ty, bitfield });
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect(";")?;
+        }
+        self.skip_attributes();
+        self.items.push(Item::Struct { name: name.clone(), fields, line });
+        Ok(CType::Struct(name))
+    }
+
+    fn enumeration(&mut self) -> Result<CType, String> {
+        self.skip_attributes();
+        self.identifier();
+        if !self.eat("{") {
+            return Ok(CType::Enum);
+        }
+        let mut next = Some(0);
+        while !self.eat("}") {
+            let line = self.line();
+            let name = self.identifier().ok_or_else(|| format!("expected an enum constant at line {}", line))?;
+            if self.eat("=") {
+                next = match self.expression() {
+                    Ok(value) => Some(value),
+                    Err(message) => {
+                        self.warn(line, format!("enum constant {} is not supported: {}", name, message));
+                        self.skip_until(&[",", "}"]);
+                        None
+                    }
+                };
+            } else if next.is_none() {
+                self.warn(line, format!("enum constant {} follows a value that could not be computed", name));
+            }
+            if let Some(value) = next {
+                self.constants.insert(name.clone(), value);
+                self.items.push(Item::Constant { name, value, line });
+                next = value.checked_add(1);
+            }
+            if !self.eat(",") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        Ok(CType::Enum)
+    }
+
+    /// Parse a declarator around `base`, returning the declared name, if any, and its type
+    fn declarator(&mut self, base: CType) -> Result<(Option<String>, CType), String> {
+        let mut ty = base;
+        while self.eat("*") {
+            ty = CType::Pointer(Box::new(ty));
+            while matches!(self.peek(), Some(Tok::Ident(word)) if matches!(word.as_str(),
+                "const" | "volatile" | "restrict" | "__restrict" | "__restrict__" | "_Nonnull" | "_Nullable"))
+            {
+                self.position += 1;
+            }
+            self.skip_attributes();
+        }
+        // In `int (*compare)(int, int)` the parenthesised part binds tighter
+        // than the parameter list after it, so the suffixes are applied first
+        if self.check("(") && matches!(self.peek_at(1), Some(Tok::Punct("*"))) {
+            let open = self.position;
+            self.skip_balanced();
+            let ty = self.suffixes(ty)?;
+            let end = self.position;
+            self.position = open + 1;
+            let (name, ty) = self.declarator(ty)?;
+            self.expect(")")?;
+            self.position = end;
+            return Ok((name, ty));
+        }
+        let name = self.identifier();
+        Ok((name, self.suffixes(ty)?))
+    }
+
+    fn suffixes(&mut self, ty: CType) -> Result<CType, String> {
+        self.skip_attributes();
+        if self.eat("[") {
+            self.skip_until(&["]"]);
+            self.expect("]")?;
+            // `int grid[2][3]` is an array of arrays, so later suffixes apply first
+            return Ok(CType::Array(Box::new(self.suffixes(ty)?)));
+        }
+        if self.eat("(") {
+            let mut parameters = Vec::new();
+            let mut variadic = false;
+            while !self.eat(")") {
+                if self.eat("...") {
+                    variadic = true;
+                    self.expect(")")?;
+                    break;
+                }
+                let specifiers = self.specifiers()?;
+                parameters.push(self.declarator(specifiers.ty)?);
+                if !self.eat(",") {
+                    self.expect(")")?;
+                    break;
+                }
+            }
+            // `f(void)` takes no parameters
+            if parameters == [(None, CType::Void)] {
+                parameters.clear();
+            }
+            self.skip_attributes();
+            return Ok(CType::Function(Box::new(CFunction { parameters, return_type: ty, variadic })));
+        }
+        Ok(ty)
+    }
+
+    /// Evaluate an integer constant expression, as found in enums and bit-field widths
+    fn expression(&mut self) -> Result<i64, String> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<i64, String> {
+        let mut left = self.unary()?;
+        while let Some(Tok::Punct(op)) = self.peek().cloned() {
+            let precedence = match op {
+                "*" | "/" | "%" => 5,
+                "+" | "-" => 4,
+                "<<" | ">>" => 3,
+                "&" => 2,
+                "^" => 1,
+                "|" => 0,
+                _ => break,
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(precedence + 1)?;
+            left = match op {
+                "*" => left.wrapping_mul(right),
+                "/" | "%" if right == 0 => return Err("division by zero".to_string()),
+                "/" => left / right,
+                "%" => left % right,
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                "&" => left & right,
+                "^" => left ^ right,
+                _ => left | right,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        let line = self.line();
+        match self.peek().cloned() {
+            Some(Tok::Number(value)) => {
+                self.position += 1;
+                Ok(value)
+            }
+            Some(Tok::Ident(name)) => {
+                self.position += 1;
+                self.constants.get(&name).copied().ok_or_else(|| format!("unknown name '{}' at line {}", name, line))
+            }
+            Some(Tok::Punct("(")) => {
+                self.position += 1;
+                let value = self.expression()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Some(Tok::Punct("-")) => {
+                self.position += 1;
+                Ok(self.unary()?.wrapping_neg())
+            }
+            Some(Tok::Punct("+")) => {
+                self.position += 1;
+                self.unary()
+            }
+            Some(Tok::Punct("~")) => {
+                self.position += 1;
+                Ok(!self.unary()?)
+            }
+            Some(Tok::Punct("!")) => {
+                self.position += 1;
+                Ok((self.unary()? == 0) as i64)
+            }
+            _ => Err(format!("expected a constant at line {}", line)),
+        }
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Tok> {
+        self.tokens.get(self.position + offset).map(|token| &token.tok)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.position).or(self.tokens.last()).map_or(1, |token| token.line)
+    }
+
+    fn check(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Punct(p)) if *p == punct)
+    }
+
+    fn check_ident(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Ident(w)) if w == word)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = self.check(punct);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat(punct) {
+            return Ok(());
+        }
+        let found = match self.peek() {
+            Some(Tok::Ident(word)) => format!("'{}'", word),
+            Some(Tok::Number(value)) => value.to_string(),
+            Some(Tok::Punct(p)) => format!("'{}'", p),
+            Some(Tok::Other(c)) => format!("'{}'", c),
+            Some(Tok::Text) => "a string".to_string(),
+            None => "the end of the header".to_string(),
+        };
+        Err(format!("expected '{}' at line {}, found {}", punct, self.line(), found))
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        let Some(Tok::Ident(name)) = self.peek().cloned() else {
+            return None;
+        };
+        self.position += 1;
+        Some(name)
+    }
+
+    fn warn(&mut self, line: usize, message: String) {
+        self.warnings.push(Warning { line, message });
+    }
+
+    /// Skip `__attribute__((...))` and similar compiler extensions
+    fn skip_attributes(&mut self) {
+        while matches!(self.peek(), Some(Tok::Ident(word)) if matches!(word.as_str(),
+            "__attribute__" | "__declspec" | "__asm__" | "__asm" | "_Alignas"))
+        {
+            self.position += 1;
+            if self.check("(") {
+                self.skip_balanced();
+            }
+        }
+    }
+
+    /// Skip from an opening bracket past its partner
+    fn skip_balanced(&mut self) {
+        let mut depth = 0;
+        while let Some(tok) = self.peek() {
+            match tok {
+                Tok::Punct("(" | "{" | "[") => depth += 1,
+                Tok::Punct(")" | "}" | "]") => depth -= 1,
+                _ => {}
+            }
+            self.position += 1;
+            if depth == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Move to the next of `stops` outside brackets, without consuming it
+    fn skip_until(&mut self, stops: &[&str]) {
+        let mut depth = 0;
+        while let Some(tok) = self.peek() {
+            match tok {
+                Tok::Punct(p) if depth == 0 && stops.contains(p) => break,
+                Tok::Punct("(" | "{" | "[") => depth += 1,
+                Tok::Punct(")" | "}" | "]") if depth == 0 => break,
+                Tok::Punct(")" | "}" | "]") => depth -= 1,
+                _ => {}
+            }
+            self.position += 1;
+        }
+    }
+
+    fn skip_declaration(&mut self) {
+        self.skip_until(&[";"]);
+        self.eat(";");
+    }
+}
+
+/// The type named by a list of C type keywords such as `unsigned long`
+fn builtin(words: &[String]) -> CType {
+    let has = |word: &str| words.iter().any(|w| w == word);
+    let longs = words.iter().filter(|w| *w == "long").count();
+    let sign = |signed: FFIType, unsigned: FFIType| CType::Scalar(if has("unsigned") { unsigned } else { signed });
+    if has("_Complex") || has("__int128") || (has("double") && longs > 0) {
+        CType::Unsupported(words.join(" "))
+    } else if has("void") {
+        CType::Void
+    } else if has("_Bool") || has("bool") {
+        CType::Scalar(FFIType::Bool)
+    } else if has("float") {
+        CType::Scalar(FFIType::Float32)
+    } else if has("double") {
+        CType::Scalar(FFIType::Float64)
+    } else if has("char") {
+        if has("unsigned") {
+            CType::Scalar(FFIType::UInt8)
+        } else if has("signed") {
+            CType::Scalar(FFIType::Int8)
+        } else {
+            CType::Char
+        }
+    } else if has("short") {
+        sign(FFIType::Int16, FFIType::UInt16)
+    } else if longs > 1 || (longs == 1 && std::mem::size_of::<c_long>() == 8) {
+        sign(FFIType::Int64, FFIType::UInt64)
+    } else {
+        sign(FFIType::Int32, FFIType::UInt32)
+    }
+}
+
+/// Typedefs from the standard headers, which bindgen does not read
+fn standard_typedef(name: &str) -> Option<FFIType> {
+    let pointer_sized = std::mem::size_of::<usize>() == 8;
+    Some(match name {
+        "int8_t" => FFIType::Int8,
+        "int16_t" => FFIType::Int16,
+        "int32_t" => FFIType::Int32,
+        "int64_t" | "off_t" | "time_t" => FFIType::Int64,
+        "uint8_t" => FFIType::UInt8,
+        "uint16_t" => FFIType::UInt16,
+        "uint32_t" => FFIType::UInt32,
+        "uint64_t" => FFIType::UInt64,
+        "size_t" | "uintptr_t" if pointer_sized => FFIType::UInt64,
+        "ssize_t" | "intptr_t" | "ptrdiff_t" if pointer_sized => FFIType::Int64,
+        "size_t" | "uintptr_t" => FFIType::UInt32,
+        "ssize_t" | "intptr_t" | "ptrdiff_t" => FFIType::Int32,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Position {
+    Parameter,
+    Return,
+    Field,
+}
+
+/// Writes the parsed items as extern block declarations
+struct Emitter<'a> {
+    typedefs: &'a HashMap<String, CType>,
+    /// Structs written so far, which later declarations may use by value
+    structs: HashMap<String, FFIType>,
+    warnings: Vec<Warning>,
+}
+
+impl Emitter<'_> {
+    /// The constant lines, then the struct and function declarations of the block
+    fn emit(&mut self, items: &[Item]) -> (Vec<String>, Vec<String>, Vec<String>) {
+        let mut constants = Vec::new();
+        let mut structs = Vec::new();
+        let mut functions = Vec::new();
+        let mut declared = HashSet::new();
+        for item in items {
+            match item {
+                Item::Constant { name, value, line } => {
+                    if is_identifier(name) {
+                        constants.push(format!("let {} = {};", name, value));
+                    } else {
+                        self.warn(*line, format!("enum constant {} is a Neksis keyword", name));
+                    }
+                }
+                Item::Struct { name, fields, line } => match self.structure(name, fields) {
+                    Ok(text) => structs.push(text),
+                    Err(reason) => self.warn(*line, format!("struct {} skipped: {}", name, reason)),
+                },
+                Item::Function { name, function, line } => {
+                    // Headers often declare a function more than once
+                    if declared.contains(name) {
+                        continue;
+                    }
+                    match self.function(name, function) {
+                        Ok(text) => {
+                            functions.push(text);
+                            declared.insert(name.clone());
+                        }
+                        Err(reason) => self.warn(*line, format!("function {} skipped: {}", name, reason)),
+                    }
+                }
+            }
+        }
+        (constants, structs, functions)
+    }
+
+    fn structure(&mut self, name: &str, fields: &[Field]) -> Result<String, String> {
+        if !is_identifier(name) {
+            return Err(format!("'{}' is a Neksis keyword", name));
+        }
+        if fields.is_empty() {
+            return Err("it has no fields".to_string());
+        }
+        let mut resolved = Vec::new();
+        for field in fields {
+            let Some(field_name) = &field.name else {
+                return Err("anonymous members are not supported".to_string());
+            };
+            if field.bitfield {
+                return Err(format!("field {} is a bit-field", field_name));
+            }
+            let (text, ty) = self
+                .resolve(&field.ty, Position::Field)
+                .map_err(|reason| format!("field {} is {}", field_name, reason))?;
+            resolved.push((field_name.clone(), text, ty));
+        }
+
+        let ty = FFIType::structure(resolved.iter().map(|(field, _, ty)| (field.clone(), ty.clone())).collect());
+        let (size, align) = ty.layout();
+        let FFIType::Struct(layout) = &ty else {
+            unreachable!("FFIType::structure builds a struct");
+        };
+        let mut text = format!("    // {} bytes, aligned to {}\n    struct {} {{\n", size, align, name);
+        for ((field, type_text, _), placed) in resolved.iter().zip(layout) {
+            text.push_str(&format!("        {}: {},  // offset {}\n", safe_name(field), type_text, placed.offset));
+        }
+        text.push_str("    }\n");
+        self.structs.insert(name.to_string(), ty);
+        Ok(text)
+    }
+
+    fn function(&self, name: &str, function: &CFunction) -> Result<String, String> {
+        if function.variadic {
+            return Err("variadic functions cannot be declared in an extern block".to_string());
+        }
+        if !is_identifier(name) {
+            return Err(format!("'{}' is a Neksis keyword", name));
+        }
+        let mut parameters = Vec::new();
+        for (index, (parameter, ty)) in function.parameters.iter().enumerate() {
+            let parameter = parameter.as_deref().map_or_else(|| format!("arg{}", index), safe_name);
+            let (text, _) = self
+                .resolve(ty, Position::Parameter)
+                .map_err(|reason| format!("parameter {} is {}", parameter, reason))?;
+            parameters.push(format!("{}: {}", parameter, text));
+        }
+        let (return_text, return_type) =
+            self.resolve(&function.return_type, Position::Return).map_err(|reason| format!("it returns {}", reason))?;
+        let returns = if return_type == FFIType::Void { String::new() } else { format!(" -> {}", return_text) };
+        Ok(format!("    fn {}({}){};\n", name, parameters.join(", "), returns))
+    }
+
+    /// The extern block spelling of a C type and the FFIType it stands for
+    fn resolve(&self, ty: &CType, position: Position) -> Result<(String, FFIType), String> {
+        match self.unalias(ty) {
+            CType::Void if position == Position::Return => Ok(("void".to_string(), FFIType::Void)),
+            CType::Void => Err("void".to_string()),
+            CType::Char => Ok(scalar(FFIType::Int8)),
+            CType::Enum => Ok(scalar(FFIType::Int32)),
+            CType::Scalar(ty) => Ok(scalar(ty)),
+            CType::Named(name) => Err(format!("of unknown type {}", name)),
+            CType::Struct(name) => match self.structs.get(&name) {
+                Some(ty) => Ok((name, ty.clone())),
+                None => Err(format!("struct {}, which has no usable definition", name)),
+            },
+            CType::Union(name) => Err(format!("union {}", name)),
+            CType::Pointer(target) => self.pointer(&target),
+            // An array parameter is a pointer to the first element
+            CType::Array(element) if position == Position::Parameter => {
+                let (text, ty) = self.resolve(&element, Position::Field)?;
+                if !ty.is_scalar() {
+                    return Err(format!("an array of {}", text));
+                }
+                Ok((format!("[{}]", text), FFIType::Array(Box::new(ty), 0)))
+            }
+            CType::Array(_) => Err("an array".to_string()),
+            // A function parameter is a pointer to the function
+            CType::Function(function) if position == Position::Parameter => self.callback(&function),
+            CType::Function(_) => Err("a function".to_string()),
+            CType::Unsupported(name) => Err(name),
+        }
+    }
+
+    fn pointer(&self, target: &CType) -> Result<(String, FFIType), String> {
+        let opaque = || Ok(("Pointer".to_string(), FFIType::Pointer(Box::new(FFIType::Void))));
+        match self.unalias(target) {
+            CType::Char => Ok(("String".to_string(), FFIType::String)),
+            CType::Function(function) => self.callback(&function),
+            CType::Scalar(_) | CType::Enum => {
+                let (text, ty) = self.resolve(target, Position::Field)?;
+                Ok((format!("*{}", text), FFIType::Pointer(Box::new(ty))))
+            }
+            CType::Struct(name) if self.structs.contains_key(&name) => {
+                Ok((format!("*{}", name), FFIType::Pointer(Box::new(self.structs[&name].clone()))))
+            }
+            // Anything else is only passed around by address
+            _ => opaque(),
+        }
+    }
+
+    fn callback(&self, function: &CFunction) -> Result<(String, FFIType), String> {
+        if function.variadic {
+            return Err("a variadic callback".to_string());
+        }
+        let mut texts = Vec::new();
+        let mut parameters = Vec::new();
+        for (index, (_, ty)) in function.parameters.iter().enumerate() {
+            let (text, ffi_type) = self.resolve(ty, Position::Parameter)?;
+            texts.push(text);
+            parameters.push(FFIParameter { name: format!("arg{}", index), ffi_type, direction: ParameterDirection::In });
+        }
+        let (return_text, return_type) = self.resolve(&function.return_type, Position::Return)?;
+        if return_type != FFIType::Void && !return_type.is_scalar() {
+            return Err(format!("a callback returning {}", return_text));
+        }
+        let returns = if return_type == FFIType::Void { String::new() } else { format!(" -> {}", return_text) };
+        let signature = FFISignature { return_type, parameters, calling_convention: CallingConvention::C };
+        Ok((format!("fn({}){}", texts.join(", "), returns), FFIType::Function(Box::new(signature))))
+    }
+
+    /// Follow typedefs down to the type they name
+    fn unalias(&self, ty: &CType) -> CType {
+        let mut ty = ty.clone();
+        let mut seen = HashSet::new();
+        while let CType::Named(name) = &ty {
+            if let Some(standard) = standard_typedef(name) {
+                return CType::Scalar(standard);
+            }
+            match self.typedefs.get(name) {
+                Some(target) if seen.insert(name.clone()) => ty = target.clone(),
+                _ => break,
+            }
+        }
+        ty
+    }
+
+    fn warn(&mut self, line: usize, message: String) {
+        self.warnings.push(Warning { line, message });
+    }
+}
+
+fn scalar(ty: FFIType) -> (String, FFIType) {
+    (ty.to_string(), ty)
+}
+
+/// Whether the Neksis lexer reads `name` as a plain identifier
+fn is_identifier(name: &str) -> bool {
+    matches!(
+        Lexer::new(name, String::new()).tokenize().as_deref(),
+        Ok([first, ..]) if matches!(&first.token, Token::Identifier(word) if word == name)
+    )
+}
+
+/// Parameter and field names that clash with Neksis keywords get a trailing underscore
+fn safe_name(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_string()
+    } else {
+        format!("{}_", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Statement;
+    use crate::test_framework::golden::run_program_at;
+    use std::process::Command;
+
+    const HEADER: &str = r#"
+#ifndef GEOMETRY_H
+#define GEOMETRY_H
+#define MAX_POINTS 64
+#define SQUARE(x) ((x) * (x))
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Shapes the library can draw */
+typedef enum { CIRCLE, SQUARE_SHAPE = 4, TRIANGLE, ALL = (1 << 3) | 1 } shape_t;
+
+typedef struct {
+    double x, y;
+} Point;
+
+struct style {
+    unsigned char visible;
+    short weight;
+    Point anchor;
+    const char *label;
+    struct style *parent;
+};
+
+struct grid { int cells[4]; };
+union number { int i; double d; };
+typedef int (*compare_fn)(const long *a, const long *b);
+typedef struct canvas Canvas;
+
+double distance(Point a, Point b);
+size_t point_count(void);
+const char *shape_name(shape_t shape, Canvas *canvas);
+void sort(long items[], size_t count, compare_fn compare);
+int log_message(const char *format, ...);
+struct style default_style(int fn, unsigned long long *out) __attribute__((pure));
+void *free(void *pointer);
+void set_number(union number n);
+static inline int twice(int x) { return x * 2; }
+
+#ifdef __cplusplus
+}
+#endif
+#endif
+"#;
+
+    fn have_c_compiler() -> bool {
+        let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let found = Command::new(compiler).arg("--version").output().is_ok();
+        if !found {
+            eprintln!("skipping: no C compiler");
+        }
+        found
+    }
+
+    #[test]
+    fn test_declarations() {
+        let bindings = Bindgen::new("libgeometry.so").generate(HEADER);
+        assert_eq!(
+            bindings.source,
+            r#"// Generated by neksis bindgen for libgeometry.so
+
+let CIRCLE = 0;
+let SQUARE_SHAPE = 4;
+let TRIANGLE = 5;
+let ALL = 9;
+
+extern "C" lib "libgeometry.so" {
+    // 16 bytes, aligned to 8
+    struct Point {
+        x: f64,  // offset 0
+        y: f64,  // offset 8
+    }
+
+    // 40 bytes, aligned to 8
+    struct style {
+        visible: u8,  // offset 0
+        weight: i16,  // offset 2
+        anchor: Point,  // offset 8
+        label: String,  // offset 24
+        parent: Pointer,  // offset 32
+    }
+
+    fn distance(a: Point, b: Point) -> f64;
+    fn point_count() -> u64;
+    fn shape_name(shape: i32, canvas: Pointer) -> String;
+    fn sort(items: [i64], count: u64, compare: fn(*i64, *i64) -> i32);
+    fn default_style(fn_: i32, out: *u64) -> style;
+}
+"#
+        );
+        assert_eq!((bindings.functions, bindings.structs, bindings.constants), (5, 2, 4));
+        let warnings: Vec<String> = bindings.warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(
+            warnings,
+            [
+                "line 4: macro MAX_POINTS is not supported; declare its value by hand",
+                "line 5: function-like macro SQUARE() is not supported",
+                "line 26: struct grid skipped: field cells is an array",
+                "line 27: union number is not supported",
+                "line 35: function log_message skipped: variadic functions cannot be declared in an extern block",
+                "line 37: function free skipped: 'free' is a Neksis keyword",
+                "line 38: function set_number skipped: parameter n is union number",
+                "line 39: twice is defined in the header, so the library may not export it",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generated_source_parses() {
+        let bindings = Bindgen::new("libgeometry.so").generate(HEADER);
+        let tokens = crate::lexer::Lexer::new(&bindings.source, "bindings.nx".to_string()).tokenize().unwrap();
+        let program = crate::parser::Parser::new(tokens).parse().unwrap();
+        let Some(Statement::Extern(block)) = program.statements.last() else {
+            panic!("expected an extern block");
+        };
+        assert_eq!(block.library, "libgeometry.so");
+        let point = FFIType::structure(vec![("x".to_string(), FFIType::Float64), ("y".to_string(), FFIType::Float64)]);
+        let distance = &block.functions[0];
+        assert_eq!(distance.name, "distance");
+        assert_eq!(distance.signature.parameters[1].ffi_type, point);
+        assert_eq!(distance.signature.return_type, FFIType::Float64);
+        let default_style = &block.functions[4];
+        assert_eq!(default_style.signature.return_type.layout(), (40, 8));
+        assert_eq!(default_style.signature.parameters[1].ffi_type, FFIType::Pointer(Box::new(FFIType::UInt64)));
+    }
+
+    #[test]
+    fn test_struct_layouts_match_c() {
+        if !have_c_compiler() {
+            return;
+        }
+        let header = "struct mixed { char tag; double value; short count; };\n\
+                      struct nested { unsigned char flag; struct mixed inner; float scale; long long total; };\n";
+        let bindings = Bindgen::new("libtest.so").generate(header);
+        let tokens = crate::lexer::Lexer::new(&bindings.source, "bindings.nx".to_string()).tokenize().unwrap();
+        crate::parser::Parser::new(tokens).parse().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("neksis-bindgen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = format!(
+            "{}#include <stdio.h>\n#include <stddef.h>\nint main(void) {{\n\
+             printf(\"%zu %zu %zu %zu\\n\", sizeof(struct mixed), offsetof(struct mixed, value), offsetof(struct mixed, count), _Alignof(struct mixed));\n\
+             printf(\"%zu %zu %zu %zu\\n\", sizeof(struct nested), offsetof(struct nested, inner), offsetof(struct nested, total), _Alignof(struct nested));\n\
+             return 0;\n}}\n",
+            header
+        );
+        std::fs::write(dir.join("layout.c"), program).unwrap();
+        let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let status = Command::new(compiler).arg(dir.join("layout.c")).arg("-o").arg(dir.join("layout")).status().unwrap();
+        assert!(status.success());
+        let output = Command::new(dir.join("layout")).output().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mixed = FFIType::structure(vec![
+            ("tag".to_string(), FFIType::Int8),
+            ("value".to_string(), FFIType::Float64),
+            ("count".to_string(), FFIType::Int16),
+        ]);
+        let nested = FFIType::structure(vec![
+            ("flag".to_string(), FFIType::UInt8),
+            ("inner".to_string(), mixed.clone()),
+            ("scale".to_string(), FFIType::Float32),
+            ("total".to_string(), FFIType::Int64),
+        ]);
+        let describe = |ty: &FFIType, fields: [usize; 2]| {
+            let FFIType::Struct(layout) = ty else { unreachable!() };
+            format!("{} {} {} {}", ty.layout().0, layout[fields[0]].offset, layout[fields[1]].offset, ty.layout().1)
+        };
+        let expected = format!("{}\n{}\n", describe(&mixed, [1, 2]), describe(&nested, [1, 3]));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+        assert!(bindings.source.contains("// 24 bytes, aligned to 8\n    struct mixed"), "{}", bindings.source);
+        assert!(bindings.source.contains("inner: mixed,  // offset 8"), "{}", bindings.source);
+    }
+
+    #[test]
+    fn test_bindings_call_libc() {
+        let header = r#"
+typedef struct { int quot; int rem; } div_t;
+int abs(int n);
+size_t strlen(const char *s);
+div_t div(int numerator, int denominator);
+void qsort(long items[], size_t count, size_t size, int (*compare)(const long *, const long *));
+"#;
+        let bindings = Bindgen::new("libc.so.6").generate(header);
+        assert!(bindings.warnings.is_empty(), "{:?}", bindings.warnings);
+        let program = format!(
+            "{}\nfn descending(a: Int, b: Int) -> Int {{\n    return b - a;\n}}\n\n\
+             let d = div(17, 5);\n\
+             println(abs(-4) + strlen(\"neksis\"));\n\
+             println(dict_get(d, \"quot\") * 10 + dict_get(d, \"rem\"));\n\
+             let items = [3, 9, 1];\n\
+             qsort(items, len(items), 8, descending);\n\
+             println(items);\n",
+            bindings.source
+        );
+        let output = run_program_at(&program, "test.nx", 0);
+        assert_eq!(output.exit_code, 0, "{}", output.render());
+        assert_eq!(output.stdout, "10\n32\n[9, 3, 1]\n");
+    }
+
+    #[test]
+    fn test_unparseable_declarations_are_skipped() {
+        let header = "int broken(int x y);\nenum { A = sizeof(int), B };\nint fine(void);\n";
+        let bindings = Bindgen::new("libx.so").generate(header);
+        assert!(bindings.source.contains("fn fine();") || bindings.source.contains("fn fine() -> i32;"));
+        let warnings: Vec<String> = bindings.warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(
+            warnings,
+            [
+                "line 1: skipped a declaration: expected ')' at line 1, found 'y'",
+                "line 2: enum constant A is not supported: unknown name 'sizeof' at line 2",
+                "line 2: enum constant B follows a value that could not be computed",
+            ]
+        );
+    }
+}
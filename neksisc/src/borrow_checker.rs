@@ -1,6 +1,9 @@
-use crate::ast::{Expression, Statement, Type};
+use crate::ast::{
+    AssignmentStatement, Expression, FunctionStatement, LetStatement, MatchArm, Parameter, Pattern, Statement, Type,
+};
 use crate::error::CompilerError;
-use std::collections::HashMap;
+use crate::visit::{self, Visitor};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum BorrowType {
@@ -30,6 +33,7 @@ pub struct VariableState {
 
 pub struct BorrowChecker {
     variables: HashMap<String, VariableState>,
+    functions: HashSet<String>,
     current_scope: usize,
     scopes: Vec<HashMap<String, VariableState>>,
 }
@@ -38,6 +42,7 @@ impl BorrowChecker {
     pub fn new() -> Self {
         Self {
             variables: HashMap::new(),
+            functions: HashSet::new(),
             current_scope: 0,
             scopes: vec![HashMap::new()],
         }
@@ -53,67 +58,21 @@ impl BorrowChecker {
     fn check_statement(&mut self, statement: &Statement) -> Result<(), CompilerError> {
         match statement {
             Statement::LetStatement { name, value, var_type } => {
-                self.check_expression(value)?;
-                
-                // Determine borrow type based on type annotation
-                let borrow_type = if let Some(var_type) = var_type {
-                    self.get_borrow_type_from_type(var_type)
-                } else {
-                    BorrowType::Owned
-                };
-                
-                let variable_state = VariableState {
-                    borrow_type,
-                    borrows: Vec::new(),
-                    is_moved: false,
-                    is_frozen: false,
-                };
-                
-                self.variables.insert(name.clone(), variable_state);
+                self.check_let(name, var_type.as_ref(), value)?;
+            }
+            Statement::Let(LetStatement { name, type_annotation, value, .. }) => {
+                self.check_let(name, type_annotation.as_ref(), value)?;
             }
             Statement::AssignmentStatement { name, value } => {
-                self.check_expression(value)?;
-                
-                if let Some(var_state) = self.variables.get_mut(name) {
-                    if var_state.is_frozen {
-                        return Err(CompilerError::borrow_error(
-                            &format!("Cannot assign to frozen variable: {}", name)
-                        ));
-                    }
-                    
-                    if var_state.borrow_type == BorrowType::Immutable {
-                        return Err(CompilerError::borrow_error(
-                            &format!("Cannot assign to immutable variable: {}", name)
-                        ));
-                    }
-                } else {
-                    return Err(CompilerError::borrow_error(
-                        &format!("Undefined variable: {}", name)
-                    ));
-                }
+                self.check_assignment(name, value)?;
             }
-            Statement::FunctionStatement { name: _, parameters, return_type: _, body } => {
-                // Enter function scope
-                self.enter_scope();
-                
-                // Check parameters
-                for param in parameters {
-                    let borrow_type = self.get_borrow_type_from_type(&param.type_annotation);
-                    
-                    let variable_state = VariableState {
-                        borrow_type,
-                        borrows: Vec::new(),
-                        is_moved: false,
-                        is_frozen: false,
-                    };
-                    
-                    self.variables.insert(param.name.clone(), variable_state);
-                }
-                
-                // Check function body
-                self.check_expression(body)?;
-                
-                self.exit_scope();
+            Statement::FunctionStatement { name, parameters, return_type: _, body } => {
+                self.functions.insert(name.clone());
+                self.check_scoped(parameters, body)?;
+            }
+            Statement::Function(FunctionStatement { name, parameters, body, .. }) => {
+                self.functions.insert(name.clone());
+                self.check_scoped(parameters, body)?;
             }
             Statement::ReturnStatement { value } => {
                 if let Some(expr) = value {
@@ -124,13 +83,72 @@ impl BorrowChecker {
                 self.check_expression(expression)?;
             }
             _ => {
-                // For other statement types, do basic checking
-                return Ok(());
+                // For other statement types, check the expressions they contain
+                let mut children = ChildChecker { checker: self, result: Ok(()) };
+                visit::walk_statement(&mut children, statement);
+                return children.result;
             }
         }
         Ok(())
     }
 
+    fn check_let(&mut self, name: &str, var_type: Option<&Type>, value: &Expression) -> Result<(), CompilerError> {
+        self.check_expression(value)?;
+
+        // Determine borrow type based on type annotation
+        let borrow_type = match var_type {
+            Some(var_type) => self.get_borrow_type_from_type(var_type),
+            None => BorrowType::Owned,
+        };
+        self.declare(name, borrow_type);
+        Ok(())
+    }
+
+    fn check_assignment(&mut self, name: &str, value: &Expression) -> Result<(), CompilerError> {
+        self.check_expression(value)?;
+
+        if let Some(var_state) = self.variables.get_mut(name) {
+            if var_state.is_frozen {
+                return Err(CompilerError::borrow_error(
+                    &format!("Cannot assign to frozen variable: {}", name)
+                ));
+            }
+
+            if var_state.borrow_type == BorrowType::Immutable {
+                return Err(CompilerError::borrow_error(
+                    &format!("Cannot assign to immutable variable: {}", name)
+                ));
+            }
+            Ok(())
+        } else {
+            Err(CompilerError::borrow_error(
+                &format!("Undefined variable: {}", name)
+            ))
+        }
+    }
+
+    /// Checks `body` in a new scope holding `parameters`
+    fn check_scoped(&mut self, parameters: &[Parameter], body: &Expression) -> Result<(), CompilerError> {
+        self.enter_scope();
+        for param in parameters {
+            let borrow_type = self.get_borrow_type_from_type(&param.type_annotation);
+            self.declare(&param.name, borrow_type);
+        }
+        let result = self.check_expression(body);
+        self.exit_scope();
+        result
+    }
+
+    fn declare(&mut self, name: &str, borrow_type: BorrowType) {
+        let variable_state = VariableState {
+            borrow_type,
+            borrows: Vec::new(),
+            is_moved: false,
+            is_frozen: false,
+        };
+        self.variables.insert(name.to_string(), variable_state);
+    }
+
     fn check_expression(&mut self, expression: &Expression) -> Result<(), CompilerError> {
         match expression {
             Expression::Literal(_) => {
@@ -158,10 +176,12 @@ impl BorrowChecker {
                         }
                     }
                     Ok(())
+                } else if self.functions.contains(name) || self.is_builtin_function(name) {
+                    Ok(())
                 } else {
-                    return Err(CompilerError::borrow_error(
+                    Err(CompilerError::borrow_error(
                         &format!("Undefined variable: {}", name)
-                    ));
+                    ))
                 }
             }
 
@@ -181,7 +201,7 @@ impl BorrowChecker {
                 }
                 
                 // Check if function exists (simplified)
-                if !self.is_builtin_function(function) {
+                if !self.is_builtin_function(function) && !self.functions.contains(function) {
                     return Err(CompilerError::borrow_error(
                         &format!("Undefined function: {}", function)
                     ));
@@ -227,9 +247,24 @@ impl BorrowChecker {
                 self.check_expression(target)?;
                 Ok(())
             }
+            Expression::Let(LetStatement { name, type_annotation, value, .. }) => {
+                self.check_let(name, type_annotation.as_ref(), value)
+            }
+            Expression::Assignment(AssignmentStatement { target, value }) => {
+                self.check_assignment(target, value)
+            }
+            Expression::Block(statements) => {
+                self.enter_scope();
+                let result = statements.iter().try_for_each(|stmt| self.check_statement(stmt));
+                self.exit_scope();
+                result
+            }
+            Expression::Lambda(lambda) => self.check_scoped(&lambda.parameters, &lambda.body),
             _ => {
-                // For other expression types, do basic checking
-                Ok(())
+                // For other expression types, check the children
+                let mut children = ChildChecker { checker: self, result: Ok(()) };
+                visit::walk_expression(&mut children, expression);
+                children.result
             }
         }
     }
@@ -363,4 +398,37 @@ impl Default for BorrowChecker {
     fn default() -> Self {
         Self::new()
     }
-} 
\ No newline at end of file
+}
+
+/// Runs the borrow checker on each child node it is given, keeping the first error
+struct ChildChecker<'a> {
+    checker: &'a mut BorrowChecker,
+    result: Result<(), CompilerError>,
+}
+
+impl Visitor for ChildChecker<'_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if self.result.is_ok() {
+            self.result = self.checker.check_statement(statement);
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if self.result.is_ok() {
+            self.result = self.checker.check_expression(expression);
+        }
+    }
+
+    fn visit_function(&mut self, function: &FunctionStatement) {
+        if self.result.is_ok() {
+            self.result = self.checker.check_scoped(&function.parameters, &function.body);
+        }
+    }
+
+    fn visit_match_arm(&mut self, arm: &MatchArm) {
+        if let Pattern::Identifier(name) = &arm.pattern {
+            self.checker.declare(name, BorrowType::Owned);
+        }
+        visit::walk_match_arm(self, arm);
+    }
+}
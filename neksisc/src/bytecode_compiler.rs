@@ -1,13 +1,23 @@
 use crate::ast::{
-    Program, Statement, Expression, Literal, BinaryOperator, UnaryOperator
+    Program, Statement, Expression, Literal, BinaryOperator, UnaryOperator, Pattern
 };
 use crate::vm::BytecodeInstruction;
 use crate::error::CompilerError;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub struct BytecodeCompiler {
     instructions: Vec<BytecodeInstruction>,
     function_definitions: HashMap<String, Vec<BytecodeInstruction>>,
+    // Source line of the next top-level statement, see `Statement::SourceLine`
+    pending_line: Option<usize>,
+    // Number of match expressions compiled, used to name their hidden subject variables
+    match_count: usize,
+    // How many `try` blocks enclose the code being compiled
+    try_depth: usize,
+    // Names declared in `extern` blocks
+    extern_functions: HashSet<String>,
+    // Names bound by `import python`
+    python_modules: HashSet<String>,
 }
 
 impl BytecodeCompiler {
@@ -15,23 +25,50 @@ impl BytecodeCompiler {
         Self {
             instructions: Vec::new(),
             function_definitions: HashMap::new(),
+            pending_line: None,
+            match_count: 0,
+            try_depth: 0,
+            extern_functions: HashSet::new(),
+            python_modules: HashSet::new(),
         }
     }
+
+    // Treat `names` as C functions even when they are not declared in the
+    // program being compiled, e.g. a single function compiled on its own
+    pub fn with_extern_functions(mut self, names: HashSet<String>) -> Self {
+        self.extern_functions.extend(names);
+        self
+    }
+
+    // Treat `names` as Python modules, as `with_extern_functions` does for C functions
+    pub fn with_python_modules(mut self, names: HashSet<String>) -> Self {
+        self.python_modules.extend(names);
+        self
+    }
+
+    /// Names bound by `import python` statements in `program`
+    pub fn python_modules(program: &Program) -> HashSet<String> {
+        program.statements.iter().filter_map(|statement| match statement {
+            Statement::PythonImport(import) => Some(import.binding().to_string()),
+            _ => None,
+        }).collect()
+    }
+
+    /// Names of the C functions declared by `extern` blocks in `program`
+    pub fn extern_functions(program: &Program) -> HashSet<String> {
+        program.statements.iter().flat_map(|statement| match statement {
+            Statement::Extern(block) => block.functions.iter().map(|function| function.name.clone()).collect(),
+            _ => Vec::new(),
+        }).collect()
+    }
     
     pub fn compile_program(&mut self, program: &Program) -> Result<Vec<BytecodeInstruction>, CompilerError> {
-        let mut has_main_function = false;
-        
-        // First pass: compile all statements and track if main exists
-        for statement in &program.statements {
-            if let Statement::Function(func_stmt) = statement {
-                if func_stmt.name == "main" {
-                    has_main_function = true;
-                }
-            }
-            self.compile_statement(statement)?;
-        }
+        self.compile_module(program)?;
         
         // If there's a main function, automatically call it
+        let has_main_function = program.statements.iter().any(|statement| {
+            matches!(statement, Statement::Function(func_stmt) if func_stmt.name == "main")
+        });
         if has_main_function {
             self.instructions.push(BytecodeInstruction::Call("main".to_string(), 0));
         }
@@ -39,8 +76,31 @@ impl BytecodeCompiler {
         Ok(self.instructions.clone())
     }
     
+    // Compile every statement without the implicit call to `main`, so callers
+    // such as the test runner can choose which function to invoke
+    pub fn compile_module(&mut self, program: &Program) -> Result<Vec<BytecodeInstruction>, CompilerError> {
+        self.extern_functions.extend(Self::extern_functions(program));
+        self.python_modules.extend(Self::python_modules(program));
+        for statement in &program.statements {
+            self.compile_statement(statement)?;
+        }
+        
+        Ok(self.instructions.clone())
+    }
+    
     fn compile_statement(&mut self, statement: &Statement) -> Result<(), CompilerError> {
+        // A function's line marker goes inside its body, where it marks the code a call runs
+        let line = self.pending_line.take();
+        if let Some(line) = line {
+            if !matches!(statement, Statement::Function(_)) {
+                self.instructions.push(BytecodeInstruction::Line(line));
+            }
+        }
+        
         match statement {
+            Statement::SourceLine(line) => {
+                self.pending_line = Some(*line);
+            }
             Statement::Let(let_stmt) => {
                 self.compile_expression(&let_stmt.value)?;
                 self.instructions.push(BytecodeInstruction::Store(let_stmt.name.clone()));
@@ -63,7 +123,7 @@ impl BytecodeCompiler {
                         self.instructions.push(BytecodeInstruction::Pop);
                     }
                     Expression::FunctionCall(_, _) => {
-                        // Function calls may or may not leave values, most do
+                        // Every call leaves its result, pop it
                         self.instructions.push(BytecodeInstruction::Pop);
                     }
                     _ => {
@@ -90,6 +150,9 @@ impl BytecodeCompiler {
                 // Compile function body
                 if let Expression::Block(statements) = &*func_stmt.body {
                     let mut temp_instructions = Vec::new();
+                    if let Some(line) = line {
+                        temp_instructions.push(BytecodeInstruction::Line(line));
+                    }
                     
                     // Generate parameter storage instructions
                     // Parameters are expected to be on the stack in reverse order
@@ -121,6 +184,9 @@ impl BytecodeCompiler {
                             BytecodeInstruction::JumpIfTrue(addr) => {
                                 *addr += function_start_in_main;
                             }
+                            BytecodeInstruction::Try(addr) => {
+                                *addr += function_start_in_main;
+                            }
                             _ => {}
                         }
                     }
@@ -129,6 +195,18 @@ impl BytecodeCompiler {
                 }
                 self.instructions.push(BytecodeInstruction::EndFunction);
             }
+            Statement::Extern(block) => {
+                for function in &block.functions {
+                    self.instructions.push(BytecodeInstruction::ExternFunction(
+                        block.library.clone(),
+                        function.name.clone(),
+                        function.signature.clone(),
+                    ));
+                }
+            }
+            Statement::PythonImport(import) => {
+                self.instructions.push(BytecodeInstruction::ImportPython(import.module.clone(), import.alias.clone()));
+            }
             _ => return Err(CompilerError::syntax_error("Unsupported statement type")),
         }
         Ok(())
@@ -136,6 +214,9 @@ impl BytecodeCompiler {
     
     fn compile_statement_for_function(&mut self, statement: &Statement, instructions: &mut Vec<BytecodeInstruction>) -> Result<(), CompilerError> {
         match statement {
+            Statement::SourceLine(line) => {
+                instructions.push(BytecodeInstruction::Line(*line));
+            }
             Statement::Let(let_stmt) => {
                 self.compile_expression_for_function(&let_stmt.value, instructions)?;
                 instructions.push(BytecodeInstruction::Store(let_stmt.name.clone()));
@@ -157,22 +238,9 @@ impl BytecodeCompiler {
                             // If expressions in function context now leave a null value, pop it in statement context
                             instructions.push(BytecodeInstruction::Pop);
                         }
-                        Expression::FunctionCall(func, _) => {
-                            // Check what kind of function call this is
-                            if let Expression::Identifier(func_name) = &**func {
-                                match func_name.as_str() {
-                                    "print" | "println" => {
-                                        // Print functions don't leave results on the stack
-                                    }
-                                    _ => {
-                                        // Other function calls may leave values, pop them
-                                        instructions.push(BytecodeInstruction::Pop);
-                                    }
-                                }
-                            } else {
-                                // Other function calls may leave values, pop them
-                                instructions.push(BytecodeInstruction::Pop);
-                            }
+                        Expression::FunctionCall(_, _) => {
+                            // Every call leaves its result, pop it
+                            instructions.push(BytecodeInstruction::Pop);
                         }
                         _ => {
                             // Other expressions leave values, pop them
@@ -184,6 +252,17 @@ impl BytecodeCompiler {
             Statement::Return(return_stmt) => {
                 if let Some(expr) = &return_stmt.value {
                     self.compile_expression_for_function(expr, instructions)?;
+                    // Returning a call's result directly lets the callee reuse this frame,
+                    // unless an enclosing `try` has to catch its errors
+                    if self.try_depth == 0 && matches!(&**expr, Expression::FunctionCall(..)) {
+                        if let Some(last) = instructions.last_mut() {
+                            if let BytecodeInstruction::Call(name, arg_count) = last {
+                                if !name.is_empty() {
+                                    *last = BytecodeInstruction::TailCall(std::mem::take(name), *arg_count);
+                                }
+                            }
+                        }
+                    }
                 } else {
                     instructions.push(BytecodeInstruction::PushNull);
                 }
@@ -204,7 +283,8 @@ impl BytecodeCompiler {
                     Literal::Bool(value) => self.instructions.push(BytecodeInstruction::PushBool(*value)),
                     Literal::Char(value) => self.instructions.push(BytecodeInstruction::PushString(value.to_string())),
                     Literal::Array(elements) => {
-                        // Create array with elements
+                        // Create the array, then push each element onto it
+                        self.instructions.push(BytecodeInstruction::NewArray);
                         for element in elements {
                             match element {
                                 Literal::Int(value) => self.instructions.push(BytecodeInstruction::PushInt(*value)),
@@ -215,8 +295,8 @@ impl BytecodeCompiler {
                                 Literal::Null => self.instructions.push(BytecodeInstruction::PushNull),
                                 _ => return Err(CompilerError::syntax_error("Unsupported array element type")),
                             }
+                            self.instructions.push(BytecodeInstruction::ArrayPush);
                         }
-                        self.instructions.push(BytecodeInstruction::NewArray);
                     },
                     Literal::Null => self.instructions.push(BytecodeInstruction::PushNull),
                 }
@@ -257,12 +337,24 @@ impl BytecodeCompiler {
                 for arg in args {
                     self.compile_expression(&arg.value)?;
                 }
+                if let Some((module, path)) = self.python_path(function) {
+                    let names = args.iter().map(|arg| arg.name.clone()).collect();
+                    self.instructions.push(BytecodeInstruction::CallPython(module, path, names));
+                    return Ok(());
+                }
                 
                 match &**function {
                     Expression::Identifier(func_name) => {
                         match func_name.as_str() {
-                            "print" => self.instructions.push(BytecodeInstruction::Println),
-                            "println" => self.instructions.push(BytecodeInstruction::Println),
+                            // C functions declared in an `extern` block take precedence over builtins
+                            name if self.extern_functions.contains(name) => {
+                                self.instructions.push(BytecodeInstruction::Call(func_name.clone(), args.len()));
+                            }
+                            // Like every call, print leaves a result: null
+                            "print" | "println" => {
+                                self.instructions.push(BytecodeInstruction::Println);
+                                self.instructions.push(BytecodeInstruction::PushNull);
+                            }
                             "read_line" => self.instructions.push(BytecodeInstruction::ReadLine),
                             "read_file" => self.instructions.push(BytecodeInstruction::ReadFile),
                             "write_file" => self.instructions.push(BytecodeInstruction::WriteFile),
@@ -340,36 +432,12 @@ impl BytecodeCompiler {
             }
             Expression::Block(statements) => {
                 let mut temp_instructions = Vec::new();
-                let mut has_return_value = false;
-                
-                for (i, statement) in statements.iter().enumerate() {
+                for statement in statements {
                     self.compile_statement_for_function(statement, &mut temp_instructions)?;
-                    
-                    // For block expressions, only the last statement should leave a value on the stack
-                    // All other statements should have their values popped
-                    if i < statements.len() - 1 {
-                        // Not the last statement - check if it's an expression that leaves a value
-                        if let Statement::Expression(expr) = statement {
-                            if !matches!(expr, Expression::Block(_)) && 
-                               !matches!(expr, Expression::FunctionCall(_, _)) &&
-                               !matches!(expr, Expression::Assignment(_)) {
-                                // This statement left a value, but it's not the last one, so pop it
-                                // Actually, compile_statement_for_function already handles popping for expressions
-                                // So we don't need to add extra pops here
-                            }
-                        }
-                    } else {
-                        // This is the last statement - check if it provides a return value
-                        if let Statement::Expression(_) = statement {
-                            has_return_value = true;
-                        }
-                    }
                 }
                 
-                // If the block doesn't have a return value from the last statement, push null
-                if !has_return_value {
-                    temp_instructions.push(BytecodeInstruction::PushNull);
-                }
+                // Statements leave nothing on the stack, so the block's value is always null
+                temp_instructions.push(BytecodeInstruction::PushNull);
                 
                 // Adjust jump addresses to be relative to the main instructions array
                 let block_start_in_main = self.instructions.len();
@@ -384,6 +452,9 @@ impl BytecodeCompiler {
                         BytecodeInstruction::JumpIfTrue(addr) => {
                             *addr += block_start_in_main;
                         }
+                        BytecodeInstruction::Try(addr) => {
+                            *addr += block_start_in_main;
+                        }
                         _ => {}
                     }
                 }
@@ -398,8 +469,9 @@ impl BytecodeCompiler {
                 let jump_if_false_index = self.instructions.len();
                 self.instructions.push(BytecodeInstruction::JumpIfFalse(0));
                 
-                // Compile then branch
+                // Compile then branch; the if produces its own null below
                 self.compile_expression(&if_expr.then_branch)?;
+                self.instructions.push(BytecodeInstruction::Pop);
                 
                 if let Some(else_expr) = &if_expr.else_branch {
                     // Add jump to skip else branch (placeholder)
@@ -412,6 +484,7 @@ impl BytecodeCompiler {
                     
                     // Compile else branch
                     self.compile_expression(else_expr)?;
+                    self.instructions.push(BytecodeInstruction::Pop);
                     
                     // Update the unconditional jump to point after the else branch
                     let after_if = self.instructions.len();
@@ -428,6 +501,31 @@ impl BytecodeCompiler {
                     self.instructions.push(BytecodeInstruction::PushNull);
                 }
             }
+            Expression::TryCatch(try_catch) => {
+                let try_index = self.instructions.len();
+                self.instructions.push(BytecodeInstruction::Try(0));
+                self.try_depth += 1;
+                self.compile_expression(&try_catch.try_block)?;
+                self.try_depth -= 1;
+                self.instructions.push(BytecodeInstruction::Pop);
+                self.instructions.push(BytecodeInstruction::EndTry);
+                let jump_index = self.instructions.len();
+                self.instructions.push(BytecodeInstruction::Jump(0));
+                
+                // An error jumps here with its message on the stack
+                self.instructions[try_index] = BytecodeInstruction::Try(self.instructions.len());
+                match &try_catch.catch_variable {
+                    Some(name) => self.instructions.push(BytecodeInstruction::Store(name.clone())),
+                    None => self.instructions.push(BytecodeInstruction::Pop),
+                }
+                self.compile_expression(&try_catch.catch_block)?;
+                self.instructions.push(BytecodeInstruction::Pop);
+                
+                let after_try = self.instructions.len();
+                self.instructions[jump_index] = BytecodeInstruction::Jump(after_try);
+                // Like if expressions, try expressions produce null
+                self.instructions.push(BytecodeInstruction::PushNull);
+            }
             Expression::While(while_expr) => {
                 // Mark the start of the loop
                 let loop_start = self.instructions.len();
@@ -439,8 +537,9 @@ impl BytecodeCompiler {
                 let jump_if_false_index = self.instructions.len();
                 self.instructions.push(BytecodeInstruction::JumpIfFalse(0));
                 
-                // Compile loop body
+                // Compile loop body, dropping its value so the stack does not grow per iteration
                 self.compile_expression(&while_expr.body)?;
+                self.instructions.push(BytecodeInstruction::Pop);
                 
                 // Add unconditional jump back to loop start
                 self.instructions.push(BytecodeInstruction::Jump(loop_start));
@@ -492,6 +591,43 @@ impl BytecodeCompiler {
                     }
                 }
             }
+            Expression::Match(match_expr) => {
+                // Keep the subject in a hidden variable so every arm can test it
+                let subject = self.next_match_subject();
+                self.compile_expression(&match_expr.expression)?;
+                self.instructions.push(BytecodeInstruction::Store(subject.clone()));
+                
+                let mut end_jumps = Vec::new();
+                for arm in &match_expr.arms {
+                    let test = pattern_test(&arm.pattern, &subject)?;
+                    let next_arm_jump = if test.is_empty() {
+                        None
+                    } else {
+                        self.instructions.extend(test);
+                        self.instructions.push(BytecodeInstruction::JumpIfFalse(0));
+                        Some(self.instructions.len() - 1)
+                    };
+                    if let Pattern::Identifier(name) = &arm.pattern {
+                        self.instructions.push(BytecodeInstruction::Load(subject.clone()));
+                        self.instructions.push(BytecodeInstruction::Store(name.clone()));
+                    }
+                    
+                    self.compile_expression(&arm.body)?;
+                    end_jumps.push(self.instructions.len());
+                    self.instructions.push(BytecodeInstruction::Jump(0));
+                    
+                    if let Some(jump) = next_arm_jump {
+                        self.instructions[jump] = BytecodeInstruction::JumpIfFalse(self.instructions.len());
+                    }
+                }
+                
+                // A match without a matching arm produces null
+                self.instructions.push(BytecodeInstruction::PushNull);
+                let after_match = self.instructions.len();
+                for jump in end_jumps {
+                    self.instructions[jump] = BytecodeInstruction::Jump(after_match);
+                }
+            }
             Expression::Assignment(assign_expr) => {
                 // Compile the value to assign
                 self.compile_expression(&assign_expr.value)?;
@@ -501,6 +637,12 @@ impl BytecodeCompiler {
                 self.instructions.push(BytecodeInstruction::Store(assign_expr.target.clone()));
                 // The duplicate value remains on stack as the expression's result
             }
+            Expression::MemberAccess(_) => {
+                let Some((module, path)) = self.python_path(expression) else {
+                    return Err(CompilerError::syntax_error(&format!("Unsupported expression type: {:?}", expression)));
+                };
+                self.instructions.push(BytecodeInstruction::LoadPython(module, path));
+            }
             _ => return Err(CompilerError::syntax_error(&format!("Unsupported expression type: {:?}", expression))),
         }
         Ok(())
@@ -516,7 +658,8 @@ impl BytecodeCompiler {
                     Literal::Bool(value) => instructions.push(BytecodeInstruction::PushBool(*value)),
                     Literal::Char(value) => instructions.push(BytecodeInstruction::PushString(value.to_string())),
                     Literal::Array(elements) => {
-                        // Create array with elements
+                        // Create the array, then push each element onto it
+                        instructions.push(BytecodeInstruction::NewArray);
                         for element in elements {
                             match element {
                                 Literal::Int(value) => instructions.push(BytecodeInstruction::PushInt(*value)),
@@ -527,8 +670,8 @@ impl BytecodeCompiler {
                                 Literal::Null => instructions.push(BytecodeInstruction::PushNull),
                                 _ => return Err(CompilerError::syntax_error("Unsupported array element type")),
                             }
+                            instructions.push(BytecodeInstruction::ArrayPush);
                         }
-                        instructions.push(BytecodeInstruction::NewArray);
                     },
                     Literal::Null => instructions.push(BytecodeInstruction::PushNull),
                 }
@@ -569,12 +712,23 @@ impl BytecodeCompiler {
                 for arg in args {
                     self.compile_expression_for_function(&arg.value, instructions)?;
                 }
+                if let Some((module, path)) = self.python_path(function) {
+                    let names = args.iter().map(|arg| arg.name.clone()).collect();
+                    instructions.push(BytecodeInstruction::CallPython(module, path, names));
+                    return Ok(());
+                }
                 
                 match &**function {
                     Expression::Identifier(func_name) => {
                         match func_name.as_str() {
-                            "print" => instructions.push(BytecodeInstruction::Println),
-                            "println" => instructions.push(BytecodeInstruction::Println),
+                            name if self.extern_functions.contains(name) => {
+                                instructions.push(BytecodeInstruction::Call(func_name.clone(), args.len()));
+                            }
+                            // Like every call, print leaves a result: null
+                            "print" | "println" => {
+                                instructions.push(BytecodeInstruction::Println);
+                                instructions.push(BytecodeInstruction::PushNull);
+                            }
                             "read_line" => instructions.push(BytecodeInstruction::ReadLine),
                             "read_file" => instructions.push(BytecodeInstruction::ReadFile),
                             "write_file" => instructions.push(BytecodeInstruction::WriteFile),
@@ -651,23 +805,12 @@ impl BytecodeCompiler {
                 }
             }
             Expression::Block(statements) => {
-                let mut has_return_value = false;
-                
-                for (i, statement) in statements.iter().enumerate() {
+                for statement in statements {
                     self.compile_statement_for_function(statement, instructions)?;
-                    
-                    // For the last statement in a block, check if it provides a return value
-                    if i == statements.len() - 1 {
-                        if let Statement::Expression(_) = statement {
-                            has_return_value = true;
-                        }
-                    }
                 }
                 
-                // If the block doesn't have a return value from the last statement, push null
-                if !has_return_value {
-                    instructions.push(BytecodeInstruction::PushNull);
-                }
+                // Statements leave nothing on the stack, so the block's value is always null
+                instructions.push(BytecodeInstruction::PushNull);
             }
             Expression::If(if_expr) => {
                 // Compile condition
@@ -677,8 +820,9 @@ impl BytecodeCompiler {
                 let jump_if_false_index = instructions.len();
                 instructions.push(BytecodeInstruction::JumpIfFalse(0));
                 
-                // Compile then branch
+                // Compile then branch; the if produces its own null below
                 self.compile_expression_for_function(&if_expr.then_branch, instructions)?;
+                instructions.push(BytecodeInstruction::Pop);
                 
                 if let Some(else_expr) = &if_expr.else_branch {
                     // Add jump to skip else branch (placeholder)
@@ -691,6 +835,7 @@ impl BytecodeCompiler {
                     
                     // Compile else branch
                     self.compile_expression_for_function(else_expr, instructions)?;
+                    instructions.push(BytecodeInstruction::Pop);
                     
                     // Update the unconditional jump to point to after the else branch
                     let after_else = instructions.len();
@@ -707,6 +852,31 @@ impl BytecodeCompiler {
                     instructions.push(BytecodeInstruction::PushNull);
                 }
             }
+            Expression::TryCatch(try_catch) => {
+                let try_index = instructions.len();
+                instructions.push(BytecodeInstruction::Try(0));
+                self.try_depth += 1;
+                self.compile_expression_for_function(&try_catch.try_block, instructions)?;
+                self.try_depth -= 1;
+                instructions.push(BytecodeInstruction::Pop);
+                instructions.push(BytecodeInstruction::EndTry);
+                let jump_index = instructions.len();
+                instructions.push(BytecodeInstruction::Jump(0));
+                
+                // An error jumps here with its message on the stack
+                instructions[try_index] = BytecodeInstruction::Try(instructions.len());
+                match &try_catch.catch_variable {
+                    Some(name) => instructions.push(BytecodeInstruction::Store(name.clone())),
+                    None => instructions.push(BytecodeInstruction::Pop),
+                }
+                self.compile_expression_for_function(&try_catch.catch_block, instructions)?;
+                instructions.push(BytecodeInstruction::Pop);
+                
+                let after_try = instructions.len();
+                instructions[jump_index] = BytecodeInstruction::Jump(after_try);
+                // Like if expressions, try expressions produce null
+                instructions.push(BytecodeInstruction::PushNull);
+            }
             Expression::While(while_expr) => {
                 // Mark the start of the loop
                 let loop_start = instructions.len();
@@ -718,8 +888,9 @@ impl BytecodeCompiler {
                 let jump_if_false_index = instructions.len();
                 instructions.push(BytecodeInstruction::JumpIfFalse(0));
                 
-                // Compile loop body
+                // Compile loop body, dropping its value so the stack does not grow per iteration
                 self.compile_expression_for_function(&while_expr.body, instructions)?;
+                instructions.push(BytecodeInstruction::Pop);
                 
                 // Add unconditional jump back to loop start
                 instructions.push(BytecodeInstruction::Jump(loop_start));
@@ -780,14 +951,111 @@ impl BytecodeCompiler {
                 instructions.push(BytecodeInstruction::Store(assign_expr.target.clone()));
                 // The duplicate value remains on stack as the expression's result
             }
+            Expression::Match(match_expr) => {
+                // Keep the subject in a hidden variable so every arm can test it
+                let subject = self.next_match_subject();
+                self.compile_expression_for_function(&match_expr.expression, instructions)?;
+                instructions.push(BytecodeInstruction::Store(subject.clone()));
+                
+                let mut end_jumps = Vec::new();
+                for arm in &match_expr.arms {
+                    let test = pattern_test(&arm.pattern, &subject)?;
+                    let next_arm_jump = if test.is_empty() {
+                        None
+                    } else {
+                        instructions.extend(test);
+                        instructions.push(BytecodeInstruction::JumpIfFalse(0));
+                        Some(instructions.len() - 1)
+                    };
+                    if let Pattern::Identifier(name) = &arm.pattern {
+                        instructions.push(BytecodeInstruction::Load(subject.clone()));
+                        instructions.push(BytecodeInstruction::Store(name.clone()));
+                    }
+                    
+                    self.compile_expression_for_function(&arm.body, instructions)?;
+                    end_jumps.push(instructions.len());
+                    instructions.push(BytecodeInstruction::Jump(0));
+                    
+                    if let Some(jump) = next_arm_jump {
+                        instructions[jump] = BytecodeInstruction::JumpIfFalse(instructions.len());
+                    }
+                }
+                
+                // A match without a matching arm produces null
+                instructions.push(BytecodeInstruction::PushNull);
+                let after_match = instructions.len();
+                for jump in end_jumps {
+                    instructions[jump] = BytecodeInstruction::Jump(after_match);
+                }
+            }
+            Expression::MemberAccess(_) => {
+                let Some((module, path)) = self.python_path(expression) else {
+                    return Err(CompilerError::syntax_error(&format!("Unsupported expression type in function: {:?}", expression)));
+                };
+                instructions.push(BytecodeInstruction::LoadPython(module, path));
+            }
             _ => return Err(CompilerError::syntax_error(&format!("Unsupported expression type in function: {:?}", expression))),
         }
         Ok(())
     }
+
+    /// The module and attribute path of `module.name` or `module.a.b`, when `module` is bound by `import python`
+    fn python_path(&self, expression: &Expression) -> Option<(String, String)> {
+        let Expression::MemberAccess(access) = expression else {
+            return None;
+        };
+        match &*access.object {
+            Expression::Identifier(name) if self.python_modules.contains(name) => Some((name.clone(), access.member.clone())),
+            object => self.python_path(object).map(|(module, path)| (module, format!("{}.{}", path, access.member))),
+        }
+    }
     
 
     
+    fn next_match_subject(&mut self) -> String {
+        self.match_count += 1;
+        format!("__match_{}", self.match_count)
+    }
+    
     pub fn get_function_definitions(&self) -> HashMap<String, Vec<BytecodeInstruction>> {
         self.function_definitions.clone()
     }
-} 
\ No newline at end of file
+}
+
+// Instructions that leave whether `subject` matches `pattern` on the stack, or
+// none for patterns that always match
+fn pattern_test(pattern: &Pattern, subject: &str) -> Result<Vec<BytecodeInstruction>, CompilerError> {
+    match pattern {
+        Pattern::Wildcard | Pattern::Identifier(_) => Ok(Vec::new()),
+        Pattern::Literal(literal) => {
+            let value = match literal {
+                Literal::Int(value) => BytecodeInstruction::PushInt(*value),
+                Literal::Float(value) => BytecodeInstruction::PushFloat(*value),
+                Literal::String(value) => BytecodeInstruction::PushString(value.clone()),
+                Literal::Bool(value) => BytecodeInstruction::PushBool(*value),
+                Literal::Char(value) => BytecodeInstruction::PushString(value.to_string()),
+                Literal::Null => BytecodeInstruction::PushNull,
+                Literal::Array(_) => return Err(CompilerError::syntax_error("Array patterns are not supported")),
+            };
+            Ok(vec![BytecodeInstruction::Load(subject.to_string()), value, BytecodeInstruction::Eq])
+        }
+        Pattern::Or(alternatives) => {
+            let mut instructions = Vec::new();
+            for (i, alternative) in alternatives.iter().enumerate() {
+                let test = pattern_test(alternative, subject)?;
+                if test.is_empty() {
+                    instructions.push(BytecodeInstruction::PushBool(true));
+                } else {
+                    instructions.extend(test);
+                }
+                if i > 0 {
+                    instructions.push(BytecodeInstruction::Or);
+                }
+            }
+            Ok(instructions)
+        }
+        Pattern::Struct(..) | Pattern::Tuple(_) => {
+            Err(CompilerError::syntax_error("Struct and tuple patterns are not supported"))
+        }
+    }
+}
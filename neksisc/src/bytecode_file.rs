@@ -0,0 +1,104 @@
+// Bytecode Files
+//
+// `neksis build --emit-bytecode` saves a compiled program so that `neksis run`
+// and embedders can run it without the source. A file is the magic bytes
+// `NXBC`, a little-endian format version, then the bincode-encoded
+// instructions. The version changes whenever `BytecodeInstruction` does, and
+// files written by another version are rejected rather than misread.
+
+use crate::error::CompilerError;
+use crate::vm::BytecodeInstruction;
+
+pub const MAGIC: &[u8; 4] = b"NXBC";
+pub const FORMAT_VERSION: u32 = 1;
+
+const HEADER_LEN: usize = MAGIC.len() + 4;
+
+pub fn encode(instructions: &[BytecodeInstruction]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    // Encoding into memory only fails for types serde cannot represent
+    bincode::serialize_into(&mut bytes, instructions).expect("bytecode instructions are serializable");
+    bytes
+}
+
+/// Whether `bytes` start like a bytecode file rather than source
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<BytecodeInstruction>, CompilerError> {
+    if !is_bytecode(bytes) || bytes.len() < HEADER_LEN {
+        return Err(CompilerError::runtime_error("not a Neksis bytecode file"));
+    }
+    let version = u32::from_le_bytes(bytes[MAGIC.len()..HEADER_LEN].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(CompilerError::runtime_error(&format!(
+            "bytecode format version {} is not supported (expected {}); rebuild it from source",
+            version, FORMAT_VERSION
+        )));
+    }
+    bincode::deserialize(&bytes[HEADER_LEN..])
+        .map_err(|e| CompilerError::runtime_error(&format!("corrupt bytecode file: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::permissions::Capability;
+    use crate::vm::{OutputCapture, VMValue};
+
+    fn compile(source: &str) -> Vec<BytecodeInstruction> {
+        let tokens = crate::lexer::Lexer::new(source, "test.nx".to_string()).tokenize().unwrap();
+        let program = crate::parser::Parser::new(tokens).parse().unwrap();
+        crate::ir::compile_program(&program, 2).unwrap()
+    }
+
+    #[test]
+    fn test_bytecode_round_trip() {
+        let source = r#"
+extern "C" lib "libc.so.6" {
+    fn abs(n: i32) -> i32;
+}
+fn classify(n: Int) -> String {
+    if n < 0 { return "negative"; }
+    return "non-negative";
+}
+let i = 0;
+while i < 3 {
+    print(classify(i - 1) + " " + abs(i - 1));
+    i = i + 1;
+}
+try { let missing = nowhere; } catch (e) { print(e); }
+"#;
+        let bytes = encode(&compile(source));
+        assert!(is_bytecode(&bytes));
+
+        // Loaded after other code, so every jump is relocated
+        let capture = OutputCapture::new();
+        let mut engine = Engine::new();
+        engine.set_output_capture(capture.clone());
+        engine.allow_all(Capability::Ffi);
+        engine.run("let before = 1;").unwrap();
+        engine.run_bytecode(&bytes).unwrap();
+        assert_eq!(capture.stdout(), "negative 1\nnon-negative 0\nnon-negative 1\nUndefined variable: nowhere\n");
+        assert_eq!(engine.call::<VMValue>("classify", (-5,)).unwrap(), VMValue::String("negative".to_string()));
+    }
+
+    #[test]
+    fn test_bad_bytecode_is_rejected() {
+        let bytes = encode(&compile("print(1);"));
+        assert_eq!(decode(b"print(1);").unwrap_err().message, "not a Neksis bytecode file");
+        assert_eq!(decode(b"NXBC").unwrap_err().message, "not a Neksis bytecode file");
+
+        let mut future = bytes.clone();
+        future[4] = 99;
+        assert_eq!(
+            decode(&future).unwrap_err().message,
+            "bytecode format version 99 is not supported (expected 1); rebuild it from source"
+        );
+        let truncated = &bytes[..bytes.len() - 3];
+        assert!(decode(truncated).unwrap_err().message.starts_with("corrupt bytecode file: "));
+    }
+}
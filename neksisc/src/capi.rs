@@ -0,0 +1,517 @@
+// C API
+//
+// The `extern "C"` interface of the `libneksisc` shared library, declared for
+// C and C++ hosts in include/neksis.h. An engine is an opaque pointer to an
+// `Engine`, and values cross the boundary as `NeksisValue`s: a `NeksisType`
+// tag and a union. Tags and bools written by C are checked before use.
+// Functions that can fail return `NEKSIS_OK` or `NEKSIS_ERROR` and leave the
+// message for `neksis_last_error`; running code returns
+// `NEKSIS_LIMIT_EXCEEDED` instead when a limit set by `neksis_set_limits`
+// stopped it. A panic inside the library is caught at the boundary and
+// reported as `NEKSIS_ERROR` rather than unwinding into C.
+//
+// Strings passed in are copied. Strings handed out belong to the engine and
+// stay valid until the next call on it.
+
+use crate::engine::Engine;
+use crate::limits::Limits;
+use crate::permissions::Capability;
+use crate::vm::{VMValue, DEFAULT_MAX_CALL_DEPTH};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::rc::Rc;
+use std::time::Duration;
+
+pub const NEKSIS_OK: c_int = 0;
+pub const NEKSIS_ERROR: c_int = -1;
+pub const NEKSIS_LIMIT_EXCEEDED: c_int = -2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NeksisType {
+    Null,
+    Int,
+    Float,
+    Bool,
+    String,
+}
+
+impl NeksisType {
+    const ALL: [NeksisType; 5] = [Self::Null, Self::Int, Self::Float, Self::Bool, Self::String];
+
+    fn from_tag(tag: c_int) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| *kind as c_int == tag)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union NeksisData {
+    pub int_value: i64,
+    pub float_value: f64,
+    pub bool_value: bool,
+    /// NUL-terminated UTF-8
+    pub string_value: *const c_char,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct NeksisValue {
+    /// A `NeksisType`
+    pub tag: c_int,
+    pub data: NeksisData,
+}
+
+impl NeksisValue {
+    const NULL: Self = Self { tag: NeksisType::Null as c_int, data: NeksisData { int_value: 0 } };
+}
+
+/// Bounds on each later load or call. A field left at 0 sets no limit, or
+/// the default depth for `max_call_depth`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct NeksisLimits {
+    pub max_instructions: u64,
+    pub max_heap_bytes: usize,
+    pub max_call_depth: usize,
+    pub max_wall_time_ms: u64,
+}
+
+/// A native function registered with `neksis_register`. It stores its result
+/// in `*result` and returns `NEKSIS_OK`, or returns `NEKSIS_ERROR` with an
+/// optional message string in `*result`; either way, strings it returns are
+/// copied before it is called again.
+pub type NeksisCallback =
+    extern "C" fn(user_data: *mut c_void, args: *const NeksisValue, argc: usize, result: *mut NeksisValue) -> c_int;
+
+pub struct NeksisEngine {
+    engine: Engine,
+    last_error: Option<CString>,
+    // Strings handed to the host by the latest call
+    strings: Vec<CString>,
+}
+
+impl NeksisEngine {
+    fn finish(&mut self, outcome: Result<(), String>) -> c_int {
+        match outcome {
+            Ok(()) => NEKSIS_OK,
+            Err(message) => {
+                self.last_error = Some(CString::new(message.replace('\0', "\\0")).unwrap());
+                NEKSIS_ERROR
+            }
+        }
+    }
+
+    // `finish` for calls that run code, which a limit may have stopped
+    fn finish_run(&mut self, outcome: Result<(), String>) -> c_int {
+        let limited = outcome.is_err() && self.engine.limit_exceeded().is_some();
+        match self.finish(outcome) {
+            NEKSIS_ERROR if limited => NEKSIS_LIMIT_EXCEEDED,
+            status => status,
+        }
+    }
+}
+
+fn to_c(value: &VMValue, strings: &mut Vec<CString>) -> Result<NeksisValue, String> {
+    let (tag, data) = match value {
+        VMValue::Null => return Ok(NeksisValue::NULL),
+        VMValue::Int(n) => (NeksisType::Int, NeksisData { int_value: *n }),
+        VMValue::Float(x) => (NeksisType::Float, NeksisData { float_value: *x }),
+        VMValue::Bool(b) => (NeksisType::Bool, NeksisData { bool_value: *b }),
+        VMValue::String(s) => {
+            let string = CString::new(s.as_str()).map_err(|_| "a string containing a NUL byte cannot be passed to C".to_string())?;
+            let pointer = string.as_ptr();
+            strings.push(string);
+            (NeksisType::String, NeksisData { string_value: pointer })
+        }
+        other => return Err(format!("{} values cannot be passed to C", other.type_name())),
+    };
+    Ok(NeksisValue { tag: tag as c_int, data })
+}
+
+unsafe fn from_c(value: &NeksisValue) -> Result<VMValue, String> {
+    let kind = NeksisType::from_tag(value.tag).ok_or_else(|| format!("unknown value tag {}", value.tag))?;
+    Ok(match kind {
+        NeksisType::Null => VMValue::Null,
+        NeksisType::Int => VMValue::Int(value.data.int_value),
+        NeksisType::Float => VMValue::Float(value.data.float_value),
+        // C may store any byte in a bool, which Rust's `bool` cannot hold
+        NeksisType::Bool => VMValue::Bool(*(ptr::addr_of!(value.data) as *const u8) != 0),
+        NeksisType::String => VMValue::String(c_str(value.data.string_value, "string value")?.to_string()),
+    })
+}
+
+unsafe fn c_str<'a>(pointer: *const c_char, what: &str) -> Result<&'a str, String> {
+    if pointer.is_null() {
+        return Err(format!("{} is NULL", what));
+    }
+    CStr::from_ptr(pointer).to_str().map_err(|_| format!("{} is not valid UTF-8", what))
+}
+
+// Run `body`, turning a panic into an error so it never unwinds into C
+fn guard<T>(body: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let message = payload.downcast_ref::<&str>().copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+        Err(format!("Internal error: {}", message))
+    })
+}
+
+/// Create an engine, or return NULL if that fails. Free it with
+/// `neksis_engine_free`.
+#[no_mangle]
+pub extern "C" fn neksis_engine_new() -> *mut NeksisEngine {
+    match guard(|| Ok(NeksisEngine { engine: Engine::new(), last_error: None, strings: Vec::new() })) {
+        Ok(engine) => Box::into_raw(Box::new(engine)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `engine` must come from `neksis_engine_new` and not have been freed, or be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn neksis_engine_free(engine: *mut NeksisEngine) {
+    if !engine.is_null() {
+        let _ = guard(|| {
+            drop(Box::from_raw(engine));
+            Ok(())
+        });
+    }
+}
+
+/// Compile and run a NUL-terminated program after everything the engine has
+/// run before.
+///
+/// # Safety
+///
+/// `engine` must be a live engine and `source` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn neksis_load_source(engine: *mut NeksisEngine, source: *const c_char) -> c_int {
+    let Some(engine) = engine.as_mut() else { return NEKSIS_ERROR };
+    let outcome = guard(|| {
+        let source = c_str(source, "source")?;
+        engine.engine.run(source).map(|_| ()).map_err(|e| e.message)
+    });
+    engine.finish_run(outcome)
+}
+
+/// Run `length` bytes written by `neksis build --emit-bytecode`.
+///
+/// # Safety
+///
+/// `engine` must be a live engine and `data` NULL or readable for `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn neksis_load_bytecode(engine: *mut NeksisEngine, data: *const u8, length: usize) -> c_int {
+    let Some(engine) = engine.as_mut() else { return NEKSIS_ERROR };
+    let outcome = guard(|| {
+        if data.is_null() {
+            return Err("bytecode is NULL".to_string());
+        }
+        let bytes = std::slice::from_raw_parts(data, length);
+        engine.engine.run_bytecode(bytes).map_err(|e| e.message)
+    });
+    engine.finish_run(outcome)
+}
+
+/// Call the Neksis function `name` with `argc` arguments, storing its result
+/// in `*result` unless `result` is NULL.
+///
+/// # Safety
+///
+/// `engine` must be a live engine, `name` a NUL-terminated string, `args`
+/// readable for `argc` values and `result` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn neksis_call(
+    engine: *mut NeksisEngine,
+    name: *const c_char,
+    args: *const NeksisValue,
+    argc: usize,
+    result: *mut NeksisValue,
+) -> c_int {
+    let Some(engine) = engine.as_mut() else { return NEKSIS_ERROR };
+    engine.strings.clear();
+    let outcome = guard(|| {
+        let name = c_str(name, "name")?;
+        let args = match argc {
+            0 => &[][..],
+            _ if args.is_null() => return Err("args is NULL".to_string()),
+            _ => std::slice::from_raw_parts(args, argc),
+        };
+        let args = args.iter().enumerate()
+            .map(|(index, arg)| from_c(arg).map_err(|e| format!("{}: argument {}: {}", name, index + 1, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = engine.engine.call::<VMValue>(name, args).map_err(|e| e.message)?;
+        let value = to_c(&value, &mut engine.strings).map_err(|e| format!("{}: return value: {}", name, e))?;
+        if !result.is_null() {
+            *result = value;
+        }
+        Ok(())
+    });
+    engine.finish_run(outcome)
+}
+
+/// Make `callback` callable from Neksis as `name(...)`. `user_data` is passed
+/// back to it unchanged. An error it reports can be caught with try/catch.
+///
+/// # Safety
+///
+/// `engine` must be a live engine and `name` a NUL-terminated string.
+/// `callback` must stay callable with `user_data` for as long as the engine lives.
+#[no_mangle]
+pub unsafe extern "C" fn neksis_register(
+    engine: *mut NeksisEngine,
+    name: *const c_char,
+    callback: Option<NeksisCallback>,
+    user_data: *mut c_void,
+) -> c_int {
+    let Some(engine) = engine.as_mut() else { return NEKSIS_ERROR };
+    let outcome = guard(|| {
+        let name = c_str(name, "name")?.to_string();
+        let callback = callback.ok_or("callback is NULL")?;
+        let function_name = name.clone();
+        engine.engine.register_function(&name, Rc::new(move |args: Vec<VMValue>| {
+            let mut strings = Vec::new();
+            let args = args.iter().enumerate()
+                .map(|(index, arg)| to_c(arg, &mut strings).map_err(|e| format!("{}: argument {}: {}", function_name, index + 1, e)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut result = NeksisValue::NULL;
+            let status = callback(user_data, args.as_ptr(), args.len(), &mut result);
+            let value = from_c(&result);
+            if status != NEKSIS_OK {
+                return Err(match value {
+                    Ok(VMValue::String(message)) => message,
+                    _ => format!("{} failed", function_name),
+                });
+            }
+            value.map_err(|e| format!("{}: return value: {}", function_name, e))
+        }));
+        Ok(())
+    });
+    engine.finish(outcome)
+}
+
+/// Let scripts use `permission` ("read", "write", "exit" or "ffi") on
+/// `scope`, a path that includes everything under it. A NULL `scope` grants
+/// it without limits. New engines have no permissions.
+///
+/// # Safety
+///
+/// `engine` must be a live engine, `permission` a NUL-terminated string and
+/// `scope` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn neksis_allow(engine: *mut NeksisEngine, permission: *const c_char, scope: *const c_char) -> c_int {
+    let Some(engine) = engine.as_mut() else { return NEKSIS_ERROR };
+    let outcome = (|| {
+        let capability = c_str(permission, "permission")?.parse::<Capability>()?;
+        if scope.is_null() {
+            engine.engine.allow_all(capability);
+            return Ok(());
+        }
+        engine.engine.allow(capability, c_str(scope, "scope")?).map_err(|e| e.message)
+    })();
+    engine.finish(outcome)
+}
+
+/// Bound the instructions, heap, call depth and time of every later load and
+/// call; each gets the whole budget to itself. A NULL `limits` removes them.
+///
+/// # Safety
+///
+/// `engine` must be a live engine and `limits` NULL or readable.
+#[no_mangle]
+pub unsafe extern "C" fn neksis_set_limits(engine: *mut NeksisEngine, limits: *const NeksisLimits) -> c_int {
+    let Some(engine) = engine.as_mut() else { return NEKSIS_ERROR };
+    let limits = match limits.as_ref() {
+        Some(limits) => Limits {
+            max_instructions: (limits.max_instructions > 0).then_some(limits.max_instructions),
+            max_heap_bytes: (limits.max_heap_bytes > 0).then_some(limits.max_heap_bytes),
+            max_call_depth: match limits.max_call_depth {
+                0 => DEFAULT_MAX_CALL_DEPTH,
+                depth => depth,
+            },
+            max_wall_time: (limits.max_wall_time_ms > 0).then(|| Duration::from_millis(limits.max_wall_time_ms)),
+        },
+        None => Limits::default(),
+    };
+    engine.engine.set_limits(limits);
+    NEKSIS_OK
+}
+
+/// The message of the latest call on `engine` that failed, or NULL if none
+/// has. It stays valid until another call fails.
+///
+/// # Safety
+///
+/// `engine` must be a live engine or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn neksis_last_error(engine: *const NeksisEngine) -> *const c_char {
+    match engine.as_ref().and_then(|engine| engine.last_error.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> NeksisValue {
+        NeksisValue { tag: NeksisType::Int as c_int, data: NeksisData { int_value: n } }
+    }
+
+    unsafe fn last_error(engine: *const NeksisEngine) -> String {
+        CStr::from_ptr(neksis_last_error(engine)).to_str().unwrap().to_string()
+    }
+
+    extern "C" fn sum(user_data: *mut c_void, args: *const NeksisValue, argc: usize, result: *mut NeksisValue) -> c_int {
+        unsafe {
+            *(user_data as *mut usize) += 1;
+            let mut total = 0;
+            for arg in std::slice::from_raw_parts(args, argc) {
+                if arg.tag != NeksisType::Int as c_int {
+                    (*result).tag = NeksisType::String as c_int;
+                    (*result).data.string_value = c"sum expects ints".as_ptr();
+                    return NEKSIS_ERROR;
+                }
+                total += arg.data.int_value;
+            }
+            *result = int(total);
+            NEKSIS_OK
+        }
+    }
+
+    #[test]
+    fn test_c_api_calls_functions() {
+        unsafe {
+            let engine = neksis_engine_new();
+            assert!(neksis_last_error(engine).is_null());
+            let source = c"fn label(n: Int, unit: String) -> String { return \"\" + n + \" \" + unit; }";
+            assert_eq!(neksis_load_source(engine, source.as_ptr()), NEKSIS_OK);
+
+            let args = [int(3), NeksisValue { tag: NeksisType::String as c_int, data: NeksisData { string_value: c"apples".as_ptr() } }];
+            let mut result = NeksisValue::NULL;
+            assert_eq!(neksis_call(engine, c"label".as_ptr(), args.as_ptr(), 2, &mut result), NEKSIS_OK);
+            assert_eq!(result.tag, NeksisType::String as c_int);
+            assert_eq!(CStr::from_ptr(result.data.string_value).to_str().unwrap(), "3 apples");
+
+            assert_eq!(neksis_call(engine, c"missing".as_ptr(), ptr::null(), 0, &mut result), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "Undefined function: missing");
+            assert_eq!(neksis_call(engine, c"label".as_ptr(), ptr::null(), 2, &mut result), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "args is NULL");
+            assert_eq!(neksis_load_source(engine, c"fn array() -> Array { return [1]; }".as_ptr()), NEKSIS_OK);
+            assert_eq!(neksis_call(engine, c"array".as_ptr(), ptr::null(), 0, &mut result), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "array: return value: array values cannot be passed to C");
+            assert_eq!(neksis_load_source(engine, c"let = ;".as_ptr()), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "Expected variable name");
+            assert_eq!(neksis_load_source(engine, ptr::null()), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "source is NULL");
+
+            let bytecode = crate::bytecode_file::encode(&crate::ir::compile_program(
+                &crate::parser::Parser::new(crate::lexer::Lexer::new("fn twice(n: Int) -> Int { return n * 2; }", "t.nx".to_string()).tokenize().unwrap()).parse().unwrap(),
+                0,
+            ).unwrap());
+            assert_eq!(neksis_load_bytecode(engine, bytecode.as_ptr(), bytecode.len()), NEKSIS_OK);
+            assert_eq!(neksis_call(engine, c"twice".as_ptr(), &int(21), 1, &mut result), NEKSIS_OK);
+            assert_eq!((result.tag, result.data.int_value), (NeksisType::Int as c_int, 42));
+            let unknown = NeksisValue { tag: 7, data: NeksisData { int_value: 21 } };
+            assert_eq!(neksis_call(engine, c"twice".as_ptr(), &unknown, 1, &mut result), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "twice: argument 1: unknown value tag 7");
+            assert_eq!(neksis_load_bytecode(engine, b"junk".as_ptr(), 4), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "not a Neksis bytecode file");
+
+            neksis_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_c_api_callbacks() {
+        unsafe {
+            let engine = neksis_engine_new();
+            let mut calls = 0usize;
+            assert_eq!(neksis_register(engine, c"sum".as_ptr(), Some(sum), &mut calls as *mut usize as *mut c_void), NEKSIS_OK);
+            let source = c"fn total() -> Int { return sum(1, 2, 39); }\nfn guarded() -> String { let message = \"\"; try { sum(1, \"x\"); } catch (e) { message = e; } return message; }";
+            assert_eq!(neksis_load_source(engine, source.as_ptr()), NEKSIS_OK);
+
+            let mut result = NeksisValue::NULL;
+            assert_eq!(neksis_call(engine, c"total".as_ptr(), ptr::null(), 0, &mut result), NEKSIS_OK);
+            assert_eq!(result.data.int_value, 42);
+            assert_eq!(neksis_call(engine, c"guarded".as_ptr(), ptr::null(), 0, &mut result), NEKSIS_OK);
+            assert_eq!(CStr::from_ptr(result.data.string_value).to_str().unwrap(), "sum expects ints");
+            assert_eq!(calls, 2);
+
+            assert_eq!(neksis_register(engine, c"nothing".as_ptr(), None, ptr::null_mut()), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "callback is NULL");
+
+            (*engine).engine.register_function("explode", Rc::new(|_: Vec<VMValue>| panic!("boom")));
+            assert_eq!(neksis_load_source(engine, c"explode();".as_ptr()), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "Internal error: boom");
+            assert_eq!(neksis_call(engine, c"total".as_ptr(), ptr::null(), 0, &mut result), NEKSIS_OK);
+            assert_eq!(result.data.int_value, 42);
+            neksis_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_c_api_permissions() {
+        unsafe {
+            let engine = neksis_engine_new();
+            let source = c"fn manifest() -> Bool { return file_exists(\"Cargo.toml\"); }";
+            assert_eq!(neksis_load_source(engine, source.as_ptr()), NEKSIS_OK);
+            let mut result = NeksisValue::NULL;
+            assert_eq!(neksis_call(engine, c"manifest".as_ptr(), ptr::null(), 0, &mut result), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "PermissionDenied: read access to 'Cargo.toml' (grant it with --allow-read)");
+
+            assert_eq!(neksis_allow(engine, c"read".as_ptr(), c"Cargo.toml".as_ptr()), NEKSIS_OK);
+            assert_eq!(neksis_call(engine, c"manifest".as_ptr(), ptr::null(), 0, &mut result), NEKSIS_OK);
+            assert_eq!(result.tag, NeksisType::Bool as c_int);
+            assert!(result.data.bool_value);
+            assert_eq!(neksis_allow(engine, c"exit".as_ptr(), ptr::null()), NEKSIS_OK);
+            assert_eq!(neksis_allow(engine, c"disk".as_ptr(), ptr::null()), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "Unknown permission 'disk'");
+            assert_eq!(neksis_allow(engine, c"exit".as_ptr(), c"0".as_ptr()), NEKSIS_ERROR);
+            assert_eq!(last_error(engine), "The exit permission cannot be limited to '0'");
+            neksis_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_c_api_limits() {
+        unsafe {
+            let engine = neksis_engine_new();
+            let source = c"fn spin() -> Int { let i = 0; while true { i = i + 1; } return i; }";
+            assert_eq!(neksis_load_source(engine, source.as_ptr()), NEKSIS_OK);
+            let limits = NeksisLimits { max_instructions: 10_000, max_heap_bytes: 0, max_call_depth: 0, max_wall_time_ms: 0 };
+            assert_eq!(neksis_set_limits(engine, &limits), NEKSIS_OK);
+            let mut result = NeksisValue::NULL;
+            assert_eq!(neksis_call(engine, c"spin".as_ptr(), ptr::null(), 0, &mut result), NEKSIS_LIMIT_EXCEEDED);
+            assert_eq!(last_error(engine), "InstructionLimitExceeded: ran more than 10000 instructions");
+            assert_eq!(neksis_call(engine, c"missing".as_ptr(), ptr::null(), 0, &mut result), NEKSIS_ERROR);
+
+            let limits = NeksisLimits { max_wall_time_ms: 20, ..limits };
+            assert_eq!(neksis_set_limits(engine, &limits), NEKSIS_OK);
+            assert_eq!(neksis_load_source(engine, c"while true { }".as_ptr()), NEKSIS_LIMIT_EXCEEDED);
+            assert_eq!(neksis_set_limits(engine, ptr::null()), NEKSIS_OK);
+            assert_eq!(neksis_load_source(engine, c"let done = 1;".as_ptr()), NEKSIS_OK);
+            neksis_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_header_declares_every_function() {
+        let header = include_str!("../include/neksis.h");
+        let exported = include_str!("capi.rs")
+            .lines()
+            .filter_map(|line| line.split("extern \"C\" fn ").nth(1))
+            .filter_map(|rest| rest.split('(').next())
+            .filter(|name| name.starts_with("neksis_"))
+            .collect::<Vec<_>>();
+        assert_eq!(exported.len(), 9);
+        for name in exported {
+            assert!(header.contains(&format!("{}(", name)), "include/neksis.h does not declare {}", name);
+        }
+    }
+}
@@ -1,16 +1,25 @@
 // Removed unused import
 use crate::error::CompilerError;
-use crate::formatter::CodeFormatter;
-use crate::linter::Linter;
+use crate::formatter::{CodeFormatter, FormatConfig};
+use crate::linter::{apply_fixes, FileLint, LintConfig, LintSeverity, Linter};
 use crate::package_manager::PackageManager;
 use crate::lsp::LSPServer;
 use crate::tests::TestSuite;
+use crate::test_framework::{find_source_files, TestRunConfig, TestRunner};
+use crate::test_framework::diff::render_unified_diff;
+use crate::test_framework::reporters::{create_reporter, ReportFormat};
+use crate::test_framework::golden::{GoldenRunner, GoldenStatus};
+use crate::profiler::Profile;
+use crate::benchmark::{format_nanos, Baseline, BenchConfig, BenchRunner, Comparison};
 use crate::lexer::Lexer;
 use crate::parser::Parser;
+use crate::limits::Limits;
+use crate::permissions::Permissions;
 use crate::bytecode_compiler::BytecodeCompiler;
 use std::env;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub struct CLI;
 
@@ -39,10 +48,14 @@ impl CLI {
         match command.as_str() {
             "init" => self.handle_init(&args[2..]),
             "build" => self.handle_build(&args[2..]),
+            "transpile" => self.handle_transpile(&args[2..]),
+            "bindgen" => self.handle_bindgen(&args[2..]),
             "run" => self.handle_run(&args[2..]),
             "install" => self.handle_install(&args[2..]),
             "lsp" => self.handle_lsp(&args[2..]),
             "test" => self.handle_test(&args[2..]),
+            "golden" => self.handle_golden(&args[2..]),
+            "bench" => self.handle_bench(&args[2..]),
             "format" => self.handle_format(&args[2..]),
             "lint" => self.handle_lint(&args[2..]),
             "repl" => self.handle_repl(&args[2..]),
@@ -75,8 +88,41 @@ impl CLI {
 
     fn handle_build(&self, args: &[String]) -> Result<(), CompilerError> {
         let default_file = "src/main.nx".to_string();
-        let source_file = args.get(0).unwrap_or(&default_file);
+        let mut source_file = &default_file;
+        let mut level = 0;
+        let mut emit_ir = false;
+        let mut native = false;
+        let mut output: Option<String> = None;
+        let mut emit_c: Option<String> = None;
+        let mut emit_bytecode: Option<String> = None;
+        let mut target: Option<String> = None;
+        let mut max_stack = crate::vm::DEFAULT_MAX_CALL_DEPTH;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--emit-ir" => emit_ir = true,
+                "--native" => native = true,
+                "-o" => output = Some(Self::flag_value(&mut iter, "-o")?.clone()),
+                "--emit-c" => emit_c = Some(Self::flag_value(&mut iter, "--emit-c")?.clone()),
+                "--emit-bytecode" => emit_bytecode = Some(Self::flag_value(&mut iter, "--emit-bytecode")?.clone()),
+                "--target" => target = Some(Self::flag_value(&mut iter, "--target")?.clone()),
+                "--max-stack" => {
+                    max_stack = Self::flag_value(&mut iter, "--max-stack")?.parse::<usize>()
+                        .map_err(|_| CompilerError::runtime_error("--max-stack expects a number"))?;
+                }
+                flag if flag.starts_with("-O") => level = Self::optimization_level(flag)?,
+                flag if flag.starts_with("--") => {
+                    return Err(CompilerError::runtime_error(&format!("Unknown build option '{}'", flag)));
+                }
+                _ => source_file = arg,
+            }
+        }
         
+        if let Some(target) = &target {
+            if target != "wasm32-wasi" {
+                return Err(CompilerError::runtime_error(&format!("Unknown target '{}' (expected wasm32-wasi)", target)));
+            }
+        }
         if !Path::new(source_file).exists() {
             return Err(CompilerError::runtime_error(&format!("Source file '{}' not found", source_file)));
         }
@@ -88,9 +134,65 @@ impl CLI {
         let mut lexer = Lexer::new(&source, source_file.to_string());
         let tokens = lexer.tokenize()?;
         let mut parser = Parser::new(tokens);
+        if target.is_some() {
+            // Line markers let errors from the wasm backend point at the source
+            parser = parser.with_line_markers();
+        }
         let ast = parser.parse()?;
-        let mut bytecode_compiler = BytecodeCompiler::new();
-        let instructions = bytecode_compiler.compile_program(&ast)?;
+        if emit_ir {
+            // Without optimizations this shows the IR exactly as lowered
+            let (module, stats) = if level == 0 {
+                (crate::ir::lower::lower_program(&ast)?, crate::ir::PassStats::default())
+            } else {
+                crate::ir::optimize_program(&ast, level)?
+            };
+            print!("{}", module);
+            if level > 0 {
+                println!("; {:?}", stats);
+            }
+            return Ok(());
+        }
+        if target.is_some() {
+            let wasm = crate::wasm::WASMCompiler::new()
+                .with_optimization(level)
+                .with_max_call_depth(max_stack)
+                .compile_to_wasm(&ast)?;
+            let output = output.unwrap_or_else(|| Path::new(source_file).with_extension("wasm").to_string_lossy().into_owned());
+            fs::write(&output, &wasm)
+                .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", output, e)))?;
+            println!("✅ Build successful!");
+            println!("📦 WebAssembly module written to {}", output);
+            return Ok(());
+        }
+        let instructions = crate::ir::compile_program(&ast, level)?;
+
+        if let Some(path) = &emit_bytecode {
+            fs::write(path, crate::bytecode_file::encode(&instructions))
+                .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", path, e)))?;
+            println!("✅ Build successful!");
+            println!("📦 Bytecode written to {}", path);
+            return Ok(());
+        }
+
+        if native || emit_c.is_some() {
+            let generator = crate::codegen::c::CGenerator::new().with_max_call_depth(max_stack);
+            let c_source = generator.generate(&instructions)?;
+            if let Some(path) = &emit_c {
+                fs::write(path, &c_source)
+                    .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", path, e)))?;
+                println!("📝 Wrote C source to {}", path);
+            }
+            if native {
+                // By default the executable is named after the source file
+                let output = output.unwrap_or_else(|| {
+                    Path::new(source_file).file_stem().and_then(|stem| stem.to_str()).unwrap_or("a.out").to_string()
+                });
+                crate::codegen::c::compile_executable(&c_source, Path::new(&output))?;
+                println!("✅ Build successful!");
+                println!("📦 Native executable written to {}", output);
+            }
+            return Ok(());
+        }
         
         println!("✅ Build successful!");
         println!("📦 Generated {} instructions", instructions.len());
@@ -98,32 +200,249 @@ impl CLI {
         Ok(())
     }
 
-    fn handle_run(&self, args: &[String]) -> Result<(), CompilerError> {
+    fn handle_transpile(&self, args: &[String]) -> Result<(), CompilerError> {
         let default_file = "src/main.nx".to_string();
-        let source_file = args.get(0).unwrap_or(&default_file);
-        
+        let mut source_file = &default_file;
+        let mut language: Option<String> = None;
+        let mut output: Option<String> = None;
+        let mut library = false;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--to" => language = Some(Self::flag_value(&mut iter, "--to")?.clone()),
+                "-o" => output = Some(Self::flag_value(&mut iter, "-o")?.clone()),
+                "--lib" => library = true,
+                flag if flag.starts_with("--") => {
+                    return Err(CompilerError::runtime_error(&format!("Unknown transpile option '{}'", flag)));
+                }
+                _ => source_file = arg,
+            }
+        }
+
+        match language.as_deref() {
+            Some("rust") => {}
+            Some(other) => return Err(CompilerError::runtime_error(&format!("Unknown language '{}' (expected rust)", other))),
+            None => return Err(CompilerError::runtime_error("transpile needs a target language, e.g. --to rust")),
+        }
         if !Path::new(source_file).exists() {
             return Err(CompilerError::runtime_error(&format!("Source file '{}' not found", source_file)));
         }
-
         let source = fs::read_to_string(source_file)
             .map_err(|e| CompilerError::runtime_error(&format!("Failed to read file: {}", e)))?;
-
-        // Compile to bytecode
         let mut lexer = Lexer::new(&source, source_file.to_string());
         let tokens = lexer.tokenize()?;
         let mut parser = Parser::new(tokens);
         let ast = parser.parse()?;
-        let mut bytecode_compiler = BytecodeCompiler::new();
-        let instructions = bytecode_compiler.compile_program(&ast)?;
+
+        // By default the crate goes next to the source file, named after it
+        let dir = output.unwrap_or_else(|| Path::new(source_file).with_extension("").to_string_lossy().into_owned());
+        let name = Path::new(source_file).file_stem().and_then(|stem| stem.to_str()).unwrap_or("neksis-program");
+        let file = crate::transpile::RustTranspiler::new().library(library).write_crate(&ast, name, Path::new(&dir))?;
+        println!("✅ Transpiled {} to Rust", source_file);
+        println!("📦 Cargo crate written to {} ({})", dir, file.display());
+        Ok(())
+    }
+
+    fn handle_bindgen(&self, args: &[String]) -> Result<(), CompilerError> {
+        let mut header: Option<&String> = None;
+        let mut library: Option<String> = None;
+        let mut output: Option<String> = None;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--lib" => library = Some(Self::flag_value(&mut iter, "--lib")?.clone()),
+                "-o" => output = Some(Self::flag_value(&mut iter, "-o")?.clone()),
+                flag if flag.starts_with("--") => {
+                    return Err(CompilerError::runtime_error(&format!("Unknown bindgen option '{}'", flag)));
+                }
+                _ => header = Some(arg),
+            }
+        }
+
+        let header = header.ok_or_else(|| CompilerError::runtime_error("bindgen needs a C header, e.g. neksis bindgen mylib.h"))?;
+        if !Path::new(header).exists() {
+            return Err(CompilerError::runtime_error(&format!("Header '{}' not found", header)));
+        }
+        let text = fs::read_to_string(header)
+            .map_err(|e| CompilerError::runtime_error(&format!("Failed to read file: {}", e)))?;
+        // `mylib.h` is assumed to describe `libmylib.so` unless --lib says otherwise
+        let library = library.unwrap_or_else(|| {
+            let stem = Path::new(header).file_stem().and_then(|stem| stem.to_str()).unwrap_or("library");
+            format!("lib{}.so", stem)
+        });
+
+        let bindings = crate::bindgen::Bindgen::new(&library).generate(&text);
+        for warning in &bindings.warnings {
+            eprintln!("⚠️  {}:{}: {}", header, warning.line, warning.message);
+        }
+        match output {
+            Some(path) => {
+                fs::write(&path, &bindings.source)
+                    .map_err(|e| CompilerError::runtime_error(&format!("Failed to write {}: {}", path, e)))?;
+                println!(
+                    "✅ Generated bindings for {} functions, {} structs and {} constants from {}",
+                    bindings.functions, bindings.structs, bindings.constants, header
+                );
+                println!("📄 Written to {}", path);
+            }
+            None => print!("{}", bindings.source),
+        }
+        Ok(())
+    }
+
+    /// Parse `-O0`, `-O1` or `-O2`
+    fn optimization_level(flag: &str) -> Result<u8, CompilerError> {
+        match flag {
+            "-O0" => Ok(0),
+            "-O1" => Ok(1),
+            "-O2" => Ok(2),
+            _ => Err(CompilerError::runtime_error(&format!("Unknown optimization level '{}' (expected -O0, -O1 or -O2)", flag))),
+        }
+    }
+
+    fn handle_run(&self, args: &[String]) -> Result<(), CompilerError> {
+        let default_file = "src/main.nx".to_string();
+        let mut source_file = &default_file;
+        let mut profile = false;
+        let mut profile_output = "profile".to_string();
+        let mut heap_profile = false;
+        let mut top = 20;
+        let mut level = 0;
+        let mut limits = Limits::default();
+        let mut jit = false;
+        let mut jit_stats = false;
+        let mut permissions = Permissions::none();
+        let mut iter = args.iter();
+        
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--profile" => profile = true,
+                "--jit" => jit = true,
+                "--jit-stats" => {
+                    jit = true;
+                    jit_stats = true;
+                }
+                "--heap-profile" => heap_profile = true,
+                "--profile-output" => {
+                    profile = true;
+                    profile_output = Self::flag_value(&mut iter, "--profile-output")?.clone();
+                }
+                "--top" => {
+                    top = Self::flag_value(&mut iter, "--top")?.parse::<usize>()
+                        .map_err(|_| CompilerError::runtime_error("--top expects a number"))?;
+                }
+                "--max-stack" => {
+                    limits.max_call_depth = Self::flag_value(&mut iter, "--max-stack")?.parse::<usize>()
+                        .map_err(|_| CompilerError::runtime_error("--max-stack expects a number"))?;
+                }
+                "--max-instructions" => {
+                    let max = Self::flag_value(&mut iter, "--max-instructions")?.parse::<u64>()
+                        .map_err(|_| CompilerError::runtime_error("--max-instructions expects a number"))?;
+                    limits.max_instructions = Some(max);
+                }
+                "--max-memory" => {
+                    let max = crate::limits::parse_bytes(Self::flag_value(&mut iter, "--max-memory")?)
+                        .map_err(|e| CompilerError::runtime_error(&format!("--max-memory: {}", e)))?;
+                    limits.max_heap_bytes = Some(max);
+                }
+                "--max-time" => {
+                    let max = crate::limits::parse_duration(Self::flag_value(&mut iter, "--max-time")?)
+                        .map_err(|e| CompilerError::runtime_error(&format!("--max-time: {}", e)))?;
+                    limits.max_wall_time = Some(max);
+                }
+                flag if Self::permission_flag(&mut permissions, flag)? => {}
+                flag if flag.starts_with("-O") => level = Self::optimization_level(flag)?,
+                flag if flag.starts_with("--") => {
+                    return Err(CompilerError::runtime_error(&format!("Unknown run option '{}'", flag)));
+                }
+                _ => source_file = arg,
+            }
+        }
+        
+        if !Path::new(source_file).exists() {
+            return Err(CompilerError::runtime_error(&format!("Source file '{}' not found", source_file)));
+        }
+
+        let contents = fs::read(source_file)
+            .map_err(|e| CompilerError::runtime_error(&format!("Failed to read file: {}", e)))?;
+        let instructions = if crate::bytecode_file::is_bytecode(&contents) {
+            // Written by `neksis build --emit-bytecode`
+            crate::bytecode_file::decode(&contents)?
+        } else {
+            let source = String::from_utf8(contents)
+                .map_err(|e| CompilerError::runtime_error(&format!("Failed to read file: {}", e)))?;
+
+            // Compile to bytecode
+            let mut lexer = Lexer::new(&source, source_file.to_string());
+            let tokens = lexer.tokenize()?;
+            let mut parser = Parser::new(tokens);
+            if heap_profile {
+                // Line markers let allocation sites name a source line
+                parser = parser.with_line_markers();
+            }
+            let ast = parser.parse()?;
+            crate::ir::compile_program(&ast, level)?
+        };
         
         println!("🚀 Running {}...", source_file);
         println!("📤 Output:");
         
         // Execute the compiled code
         let mut vm = crate::vm::VM::new();
+        vm.set_limits(limits);
+        vm.set_permissions(permissions);
+        if jit {
+            vm.enable_jit().map_err(|e| CompilerError::runtime_error(&e))?;
+        }
+        if profile {
+            vm.enable_profiling();
+        }
         vm.load_instructions(instructions);
-        vm.run()?;
+        if heap_profile {
+            vm.enable_heap_profiling();
+        }
+        let result = vm.run();
+        if let Some(profile) = vm.take_profile() {
+            self.write_profile(&profile, &profile_output, top)?;
+        }
+        if let Some(report) = vm.take_heap_profile() {
+            println!();
+            println!("📦 Heap profile");
+            print!("{}", report.summary(top));
+        }
+        if let Some(stats) = vm.jit_stats().filter(|_| jit_stats) {
+            println!();
+            println!("⚡ JIT: {} compiled, {} rejected, {} native calls, {} guard failures, {} bailouts",
+                stats.compiled, stats.rejected, stats.native_calls, stats.guard_failures, stats.bailouts);
+        }
+        result?;
+        if let Some(code) = vm.exit_code() {
+            std::process::exit(code);
+        }
+        
+        Ok(())
+    }
+
+    // Apply an `--allow-*` flag, returning false for any other argument
+    fn permission_flag(permissions: &mut Permissions, flag: &str) -> Result<bool, CompilerError> {
+        permissions.allow_flag(flag).map_err(|e| CompilerError::runtime_error(&e))
+    }
+
+    fn write_profile(&self, profile: &Profile, prefix: &str, top: usize) -> Result<(), CompilerError> {
+        let folded_path = format!("{}.folded", prefix);
+        let trace_path = format!("{}.trace.json", prefix);
+        fs::write(&folded_path, profile.folded_stacks())
+            .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", folded_path, e)))?;
+        fs::write(&trace_path, profile.chrome_trace())
+            .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", trace_path, e)))?;
+        
+        println!();
+        println!("🔥 Hot functions ({:.3?} total)", profile.total);
+        print!("{}", profile.hot_functions(top));
+        println!("📝 Wrote folded stacks to {} and a Chrome trace to {}", folded_path, trace_path);
+        if profile.dropped_events > 0 {
+            println!("⚠️  Trace truncated: {} calls were not recorded", profile.dropped_events);
+        }
         
         Ok(())
     }
@@ -158,8 +477,262 @@ impl CLI {
         Ok(())
     }
 
-    fn handle_test(&self, _args: &[String]) -> Result<(), CompilerError> {
-        println!("🧪 Running neksis test suite...");
+    fn handle_test(&self, args: &[String]) -> Result<(), CompilerError> {
+        let mut config = TestRunConfig::default();
+        let mut paths = Vec::new();
+        let mut format = ReportFormat::Pretty;
+        let mut output: Option<PathBuf> = None;
+        let mut coverage_output = PathBuf::from("lcov.info");
+        let mut iter = args.iter();
+        
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--self-test" => return self.run_self_tests(),
+                "--coverage" => config.coverage = true,
+                "--coverage-output" => {
+                    config.coverage = true;
+                    coverage_output = PathBuf::from(Self::flag_value(&mut iter, "--coverage-output")?);
+                }
+                "--filter" => {
+                    config.filter = Some(Self::flag_value(&mut iter, "--filter")?.clone());
+                }
+                "--timeout" => {
+                    let millis = Self::flag_value(&mut iter, "--timeout")?.parse::<u64>()
+                        .map_err(|_| CompilerError::runtime_error("--timeout expects a number of milliseconds"))?;
+                    config.timeout = Duration::from_millis(millis);
+                }
+                "--format" => {
+                    let name = Self::flag_value(&mut iter, "--format")?;
+                    format = ReportFormat::parse(name).ok_or_else(|| {
+                        CompilerError::runtime_error(&format!("Unknown test format '{}', expected pretty, json or junit", name))
+                    })?;
+                }
+                "--output" | "-o" => {
+                    output = Some(PathBuf::from(Self::flag_value(&mut iter, "--output")?));
+                }
+                "--jobs" | "-j" => {
+                    config.jobs = Self::flag_value(&mut iter, "--jobs")?.parse::<usize>()
+                        .map_err(|_| CompilerError::runtime_error("--jobs expects a number"))?;
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(CompilerError::runtime_error(&format!("Unknown test option '{}'", flag)));
+                }
+                path => paths.push(PathBuf::from(path)),
+            }
+        }
+        if paths.is_empty() {
+            paths.push(PathBuf::from("."));
+        }
+        
+        let out: Box<dyn std::io::Write> = match &output {
+            Some(path) => Box::new(fs::File::create(path)
+                .map_err(|e| CompilerError::runtime_error(&format!("Failed to create '{}': {}", path.display(), e)))?),
+            None => Box::new(std::io::stdout()),
+        };
+        let mut reporter = create_reporter(format, out);
+        
+        let runner = TestRunner::new(config);
+        let discovery = runner.discover(&paths)?;
+        let report = runner.run(discovery, reporter.as_mut());
+        
+        if let Some(coverage) = &report.coverage {
+            fs::write(&coverage_output, coverage.to_lcov())
+                .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", coverage_output.display(), e)))?;
+            // Keep machine-readable reports on stdout parseable
+            let summary = format!("\n📊 Coverage\n{}📝 Wrote lcov data to {}", coverage.summary(), coverage_output.display());
+            if format == ReportFormat::Pretty || output.is_some() {
+                println!("{}", summary);
+            } else {
+                eprintln!("{}", summary);
+            }
+        }
+        
+        if !report.is_success() {
+            return Err(CompilerError::runtime_error(&format!("{} tests failed", report.failed())));
+        }
+        
+        Ok(())
+    }
+    
+    fn handle_golden(&self, args: &[String]) -> Result<(), CompilerError> {
+        let mut runner = GoldenRunner::new();
+        let mut paths = Vec::new();
+        let mut iter = args.iter();
+        
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--bless" => runner.bless = true,
+                "--filter" => {
+                    runner.filter = Some(Self::flag_value(&mut iter, "--filter")?.clone());
+                }
+                "--timeout" => {
+                    let millis = Self::flag_value(&mut iter, "--timeout")?.parse::<u64>()
+                        .map_err(|_| CompilerError::runtime_error("--timeout expects a number of milliseconds"))?;
+                    runner.timeout = Duration::from_millis(millis);
+                }
+                "--jit" => runner.jit_threshold = Some(crate::jit_compiler::DEFAULT_HOT_THRESHOLD),
+                flag if flag.starts_with("-O") => runner.opt_level = Self::optimization_level(flag)?,
+                flag if flag.starts_with("--") => {
+                    return Err(CompilerError::runtime_error(&format!("Unknown golden option '{}'", flag)));
+                }
+                path => paths.push(PathBuf::from(path)),
+            }
+        }
+        if paths.is_empty() {
+            paths.push(PathBuf::from("tests"));
+        }
+        
+        println!("🧪 Checking golden output...");
+        
+        let results = runner.run(&paths)?;
+        let mut failed = 0;
+        for result in &results {
+            match &result.status {
+                GoldenStatus::Matched => println!("✅ {}", result.file.display()),
+                GoldenStatus::Blessed => println!("📝 {} (blessed)", result.file.display()),
+                GoldenStatus::MissingExpected => {
+                    failed += 1;
+                    println!("❌ {} has no {} file (run with --bless to create it)",
+                        result.file.display(), GoldenRunner::expected_path(&result.file).display());
+                }
+                GoldenStatus::TimedOut => {
+                    failed += 1;
+                    println!("⏱️ {} timed out", result.file.display());
+                }
+                GoldenStatus::Mismatched(diff) => {
+                    failed += 1;
+                    println!("❌ {} output differs", result.file.display());
+                    println!("{}", diff);
+                }
+            }
+        }
+        
+        println!();
+        println!("{} passed; {} failed", results.len() - failed, failed);
+        
+        if failed > 0 {
+            return Err(CompilerError::runtime_error(&format!("{} golden tests failed", failed)));
+        }
+        
+        Ok(())
+    }
+    
+    fn handle_bench(&self, args: &[String]) -> Result<(), CompilerError> {
+        let mut config = BenchConfig::default();
+        let mut paths = Vec::new();
+        // By default compare with and then update the `base` baseline
+        let mut compare_with = Some("base".to_string());
+        let mut save_as = Some("base".to_string());
+        let mut iter = args.iter();
+        
+        let millis = |value: &String, flag: &str| -> Result<Duration, CompilerError> {
+            value.parse::<u64>()
+                .map(Duration::from_millis)
+                .map_err(|_| CompilerError::runtime_error(&format!("{} expects a number of milliseconds", flag)))
+        };
+        
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--filter" => config.filter = Some(Self::flag_value(&mut iter, "--filter")?.clone()),
+                "--warmup" => config.warmup = millis(Self::flag_value(&mut iter, "--warmup")?, "--warmup")?,
+                "--measure" => config.measurement = millis(Self::flag_value(&mut iter, "--measure")?, "--measure")?,
+                "--samples" => {
+                    config.samples = Self::flag_value(&mut iter, "--samples")?.parse::<usize>()
+                        .map_err(|_| CompilerError::runtime_error("--samples expects a number"))?;
+                }
+                "--threshold" => {
+                    let percent = Self::flag_value(&mut iter, "--threshold")?.parse::<f64>()
+                        .map_err(|_| CompilerError::runtime_error("--threshold expects a percentage"))?;
+                    config.noise_threshold = percent / 100.0;
+                }
+                "--baseline" => {
+                    compare_with = Some(Self::flag_value(&mut iter, "--baseline")?.clone());
+                    save_as = None;
+                }
+                "--save-baseline" => save_as = Some(Self::flag_value(&mut iter, "--save-baseline")?.clone()),
+                "--no-baseline" => {
+                    compare_with = None;
+                    save_as = None;
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(CompilerError::runtime_error(&format!("Unknown bench option '{}'", flag)));
+                }
+                path => paths.push(PathBuf::from(path)),
+            }
+        }
+        if paths.is_empty() {
+            paths.push(PathBuf::from("."));
+        }
+        
+        let noise_threshold = config.noise_threshold;
+        let runner = BenchRunner::new(config);
+        let (cases, load_errors) = runner.discover(&paths)?;
+        for error in &load_errors {
+            println!("❌ {}: {}", error.file.display(), error.message);
+        }
+        
+        let baseline = match &compare_with {
+            Some(name) => Baseline::load(&Baseline::path_for(name))?,
+            None => None,
+        };
+        
+        println!("⏱️ Running {} neksis benchmarks...", cases.len());
+        
+        let mut recorded = Baseline::default();
+        let mut failed = load_errors.len();
+        for case in &cases {
+            let result = match runner.run_case(case) {
+                Ok(result) => result,
+                Err(message) => {
+                    failed += 1;
+                    println!("bench {} ... ❌ FAILED: {}", case.qualified_name(), message);
+                    continue;
+                }
+            };
+            let stats = &result.statistics;
+            println!(
+                "bench {} ... {} ± {} (median {}, {} samples × {} iterations)",
+                case.qualified_name(),
+                format_nanos(stats.mean),
+                format_nanos(stats.stddev),
+                format_nanos(stats.median),
+                result.samples,
+                result.iterations_per_sample
+            );
+            if stats.mild_outliers + stats.severe_outliers > 0 {
+                println!("      outliers: {} mild, {} severe", stats.mild_outliers, stats.severe_outliers);
+            }
+            if let Some(baseline) = &baseline {
+                match baseline.compare(&result, noise_threshold) {
+                    Comparison::New => println!("      new benchmark (not in baseline)"),
+                    Comparison::NoChange(change) => println!("      no change ({:+.1}%)", change * 100.0),
+                    Comparison::Improved(change) => println!("      🚀 improved {:+.1}%", change * 100.0),
+                    Comparison::Regressed(change) => println!("      🐢 regressed {:+.1}%", change * 100.0),
+                }
+            }
+            recorded.record(&result);
+        }
+        
+        if let Some(name) = &save_as {
+            let path = Baseline::path_for(name);
+            // Keep entries for benchmarks that were filtered out of this run
+            let mut merged = Baseline::load(&path)?.unwrap_or_default();
+            merged.benchmarks.extend(recorded.benchmarks);
+            merged.save(&path)?;
+            println!();
+            println!("📝 Saved baseline '{}' to {}", name, path.display());
+        }
+        
+        if failed > 0 {
+            return Err(CompilerError::runtime_error(&format!("{} benchmarks failed", failed)));
+        }
+        
+        Ok(())
+    }
+    
+    // The compiler's own internal self-test suite
+    fn run_self_tests(&self) -> Result<(), CompilerError> {
+        println!("🧪 Running neksis compiler self-tests...");
         
         let test_suite = TestSuite::new();
         let results = test_suite.run_all_tests()?;
@@ -171,73 +744,200 @@ impl CLI {
         
         Ok(())
     }
+    
+    fn flag_value<'a>(iter: &mut std::slice::Iter<'a, String>, flag: &str) -> Result<&'a String, CompilerError> {
+        iter.next().ok_or_else(|| CompilerError::runtime_error(&format!("{} requires a value", flag)))
+    }
 
     fn handle_format(&self, args: &[String]) -> Result<(), CompilerError> {
-        let source_file = args.get(0).ok_or_else(|| {
-            CompilerError::runtime_error("File path required. Usage: neksis format <file.nx>")
-        })?;
-        
-        if !Path::new(source_file).exists() {
-            return Err(CompilerError::runtime_error(&format!("File '{}' not found", source_file)));
+        let mut check = false;
+        let mut paths = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "--check" => check = true,
+                flag if flag.starts_with("--") => {
+                    return Err(CompilerError::runtime_error(&format!("Unknown format option '{}'", flag)));
+                }
+                path => {
+                    if !Path::new(path).exists() {
+                        return Err(CompilerError::runtime_error(&format!("File '{}' not found", path)));
+                    }
+                    paths.push(PathBuf::from(path));
+                }
+            }
+        }
+        if paths.is_empty() {
+            paths.push(PathBuf::from("."));
         }
 
-        let source = fs::read_to_string(source_file)
-            .map_err(|e| CompilerError::runtime_error(&format!("Failed to read file: {}", e)))?;
+        let config = FormatConfig::load(Path::new("."))?;
+        let formatter = CodeFormatter::from_config(&config);
+        let files = find_source_files(&paths)?;
+
+        let mut changed = 0;
+        let mut failed = 0;
+        for file in &files {
+            let name = file.display().to_string();
+            let source = fs::read_to_string(file)
+                .map_err(|e| CompilerError::runtime_error(&format!("Failed to read file: {}", e)))?;
+            let formatted = match formatter.format_source(&source) {
+                Ok(formatted) => formatted,
+                Err(e) => {
+                    println!("❌ {} could not be formatted: {}", name, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            if formatted == source {
+                continue;
+            }
+            changed += 1;
+
+            if check {
+                println!("❌ {} is not formatted", name);
+                print!("{}", render_unified_diff(&name, &format!("{} (formatted)", name), &source, &formatted, 3));
+            } else {
+                fs::write(file, formatted)
+                    .map_err(|e| CompilerError::runtime_error(&format!("Failed to write formatted code: {}", e)))?;
+                println!("✅ Formatted '{}'", name);
+            }
+        }
+
+        if failed > 0 {
+            return Err(CompilerError::runtime_error(&format!("{} files could not be formatted", failed)));
+        }
+        if check && changed > 0 {
+            return Err(CompilerError::runtime_error(&format!(
+                "{} of {} files need formatting; run 'neksis format' to fix them",
+                changed,
+                files.len()
+            )));
+        }
+        if check {
+            println!("✅ {} files already formatted", files.len());
+        } else {
+            println!("✅ {} files formatted, {} already formatted", changed, files.len() - changed);
+        }
 
-        let formatter = CodeFormatter::new();
-        let formatted = formatter.format_source(&source)?;
-        
-        // Write formatted code back to file
-        fs::write(source_file, formatted)
-            .map_err(|e| CompilerError::runtime_error(&format!("Failed to write formatted code: {}", e)))?;
-        
-        println!("✅ Formatted '{}' successfully!", source_file);
-        
         Ok(())
     }
 
     fn handle_lint(&self, args: &[String]) -> Result<(), CompilerError> {
-        let source_file = args.get(0).ok_or_else(|| {
-            CompilerError::runtime_error("File path required. Usage: neksis lint <file.nx>")
-        })?;
-        
-        if !Path::new(source_file).exists() {
-            return Err(CompilerError::runtime_error(&format!("File '{}' not found", source_file)));
+        let mut fix = false;
+        let mut format = "pretty".to_string();
+        let mut output = None;
+        let mut paths = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--fix" => fix = true,
+                "--format" => format = Self::flag_value(&mut iter, "--format")?.clone(),
+                "--output" | "-o" => output = Some(PathBuf::from(Self::flag_value(&mut iter, "--output")?)),
+                flag if flag.starts_with("--") => {
+                    return Err(CompilerError::runtime_error(&format!("Unknown lint option '{}'", flag)));
+                }
+                path => {
+                    if !Path::new(path).exists() {
+                        return Err(CompilerError::runtime_error(&format!("File '{}' not found", path)));
+                    }
+                    paths.push(PathBuf::from(path));
+                }
+            }
+        }
+        if !matches!(format.as_str(), "pretty" | "json" | "sarif") {
+            return Err(CompilerError::runtime_error(&format!(
+                "Unknown lint format '{}', expected pretty, json or sarif", format
+            )));
+        }
+        if paths.is_empty() {
+            paths.push(PathBuf::from("."));
         }
 
-        let source = fs::read_to_string(source_file)
-            .map_err(|e| CompilerError::runtime_error(&format!("Failed to read file: {}", e)))?;
+        let linter = Linter::from_config(&LintConfig::load(Path::new("."))?)?;
+        let files = find_source_files(&paths)?;
+        let pretty = format == "pretty";
 
-        let linter = Linter::new();
-        let issues = linter.lint_source(&source, source_file)?;
-        
-        if issues.is_empty() {
-            println!("✅ No linting issues found in '{}'", source_file);
-        } else {
-            println!("🔍 Found {} linting issues in '{}':", issues.len(), source_file);
-            println!();
-            
-            for issue in issues {
-                let severity_icon = match issue.severity {
-                    crate::linter::LintSeverity::Error => "❌",
-                    crate::linter::LintSeverity::Warning => "⚠️",
-                    crate::linter::LintSeverity::Info => "ℹ️",
-                };
-                
-                println!("{} {}:{} - {}", 
-                    severity_icon, 
-                    issue.line, 
-                    issue.column, 
-                    issue.message
-                );
-                
-                if let Some(suggestion) = issue.suggestion {
-                    println!("   💡 {}", suggestion);
+        let mut results = Vec::new();
+        let mut failed = 0;
+        for file in &files {
+            let name = file.display().to_string();
+            let source = fs::read_to_string(file)
+                .map_err(|e| CompilerError::runtime_error(&format!("Failed to read file: {}", e)))?;
+            let mut issues = match linter.lint_source(&source, &name) {
+                Ok(issues) => issues,
+                Err(e) => {
+                    eprintln!("❌ {} could not be linted: {}", name, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            if fix {
+                let (fixed, applied) = apply_fixes(&source, &issues);
+                if applied > 0 {
+                    fs::write(file, &fixed)
+                        .map_err(|e| CompilerError::runtime_error(&format!("Failed to write file: {}", e)))?;
+                    if pretty {
+                        println!("🔧 Applied {} fixes to '{}'", applied, name);
+                    }
+                    issues = linter.lint_source(&fixed, &name)?;
                 }
+            }
+            results.push(FileLint { file: name, issues });
+        }
+
+        let total: usize = results.iter().map(|result| result.issues.len()).sum();
+        let errors = results
+            .iter()
+            .flat_map(|result| &result.issues)
+            .filter(|issue| issue.severity == LintSeverity::Error)
+            .count();
+
+        if pretty {
+            for result in &results {
+                for issue in &result.issues {
+                    let severity_icon = match issue.severity {
+                        LintSeverity::Error => "❌",
+                        LintSeverity::Warning => "⚠️",
+                        LintSeverity::Info => "ℹ️",
+                    };
+                    println!("{} {}:{}:{} - {} [{}]",
+                        severity_icon,
+                        result.file,
+                        issue.line,
+                        issue.column,
+                        issue.message,
+                        issue.rule
+                    );
+                    if let Some(suggestion) = &issue.suggestion {
+                        println!("   💡 {}", suggestion);
+                    }
+                }
+            }
+            if total == 0 {
+                println!("✅ No linting issues found in {} files", results.len());
+            } else {
                 println!();
+                println!("🔍 Found {} linting issues ({} errors) in {} files", total, errors, results.len());
+            }
+        } else {
+            let report = if format == "sarif" {
+                linter.render_sarif(&results)
+            } else {
+                linter.render_json(&results)
+            };
+            match &output {
+                Some(path) => fs::write(path, report + "\n")
+                    .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", path.display(), e)))?,
+                None => println!("{}", report),
             }
         }
-        
+
+        if failed > 0 {
+            return Err(CompilerError::runtime_error(&format!("{} files could not be linted", failed)));
+        }
+        if errors > 0 {
+            return Err(CompilerError::runtime_error(&format!("{} lint errors found", errors)));
+        }
         Ok(())
     }
 
@@ -259,6 +959,13 @@ impl CLI {
             return Err(CompilerError::runtime_error(&format!("File '{}' not found", source_file)));
         }
 
+        let mut permissions = Permissions::none();
+        for flag in &args[1..] {
+            if !Self::permission_flag(&mut permissions, flag)? {
+                return Err(CompilerError::runtime_error(&format!("Unknown option '{}'", flag)));
+            }
+        }
+
         let source = fs::read_to_string(source_file)
             .map_err(|e| CompilerError::runtime_error(&format!("Failed to read file: {}", e)))?;
 
@@ -272,8 +979,12 @@ impl CLI {
         
         // Execute the compiled code
         let mut vm = crate::vm::VM::new();
+        vm.set_permissions(permissions);
         vm.load_instructions(instructions);
         vm.run()?;
+        if let Some(code) = vm.exit_code() {
+            std::process::exit(code);
+        }
         
         Ok(())
     }
@@ -286,12 +997,68 @@ impl CLI {
         println!("Commands:");
         println!("  init [project-name]     Initialize a new neksis project");
         println!("  build [file.nx]         Compile a neksis source file");
-        println!("  run [file.nx]           Compile and run a neksis source file");
+        println!("       -O0, -O1, -O2      Optimization level (default -O0)");
+        println!("       --emit-ir          Print the intermediate representation instead of building");
+        println!("       --native           Build a standalone executable through C (needs cc, or $CC)");
+        println!("       -o <file>          Name of the executable (default: the source file's name)");
+        println!("       --emit-c <file>    Write the generated C source to <file>");
+        println!("       --max-stack <n>    Maximum call depth of the executable (default 10000)");
+        println!("       --target wasm32-wasi  Build a WebAssembly module for WASI runtimes");
+        println!("       --emit-bytecode <file>  Save the compiled program for `neksis run` and embedders");
+        println!("  transpile --to rust [file.nx]  Translate a program into a cargo crate");
+        println!("       -o <dir>           Where to write the crate (default: the source file's name)");
+        println!("       --lib              Emit a library crate with public items and no main");
+        println!("  bindgen <header.h>      Generate an extern block from a C header");
+        println!("       --lib <name>       Library to load the functions from (default: lib<header>.so)");
+        println!("       -o <file>          Write the bindings to <file> instead of stdout");
+        println!("  run [file.nx]           Compile and run a neksis source file, or run a bytecode file");
+        println!("       --profile          Time every function call and print the hottest functions");
+        println!("       --profile-output <prefix>  Write <prefix>.folded and <prefix>.trace.json (default: profile)");
+        println!("       --heap-profile     Report peak heap usage, top allocation sites and values live at exit");
+        println!("       --top <n>          Number of rows in the profile tables (default 20)");
+        println!("       --max-stack <n>    Maximum call depth before a StackOverflow error (default 10000)");
+        println!("       --max-instructions <n>  Stop the program after <n> instructions");
+        println!("       --max-memory <size>     Stop the program once its heap passes <size>, e.g. 64MB");
+        println!("       --max-time <duration>   Stop the program after <duration>, e.g. 500ms or 2s; a built-in that");
+        println!("                               blocks, such as sleep or read_line, finishes first");
+        println!("       --jit              Compile hot numeric functions to native code");
+        println!("       --jit-stats        Like --jit, and report what the JIT compiled and ran");
+        println!("       -O0, -O1, -O2      Optimization level (default -O0); -O1 and up optimize through the IR");
+        println!("       --allow-read[=<paths>]   Let the program read files, or only these paths (comma-separated)");
+        println!("       --allow-write[=<paths>]  Let the program write files, or only these paths");
+        println!("       --allow-exit       Let the program call exit()");
+        println!("       --allow-ffi        Let the program load C libraries and Python modules");
+        println!("       --allow-all, -A    Grant every permission");
         println!("  install <package>       Install a package dependency");
         println!("  lsp                     Start the Language Server Protocol server");
-        println!("  test                    Run the test suite");
-        println!("  format <file.nx>        Format a neksis source file");
-        println!("  lint <file.nx>          Lint a neksis source file");
+        println!("  test [paths...]         Run @test functions found in .nx files");
+        println!("       --filter <name>    Only run tests whose name contains <name>");
+        println!("       --timeout <ms>     Fail tests that run longer than <ms> (default 10000)");
+        println!("       --jobs <n>         Number of tests to run in parallel");
+        println!("       --format <fmt>     Report format: pretty, json or junit");
+        println!("       --output <file>    Write the report to <file> instead of stdout");
+        println!("       --coverage         Record line, branch and function coverage");
+        println!("       --coverage-output <file>  Where to write lcov data (default lcov.info)");
+        println!("       --self-test        Run the compiler's internal self-tests");
+        println!("  golden [paths...]       Compare program output with .expected files (default: tests/)");
+        println!("       --bless            Rewrite the .expected files from the current output");
+        println!("       -O0, -O1, -O2      Compile the programs at this optimization level (default -O0)");
+        println!("       --jit              Run the programs with the JIT enabled");
+        println!("  bench [paths...]        Run @bench functions and compare with the saved baseline");
+        println!("       --filter <name>    Only run benchmarks whose name contains <name>");
+        println!("       --warmup <ms>      Warmup time per benchmark (default 500)");
+        println!("       --measure <ms>     Measurement time per benchmark (default 2000)");
+        println!("       --samples <n>      Number of samples to collect (default 50)");
+        println!("       --threshold <pct>  Changes smaller than this are noise (default 5)");
+        println!("       --baseline <name>  Compare with a named baseline without updating it");
+        println!("       --save-baseline <name>  Save results under <name> (default: base)");
+        println!("       --no-baseline      Neither compare with nor save a baseline");
+        println!("  format [paths...]       Format .nx files in place (default: the current directory)");
+        println!("       --check            Print a diff and fail instead of rewriting unformatted files");
+        println!("  lint [paths...]         Lint .nx files (default: the current directory)");
+        println!("       --fix              Apply the fixes rules suggest, then report what remains");
+        println!("       --format <fmt>     Output format: pretty, json or sarif (default pretty)");
+        println!("       --output <file>    Write the json or sarif report to <file>");
         println!("  repl                    Start the interactive REPL");
         println!("  help                    Show this help message");
         println!("  version                 Show version information");
@@ -301,6 +1068,7 @@ impl CLI {
         println!("  neksis run src/main.nx");
         println!("  neksis format src/main.nx");
         println!("  neksis lint src/main.nx");
+        println!("  neksis test tests/ --filter parser");
         println!("  neksis repl              # Start interactive REPL");
         println!("  neksis src/main.nx      # Direct execution");
         println!();
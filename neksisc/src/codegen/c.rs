@@ -0,0 +1,649 @@
+// C backend
+//
+// Lowers compiled bytecode to a standalone C program and builds it with the
+// system C compiler. Every neksis function becomes a C function and every
+// instruction a call into the runtime in `c_runtime.c`, which keeps the
+// VM's value stack, dynamic values and error messages, so a native build
+// prints exactly what `neksis run` prints. Jumps become `goto`s and `try`
+// blocks use setjmp/longjmp.
+
+use crate::error::CompilerError;
+use crate::vm::{BytecodeInstruction, DEFAULT_MAX_CALL_DEPTH};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::process::Command;
+
+const RUNTIME: &str = include_str!("c_runtime.c");
+
+pub struct CGenerator {
+    max_call_depth: usize,
+}
+
+// A function of the program, as the VM's function table sees it
+struct FunctionInfo {
+    name: String,
+    start: usize,
+    end: usize,
+    params: usize,
+}
+
+// The code of one C function: a neksis function, or the top level
+struct Unit<'a> {
+    function: Option<&'a FunctionInfo>,
+    // Instruction indices in order, and where each `DefineFunction` on the top level skips to
+    indices: Vec<usize>,
+    skips: HashMap<usize, usize>,
+}
+
+impl Default for CGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CGenerator {
+    pub fn new() -> Self {
+        Self { max_call_depth: DEFAULT_MAX_CALL_DEPTH }
+    }
+
+    // Calls nested deeper than `depth` fail with a `StackOverflow` error, like `--max-stack`
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    pub fn generate(&self, instructions: &[BytecodeInstruction]) -> Result<String, CompilerError> {
+        let functions = function_table(instructions);
+        let ids: HashMap<&str, usize> = functions.iter().enumerate().map(|(id, f)| (f.name.as_str(), id)).collect();
+        let mut strings = StringTable::default();
+
+        let mut units = Vec::new();
+        for function in &functions {
+            units.push(Unit { function: Some(function), indices: (function.start..function.end).collect(), skips: HashMap::new() });
+        }
+        units.push(top_level(instructions));
+
+        let mut bodies = String::new();
+        let mut max_locals = 1;
+        for (id, unit) in units.iter().enumerate() {
+            let emitter = UnitEmitter { id, unit, instructions, functions: &functions, ids: &ids };
+            let (body, locals) = emitter.emit(&mut strings)?;
+            max_locals = max_locals.max(locals);
+            bodies.push_str(&body);
+        }
+
+        let mut out = String::new();
+        out.push_str(RUNTIME);
+        out.push_str("\n/* ---- Program ---- */\n\n");
+        let _ = writeln!(out, "static nx_string *S[{}];\n", strings.values.len().max(1));
+        for (id, function) in functions.iter().enumerate() {
+            let _ = writeln!(out, "static void nx_fn_{}(void); /* {} */", id, comment(&function.name));
+        }
+        out.push('\n');
+        out.push_str(&bodies);
+        out.push_str("static void nx_strings_init(void) {\n");
+        for (index, value) in strings.values.iter().enumerate() {
+            let _ = writeln!(out, "    S[{}] = nx_string_new({}, {});", index, c_string(value.as_bytes()), value.len());
+        }
+        out.push_str("}\n\n");
+        out.push_str("int main(void) {\n");
+        let _ = writeln!(out, "    nx_init({}, {});", self.max_call_depth, max_locals);
+        out.push_str("    nx_strings_init();\n");
+        out.push_str("    return nx_main(nx_program);\n");
+        out.push_str("}\n");
+        Ok(out)
+    }
+}
+
+// Compile C source to the executable `output` with `$CC` (default `cc`)
+pub fn compile_executable(c_source: &str, output: &Path) -> Result<(), CompilerError> {
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let source_path = std::env::temp_dir().join(format!(
+        "neksis-{}-{}.c",
+        std::process::id(),
+        output.file_name().and_then(|name| name.to_str()).unwrap_or("program")
+    ));
+    std::fs::write(&source_path, c_source)
+        .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", source_path.display(), e)))?;
+    let result = Command::new(&compiler)
+        .arg("-O2")
+        .arg("-o")
+        .arg(output)
+        .arg(&source_path)
+        .args(["-lm", "-pthread"])
+        .output();
+    let _ = std::fs::remove_file(&source_path);
+
+    let result = result.map_err(|e| {
+        CompilerError::codegen_error("c", &format!("Failed to run C compiler '{}': {} (set CC to choose another)", compiler, e))
+    })?;
+    if !result.status.success() {
+        return Err(CompilerError::codegen_error(
+            "c",
+            &format!("{} failed:\n{}", compiler, String::from_utf8_lossy(&result.stderr).trim_end()),
+        ));
+    }
+    Ok(())
+}
+
+// The functions `VM::build_function_table` finds, in order of definition;
+// when a name is defined twice the last definition is the one that runs
+fn function_table(instructions: &[BytecodeInstruction]) -> Vec<FunctionInfo> {
+    let mut functions: Vec<FunctionInfo> = Vec::new();
+    let mut current: Option<(String, usize, usize)> = None;
+    let mut close = |current: &mut Option<(String, usize, usize)>, end: usize| {
+        if let Some((name, start, params)) = current.take() {
+            functions.retain(|function| function.name != name);
+            functions.push(FunctionInfo { name, start, end, params });
+        }
+    };
+    for (ip, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            BytecodeInstruction::DefineFunction(name, params) => {
+                close(&mut current, ip);
+                current = Some((name.clone(), ip + 1, *params));
+            }
+            BytecodeInstruction::EndFunction => close(&mut current, ip),
+            _ => {}
+        }
+    }
+    functions.sort_by_key(|function| function.start);
+    functions
+}
+
+// The VM skips from a `DefineFunction` to the next `EndFunction`, so the top
+// level is everything outside those ranges
+fn top_level(instructions: &[BytecodeInstruction]) -> Unit<'static> {
+    let mut indices = Vec::new();
+    let mut skips = HashMap::new();
+    let mut ip = 0;
+    while ip < instructions.len() {
+        indices.push(ip);
+        if let BytecodeInstruction::DefineFunction(_, _) = instructions[ip] {
+            let end = instructions[ip..]
+                .iter()
+                .position(|instruction| matches!(instruction, BytecodeInstruction::EndFunction))
+                .map_or(instructions.len(), |offset| ip + offset + 1);
+            skips.insert(ip, end);
+            ip = end;
+        } else {
+            ip += 1;
+        }
+    }
+    Unit { function: None, indices, skips }
+}
+
+#[derive(Default)]
+struct StringTable {
+    values: Vec<String>,
+    ids: HashMap<String, usize>,
+}
+
+impl StringTable {
+    fn id(&mut self, value: &str) -> usize {
+        if let Some(&id) = self.ids.get(value) {
+            return id;
+        }
+        self.values.push(value.to_string());
+        self.ids.insert(value.to_string(), self.values.len() - 1);
+        self.values.len() - 1
+    }
+}
+
+struct UnitEmitter<'a> {
+    id: usize,
+    unit: &'a Unit<'a>,
+    instructions: &'a [BytecodeInstruction],
+    functions: &'a [FunctionInfo],
+    ids: &'a HashMap<&'a str, usize>,
+}
+
+impl UnitEmitter<'_> {
+    fn name(&self) -> &str {
+        self.unit.function.map_or("<top level>", |function| function.name.as_str())
+    }
+
+    fn error(&self, message: &str) -> CompilerError {
+        CompilerError::codegen_error("c", &format!("{} in {}", message, self.name()))
+    }
+
+    // The C function, and how many locals it has
+    fn emit(&self, strings: &mut StringTable) -> Result<(String, usize), CompilerError> {
+        let mut slots: Vec<&str> = Vec::new();
+        for &ip in &self.unit.indices {
+            if let BytecodeInstruction::Load(name) | BytecodeInstruction::Store(name) = &self.instructions[ip] {
+                if !slots.contains(&name.as_str()) {
+                    slots.push(name);
+                }
+            }
+        }
+        let labels = self.labels()?;
+
+        let mut out = String::new();
+        match self.unit.function {
+            Some(function) => {
+                let _ = writeln!(out, "/* {} */", comment(&function.name));
+                let _ = writeln!(out, "static void nx_fn_{}(void) {{", self.id);
+                if slots.is_empty() {
+                    let _ = writeln!(out, "    nx_enter({}, 0);", c_string(function.name.as_bytes()));
+                } else {
+                    let _ = writeln!(out, "    nx_value *L = nx_enter({}, {});", c_string(function.name.as_bytes()), slots.len());
+                }
+            }
+            None => {
+                if !slots.is_empty() {
+                    let _ = writeln!(out, "static nx_value G[{}];\n", slots.len());
+                }
+                out.push_str("static void *nx_program(void *unused) {\n");
+                out.push_str("    (void)unused;\n");
+                if !slots.is_empty() {
+                    out.push_str("    nx_value *L = G;\n");
+                }
+            }
+        }
+
+        for &ip in &self.unit.indices {
+            if labels.contains(&ip) {
+                let _ = writeln!(out, "L_{}:;", ip);
+            }
+            let slot = |name: &str| slots.iter().position(|slot| *slot == name).unwrap();
+            let code = self.instruction(ip, strings, &slot)?;
+            for line in code.lines() {
+                let _ = writeln!(out, "    {}", line);
+            }
+        }
+
+        match self.unit.function {
+            Some(_) => {
+                if self.falls_through() {
+                    return Err(self.error("Control reaches the end of the function without a return"));
+                }
+            }
+            None => {
+                if labels.contains(&self.instructions.len()) {
+                    let _ = writeln!(out, "L_{}:;", self.instructions.len());
+                }
+                out.push_str("    return NULL;\n");
+            }
+        }
+        out.push_str("}\n\n");
+        Ok((out, slots.len()))
+    }
+
+    // Indices that are jumped to, checked to stay inside this unit
+    fn labels(&self) -> Result<BTreeSet<usize>, CompilerError> {
+        let mut labels = BTreeSet::new();
+        for &ip in &self.unit.indices {
+            let target = match &self.instructions[ip] {
+                BytecodeInstruction::Jump(target)
+                | BytecodeInstruction::JumpIfFalse(target)
+                | BytecodeInstruction::JumpIfTrue(target)
+                | BytecodeInstruction::Try(target) => *target,
+                BytecodeInstruction::DefineFunction(_, _) => self.unit.skips[&ip],
+                _ => continue,
+            };
+            let inside = match self.unit.function {
+                Some(_) => self.unit.indices.contains(&target),
+                None => target == self.instructions.len() || self.unit.indices.binary_search(&target).is_ok(),
+            };
+            if !inside {
+                return Err(self.error(&format!("Jump from {} to {} leaves the function", ip, target)));
+            }
+            labels.insert(target);
+        }
+        Ok(labels)
+    }
+
+    fn falls_through(&self) -> bool {
+        !matches!(
+            self.unit.indices.last().map(|&ip| &self.instructions[ip]),
+            Some(BytecodeInstruction::Return | BytecodeInstruction::Jump(_))
+        )
+    }
+
+    // The arity check the VM makes at run time, which only fails when the counts differ
+    fn arity_check(&self, name: &str, id: usize, arg_count: usize) -> String {
+        let params = self.functions[id].params;
+        if params == arg_count {
+            String::new()
+        } else {
+            format!("nx_check_arity({}, {}, {});\n", c_string(name.as_bytes()), params, arg_count)
+        }
+    }
+
+    fn call(&self, name: &str, arg_count: usize) -> String {
+        match self.ids.get(name) {
+            Some(&id) => format!("{}nx_fn_{}();", self.arity_check(name, id, arg_count), id),
+            None if name.is_empty() => format!("nx_call_dynamic({});", arg_count),
+            None => format!("nx_call_builtin({}, {});", c_string(name.as_bytes()), arg_count),
+        }
+    }
+
+    fn instruction(&self, ip: usize, strings: &mut StringTable, slot: &dyn Fn(&str) -> usize) -> Result<String, CompilerError> {
+        use BytecodeInstruction as I;
+        let in_function = self.unit.function.is_some();
+        let code = match &self.instructions[ip] {
+            I::PushInt(value) if *value == i64::MIN => "nx_push_int(INT64_MIN);".to_string(),
+            I::PushInt(value) => format!("nx_push_int(INT64_C({}));", value),
+            I::PushFloat(value) => format!("nx_push_float_bits(UINT64_C({:#x})); /* {} */", value.to_bits(), value),
+            I::PushString(value) => format!("nx_push_string(S[{}]);", strings.id(value)),
+            I::PushBool(value) => format!("nx_push_bool({});", *value as u8),
+            I::PushNull => "nx_push_null();".to_string(),
+            I::Pop => "nx_op_pop();".to_string(),
+            I::Dup => "nx_op_dup();".to_string(),
+            I::Swap => "nx_op_swap();".to_string(),
+
+            I::Load(name) => format!("nx_load(&L[{}], {});", slot(name), c_string(name.as_bytes())),
+            I::Store(name) => format!("nx_store(&L[{}]); /* {} */", slot(name), comment(name)),
+            I::LoadGlobal(name) => format!("nx_load_global({});", c_string(name.as_bytes())),
+            I::StoreGlobal(name) => format!("nx_store_global({});", c_string(name.as_bytes())),
+
+            I::Jump(target) => format!("goto L_{};", target),
+            I::JumpIfFalse(target) => format!("if (!nx_pop_truthy()) goto L_{};", target),
+            I::JumpIfTrue(target) => format!("if (nx_pop_truthy()) goto L_{};", target),
+            I::Line(line) => format!("/* line {} */", line),
+
+            I::Call(name, arg_count) => self.call(name, *arg_count),
+            I::TailCall(name, arg_count) => match self.ids.get(name.as_str()) {
+                // The callee takes over the frame; the C compiler turns this into a jump
+                Some(&id) if in_function => {
+                    format!("{}nx_leave();\nnx_fn_{}();\nreturn;", self.arity_check(name, id, *arg_count), id)
+                }
+                _ => self.call(name, *arg_count),
+            },
+            I::Return if in_function => "nx_leave();\nreturn;".to_string(),
+            I::Return => "nx_return_without_call();".to_string(),
+            I::DefineFunction(name, _) => format!("goto L_{}; /* fn {} */", self.unit.skips[&ip], comment(name)),
+            I::EndFunction => String::new(),
+            I::CallMethod(name, arg_count) => match self.ids.get(name.as_str()) {
+                Some(_) => self.call(name, *arg_count),
+                None => format!("nx_undefined_function({});", c_string(name.as_bytes())),
+            },
+
+            I::Throw(_) => "nx_throw();".to_string(),
+            I::Try(target) => format!("if (setjmp(nx_try()->jump)) goto L_{};", target),
+            I::EndTry => "nx_end_try();".to_string(),
+            I::Catch | I::Finally => String::new(),
+
+            I::GetProperty(name) => format!("nx_get_property({});", c_string(name.as_bytes())),
+            I::SetProperty(name) => format!("nx_set_property({});", c_string(name.as_bytes())),
+
+            I::ExternFunction(library, name, _) => {
+                return Err(self.error(&format!("extern function '{}' from {} can only be called by the VM", name, library)));
+            }
+            I::ImportPython(module, _) | I::CallPython(module, ..) | I::LoadPython(module, _) => {
+                return Err(self.error(&format!("Python module '{}' can only be used by the VM", module)));
+            }
+
+            other => match runtime_function(other) {
+                Some(function) => format!("{}();", function),
+                None => return Err(self.error(&format!("Unsupported instruction {:?}", other))),
+            },
+        };
+        Ok(code)
+    }
+}
+
+// The runtime function for an instruction that only works on the stack
+fn runtime_function(instruction: &BytecodeInstruction) -> Option<&'static str> {
+    use BytecodeInstruction as I;
+    Some(match instruction {
+        I::Add => "nx_add",
+        I::Sub => "nx_sub",
+        I::Mul => "nx_mul",
+        I::Div => "nx_div",
+        I::Mod => "nx_mod",
+        I::Neg => "nx_neg",
+        I::Eq => "nx_eq",
+        I::Ne => "nx_ne",
+        I::Lt => "nx_lt",
+        I::Le => "nx_le",
+        I::Gt => "nx_gt",
+        I::Ge => "nx_ge",
+        I::And => "nx_and",
+        I::Or => "nx_or",
+        I::Not => "nx_not",
+        I::Print => "nx_print",
+        I::Println => "nx_println",
+        I::ReadLine => "nx_read_line",
+        I::ReadFile => "nx_read_file",
+        I::WriteFile => "nx_write_file",
+        I::AppendFile => "nx_append_file",
+        I::FileExists => "nx_file_exists",
+        I::Abs => "nx_abs",
+        I::Sqrt => "nx_sqrt",
+        I::Sin => "nx_sin",
+        I::Cos => "nx_cos",
+        I::Tan => "nx_tan",
+        I::Floor => "nx_floor",
+        I::Ceil => "nx_ceil",
+        I::Round => "nx_round",
+        I::Pow => "nx_pow",
+        I::Min => "nx_min",
+        I::Max => "nx_max",
+        I::NewObject => "nx_new_object",
+        I::NewArray => "nx_new_array",
+        I::GetIndex => "nx_get_index",
+        I::SetIndex => "nx_set_index",
+        I::ToString => "nx_to_string_op",
+        I::ToInt => "nx_to_int",
+        I::ToFloat => "nx_to_float",
+        I::ToBool => "nx_to_bool",
+        I::StringLen => "nx_len",
+        I::Substring => "nx_substring",
+        I::StringConcat => "nx_string_concat",
+        I::StringContains => "nx_string_contains",
+        I::StringStartsWith => "nx_string_starts_with",
+        I::StringEndsWith => "nx_string_ends_with",
+        I::StringToUpper => "nx_string_to_upper",
+        I::StringToLower => "nx_string_to_lower",
+        I::StringTrim => "nx_string_trim",
+        I::StringSplit => "nx_string_split",
+        I::StringJoin => "nx_string_join",
+        I::Random => "nx_random",
+        I::RandomInt => "nx_random_int",
+        I::TypeOf => "nx_type_of",
+        I::Time => "nx_time",
+        I::Sleep => "nx_sleep",
+        I::Exit => "nx_exit",
+        I::DictNew => "nx_dict_new",
+        I::DictSet => "nx_dict_set",
+        I::DictGet => "nx_dict_get",
+        I::DictHas => "nx_dict_has",
+        I::DictKeys => "nx_dict_keys",
+        I::DictSize => "nx_dict_size",
+        I::DictRemove => "nx_dict_remove",
+        I::DictClear => "nx_dict_clear",
+        I::ArrayPush => "nx_array_push",
+        I::ArrayPop => "nx_array_pop",
+        I::ArrayReverse => "nx_array_reverse",
+        I::ArraySort => "nx_array_sort",
+        I::ArrayFilter => "nx_array_filter",
+        I::ArrayMap => "nx_array_map",
+        I::ArrayReduce => "nx_array_reduce",
+        I::ArrayFind => "nx_array_find",
+        I::ArraySlice => "nx_array_slice",
+        I::JsonParse => "nx_json_parse",
+        I::JsonStringify => "nx_json_stringify",
+        I::TryCatch => "nx_try_catch",
+        I::ThrowError => "nx_throw_error",
+        _ => return None,
+    })
+}
+
+// A C string literal; octal escapes keep any byte, and `?` is escaped so no trigraph can form
+fn c_string(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'?' => out.push_str("\\?"),
+            b' '..=b'~' => out.push(byte as char),
+            _ => {
+                let _ = write!(out, "\\{:03o}", byte);
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Text that is safe inside a C comment
+fn comment(text: &str) -> String {
+    text.replace("*/", "* /").replace(|c: char| c.is_control(), " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::test_framework::golden::{run_program_at, ProgramOutput};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn have_c_compiler() -> bool {
+        let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let found = Command::new(compiler).arg("--version").output().is_ok();
+        if !found {
+            eprintln!("skipping: no C compiler");
+        }
+        found
+    }
+
+    // Build `source` at `level` with the C backend and run the executable
+    fn run_native(source: &str, level: u8) -> ProgramOutput {
+        static BUILDS: AtomicUsize = AtomicUsize::new(0);
+        let tokens = Lexer::new(source, "test.nx".to_string()).tokenize().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let instructions = crate::ir::compile_program(&ast, level).unwrap();
+        let c_source = CGenerator::new().generate(&instructions).unwrap();
+        let executable = std::env::temp_dir().join(format!(
+            "neksis-c-test-{}-{}",
+            std::process::id(),
+            BUILDS.fetch_add(1, Ordering::SeqCst)
+        ));
+        compile_executable(&c_source, &executable).unwrap();
+        let output = Command::new(&executable).output().unwrap();
+        let _ = std::fs::remove_file(&executable);
+        ProgramOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            exit_code: output.status.code().unwrap_or(-1),
+        }
+    }
+
+    fn assert_same_as_vm(source: &str) {
+        for level in [0, 2] {
+            let expected = run_program_at(source, "test.nx", level);
+            let actual = run_native(source, level);
+            assert_eq!(actual.render(), expected.render(), "native output differs at -O{}", level);
+        }
+    }
+
+    #[test]
+    fn test_values_and_builtins() {
+        if !have_c_compiler() {
+            return;
+        }
+        assert_same_as_vm(r#"
+            fn main() {
+                println(0.1 + 0.2);
+                println(1.0 / 3.0);
+                println(1e21 * 10.0);
+                println(0.000001234);
+                println(2.0);
+                println(pow(10.0, 400.0));
+                println(-7 / 2);
+                println(7 % -3);
+                println(split("a,b,,c", ","));
+                println(to_upper("MiXed") + trim("  x  ") + substring("hello", 1, 3));
+                let items = [5, 3, 9, 1];
+                println(array_sort(items));
+                println(typeof(items) + " " + typeof(1.5));
+                println("x" + 1 + 2.5);
+                println(round(-2.5));
+                println(max(3, 4.5));
+            }
+        "#);
+    }
+
+    #[test]
+    fn test_errors_and_calls() {
+        if !have_c_compiler() {
+            return;
+        }
+        // Errors unwind through calls to the nearest `try`, and tail calls reuse the frame
+        assert_same_as_vm(r#"
+            fn fails(x: Int) -> Int {
+                return 10 / x;
+            }
+            fn deep(n: Int) -> Int {
+                if n == 0 {
+                    return 0;
+                }
+                return 1 + deep(n - 1);
+            }
+            fn count(n: Int, total: Int) -> Int {
+                if n == 0 {
+                    return total;
+                }
+                return count(n - 1, total + n);
+            }
+            fn main() {
+                try {
+                    println(fails(0));
+                } catch (e) {
+                    println("caught: " + e);
+                }
+                try {
+                    deep(20000);
+                } catch (e) {
+                    println(e);
+                }
+                println(count(100000, 0));
+                println(fails(2));
+                fails(0);
+                println("unreachable");
+            }
+        "#);
+        assert_same_as_vm("fn main() {\n    println(\"bye\");\n    exit(3);\n}\n");
+        assert_same_as_vm("fn add(a: Int, b: Int) -> Int {\n    return a + b;\n}\nfn main() {\n    println(add(1));\n}\n");
+    }
+
+    #[test]
+    fn test_repository_corpus_native() {
+        if !have_c_compiler() {
+            return;
+        }
+        // Every golden program must print the same natively as in the VM
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests");
+        let mut programs: Vec<_> = std::fs::read_dir(&corpus)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "nx"))
+            .collect();
+        programs.sort();
+        let failures: Vec<String> = std::thread::scope(|scope| {
+            let workers: Vec<_> = programs
+                .chunks(programs.len().div_ceil(4).max(1))
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .filter_map(|path| {
+                                let source = std::fs::read_to_string(path).unwrap();
+                                let expected = run_program_at(&source, "test.nx", 2);
+                                let actual = run_native(&source, 2);
+                                (actual != expected).then(|| format!("{}:\n{}", path.display(), actual.render()))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+        });
+        assert!(failures.is_empty(), "native output mismatches:\n{}", failures.join("\n"));
+    }
+}
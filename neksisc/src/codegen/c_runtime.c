@@ -0,0 +1,1999 @@
+/*
+ * neksis native runtime
+ *
+ * Values, reference counted strings, arrays and objects, and the operations
+ * the generated code calls for each bytecode instruction. Everything here
+ * follows the VM in vm.rs, including its error messages, so a program
+ * prints the same whether it is interpreted or built with --native.
+ * Containers that reference themselves are never freed; unlike the VM there
+ * is no cycle collector.
+ */
+#include <errno.h>
+#include <inttypes.h>
+#include <math.h>
+#include <pthread.h>
+#include <setjmp.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <time.h>
+#include <unistd.h>
+
+/* NX_UNSET marks a local that has not been assigned; it comes first so zeroed slots are unset */
+typedef enum { NX_UNSET, NX_NULL, NX_INT, NX_FLOAT, NX_BOOL, NX_STRING, NX_ARRAY, NX_OBJECT, NX_BUILTIN } nx_tag;
+
+typedef struct nx_string {
+    size_t refs;
+    size_t len;
+    char data[];
+} nx_string;
+
+struct nx_array;
+struct nx_object;
+
+typedef struct nx_value {
+    nx_tag tag;
+    union {
+        int64_t i;
+        double f;
+        int b;
+        nx_string *s;
+        struct nx_array *a;
+        struct nx_object *o;
+        const char *builtin;
+    } as;
+} nx_value;
+
+typedef struct nx_array {
+    size_t refs;
+    size_t len, cap;
+    nx_value *items;
+} nx_array;
+
+/* Keys keep their insertion order */
+typedef struct nx_object {
+    size_t refs;
+    size_t len, cap;
+    nx_string **keys;
+    nx_value *values;
+} nx_object;
+
+static const nx_value NX_NULL_VALUE = { NX_NULL, { 0 } };
+static const nx_value NX_UNSET_VALUE = { NX_UNSET, { 0 } };
+
+static void nx_raise(nx_string *message) __attribute__((noreturn));
+static void nx_raisef(const char *format, ...) __attribute__((noreturn, format(printf, 1, 2)));
+
+static void *nx_alloc(size_t size) {
+    void *memory = malloc(size ? size : 1);
+    if (!memory) {
+        fputs("error: out of memory\n", stderr);
+        exit(1);
+    }
+    return memory;
+}
+
+static void *nx_realloc(void *memory, size_t size) {
+    memory = realloc(memory, size ? size : 1);
+    if (!memory) {
+        fputs("error: out of memory\n", stderr);
+        exit(1);
+    }
+    return memory;
+}
+
+/* ---- Reference counting ---- */
+
+static void nx_release(nx_value value);
+
+static inline void nx_retain(nx_value value) {
+    switch (value.tag) {
+    case NX_STRING: value.as.s->refs++; break;
+    case NX_ARRAY: value.as.a->refs++; break;
+    case NX_OBJECT: value.as.o->refs++; break;
+    default: break;
+    }
+}
+
+static void nx_string_release(nx_string *string) {
+    if (--string->refs == 0) {
+        free(string);
+    }
+}
+
+static void nx_release(nx_value value) {
+    switch (value.tag) {
+    case NX_STRING:
+        nx_string_release(value.as.s);
+        break;
+    case NX_ARRAY:
+        if (--value.as.a->refs == 0) {
+            nx_array *array = value.as.a;
+            for (size_t i = 0; i < array->len; i++) {
+                nx_release(array->items[i]);
+            }
+            free(array->items);
+            free(array);
+        }
+        break;
+    case NX_OBJECT:
+        if (--value.as.o->refs == 0) {
+            nx_object *object = value.as.o;
+            for (size_t i = 0; i < object->len; i++) {
+                nx_string_release(object->keys[i]);
+                nx_release(object->values[i]);
+            }
+            free(object->keys);
+            free(object->values);
+            free(object);
+        }
+        break;
+    default:
+        break;
+    }
+}
+
+/* ---- Constructors ---- */
+
+static nx_string *nx_string_new(const char *data, size_t len) {
+    nx_string *string = nx_alloc(sizeof(nx_string) + len + 1);
+    string->refs = 1;
+    string->len = len;
+    memcpy(string->data, data, len);
+    string->data[len] = '\0';
+    return string;
+}
+
+static nx_string *nx_string_cstr(const char *text) {
+    return nx_string_new(text, strlen(text));
+}
+
+static inline nx_value nx_int(int64_t i) { nx_value v; v.tag = NX_INT; v.as.i = i; return v; }
+static inline nx_value nx_float(double f) { nx_value v; v.tag = NX_FLOAT; v.as.f = f; return v; }
+static inline nx_value nx_bool(int b) { nx_value v; v.tag = NX_BOOL; v.as.b = b != 0; return v; }
+static inline nx_value nx_str(nx_string *s) { nx_value v; v.tag = NX_STRING; v.as.s = s; return v; }
+
+static nx_value nx_array_new(void) {
+    nx_array *array = nx_alloc(sizeof(nx_array));
+    array->refs = 1;
+    array->len = array->cap = 0;
+    array->items = NULL;
+    nx_value v;
+    v.tag = NX_ARRAY;
+    v.as.a = array;
+    return v;
+}
+
+/* Takes ownership of `value` */
+static void nx_array_append(nx_array *array, nx_value value) {
+    if (array->len == array->cap) {
+        array->cap = array->cap ? array->cap * 2 : 4;
+        array->items = nx_realloc(array->items, array->cap * sizeof(nx_value));
+    }
+    array->items[array->len++] = value;
+}
+
+static nx_value nx_object_new(void) {
+    nx_object *object = nx_alloc(sizeof(nx_object));
+    object->refs = 1;
+    object->len = object->cap = 0;
+    object->keys = NULL;
+    object->values = NULL;
+    nx_value v;
+    v.tag = NX_OBJECT;
+    v.as.o = object;
+    return v;
+}
+
+static long nx_object_find(nx_object *object, const nx_string *key) {
+    for (size_t i = 0; i < object->len; i++) {
+        if (object->keys[i]->len == key->len && memcmp(object->keys[i]->data, key->data, key->len) == 0) {
+            return (long)i;
+        }
+    }
+    return -1;
+}
+
+/* Takes ownership of `key` and `value` */
+static void nx_object_insert(nx_object *object, nx_string *key, nx_value value) {
+    long index = nx_object_find(object, key);
+    if (index >= 0) {
+        nx_string_release(key);
+        nx_release(object->values[index]);
+        object->values[index] = value;
+        return;
+    }
+    if (object->len == object->cap) {
+        object->cap = object->cap ? object->cap * 2 : 4;
+        object->keys = nx_realloc(object->keys, object->cap * sizeof(nx_string *));
+        object->values = nx_realloc(object->values, object->cap * sizeof(nx_value));
+    }
+    object->keys[object->len] = key;
+    object->values[object->len] = value;
+    object->len++;
+}
+
+/* ---- Strings ---- */
+
+typedef struct {
+    char *data;
+    size_t len, cap;
+} nx_buffer;
+
+static void nx_buffer_append(nx_buffer *buffer, const char *data, size_t len) {
+    if (buffer->len + len + 1 > buffer->cap) {
+        buffer->cap = (buffer->len + len + 1) * 2;
+        buffer->data = nx_realloc(buffer->data, buffer->cap);
+    }
+    memcpy(buffer->data + buffer->len, data, len);
+    buffer->len += len;
+    buffer->data[buffer->len] = '\0';
+}
+
+static void nx_buffer_puts(nx_buffer *buffer, const char *text) {
+    nx_buffer_append(buffer, text, strlen(text));
+}
+
+static nx_string *nx_buffer_finish(nx_buffer *buffer) {
+    nx_string *string = nx_string_new(buffer->data ? buffer->data : "", buffer->len);
+    free(buffer->data);
+    return string;
+}
+
+/* Rust's `Display` for f64: the shortest digits that round trip, never in exponent notation */
+static void nx_format_float(nx_buffer *out, double value) {
+    if (isnan(value)) {
+        nx_buffer_puts(out, "NaN");
+        return;
+    }
+    if (isinf(value)) {
+        nx_buffer_puts(out, value < 0 ? "-inf" : "inf");
+        return;
+    }
+    if (signbit(value)) {
+        nx_buffer_puts(out, "-");
+        value = -value;
+    }
+    if (value == 0.0) {
+        nx_buffer_puts(out, "0");
+        return;
+    }
+    char scientific[40];
+    for (int precision = 0; precision < 17; precision++) {
+        snprintf(scientific, sizeof scientific, "%.*e", precision, value);
+        if (strtod(scientific, NULL) == value) {
+            break;
+        }
+    }
+    /* scientific is d[.ddd]e[+-]xx */
+    char digits[24];
+    size_t count = 0;
+    const char *p = scientific;
+    for (; *p && *p != 'e'; p++) {
+        if (*p != '.') {
+            digits[count++] = *p;
+        }
+    }
+    int exponent = atoi(p + 1);
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+    if (exponent < 0) {
+        nx_buffer_puts(out, "0.");
+        for (int i = 0; i < -exponent - 1; i++) {
+            nx_buffer_puts(out, "0");
+        }
+        nx_buffer_append(out, digits, count);
+        return;
+    }
+    size_t integer_digits = (size_t)exponent + 1;
+    if (count <= integer_digits) {
+        nx_buffer_append(out, digits, count);
+        for (size_t i = count; i < integer_digits; i++) {
+            nx_buffer_puts(out, "0");
+        }
+    } else {
+        nx_buffer_append(out, digits, integer_digits);
+        nx_buffer_puts(out, ".");
+        nx_buffer_append(out, digits + integer_digits, count - integer_digits);
+    }
+}
+
+typedef struct {
+    const void *items[64];
+    size_t len;
+} nx_open_set;
+
+/* `VMValue::to_string`; containers already being printed show as `[...]` or `{...}` */
+static void nx_format(nx_buffer *out, nx_value value, nx_open_set *open) {
+    char number[32];
+    switch (value.tag) {
+    case NX_INT:
+        snprintf(number, sizeof number, "%" PRId64, value.as.i);
+        nx_buffer_puts(out, number);
+        break;
+    case NX_FLOAT:
+        nx_format_float(out, value.as.f);
+        break;
+    case NX_STRING:
+        nx_buffer_append(out, value.as.s->data, value.as.s->len);
+        break;
+    case NX_BOOL:
+        nx_buffer_puts(out, value.as.b ? "true" : "false");
+        break;
+    case NX_NULL:
+    case NX_UNSET:
+        nx_buffer_puts(out, "null");
+        break;
+    case NX_BUILTIN:
+        nx_buffer_puts(out, "<builtin ");
+        nx_buffer_puts(out, value.as.builtin);
+        nx_buffer_puts(out, ">");
+        break;
+    case NX_ARRAY:
+    case NX_OBJECT: {
+        int is_array = value.tag == NX_ARRAY;
+        const void *address = is_array ? (const void *)value.as.a : (const void *)value.as.o;
+        for (size_t i = 0; i < open->len; i++) {
+            if (open->items[i] == address) {
+                nx_buffer_puts(out, is_array ? "[...]" : "{...}");
+                return;
+            }
+        }
+        int tracked = open->len < 64;
+        if (tracked) {
+            open->items[open->len++] = address;
+        }
+        nx_buffer_puts(out, is_array ? "[" : "{");
+        size_t len = is_array ? value.as.a->len : value.as.o->len;
+        for (size_t i = 0; i < len; i++) {
+            if (i > 0) {
+                nx_buffer_puts(out, ", ");
+            }
+            if (is_array) {
+                nx_format(out, value.as.a->items[i], open);
+            } else {
+                nx_buffer_append(out, value.as.o->keys[i]->data, value.as.o->keys[i]->len);
+                nx_buffer_puts(out, ": ");
+                nx_format(out, value.as.o->values[i], open);
+            }
+        }
+        nx_buffer_puts(out, is_array ? "]" : "}");
+        if (tracked) {
+            open->len--;
+        }
+        break;
+    }
+    }
+}
+
+/* A new string; strings themselves are returned with another reference */
+static nx_string *nx_to_string(nx_value value) {
+    if (value.tag == NX_STRING) {
+        value.as.s->refs++;
+        return value.as.s;
+    }
+    nx_buffer out = { 0 };
+    nx_open_set open = { .len = 0 };
+    nx_format(&out, value, &open);
+    return nx_buffer_finish(&out);
+}
+
+static nx_string *nx_concat(const nx_string *a, const nx_string *b) {
+    nx_string *string = nx_alloc(sizeof(nx_string) + a->len + b->len + 1);
+    string->refs = 1;
+    string->len = a->len + b->len;
+    memcpy(string->data, a->data, a->len);
+    memcpy(string->data + a->len, b->data, b->len);
+    string->data[string->len] = '\0';
+    return string;
+}
+
+static int nx_truthy(nx_value value) {
+    switch (value.tag) {
+    case NX_INT: return value.as.i != 0;
+    case NX_FLOAT: return value.as.f != 0.0;
+    case NX_STRING: return value.as.s->len != 0;
+    case NX_BOOL: return value.as.b;
+    case NX_NULL:
+    case NX_UNSET: return 0;
+    default: return 1;
+    }
+}
+
+/* `VMValue`'s `PartialEq`: containers compare by identity */
+static int nx_equal(nx_value a, nx_value b) {
+    switch (a.tag) {
+    case NX_INT:
+        if (b.tag == NX_INT) return a.as.i == b.as.i;
+        if (b.tag == NX_FLOAT) return (double)a.as.i == b.as.f;
+        return 0;
+    case NX_FLOAT:
+        if (b.tag == NX_FLOAT) return a.as.f == b.as.f;
+        if (b.tag == NX_INT) return a.as.f == (double)b.as.i;
+        return 0;
+    case NX_STRING:
+        return b.tag == NX_STRING && a.as.s->len == b.as.s->len && memcmp(a.as.s->data, b.as.s->data, a.as.s->len) == 0;
+    case NX_BOOL:
+        return b.tag == NX_BOOL && a.as.b == b.as.b;
+    case NX_NULL:
+        return b.tag == NX_NULL;
+    case NX_ARRAY:
+        return b.tag == NX_ARRAY && a.as.a == b.as.a;
+    case NX_OBJECT:
+        return b.tag == NX_OBJECT && a.as.o == b.as.o;
+    default:
+        return 0;
+    }
+}
+
+/* Deep equality used by assertions */
+static int nx_structurally_equal(nx_value a, nx_value b) {
+    if (a.tag == NX_ARRAY && b.tag == NX_ARRAY) {
+        if (a.as.a == b.as.a) return 1;
+        if (a.as.a->len != b.as.a->len) return 0;
+        for (size_t i = 0; i < a.as.a->len; i++) {
+            if (!nx_structurally_equal(a.as.a->items[i], b.as.a->items[i])) return 0;
+        }
+        return 1;
+    }
+    if (a.tag == NX_OBJECT && b.tag == NX_OBJECT) {
+        if (a.as.o == b.as.o) return 1;
+        if (a.as.o->len != b.as.o->len) return 0;
+        for (size_t i = 0; i < a.as.o->len; i++) {
+            long j = nx_object_find(b.as.o, a.as.o->keys[i]);
+            if (j < 0 || !nx_structurally_equal(a.as.o->values[i], b.as.o->values[j])) return 0;
+        }
+        return 1;
+    }
+    return nx_equal(a, b);
+}
+
+/* `VMValue`'s `PartialOrd`: -1, 0 or 1, or 2 when the values are unordered */
+static int nx_compare(nx_value a, nx_value b) {
+    double x, y;
+    if (a.tag == NX_INT && b.tag == NX_INT) {
+        return (a.as.i > b.as.i) - (a.as.i < b.as.i);
+    } else if (a.tag == NX_STRING && b.tag == NX_STRING) {
+        size_t len = a.as.s->len < b.as.s->len ? a.as.s->len : b.as.s->len;
+        int order = memcmp(a.as.s->data, b.as.s->data, len);
+        if (order != 0) return order < 0 ? -1 : 1;
+        return (a.as.s->len > b.as.s->len) - (a.as.s->len < b.as.s->len);
+    } else if (a.tag == NX_BOOL && b.tag == NX_BOOL) {
+        return (a.as.b > b.as.b) - (a.as.b < b.as.b);
+    } else if ((a.tag == NX_INT || a.tag == NX_FLOAT) && (b.tag == NX_INT || b.tag == NX_FLOAT)) {
+        x = a.tag == NX_INT ? (double)a.as.i : a.as.f;
+        y = b.tag == NX_INT ? (double)b.as.i : b.as.f;
+        if (isnan(x) || isnan(y)) return 2;
+        return (x > y) - (x < y);
+    }
+    return 2;
+}
+
+/* Rust's saturating `as i64` */
+static int64_t nx_float_to_int(double value) {
+    if (isnan(value)) return 0;
+    if (value >= 9223372036854775807.0) return INT64_MAX;
+    if (value <= -9223372036854775808.0) return INT64_MIN;
+    return (int64_t)value;
+}
+
+static int64_t nx_wrap_add(int64_t a, int64_t b) { return (int64_t)((uint64_t)a + (uint64_t)b); }
+static int64_t nx_wrap_sub(int64_t a, int64_t b) { return (int64_t)((uint64_t)a - (uint64_t)b); }
+static int64_t nx_wrap_mul(int64_t a, int64_t b) { return (int64_t)((uint64_t)a * (uint64_t)b); }
+
+/* ---- Machine state ---- */
+
+static nx_value *nx_stack;
+static size_t nx_sp, nx_stack_cap;
+
+typedef struct {
+    const char *function;
+    nx_value *locals;
+    size_t count;
+} nx_frame;
+
+static nx_frame *nx_frames;
+static size_t nx_depth, nx_frames_cap;
+static size_t nx_max_depth = 10000;
+
+/* Locals of active calls, allocated like a stack. They live outside the C
+ * stack so that calls in tail position compile to jumps. */
+static nx_value *nx_locals;
+static size_t nx_locals_len, nx_locals_cap;
+
+typedef struct {
+    jmp_buf jump;
+    size_t stack_len;
+    size_t depth;
+} nx_handler;
+
+static nx_handler **nx_handlers;
+static size_t nx_handler_count, nx_handlers_cap;
+
+/* Variables stored with StoreGlobal */
+static nx_object *nx_globals;
+
+static inline void nx_push(nx_value value) {
+    if (nx_sp == nx_stack_cap) {
+        nx_stack_cap = nx_stack_cap ? nx_stack_cap * 2 : 1024;
+        nx_stack = nx_realloc(nx_stack, nx_stack_cap * sizeof(nx_value));
+    }
+    nx_stack[nx_sp++] = value;
+}
+
+/* Pops into `out` and returns 1, or returns 0 when the stack is empty */
+static inline int nx_pop(nx_value *out) {
+    if (nx_sp == 0) {
+        *out = NX_NULL_VALUE;
+        return 0;
+    }
+    *out = nx_stack[--nx_sp];
+    return 1;
+}
+
+static inline nx_value nx_pop_or(const char *message) {
+    nx_value value;
+    if (!nx_pop(&value)) {
+        nx_raisef("%s", message);
+    }
+    return value;
+}
+
+static void nx_push_null(void) { nx_push(NX_NULL_VALUE); }
+static void nx_push_int(int64_t i) { nx_push(nx_int(i)); }
+static void nx_push_float(double f) { nx_push(nx_float(f)); }
+
+/* Float constants are emitted as their bits so every value round trips exactly */
+static void nx_push_float_bits(uint64_t bits) {
+    double f;
+    memcpy(&f, &bits, sizeof f);
+    nx_push_float(f);
+}
+static void nx_push_bool(int b) { nx_push(nx_bool(b)); }
+
+static void nx_push_string(nx_string *constant) {
+    constant->refs++;
+    nx_push(nx_str(constant));
+}
+
+static void nx_op_pop(void) {
+    nx_value value;
+    if (nx_pop(&value)) {
+        nx_release(value);
+    }
+}
+
+static void nx_op_dup(void) {
+    if (nx_sp == 0) nx_raisef("Stack underflow");
+    nx_value top = nx_stack[nx_sp - 1];
+    nx_retain(top);
+    nx_push(top);
+}
+
+static void nx_op_swap(void) {
+    if (nx_sp < 2) nx_raisef("Stack underflow for swap");
+    nx_value top = nx_stack[nx_sp - 1];
+    nx_stack[nx_sp - 1] = nx_stack[nx_sp - 2];
+    nx_stack[nx_sp - 2] = top;
+}
+
+/* ---- Errors and calls ---- */
+
+static void nx_flush(void) {
+    fflush(stdout);
+}
+
+static void nx_pop_frame(void) {
+    nx_frame *frame = &nx_frames[--nx_depth];
+    for (size_t i = 0; i < frame->count; i++) {
+        nx_release(frame->locals[i]);
+    }
+    nx_locals_len -= frame->count;
+}
+
+/* Unwind to the innermost `try` block with the message on the stack, or
+ * end the program the way `neksis run` reports an uncaught error */
+static void nx_raise(nx_string *message) {
+    if (nx_handler_count == 0) {
+        nx_flush();
+        fputs("error: ", stderr);
+        fwrite(message->data, 1, message->len, stderr);
+        fputs("\n", stderr);
+        exit(1);
+    }
+    nx_handler *handler = nx_handlers[--nx_handler_count];
+    while (nx_sp > handler->stack_len) {
+        nx_release(nx_stack[--nx_sp]);
+    }
+    while (nx_depth > handler->depth) {
+        nx_pop_frame();
+    }
+    nx_push(nx_str(message));
+    jmp_buf jump;
+    memcpy(jump, handler->jump, sizeof(jmp_buf));
+    free(handler);
+    longjmp(jump, 1);
+}
+
+static void nx_raisef(const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    int len = vsnprintf(NULL, 0, format, args);
+    va_end(args);
+    nx_string *message = nx_alloc(sizeof(nx_string) + (size_t)len + 1);
+    message->refs = 1;
+    message->len = (size_t)len;
+    va_start(args, format);
+    vsnprintf(message->data, (size_t)len + 1, format, args);
+    va_end(args);
+    nx_raise(message);
+}
+
+static nx_handler *nx_try(void) {
+    nx_handler *handler = nx_alloc(sizeof(nx_handler));
+    handler->stack_len = nx_sp;
+    handler->depth = nx_depth;
+    if (nx_handler_count == nx_handlers_cap) {
+        nx_handlers_cap = nx_handlers_cap ? nx_handlers_cap * 2 : 16;
+        nx_handlers = nx_realloc(nx_handlers, nx_handlers_cap * sizeof(nx_handler *));
+    }
+    nx_handlers[nx_handler_count++] = handler;
+    return handler;
+}
+
+static void nx_end_try(void) {
+    if (nx_handler_count > 0) {
+        free(nx_handlers[--nx_handler_count]);
+    }
+}
+
+/* `try` blocks of frames that have returned can no longer catch anything */
+static void nx_drop_handlers_above(size_t depth) {
+    while (nx_handler_count > 0 && nx_handlers[nx_handler_count - 1]->depth > depth) {
+        free(nx_handlers[--nx_handler_count]);
+    }
+}
+
+/* Outermost call first, with repeated calls and short repeating cycles collapsed */
+static void nx_summarize_call_chain(nx_buffer *out, const char **chain, size_t len) {
+    enum { MAX_CYCLE = 4, MAX_PARTS = 16 };
+    nx_buffer *parts = NULL;
+    size_t part_count = 0;
+    size_t i = 0;
+    while (i < len) {
+        size_t best_length = 1, best_repeats = 1, best_score = 0;
+        for (size_t length = 1; length <= MAX_CYCLE && length <= len - i; length++) {
+            size_t repeats = 1;
+            while (i + (repeats + 1) * length <= len) {
+                int same = 1;
+                for (size_t k = 0; k < length; k++) {
+                    if (strcmp(chain[i + repeats * length + k], chain[i + k]) != 0) {
+                        same = 0;
+                        break;
+                    }
+                }
+                if (!same) break;
+                repeats++;
+            }
+            /* The most calls covered wins, and the shorter cycle on a tie */
+            size_t score = repeats > 1 ? length * repeats : 0;
+            if (length == 1 || score > best_score) {
+                best_length = length;
+                best_repeats = repeats;
+                best_score = score;
+            }
+        }
+        nx_buffer part = { 0 };
+        if (best_repeats > 1) {
+            char count[32];
+            if (best_length > 1) nx_buffer_puts(&part, "(");
+            for (size_t k = 0; k < best_length; k++) {
+                if (k > 0) nx_buffer_puts(&part, " -> ");
+                nx_buffer_puts(&part, chain[i + k]);
+            }
+            if (best_length > 1) nx_buffer_puts(&part, ")");
+            snprintf(count, sizeof count, " (x%zu)", best_repeats);
+            nx_buffer_puts(&part, count);
+            i += best_length * best_repeats;
+        } else {
+            nx_buffer_puts(&part, chain[i]);
+            i++;
+        }
+        parts = nx_realloc(parts, (part_count + 1) * sizeof(nx_buffer));
+        parts[part_count++] = part;
+    }
+    size_t hidden = part_count > MAX_PARTS ? part_count - MAX_PARTS : 0;
+    int first = 1;
+    for (size_t k = 0; k < part_count; k++) {
+        if (hidden && k == MAX_PARTS / 2) {
+            char more[48];
+            snprintf(more, sizeof more, "%s... %zu more ...", first ? "" : " -> ", hidden);
+            nx_buffer_puts(out, more);
+            first = 0;
+        }
+        if (!(hidden && k >= MAX_PARTS / 2 && k < MAX_PARTS / 2 + hidden)) {
+            if (!first) nx_buffer_puts(out, " -> ");
+            nx_buffer_append(out, parts[k].data ? parts[k].data : "", parts[k].len);
+            first = 0;
+        }
+        free(parts[k].data);
+    }
+    free(parts);
+}
+
+static void nx_stack_overflow(const char *callee) __attribute__((noreturn));
+static void nx_stack_overflow(const char *callee) {
+    const char **chain = nx_alloc((nx_depth + 1) * sizeof(const char *));
+    for (size_t i = 0; i < nx_depth; i++) {
+        chain[i] = nx_frames[i].function;
+    }
+    chain[nx_depth] = callee;
+    nx_buffer out = { 0 };
+    char head[96];
+    snprintf(head, sizeof head, "StackOverflow: maximum call depth of %zu exceeded\ncall chain: ", nx_max_depth);
+    nx_buffer_puts(&out, head);
+    nx_summarize_call_chain(&out, chain, nx_depth + 1);
+    free(chain);
+    nx_raise(nx_buffer_finish(&out));
+}
+
+/* Start a call to `function` and return its `count` locals */
+static nx_value *nx_enter(const char *function, size_t count) {
+    if (nx_depth >= nx_max_depth) {
+        nx_stack_overflow(function);
+    }
+    if (nx_depth == nx_frames_cap) {
+        nx_frames_cap = nx_frames_cap ? nx_frames_cap * 2 : 64;
+        nx_frames = nx_realloc(nx_frames, nx_frames_cap * sizeof(nx_frame));
+    }
+    if (nx_locals_len + count > nx_locals_cap) {
+        /* Reserved up front in nx_init, so this only happens past the call depth limit */
+        fputs("error: out of memory for locals\n", stderr);
+        exit(1);
+    }
+    nx_value *locals = nx_locals + nx_locals_len;
+    for (size_t i = 0; i < count; i++) {
+        locals[i] = NX_UNSET_VALUE;
+    }
+    nx_locals_len += count;
+    nx_frames[nx_depth].function = function;
+    nx_frames[nx_depth].locals = locals;
+    nx_frames[nx_depth].count = count;
+    nx_depth++;
+    return locals;
+}
+
+static void nx_leave(void) {
+    nx_pop_frame();
+    nx_drop_handlers_above(nx_depth);
+}
+
+static void nx_check_arity(const char *function, size_t expected, size_t got) {
+    if (expected != got) {
+        nx_raisef("Function %s expects %zu arguments, got %zu", function, expected, got);
+    }
+}
+
+static void nx_undefined_function(const char *function) {
+    nx_raisef("Undefined function: %s", function);
+}
+
+static void nx_return_without_call(void) {
+    nx_raisef("Return without function call");
+}
+
+/* ---- Variables ---- */
+
+static void nx_load(nx_value *slot, const char *name) {
+    if (slot && slot->tag != NX_UNSET) {
+        nx_retain(*slot);
+        nx_push(*slot);
+        return;
+    }
+    nx_string *key = nx_string_cstr(name);
+    long index = nx_globals ? nx_object_find(nx_globals, key) : -1;
+    nx_string_release(key);
+    if (index >= 0) {
+        nx_value value = nx_globals->values[index];
+        nx_retain(value);
+        nx_push(value);
+    } else if (strcmp(name, "print") == 0 || strcmp(name, "println") == 0 || strcmp(name, "read_line") == 0) {
+        nx_value builtin;
+        builtin.tag = NX_BUILTIN;
+        builtin.as.builtin = strcmp(name, "print") == 0 ? "print" : strcmp(name, "println") == 0 ? "println" : "read_line";
+        nx_push(builtin);
+    } else {
+        nx_raisef("Undefined variable: %s", name);
+    }
+}
+
+static void nx_store(nx_value *slot) {
+    nx_value value = nx_pop_or("Stack underflow");
+    nx_release(*slot);
+    *slot = value;
+}
+
+static void nx_load_global(const char *name) {
+    nx_string *key = nx_string_cstr(name);
+    long index = nx_globals ? nx_object_find(nx_globals, key) : -1;
+    nx_string_release(key);
+    if (index < 0) {
+        nx_raisef("Global variable '%s' not found", name);
+    }
+    nx_retain(nx_globals->values[index]);
+    nx_push(nx_globals->values[index]);
+}
+
+static void nx_store_global(const char *name) {
+    nx_value value = nx_pop_or("Stack underflow");
+    if (!nx_globals) {
+        nx_globals = nx_object_new().as.o;
+    }
+    nx_object_insert(nx_globals, nx_string_cstr(name), value);
+}
+
+/* ---- Arithmetic, comparison and logic ---- */
+
+typedef enum { NX_ADD, NX_SUB, NX_MUL, NX_DIV, NX_MOD } nx_arith;
+
+static void nx_arithmetic(nx_arith op) {
+    nx_value right, left;
+    int have_right = nx_pop(&right), have_left = nx_pop(&left);
+    if (!have_right || !have_left) {
+        nx_release(right);
+        nx_release(left);
+        nx_raisef("Stack underflow");
+    }
+    if (left.tag == NX_INT && right.tag == NX_INT) {
+        int64_t a = left.as.i, b = right.as.i;
+        switch (op) {
+        case NX_ADD: nx_push_int(nx_wrap_add(a, b)); return;
+        case NX_SUB: nx_push_int(nx_wrap_sub(a, b)); return;
+        case NX_MUL: nx_push_int(nx_wrap_mul(a, b)); return;
+        case NX_DIV:
+            if (b == 0) nx_raisef("Division by zero");
+            nx_push_int(b == -1 ? nx_wrap_sub(0, a) : a / b);
+            return;
+        case NX_MOD:
+            if (b == 0) nx_raisef("Division by zero");
+            nx_push_int(b == -1 ? 0 : a % b);
+            return;
+        }
+    }
+    int left_number = left.tag == NX_INT || left.tag == NX_FLOAT;
+    int right_number = right.tag == NX_INT || right.tag == NX_FLOAT;
+    if (left_number && right_number) {
+        double a = left.tag == NX_INT ? (double)left.as.i : left.as.f;
+        double b = right.tag == NX_INT ? (double)right.as.i : right.as.f;
+        switch (op) {
+        case NX_ADD: nx_push_float(a + b); return;
+        case NX_SUB: nx_push_float(a - b); return;
+        case NX_MUL: nx_push_float(a * b); return;
+        case NX_DIV:
+            if (b == 0.0) nx_raisef("Division by zero");
+            nx_push_float(a / b);
+            return;
+        case NX_MOD:
+            if (b == 0.0) nx_raisef("Division by zero");
+            nx_push_float(fmod(a, b));
+            return;
+        }
+    }
+    if (op == NX_ADD && (left.tag == NX_STRING || right.tag == NX_STRING) &&
+        (left.tag == NX_STRING || left_number) && (right.tag == NX_STRING || right_number)) {
+        nx_string *a = nx_to_string(left), *b = nx_to_string(right);
+        nx_push(nx_str(nx_concat(a, b)));
+        nx_string_release(a);
+        nx_string_release(b);
+        nx_release(left);
+        nx_release(right);
+        return;
+    }
+    nx_release(left);
+    nx_release(right);
+    nx_raisef("Cannot perform arithmetic on non-numeric value");
+}
+
+static void nx_add(void) { nx_arithmetic(NX_ADD); }
+static void nx_sub(void) { nx_arithmetic(NX_SUB); }
+static void nx_mul(void) { nx_arithmetic(NX_MUL); }
+static void nx_div(void) { nx_arithmetic(NX_DIV); }
+static void nx_mod(void) { nx_arithmetic(NX_MOD); }
+
+static void nx_neg(void) {
+    nx_value value = nx_pop_or("Stack underflow");
+    if (value.tag == NX_INT) {
+        nx_push_int(nx_wrap_sub(0, value.as.i));
+    } else if (value.tag == NX_FLOAT) {
+        nx_push_float(-value.as.f);
+    } else {
+        nx_release(value);
+        nx_raisef("Cannot negate non-numeric value");
+    }
+}
+
+static void nx_pop_two(nx_value *left, nx_value *right) {
+    int have_right = nx_pop(right), have_left = nx_pop(left);
+    if (!have_right || !have_left) {
+        nx_release(*right);
+        nx_release(*left);
+        nx_raisef("Stack underflow");
+    }
+}
+
+typedef enum { NX_EQ, NX_NE, NX_LT, NX_LE, NX_GT, NX_GE } nx_comparison;
+
+static void nx_compare_op(nx_comparison op) {
+    nx_value left, right;
+    nx_pop_two(&left, &right);
+    int result;
+    if (op == NX_EQ || op == NX_NE) {
+        result = nx_equal(left, right) == (op == NX_EQ);
+    } else {
+        int order = nx_compare(left, right);
+        if (order == 2) {
+            nx_release(left);
+            nx_release(right);
+            nx_raisef("Cannot compare values");
+        }
+        result = op == NX_LT ? order < 0 : op == NX_LE ? order <= 0 : op == NX_GT ? order > 0 : order >= 0;
+    }
+    nx_release(left);
+    nx_release(right);
+    nx_push_bool(result);
+}
+
+static void nx_eq(void) { nx_compare_op(NX_EQ); }
+static void nx_ne(void) { nx_compare_op(NX_NE); }
+static void nx_lt(void) { nx_compare_op(NX_LT); }
+static void nx_le(void) { nx_compare_op(NX_LE); }
+static void nx_gt(void) { nx_compare_op(NX_GT); }
+static void nx_ge(void) { nx_compare_op(NX_GE); }
+
+static void nx_and(void) {
+    nx_value left, right;
+    nx_pop_two(&left, &right);
+    int result = nx_truthy(left) && nx_truthy(right);
+    nx_release(left);
+    nx_release(right);
+    nx_push_bool(result);
+}
+
+static void nx_or(void) {
+    nx_value left, right;
+    nx_pop_two(&left, &right);
+    int result = nx_truthy(left) || nx_truthy(right);
+    nx_release(left);
+    nx_release(right);
+    nx_push_bool(result);
+}
+
+static void nx_not(void) {
+    nx_value value = nx_pop_or("Stack underflow");
+    int result = !nx_truthy(value);
+    nx_release(value);
+    nx_push_bool(result);
+}
+
+/* The condition of a conditional jump */
+static int nx_pop_truthy(void) {
+    nx_value value = nx_pop_or("Stack underflow");
+    int result = nx_truthy(value);
+    nx_release(value);
+    return result;
+}
+
+/* ---- Output and files ---- */
+
+static void nx_write_value(FILE *stream, nx_value value, int newline) {
+    nx_string *text = nx_to_string(value);
+    fwrite(text->data, 1, text->len, stream);
+    if (newline) {
+        fputc('\n', stream);
+    }
+    nx_string_release(text);
+    nx_release(value);
+}
+
+static void nx_print(void) { nx_write_value(stdout, nx_pop_or("Stack underflow"), 0); }
+static void nx_println(void) { nx_write_value(stdout, nx_pop_or("Stack underflow"), 1); }
+
+static int nx_is_space(unsigned char c) {
+    return c == ' ' || c == '\t' || c == '\n' || c == '\r' || c == '\v' || c == '\f';
+}
+
+static nx_string *nx_trimmed(const char *data, size_t len) {
+    size_t start = 0;
+    while (start < len && nx_is_space((unsigned char)data[start])) start++;
+    while (len > start && nx_is_space((unsigned char)data[len - 1])) len--;
+    return nx_string_new(data + start, len - start);
+}
+
+static void nx_read_line(void) {
+    nx_buffer line = { 0 };
+    int c;
+    while ((c = getchar()) != EOF) {
+        char ch = (char)c;
+        nx_buffer_append(&line, &ch, 1);
+        if (c == '\n') break;
+    }
+    nx_push(nx_str(nx_trimmed(line.data ? line.data : "", line.len)));
+    free(line.data);
+}
+
+/* Rust's `io::Error` display */
+static const char *nx_os_error(char *buffer, size_t size) {
+    int code = errno;
+    const char *text = strerror(code);
+    snprintf(buffer, size, "%s (os error %d)", text, code);
+    if (buffer[0] >= 'a' && buffer[0] <= 'z') buffer[0] = (char)(buffer[0] - 'a' + 'A');
+    return buffer;
+}
+
+static void nx_read_file(void) {
+    nx_value path = nx_pop_or("read_file expects a string path");
+    if (path.tag != NX_STRING) {
+        nx_release(path);
+        nx_raisef("read_file expects a string path");
+    }
+    FILE *file = fopen(path.as.s->data, "rb");
+    struct stat info;
+    if (file && fstat(fileno(file), &info) == 0 && S_ISDIR(info.st_mode)) {
+        fclose(file);
+        file = NULL;
+        errno = EISDIR;
+    }
+    if (!file) {
+        char error[256];
+        nx_os_error(error, sizeof error);
+        nx_raisef("Failed to read file '%s': %s", path.as.s->data, error);
+    }
+    nx_buffer content = { 0 };
+    char chunk[4096];
+    size_t n;
+    while ((n = fread(chunk, 1, sizeof chunk, file)) > 0) {
+        nx_buffer_append(&content, chunk, n);
+    }
+    fclose(file);
+    nx_release(path);
+    nx_push(nx_str(nx_buffer_finish(&content)));
+}
+
+static void nx_write_or_append(const char *mode, const char *name) {
+    nx_value content, path;
+    int have_content = nx_pop(&content), have_path = nx_pop(&path);
+    if (!have_content || !have_path || content.tag != NX_STRING || path.tag != NX_STRING) {
+        nx_release(content);
+        nx_release(path);
+        nx_raisef("%s expects string arguments", name);
+    }
+    FILE *file = fopen(path.as.s->data, mode);
+    char error[256];
+    if (!file) {
+        nx_os_error(error, sizeof error);
+        if (mode[0] == 'a') {
+            nx_raisef("Failed to open file '%s' for appending: %s", path.as.s->data, error);
+        }
+        nx_raisef("Failed to write file '%s': %s", path.as.s->data, error);
+    }
+    if (fwrite(content.as.s->data, 1, content.as.s->len, file) != content.as.s->len) {
+        nx_os_error(error, sizeof error);
+        fclose(file);
+        if (mode[0] == 'a') {
+            nx_raisef("Failed to append to file '%s': %s", path.as.s->data, error);
+        }
+        nx_raisef("Failed to write file '%s': %s", path.as.s->data, error);
+    }
+    fclose(file);
+    nx_release(content);
+    nx_release(path);
+    nx_push_null();
+}
+
+static void nx_write_file(void) { nx_write_or_append("wb", "write_file"); }
+static void nx_append_file(void) { nx_write_or_append("ab", "append_file"); }
+
+static void nx_file_exists(void) {
+    nx_value path = nx_pop_or("file_exists expects a string path");
+    if (path.tag != NX_STRING) {
+        nx_release(path);
+        nx_raisef("file_exists expects a string path");
+    }
+    struct stat info;
+    int exists = stat(path.as.s->data, &info) == 0;
+    nx_release(path);
+    nx_push_bool(exists);
+}
+
+/* ---- Math ---- */
+
+static double nx_number(nx_value value, const char *message) {
+    if (value.tag == NX_INT) return (double)value.as.i;
+    if (value.tag == NX_FLOAT) return value.as.f;
+    nx_release(value);
+    nx_raisef("%s", message);
+}
+
+static void nx_abs(void) {
+    nx_value value = nx_pop_or("Stack underflow for abs");
+    if (value.tag == NX_INT) {
+        nx_push_int(value.as.i < 0 ? nx_wrap_sub(0, value.as.i) : value.as.i);
+    } else {
+        nx_push_float(fabs(nx_number(value, "abs expects a numeric value")));
+    }
+}
+
+static void nx_sqrt(void) {
+    double value = nx_number(nx_pop_or("Stack underflow for sqrt"), "sqrt expects a numeric value");
+    if (value < 0.0) nx_raisef("sqrt of negative number");
+    nx_push_float(sqrt(value));
+}
+
+static void nx_sin(void) { nx_push_float(sin(nx_number(nx_pop_or("Stack underflow for sin"), "sin expects a numeric value"))); }
+static void nx_cos(void) { nx_push_float(cos(nx_number(nx_pop_or("Stack underflow for cos"), "cos expects a numeric value"))); }
+static void nx_tan(void) { nx_push_float(tan(nx_number(nx_pop_or("Stack underflow for tan"), "tan expects a numeric value"))); }
+
+static void nx_rounding(double (*round_fn)(double), const char *name) {
+    char underflow[48], message[48];
+    snprintf(underflow, sizeof underflow, "Stack underflow for %s", name);
+    snprintf(message, sizeof message, "%s expects a numeric value", name);
+    nx_value value = nx_pop_or(underflow);
+    if (value.tag == NX_INT) {
+        nx_push(value);
+    } else {
+        nx_push_int(nx_float_to_int(round_fn(nx_number(value, message))));
+    }
+}
+
+static void nx_floor(void) { nx_rounding(floor, "floor"); }
+static void nx_ceil(void) { nx_rounding(ceil, "ceil"); }
+static void nx_round(void) { nx_rounding(round, "round"); }
+
+static void nx_pow(void) {
+    nx_value exponent, base;
+    int have_exponent = nx_pop(&exponent), have_base = nx_pop(&base);
+    if (!have_exponent || !have_base) nx_raisef("Stack underflow for pow");
+    if (base.tag == NX_INT && exponent.tag == NX_INT && exponent.as.i >= 0) {
+        uint32_t e = (uint32_t)exponent.as.i;
+        int64_t result = 1, factor = base.as.i;
+        while (e) {
+            if (e & 1) result = nx_wrap_mul(result, factor);
+            factor = nx_wrap_mul(factor, factor);
+            e >>= 1;
+        }
+        nx_push_int(result);
+        return;
+    }
+    int numbers = (base.tag == NX_INT || base.tag == NX_FLOAT) && (exponent.tag == NX_INT || exponent.tag == NX_FLOAT);
+    if (!numbers) {
+        nx_release(base);
+        nx_release(exponent);
+        nx_raisef("pow expects numeric values");
+    }
+    nx_push_float(pow(nx_number(base, ""), nx_number(exponent, "")));
+}
+
+static void nx_min_max(int is_max) {
+    nx_value b, a;
+    int have_b = nx_pop(&b), have_a = nx_pop(&a);
+    if (!have_b || !have_a) nx_raisef(is_max ? "Stack underflow for max" : "Stack underflow for min");
+    if (a.tag == NX_INT && b.tag == NX_INT) {
+        nx_push_int(is_max ? (a.as.i > b.as.i ? a.as.i : b.as.i) : (a.as.i < b.as.i ? a.as.i : b.as.i));
+        return;
+    }
+    int numbers = (a.tag == NX_INT || a.tag == NX_FLOAT) && (b.tag == NX_INT || b.tag == NX_FLOAT);
+    if (!numbers) {
+        nx_release(a);
+        nx_release(b);
+        nx_raisef(is_max ? "max expects numeric values" : "min expects numeric values");
+    }
+    double x = nx_number(a, ""), y = nx_number(b, "");
+    nx_push_float(is_max ? fmax(x, y) : fmin(x, y));
+}
+
+static void nx_min(void) { nx_min_max(0); }
+static void nx_max(void) { nx_min_max(1); }
+
+/* ---- Strings ---- */
+
+static void nx_len(void) {
+    nx_value value = nx_pop_or("len expects a string, array or object");
+    int64_t len;
+    switch (value.tag) {
+    case NX_STRING: len = (int64_t)value.as.s->len; break;
+    case NX_ARRAY: len = (int64_t)value.as.a->len; break;
+    case NX_OBJECT: len = (int64_t)value.as.o->len; break;
+    default:
+        nx_release(value);
+        nx_raisef("len expects a string, array or object");
+    }
+    nx_release(value);
+    nx_push_int(len);
+}
+
+static void nx_substring(void) {
+    nx_value len, start, string;
+    int have_len = nx_pop(&len), have_start = nx_pop(&start), have_string = nx_pop(&string);
+    if (!have_len || !have_start || !have_string || len.tag != NX_INT || start.tag != NX_INT || string.tag != NX_STRING) {
+        nx_release(len);
+        nx_release(start);
+        nx_release(string);
+        nx_raisef("substring expects string, int, int");
+    }
+    size_t from = (size_t)start.as.i, to = from + (size_t)len.as.i;
+    if (from >= string.as.s->len || to > string.as.s->len || to < from) {
+        nx_release(string);
+        nx_raisef("substring indices out of bounds");
+    }
+    nx_push(nx_str(nx_string_new(string.as.s->data + from, to - from)));
+    nx_release(string);
+}
+
+/* Pops two strings, raising `message` if either is missing or not a string */
+static void nx_pop_strings(nx_value *first, nx_value *second, const char *message) {
+    int have_second = nx_pop(second), have_first = nx_pop(first);
+    if (!have_second || !have_first || first->tag != NX_STRING || second->tag != NX_STRING) {
+        nx_release(*first);
+        nx_release(*second);
+        nx_raisef("%s", message);
+    }
+}
+
+static void nx_string_concat(void) {
+    nx_value a, b;
+    nx_pop_strings(&a, &b, "concat expects string arguments");
+    nx_push(nx_str(nx_concat(a.as.s, b.as.s)));
+    nx_release(a);
+    nx_release(b);
+}
+
+static int nx_find(const nx_string *haystack, const nx_string *needle, size_t from) {
+    if (needle->len == 0) return (int)from;
+    for (size_t i = from; i + needle->len <= haystack->len; i++) {
+        if (memcmp(haystack->data + i, needle->data, needle->len) == 0) return (int)i;
+    }
+    return -1;
+}
+
+static void nx_string_contains(void) {
+    nx_value s, part;
+    nx_pop_strings(&s, &part, "contains expects string arguments");
+    int result = nx_find(s.as.s, part.as.s, 0) >= 0;
+    nx_release(s);
+    nx_release(part);
+    nx_push_bool(result);
+}
+
+static void nx_string_starts_with(void) {
+    nx_value s, prefix;
+    nx_pop_strings(&s, &prefix, "starts_with expects string arguments");
+    int result = prefix.as.s->len <= s.as.s->len && memcmp(s.as.s->data, prefix.as.s->data, prefix.as.s->len) == 0;
+    nx_release(s);
+    nx_release(prefix);
+    nx_push_bool(result);
+}
+
+static void nx_string_ends_with(void) {
+    nx_value s, suffix;
+    nx_pop_strings(&s, &suffix, "ends_with expects string arguments");
+    size_t n = suffix.as.s->len;
+    int result = n <= s.as.s->len && memcmp(s.as.s->data + s.as.s->len - n, suffix.as.s->data, n) == 0;
+    nx_release(s);
+    nx_release(suffix);
+    nx_push_bool(result);
+}
+
+static void nx_change_case(int upper, const char *message) {
+    nx_value value = nx_pop_or(message);
+    if (value.tag != NX_STRING) {
+        nx_release(value);
+        nx_raisef("%s", message);
+    }
+    nx_string *result = nx_string_new(value.as.s->data, value.as.s->len);
+    for (size_t i = 0; i < result->len; i++) {
+        char c = result->data[i];
+        if (upper && c >= 'a' && c <= 'z') result->data[i] = (char)(c - 'a' + 'A');
+        if (!upper && c >= 'A' && c <= 'Z') result->data[i] = (char)(c - 'A' + 'a');
+    }
+    nx_release(value);
+    nx_push(nx_str(result));
+}
+
+static void nx_string_to_upper(void) { nx_change_case(1, "to_upper expects a string"); }
+static void nx_string_to_lower(void) { nx_change_case(0, "to_lower expects a string"); }
+
+static void nx_string_trim(void) {
+    nx_value value = nx_pop_or("trim expects a string");
+    if (value.tag != NX_STRING) {
+        nx_release(value);
+        nx_raisef("trim expects a string");
+    }
+    nx_push(nx_str(nx_trimmed(value.as.s->data, value.as.s->len)));
+    nx_release(value);
+}
+
+static void nx_string_split(void) {
+    nx_value s, delimiter;
+    nx_pop_strings(&s, &delimiter, "split expects string arguments");
+    nx_value parts = nx_array_new();
+    const nx_string *text = s.as.s, *sep = delimiter.as.s;
+    if (sep->len == 0) {
+        /* Rust splits around every character */
+        nx_array_append(parts.as.a, nx_str(nx_string_new("", 0)));
+        size_t i = 0;
+        while (i < text->len) {
+            size_t j = i + 1;
+            while (j < text->len && ((unsigned char)text->data[j] & 0xC0) == 0x80) j++;
+            nx_array_append(parts.as.a, nx_str(nx_string_new(text->data + i, j - i)));
+            i = j;
+        }
+        nx_array_append(parts.as.a, nx_str(nx_string_new("", 0)));
+    } else {
+        size_t start = 0;
+        int found;
+        while ((found = nx_find(text, sep, start)) >= 0) {
+            nx_array_append(parts.as.a, nx_str(nx_string_new(text->data + start, (size_t)found - start)));
+            start = (size_t)found + sep->len;
+        }
+        nx_array_append(parts.as.a, nx_str(nx_string_new(text->data + start, text->len - start)));
+    }
+    nx_release(s);
+    nx_release(delimiter);
+    nx_push(parts);
+}
+
+static void nx_string_join(void) {
+    nx_value delimiter, array;
+    int have_delimiter = nx_pop(&delimiter), have_array = nx_pop(&array);
+    if (!have_delimiter || !have_array || delimiter.tag != NX_STRING || array.tag != NX_ARRAY) {
+        nx_release(delimiter);
+        nx_release(array);
+        nx_raisef("join expects array and string arguments");
+    }
+    nx_buffer out = { 0 };
+    nx_buffer_append(&out, "", 0);
+    for (size_t i = 0; i < array.as.a->len; i++) {
+        nx_value item = array.as.a->items[i];
+        if (item.tag == NX_ARRAY || item.tag == NX_OBJECT || item.tag == NX_BUILTIN) {
+            free(out.data);
+            nx_release(delimiter);
+            nx_release(array);
+            nx_raisef("join can only handle primitive types");
+        }
+        if (i > 0) nx_buffer_append(&out, delimiter.as.s->data, delimiter.as.s->len);
+        nx_string *text = nx_to_string(item);
+        nx_buffer_append(&out, text->data, text->len);
+        nx_string_release(text);
+    }
+    nx_release(delimiter);
+    nx_release(array);
+    nx_push(nx_str(nx_buffer_finish(&out)));
+}
+
+/* ---- Conversions and utilities ---- */
+
+static void nx_to_string_op(void) {
+    nx_value value = nx_pop_or("Stack underflow");
+    nx_push(nx_str(nx_to_string(value)));
+    nx_release(value);
+}
+
+/* Rust's `str::parse::<i64>`: an optional sign and decimal digits only */
+static int nx_parse_int(const nx_string *s, int64_t *out) {
+    const char *p = s->data, *end = s->data + s->len;
+    int negative = 0;
+    if (p < end && (*p == '+' || *p == '-')) negative = *p++ == '-';
+    if (p == end) return 0;
+    uint64_t magnitude = 0, limit = negative ? (uint64_t)INT64_MAX + 1 : (uint64_t)INT64_MAX;
+    for (; p < end; p++) {
+        if (*p < '0' || *p > '9') return 0;
+        uint64_t digit = (uint64_t)(*p - '0');
+        if (magnitude > (limit - digit) / 10) return 0;
+        magnitude = magnitude * 10 + digit;
+    }
+    *out = negative ? (int64_t)(0 - magnitude) : (int64_t)magnitude;
+    return 1;
+}
+
+static void nx_to_int(void) {
+    nx_value value = nx_pop_or("Stack underflow");
+    int64_t result = 0;
+    switch (value.tag) {
+    case NX_INT: result = value.as.i; break;
+    case NX_FLOAT: result = nx_float_to_int(value.as.f); break;
+    case NX_STRING: if (!nx_parse_int(value.as.s, &result)) result = 0; break;
+    case NX_BOOL: result = value.as.b; break;
+    default: break;
+    }
+    nx_release(value);
+    nx_push_int(result);
+}
+
+static void nx_to_float(void) {
+    nx_value value = nx_pop_or("Stack underflow");
+    double result = 0.0;
+    switch (value.tag) {
+    case NX_INT: result = (double)value.as.i; break;
+    case NX_FLOAT: result = value.as.f; break;
+    case NX_STRING: {
+        char *end;
+        const char *text = value.as.s->data;
+        int plain = value.as.s->len > 0 && !nx_is_space((unsigned char)text[0]) && !strpbrk(text, "xX");
+        double parsed = plain ? strtod(text, &end) : 0.0;
+        result = plain && end == text + value.as.s->len ? parsed : 0.0;
+        break;
+    }
+    case NX_BOOL: result = value.as.b ? 1.0 : 0.0; break;
+    default: break;
+    }
+    nx_release(value);
+    nx_push_float(result);
+}
+
+static void nx_to_bool(void) {
+    nx_value value = nx_pop_or("Stack underflow");
+    int result = nx_truthy(value);
+    nx_release(value);
+    nx_push_bool(result);
+}
+
+static uint64_t nx_random_bits(void) {
+    static uint64_t state;
+    struct timespec now;
+    clock_gettime(CLOCK_REALTIME, &now);
+    state ^= (uint64_t)now.tv_nsec * 0x9E3779B97F4A7C15ULL + (uint64_t)now.tv_sec;
+    state ^= state >> 33;
+    state *= 0xFF51AFD7ED558CCDULL;
+    state ^= state >> 33;
+    return state;
+}
+
+static void nx_random(void) {
+    nx_push_float((double)nx_random_bits() / (double)UINT64_MAX);
+}
+
+static void nx_random_int(void) {
+    nx_value max, min;
+    int have_max = nx_pop(&max), have_min = nx_pop(&min);
+    if (!have_max || !have_min || max.tag != NX_INT || min.tag != NX_INT) {
+        nx_release(max);
+        nx_release(min);
+        nx_raisef("random_int expects integer arguments");
+    }
+    if (min.as.i >= max.as.i) nx_raisef("random_int: min must be less than max");
+    uint64_t range = (uint64_t)max.as.i - (uint64_t)min.as.i;
+    nx_push_int(nx_wrap_add(min.as.i, (int64_t)(nx_random_bits() % range)));
+}
+
+static void nx_type_of(void) {
+    nx_value value = nx_pop_or("Stack underflow for typeof");
+    const char *name;
+    switch (value.tag) {
+    case NX_INT: name = "int"; break;
+    case NX_FLOAT: name = "float"; break;
+    case NX_STRING: name = "string"; break;
+    case NX_BOOL: name = "bool"; break;
+    case NX_ARRAY: name = "array"; break;
+    case NX_OBJECT: name = "object"; break;
+    case NX_BUILTIN: name = "builtin_function"; break;
+    default: name = "null"; break;
+    }
+    nx_release(value);
+    nx_push(nx_str(nx_string_cstr(name)));
+}
+
+static void nx_time(void) {
+    nx_push_int((int64_t)time(NULL));
+}
+
+static void nx_sleep(void) {
+    nx_value value = nx_pop_or("Stack underflow for sleep");
+    double seconds = nx_number(value, "sleep expects a numeric argument");
+    if (seconds >= 1.0) {
+        nx_flush();
+        sleep((unsigned int)seconds);
+    }
+    nx_push_null();
+}
+
+static void nx_exit(void) {
+    nx_value value;
+    int code = 0;
+    if (nx_pop(&value) && value.tag == NX_INT) {
+        code = (int)value.as.i;
+    }
+    nx_flush();
+    exit(code);
+}
+
+/* ---- Objects and dictionaries ---- */
+
+static void nx_new_object(void) { nx_push(nx_object_new()); }
+
+static void nx_get_property(const char *name) {
+    nx_value object = nx_pop_or("Cannot get property from non-object");
+    if (object.tag != NX_OBJECT) {
+        nx_release(object);
+        nx_raisef("Cannot get property from non-object");
+    }
+    nx_string *key = nx_string_cstr(name);
+    long index = nx_object_find(object.as.o, key);
+    nx_string_release(key);
+    nx_value value = index >= 0 ? object.as.o->values[index] : NX_NULL_VALUE;
+    nx_retain(value);
+    nx_release(object);
+    nx_push(value);
+}
+
+static void nx_set_property(const char *name) {
+    nx_value value, object;
+    int have_value = nx_pop(&value), have_object = nx_pop(&object);
+    if (!have_value || !have_object || object.tag != NX_OBJECT) {
+        nx_release(value);
+        nx_release(object);
+        nx_raisef("Cannot set property on non-object");
+    }
+    nx_object_insert(object.as.o, nx_string_cstr(name), value);
+    nx_push(object);
+}
+
+static void nx_dict_new(void) { nx_push(nx_object_new()); }
+
+/* Pops a key and a dictionary; the key is converted to a string */
+static nx_string *nx_pop_key(nx_value *dict, const char *message) {
+    nx_value key;
+    int have_key = nx_pop(&key), have_dict = nx_pop(dict);
+    if (!have_key || !have_dict || dict->tag != NX_OBJECT) {
+        nx_release(key);
+        nx_release(*dict);
+        nx_raisef("%s", message);
+    }
+    nx_string *text = nx_to_string(key);
+    nx_release(key);
+    return text;
+}
+
+static void nx_dict_set(void) {
+    nx_value value, dict;
+    if (!nx_pop(&value)) nx_raisef("Invalid arguments for dict_set");
+    nx_string *key = nx_pop_key(&dict, "Invalid arguments for dict_set");
+    nx_object_insert(dict.as.o, key, value);
+    nx_push(dict);
+}
+
+static void nx_dict_get(void) {
+    nx_value dict;
+    nx_string *key = nx_pop_key(&dict, "Invalid arguments for dict_get");
+    long index = nx_object_find(dict.as.o, key);
+    nx_value value = index >= 0 ? dict.as.o->values[index] : NX_NULL_VALUE;
+    nx_retain(value);
+    nx_string_release(key);
+    nx_release(dict);
+    nx_push(value);
+}
+
+static void nx_dict_has(void) {
+    nx_value dict;
+    nx_string *key = nx_pop_key(&dict, "Invalid arguments for dict_has");
+    int has = nx_object_find(dict.as.o, key) >= 0;
+    nx_string_release(key);
+    nx_release(dict);
+    nx_push_bool(has);
+}
+
+static void nx_dict_remove(void) {
+    nx_value dict;
+    nx_string *key = nx_pop_key(&dict, "Invalid arguments for dict_remove");
+    long index = nx_object_find(dict.as.o, key);
+    nx_value removed = NX_NULL_VALUE;
+    if (index >= 0) {
+        nx_object *object = dict.as.o;
+        nx_string_release(object->keys[index]);
+        removed = object->values[index];
+        memmove(object->keys + index, object->keys + index + 1, (object->len - (size_t)index - 1) * sizeof(nx_string *));
+        memmove(object->values + index, object->values + index + 1, (object->len - (size_t)index - 1) * sizeof(nx_value));
+        object->len--;
+    }
+    nx_string_release(key);
+    nx_push(dict);
+    nx_push(removed);
+}
+
+static nx_value nx_pop_dict(const char *message) {
+    nx_value dict = nx_pop_or(message);
+    if (dict.tag != NX_OBJECT) {
+        nx_release(dict);
+        nx_raisef("%s", message);
+    }
+    return dict;
+}
+
+static void nx_dict_keys(void) {
+    nx_value dict = nx_pop_dict("Invalid argument for dict_keys");
+    nx_value keys = nx_array_new();
+    for (size_t i = 0; i < dict.as.o->len; i++) {
+        dict.as.o->keys[i]->refs++;
+        nx_array_append(keys.as.a, nx_str(dict.as.o->keys[i]));
+    }
+    nx_release(dict);
+    nx_push(keys);
+}
+
+static void nx_dict_size(void) {
+    nx_value dict = nx_pop_dict("Invalid argument for dict_size");
+    int64_t size = (int64_t)dict.as.o->len;
+    nx_release(dict);
+    nx_push_int(size);
+}
+
+static void nx_dict_clear(void) {
+    nx_value dict = nx_pop_dict("Invalid argument for dict_clear");
+    nx_object *object = dict.as.o;
+    for (size_t i = 0; i < object->len; i++) {
+        nx_string_release(object->keys[i]);
+        nx_release(object->values[i]);
+    }
+    object->len = 0;
+    nx_push(dict);
+}
+
+/* ---- Arrays ---- */
+
+static void nx_new_array(void) { nx_push(nx_array_new()); }
+
+static nx_value nx_pop_array(const char *message) {
+    nx_value array = nx_pop_or(message);
+    if (array.tag != NX_ARRAY) {
+        nx_release(array);
+        nx_raisef("%s", message);
+    }
+    return array;
+}
+
+static void nx_get_index(void) {
+    nx_value index, array;
+    int have_index = nx_pop(&index), have_array = nx_pop(&array);
+    if (!have_index || !have_array || array.tag != NX_ARRAY) {
+        nx_release(index);
+        nx_release(array);
+        nx_raisef("Invalid array access");
+    }
+    if (index.tag != NX_INT) {
+        nx_release(index);
+        nx_release(array);
+        nx_raisef("Invalid array index");
+    }
+    nx_value element = NX_NULL_VALUE;
+    if (index.as.i >= 0 && (uint64_t)index.as.i < array.as.a->len) {
+        element = array.as.a->items[index.as.i];
+        nx_retain(element);
+    }
+    nx_release(array);
+    nx_push(element);
+}
+
+static void nx_set_index(void) {
+    nx_value value, index, array;
+    int have_value = nx_pop(&value), have_index = nx_pop(&index), have_array = nx_pop(&array);
+    if (!have_value || !have_index || !have_array || array.tag != NX_ARRAY) {
+        nx_release(value);
+        nx_release(index);
+        nx_release(array);
+        nx_raisef("Invalid array assignment");
+    }
+    if (index.tag != NX_INT) {
+        nx_release(value);
+        nx_release(index);
+        nx_release(array);
+        nx_raisef("Invalid array index");
+    }
+    if (index.as.i >= 0 && (uint64_t)index.as.i < array.as.a->len) {
+        nx_release(array.as.a->items[index.as.i]);
+        array.as.a->items[index.as.i] = value;
+    } else {
+        nx_release(value);
+    }
+    nx_push(array);
+}
+
+static void nx_array_push(void) {
+    nx_value value, array;
+    int have_value = nx_pop(&value), have_array = nx_pop(&array);
+    if (!have_value || !have_array || array.tag != NX_ARRAY) {
+        nx_release(value);
+        nx_release(array);
+        nx_raisef("Invalid arguments for array_push");
+    }
+    nx_array_append(array.as.a, value);
+    nx_push(array);
+}
+
+static void nx_array_pop(void) {
+    nx_value array = nx_pop_array("Invalid argument for array_pop");
+    nx_value popped = array.as.a->len ? array.as.a->items[--array.as.a->len] : NX_NULL_VALUE;
+    nx_push(array);
+    nx_push(popped);
+}
+
+static void nx_array_reverse(void) {
+    nx_value array = nx_pop_array("Invalid argument for array_reverse");
+    nx_value *items = array.as.a->items;
+    for (size_t i = 0, j = array.as.a->len; i + 1 < j; i++, j--) {
+        nx_value item = items[i];
+        items[i] = items[j - 1];
+        items[j - 1] = item;
+    }
+    nx_push(array);
+}
+
+/* The order `array_sort` uses: Ints, Floats and Strings among themselves, everything else equal */
+static int nx_sort_order(nx_value a, nx_value b) {
+    if ((a.tag == NX_INT && b.tag == NX_INT) || (a.tag == NX_STRING && b.tag == NX_STRING)) {
+        return nx_compare(a, b);
+    }
+    if (a.tag == NX_FLOAT && b.tag == NX_FLOAT) {
+        int order = nx_compare(a, b);
+        return order == 2 ? 0 : order;
+    }
+    return 0;
+}
+
+/* A stable merge sort, like Rust's `sort_by` */
+static void nx_merge_sort(nx_value *items, nx_value *scratch, size_t len) {
+    if (len < 2) return;
+    size_t half = len / 2;
+    nx_merge_sort(items, scratch, half);
+    nx_merge_sort(items + half, scratch, len - half);
+    size_t i = 0, j = half, k = 0;
+    while (i < half && j < len) {
+        scratch[k++] = nx_sort_order(items[j], items[i]) < 0 ? items[j++] : items[i++];
+    }
+    while (i < half) scratch[k++] = items[i++];
+    while (j < len) scratch[k++] = items[j++];
+    memcpy(items, scratch, len * sizeof(nx_value));
+}
+
+static void nx_array_sort(void) {
+    nx_value array = nx_pop_array("Invalid argument for array_sort");
+    nx_value *scratch = nx_alloc(array.as.a->len * sizeof(nx_value));
+    nx_merge_sort(array.as.a->items, scratch, array.as.a->len);
+    free(scratch);
+    nx_push(array);
+}
+
+static void nx_array_slice(void) {
+    nx_value end, start, array;
+    int have_end = nx_pop(&end), have_start = nx_pop(&start), have_array = nx_pop(&array);
+    if (!have_end || !have_start || !have_array || array.tag != NX_ARRAY) {
+        nx_release(end);
+        nx_release(start);
+        nx_release(array);
+        nx_raisef("Invalid arguments for array_slice");
+    }
+    if (start.tag != NX_INT || end.tag != NX_INT) {
+        nx_release(end);
+        nx_release(start);
+        nx_release(array);
+        nx_raisef("Array slice indices must be integers");
+    }
+    size_t len = array.as.a->len;
+    size_t from = start.as.i > 0 ? (size_t)start.as.i : 0;
+    size_t to = end.as.i > 0 ? (size_t)end.as.i : 0;
+    if (to > len) to = len;
+    nx_value slice = nx_array_new();
+    if (from <= to && from < len) {
+        for (size_t i = from; i < to; i++) {
+            nx_retain(array.as.a->items[i]);
+            nx_array_append(slice.as.a, array.as.a->items[i]);
+        }
+    }
+    nx_release(array);
+    nx_push(slice);
+}
+
+static void nx_array_filter(void) { nx_push(nx_pop_array("Invalid argument for array_filter")); }
+static void nx_array_map(void) { nx_push(nx_pop_array("Invalid argument for array_map")); }
+
+static void nx_array_first(const char *message) {
+    nx_value array = nx_pop_array(message);
+    nx_value first = array.as.a->len ? array.as.a->items[0] : NX_NULL_VALUE;
+    nx_retain(first);
+    nx_release(array);
+    nx_push(first);
+}
+
+static void nx_array_reduce(void) { nx_array_first("Invalid argument for array_reduce"); }
+static void nx_array_find(void) { nx_array_first("Invalid argument for array_find"); }
+
+/* ---- JSON ---- */
+
+static void nx_json_parse(void) {
+    nx_value text = nx_pop_or("Invalid argument for json_parse");
+    if (text.tag != NX_STRING) {
+        nx_release(text);
+        nx_raisef("Invalid argument for json_parse");
+    }
+    const nx_string *s = text.as.s;
+    if (s->len > 0 && s->data[0] == '{' && s->data[s->len - 1] == '}') {
+        nx_release(text);
+        nx_push(nx_object_new());
+    } else if (s->len > 0 && s->data[0] == '[' && s->data[s->len - 1] == ']') {
+        nx_release(text);
+        nx_push(nx_array_new());
+    } else {
+        nx_push(text);
+    }
+}
+
+static void nx_json_scalar(nx_buffer *out, nx_value value) {
+    switch (value.tag) {
+    case NX_STRING:
+        nx_buffer_puts(out, "\"");
+        nx_buffer_append(out, value.as.s->data, value.as.s->len);
+        nx_buffer_puts(out, "\"");
+        break;
+    case NX_INT:
+    case NX_FLOAT:
+    case NX_BOOL: {
+        nx_open_set open = { .len = 0 };
+        nx_format(out, value, &open);
+        break;
+    }
+    default:
+        nx_buffer_puts(out, "null");
+        break;
+    }
+}
+
+static void nx_json_stringify(void) {
+    nx_value value = nx_pop_or("Stack underflow for json_stringify");
+    nx_buffer out = { 0 };
+    if (value.tag == NX_OBJECT) {
+        nx_buffer_puts(&out, "{");
+        for (size_t i = 0; i < value.as.o->len; i++) {
+            if (i > 0) nx_buffer_puts(&out, ",");
+            nx_buffer_puts(&out, "\"");
+            nx_buffer_append(&out, value.as.o->keys[i]->data, value.as.o->keys[i]->len);
+            nx_buffer_puts(&out, "\":");
+            nx_json_scalar(&out, value.as.o->values[i]);
+        }
+        nx_buffer_puts(&out, "}");
+    } else if (value.tag == NX_ARRAY) {
+        nx_buffer_puts(&out, "[");
+        for (size_t i = 0; i < value.as.a->len; i++) {
+            if (i > 0) nx_buffer_puts(&out, ",");
+            nx_json_scalar(&out, value.as.a->items[i]);
+        }
+        nx_buffer_puts(&out, "]");
+    } else {
+        nx_json_scalar(&out, value);
+    }
+    nx_release(value);
+    nx_push(nx_str(nx_buffer_finish(&out)));
+}
+
+/* ---- Error handling ---- */
+
+/* `try_catch` is a placeholder in the VM too */
+static void nx_try_catch(void) { nx_push_null(); }
+
+static void nx_throw_error(void) {
+    nx_value message;
+    if (nx_pop(&message) && message.tag == NX_STRING) {
+        nx_raisef("Thrown error: %s", message.as.s->data);
+    }
+    nx_release(message);
+    nx_raisef("Thrown error: Unknown error");
+}
+
+/* `Throw` stops the program without an error, as it does in the VM */
+static void nx_throw(void) {
+    nx_flush();
+    exit(0);
+}
+
+/* ---- Builtins called by name ---- */
+
+static void nx_assertion_failed(nx_value left, nx_value right, int expect_equal) {
+    nx_string *l = nx_to_string(left), *r = nx_to_string(right);
+    nx_raisef("assertion failed: left %s right\n  left: %s\n right: %s", expect_equal ? "==" : "!=", l->data, r->data);
+}
+
+static void nx_call_builtin(const char *name, size_t arg_count) {
+    if (strcmp(name, "print") == 0 || strcmp(name, "println") == 0) {
+        if (arg_count != 1) nx_raisef("%s expects 1 argument", name);
+        nx_write_value(stdout, nx_pop_or("Stack underflow"), name[5] == 'l');
+    } else if (strcmp(name, "eprint") == 0 || strcmp(name, "eprintln") == 0) {
+        if (arg_count != 1) nx_raisef("%s expects 1 argument", name);
+        nx_flush();
+        nx_write_value(stderr, nx_pop_or("Stack underflow"), name[6] == 'l');
+        nx_push_null();
+    } else if (strcmp(name, "read_line") == 0) {
+        if (arg_count != 0) nx_raisef("read_line expects 0 arguments");
+        nx_read_line();
+    } else if (strcmp(name, "read_file") == 0) {
+        if (arg_count != 1) nx_raisef("read_file expects 1 argument");
+        nx_read_file();
+    } else if (strcmp(name, "write_file") == 0) {
+        if (arg_count != 2) nx_raisef("write_file expects 2 arguments");
+        nx_write_file();
+    } else if (strcmp(name, "append_file") == 0) {
+        if (arg_count != 2) nx_raisef("append_file expects 2 arguments");
+        nx_append_file();
+    } else if (strcmp(name, "file_exists") == 0) {
+        if (arg_count != 1) nx_raisef("file_exists expects 1 argument");
+        nx_file_exists();
+    } else if (strcmp(name, "assert") == 0) {
+        if (arg_count != 1 && arg_count != 2) nx_raisef("assert expects 1 or 2 arguments");
+        nx_value message = NX_NULL_VALUE;
+        int has_message = arg_count == 2 && nx_pop(&message);
+        nx_value condition = nx_pop_or("Stack underflow");
+        if (!nx_truthy(condition)) {
+            if (has_message) {
+                nx_string *text = nx_to_string(message);
+                nx_raisef("assertion failed: %s", text->data);
+            }
+            nx_raisef("assertion failed");
+        }
+        nx_release(message);
+        nx_release(condition);
+        nx_push_null();
+    } else if (strcmp(name, "assert_eq") == 0 || strcmp(name, "assert_ne") == 0) {
+        if (arg_count != 2) nx_raisef("%s expects 2 arguments", name);
+        nx_value left, right;
+        nx_pop_two(&left, &right);
+        int expect_equal = name[7] == 'e';
+        if (nx_structurally_equal(left, right) != expect_equal) {
+            nx_assertion_failed(left, right, expect_equal);
+        }
+        nx_release(left);
+        nx_release(right);
+        nx_push_null();
+    } else {
+        nx_raisef("Unknown built-in function: %s", name);
+    }
+}
+
+/* A call through a value, `f(x)` where `f` is not a function name */
+static void nx_call_dynamic(size_t arg_count) {
+    nx_value callee;
+    if (nx_pop(&callee) && callee.tag == NX_BUILTIN) {
+        nx_call_builtin(callee.as.builtin, arg_count);
+        return;
+    }
+    nx_release(callee);
+    nx_op_pop();
+    nx_raisef("Invalid function call");
+}
+
+/* `max_locals` is the most locals any one function has */
+static void nx_init(size_t max_depth, size_t max_locals) {
+    nx_max_depth = max_depth;
+    nx_stack_cap = 1024;
+    nx_stack = nx_alloc(nx_stack_cap * sizeof(nx_value));
+    nx_locals_cap = (max_depth + 1) * max_locals;
+    nx_locals = nx_alloc(nx_locals_cap * sizeof(nx_value));
+}
+
+/* Run `program` on a thread whose stack fits the deepest call the limit allows */
+static int nx_main(void *(*program)(void *)) {
+    pthread_attr_t attributes;
+    pthread_t thread;
+    size_t stack_size = (size_t)64 << 20;
+    if (nx_max_depth > 65536) {
+        stack_size = nx_max_depth * 1024;
+    }
+    pthread_attr_init(&attributes);
+    pthread_attr_setstacksize(&attributes, stack_size);
+    if (pthread_create(&thread, &attributes, program, NULL) != 0) {
+        program(NULL);
+    } else {
+        pthread_join(thread, NULL);
+    }
+    nx_flush();
+    return 0;
+}
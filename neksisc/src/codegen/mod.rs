@@ -1,3 +1,4 @@
+pub mod c;
 pub mod simple;
 
 #[cfg(feature = "llvm-backend")]
@@ -138,12 +138,15 @@ impl SimpleCodeGen {
             }
             Statement::Function(_) => Ok("0".to_string()), // Functions are handled separately
             Statement::Module(_) => Ok("0".to_string()), // Modules are handled at a different level
+            Statement::SourceLine(_) => Ok("0".to_string()), // Position markers generate no code
             Statement::Move(_) => Ok("0".to_string()), // TODO: Implement move semantics
             Statement::Drop(_) => Ok("0".to_string()), // TODO: Implement drop semantics
             Statement::Struct(_) | Statement::Enum(_) | Statement::Use(_) => Ok("0".to_string()), // TODO: Implement
             Statement::GenericFunction(_) => Ok("0".to_string()), // TODO: Implement generic functions
             Statement::Trait(_) | Statement::Impl(_) => Ok("0".to_string()), // TODO: Implement traits and impls
             Statement::Class(_) => Ok("0".to_string()),
+            Statement::Extern(_) => Err(CompilerError::codegen_error("simple", "extern blocks are only supported by the VM")),
+            Statement::PythonImport(_) => Err(CompilerError::codegen_error("simple", "Python imports are only supported by the VM")),
             Statement::LetStatement { name, value, var_type } => {
                 let value_code = self.generate_expression(value)?;
                 let type_annotation = var_type.as_ref().map(|t| format!(": {}", t)).unwrap_or_default();
@@ -0,0 +1,289 @@
+// Source Coverage for Neksis
+//
+// The VM counts how often each `Line` marker and each conditional jump
+// executes. This module maps those per-instruction counters back to source
+// lines, branches and functions, and renders them as lcov or as a terminal
+// summary.
+
+use crate::vm::BytecodeInstruction;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Raw hit counters recorded by the VM, keyed by instruction index
+#[derive(Debug, Clone, Default)]
+pub struct CoverageCounters {
+    lines: HashMap<usize, u64>,
+    /// For each conditional jump: [fell through, jumped]
+    branches: HashMap<usize, [u64; 2]>,
+}
+
+impl CoverageCounters {
+    pub fn record_line(&mut self, ip: usize) {
+        *self.lines.entry(ip).or_insert(0) += 1;
+    }
+
+    pub fn record_branch(&mut self, ip: usize, jumped: bool) {
+        self.branches.entry(ip).or_insert([0, 0])[jumped as usize] += 1;
+    }
+
+    /// Add counters from another run of the same instructions
+    pub fn merge(&mut self, other: &CoverageCounters) {
+        for (ip, hits) in &other.lines {
+            *self.lines.entry(*ip).or_insert(0) += hits;
+        }
+        for (ip, counts) in &other.branches {
+            let entry = self.branches.entry(*ip).or_insert([0, 0]);
+            entry[0] += counts[0];
+            entry[1] += counts[1];
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCoverage {
+    pub name: String,
+    pub line: usize,
+    pub calls: u64,
+    /// Hit count for each line in the function body
+    pub lines: BTreeMap<usize, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchCoverage {
+    pub line: usize,
+    /// Index of the conditional jump among those on the same line
+    pub block: usize,
+    /// [fell through, jumped], or `None` if the jump was never reached
+    pub taken: Option<[u64; 2]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileCoverage {
+    pub path: PathBuf,
+    pub lines: BTreeMap<usize, u64>,
+    pub branches: Vec<BranchCoverage>,
+    pub functions: Vec<FunctionCoverage>,
+}
+
+impl FileCoverage {
+    /// Map counters back onto the source lines of `instructions`. The
+    /// instructions must have been compiled with line markers.
+    pub fn from_counters(path: &Path, instructions: &[BytecodeInstruction], counters: &CoverageCounters) -> Self {
+        let mut lines = BTreeMap::new();
+        let mut branches = Vec::new();
+        let mut functions: Vec<FunctionCoverage> = Vec::new();
+        let mut blocks_per_line: HashMap<usize, usize> = HashMap::new();
+        let mut in_function = false;
+        let mut current_line = 0;
+
+        for (ip, instruction) in instructions.iter().enumerate() {
+            match instruction {
+                BytecodeInstruction::DefineFunction(name, _) => {
+                    functions.push(FunctionCoverage { name: name.clone(), line: 0, calls: 0, lines: BTreeMap::new() });
+                    in_function = true;
+                    current_line = 0;
+                }
+                BytecodeInstruction::EndFunction => {
+                    in_function = false;
+                    current_line = 0;
+                }
+                BytecodeInstruction::Line(line) => {
+                    current_line = *line;
+                    let hits = counters.lines.get(&ip).copied().unwrap_or(0);
+                    // Several statements on one line count as one line
+                    let entry = lines.entry(*line).or_insert(0);
+                    *entry = (*entry).max(hits);
+                    if let Some(function) = functions.last_mut().filter(|_| in_function) {
+                        // The first marker of a function body is its `fn` line
+                        if function.line == 0 {
+                            function.line = *line;
+                            function.calls = hits;
+                        }
+                        let entry = function.lines.entry(*line).or_insert(0);
+                        *entry = (*entry).max(hits);
+                    }
+                }
+                BytecodeInstruction::JumpIfFalse(_) | BytecodeInstruction::JumpIfTrue(_) if current_line > 0 => {
+                    let block = blocks_per_line.entry(current_line).or_insert(0);
+                    branches.push(BranchCoverage {
+                        line: current_line,
+                        block: *block,
+                        taken: counters.branches.get(&ip).copied(),
+                    });
+                    *block += 1;
+                }
+                _ => {}
+            }
+        }
+
+        // Functions without any markers (e.g. compiled without line tracking) cannot be placed
+        functions.retain(|function| function.line > 0);
+
+        Self { path: path.to_path_buf(), lines, branches, functions }
+    }
+
+    pub fn lines_found(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|hits| **hits > 0).count()
+    }
+
+    pub fn branches_found(&self) -> usize {
+        self.branches.len() * 2
+    }
+
+    pub fn branches_hit(&self) -> usize {
+        self.branches
+            .iter()
+            .filter_map(|branch| branch.taken)
+            .map(|taken| taken.iter().filter(|count| **count > 0).count())
+            .sum()
+    }
+
+    pub fn functions_hit(&self) -> usize {
+        self.functions.iter().filter(|function| function.calls > 0).count()
+    }
+}
+
+/// Coverage for every file that was run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoverageReport {
+    pub files: Vec<FileCoverage>,
+}
+
+impl CoverageReport {
+    /// Render the report in the lcov tracefile format
+    pub fn to_lcov(&self) -> String {
+        let mut out = String::new();
+        for file in &self.files {
+            out.push_str("TN:\n");
+            out.push_str(&format!("SF:{}\n", file.path.display()));
+            for function in &file.functions {
+                out.push_str(&format!("FN:{},{}\n", function.line, function.name));
+            }
+            for function in &file.functions {
+                out.push_str(&format!("FNDA:{},{}\n", function.calls, function.name));
+            }
+            out.push_str(&format!("FNF:{}\n", file.functions.len()));
+            out.push_str(&format!("FNH:{}\n", file.functions_hit()));
+            for branch in &file.branches {
+                for side in 0..2 {
+                    let taken = match branch.taken {
+                        Some(counts) => counts[side].to_string(),
+                        None => "-".to_string(),
+                    };
+                    out.push_str(&format!("BRDA:{},{},{},{}\n", branch.line, branch.block, side, taken));
+                }
+            }
+            out.push_str(&format!("BRF:{}\n", file.branches_found()));
+            out.push_str(&format!("BRH:{}\n", file.branches_hit()));
+            for (line, hits) in &file.lines {
+                out.push_str(&format!("DA:{},{}\n", line, hits));
+            }
+            out.push_str(&format!("LF:{}\n", file.lines_found()));
+            out.push_str(&format!("LH:{}\n", file.lines_hit()));
+            out.push_str("end_of_record\n");
+        }
+        out
+    }
+
+    /// A table of line, branch and function coverage per file, with the
+    /// line coverage of each function underneath
+    pub fn summary(&self) -> String {
+        let width = self
+            .files
+            .iter()
+            .flat_map(|file| {
+                let functions = file.functions.iter().map(|function| function.name.len() + 2);
+                std::iter::once(file.path.display().to_string().len()).chain(functions)
+            })
+            .chain(std::iter::once("Total".len()))
+            .max()
+            .unwrap_or(0)
+            + 2;
+
+        let mut out = format!("{:<width$}{:<20}{:<20}{}\n", "File", "Lines", "Branches", "Functions", width = width);
+        let (mut lines, mut branches, mut functions) = ((0, 0), (0, 0), (0, 0));
+        for file in &self.files {
+            out.push_str(&format!(
+                "{:<width$}{:<20}{:<20}{}\n",
+                file.path.display(),
+                ratio(file.lines_hit(), file.lines_found()),
+                ratio(file.branches_hit(), file.branches_found()),
+                ratio(file.functions_hit(), file.functions.len()),
+                width = width
+            ));
+            for function in &file.functions {
+                let hit = function.lines.values().filter(|hits| **hits > 0).count();
+                out.push_str(&format!(
+                    "  {:<width$}{}\n",
+                    function.name,
+                    ratio(hit, function.lines.len()),
+                    width = width - 2
+                ));
+            }
+            lines = (lines.0 + file.lines_hit(), lines.1 + file.lines_found());
+            branches = (branches.0 + file.branches_hit(), branches.1 + file.branches_found());
+            functions = (functions.0 + file.functions_hit(), functions.1 + file.functions.len());
+        }
+        out.push_str(&format!(
+            "{:<width$}{:<20}{:<20}{}\n",
+            "Total",
+            ratio(lines.0, lines.1),
+            ratio(branches.0, branches.1),
+            ratio(functions.0, functions.1),
+            width = width
+        ));
+        out
+    }
+}
+
+fn ratio(hit: usize, found: usize) -> String {
+    if found == 0 {
+        return "-".to_string();
+    }
+    format!("{}/{} ({:.1}%)", hit, found, hit as f64 * 100.0 / found as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode_compiler::BytecodeCompiler;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_line_branch_and_function_coverage() {
+        let source = "fn sign(n: Int) -> Int {\n    if n < 0 {\n        return -1;\n    }\n    return 1;\n}\n\nfn unused() {\n    println(\"never\");\n}\n\nfn main() {\n    sign(5);\n}\n";
+        let tokens = Lexer::new(source, "sign.nx".to_string()).tokenize().unwrap();
+        let program = Parser::new(tokens).with_line_markers().parse().unwrap();
+        let instructions = BytecodeCompiler::new().compile_program(&program).unwrap();
+
+        let mut vm = VM::new();
+        vm.set_output_capture(crate::vm::OutputCapture::new());
+        vm.enable_coverage();
+        vm.load_instructions(instructions.clone());
+        vm.run().unwrap();
+        let counters = vm.take_coverage().unwrap();
+
+        let file = FileCoverage::from_counters(Path::new("sign.nx"), &instructions, &counters);
+        assert_eq!(file.lines.get(&2), Some(&1));
+        assert_eq!(file.lines.get(&3), Some(&0));
+        assert_eq!(file.lines.get(&5), Some(&1));
+        assert_eq!(file.lines.get(&9), Some(&0));
+        assert_eq!(file.branches, vec![BranchCoverage { line: 2, block: 0, taken: Some([0, 1]) }]);
+
+        let names: Vec<(&str, usize, u64)> = file.functions.iter().map(|f| (f.name.as_str(), f.line, f.calls)).collect();
+        assert_eq!(names, vec![("sign", 1, 1), ("unused", 8, 0), ("main", 12, 1)]);
+
+        let lcov = CoverageReport { files: vec![file] }.to_lcov();
+        assert!(lcov.starts_with("TN:\nSF:sign.nx\nFN:1,sign\n"));
+        assert!(lcov.contains("FNDA:0,unused\n"));
+        assert!(lcov.contains("BRDA:2,0,0,0\nBRDA:2,0,1,1\nBRF:2\nBRH:1\n"));
+        assert!(lcov.contains("DA:3,0\n"));
+        assert!(lcov.ends_with("end_of_record\n"));
+    }
+}
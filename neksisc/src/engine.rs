@@ -0,0 +1,644 @@
+// Embedding Neksis
+//
+// An `Engine` owns a VM that keeps its variables, functions and imports from
+// one run to the next, so a host can hand it a program a piece at a time, read
+// and write its globals, give it native functions to call and call its
+// functions back. `IntoValue` and `FromValue` convert between Rust and Neksis
+// values, so native functions are written with ordinary Rust types. The
+// `neksis` Python module is a thin layer over the same engine.
+//
+// Engines share nothing: each has its own VM, globals, functions, output and
+// permissions. A new engine's scripts cannot touch files, the network, the
+// environment or other processes, exit or load native code until `allow`
+// grants it. `set_limits` bounds how much time, memory and how many
+// instructions each run may use. An engine stays on the thread that created it.
+
+use crate::ast::{LetStatement, Program, Statement};
+use crate::bytecode_compiler::BytecodeCompiler;
+use crate::bytecode_file;
+use crate::error::CompilerError;
+use crate::lexer::Lexer;
+use crate::limits::{Limit, Limits};
+use crate::linter::{LintSeverity, Linter};
+use crate::parser::Parser;
+use crate::permissions::{Capability, Permissions};
+use crate::vm::{BytecodeInstruction, HostFunction, OutputCapture, VMValue, VM};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+// Receives the value of a run's trailing expression statement
+const RESULT_VARIABLE: &str = "__engine_result";
+
+const SOURCE_NAME: &str = "<engine>";
+
+pub struct Engine {
+    vm: VM,
+    // Modules imported with `import python` by earlier runs, which later runs may use
+    python_modules: HashSet<String>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            vm: VM::new(),
+            python_modules: HashSet::new(),
+        }
+    }
+
+    // Send print output to `capture` instead of the process stdout/stderr
+    pub fn set_output_capture(&mut self, capture: OutputCapture) {
+        self.vm.set_output_capture(capture);
+    }
+
+    /// Let scripts use `capability` on `scope`, a path that includes
+    /// everything under it
+    pub fn allow(&mut self, capability: Capability, scope: &str) -> Result<(), CompilerError> {
+        self.vm.permissions_mut().allow(capability, scope).map_err(|message| CompilerError::runtime_error(&message))
+    }
+
+    /// Let scripts use `capability` without limits
+    pub fn allow_all(&mut self, capability: Capability) {
+        self.vm.permissions_mut().allow_all(capability);
+    }
+
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.vm.set_permissions(permissions);
+    }
+
+    /// Bound every later `run`, `run_bytecode` and `call`; each gets the
+    /// whole budget to itself
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.vm.set_limits(limits);
+    }
+
+    /// The limit that stopped the last run or call, if one did
+    pub fn limit_exceeded(&self) -> Option<Limit> {
+        self.vm.limit_exceeded()
+    }
+
+    /// Compile and run `source` after everything this engine has run before.
+    /// Returns the value of the last statement if it is an expression, and
+    /// null otherwise.
+    pub fn run(&mut self, source: &str) -> Result<VMValue, CompilerError> {
+        // Like a notebook cell, the trailing expression may leave out its semicolon
+        let mut program = parse(source).or_else(|error| parse(&format!("{}\n;", source)).map_err(|_| error))?;
+        if let Some(last) = program.statements.last_mut() {
+            if let Statement::Expression(value) = last {
+                *last = Statement::Let(LetStatement {
+                    name: RESULT_VARIABLE.to_string(),
+                    type_annotation: None,
+                    value: Box::new(value.clone()),
+                    is_mutable: false,
+                });
+            }
+        }
+
+        self.python_modules.extend(BytecodeCompiler::python_modules(&program));
+        let instructions = BytecodeCompiler::new()
+            .with_python_modules(self.python_modules.clone())
+            .compile_program(&program)?;
+        self.vm.run_appended(instructions).map_err(|message| CompilerError::runtime_error(&message))?;
+        Ok(self.vm.remove_global(RESULT_VARIABLE).unwrap_or(VMValue::Null))
+    }
+
+    /// Run a program saved by `neksis build --emit-bytecode`, after everything
+    /// this engine has run before
+    pub fn run_bytecode(&mut self, bytes: &[u8]) -> Result<(), CompilerError> {
+        let instructions = bytecode_file::decode(bytes)?;
+        for instruction in &instructions {
+            if let BytecodeInstruction::ImportPython(module, alias) = instruction {
+                let binding = alias.as_deref().unwrap_or_else(|| module.split('.').next().unwrap_or(module));
+                self.python_modules.insert(binding.to_string());
+            }
+        }
+        self.vm.run_appended(instructions).map_err(|message| CompilerError::runtime_error(&message))
+    }
+
+    /// `run`, with the result converted to `T`
+    pub fn eval<T: FromValue>(&mut self, source: &str) -> Result<T, CompilerError> {
+        let value = self.run(source)?;
+        T::from_value(value).map_err(|e| CompilerError::runtime_error(&format!("result: {}", e)))
+    }
+
+    /// Call the Neksis function `name`, defined by an earlier run, e.g.
+    /// `engine.call::<i64>("add", (1, 2))`
+    pub fn call<T: FromValue>(&mut self, name: &str, args: impl IntoArgs) -> Result<T, CompilerError> {
+        let value = self.vm.call(name, args.into_args()).map_err(|message| CompilerError::runtime_error(&message))?;
+        T::from_value(value).map_err(|e| CompilerError::runtime_error(&format!("{}: return value: {}", name, e)))
+    }
+
+    /// Make a Rust closure callable from programs as `name(...)`. Its
+    /// parameters and result are converted with `FromValue` and `IntoValue`;
+    /// an `Err` it returns, like a wrong argument, is a runtime error that
+    /// try/catch can handle.
+    pub fn register_fn<Args>(&mut self, name: &str, function: impl IntoHostFunction<Args>) {
+        self.vm.register_function(name, function.into_host_function(name));
+    }
+
+    // `register_fn` for a function that takes the arguments as they come
+    pub fn register_function(&mut self, name: &str, function: HostFunction) {
+        self.vm.register_function(name, function);
+    }
+
+    pub fn global(&self, name: &str) -> Option<VMValue> {
+        self.vm.global(name)
+    }
+
+    /// The global `name` converted to `T`
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<T, CompilerError> {
+        let value = self.vm.global(name)
+            .ok_or_else(|| CompilerError::runtime_error(&format!("Undefined variable: {}", name)))?;
+        T::from_value(value).map_err(|e| CompilerError::runtime_error(&format!("{}: {}", name, e)))
+    }
+
+    // Define a global that later runs, and every function in them, can read
+    pub fn set_global(&mut self, name: &str, value: impl IntoValue) {
+        self.vm.set_global(name, value.into_value());
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A Rust value that can be passed to Neksis
+pub trait IntoValue {
+    fn into_value(self) -> VMValue;
+}
+
+/// A Rust value that can be read from a Neksis value, if it has the right type
+pub trait FromValue: Sized {
+    fn from_value(value: VMValue) -> Result<Self, String>;
+}
+
+fn mismatch(expected: &str, value: &VMValue) -> String {
+    format!("expected {}, got {}", expected, value.type_name())
+}
+
+impl IntoValue for VMValue {
+    fn into_value(self) -> VMValue {
+        self
+    }
+}
+
+impl FromValue for VMValue {
+    fn from_value(value: VMValue) -> Result<Self, String> {
+        Ok(value)
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> VMValue {
+        VMValue::Int(self)
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: VMValue) -> Result<Self, String> {
+        match value {
+            VMValue::Int(n) => Ok(n),
+            other => Err(mismatch("int", &other)),
+        }
+    }
+}
+
+impl IntoValue for i32 {
+    fn into_value(self) -> VMValue {
+        VMValue::Int(self as i64)
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: VMValue) -> Result<Self, String> {
+        let n = i64::from_value(value)?;
+        i32::try_from(n).map_err(|_| format!("{} does not fit in an i32", n))
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> VMValue {
+        VMValue::Float(self)
+    }
+}
+
+// Ints are accepted where floats are expected, as in Neksis arithmetic
+impl FromValue for f64 {
+    fn from_value(value: VMValue) -> Result<Self, String> {
+        match value {
+            VMValue::Float(x) => Ok(x),
+            VMValue::Int(n) => Ok(n as f64),
+            other => Err(mismatch("float", &other)),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> VMValue {
+        VMValue::Bool(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: VMValue) -> Result<Self, String> {
+        match value {
+            VMValue::Bool(b) => Ok(b),
+            other => Err(mismatch("bool", &other)),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> VMValue {
+        VMValue::String(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> VMValue {
+        VMValue::String(self.to_string())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: VMValue) -> Result<Self, String> {
+        match value {
+            VMValue::String(s) => Ok(s),
+            other => Err(mismatch("string", &other)),
+        }
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> VMValue {
+        VMValue::Null
+    }
+}
+
+// Any value can be ignored, e.g. the result of a function called for its effect
+impl FromValue for () {
+    fn from_value(_: VMValue) -> Result<Self, String> {
+        Ok(())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> VMValue {
+        self.map_or(VMValue::Null, IntoValue::into_value)
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: VMValue) -> Result<Self, String> {
+        match value {
+            VMValue::Null => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> VMValue {
+        VMValue::array(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: VMValue) -> Result<Self, String> {
+        let VMValue::Array(items) = value else {
+            return Err(mismatch("array", &value));
+        };
+        let items = items.borrow();
+        items.iter().enumerate()
+            .map(|(index, item)| T::from_value(item.clone()).map_err(|e| format!("element {}: {}", index, e)))
+            .collect()
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self) -> VMValue {
+        VMValue::object(self.into_iter().map(|(key, value)| (key, value.into_value())).collect())
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(value: VMValue) -> Result<Self, String> {
+        let VMValue::Object(fields) = value else {
+            return Err(mismatch("object", &value));
+        };
+        let fields = fields.borrow();
+        fields.iter()
+            .map(|(key, value)| {
+                let value = T::from_value(value.clone()).map_err(|e| format!("field '{}': {}", key, e))?;
+                Ok((key.clone(), value))
+            })
+            .collect()
+    }
+}
+
+/// What a function given to `register_fn` returns: a value, or a `Result`
+/// whose error becomes a runtime error
+pub trait IntoHostResult {
+    fn into_host_result(self) -> Result<VMValue, String>;
+}
+
+impl<T: IntoValue> IntoHostResult for T {
+    fn into_host_result(self) -> Result<VMValue, String> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue, E: fmt::Display> IntoHostResult for Result<T, E> {
+    fn into_host_result(self) -> Result<VMValue, String> {
+        self.map(IntoValue::into_value).map_err(|e| e.to_string())
+    }
+}
+
+/// A Rust closure that `register_fn` can wrap; `Args` is the tuple of its
+/// parameter types
+pub trait IntoHostFunction<Args> {
+    fn into_host_function(self, name: &str) -> HostFunction;
+}
+
+/// The arguments for `Engine::call`: a tuple of values, or a `Vec<VMValue>`
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<VMValue>;
+}
+
+impl IntoArgs for Vec<VMValue> {
+    fn into_args(self) -> Vec<VMValue> {
+        self
+    }
+}
+
+macro_rules! impl_arity {
+    ($count:expr $(, $arg:ident $index:tt)*) => {
+        impl<$($arg: IntoValue),*> IntoArgs for ($($arg,)*) {
+            fn into_args(self) -> Vec<VMValue> {
+                vec![$(self.$index.into_value()),*]
+            }
+        }
+
+        impl<F, R, $($arg),*> IntoHostFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoHostResult,
+            $($arg: FromValue,)*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_host_function(self, name: &str) -> HostFunction {
+                let name = name.to_string();
+                Rc::new(move |args: Vec<VMValue>| {
+                    if args.len() != $count {
+                        return Err(format!("Function {} expects {} arguments, got {}", name, $count, args.len()));
+                    }
+                    let mut args = args.into_iter();
+                    $(
+                        let $arg = $arg::from_value(args.next().unwrap())
+                            .map_err(|e| format!("{}: argument {}: {}", name, $index + 1, e))?;
+                    )*
+                    self($($arg),*).into_host_result()
+                })
+            }
+        }
+    };
+}
+
+impl_arity!(0);
+impl_arity!(1, A 0);
+impl_arity!(2, A 0, B 1);
+impl_arity!(3, A 0, B 1, C 2);
+impl_arity!(4, A 0, B 1, C 2, D 3);
+impl_arity!(5, A 0, B 1, C 2, D 3, E 4);
+impl_arity!(6, A 0, B 1, C 2, D 3, E 4, G 5);
+
+/// A problem `check` found in a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: LintSeverity,
+    pub message: String,
+    /// 1-based; not every compiler error knows where it happened
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Diagnostic {
+    fn from_error(error: &CompilerError) -> Self {
+        let line = match &error.location {
+            Some(location) => Some(location.line),
+            // Parser errors name their line in the message
+            None => error.message.rfind(" at line ").and_then(|start| {
+                let digits: String = error.message[start + 9..].chars().take_while(char::is_ascii_digit).collect();
+                digits.parse().ok()
+            }),
+        };
+        Self {
+            severity: LintSeverity::Error,
+            message: error.message.clone(),
+            line,
+            column: error.location.as_ref().map(|location| location.column),
+        }
+    }
+}
+
+/// Compile `source` without running it. A program that does not compile gets
+/// the error that stopped it; one that does gets the linter's findings.
+pub fn check(source: &str) -> Vec<Diagnostic> {
+    let compiled = parse(source).and_then(|program| BytecodeCompiler::new().compile_program(&program));
+    if let Err(error) = compiled {
+        return vec![Diagnostic::from_error(&error)];
+    }
+    match Linter::new().lint_source(source, SOURCE_NAME) {
+        Ok(issues) => issues
+            .into_iter()
+            .map(|issue| Diagnostic {
+                severity: issue.severity,
+                message: issue.message,
+                line: Some(issue.line),
+                column: Some(issue.column),
+            })
+            .collect(),
+        Err(error) => vec![Diagnostic::from_error(&error)],
+    }
+}
+
+fn parse(source: &str) -> Result<Program, CompilerError> {
+    let tokens = Lexer::new(source, SOURCE_NAME.to_string()).tokenize()?;
+    Ok(Parser::new(tokens).parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_engine_keeps_state_between_runs() {
+        let capture = OutputCapture::new();
+        let mut engine = Engine::new();
+        engine.set_output_capture(capture.clone());
+
+        engine.run("fn square(x: Int) -> Int { return x * x; }\nlet total = 0;").unwrap();
+        engine.run("let total = total + square(4);\nif total > 10 { print(\"big\"); } else { print(\"small\"); }").unwrap();
+        assert_eq!(engine.run("total + 1").unwrap(), VMValue::Int(17));
+        assert_eq!(capture.stdout(), "big\n");
+        // Neither a statement nor a print call produces a value
+        assert_eq!(engine.run("let unused = 1;").unwrap(), VMValue::Null);
+        assert_eq!(engine.run("print(unused);").unwrap(), VMValue::Null);
+
+        // A failed run leaves the engine usable
+        let error = engine.run("let y = missing_variable;").unwrap_err();
+        assert_eq!(error.message, "Undefined variable: missing_variable");
+        assert_eq!(engine.run("square(total)").unwrap(), VMValue::Int(256));
+    }
+
+    #[test]
+    fn test_engine_globals_and_host_functions() {
+        let mut engine = Engine::new();
+        engine.set_global("limit", VMValue::Int(3));
+        engine.register_function("double", Rc::new(|args: Vec<VMValue>| match args.as_slice() {
+            [VMValue::Int(n)] => Ok(VMValue::Int(n * 2)),
+            _ => Err("double expects an Int".to_string()),
+        }));
+
+        // Globals are visible inside functions as well as at the top level
+        engine.run("fn capped(x: Int) -> Int { if x > limit { return limit; } return x; }").unwrap();
+        assert_eq!(engine.run("capped(double(5))").unwrap(), VMValue::Int(3));
+        assert_eq!(engine.run("double(\"a\")").unwrap_err().message, "double expects an Int");
+        let caught = "let message = \"\";\ntry { double(true); } catch (e) { message = e; }\nmessage";
+        assert_eq!(engine.run(caught).unwrap(), VMValue::String("double expects an Int".to_string()));
+
+        engine.run("let limit = limit * 10;").unwrap();
+        assert_eq!(engine.global("limit"), Some(VMValue::Int(30)));
+        assert_eq!(engine.global("__engine_result"), None);
+    }
+
+    #[test]
+    fn test_register_fn_converts_values() {
+        let mut engine = Engine::new();
+        engine.register_fn("add", |a: i64, b: i64| a + b);
+        engine.register_fn("greet", |name: String| format!("hello, {}", name));
+        engine.register_fn("answer", || 42);
+        engine.register_fn("mean", |values: Vec<f64>| {
+            if values.is_empty() {
+                return Err("mean of an empty array");
+            }
+            Ok(values.iter().sum::<f64>() / values.len() as f64)
+        });
+        engine.register_fn("first_word", |text: String| text.split_whitespace().next().map(str::to_string));
+
+        assert_eq!(engine.eval::<i64>("add(answer(), 3)").unwrap(), 45);
+        assert_eq!(engine.eval::<String>("greet(\"neksis\")").unwrap(), "hello, neksis");
+        assert_eq!(engine.eval::<f64>("mean([1, 2.5, 4.5])").unwrap(), 8.0 / 3.0);
+        assert_eq!(engine.eval::<Option<String>>("first_word(\"   \")").unwrap(), None);
+        assert_eq!(engine.eval::<Option<String>>("first_word(\"big red dog\")").unwrap(), Some("big".to_string()));
+
+        assert_eq!(engine.run("add(1, \"2\")").unwrap_err().message, "add: argument 2: expected int, got string");
+        assert_eq!(engine.run("mean([1, true])").unwrap_err().message, "mean: argument 1: element 1: expected float, got bool");
+        assert_eq!(engine.run("add(1)").unwrap_err().message, "Function add expects 2 arguments, got 1");
+        assert_eq!(engine.eval::<bool>("add(1, 2)").unwrap_err().message, "result: expected bool, got int");
+        let caught = "let message = \"\";\ntry { mean([]); } catch (e) { message = e; }\nmessage";
+        assert_eq!(engine.eval::<String>(caught).unwrap(), "mean of an empty array");
+    }
+
+    #[test]
+    fn test_call_functions_and_typed_globals() {
+        let mut engine = Engine::new();
+        engine.run("fn area(w: Float, h: Float) -> Float { return w * h; }\nfn label(n: Int) -> String { return \"n=\" + n; }").unwrap();
+        assert_eq!(engine.call::<f64>("area", (2.5, 4)).unwrap(), 10.0);
+        assert_eq!(engine.call::<String>("label", (7,)).unwrap(), "n=7");
+        assert_eq!(engine.call::<VMValue>("label", vec![VMValue::Int(1)]).unwrap(), VMValue::String("n=1".to_string()));
+        assert_eq!(engine.call::<i64>("label", (7,)).unwrap_err().message, "label: return value: expected int, got string");
+        assert_eq!(engine.call::<()>("area", (1.0,)).unwrap_err().message, "Function area expects 2 arguments, got 1");
+        assert_eq!(engine.call::<()>("missing", ()).unwrap_err().message, "Undefined function: missing");
+
+        engine.set_global("config", HashMap::from([("retries".to_string(), 3)]));
+        engine.set_global("names", vec!["ada", "grace"]);
+        engine.set_global("nothing", None::<i64>);
+        assert_eq!(engine.eval::<i64>("dict_get(config, \"retries\") * 2").unwrap(), 6);
+        assert_eq!(engine.get::<Vec<String>>("names").unwrap(), vec!["ada".to_string(), "grace".to_string()]);
+        assert_eq!(engine.get::<HashMap<String, i32>>("config").unwrap()["retries"], 3);
+        assert_eq!(engine.get::<Option<i64>>("nothing").unwrap(), None);
+        assert_eq!(engine.get::<i64>("names").unwrap_err().message, "names: expected int, got array");
+        assert_eq!(engine.get::<i64>("nope").unwrap_err().message, "Undefined variable: nope");
+    }
+
+    #[test]
+    fn test_engines_are_isolated() {
+        let mut first = Engine::new();
+        let mut second = Engine::new();
+        first.register_fn("source", || "first");
+        second.register_fn("source", || "second");
+
+        first.run("fn only_here() -> Int { return 1; }\nlet origin = source();").unwrap();
+        second.run("let origin = 2;").unwrap();
+        assert_eq!(first.get::<String>("origin").unwrap(), "first");
+        assert_eq!(second.get::<i64>("origin").unwrap(), 2);
+        assert_eq!(second.eval::<String>("source()").unwrap(), "second");
+        assert_eq!(first.call::<i64>("only_here", ()).unwrap(), 1);
+        assert!(second.call::<i64>("only_here", ()).is_err());
+    }
+
+    #[test]
+    fn test_engine_permissions() {
+        let dir = std::env::temp_dir().join(format!("neksis-engine-permissions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.txt").display().to_string();
+        let mut engine = Engine::new();
+        engine.set_global("path", path.as_str());
+
+        let caught = "let message = \"\";\ntry { write_file(path, \"hi\"); } catch (e) { message = e; }\nmessage";
+        assert_eq!(
+            engine.eval::<String>(caught).unwrap(),
+            format!("PermissionDenied: write access to '{}' (grant it with --allow-write)", path)
+        );
+        assert!(engine.run("exit(1);").unwrap_err().message.starts_with("PermissionDenied: exit"));
+
+        engine.allow(Capability::Write, dir.to_str().unwrap()).unwrap();
+        engine.run("write_file(path, \"hi\");").unwrap();
+        assert!(engine.run("read_file(path)").unwrap_err().message.starts_with("PermissionDenied: read access"));
+        engine.allow_all(Capability::Read);
+        assert_eq!(engine.eval::<String>("read_file(path)").unwrap(), "hi");
+        assert_eq!(engine.allow(Capability::Ffi, "libc.so.6").unwrap_err().message, "The ffi permission cannot be limited to 'libc.so.6'");
+
+        engine.set_permissions(Permissions::none());
+        assert!(engine.run("read_file(path)").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_engine_limits() {
+        let mut engine = Engine::new();
+        engine.run("fn spin(n: Int) -> Int { let i = 0; while i < n { i = i + 1; } return i; }").unwrap();
+        engine.set_limits(Limits { max_instructions: Some(5_000), ..Limits::default() });
+
+        let error = engine.run("try { spin(100000); } catch (e) { print(\"caught\"); }").unwrap_err();
+        assert!(error.message.starts_with("InstructionLimitExceeded"));
+        assert_eq!(engine.limit_exceeded(), Some(Limit::Instructions));
+
+        // Each run and call starts with a fresh budget
+        assert_eq!(engine.call::<i64>("spin", (100,)).unwrap(), 100);
+        assert_eq!(engine.limit_exceeded(), None);
+        assert!(engine.call::<i64>("spin", (100000,)).is_err());
+        assert_eq!(engine.limit_exceeded(), Some(Limit::Instructions));
+
+        engine.set_limits(Limits::default());
+        assert_eq!(engine.call::<i64>("spin", (100000,)).unwrap(), 100000);
+    }
+
+    #[test]
+    fn test_check_reports_diagnostics() {
+        assert_eq!(check("fn f(x: Int) -> Int { return x; }\nprint(f(1));"), Vec::new());
+
+        let errors = check("let a = 1;\nfn f(x) { return x; }");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].severity, LintSeverity::Error);
+        assert_eq!(errors[0].line, Some(2));
+        assert!(errors[0].message.starts_with("Expected ':' after parameter name"), "{}", errors[0].message);
+
+        let warnings = check("fn f() -> Int {\n    let unused = 1;\n    return 2;\n}\nprint(f());");
+        assert!(warnings.iter().any(|warning| warning.severity == LintSeverity::Warning && warning.line == Some(2)), "{:?}", warnings);
+    }
+}
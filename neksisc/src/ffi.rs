@@ -1,8 +1,28 @@
+// Foreign function interface
+//
+// Calls C functions in shared libraries through libffi. A signature is
+// described with `FFIType`s; arguments are written into C-layout buffers,
+// the call is made with `ffi_call`, and the result is read back. Arrays are
+// passed as pointers to temporary buffers that are copied back afterwards,
+// and `FFIValue::Callback`s become libffi closures that C can call.
+// `PythonInterop` does the same job for Python modules, through pyo3.
+
 use std::ffi::{CString, CStr};
+use std::fmt;
 use std::os::raw::{c_void, c_char};
 use std::collections::HashMap;
+use std::rc::Rc;
+use libffi::low::ffi_cif;
+use libffi::middle::{Cif, Closure, Type as LibffiType};
+use libffi::raw::ffi_arg;
+use pyo3::prelude::*;
+use pyo3::AsPyPointer;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyList, PyLong, PyString, PyTuple};
+use serde::{Deserialize, Serialize};
 use crate::ast::Type;
 use crate::error::CompilerError;
+use crate::memory_manager::CycleCollector;
+use crate::vm::VMValue;
 
 
 #[derive(Debug)]
@@ -16,24 +36,25 @@ pub struct FFILibrary {
 pub struct FFIFunction {
     pub name: String,
     pub signature: FFISignature,
-    pub symbol: Option<libloading::Symbol<'static, fn()>>,
+    // Address of the function in the loaded library
+    pub symbol: Option<*const c_void>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FFISignature {
     pub return_type: FFIType,
     pub parameters: Vec<FFIParameter>,
     pub calling_convention: CallingConvention,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FFIParameter {
     pub name: String,
     pub ffi_type: FFIType,
     pub direction: ParameterDirection,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FFIType {
     Void,
     Int8,
@@ -56,21 +77,21 @@ pub enum FFIType {
     Custom(String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FFIField {
     pub name: String,
     pub ffi_type: FFIType,
     pub offset: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ParameterDirection {
     In,
     Out,
     InOut,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CallingConvention {
     C,
     StdCall,
@@ -111,206 +132,442 @@ impl FFIContext {
     }
 
     pub fn load_library(&mut self, name: &str, path: &str) -> Result<(), CompilerError> {
-        unsafe {
-            let library = libloading::Library::new(path)
-                .map_err(|e| CompilerError::ffi_error("library", &format!("Failed to load library: {}", e)))?;
-
-            let mut ffi_library = FFILibrary {
-                name: name.to_string(),
-                functions: HashMap::new(),
-                handle: Some(library),
-            };
-
-            // Register common functions
-            self.register_common_functions(&mut ffi_library)?;
-
-            self.libraries.insert(name.to_string(), ffi_library);
-            Ok(())
-        }
-    }
-
-    fn register_common_functions(&self, library: &mut FFILibrary) -> Result<(), CompilerError> {
-        // Register malloc/free
-        let malloc_sig = FFISignature {
-            return_type: FFIType::Pointer(Box::new(FFIType::Void)),
-            parameters: vec![
-                FFIParameter {
-                    name: "size".to_string(),
-                    ffi_type: FFIType::UInt64,
-                    direction: ParameterDirection::In,
-                }
-            ],
-            calling_convention: CallingConvention::C,
-        };
-
-        let free_sig = FFISignature {
-            return_type: FFIType::Void,
-            parameters: vec![
-                FFIParameter {
-                    name: "ptr".to_string(),
-                    ffi_type: FFIType::Pointer(Box::new(FFIType::Void)),
-                    direction: ParameterDirection::In,
-                }
-            ],
-            calling_convention: CallingConvention::C,
-        };
-
-        library.functions.insert("malloc".to_string(), FFIFunction {
-            name: "malloc".to_string(),
-            signature: malloc_sig,
-            symbol: None,
+        let library = unsafe { libloading::Library::new(path) }
+            .map_err(|e| CompilerError::ffi_error("library", &format!("Failed to load library '{}': {}", path, e)))?;
+        self.libraries.insert(name.to_string(), FFILibrary {
+            name: name.to_string(),
+            functions: HashMap::new(),
+            handle: Some(library),
         });
+        Ok(())
+    }
 
-        library.functions.insert("free".to_string(), FFIFunction {
-            name: "free".to_string(),
-            signature: free_sig,
-            symbol: None,
+    // Look up `function_name` in a loaded library so it can be called with `signature`
+    pub fn declare_function(&mut self, library_name: &str, function_name: &str, signature: FFISignature) -> Result<(), CompilerError> {
+        let library = self.libraries.get_mut(library_name)
+            .ok_or_else(|| CompilerError::ffi_error("library", &format!("Library '{}' not found", library_name)))?;
+        let handle = library.handle.as_ref()
+            .ok_or_else(|| CompilerError::ffi_error("library", &format!("Library '{}' is not loaded", library_name)))?;
+        let symbol = unsafe { handle.get::<unsafe extern "C" fn()>(function_name.as_bytes()) }
+            .map_err(|e| CompilerError::ffi_error("function", &format!("Function '{}' not found in '{}': {}", function_name, library_name, e)))?;
+        let address = *symbol as *const c_void;
+        library.functions.insert(function_name.to_string(), FFIFunction {
+            name: function_name.to_string(),
+            signature,
+            symbol: Some(address),
         });
-
         Ok(())
     }
 
-    pub fn call_function(&mut self, library_name: &str, function_name: &str, args: Vec<FFIValue>) -> Result<FFIValue, CompilerError> {
-        // Get library and function signatures without holding mutable borrow
-        let (signature, return_type) = {
-            let library = self.libraries.get(library_name)
-                .ok_or_else(|| CompilerError::ffi_error("library", &format!("Library '{}' not found", library_name)))?;
-            
-            let function = library.functions.get(function_name)
-                .ok_or_else(|| CompilerError::ffi_error("function", &format!("Function '{}' not found", function_name)))?;
-            
-            (function.signature.clone(), function.signature.return_type.clone())
-        };
+    pub fn call_function(&mut self, library_name: &str, function_name: &str, mut args: Vec<FFIValue>) -> Result<FFIValue, CompilerError> {
+        let library = self.libraries.get(library_name)
+            .ok_or_else(|| CompilerError::ffi_error("library", &format!("Library '{}' not found", library_name)))?;
+        let function = library.functions.get(function_name)
+            .ok_or_else(|| CompilerError::ffi_error("function", &format!("Function '{}' not found", function_name)))?;
+        let address = function.symbol
+            .ok_or_else(|| CompilerError::ffi_error("function", &format!("Function '{}' has no address", function_name)))?;
 
-        // Validate arguments
-        self.validate_function_call(&signature, &args)?;
+        unsafe { call_raw(address, &function.signature, &mut args) }
+            .map_err(|e| CompilerError::ffi_error("call", &format!("{}: {}", function_name, e)))
+    }
+}
 
-        // Convert arguments to C types
-        let c_args = self.convert_to_c_args(&signature.parameters, args)?;
+impl FFIType {
+    /// A C struct whose fields are laid out in order with C alignment rules
+    pub fn structure(fields: Vec<(String, FFIType)>) -> FFIType {
+        let mut offset = 0;
+        let fields = fields.into_iter().map(|(name, ffi_type)| {
+            let (size, align) = ffi_type.layout();
+            offset = usize::next_multiple_of(offset, align);
+            let field = FFIField { name, ffi_type, offset };
+            offset += size;
+            field
+        }).collect();
+        FFIType::Struct(fields)
+    }
 
-        // Call the function
-        let result = unsafe {
-            self.execute_function_call(&signature, &c_args)?
-        };
+    /// Size and alignment of the C representation; arrays, strings and
+    /// functions are passed as pointers
+    pub fn layout(&self) -> (usize, usize) {
+        fn of<T>() -> (usize, usize) {
+            (std::mem::size_of::<T>(), std::mem::align_of::<T>())
+        }
+        match self {
+            FFIType::Void => (0, 1),
+            FFIType::Int8 | FFIType::UInt8 | FFIType::Bool => of::<u8>(),
+            FFIType::Int16 | FFIType::UInt16 => of::<u16>(),
+            FFIType::Int32 | FFIType::UInt32 => of::<u32>(),
+            FFIType::Int64 | FFIType::UInt64 => of::<u64>(),
+            FFIType::Float32 => of::<f32>(),
+            FFIType::Float64 => of::<f64>(),
+            FFIType::Struct(fields) | FFIType::Union(fields) => {
+                let align = fields.iter().map(|field| field.ffi_type.layout().1).max().unwrap_or(1);
+                let end = fields.iter().map(|field| field.offset + field.ffi_type.layout().0).max().unwrap_or(0);
+                (end.next_multiple_of(align), align)
+            }
+            FFIType::String | FFIType::Pointer(_) | FFIType::Array(..) | FFIType::Function(_) | FFIType::Custom(_) => {
+                of::<*const c_void>()
+            }
+        }
+    }
 
-        // Convert result back to Neksis type
-        let neksis_result = self.convert_from_c_value(&return_type, result)?;
+    /// Numbers, booleans and pointers: values that fit in a register
+    pub fn is_scalar(&self) -> bool {
+        !matches!(
+            self,
+            FFIType::Void | FFIType::String | FFIType::Array(..) | FFIType::Struct(_) | FFIType::Union(_)
+                | FFIType::Function(_) | FFIType::Custom(_)
+        )
+    }
 
-        Ok(neksis_result)
+    fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            FFIType::Int8 | FFIType::Int16 | FFIType::Int32 | FFIType::Int64
+                | FFIType::UInt8 | FFIType::UInt16 | FFIType::UInt32 | FFIType::UInt64
+        )
     }
 
-    fn validate_function_call(&self, signature: &FFISignature, args: &[FFIValue]) -> Result<(), CompilerError> {
-        if args.len() != signature.parameters.len() {
-            return Err(CompilerError::ffi_error("arguments", "Argument count mismatch"));
-        }
+    fn libffi_type(&self) -> Result<LibffiType, String> {
+        Ok(match self {
+            FFIType::Void => LibffiType::void(),
+            FFIType::Int8 => LibffiType::i8(),
+            FFIType::Int16 => LibffiType::i16(),
+            FFIType::Int32 => LibffiType::i32(),
+            FFIType::Int64 => LibffiType::i64(),
+            FFIType::UInt8 | FFIType::Bool => LibffiType::u8(),
+            FFIType::UInt16 => LibffiType::u16(),
+            FFIType::UInt32 => LibffiType::u32(),
+            FFIType::UInt64 => LibffiType::u64(),
+            FFIType::Float32 => LibffiType::f32(),
+            FFIType::Float64 => LibffiType::f64(),
+            FFIType::String | FFIType::Pointer(_) | FFIType::Array(..) | FFIType::Function(_) => LibffiType::pointer(),
+            FFIType::Struct(fields) if fields.is_empty() => return Err("empty structs cannot be passed by value".to_string()),
+            FFIType::Struct(fields) => LibffiType::structure(
+                fields.iter().map(|field| field.ffi_type.libffi_type()).collect::<Result<Vec<_>, _>>()?,
+            ),
+            FFIType::Union(_) => return Err("unions cannot be passed by value".to_string()),
+            FFIType::Custom(name) => return Err(format!("unknown C type '{}'", name)),
+        })
+    }
+}
 
-        for (i, (arg, param)) in args.iter().zip(signature.parameters.iter()).enumerate() {
-            // We need to infer the FFI type from the value since FFIValue doesn't have ffi_type field
-            let arg_type = self.infer_ffi_type_from_value(arg);
-            if !self.is_compatible_type(&arg_type, &param.ffi_type) {
-                return Err(CompilerError::ffi_error("type", &format!("Argument {} type mismatch", i)));
+impl fmt::Display for FFIType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FFIType::Void => write!(f, "void"),
+            FFIType::Int8 => write!(f, "i8"),
+            FFIType::Int16 => write!(f, "i16"),
+            FFIType::Int32 => write!(f, "i32"),
+            FFIType::Int64 => write!(f, "i64"),
+            FFIType::UInt8 => write!(f, "u8"),
+            FFIType::UInt16 => write!(f, "u16"),
+            FFIType::UInt32 => write!(f, "u32"),
+            FFIType::UInt64 => write!(f, "u64"),
+            FFIType::Float32 => write!(f, "f32"),
+            FFIType::Float64 => write!(f, "f64"),
+            FFIType::Bool => write!(f, "bool"),
+            FFIType::String => write!(f, "String"),
+            FFIType::Pointer(inner) => write!(f, "*{}", inner),
+            FFIType::Array(inner, _) => write!(f, "[{}]", inner),
+            FFIType::Struct(fields) | FFIType::Union(fields) => {
+                let keyword = if matches!(self, FFIType::Struct(_)) { "struct" } else { "union" };
+                let fields: Vec<String> = fields.iter().map(|field| format!("{}: {}", field.name, field.ffi_type)).collect();
+                write!(f, "{} {{ {} }}", keyword, fields.join(", "))
             }
+            FFIType::Function(signature) => {
+                let params: Vec<String> = signature.parameters.iter().map(|param| param.ffi_type.to_string()).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), signature.return_type)
+            }
+            FFIType::Custom(name) => write!(f, "{}", name),
         }
-
-        Ok(())
     }
+}
 
-    fn is_compatible_type(&self, from: &FFIType, to: &FFIType) -> bool {
-        match (from, to) {
-            (FFIType::Int32, FFIType::Int32) => true,
-            (FFIType::Float64, FFIType::Float64) => true,
-            (FFIType::Pointer(_), FFIType::Pointer(_)) => true,
-            (FFIType::Bool, FFIType::Bool) => true,
-            _ => false, // Add more compatibility rules as needed
+impl FFISignature {
+    fn cif(&self) -> Result<Cif, String> {
+        if self.calling_convention != CallingConvention::C {
+            return Err(format!("the {:?} calling convention is not supported", self.calling_convention));
         }
+        let params = self.parameters.iter().map(|param| param.ffi_type.libffi_type()).collect::<Result<Vec<_>, _>>()?;
+        Ok(Cif::new(params, self.return_type.libffi_type()?))
     }
+}
 
-    fn infer_ffi_type_from_value(&self, value: &FFIValue) -> FFIType {
-        match value {
-            FFIValue::Void => FFIType::Void,
-            FFIValue::Int8(_) => FFIType::Int8,
-            FFIValue::Int16(_) => FFIType::Int16,
-            FFIValue::Int32(_) => FFIType::Int32,
-            FFIValue::Int64(_) => FFIType::Int64,
-            FFIValue::UInt8(_) => FFIType::UInt8,
-            FFIValue::UInt16(_) => FFIType::UInt16,
-            FFIValue::UInt32(_) => FFIType::UInt32,
-            FFIValue::UInt64(_) => FFIType::UInt64,
-            FFIValue::Float32(_) => FFIType::Float32,
-            FFIValue::Float64(_) => FFIType::Float64,
-            FFIValue::Bool(_) => FFIType::Bool,
-            FFIValue::Pointer(_) => FFIType::Pointer(Box::new(FFIType::Void)),
-            FFIValue::String(_) => FFIType::String,
-            FFIValue::Array(_) => FFIType::Array(Box::new(FFIType::Void), 0),
-            FFIValue::Struct(_) => FFIType::Struct(vec![]),
+/// Call the C function at `address` with `signature`. Array arguments are
+/// passed as pointers to temporary buffers and hold the buffer's contents
+/// after the call.
+///
+/// # Safety
+///
+/// `address` must be a C function whose real signature matches `signature`.
+pub unsafe fn call_raw(address: *const c_void, signature: &FFISignature, args: &mut [FFIValue]) -> Result<FFIValue, String> {
+    if args.len() != signature.parameters.len() {
+        return Err(format!("expects {} arguments, got {}", signature.parameters.len(), args.len()));
+    }
+    let cif = signature.cif()?;
+    let mut marshal = Marshal::default();
+    let mut slots: Vec<Vec<u64>> = signature.parameters.iter().map(|param| words(param.ffi_type.layout().0)).collect();
+    for (index, (param, value)) in signature.parameters.iter().zip(args.iter()).enumerate() {
+        marshal.write(&param.ffi_type, value, slots[index].as_mut_ptr() as *mut u8)
+            .map_err(|e| format!("argument {} ({}): {}", index + 1, param.name, e))?;
+    }
+    let mut pointers: Vec<*mut c_void> = slots.iter_mut().map(|slot| slot.as_mut_ptr() as *mut c_void).collect();
+    let mut result = words(signature.return_type.layout().0.max(std::mem::size_of::<ffi_arg>()));
+
+    let function = std::mem::transmute::<*const c_void, unsafe extern "C" fn()>(address);
+    libffi::raw::ffi_call(cif.as_raw_ptr(), Some(function), result.as_mut_ptr() as *mut c_void, pointers.as_mut_ptr());
+
+    for ((param, value), slot) in signature.parameters.iter().zip(args.iter_mut()).zip(&slots) {
+        if let (FFIType::Array(element, _), FFIValue::Array(items)) = (&param.ffi_type, value) {
+            let buffer = *(slot.as_ptr() as *const *const u8);
+            let stride = element.layout().0;
+            for (index, item) in items.iter_mut().enumerate() {
+                *item = read(element, buffer.add(index * stride));
+            }
         }
     }
+    Ok(read_result(&signature.return_type, result.as_ptr() as *const u8))
+}
 
-    fn convert_to_c_args(&self, parameters: &[FFIParameter], args: Vec<FFIValue>) -> Result<Vec<FFIValue>, CompilerError> {
-        let mut c_args = Vec::new();
+// Zeroed, 8-byte aligned storage for at least `size` bytes
+fn words(size: usize) -> Vec<u64> {
+    vec![0; size.div_ceil(8).max(1)]
+}
 
-        for (param, arg) in parameters.iter().zip(args.iter()) {
-            let converted = self.convert_to_c_value(&param.ffi_type, arg)?;
-            c_args.push(converted);
-        }
+fn integer(value: &FFIValue) -> Option<i128> {
+    Some(match value {
+        FFIValue::Int8(v) => *v as i128,
+        FFIValue::Int16(v) => *v as i128,
+        FFIValue::Int32(v) => *v as i128,
+        FFIValue::Int64(v) => *v as i128,
+        FFIValue::UInt8(v) => *v as i128,
+        FFIValue::UInt16(v) => *v as i128,
+        FFIValue::UInt32(v) => *v as i128,
+        FFIValue::UInt64(v) => *v as i128,
+        _ => return None,
+    })
+}
 
-        Ok(c_args)
-    }
-
-    fn convert_to_c_value(&self, target_type: &FFIType, value: &FFIValue) -> Result<FFIValue, CompilerError> {
-        match (target_type, value) {
-            (FFIType::Int32, FFIValue::Int32(v)) => Ok(FFIValue::Int32(*v)),
-            (FFIType::Float64, FFIValue::Float64(v)) => Ok(FFIValue::Float64(*v)),
-            (FFIType::Bool, FFIValue::Bool(v)) => Ok(FFIValue::Bool(*v)),
-            (FFIType::Pointer(_), FFIValue::Pointer(p)) => Ok(FFIValue::Pointer(*p)),
-            (FFIType::String, FFIValue::String(s)) => {
-                // Convert string to C string
-                let c_string = CString::new(s.as_str())
-                    .map_err(|e| CompilerError::ffi_error("string", &format!("Invalid string: {}", e)))?;
-                let ptr = c_string.into_raw();
-                Ok(FFIValue::Pointer(ptr as *mut c_void))
-            }
-            _ => Err(CompilerError::ffi_error("conversion", "Unsupported type conversion")),
+fn float(value: &FFIValue) -> Option<f64> {
+    match value {
+        FFIValue::Float32(v) => Some(*v as f64),
+        FFIValue::Float64(v) => Some(*v),
+        _ => integer(value).map(|v| v as f64),
+    }
+}
+
+fn mismatch(ty: &FFIType, value: &FFIValue) -> String {
+    format!("expected {}, got {:?}", ty, value)
+}
+
+// Storage that must stay alive until a call returns: C strings, array
+// buffers and callback closures
+#[derive(Default)]
+struct Marshal {
+    strings: Vec<CString>,
+    buffers: Vec<Vec<u64>>,
+    closures: Vec<Closure<'static>>,
+    callbacks: Vec<*mut Callback>,
+}
+
+impl Drop for Marshal {
+    fn drop(&mut self) {
+        // A closure refers to its callback, so it goes first
+        self.closures.clear();
+        for callback in self.callbacks.drain(..) {
+            drop(unsafe { Box::from_raw(callback) });
         }
     }
+}
 
-    fn convert_from_c_value(&self, target_type: &FFIType, value: FFIValue) -> Result<FFIValue, CompilerError> {
-        match target_type {
-            FFIType::Int32 => Ok(value),
-            FFIType::Float64 => Ok(value),
-            FFIType::Bool => Ok(value),
-            FFIType::Pointer(_) => Ok(value),
+impl Marshal {
+    // Write the C representation of `value` as a `ty` at `at`
+    unsafe fn write(&mut self, ty: &FFIType, value: &FFIValue, at: *mut u8) -> Result<(), String> {
+        macro_rules! int {
+            ($t:ty) => {{
+                let number = integer(value).ok_or_else(|| mismatch(ty, value))?;
+                let number = <$t>::try_from(number).map_err(|_| format!("{} does not fit in {}", number, ty))?;
+                (at as *mut $t).write_unaligned(number);
+            }};
+        }
+        match ty {
+            FFIType::Int8 => int!(i8),
+            FFIType::Int16 => int!(i16),
+            FFIType::Int32 => int!(i32),
+            FFIType::Int64 => int!(i64),
+            FFIType::UInt8 => int!(u8),
+            FFIType::UInt16 => int!(u16),
+            FFIType::UInt32 => int!(u32),
+            FFIType::UInt64 => int!(u64),
+            FFIType::Float32 => (at as *mut f32).write_unaligned(float(value).ok_or_else(|| mismatch(ty, value))? as f32),
+            FFIType::Float64 => (at as *mut f64).write_unaligned(float(value).ok_or_else(|| mismatch(ty, value))?),
+            FFIType::Bool => match value {
+                FFIValue::Bool(b) => *at = *b as u8,
+                _ => return Err(mismatch(ty, value)),
+            },
             FFIType::String => {
-                // Convert C string back to Neksis string
-                match value {
-                    FFIValue::Pointer(ptr) => {
-                        if ptr.is_null() {
-                            Ok(FFIValue::String("".to_string()))
-                        } else {
-                            unsafe {
-                                let c_str = CStr::from_ptr(ptr as *const c_char);
-                                let string = c_str.to_string_lossy().to_string();
-                                Ok(FFIValue::String(string))
-                            }
-                        }
+                let pointer = match value {
+                    FFIValue::String(s) => {
+                        let c_string = CString::new(s.as_str()).map_err(|_| "string contains a NUL byte".to_string())?;
+                        let pointer = c_string.as_ptr() as *const c_void;
+                        self.strings.push(c_string);
+                        pointer
                     }
-                    _ => Err(CompilerError::ffi_error("conversion", "Expected pointer for string conversion")),
+                    FFIValue::Pointer(p) => *p as *const c_void,
+                    _ => return Err(mismatch(ty, value)),
+                };
+                (at as *mut *const c_void).write_unaligned(pointer);
+            }
+            FFIType::Pointer(_) => {
+                let pointer = match value {
+                    FFIValue::Pointer(p) => *p,
+                    FFIValue::String(_) => return self.write(&FFIType::String, value, at),
+                    _ => integer(value).ok_or_else(|| mismatch(ty, value))? as usize as *mut c_void,
+                };
+                (at as *mut *mut c_void).write_unaligned(pointer);
+            }
+            FFIType::Array(element, _) => {
+                let FFIValue::Array(items) = value else {
+                    return Err(mismatch(ty, value));
+                };
+                let stride = element.layout().0;
+                let mut buffer = words(stride * items.len());
+                let start = buffer.as_mut_ptr() as *mut u8;
+                for (index, item) in items.iter().enumerate() {
+                    self.write(element, item, start.add(index * stride)).map_err(|e| format!("element {}: {}", index, e))?;
                 }
+                self.buffers.push(buffer);
+                (at as *mut *mut u8).write_unaligned(start);
+            }
+            FFIType::Struct(fields) => {
+                let FFIValue::Struct(values) = value else {
+                    return Err(mismatch(ty, value));
+                };
+                for field in fields {
+                    let field_value = values.get(&field.name).ok_or_else(|| format!("missing field '{}'", field.name))?;
+                    self.write(&field.ffi_type, field_value, at.add(field.offset))
+                        .map_err(|e| format!("field '{}': {}", field.name, e))?;
+                }
+            }
+            FFIType::Function(signature) => {
+                let pointer = match value {
+                    FFIValue::Callback(function) => self.closure(signature, function)?,
+                    FFIValue::Pointer(p) => *p as *const c_void,
+                    _ => return Err(mismatch(ty, value)),
+                };
+                (at as *mut *const c_void).write_unaligned(pointer);
+            }
+            FFIType::Void | FFIType::Union(_) | FFIType::Custom(_) => {
+                return Err(format!("values of type {} cannot be passed", ty));
             }
-            _ => Err(CompilerError::ffi_error("conversion", "Unsupported return type conversion")),
         }
+        Ok(())
     }
 
-    unsafe fn execute_function_call(&self, signature: &FFISignature, _args: &[FFIValue]) -> Result<FFIValue, CompilerError> {
-        // This is a simplified implementation
-        // In a real implementation, you would use libffi or similar to call the function
-        match signature.return_type {
-            FFIType::Int32 => Ok(FFIValue::Int32(0)), // Placeholder
-            FFIType::Float64 => Ok(FFIValue::Float64(0.0)), // Placeholder
-            FFIType::Bool => Ok(FFIValue::Bool(false)), // Placeholder
-            FFIType::Void => Ok(FFIValue::Void), // Placeholder
-            _ => Err(CompilerError::ffi_error("call", "Unsupported return type")),
+    // A C function pointer that runs `function` for as long as this call lasts
+    fn closure(&mut self, signature: &FFISignature, function: &FFICallback) -> Result<*const c_void, String> {
+        if !signature.return_type.is_scalar() && signature.return_type != FFIType::Void {
+            return Err(format!("callbacks cannot return {}", signature.return_type));
         }
+        let cif = signature.cif()?;
+        let callback: &'static Callback = Box::leak(Box::new(Callback { signature: signature.clone(), function: function.clone() }));
+        self.callbacks.push(callback as *const Callback as *mut Callback);
+        let closure = Closure::new(cif, trampoline, callback);
+        let pointer = *closure.code_ptr() as *const c_void;
+        self.closures.push(closure);
+        Ok(pointer)
+    }
+}
+
+// Read a C value of type `ty` stored at `at`
+unsafe fn read(ty: &FFIType, at: *const u8) -> FFIValue {
+    match ty {
+        FFIType::Void => FFIValue::Void,
+        FFIType::Int8 => FFIValue::Int8((at as *const i8).read_unaligned()),
+        FFIType::Int16 => FFIValue::Int16((at as *const i16).read_unaligned()),
+        FFIType::Int32 => FFIValue::Int32((at as *const i32).read_unaligned()),
+        FFIType::Int64 => FFIValue::Int64((at as *const i64).read_unaligned()),
+        FFIType::UInt8 => FFIValue::UInt8(*at),
+        FFIType::UInt16 => FFIValue::UInt16((at as *const u16).read_unaligned()),
+        FFIType::UInt32 => FFIValue::UInt32((at as *const u32).read_unaligned()),
+        FFIType::UInt64 => FFIValue::UInt64((at as *const u64).read_unaligned()),
+        FFIType::Float32 => FFIValue::Float32((at as *const f32).read_unaligned()),
+        FFIType::Float64 => FFIValue::Float64((at as *const f64).read_unaligned()),
+        FFIType::Bool => FFIValue::Bool(*at != 0),
+        FFIType::String => {
+            let pointer = (at as *const *const c_char).read_unaligned();
+            if pointer.is_null() {
+                FFIValue::Pointer(std::ptr::null_mut())
+            } else {
+                FFIValue::String(CStr::from_ptr(pointer).to_string_lossy().into_owned())
+            }
+        }
+        FFIType::Struct(fields) => FFIValue::Struct(
+            fields.iter().map(|field| (field.name.clone(), read(&field.ffi_type, at.add(field.offset)))).collect(),
+        ),
+        _ => FFIValue::Pointer((at as *const *mut c_void).read_unaligned()),
+    }
+}
+
+// libffi widens integer results narrower than a register to a full `ffi_arg`
+unsafe fn read_result(ty: &FFIType, at: *const u8) -> FFIValue {
+    let wide = (at as *const ffi_arg).read_unaligned();
+    match ty {
+        FFIType::Int8 => FFIValue::Int8(wide as i8),
+        FFIType::Int16 => FFIValue::Int16(wide as i16),
+        FFIType::Int32 => FFIValue::Int32(wide as i32),
+        FFIType::UInt8 => FFIValue::UInt8(wide as u8),
+        FFIType::UInt16 => FFIValue::UInt16(wide as u16),
+        FFIType::UInt32 => FFIValue::UInt32(wide as u32),
+        FFIType::Bool => FFIValue::Bool(wide as u8 != 0),
+        _ => read(ty, at),
+    }
+}
+
+/// A host function that C code can call through a function pointer,
+/// for example a comparator handed to `qsort`
+#[derive(Clone)]
+pub struct FFICallback(pub Rc<dyn Fn(Vec<FFIValue>) -> FFIValue>);
+
+impl fmt::Debug for FFICallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FFICallback")
+    }
+}
+
+struct Callback {
+    signature: FFISignature,
+    function: FFICallback,
+}
+
+unsafe extern "C" fn trampoline(_cif: &ffi_cif, result: &mut c_void, args: *const *const c_void, callback: &Callback) {
+    let result = result as *mut c_void as *mut u8;
+    let values = callback.signature.parameters.iter().enumerate().map(|(index, param)| {
+        let at = *args.add(index) as *const u8;
+        match &param.ffi_type {
+            // Comparators receive pointers to the elements; hand over the elements themselves
+            FFIType::Pointer(inner) if inner.is_scalar() => {
+                let pointer = (at as *const *const u8).read_unaligned();
+                if pointer.is_null() { FFIValue::Pointer(std::ptr::null_mut()) } else { read(inner, pointer) }
+            }
+            ty => read(ty, at),
+        }
+    }).collect();
+    // Unwinding into C is undefined behaviour, so a panic returns zero instead
+    let value = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| (callback.function.0)(values)));
+    let return_type = &callback.signature.return_type;
+    let (size, _) = return_type.layout();
+    std::ptr::write_bytes(result, 0, size.max(std::mem::size_of::<ffi_arg>()));
+    let Ok(value) = value else { return };
+    if return_type.is_integer() || *return_type == FFIType::Bool {
+        let number = match value {
+            FFIValue::Bool(b) => b as i128,
+            _ => integer(&value).unwrap_or(0),
+        };
+        (result as *mut ffi_arg).write_unaligned(number as i64 as ffi_arg);
+    } else if *return_type != FFIType::Void {
+        let _ = Marshal::default().write(return_type, &value, result);
     }
 }
 
@@ -332,6 +589,7 @@ pub enum FFIValue {
     String(String),
     Array(Vec<FFIValue>),
     Struct(HashMap<String, FFIValue>),
+    Callback(FFICallback),
 }
 
 impl FFIMemoryManager {
@@ -393,32 +651,181 @@ impl FFIMemoryManager {
 }
 
 // Python interop support
-#[derive(Clone)]
+//
+// Modules imported with `import python "name"` run in an interpreter embedded
+// in the process. Values are converted at the boundary: numbers, strings,
+// booleans and None map directly, lists and tuples become arrays and dicts
+// become objects. A Python exception becomes a runtime error carrying the
+// exception's type and message, which `try`/`catch` can catch.
+#[derive(Default)]
 pub struct PythonInterop {
-    pub interpreter: Option<pyo3::Python<'static>>,
-    pub modules: HashMap<String, pyo3::PyObject>,
+    /// Imported modules, by the name the program binds them to
+    pub modules: HashMap<String, PyObject>,
 }
 
 impl PythonInterop {
     pub fn new() -> Result<Self, CompilerError> {
-        // Initialize Python interpreter
-        let _interpreter: Result<(), CompilerError> = pyo3::Python::with_gil(|_py| {
-            // Set up Python environment
-            Ok(())
-        });
+        Ok(Self::default())
+    }
+
+    /// Import `module` and bind it to `alias`. Without an alias, `os.path`
+    /// binds the package `os`, like Python's `import os.path`
+    pub fn import(&mut self, module: &str, alias: Option<&str>) -> Result<(), String> {
+        let (binding, object) = Python::with_gil(|py| -> PyResult<(String, PyObject)> {
+            let imported = PyModule::import(py, module)?;
+            Ok(match alias {
+                Some(alias) => (alias.to_string(), imported.into_py(py)),
+                None => {
+                    let package = module.split('.').next().unwrap_or(module);
+                    (package.to_string(), PyModule::import(py, package)?.into_py(py))
+                }
+            })
+        })
+        .map_err(|e| python_error(&e))?;
+        self.modules.insert(binding, object);
+        Ok(())
+    }
 
-        Ok(Self {
-            interpreter: None, // Will be set when needed
-            modules: HashMap::new(),
+    /// Call `module.path(args...)`; arguments with a name are passed as keyword arguments
+    pub fn call_python_function(
+        &self,
+        module: &str,
+        path: &str,
+        args: &[(Option<String>, VMValue)],
+        collector: &mut CycleCollector,
+    ) -> Result<VMValue, String> {
+        Python::with_gil(|py| {
+            let function = self.attribute(py, module, path)?;
+            let mut positional = Vec::new();
+            let keywords = PyDict::new(py);
+            for (name, value) in args {
+                let value = python_object(py, value)?;
+                match name {
+                    Some(name) => keywords.set_item(name, value).map_err(|e| python_error(&e))?,
+                    None => positional.push(value),
+                }
+            }
+            let result = function
+                .call(PyTuple::new(py, positional), Some(keywords))
+                .map_err(|e| python_error(&e))?;
+            neksis_value(result, collector).map_err(|e| format!("{}.{} returned {}", module, path, e))
+        })
+    }
+
+    /// Read `module.path`, e.g. `math.pi`
+    pub fn get_attribute(&self, module: &str, path: &str, collector: &mut CycleCollector) -> Result<VMValue, String> {
+        Python::with_gil(|py| {
+            let value = self.attribute(py, module, path)?;
+            neksis_value(value, collector).map_err(|e| format!("{}.{} is {}", module, path, e))
         })
     }
 
-    pub fn call_python_function(&self, _function_name: &str, _args: Vec<FFIValue>) -> Result<FFIValue, CompilerError> {
-        // For now, return a placeholder since Python interop is not fully implemented
-        Err(CompilerError::ffi_error("Python", "Python interop not yet implemented"))
+    fn attribute<'py>(&self, py: Python<'py>, module: &str, path: &str) -> Result<&'py PyAny, String> {
+        let module_object = self.modules.get(module).ok_or_else(|| format!("Python module {} is not imported", module))?;
+        let mut object = module_object.clone_ref(py).into_ref(py);
+        for name in path.split('.') {
+            object = object.getattr(name).map_err(|e| python_error(&e))?;
+        }
+        Ok(object)
+    }
+}
+
+/// `ValueError: math domain error`
+pub fn python_error(error: &PyErr) -> String {
+    error.to_string()
+}
+
+/// Convert a Neksis value to the matching Python object
+pub fn python_object(py: Python<'_>, value: &VMValue) -> Result<PyObject, String> {
+    fn convert(py: Python<'_>, value: &VMValue, open: &mut Vec<usize>) -> Result<PyObject, String> {
+        Ok(match value {
+            VMValue::Int(n) => n.into_py(py),
+            VMValue::Float(x) => x.into_py(py),
+            VMValue::String(s) => s.into_py(py),
+            VMValue::Bool(b) => b.into_py(py),
+            VMValue::Null => py.None(),
+            VMValue::Array(items) => {
+                let address = Rc::as_ptr(items) as usize;
+                if open.contains(&address) {
+                    return Err("an array that contains itself cannot be passed to Python".to_string());
+                }
+                open.push(address);
+                let items = items.borrow().iter().map(|item| convert(py, item, open)).collect::<Result<Vec<_>, _>>()?;
+                open.pop();
+                PyList::new(py, items).into_py(py)
+            }
+            VMValue::Object(fields) => {
+                let address = Rc::as_ptr(fields) as usize;
+                if open.contains(&address) {
+                    return Err("an object that contains itself cannot be passed to Python".to_string());
+                }
+                open.push(address);
+                let dict = PyDict::new(py);
+                for (key, field) in fields.borrow().iter() {
+                    dict.set_item(key, convert(py, field, open)?).map_err(|e| python_error(&e))?;
+                }
+                open.pop();
+                dict.into_py(py)
+            }
+            VMValue::Function(name, _) | VMValue::BuiltinFunction(name) => {
+                return Err(format!("function {} cannot be passed to Python", name));
+            }
+        })
     }
+    convert(py, value, &mut Vec::new())
+}
 
+/// Convert a Python object to a Neksis value, tracking new arrays and objects in `collector`
+pub fn neksis_value(object: &PyAny, collector: &mut CycleCollector) -> Result<VMValue, String> {
+    fn convert(object: &PyAny, collector: &mut CycleCollector, open: &mut Vec<usize>) -> Result<VMValue, String> {
+        if object.is_none() {
+            return Ok(VMValue::Null);
+        }
+        // bool is a subclass of int, so it is checked first
+        if let Ok(b) = object.downcast::<PyBool>() {
+            return Ok(VMValue::Bool(b.is_true()));
+        }
+        if object.downcast::<PyLong>().is_ok() {
+            return object.extract::<i64>().map(VMValue::Int).map_err(|_| "an integer too large for Int".to_string());
+        }
+        if let Ok(x) = object.downcast::<PyFloat>() {
+            return Ok(VMValue::Float(x.value()));
+        }
+        if let Ok(s) = object.downcast::<PyString>() {
+            return s.to_str().map(|s| VMValue::String(s.to_string())).map_err(|e| python_error(&e));
+        }
 
+        let address = object.as_ptr() as usize;
+        if open.contains(&address) {
+            return Err("a container that contains itself".to_string());
+        }
+        let items: Option<Vec<&PyAny>> = if let Ok(list) = object.downcast::<PyList>() {
+            Some(list.iter().collect())
+        } else if let Ok(tuple) = object.downcast::<PyTuple>() {
+            Some(tuple.iter().collect())
+        } else {
+            None
+        };
+        if let Some(items) = items {
+            open.push(address);
+            let items = items.into_iter().map(|item| convert(item, collector, open)).collect::<Result<Vec<_>, _>>()?;
+            open.pop();
+            return Ok(collector.array(items));
+        }
+        if let Ok(dict) = object.downcast::<PyDict>() {
+            open.push(address);
+            let mut fields = HashMap::new();
+            for (key, value) in dict.iter() {
+                let key: String = key.extract().map_err(|_| format!("a dict with the non-string key {}", key))?;
+                fields.insert(key, convert(value, collector, open)?);
+            }
+            open.pop();
+            return Ok(collector.object(fields));
+        }
+        let type_name = object.get_type().name().unwrap_or("object");
+        Err(format!("a Python {}, which has no Neksis equivalent", type_name))
+    }
+    convert(object, collector, &mut Vec::new())
 }
 
 // Rust interop support
@@ -469,7 +876,7 @@ pub fn create_rust_interop() -> RustInterop {
 // Type conversion utilities
 pub fn neksis_to_ffi_type(neksis_type: &Type) -> FFIType {
     match neksis_type {
-        Type::Int => FFIType::Int32,
+        Type::Int => FFIType::Int64,
         Type::Float => FFIType::Float64,
         Type::Bool => FFIType::Bool,
         Type::String => FFIType::String,
@@ -481,7 +888,7 @@ pub fn neksis_to_ffi_type(neksis_type: &Type) -> FFIType {
 
 pub fn ffi_to_neksis_type(ffi_type: &FFIType) -> Type {
     match ffi_type {
-        FFIType::Int32 => Type::Int,
+        FFIType::Int64 => Type::Int,
         FFIType::Float64 => Type::Float,
         FFIType::Bool => Type::Bool,
         FFIType::String => Type::String,
@@ -489,4 +896,291 @@ pub fn ffi_to_neksis_type(ffi_type: &FFIType) -> Type {
         FFIType::Pointer(inner) => Type::Pointer(Box::new(ffi_to_neksis_type(inner))),
         _ => Type::Void, // Default fallback
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_framework::golden::run_program_at;
+
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Sample {
+        flag: u8,
+        count: i32,
+        scale: f64,
+    }
+
+    extern "C" fn scale_sample(sample: Sample, factor: f64, enabled: bool) -> Sample {
+        Sample { flag: enabled as u8, count: sample.count * 2, scale: sample.scale * factor }
+    }
+
+    extern "C" fn apply_twice(function: extern "C" fn(i16) -> i16, value: i16) -> i16 {
+        function(function(value))
+    }
+
+    extern "C" fn total(items: *mut i32, count: u64) -> i64 {
+        let items = unsafe { std::slice::from_raw_parts_mut(items, count as usize) };
+        let sum = items.iter().map(|&item| item as i64).sum();
+        items.iter_mut().for_each(|item| *item += 1);
+        sum
+    }
+
+    fn signature(parameters: Vec<FFIType>, return_type: FFIType) -> FFISignature {
+        FFISignature {
+            return_type,
+            parameters: parameters
+                .into_iter()
+                .enumerate()
+                .map(|(index, ffi_type)| FFIParameter { name: format!("p{}", index), ffi_type, direction: ParameterDirection::In })
+                .collect(),
+            calling_convention: CallingConvention::C,
+        }
+    }
+
+    fn sample_type() -> FFIType {
+        FFIType::structure(vec![
+            ("flag".to_string(), FFIType::UInt8),
+            ("count".to_string(), FFIType::Int32),
+            ("scale".to_string(), FFIType::Float64),
+        ])
+    }
+
+    #[test]
+    fn test_struct_layout_matches_c() {
+        let FFIType::Struct(fields) = sample_type() else { unreachable!() };
+        let offsets: Vec<usize> = fields.iter().map(|field| field.offset).collect();
+        assert_eq!(offsets, vec![0, 4, 8]);
+        assert_eq!(sample_type().layout(), (std::mem::size_of::<Sample>(), std::mem::align_of::<Sample>()));
+    }
+
+    #[test]
+    fn test_structs_pass_and_return_by_value() {
+        let signature = signature(vec![sample_type(), FFIType::Float64, FFIType::Bool], sample_type());
+        let sample: HashMap<String, FFIValue> = [
+            ("flag".to_string(), FFIValue::UInt8(0)),
+            ("count".to_string(), FFIValue::Int64(21)),
+            ("scale".to_string(), FFIValue::Float64(1.5)),
+        ]
+        .into_iter()
+        .collect();
+        let mut args = vec![FFIValue::Struct(sample), FFIValue::Int64(2), FFIValue::Bool(true)];
+        let result = unsafe { call_raw(scale_sample as *const c_void, &signature, &mut args) }.unwrap();
+        let FFIValue::Struct(fields) = result else { panic!("expected a struct, got {:?}", result) };
+        assert!(matches!(fields["flag"], FFIValue::UInt8(1)));
+        assert!(matches!(fields["count"], FFIValue::Int32(42)));
+        assert!(matches!(fields["scale"], FFIValue::Float64(scale) if scale == 3.0));
+    }
+
+    #[test]
+    fn test_callbacks_and_narrow_integers() {
+        let callback = FFICallback(Rc::new(|args: Vec<FFIValue>| match args[0] {
+            FFIValue::Int16(value) => FFIValue::Int64(value as i64 * -3),
+            _ => FFIValue::Void,
+        }));
+        let signature = signature(
+            vec![FFIType::Function(Box::new(signature(vec![FFIType::Int16], FFIType::Int16))), FFIType::Int16],
+            FFIType::Int16,
+        );
+        let mut args = vec![FFIValue::Callback(callback), FFIValue::Int64(5)];
+        let result = unsafe { call_raw(apply_twice as *const c_void, &signature, &mut args) }.unwrap();
+        assert!(matches!(result, FFIValue::Int16(45)), "{:?}", result);
+    }
+
+    #[test]
+    fn test_arrays_are_copied_back() {
+        let signature = signature(vec![FFIType::Array(Box::new(FFIType::Int32), 0), FFIType::UInt64], FFIType::Int64);
+        let mut args = vec![FFIValue::Array(vec![FFIValue::Int64(1), FFIValue::Int64(2), FFIValue::Int64(3)]), FFIValue::Int64(3)];
+        let result = unsafe { call_raw(total as *const c_void, &signature, &mut args) }.unwrap();
+        assert!(matches!(result, FFIValue::Int64(6)));
+        let FFIValue::Array(items) = &args[0] else { unreachable!() };
+        assert!(matches!(items[..], [FFIValue::Int32(2), FFIValue::Int32(3), FFIValue::Int32(4)]));
+    }
+
+    #[test]
+    fn test_arguments_are_checked() {
+        let signature = signature(vec![FFIType::Int32], FFIType::Int32);
+        let call = |value: FFIValue| unsafe { call_raw(std::ptr::null(), &signature, &mut [value]) }.unwrap_err();
+        assert_eq!(call(FFIValue::Int64(1 << 40)), "argument 1 (p0): 1099511627776 does not fit in i32");
+        assert_eq!(call(FFIValue::Float64(1.5)), "argument 1 (p0): expected i32, got Float64(1.5)");
+        let structure = self::signature(vec![sample_type()], FFIType::Void);
+        let error = unsafe { call_raw(std::ptr::null(), &structure, &mut [FFIValue::Struct(HashMap::new())]) }.unwrap_err();
+        assert_eq!(error, "argument 1 (p0): missing field 'flag'");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_context_calls_libm_and_libc() {
+        let mut context = FFIContext::new();
+        context.load_library("m", "libm.so.6").unwrap();
+        context.load_library("c", "libc.so.6").unwrap();
+        context.declare_function("m", "cos", signature(vec![FFIType::Float64], FFIType::Float64)).unwrap();
+        context.declare_function("c", "strlen", signature(vec![FFIType::String], FFIType::UInt64)).unwrap();
+        context.declare_function("c", "getenv", signature(vec![FFIType::String], FFIType::String)).unwrap();
+        assert!(context.declare_function("c", "no_such_function", signature(vec![], FFIType::Void)).is_err());
+
+        let cos = context.call_function("m", "cos", vec![FFIValue::Float64(0.0)]).unwrap();
+        assert!(matches!(cos, FFIValue::Float64(value) if value == 1.0));
+        let length = context.call_function("c", "strlen", vec![FFIValue::String("neksis".to_string())]).unwrap();
+        assert!(matches!(length, FFIValue::UInt64(6)));
+        let unset = context.call_function("c", "getenv", vec![FFIValue::String("NEKSIS_FFI_UNSET".to_string())]).unwrap();
+        assert!(matches!(unset, FFIValue::Pointer(pointer) if pointer.is_null()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_extern_blocks_in_programs() {
+        let source = r#"
+extern "C" lib "libm.so.6" {
+    fn cos(x: Float) -> Float;
+    fn pow(x: f64, y: f64) -> f64;
+}
+extern "C" lib "libc.so.6" {
+    struct Div { quot: i32, rem: i32 }
+    fn abs(n: i32) -> i32;
+    fn atoi(s: String) -> i32;
+    fn div(a: i32, b: i32) -> Div;
+    fn qsort(items: [Int], count: u64, size: u64, compare: fn(*i64, *i64) -> i32);
+}
+
+fn descending(a: Int, b: Int) -> Int {
+    return b - a;
+}
+
+fn broken(a: Int, b: Int) -> Int {
+    return missing(a);
+}
+
+fn main() {
+    println(cos(0));
+    println(pow(2, 10));
+    println(abs(0 - 7) + atoi("35"));
+    let d = div(17, 5);
+    println(dict_get(d, "quot") + " r " + dict_get(d, "rem"));
+    let items = [5, 3, 9, 1, 7];
+    qsort(items, len(items), 8, descending);
+    println(items);
+    try {
+        qsort(items, len(items), 8, broken);
+    } catch (e) {
+        println("caught: " + e);
+    }
+    try {
+        abs(4294967296);
+    } catch (e) {
+        println("caught: " + e);
+    }
+}
+"#;
+        // Under -O2 the extern names must still win over the builtins `cos`, `pow` and `abs`
+        for level in [0, 2] {
+            let output = run_program_at(source, "test.nx", level);
+            assert_eq!(output.exit_code, 0, "{}", output.render());
+            assert_eq!(
+                output.stdout,
+                "1\n1024\n42\n3 r 2\n[9, 7, 5, 3, 1]\ncaught: Unknown built-in function: missing\n\
+                 caught: abs: argument 1 (n): 4294967296 does not fit in i32\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_extern_parse_errors() {
+        let parse = |source: &str| {
+            let tokens = crate::lexer::Lexer::new(source, "test.nx".to_string()).tokenize().unwrap();
+            crate::parser::Parser::new(tokens).parse().unwrap_err().to_string()
+        };
+        assert!(parse("extern \"stdcall\" lib \"x\" { }").contains("only \"C\" is supported"));
+        assert!(parse("extern \"C\" lib \"x\" { fn f(a: Widget); }").contains("Unknown C type 'Widget'"));
+    }
+
+    #[test]
+    fn test_python_modules() {
+        let source = r#"
+import python "math";
+import python "os.path";
+import python "json" as js;
+
+fn hypotenuse(a: Float, b: Float) -> Float {
+    return math.sqrt(a * a + b * b);
+}
+
+println(hypotenuse(3.0, 4.0));
+println(math.factorial(20));
+println(math.pi > 3.14);
+println(os.path.join("usr", "lib"));
+println(js.loads("[1, 2.5, true, null, [\"x\"]]"));
+let settings = dict_new();
+dict_set(settings, "depth", [1, 2]);
+dict_set(settings, "name", null);
+println(js.dumps(settings, sort_keys=true));
+try {
+    math.sqrt(-1);
+} catch (e) {
+    println("caught: " + e);
+}
+try {
+    println(math.missing);
+} catch (e) {
+    println("caught: " + e);
+}
+"#;
+        for level in [0, 2] {
+            let output = run_program_at(source, "test.nx", level);
+            assert_eq!(output.exit_code, 0, "{}", output.render());
+            assert_eq!(
+                output.stdout,
+                "5\n2432902008176640000\ntrue\nusr/lib\n[1, 2.5, true, null, [x]]\n{\"depth\": [1, 2], \"name\": null}\n\
+                 caught: ValueError: math domain error\n\
+                 caught: AttributeError: module 'math' has no attribute 'missing'\n"
+            );
+        }
+
+        let output = run_program_at("import python \"no_such_module\";\n", "test.nx", 0);
+        assert!(output.stderr.contains("ModuleNotFoundError: No module named 'no_such_module'"), "{}", output.render());
+    }
+
+    #[test]
+    fn test_python_value_conversions() {
+        let mut collector = CycleCollector::new();
+        let fields = HashMap::from([
+            ("items".to_string(), VMValue::array(vec![VMValue::Int(1), VMValue::Float(0.5), VMValue::Null])),
+            ("flag".to_string(), VMValue::Bool(true)),
+            ("name".to_string(), VMValue::String("neksis".to_string())),
+        ]);
+        let value = VMValue::object(fields);
+        Python::with_gil(|py| {
+            let object = python_object(py, &value).unwrap();
+            let object = object.as_ref(py);
+            assert_eq!(object.get_type().name().unwrap(), "dict");
+            assert_eq!(object.get_item("flag").unwrap().get_type().name().unwrap(), "bool");
+            let VMValue::Object(back) = neksis_value(object, &mut collector).unwrap() else {
+                panic!("a dict should become an object");
+            };
+            assert_eq!(back.borrow().len(), 3);
+            assert_eq!(back.borrow()["items"].to_string(), "[1, 0.5, null]");
+            assert_eq!(back.borrow()["flag"].to_string(), "true");
+            assert_eq!(back.borrow()["name"].to_string(), "neksis");
+
+            let tuple = py.eval("(True, 2 ** 40, 'x')", None, None).unwrap();
+            assert_eq!(neksis_value(tuple, &mut collector).unwrap().to_string(), "[true, 1099511627776, x]");
+            let error = neksis_value(py.eval("2 ** 70", None, None).unwrap(), &mut collector).unwrap_err();
+            assert_eq!(error, "an integer too large for Int");
+            let error = neksis_value(py.eval("{1: 'one'}", None, None).unwrap(), &mut collector).unwrap_err();
+            assert_eq!(error, "a dict with the non-string key 1");
+            let error = neksis_value(py.eval("1j", None, None).unwrap(), &mut collector).unwrap_err();
+            assert_eq!(error, "a Python complex, which has no Neksis equivalent");
+            let looped = py.eval("(lambda l: (l.append(l), l)[1])([])", None, None).unwrap();
+            assert_eq!(neksis_value(looped, &mut collector).unwrap_err(), "a container that contains itself");
+        });
+
+        let looped = VMValue::array(Vec::new());
+        if let VMValue::Array(items) = &looped {
+            items.borrow_mut().push(looped.clone());
+        }
+        let error = Python::with_gil(|py| python_object(py, &looped).map(|_| ()).unwrap_err());
+        assert_eq!(error, "an array that contains itself cannot be passed to Python");
+        if let VMValue::Array(items) = &looped {
+            items.borrow_mut().clear();
+        }
+    }
+}
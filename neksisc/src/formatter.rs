@@ -1,8 +1,47 @@
-use crate::ast::{Program, Statement, Expression, FunctionStatement, LetStatement, ReturnStatement};
+// Source formatter
+//
+// Formatting works on the source's own tokens rather than on the AST, so
+// comments, blank lines and every construct the parser accepts survive: only
+// the whitespace between tokens is rewritten. Line breaks the author chose are
+// kept, statements and blocks get lines of their own, indentation follows
+// nesting, and lines longer than the configured width are split at commas.
+
+use std::path::Path;
+use serde::{Deserialize, Serialize};
 use crate::lexer::Lexer;
 use crate::parser::Parser;
 use crate::error::CompilerError;
-// Removed unused import
+use crate::package_manager::PackageManifest;
+
+/// The `format` section of `nexus.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FormatConfig {
+    /// Spaces per indentation level
+    pub indent_size: usize,
+    /// Lines longer than this are split at commas where possible
+    pub line_width: usize,
+    /// Indent with tabs instead of spaces
+    pub use_tabs: bool,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        Self {
+            indent_size: 4,
+            line_width: 100,
+            use_tabs: false,
+        }
+    }
+}
+
+impl FormatConfig {
+    /// The `format` section of `dir/nexus.json`, or the defaults when there is none
+    pub fn load(dir: &Path) -> Result<Self, CompilerError> {
+        let manifest = PackageManifest::load(dir)?;
+        Ok(manifest.and_then(|manifest| manifest.format).unwrap_or_default())
+    }
+}
 
 pub struct CodeFormatter {
     indent_size: usize,
@@ -19,6 +58,13 @@ impl CodeFormatter {
         }
     }
 
+    pub fn from_config(config: &FormatConfig) -> Self {
+        Self::new()
+            .with_indent_size(config.indent_size)
+            .with_max_line_length(config.line_width)
+            .with_use_spaces(!config.use_tabs)
+    }
+
     pub fn with_indent_size(mut self, size: usize) -> Self {
         self.indent_size = size;
         self
@@ -35,204 +81,523 @@ impl CodeFormatter {
     }
 
     pub fn format_source(&self, source: &str) -> Result<String, CompilerError> {
-        // Parse the source code
-        let mut lexer = Lexer::new(source, "format".to_string());
-        let tokens = lexer.tokenize()?;
-        let mut parser = Parser::new(tokens);
-        let ast = parser.parse()?;
-
-        // Format the AST
-        let mut formatted = String::new();
-        self.format_program(&ast, &mut formatted, 0)?;
-        
+        // Only well-formed programs are formatted
+        let original_tokens = Lexer::new(source, "format".to_string()).tokenize()?;
+        Parser::new(original_tokens.clone()).parse()?;
+
+        let pieces = scan(source);
+        let mut lines = Vec::new();
+        for line in layout(&pieces) {
+            self.fit_line(line, &mut lines);
+        }
+        let formatted = self.render(&lines);
+
+        // Formatting must never change what the program means
+        let formatted_tokens = Lexer::new(&formatted, "format".to_string()).tokenize()?;
+        let same_tokens = original_tokens.len() == formatted_tokens.len()
+            && original_tokens.iter().zip(&formatted_tokens).all(|(a, b)| a.token == b.token);
+        if !same_tokens {
+            return Err(CompilerError::internal_error("Formatting changed the program's tokens"));
+        }
         Ok(formatted)
     }
 
-    fn format_program(&self, program: &Program, output: &mut String, indent: usize) -> Result<(), CompilerError> {
-        for (i, statement) in program.statements.iter().enumerate() {
-            if i > 0 {
-                output.push('\n');
+    /// Push `line`, split at the commas of its first bracketed group while it is too wide
+    fn fit_line(&self, line: Line, lines: &mut Vec<Line>) {
+        if self.width(&line) <= self.max_line_length {
+            lines.push(line);
+            return;
+        }
+        let Some((open, close)) = splittable_group(&line.items) else {
+            lines.push(line);
+            return;
+        };
+
+        let Line { indent, blank_before, mut items } = line;
+        let tail = items.split_off(close);
+        let inner = items.split_off(open + 1);
+        self.fit_line(Line { indent, blank_before, items }, lines);
+
+        let mut depth = 0usize;
+        let mut segment = Vec::new();
+        for item in inner {
+            match item.text.as_str() {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            let ends_segment = depth == 0 && item.text == ",";
+            segment.push(item);
+            if ends_segment {
+                self.fit_line(Line::new(indent + 1, std::mem::take(&mut segment)), lines);
             }
-            self.format_statement(statement, output, indent)?;
         }
-        Ok(())
-    }
-
-    fn format_statement(&self, statement: &Statement, output: &mut String, indent: usize) -> Result<(), CompilerError> {
-        match statement {
-            Statement::Function(func) => self.format_function(func, output, indent)?,
-            Statement::Let(let_stmt) => self.format_let_statement(let_stmt, output, indent)?,
-            Statement::Return(return_stmt) => self.format_return_statement(return_stmt, output, indent)?,
-            Statement::Expression(expr) => {
-                self.add_indent(output, indent);
-                self.format_expression(expr, output)?;
-                output.push(';');
+        if !segment.is_empty() {
+            self.fit_line(Line::new(indent + 1, segment), lines);
+        }
+        self.fit_line(Line::new(indent, tail), lines);
+    }
+
+    fn width(&self, line: &Line) -> usize {
+        let text: usize = line.items.iter()
+            .enumerate()
+            .map(|(i, item)| item.text.chars().count() + usize::from(i > 0 && item.space))
+            .sum();
+        line.indent * self.indent_size + text
+    }
+
+    fn render(&self, lines: &[Line]) -> String {
+        let mut output = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 && line.blank_before {
+                output.push('\n');
+            }
+            self.add_indent(&mut output, line.indent);
+            for (j, item) in line.items.iter().enumerate() {
+                if j > 0 && item.space {
+                    output.push(' ');
+                }
+                output.push_str(&item.text);
             }
-            _ => {
-                // For other statement types, add a placeholder
-                self.add_indent(output, indent);
-                output.push_str("// TODO: Format this statement type");
+            output.push('\n');
+        }
+        output
+    }
+
+    fn add_indent(&self, output: &mut String, indent: usize) {
+        let indent_str = if self.use_spaces {
+            " ".repeat(indent * self.indent_size)
+        } else {
+            "\t".repeat(indent)
+        };
+        output.push_str(&indent_str);
+    }
+}
+
+impl Default for CodeFormatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Word,
+    Number,
+    Text,
+    Punct,
+    Comment,
+}
+
+/// A token or comment together with the whitespace that preceded it
+#[derive(Debug, Clone)]
+struct Piece {
+    kind: Kind,
+    text: String,
+    /// Line breaks between this piece and the previous one
+    newlines: usize,
+    /// Whether any whitespace preceded it
+    spaced: bool,
+    /// Whether this `<` or `>` delimits generic arguments rather than comparing
+    generic: bool,
+}
+
+impl Piece {
+    fn is(&self, text: &str) -> bool {
+        self.kind == Kind::Punct && self.text == text
+    }
+
+    fn is_open(&self) -> bool {
+        self.is("(") || self.is("[") || self.is("{")
+    }
+
+    fn is_close(&self) -> bool {
+        self.is(")") || self.is("]") || self.is("}")
+    }
+
+    fn is_keyword(&self, keywords: &[&str]) -> bool {
+        self.kind == Kind::Word && keywords.contains(&self.text.as_str())
+    }
+}
+
+/// Operators that read the same as the lexer's multi-character tokens
+const LONG_PUNCTUATION: [&str; 12] = ["::", "..", "->", "*.", "==", "=>", "!=", "<=", ">=", "&&", "||", "|>"];
+
+/// Operators written with a space on either side
+const BINARY_OPERATORS: [&str; 18] = [
+    "=", "==", "!=", "<", ">", "<=", ">=", "+", "-", "*", "/", "%", "&&", "||", "->", "=>", "|>", "*.",
+];
+
+/// Keywords followed by a space before `(`, and after which `-`, `!` and `&` are prefix operators
+const KEYWORDS: [&str; 13] = [
+    "if", "while", "for", "match", "return", "in", "and", "or", "not", "else", "throw", "catch", "let",
+];
+
+/// Split `source` into tokens and comments, keeping each one's exact text
+fn scan(source: &str) -> Vec<Piece> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut pieces = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let mut newlines = 0;
+        let mut spaced = false;
+        while pos < chars.len() && chars[pos].is_whitespace() {
+            if chars[pos] == '\n' {
+                newlines += 1;
             }
+            spaced = true;
+            pos += 1;
+        }
+        if pos >= chars.len() {
+            break;
         }
-        Ok(())
-    }
-
-    fn format_function(&self, func: &FunctionStatement, output: &mut String, indent: usize) -> Result<(), CompilerError> {
-        self.add_indent(output, indent);
-        output.push_str("fn ");
-        output.push_str(&func.name);
-        output.push('(');
-        
-        // Format parameters
-        for (i, param) in func.parameters.iter().enumerate() {
-            if i > 0 {
-                output.push_str(", ");
+
+        let start = pos;
+        let c = chars[pos];
+        let next = chars.get(pos + 1).copied();
+        let kind = if (c == '/' && next == Some('/')) || c == '#' {
+            while pos < chars.len() && chars[pos] != '\n' {
+                pos += 1;
+            }
+            Kind::Comment
+        } else if c == '"' {
+            pos = scan_string(&chars, pos + 1);
+            Kind::Text
+        } else if c == '\'' {
+            pos += 1;
+            if chars.get(pos) == Some(&'\\') {
+                pos += 1;
+            }
+            pos = (pos + 2).min(chars.len());
+            Kind::Text
+        } else if c.is_alphabetic() || c == '_' {
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
             }
-            output.push_str(&param.name);
-            output.push_str(": ");
-            self.format_type(&param.type_annotation, output)?;
+            Kind::Word
+        } else if c.is_ascii_digit() {
+            pos = scan_number(&chars, pos + 1);
+            Kind::Number
+        } else {
+            let rest: String = chars[pos..chars.len().min(pos + 3)].iter().collect();
+            pos += if rest.starts_with("...") {
+                3
+            } else if LONG_PUNCTUATION.iter().any(|op| rest.starts_with(op)) {
+                2
+            } else {
+                1
+            };
+            Kind::Punct
+        };
+
+        let text: String = chars[start..pos].iter().collect();
+        let text = if kind == Kind::Comment { text.trim_end().to_string() } else { text };
+        pieces.push(Piece { kind, text, newlines, spaced, generic: false });
+    }
+
+    mark_generics(&mut pieces);
+    pieces
+}
+
+/// Mark the angle brackets of generic types such as `Array<Map<String, Int>>`
+fn mark_generics(pieces: &mut [Piece]) {
+    for start in 1..pieces.len() {
+        let names_type = pieces[start - 1].kind == Kind::Word
+            && pieces[start - 1].text.starts_with(|c: char| c.is_uppercase());
+        if !pieces[start].is("<") || !names_type {
+            continue;
         }
-        output.push(')');
-        
-        // Format return type
-        if let Some(return_type) = &func.return_type {
-            output.push_str(" -> ");
-            self.format_type(return_type, output)?;
+        let mut depth = 0;
+        for end in start..pieces.len() {
+            let piece = &pieces[end];
+            if piece.is("<") {
+                depth += 1;
+            } else if piece.is(">") {
+                depth -= 1;
+                if depth == 0 {
+                    for piece in &mut pieces[start..=end] {
+                        piece.generic = piece.is("<") || piece.is(">");
+                    }
+                    break;
+                }
+            } else if piece.kind != Kind::Word && ![",", "::", "[", "]", "&"].iter().any(|t| piece.is(t)) {
+                break;
+            }
         }
-        
-        output.push_str(" {\n");
-        
-        // Format function body
-        if let Expression::Block(statements) = &*func.body {
-            for statement in statements {
-                self.format_statement(statement, output, indent + 1)?;
-                output.push('\n');
+    }
+}
+
+/// The position just past a string literal whose opening quote is before `pos`
+fn scan_string(chars: &[char], mut pos: usize) -> usize {
+    while pos < chars.len() {
+        match chars[pos] {
+            '"' => return pos + 1,
+            '\\' => pos += 2,
+            '{' => {
+                // Interpolations run to the matching brace, quotes included
+                let mut depth = 0;
+                while pos < chars.len() {
+                    match chars[pos] {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    pos += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
             }
+            _ => pos += 1,
         }
-        
-        self.add_indent(output, indent);
-        output.push('}');
-        Ok(())
-    }
-
-    fn format_let_statement(&self, let_stmt: &LetStatement, output: &mut String, indent: usize) -> Result<(), CompilerError> {
-        self.add_indent(output, indent);
-        output.push_str("let ");
-        output.push_str(&let_stmt.name);
-        
-        if let Some(type_annotation) = &let_stmt.type_annotation {
-            output.push_str(": ");
-            self.format_type(type_annotation, output)?;
+    }
+    chars.len()
+}
+
+/// The position just past a number literal, following the lexer's rules
+fn scan_number(chars: &[char], mut pos: usize) -> usize {
+    while pos < chars.len() {
+        match chars[pos] {
+            '0'..='9' | '_' => pos += 1,
+            '.' if chars.get(pos + 1).is_some_and(|c| c.is_ascii_digit()) => pos += 1,
+            'e' | 'E' => {
+                pos += 1;
+                if matches!(chars.get(pos), Some('+') | Some('-')) {
+                    pos += 1;
+                }
+            }
+            _ => break,
         }
-        
-        output.push_str(" = ");
-        self.format_expression(&let_stmt.value, output)?;
-        output.push(';');
-        Ok(())
-    }
-
-    fn format_return_statement(&self, return_stmt: &ReturnStatement, output: &mut String, indent: usize) -> Result<(), CompilerError> {
-        self.add_indent(output, indent);
-        output.push_str("return");
-        
-        if let Some(value) = &return_stmt.value {
-            output.push(' ');
-            self.format_expression(value, output)?;
+    }
+    pos
+}
+
+#[derive(Debug, Clone)]
+struct Item {
+    text: String,
+    /// Whether a space separates this item from the previous one on the line
+    space: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    indent: usize,
+    blank_before: bool,
+    items: Vec<Item>,
+}
+
+impl Line {
+    fn new(indent: usize, mut items: Vec<Item>) -> Self {
+        if let Some(first) = items.first_mut() {
+            first.space = false;
         }
-        
-        output.push(';');
-        Ok(())
-    }
-
-    fn format_expression(&self, expression: &Expression, output: &mut String) -> Result<(), CompilerError> {
-        match expression {
-            Expression::Literal(literal) => self.format_literal(literal, output)?,
-            Expression::Identifier(name) => output.push_str(name),
-            Expression::BinaryOp(binary_op) => {
-                self.format_expression(&binary_op.left, output)?;
-                output.push(' ');
-                output.push_str(&self.format_operator(&binary_op.operator));
-                output.push(' ');
-                self.format_expression(&binary_op.right, output)?;
+        Self { indent, blank_before: false, items }
+    }
+}
+
+/// The first group on the line that opens and closes on it and has a comma at its top level
+fn splittable_group(items: &[Item]) -> Option<(usize, usize)> {
+    let mut stack = Vec::new();
+    let mut best: Option<(usize, usize)> = None;
+    let mut commas = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        match item.text.as_str() {
+            "(" | "[" | "{" => {
+                stack.push(i);
+                commas.push(false);
             }
-            Expression::FunctionCall(callee, args) => {
-                self.format_expression(callee, output)?;
-                output.push('(');
-                for (i, arg) in args.iter().enumerate() {
-                    if i > 0 {
-                        output.push_str(", ");
+            ")" | "]" | "}" => {
+                if let (Some(open), Some(has_comma)) = (stack.pop(), commas.pop()) {
+                    if has_comma && i > open + 1 && best.is_none_or(|(b, _)| open < b) {
+                        best = Some((open, i));
                     }
-                    self.format_expression(&arg.value, output)?;
                 }
-                output.push(')');
             }
-            Expression::Block(statements) => {
-                output.push_str("{\n");
-                for statement in statements {
-                    self.format_statement(statement, output, 1)?;
-                    output.push('\n');
+            "," => {
+                if let Some(has_comma) = commas.last_mut() {
+                    *has_comma = true;
                 }
-                output.push('}');
-            }
-            _ => {
-                // For other expression types, add a placeholder
-                output.push_str("/* TODO: Format this expression type */");
             }
+            _ => {}
         }
-        Ok(())
     }
+    best
+}
 
-    fn format_literal(&self, literal: &crate::ast::Literal, output: &mut String) -> Result<(), CompilerError> {
-        match literal {
-            crate::ast::Literal::Int(value) => output.push_str(&value.to_string()),
-            crate::ast::Literal::Float(value) => output.push_str(&value.to_string()),
-            crate::ast::Literal::String(value) => {
-                output.push('"');
-                output.push_str(value);
-                output.push('"');
+/// For each opening bracket, the index of the bracket that closes it
+fn matching_brackets(pieces: &[Piece]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; pieces.len()];
+    let mut stack = Vec::new();
+    for (i, piece) in pieces.iter().enumerate() {
+        if piece.is_open() {
+            stack.push(i);
+        } else if piece.is_close() {
+            if let Some(open) = stack.pop() {
+                matches[open] = Some(i);
+                matches[i] = Some(open);
             }
-            crate::ast::Literal::Bool(value) => output.push_str(&value.to_string()),
-            _ => output.push_str("/* TODO: Format this literal type */"),
         }
-        Ok(())
     }
+    matches
+}
 
-    fn format_type(&self, type_expr: &crate::ast::Type, output: &mut String) -> Result<(), CompilerError> {
-        match type_expr {
-            crate::ast::Type::Int => output.push_str("Int"),
-            crate::ast::Type::Float => output.push_str("Float"),
-            crate::ast::Type::String => output.push_str("String"),
-            crate::ast::Type::Bool => output.push_str("Bool"),
-            crate::ast::Type::Void => output.push_str("Void"),
-            _ => output.push_str("/* TODO: Format this type */"),
+/// Braces that stay on one line: written on one line, without statements or comments inside
+fn inline_braces(pieces: &[Piece], matches: &[Option<usize>]) -> Vec<bool> {
+    let mut inline = vec![false; pieces.len()];
+    for (i, piece) in pieces.iter().enumerate() {
+        if !piece.is("{") {
+            continue;
         }
-        Ok(())
-    }
-
-    fn format_operator(&self, op: &crate::ast::BinaryOperator) -> &str {
-        match op {
-            crate::ast::BinaryOperator::Add => "+",
-            crate::ast::BinaryOperator::Subtract => "-",
-            crate::ast::BinaryOperator::Multiply => "*",
-            crate::ast::BinaryOperator::Divide => "/",
-            crate::ast::BinaryOperator::Equal => "==",
-            crate::ast::BinaryOperator::NotEqual => "!=",
-            crate::ast::BinaryOperator::LessThan => "<",
-            crate::ast::BinaryOperator::LessThanOrEqual => "<=",
-            crate::ast::BinaryOperator::GreaterThan => ">",
-            crate::ast::BinaryOperator::GreaterThanOrEqual => ">=",
-            _ => "/* TODO: Format this operator */",
+        if let Some(close) = matches[i] {
+            let fits = pieces[i + 1..=close]
+                .iter()
+                .all(|p| p.newlines == 0 && p.kind != Kind::Comment && !p.is(";"));
+            inline[i] = fits;
+            inline[close] = fits;
         }
     }
+    inline
+}
+
+/// Whether `piece`, following `previous`, is a prefix operator
+fn is_prefix(previous: Option<&Piece>, piece: &Piece) -> bool {
+    if piece.is("!") {
+        return true;
+    }
+    if !(piece.is("-") || piece.is("&") || piece.is("*")) {
+        return false;
+    }
+    match previous {
+        None => true,
+        Some(p) if p.kind == Kind::Punct => !p.is_close(),
+        Some(p) => p.is_keyword(&KEYWORDS),
+    }
+}
 
-    fn add_indent(&self, output: &mut String, indent: usize) {
-        let indent_str = if self.use_spaces {
-            " ".repeat(indent * self.indent_size)
-        } else {
-            "\t".repeat(indent)
+/// Whether a space goes between `prev` and `cur` when they share a line
+fn needs_space(before_prev: Option<&Piece>, prev: &Piece, cur: &Piece) -> bool {
+    if cur.kind == Kind::Comment {
+        return true;
+    }
+    if [",", ";", ")", "]", ".", "::", "..", "...", "?", ":"].iter().any(|t| cur.is(t)) {
+        return false;
+    }
+    if ["(", "[", ".", "::", "..", "...", "@", "!"].iter().any(|t| prev.is(t)) {
+        return false;
+    }
+    if cur.is("}") {
+        return !prev.is("{");
+    }
+    if prev.is("{") || prev.is("}") || cur.is("{") {
+        return true;
+    }
+    if prev.is(",") || prev.is(";") || prev.is(":") {
+        return true;
+    }
+    if is_prefix(before_prev, prev) {
+        return false;
+    }
+    if (prev.generic && prev.is("<")) || cur.generic {
+        return false;
+    }
+    // Closure parameters read differently from `|` operators; keep the author's spacing
+    if prev.is("|") || cur.is("|") {
+        return cur.spaced;
+    }
+    let ends_operand = matches!(prev.kind, Kind::Word | Kind::Number | Kind::Text)
+        || prev.is_close()
+        || prev.generic;
+    if cur.is("(") || cur.is("[") {
+        return prev.is_keyword(&KEYWORDS) || !ends_operand;
+    }
+    if BINARY_OPERATORS.iter().any(|op| prev.is(op) || cur.is(op)) {
+        return true;
+    }
+    if prev.kind != Kind::Punct && cur.kind != Kind::Punct {
+        return true;
+    }
+    cur.spaced
+}
+
+/// Lay the pieces out in lines, with indentation from bracket nesting
+fn layout(pieces: &[Piece]) -> Vec<Line> {
+    let matches = matching_brackets(pieces);
+    let inline = inline_braces(pieces, &matches);
+    let is_block_open = |i: usize| pieces[i].is("{") && !inline[i];
+    let is_block_close = |i: usize| pieces[i].is("}") && !inline[i];
+
+    let mut lines: Vec<Line> = Vec::new();
+    // Open brackets, with the line each was opened on and whether it is a block
+    let mut open: Vec<(usize, bool)> = Vec::new();
+
+    for (i, piece) in pieces.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|p| &pieces[p]);
+        let starts_line = match prev {
+            None => true,
+            Some(prev) => {
+                let joins_block_end = is_block_close(i - 1)
+                    && (piece.is_keyword(&["else", "catch"])
+                        || [",", ";", ")", "]", "."].iter().any(|t| piece.is(t)));
+                let trailing_comment = piece.kind == Kind::Comment && piece.newlines == 0;
+                if joins_block_end {
+                    false
+                } else if piece.newlines > 0 || is_block_close(i) || is_block_close(i - 1) {
+                    true
+                } else if trailing_comment {
+                    false
+                } else {
+                    is_block_open(i - 1)
+                        || (prev.is(";") && open.last().is_none_or(|&(_, block)| block))
+                }
+            }
         };
-        output.push_str(&indent_str);
+
+        if starts_line {
+            // Closing brackets at the start of a line take their opener's indentation
+            let mut closers = 0;
+            while i + closers < pieces.len() && pieces[i + closers].is_close() && open.len() > closers {
+                closers += 1;
+                let next = i + closers;
+                if next >= pieces.len() || pieces[next].newlines > 0 || is_block_close(next) {
+                    break;
+                }
+            }
+            let mut indent = 0;
+            let mut last_line = None;
+            for &(line, _) in &open[..open.len() - closers] {
+                if last_line != Some(line) {
+                    indent += 1;
+                    last_line = Some(line);
+                }
+            }
+
+            let after_block_open = i > 0 && is_block_open(i - 1);
+            let blank_before = !lines.is_empty()
+                && piece.newlines > 1
+                && !after_block_open
+                && !is_block_close(i);
+            lines.push(Line { indent, blank_before, items: Vec::new() });
+        }
+
+        let line_index = lines.len() - 1;
+        let line = lines.last_mut().expect("a line was started");
+        let space = match prev {
+            Some(prev) if !line.items.is_empty() => {
+                needs_space(i.checked_sub(2).map(|p| &pieces[p]), prev, piece)
+            }
+            _ => false,
+        };
+        line.items.push(Item { text: piece.text.clone(), space });
+
+        if piece.is_open() {
+            open.push((line_index, is_block_open(i)));
+        } else if piece.is_close() {
+            open.pop();
+        }
     }
+
+    lines
 }
 
 #[cfg(test)]
@@ -244,7 +609,7 @@ mod tests {
         let formatter = CodeFormatter::new();
         let source = "fn main() { let x = 42; return x; }";
         let formatted = formatter.format_source(source).unwrap();
-        
+
         assert!(formatted.contains("fn main()"));
         assert!(formatted.contains("let x = 42;"));
         assert!(formatted.contains("return x;"));
@@ -255,7 +620,53 @@ mod tests {
         let formatter = CodeFormatter::new();
         let source = "fn add(x: Int, y: Int) -> Int { return x + y; }";
         let formatted = formatter.format_source(source).unwrap();
-        
+
         assert!(formatted.contains("fn add(x: Int, y: Int) -> Int"));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_format_keeps_comments_and_blank_lines() {
+        let formatter = CodeFormatter::new();
+        let source = "// Entry point\nfn main() {\n  let x=1;  // the answer\n\n\n\n  if (x>0) { println(x); }\n  else{ println(-x); }\n}\n";
+        let formatted = formatter.format_source(source).unwrap();
+
+        assert_eq!(
+            formatted,
+            "// Entry point\nfn main() {\n    let x = 1; // the answer\n\n    if (x > 0) {\n        println(x);\n    } else {\n        println(-x);\n    }\n}\n"
+        );
+        assert_eq!(formatter.format_source(&formatted).unwrap(), formatted);
+
+        let hashed = formatter.format_source("# Setup
+let x=1;  # the answer
+").unwrap();
+        assert_eq!(hashed, "# Setup
+let x = 1; # the answer
+");
+    }
+
+    #[test]
+    fn test_format_every_statement_kind() {
+        let formatter = CodeFormatter::new();
+        let source = "struct Point { x: Int, y: Int }\nlet total = 0; let i = 0;\nwhile (i < 3) { total = total + i; i = i + 1; }\nlet items = [1, 2, 3];\nprintln(items[0]);\n";
+        let formatted = formatter.format_source(source).unwrap();
+
+        assert_eq!(
+            formatted,
+            "struct Point { x: Int, y: Int }\nlet total = 0;\nlet i = 0;\nwhile (i < 3) {\n    total = total + i;\n    i = i + 1;\n}\nlet items = [1, 2, 3];\nprintln(items[0]);\n"
+        );
+    }
+
+    #[test]
+    fn test_format_splits_long_lines_and_uses_config() {
+        let config = FormatConfig { indent_size: 2, line_width: 30, use_tabs: false };
+        let formatter = CodeFormatter::from_config(&config);
+        let source = "fn main() { println(concat(\"first\", \"second\", \"third\")); }";
+        let formatted = formatter.format_source(source).unwrap();
+
+        assert_eq!(
+            formatted,
+            "fn main() {\n  println(concat(\n    \"first\",\n    \"second\",\n    \"third\"\n  ));\n}\n"
+        );
+        assert_eq!(formatter.format_source(&formatted).unwrap(), formatted);
+    }
+}
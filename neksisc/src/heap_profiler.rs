@@ -0,0 +1,448 @@
+// Heap Profiler for Neksis
+//
+// Connects the VM to `MemoryProfiler`: strings, arrays and objects created
+// while a program runs are recorded together with the Neksis function and
+// line that created them. Arrays and objects are keyed by the address of
+// their shared container and strings by the address of their buffer. Frees
+// are found by periodically walking everything reachable from the VM's stack
+// and variables; whatever is still reachable when the program ends is
+// reported as live at exit.
+
+use crate::memory_manager::{container_address, WeakContainer};
+use crate::memory_profiler::MemoryProfiler;
+use crate::vm::{BytecodeInstruction, CallFrame, VMValue};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+/// Reachability is checked after at least this many recorded allocations,
+/// or as many as survived the previous check if that is more. Frees are only
+/// noticed by these checks, so the peak can overshoot by up to one interval.
+const MIN_COLLECTION_INTERVAL: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HeapKind {
+    String,
+    Array,
+    Object,
+}
+
+impl HeapKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeapKind::String => "string",
+            HeapKind::Array => "array",
+            HeapKind::Object => "object",
+        }
+    }
+}
+
+/// Address, current size in bytes and kind of a value's own allocation, or
+/// `None` for scalars and empty strings, which do not allocate. Container
+/// sizes include the element storage but not the elements' own allocations.
+fn heap_identity(value: &VMValue) -> Option<(usize, usize, HeapKind)> {
+    let address = container_address(value);
+    match value {
+        VMValue::String(text) if text.capacity() > 0 => Some((text.as_ptr() as usize, text.capacity(), HeapKind::String)),
+        VMValue::Array(items) => {
+            let capacity = items.try_borrow().map_or(0, |items| items.capacity());
+            let bytes = std::mem::size_of::<RefCell<Vec<VMValue>>>() + capacity * std::mem::size_of::<VMValue>();
+            address.map(|address| (address, bytes, HeapKind::Array))
+        }
+        VMValue::Object(fields) => {
+            let capacity = fields.try_borrow().map_or(0, |fields| fields.capacity());
+            let bucket = std::mem::size_of::<String>() + std::mem::size_of::<VMValue>() + 1;
+            let bytes = std::mem::size_of::<RefCell<HashMap<String, VMValue>>>() + capacity * bucket;
+            address.map(|address| (address, bytes, HeapKind::Object))
+        }
+        _ => None,
+    }
+}
+
+fn for_each_child(value: &VMValue, visit: &mut dyn FnMut(&VMValue)) {
+    match value {
+        VMValue::Array(items) => {
+            if let Ok(items) = items.try_borrow() {
+                items.iter().for_each(visit);
+            }
+        }
+        VMValue::Object(fields) => {
+            if let Ok(fields) = fields.try_borrow() {
+                fields.values().for_each(visit);
+            }
+        }
+        VMValue::Function(_, captured) => captured.iter().for_each(visit),
+        _ => {}
+    }
+}
+
+/// Instructions whose result is always a newly allocated value (or a scalar).
+/// For anything else a value only counts as new if it is not known yet.
+pub fn always_allocates(instruction: &BytecodeInstruction) -> bool {
+    use BytecodeInstruction::*;
+    matches!(
+        instruction,
+        PushString(_) | Load(_) | LoadGlobal(_) | Dup | Add | StringConcat | Substring | ToString
+            | StringToUpper | StringToLower | StringTrim | StringSplit | StringJoin | GetIndex
+            | GetProperty(_) | DictGet | DictKeys | NewArray | ArraySlice | ReadLine | ReadFile
+            | JsonParse | JsonStringify | TypeOf
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteStats {
+    /// `function:line`, or just the function when line markers are missing
+    pub site: String,
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveValue {
+    pub kind: HeapKind,
+    pub bytes: usize,
+    pub site: String,
+    pub preview: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeapReport {
+    pub total_allocations: usize,
+    pub total_bytes: usize,
+    pub peak_bytes: usize,
+    /// Sorted by bytes allocated, largest first
+    pub sites: Vec<SiteStats>,
+    /// Values reachable when the program ended, largest first
+    pub live: Vec<LiveValue>,
+}
+
+impl HeapReport {
+    pub fn live_bytes(&self) -> usize {
+        self.live.iter().map(|value| value.bytes).sum()
+    }
+
+    /// Peak usage, the `limit` busiest allocation sites and the values live at exit
+    pub fn summary(&self, limit: usize) -> String {
+        let mut out = format!(
+            "Peak usage:   {}\nAllocations:  {} ({} total)\nLive at exit: {} values ({})\n",
+            format_bytes(self.peak_bytes),
+            self.total_allocations,
+            format_bytes(self.total_bytes),
+            self.live.len(),
+            format_bytes(self.live_bytes())
+        );
+
+        let width = self.sites.iter().take(limit).map(|site| site.site.len()).max().unwrap_or(0).max("Site".len()) + 2;
+        out.push_str(&format!("\nTop allocation sites:\n{:<width$}{:>12}{:>12}\n", "Site", "Allocations", "Bytes", width = width));
+        for site in self.sites.iter().take(limit) {
+            out.push_str(&format!(
+                "{:<width$}{:>12}{:>12}\n",
+                site.site,
+                site.allocations,
+                format_bytes(site.bytes),
+                width = width
+            ));
+        }
+
+        if !self.live.is_empty() {
+            out.push_str("\nLive at exit:\n");
+            for value in self.live.iter().take(limit) {
+                out.push_str(&format!(
+                    "  {:<8}{:>10}  {:<width$}{}\n",
+                    value.kind.name(),
+                    format_bytes(value.bytes),
+                    value.site,
+                    value.preview,
+                    width = width
+                ));
+            }
+        }
+        out
+    }
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+struct Tracked {
+    kind: HeapKind,
+    /// Lets a container's address be recognised as reused once it was freed
+    container: Option<WeakContainer>,
+}
+
+pub struct HeapProfiler {
+    memory: MemoryProfiler,
+    /// Index into `site_names` for every instruction
+    site_of_ip: Vec<usize>,
+    site_names: Vec<String>,
+    /// Allocations currently believed to be live
+    tracked: HashMap<usize, Tracked>,
+    totals: HashMap<usize, SiteStats>,
+    since_collection: usize,
+    collection_interval: usize,
+}
+
+impl HeapProfiler {
+    pub fn new() -> Self {
+        Self {
+            memory: MemoryProfiler::new(),
+            site_of_ip: Vec::new(),
+            site_names: Vec::new(),
+            tracked: HashMap::new(),
+            totals: HashMap::new(),
+            since_collection: 0,
+            collection_interval: MIN_COLLECTION_INTERVAL,
+        }
+    }
+
+    /// Work out the function and source line of every instruction. Lines are
+    /// only known when the program was parsed with line markers.
+    pub fn index_sites(&mut self, instructions: &[BytecodeInstruction]) {
+        let mut names: HashMap<String, usize> = HashMap::new();
+        let mut site_names = Vec::new();
+        let mut intern = |name: String| {
+            *names.entry(name.clone()).or_insert_with(|| {
+                site_names.push(name);
+                site_names.len() - 1
+            })
+        };
+
+        let mut function = crate::profiler::ROOT_FRAME.to_string();
+        let mut line = 0;
+        let mut site_of_ip = Vec::with_capacity(instructions.len());
+        for instruction in instructions {
+            match instruction {
+                BytecodeInstruction::DefineFunction(name, _) => {
+                    function = name.clone();
+                    line = 0;
+                }
+                BytecodeInstruction::Line(number) => line = *number,
+                _ => {}
+            }
+            let site = if line > 0 { format!("{}:{}", function, line) } else { function.clone() };
+            site_of_ip.push(intern(site));
+            if let BytecodeInstruction::EndFunction = instruction {
+                function = crate::profiler::ROOT_FRAME.to_string();
+                line = 0;
+            }
+        }
+
+        self.site_of_ip = site_of_ip;
+        self.site_names = site_names;
+    }
+
+    fn site_at(&self, ip: usize) -> usize {
+        self.site_of_ip.get(ip).copied().unwrap_or(0)
+    }
+
+    fn site_name(&self, site: usize) -> String {
+        self.site_names.get(site).cloned().unwrap_or_else(|| crate::profiler::ROOT_FRAME.to_string())
+    }
+
+    /// Record `value`, the result left on the stack by the instruction at
+    /// `ip`, and anything newly allocated inside it
+    pub(crate) fn record_result(&mut self, ip: usize, fresh: bool, value: &VMValue, call_stack: &[CallFrame]) {
+        let Some((address, bytes, kind)) = heap_identity(value) else { return };
+        if let Some(tracked) = self.tracked.get(&address) {
+            let reused = match &tracked.container {
+                // Containers are shared rather than copied, so a live one is the same container
+                Some(container) => !container.is_alive(),
+                // A known string that was moved, not allocated, unless the instruction always allocates
+                None => fresh,
+            };
+            if !reused {
+                return;
+            }
+            self.release(address);
+        }
+
+        let site = self.site_at(ip);
+        // Outermost caller first, ending with the allocating site
+        let mut stack_trace: Vec<String> = call_stack
+            .iter()
+            .map(|frame| self.site_name(self.site_at(frame.return_ip.saturating_sub(1))))
+            .collect();
+        stack_trace.push(self.site_name(site));
+        self.memory.record_allocation_with_trace(address, bytes, &self.site_name(site), stack_trace);
+        self.tracked.insert(address, Tracked { kind, container: WeakContainer::from_value(value) });
+        let name = self.site_name(site);
+        let totals = self.totals.entry(site).or_insert(SiteStats { site: name, allocations: 0, bytes: 0 });
+        totals.allocations += 1;
+        totals.bytes += bytes;
+        self.since_collection += 1;
+
+        // Elements of a new container may not have been seen yet
+        for_each_child(value, &mut |child| self.record_result(ip, false, child, call_stack));
+    }
+
+    fn release(&mut self, address: usize) {
+        self.tracked.remove(&address);
+        self.memory.record_deallocation(address);
+    }
+
+    pub fn wants_collection(&self) -> bool {
+        self.since_collection >= self.collection_interval
+    }
+
+    /// Release every tracked buffer that is no longer reachable from `roots`
+    pub fn collect<'a>(&mut self, roots: impl Iterator<Item = &'a VMValue>) {
+        let mut live = HashSet::new();
+        reachable(roots, &mut |address, _, _| {
+            live.insert(address);
+        });
+        let dead: Vec<usize> = self.tracked.keys().filter(|address| !live.contains(*address)).copied().collect();
+        for address in dead {
+            self.release(address);
+        }
+        self.since_collection = 0;
+        self.collection_interval = self.tracked.len().max(MIN_COLLECTION_INTERVAL);
+    }
+
+    /// Final collection and report once the program has finished
+    pub fn finish<'a>(mut self, roots: impl Iterator<Item = &'a VMValue>) -> HeapReport {
+        let roots: Vec<&VMValue> = roots.collect();
+        self.collect(roots.iter().copied());
+        // Size at exit and a preview of every live value
+        let mut live_values = HashMap::new();
+        reachable(roots.into_iter(), &mut |address, bytes, value| {
+            live_values.insert(address, (bytes, preview(value)));
+        });
+
+        let stats = self.memory.get_stats();
+        let mut live: Vec<LiveValue> = self
+            .memory
+            .detect_memory_leaks()
+            .into_iter()
+            .filter_map(|record| {
+                let tracked = self.tracked.get(&record.address)?;
+                let (bytes, preview) = live_values.remove(&record.address).unwrap_or((record.size, String::new()));
+                Some(LiveValue { kind: tracked.kind, bytes, site: record.allocation_site, preview })
+            })
+            .collect();
+        live.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.site.cmp(&b.site)));
+
+        let mut sites: Vec<SiteStats> = self.totals.into_values().collect();
+        sites.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.site.cmp(&b.site)));
+
+        HeapReport {
+            total_allocations: stats.allocation_count,
+            total_bytes: stats.allocated_bytes,
+            // Containers grow after they are first seen, so the live sizes at exit can exceed the recorded peak
+            peak_bytes: stats.peak_allocated_bytes.max(live.iter().map(|value| value.bytes).sum()),
+            sites,
+            live,
+        }
+    }
+}
+
+impl Default for HeapProfiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Call `visit` with the address, size and value of every allocation
+// reachable from `roots`, once each
+fn reachable<'a>(roots: impl Iterator<Item = &'a VMValue>, visit: &mut dyn FnMut(usize, usize, &VMValue)) {
+    fn walk(value: &VMValue, seen: &mut HashSet<usize>, visit: &mut dyn FnMut(usize, usize, &VMValue)) {
+        if let Some((address, bytes, _)) = heap_identity(value) {
+            if !seen.insert(address) {
+                return;
+            }
+            visit(address, bytes, value);
+        }
+        for_each_child(value, &mut |child| walk(child, seen, visit));
+    }
+    let mut seen = HashSet::new();
+    for root in roots {
+        walk(root, &mut seen, visit);
+    }
+}
+
+/// Bytes of every allocation reachable from `roots`, sized as in the heap
+/// report, and the number of values visited to find them
+pub(crate) fn measure<'a>(roots: impl Iterator<Item = &'a VMValue>) -> (usize, usize) {
+    let mut values = 0;
+    let mut seen = HashSet::new();
+    let mut bytes = 0;
+    fn walk(value: &VMValue, values: &mut usize, seen: &mut HashSet<usize>, bytes: &mut usize) {
+        *values += 1;
+        if let Some((address, size, _)) = heap_identity(value) {
+            if !seen.insert(address) {
+                return;
+            }
+            *bytes += size;
+        }
+        for_each_child(value, &mut |child| walk(child, values, seen, bytes));
+    }
+    for root in roots {
+        walk(root, &mut values, &mut seen, &mut bytes);
+    }
+    (bytes, values)
+}
+
+/// Size of `value`'s own allocation, as `measure` counts it
+pub(crate) fn allocation_size(value: &VMValue) -> usize {
+    heap_identity(value).map_or(0, |(_, bytes, _)| bytes)
+}
+
+fn preview(value: &VMValue) -> String {
+    let text = match value {
+        VMValue::String(text) => format!("{:?}", text),
+        other => other.to_string(),
+    };
+    if text.chars().count() > 40 {
+        format!("{}...", text.chars().take(37).collect::<String>())
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode_compiler::BytecodeCompiler;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::vm::{OutputCapture, VM};
+
+    fn profile(source: &str) -> HeapReport {
+        let tokens = Lexer::new(source, "heap.nx".to_string()).tokenize().unwrap();
+        let program = Parser::new(tokens).with_line_markers().parse().unwrap();
+        let instructions = BytecodeCompiler::new().compile_module(&program).unwrap();
+
+        let mut vm = VM::new();
+        vm.set_output_capture(OutputCapture::new());
+        vm.enable_heap_profiling();
+        vm.load_instructions(instructions);
+        vm.run().unwrap();
+        vm.take_heap_profile().unwrap()
+    }
+
+    #[test]
+    fn test_sites_and_live_values() {
+        let report = profile(
+            "fn greeting(name: String) -> String {\n    return \"hello \" + name;\n}\n\nlet kept = \"kept \" + \"value\";\nlet i = 0;\nwhile (i < 10) {\n    greeting(\"world\");\n    i = i + 1;\n}\n",
+        );
+
+        let site = |name: &str| report.sites.iter().find(|site| site.site == name).cloned();
+        // Each call allocates the literal, a copy of `name` and the concatenation
+        assert_eq!(site("greeting:2").map(|site| site.allocations), Some(30));
+        assert_eq!(site("<module>:8").map(|site| site.allocations), Some(10));
+
+        // Only the string bound to `kept` survives; the greetings were discarded
+        // Calls no longer copy the caller's variables, so it keeps the site that created it
+        assert_eq!(report.live.len(), 1);
+        assert_eq!(report.live[0].kind, HeapKind::String);
+        assert_eq!(report.live[0].site, "<module>:5");
+        assert_eq!(report.live[0].preview, "\"kept value\"");
+        assert!(report.peak_bytes >= report.live_bytes());
+        assert!(report.summary(5).contains("greeting:2"));
+    }
+}
@@ -0,0 +1,276 @@
+// IR to bytecode
+//
+// A temp that is used once, later in the block that defines it, stays on
+// the VM stack between its definition and its use, which is what the
+// `BytecodeCompiler` produces for expression trees. Every other temp is kept
+// in a hidden local (`%tN`), except constants, which are pushed again where
+// they are used. Blocks are laid out so that jumps fall through where they can.
+
+use super::{BlockId, Const, Function, Module, Op, Temp, Terminator, Unit};
+use crate::vm::BytecodeInstruction;
+use std::collections::{HashMap, HashSet};
+
+pub fn generate(module: &Module) -> Vec<BytecodeInstruction> {
+    let mut output = Vec::new();
+    for unit in module.functions.iter().chain(std::iter::once(&module.top_level)) {
+        let code = match unit {
+            Unit::Ir(function) => generate_function(function),
+            Unit::Bytecode(instructions) => instructions.clone(),
+        };
+        let offset = output.len();
+        output.extend(code.into_iter().map(|instruction| relocate(instruction, offset)));
+    }
+    output
+}
+
+fn relocate(instruction: BytecodeInstruction, offset: usize) -> BytecodeInstruction {
+    match instruction {
+        BytecodeInstruction::Jump(target) => BytecodeInstruction::Jump(target + offset),
+        BytecodeInstruction::JumpIfFalse(target) => BytecodeInstruction::JumpIfFalse(target + offset),
+        BytecodeInstruction::JumpIfTrue(target) => BytecodeInstruction::JumpIfTrue(target + offset),
+        BytecodeInstruction::Try(target) => BytecodeInstruction::Try(target + offset),
+        other => other,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Block(BlockId),
+    End,
+}
+
+/// Bytecode for one unit, with jump targets relative to its first instruction
+fn generate_function(function: &Function) -> Vec<BytecodeInstruction> {
+    let mut code = Vec::new();
+    if let Some(name) = &function.name {
+        code.push(BytecodeInstruction::DefineFunction(name.clone(), function.params.len()));
+        for param in function.params.iter().rev() {
+            code.push(BytecodeInstruction::Store(param.clone()));
+        }
+    }
+
+    let counts = function.use_counts();
+    let constants: HashMap<Temp, Const> = function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match (&inst.op, inst.dest) {
+            (Op::Const(value), Some(dest)) => Some((dest, value.clone())),
+            _ => None,
+        })
+        .collect();
+    let layout = layout(function);
+    let mut starts = vec![0; function.blocks.len()];
+    let mut fixups: Vec<(usize, Target)> = Vec::new();
+
+    for (position, &block) in layout.iter().enumerate() {
+        starts[block] = code.len();
+        let next = layout.get(position + 1).copied();
+        let emitter = BlockEmitter { function, block, counts: &counts, constants: &constants };
+        code.extend(emitter.emit());
+
+        let mut jump = |code: &mut Vec<BytecodeInstruction>, instruction: BytecodeInstruction, target: Target| {
+            fixups.push((code.len(), target));
+            code.push(instruction);
+        };
+        match function.blocks[block].term {
+            Terminator::Jump(target) => {
+                if next != Some(target) {
+                    jump(&mut code, BytecodeInstruction::Jump(0), Target::Block(target));
+                }
+            }
+            Terminator::Branch(_, then_block, else_block) => {
+                if then_block == else_block {
+                    code.push(BytecodeInstruction::Pop);
+                    if next != Some(then_block) {
+                        jump(&mut code, BytecodeInstruction::Jump(0), Target::Block(then_block));
+                    }
+                } else if next == Some(then_block) {
+                    jump(&mut code, BytecodeInstruction::JumpIfFalse(0), Target::Block(else_block));
+                } else if next == Some(else_block) {
+                    jump(&mut code, BytecodeInstruction::JumpIfTrue(0), Target::Block(then_block));
+                } else {
+                    jump(&mut code, BytecodeInstruction::JumpIfFalse(0), Target::Block(else_block));
+                    jump(&mut code, BytecodeInstruction::Jump(0), Target::Block(then_block));
+                }
+            }
+            Terminator::Return(_) => {
+                // A call whose result is returned as is can reuse the frame
+                if code.len() > starts[block] {
+                    if let Some(BytecodeInstruction::Call(name, arg_count)) = code.last() {
+                        if !name.is_empty() {
+                            let tail_call = BytecodeInstruction::TailCall(name.clone(), *arg_count);
+                            *code.last_mut().unwrap() = tail_call;
+                        }
+                    }
+                }
+                code.push(BytecodeInstruction::Return);
+            }
+            Terminator::End => {
+                if next.is_some() {
+                    jump(&mut code, BytecodeInstruction::Jump(0), Target::End);
+                }
+            }
+        }
+    }
+
+    let end = code.len();
+    for (index, target) in fixups {
+        let address = match target {
+            Target::Block(block) => starts[block],
+            Target::End => end,
+        };
+        code[index] = match &code[index] {
+            BytecodeInstruction::Jump(_) => BytecodeInstruction::Jump(address),
+            BytecodeInstruction::JumpIfFalse(_) => BytecodeInstruction::JumpIfFalse(address),
+            BytecodeInstruction::JumpIfTrue(_) => BytecodeInstruction::JumpIfTrue(address),
+            other => unreachable!("fixup on {:?}", other),
+        };
+    }
+    if function.name.is_some() {
+        code.push(BytecodeInstruction::EndFunction);
+    }
+    code
+}
+
+/// Block order: follow each block's preferred successor while it is not yet
+/// placed, then continue with the lowest unplaced block
+fn layout(function: &Function) -> Vec<BlockId> {
+    let mut placed = vec![false; function.blocks.len()];
+    let mut order = Vec::with_capacity(function.blocks.len());
+    for start in 0..function.blocks.len() {
+        let mut block = start;
+        while !placed[block] {
+            placed[block] = true;
+            order.push(block);
+            block = match function.blocks[block].term {
+                Terminator::Jump(target) | Terminator::Branch(_, target, _) => target,
+                Terminator::Return(_) | Terminator::End => break,
+            };
+        }
+    }
+    order
+}
+
+fn spill_name(temp: Temp) -> String {
+    format!("%t{}", temp)
+}
+
+struct BlockEmitter<'a> {
+    function: &'a Function,
+    block: BlockId,
+    counts: &'a [usize],
+    constants: &'a HashMap<Temp, Const>,
+}
+
+impl BlockEmitter<'_> {
+    /// The block's instructions, leaving the terminator's operand on the stack
+    fn emit(&self) -> Vec<BytecodeInstruction> {
+        let mut stacked = self.stack_candidates();
+        loop {
+            match self.simulate(&stacked) {
+                Ok(code) => return code,
+                Err(conflicts) => {
+                    for temp in conflicts {
+                        stacked.remove(&temp);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Temps used exactly once, later in this block
+    fn stack_candidates(&self) -> HashSet<Temp> {
+        let block = &self.function.blocks[self.block];
+        let used_here: HashSet<Temp> = block
+            .insts
+            .iter()
+            .flat_map(|inst| inst.op.operands())
+            .chain(block.term.operand())
+            .collect();
+        block
+            .insts
+            .iter()
+            .filter_map(|inst| inst.dest)
+            .filter(|dest| self.counts[*dest] == 1 && used_here.contains(dest))
+            .collect()
+    }
+
+    /// Emit the block assuming `stacked` temps stay on the stack; on a
+    /// conflict, returns the temps that have to be spilled instead
+    fn simulate(&self, stacked: &HashSet<Temp>) -> Result<Vec<BytecodeInstruction>, Vec<Temp>> {
+        let block = &self.function.blocks[self.block];
+        let mut stack: Vec<Temp> = Vec::new();
+        let mut code = Vec::new();
+        for inst in &block.insts {
+            if let (Op::Const(_), Some(dest)) = (&inst.op, inst.dest) {
+                // Unstacked constants are pushed where they are used
+                if stacked.contains(&dest) {
+                    stack.push(dest);
+                    code.push(self.constants[&dest].instruction());
+                }
+                continue;
+            }
+            self.push_operands(&inst.op.operands(), stacked, &mut stack, &mut code)?;
+            match &inst.op {
+                Op::Const(value) => code.push(value.instruction()),
+                Op::Load(name) => code.push(BytecodeInstruction::Load(name.clone())),
+                Op::Store(name, _) => code.push(BytecodeInstruction::Store(name.clone())),
+                Op::Binary(op, ..) => code.push(op.instruction()),
+                Op::Unary(op, _) => code.push(op.instruction()),
+                Op::Builtin(builtin, _) => code.push(builtin.instruction()),
+                Op::Call(name, args) => code.push(BytecodeInstruction::Call(name.clone(), args.len())),
+                Op::ArrayLiteral(elements) => {
+                    code.push(BytecodeInstruction::NewArray);
+                    for element in elements {
+                        code.push(element.instruction());
+                        code.push(BytecodeInstruction::ArrayPush);
+                    }
+                }
+                Op::GetIndex(..) => code.push(BytecodeInstruction::GetIndex),
+                Op::Line(line) => code.push(BytecodeInstruction::Line(*line)),
+            }
+            if let Some(dest) = inst.dest {
+                if stacked.contains(&dest) {
+                    stack.push(dest);
+                } else if self.counts[dest] > 0 {
+                    code.push(BytecodeInstruction::Store(spill_name(dest)));
+                } else {
+                    code.push(BytecodeInstruction::Pop);
+                }
+            }
+        }
+        if let Some(operand) = block.term.operand() {
+            self.push_operands(&[operand], stacked, &mut stack, &mut code)?;
+        }
+        Ok(code)
+    }
+
+    /// Stacked operands must be a prefix of `operands` and sit on top of the
+    /// stack in order; the rest are pushed from locals or as constants
+    fn push_operands(
+        &self,
+        operands: &[Temp],
+        stacked: &HashSet<Temp>,
+        stack: &mut Vec<Temp>,
+        code: &mut Vec<BytecodeInstruction>,
+    ) -> Result<(), Vec<Temp>> {
+        let prefix = operands.iter().take_while(|temp| stacked.contains(temp)).count();
+        let in_order = prefix <= stack.len() && stack[stack.len() - prefix..] == operands[..prefix];
+        if !in_order || operands[prefix..].iter().any(|temp| stacked.contains(temp)) {
+            let mut conflicts: Vec<Temp> = operands.iter().copied().filter(|temp| stacked.contains(temp)).collect();
+            // Whatever sits above the operands blocks them too
+            let lowest = stack.iter().position(|temp| conflicts.contains(temp)).unwrap_or(0);
+            conflicts.extend(&stack[lowest..]);
+            return Err(conflicts);
+        }
+        stack.truncate(stack.len() - prefix);
+        for &operand in &operands[prefix..] {
+            code.push(match self.constants.get(&operand) {
+                Some(value) => value.instruction(),
+                None => BytecodeInstruction::Load(spill_name(operand)),
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+// Common subexpression elimination
+//
+// Walks the dominator tree with a scoped table of pure instructions, so a
+// computation is reused wherever an identical one dominates it. Loads are
+// only reused within an extended basic block, where no other path can have
+// stored to the variable in between, and a load right after a store reuses
+// the stored value.
+
+use super::{Const, Function, Op, Temp};
+use std::collections::HashMap;
+
+enum Step {
+    Enter(usize, HashMap<String, Temp>),
+    Leave(Vec<String>),
+}
+
+/// Returns how many instructions were replaced by an earlier equal one
+pub fn eliminate_common_subexpressions(function: &mut Function) -> usize {
+    let idom = function.dominators();
+    let order = function.reverse_postorder();
+    let predecessors = function.predecessors();
+    let mut children = vec![Vec::new(); function.blocks.len()];
+    for &block in order.iter().skip(1) {
+        if let Some(parent) = idom[block] {
+            children[parent].push(block);
+        }
+    }
+    let constants: HashMap<Temp, Const> = function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match (&inst.op, inst.dest) {
+            (Op::Const(value), Some(dest)) => Some((dest, value.clone())),
+            _ => None,
+        })
+        .collect();
+
+    let mut available: HashMap<String, Temp> = HashMap::new();
+    let mut replacements: HashMap<Temp, Temp> = HashMap::new();
+    let mut shared = 0;
+    let mut steps = vec![Step::Enter(0, HashMap::new())];
+    while let Some(step) = steps.pop() {
+        let (block, mut loads) = match step {
+            Step::Enter(block, loads) => (block, loads),
+            Step::Leave(keys) => {
+                for key in keys {
+                    available.remove(&key);
+                }
+                continue;
+            }
+        };
+        let mut added = Vec::new();
+        let insts = std::mem::take(&mut function.blocks[block].insts);
+        let mut kept = Vec::with_capacity(insts.len());
+        for mut inst in insts {
+            for operand in inst.op.operands_mut() {
+                *operand = resolve(&replacements, *operand);
+            }
+            match (&inst.op, inst.dest) {
+                (Op::Load(name), Some(dest)) => {
+                    if let Some(&earlier) = loads.get(name) {
+                        replacements.insert(dest, earlier);
+                        shared += 1;
+                        continue;
+                    }
+                    loads.insert(name.clone(), dest);
+                }
+                (Op::Store(name, value), _) => {
+                    loads.insert(name.clone(), *value);
+                }
+                // Constants are rematerialized by codegen, sharing them gains nothing
+                (Op::Const(_), _) => {}
+                (op, Some(dest)) if op.is_pure() => {
+                    let key = expression_key(op, &constants);
+                    if let Some(&earlier) = available.get(&key) {
+                        replacements.insert(dest, earlier);
+                        shared += 1;
+                        continue;
+                    }
+                    available.insert(key.clone(), dest);
+                    added.push(key);
+                }
+                _ => {}
+            }
+            kept.push(inst);
+        }
+        function.blocks[block].insts = kept;
+        if let Some(operand) = function.blocks[block].term.operand_mut() {
+            *operand = resolve(&replacements, *operand);
+        }
+
+        steps.push(Step::Leave(added));
+        for &child in children[block].iter().rev() {
+            // A block entered only from here sees the same variables as the end of this block
+            let inherited = if predecessors[child] == [block] { loads.clone() } else { HashMap::new() };
+            steps.push(Step::Enter(child, inherited));
+        }
+    }
+    function.replace_uses(&replacements);
+    shared
+}
+
+fn resolve(replacements: &HashMap<Temp, Temp>, mut temp: Temp) -> Temp {
+    while let Some(&next) = replacements.get(&temp) {
+        temp = next;
+    }
+    temp
+}
+
+/// Identifies an instruction by its operation and operands, with constant
+/// operands spelled out and commutative operands in a fixed order
+fn expression_key(op: &Op, constants: &HashMap<Temp, Const>) -> String {
+    let mut operands: Vec<String> = op
+        .operands()
+        .into_iter()
+        .map(|temp| match constants.get(&temp) {
+            Some(value) => format!("{:?}", value),
+            None => format!("%{}", temp),
+        })
+        .collect();
+    let name = match op {
+        Op::Binary(binary, ..) => {
+            if binary.is_commutative() {
+                operands.sort();
+            }
+            format!("{:?}", binary)
+        }
+        Op::Unary(unary, _) => format!("{:?}", unary),
+        Op::Builtin(builtin, _) => builtin.name.to_string(),
+        other => format!("{:?}", other),
+    };
+    format!("{}({})", name, operands.join(", "))
+}
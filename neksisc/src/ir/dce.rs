@@ -0,0 +1,214 @@
+// Dead code elimination
+//
+// Removes blocks that can no longer be reached (typically after constant
+// branches were folded), merges straight-line chains of blocks, drops
+// instructions whose result is unused and which cannot fail, and deletes
+// stores to variables that are never read again. Variables are local to
+// their function in the VM, so nothing is live once it returns.
+
+use super::fold::{self, VarStates};
+use super::{BlockId, Function, Op, Terminator};
+use std::collections::HashSet;
+
+/// Run all the cleanups until nothing changes; returns how many
+/// instructions (terminators included) were removed
+pub fn eliminate_dead_code(function: &mut Function) -> usize {
+    let before = function.instruction_count();
+    loop {
+        let mut changed = simplify_cfg(function);
+        changed |= remove_unused(function);
+        changed |= remove_dead_stores(function);
+        if !changed {
+            break;
+        }
+    }
+    before - function.instruction_count()
+}
+
+fn simplify_cfg(function: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let mut step = thread_jumps(function);
+        step |= merge_blocks(function);
+        step |= remove_unreachable(function);
+        if !step {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+/// Point edges into empty blocks that only jump elsewhere straight at the target
+fn thread_jumps(function: &mut Function) -> bool {
+    let mut changed = false;
+    for block in 1..function.blocks.len() {
+        let target = match function.blocks[block].term {
+            Terminator::Jump(target) if function.blocks[block].insts.is_empty() && target != block => target,
+            _ => continue,
+        };
+        for other in 0..function.blocks.len() {
+            if other != block && function.blocks[other].term.successors().contains(&block) {
+                function.blocks[other].term.retarget(block, target);
+                changed = true;
+            }
+        }
+    }
+    for block in &mut function.blocks {
+        if let Terminator::Branch(_, then_block, else_block) = block.term {
+            if then_block == else_block {
+                block.term = Terminator::Jump(then_block);
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Append a block to its only predecessor when that predecessor jumps straight to it
+fn merge_blocks(function: &mut Function) -> bool {
+    let mut changed = false;
+    let mut predecessors = function.predecessors();
+    for block in 0..function.blocks.len() {
+        while let Terminator::Jump(successor) = function.blocks[block].term {
+            if successor == block || successor == 0 || predecessors[successor] != [block] {
+                break;
+            }
+            let merged = std::mem::replace(
+                &mut function.blocks[successor],
+                super::Block { insts: Vec::new(), term: Terminator::End },
+            );
+            for next in merged.term.successors() {
+                for predecessor in &mut predecessors[next] {
+                    if *predecessor == successor {
+                        *predecessor = block;
+                    }
+                }
+            }
+            predecessors[successor].clear();
+            function.blocks[block].insts.extend(merged.insts);
+            function.blocks[block].term = merged.term;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Drop unreachable blocks and renumber the rest, keeping their order
+fn remove_unreachable(function: &mut Function) -> bool {
+    let reachable: HashSet<BlockId> = function.reverse_postorder().into_iter().collect();
+    if reachable.len() == function.blocks.len() {
+        return false;
+    }
+    let mut renumbered = vec![usize::MAX; function.blocks.len()];
+    let mut blocks = Vec::new();
+    for (id, block) in std::mem::take(&mut function.blocks).into_iter().enumerate() {
+        if reachable.contains(&id) {
+            renumbered[id] = blocks.len();
+            blocks.push(block);
+        }
+    }
+    for block in &mut blocks {
+        match &mut block.term {
+            Terminator::Jump(target) => *target = renumbered[*target],
+            Terminator::Branch(_, then_block, else_block) => {
+                *then_block = renumbered[*then_block];
+                *else_block = renumbered[*else_block];
+            }
+            Terminator::Return(_) | Terminator::End => {}
+        }
+    }
+    function.blocks = blocks;
+    true
+}
+
+/// Remove instructions whose result is unused and that cannot fail. Loads
+/// only go when the variable is certainly assigned, since loading an
+/// undefined variable is an error.
+fn remove_unused(function: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let counts = function.use_counts();
+        let states = fold::variable_states(function);
+        let mut removed = false;
+        for (block, state) in function.blocks.iter_mut().zip(states) {
+            let Some(mut state): Option<VarStates> = state else { continue };
+            block.insts.retain(|inst| {
+                let unused = matches!(inst.dest, Some(dest) if counts[dest] == 0);
+                let removable = match &inst.op {
+                    Op::Load(name) => fold::definitely_assigned(&state, name),
+                    Op::Store(name, _) => {
+                        state.insert(name.clone(), fold::VarState::Assigned);
+                        false
+                    }
+                    op => !op.has_effect() && !matches!(op, Op::Line(_)),
+                };
+                let keep = !(unused && removable);
+                removed |= !keep;
+                keep
+            });
+        }
+        if !removed {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+/// Remove stores whose value is overwritten or never read, using backward liveness
+fn remove_dead_stores(function: &mut Function) -> bool {
+    let successors: Vec<Vec<BlockId>> = function.blocks.iter().map(|block| block.term.successors()).collect();
+    let mut live_in: Vec<HashSet<String>> = vec![HashSet::new(); function.blocks.len()];
+    let mut order = function.reverse_postorder();
+    order.reverse();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in &order {
+            let mut live: HashSet<String> =
+                successors[block].iter().flat_map(|successor| live_in[*successor].iter().cloned()).collect();
+            for inst in function.blocks[block].insts.iter().rev() {
+                match &inst.op {
+                    Op::Store(name, _) => {
+                        live.remove(name);
+                    }
+                    Op::Load(name) => {
+                        live.insert(name.clone());
+                    }
+                    _ => {}
+                }
+            }
+            if live != live_in[block] {
+                live_in[block] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let mut removed = false;
+    for block in order {
+        let mut live: HashSet<String> =
+            successors[block].iter().flat_map(|successor| live_in[*successor].iter().cloned()).collect();
+        let insts = std::mem::take(&mut function.blocks[block].insts);
+        let mut kept = Vec::with_capacity(insts.len());
+        for inst in insts.into_iter().rev() {
+            match &inst.op {
+                Op::Store(name, _) if !live.contains(name) => {
+                    removed = true;
+                    continue;
+                }
+                Op::Store(name, _) => {
+                    live.remove(name);
+                }
+                Op::Load(name) => {
+                    live.insert(name.clone());
+                }
+                _ => {}
+            }
+            kept.push(inst);
+        }
+        kept.reverse();
+        function.blocks[block].insts = kept;
+    }
+    removed
+}
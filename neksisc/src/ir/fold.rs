@@ -0,0 +1,213 @@
+// Constant propagation
+//
+// Works out which variables hold a known constant at the start of every
+// block, then replaces loads of them, and instructions whose operands are all
+// constants, with the constant itself. Branches on a constant become jumps.
+// Results are computed by running the operation on the VM, so folding agrees
+// with runtime behaviour exactly; operations that fail or would overflow are
+// left for runtime to report.
+
+use super::{BinOp, Const, Function, Op, Temp, Terminator, UnOp};
+use crate::vm::{BytecodeInstruction, VM};
+use std::collections::HashMap;
+
+/// What is known about a variable at a program point. A variable missing
+/// from the map has not been assigned on any path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VarState {
+    Const(Const),
+    Assigned,
+    MaybeUnassigned,
+}
+
+pub type VarStates = HashMap<String, VarState>;
+
+/// Builtins folded when their arguments are constants. Each either cannot
+/// panic in the VM or is guarded in `fold_instruction`.
+const FOLDABLE_BUILTINS: &[&str] = &[
+    "abs", "sqrt", "sin", "cos", "tan", "floor", "ceil", "round", "min", "max", "len", "concat", "contains",
+    "starts_with", "ends_with", "to_upper", "to_lower", "trim", "typeof",
+];
+
+/// The state of every variable on entry to each block, or `None` for
+/// unreachable blocks
+pub fn variable_states(function: &Function) -> Vec<Option<VarStates>> {
+    let order = function.reverse_postorder();
+    let predecessors = function.predecessors();
+    let mut entry_state = VarStates::new();
+    for param in &function.params {
+        entry_state.insert(param.clone(), VarState::Assigned);
+    }
+
+    let mut states_in: Vec<Option<VarStates>> = vec![None; function.blocks.len()];
+    let mut states_out: Vec<Option<VarStates>> = vec![None; function.blocks.len()];
+    let mut constants: HashMap<Temp, Const> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in &order {
+            let state = if block == 0 {
+                Some(entry_state.clone())
+            } else {
+                predecessors[block]
+                    .iter()
+                    .filter_map(|predecessor| states_out[*predecessor].as_ref())
+                    .fold(None, |merged: Option<VarStates>, state| match merged {
+                        None => Some(state.clone()),
+                        Some(merged) => Some(meet(&merged, state)),
+                    })
+            };
+            let Some(mut state) = state else { continue };
+            states_in[block] = Some(state.clone());
+            for inst in &function.blocks[block].insts {
+                transfer(&inst.op, inst.dest, &mut state, &mut constants);
+            }
+            if states_out[block].as_ref() != Some(&state) {
+                states_out[block] = Some(state);
+                changed = true;
+            }
+        }
+    }
+    states_in
+}
+
+fn meet(a: &VarStates, b: &VarStates) -> VarStates {
+    let mut merged = VarStates::new();
+    for (name, state) in a {
+        let state = match b.get(name) {
+            Some(other) if other == state => state.clone(),
+            Some(VarState::MaybeUnassigned) | None => VarState::MaybeUnassigned,
+            Some(_) if *state == VarState::MaybeUnassigned => VarState::MaybeUnassigned,
+            Some(_) => VarState::Assigned,
+        };
+        merged.insert(name.clone(), state);
+    }
+    for name in b.keys() {
+        merged.entry(name.clone()).or_insert(VarState::MaybeUnassigned);
+    }
+    merged
+}
+
+fn transfer(op: &Op, dest: Option<Temp>, state: &mut VarStates, constants: &mut HashMap<Temp, Const>) {
+    match op {
+        Op::Const(value) => {
+            constants.insert(dest.unwrap(), value.clone());
+        }
+        Op::Load(name) => match state.get(name) {
+            Some(VarState::Const(value)) => {
+                constants.insert(dest.unwrap(), value.clone());
+            }
+            _ => {
+                constants.remove(&dest.unwrap());
+            }
+        },
+        Op::Store(name, value) => {
+            let known = match constants.get(value) {
+                Some(value) => VarState::Const(value.clone()),
+                None => VarState::Assigned,
+            };
+            state.insert(name.clone(), known);
+        }
+        _ => {}
+    }
+}
+
+/// Whether `name` has been assigned on every path to the start of `block`
+pub fn definitely_assigned(states: &VarStates, name: &str) -> bool {
+    matches!(states.get(name), Some(VarState::Const(_) | VarState::Assigned))
+}
+
+/// Run propagation and folding until nothing changes; returns how many instructions were folded
+pub fn propagate_constants(function: &mut Function) -> usize {
+    let mut total = 0;
+    for _ in 0..8 {
+        let states = variable_states(function);
+        let folded = fold_once(function, &states);
+        total += folded;
+        if folded == 0 {
+            break;
+        }
+    }
+    total
+}
+
+fn fold_once(function: &mut Function, states: &[Option<VarStates>]) -> usize {
+    let mut folded = 0;
+    let mut constants: HashMap<Temp, Const> = HashMap::new();
+    for block in function.reverse_postorder() {
+        let Some(mut state) = states[block].clone() else { continue };
+        for inst in &mut function.blocks[block].insts {
+            if let (Some(dest), Some(value)) = (inst.dest, fold_instruction(&inst.op, &state, &constants)) {
+                inst.op = Op::Const(value);
+                constants.insert(dest, match &inst.op {
+                    Op::Const(value) => value.clone(),
+                    _ => unreachable!(),
+                });
+                folded += 1;
+            }
+            transfer(&inst.op, inst.dest, &mut state, &mut constants);
+        }
+        let term = &mut function.blocks[block].term;
+        if let Terminator::Branch(condition, then_block, else_block) = *term {
+            if let Some(value) = constants.get(&condition) {
+                *term = Terminator::Jump(if value.to_value().to_bool() { then_block } else { else_block });
+                folded += 1;
+            }
+        }
+    }
+    folded
+}
+
+fn fold_instruction(op: &Op, state: &VarStates, constants: &HashMap<Temp, Const>) -> Option<Const> {
+    let operand = |temp: &Temp| constants.get(temp).cloned();
+    match op {
+        Op::Load(name) => match state.get(name) {
+            Some(VarState::Const(value)) => Some(value.clone()),
+            _ => None,
+        },
+        Op::Binary(binary, left, right) => {
+            let (left, right) = (operand(left)?, operand(right)?);
+            if let (Const::Int(a), Const::Int(b)) = (&left, &right) {
+                let fits = match binary {
+                    BinOp::Add => a.checked_add(*b).is_some(),
+                    BinOp::Sub => a.checked_sub(*b).is_some(),
+                    BinOp::Mul => a.checked_mul(*b).is_some(),
+                    BinOp::Div => a.checked_div(*b).is_some(),
+                    BinOp::Mod => a.checked_rem(*b).is_some(),
+                    _ => true,
+                };
+                if !fits {
+                    return None;
+                }
+            }
+            evaluate(&[left, right], binary.instruction())
+        }
+        Op::Unary(unary, value) => {
+            let value = operand(value)?;
+            if *unary == UnOp::Neg && value == Const::Int(i64::MIN) {
+                return None;
+            }
+            evaluate(&[value], unary.instruction())
+        }
+        Op::Builtin(builtin, args) if FOLDABLE_BUILTINS.contains(&builtin.name) => {
+            let args = args.iter().map(operand).collect::<Option<Vec<_>>>()?;
+            match (builtin.name, args.as_slice()) {
+                ("abs", [Const::Int(i64::MIN)]) => return None,
+                // Only strings are immutable; the length of an array can change
+                ("len", [arg]) if !matches!(arg, Const::String(_)) => return None,
+                _ => {}
+            }
+            evaluate(&args, builtin.instruction())
+        }
+        _ => None,
+    }
+}
+
+fn evaluate(args: &[Const], operation: BytecodeInstruction) -> Option<Const> {
+    let mut instructions: Vec<BytecodeInstruction> = args.iter().map(Const::instruction).collect();
+    instructions.push(operation);
+    match VM::evaluate(instructions) {
+        Ok(Some(value)) => Const::from_value(&value),
+        _ => None,
+    }
+}
@@ -0,0 +1,253 @@
+// Function inlining
+//
+// Replaces calls to small, non-recursive functions with a copy of their
+// body. The callee's variables are renamed (`name$N`) because a call starts
+// with empty locals and must not see or change the caller's. Only callees
+// that never read a possibly unassigned variable qualify: in the VM such a
+// read would fall back to globals and builtins, which a renamed variable
+// cannot reproduce.
+
+use super::{dce, fold};
+use super::{BlockId, Function, Inst, Module, Op, Temp, Terminator, Unit};
+use crate::vm::BytecodeInstruction;
+use std::collections::{HashMap, HashSet};
+
+/// Largest callee body that is copied into its callers
+const MAX_CALLEE_SIZE: usize = 30;
+/// Callers stop growing once they reach this size
+const MAX_CALLER_SIZE: usize = 2000;
+const ROUNDS: usize = 2;
+
+/// Returns how many call sites were inlined
+pub fn inline_calls(module: &mut Module) -> usize {
+    let mut inlined = 0;
+    let mut copies = 0;
+    for _ in 0..ROUNDS {
+        let callees = candidates(module);
+        if callees.is_empty() {
+            break;
+        }
+        let mut changed = 0;
+        for function in module.ir_functions_mut() {
+            changed += inline_into(function, &callees, &mut copies);
+        }
+        if changed == 0 {
+            break;
+        }
+        inlined += changed;
+    }
+    inlined
+}
+
+fn candidates(module: &Module) -> HashMap<String, Function> {
+    let mut definitions: HashMap<String, usize> = HashMap::new();
+    for unit in &module.functions {
+        let name = match unit {
+            Unit::Ir(function) => function.name.clone(),
+            Unit::Bytecode(instructions) => instructions.iter().find_map(|instruction| match instruction {
+                BytecodeInstruction::DefineFunction(name, _) => Some(name.clone()),
+                _ => None,
+            }),
+        };
+        if let Some(name) = name {
+            *definitions.entry(name).or_default() += 1;
+        }
+    }
+
+    let recursive = recursive_functions(module);
+    let mut callees = HashMap::new();
+    for unit in &module.functions {
+        let Unit::Ir(function) = unit else { continue };
+        let Some(name) = &function.name else { continue };
+        // Copy the callee without the unreachable blocks lowering leaves behind
+        let mut function = function.clone();
+        dce::eliminate_dead_code(&mut function);
+        // A redefinition replaces the earlier function at runtime
+        if definitions[name] != 1 || function.instruction_count() > MAX_CALLEE_SIZE {
+            continue;
+        }
+        if !recursive.contains(name) && reads_only_assigned(&function) {
+            callees.insert(name.clone(), function);
+        }
+    }
+    callees
+}
+
+/// Functions that can reach themselves through calls; inlining them would
+/// change the call chain a stack overflow reports
+fn recursive_functions(module: &Module) -> HashSet<String> {
+    let mut calls: HashMap<String, HashSet<String>> = HashMap::new();
+    for unit in &module.functions {
+        let (name, callees): (Option<String>, HashSet<String>) = match unit {
+            Unit::Ir(function) => (
+                function.name.clone(),
+                function
+                    .blocks
+                    .iter()
+                    .flat_map(|block| &block.insts)
+                    .filter_map(|inst| match &inst.op {
+                        Op::Call(callee, _) => Some(callee.clone()),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            Unit::Bytecode(instructions) => {
+                let mut name = None;
+                let mut callees = HashSet::new();
+                for instruction in instructions {
+                    match instruction {
+                        BytecodeInstruction::DefineFunction(defined, _) => name = Some(defined.clone()),
+                        BytecodeInstruction::Call(callee, _) | BytecodeInstruction::TailCall(callee, _) => {
+                            callees.insert(callee.clone());
+                        }
+                        _ => {}
+                    }
+                }
+                (name, callees)
+            }
+        };
+        if let Some(name) = name {
+            calls.entry(name).or_default().extend(callees);
+        }
+    }
+
+    let mut recursive = HashSet::new();
+    for start in calls.keys() {
+        let mut seen = HashSet::new();
+        let mut worklist: Vec<&String> = calls[start].iter().collect();
+        while let Some(name) = worklist.pop() {
+            if name == start {
+                recursive.insert(start.clone());
+                break;
+            }
+            if seen.insert(name) {
+                worklist.extend(calls.get(name).into_iter().flatten());
+            }
+        }
+    }
+    recursive
+}
+
+fn reads_only_assigned(function: &Function) -> bool {
+    let states = fold::variable_states(function);
+    function.blocks.iter().zip(states).all(|(block, state)| {
+        let Some(mut state) = state else { return true };
+        block.insts.iter().all(|inst| match &inst.op {
+            Op::Load(name) => fold::definitely_assigned(&state, name),
+            Op::Store(name, _) => {
+                state.insert(name.clone(), fold::VarState::Assigned);
+                true
+            }
+            _ => true,
+        })
+    })
+}
+
+fn inline_into(function: &mut Function, callees: &HashMap<String, Function>, copies: &mut usize) -> usize {
+    let mut inlined = 0;
+    let mut block = 0;
+    while block < function.blocks.len() {
+        let site = function.blocks[block].insts.iter().position(|inst| match &inst.op {
+            Op::Call(name, args) => callees
+                .get(name)
+                .is_some_and(|callee| callee.params.len() == args.len() && function.name.as_ref() != Some(name)),
+            _ => false,
+        });
+        match site {
+            Some(index) if function.instruction_count() < MAX_CALLER_SIZE => {
+                *copies += 1;
+                inline_site(function, block, index, callees, *copies);
+                inlined += 1;
+            }
+            // The rest of a split block moves to a new block, which is visited later
+            _ => block += 1,
+        }
+    }
+    inlined
+}
+
+fn inline_site(function: &mut Function, block: BlockId, index: usize, callees: &HashMap<String, Function>, copy: usize) {
+    let Inst { dest, op: Op::Call(name, args) } = function.blocks[block].insts[index].clone() else {
+        unreachable!("inline site is a call")
+    };
+    let callee = &callees[&name];
+    let rename = |variable: &str| format!("{}${}", variable, copy);
+
+    // When the caller returns the call's result as is, the callee's returns
+    // stay returns, which keeps the calls they return in tail position
+    let tail = index + 1 == function.blocks[block].insts.len()
+        && dest.is_some_and(|dest| function.blocks[block].term == Terminator::Return(dest));
+
+    // Split the caller: everything after the call continues in a new block
+    let continuation = if tail { None } else { Some(function.new_block()) };
+    let rest = function.blocks[block].insts.split_off(index + 1);
+    function.blocks[block].insts.pop();
+    let term = std::mem::replace(&mut function.blocks[block].term, Terminator::End);
+    if let Some(continuation) = continuation {
+        function.blocks[continuation].insts = rest;
+        function.blocks[continuation].term = term;
+    }
+
+    // Arguments are stored the way the callee's prologue stores them
+    for (param, arg) in callee.params.iter().zip(&args).rev() {
+        function.blocks[block].insts.push(Inst { dest: None, op: Op::Store(rename(param), *arg) });
+    }
+
+    let block_map: Vec<BlockId> = callee.blocks.iter().map(|_| function.new_block()).collect();
+    let mut temp_map: HashMap<Temp, Temp> = HashMap::new();
+    let mut temp = |function: &mut Function, old: Temp| *temp_map.entry(old).or_insert_with(|| function.new_temp());
+    let returns = callee.blocks.iter().filter(|block| matches!(block.term, Terminator::Return(_))).count();
+    let result_variable = format!("$ret${}", copy);
+    let mut single_result = None;
+
+    for (old, callee_block) in callee.blocks.iter().enumerate() {
+        let mut insts = Vec::with_capacity(callee_block.insts.len());
+        for inst in &callee_block.insts {
+            let mut op = match &inst.op {
+                Op::Load(variable) => Op::Load(rename(variable)),
+                Op::Store(variable, value) => Op::Store(rename(variable), *value),
+                other => other.clone(),
+            };
+            for operand in op.operands_mut() {
+                *operand = temp(function, *operand);
+            }
+            let dest = inst.dest.map(|dest| temp(function, dest));
+            insts.push(Inst { dest, op });
+        }
+        let term = match &callee_block.term {
+            Terminator::Jump(target) => Terminator::Jump(block_map[*target]),
+            Terminator::Branch(condition, then_block, else_block) => {
+                Terminator::Branch(temp(function, *condition), block_map[*then_block], block_map[*else_block])
+            }
+            Terminator::Return(value) => {
+                let value = temp(function, *value);
+                let Some(continuation) = continuation else {
+                    function.blocks[block_map[old]].insts = insts;
+                    function.blocks[block_map[old]].term = Terminator::Return(value);
+                    continue;
+                };
+                if returns == 1 {
+                    single_result = Some(value);
+                } else {
+                    insts.push(Inst { dest: None, op: Op::Store(result_variable.clone(), value) });
+                }
+                Terminator::Jump(continuation)
+            }
+            Terminator::End => Terminator::End,
+        };
+        function.blocks[block_map[old]].insts = insts;
+        function.blocks[block_map[old]].term = term;
+    }
+    function.blocks[block].term = Terminator::Jump(block_map[0]);
+
+    let (Some(dest), Some(continuation)) = (dest, continuation) else { return };
+    let result = match single_result {
+        Some(result) => result,
+        None => {
+            let result = function.new_temp();
+            function.blocks[continuation].insts.insert(0, Inst { dest: Some(result), op: Op::Load(result_variable) });
+            result
+        }
+    };
+    function.replace_uses(&HashMap::from([(dest, result)]));
+}
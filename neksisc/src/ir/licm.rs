@@ -0,0 +1,212 @@
+// Loop-invariant code motion
+//
+// `while` loops are first rotated: the condition is copied in front of the
+// loop as a guard, so the first block of the body runs on every iteration,
+// including the first. Pure instructions whose operands do not change in the
+// loop then move to the preheader. Anything that could fail only moves when
+// it sits at the top of the loop header, before any instruction that fails
+// or has an effect, so an error happens at the same point as before.
+
+use super::fold::{self, VarState};
+use super::{BlockId, Const, Function, Inst, Op, Temp, Terminator};
+use std::collections::{HashMap, HashSet};
+
+/// Largest loop condition that is duplicated when rotating a loop
+const MAX_GUARD_SIZE: usize = 12;
+const MAX_ROTATIONS: usize = 32;
+
+/// Returns how many instructions were moved out of loops
+pub fn hoist_loop_invariants(function: &mut Function) -> usize {
+    rotate_loops(function);
+    let mut hoisted = 0;
+    let mut done: HashSet<BlockId> = HashSet::new();
+    loop {
+        // Innermost loops come first, so their invariants can move further out afterwards
+        let next = natural_loops(function).into_iter().find(|(header, _)| !done.contains(header));
+        let Some((header, body)) = next else { break };
+        done.insert(header);
+        if let Some(preheader) = ensure_preheader(function, header, &body) {
+            hoisted += hoist(function, header, &body, preheader);
+        }
+    }
+    hoisted
+}
+
+/// Loops as (header, blocks) pairs, smallest first
+fn natural_loops(function: &Function) -> Vec<(BlockId, HashSet<BlockId>)> {
+    let idom = function.dominators();
+    let predecessors = function.predecessors();
+    let mut loops: HashMap<BlockId, HashSet<BlockId>> = HashMap::new();
+    for block in function.reverse_postorder() {
+        for header in function.blocks[block].term.successors() {
+            if !Function::dominates(&idom, header, block) {
+                continue;
+            }
+            let body = loops.entry(header).or_insert_with(|| HashSet::from([header]));
+            let mut worklist = vec![block];
+            while let Some(member) = worklist.pop() {
+                if body.insert(member) {
+                    worklist.extend(predecessors[member].iter().filter(|pred| idom[**pred].is_some()));
+                }
+            }
+        }
+    }
+    let mut loops: Vec<_> = loops.into_iter().collect();
+    loops.sort_by_key(|(header, body)| (body.len(), *header));
+    loops
+}
+
+fn rotate_loops(function: &mut Function) {
+    let mut rotated: HashSet<BlockId> = HashSet::new();
+    for _ in 0..MAX_ROTATIONS {
+        let loops = natural_loops(function);
+        let candidate = loops.iter().find_map(|(header, body)| rotation(function, *header, body, &rotated));
+        let Some((header, entry, inner)) = candidate else { break };
+        rotate(function, header, entry, inner);
+        rotated.insert(inner);
+    }
+}
+
+/// For a loop whose header only evaluates the exit condition, returns the
+/// header, its single entry edge and the first block of the body
+fn rotation(
+    function: &Function,
+    header: BlockId,
+    body: &HashSet<BlockId>,
+    rotated: &HashSet<BlockId>,
+) -> Option<(BlockId, BlockId, BlockId)> {
+    if rotated.contains(&header) || function.blocks[header].insts.len() > MAX_GUARD_SIZE {
+        return None;
+    }
+    let predecessors = function.predecessors();
+    let outside: Vec<BlockId> = predecessors[header].iter().copied().filter(|pred| !body.contains(pred)).collect();
+    let [entry] = outside[..] else { return None };
+    let Terminator::Branch(_, then_block, else_block) = function.blocks[header].term else { return None };
+    let inner = match (body.contains(&then_block), body.contains(&else_block)) {
+        (true, false) => then_block,
+        (false, true) => else_block,
+        _ => return None,
+    };
+    if inner == header || predecessors[inner] != [header] {
+        return None;
+    }
+    // The guard gets its own copy of the condition, so nothing else may use the header's temps
+    let local: HashSet<Temp> = function.blocks[header].insts.iter().filter_map(|inst| inst.dest).collect();
+    let used_elsewhere = function.blocks.iter().enumerate().filter(|(id, _)| *id != header).any(|(_, block)| {
+        block.insts.iter().flat_map(|inst| inst.op.operands()).chain(block.term.operand()).any(|temp| local.contains(&temp))
+    });
+    if used_elsewhere {
+        return None;
+    }
+    Some((header, entry, inner))
+}
+
+fn rotate(function: &mut Function, header: BlockId, entry: BlockId, inner: BlockId) {
+    let guard = function.new_block();
+    let preheader = function.new_block();
+    let mut renamed: HashMap<Temp, Temp> = HashMap::new();
+    let mut insts = function.blocks[header].insts.clone();
+    for inst in &mut insts {
+        for operand in inst.op.operands_mut() {
+            *operand = renamed.get(operand).copied().unwrap_or(*operand);
+        }
+        if let Some(dest) = inst.dest {
+            let fresh = function.new_temp();
+            renamed.insert(dest, fresh);
+            inst.dest = Some(fresh);
+        }
+    }
+    let mut term = function.blocks[header].term.clone();
+    if let Some(operand) = term.operand_mut() {
+        *operand = renamed.get(operand).copied().unwrap_or(*operand);
+    }
+    term.retarget(inner, preheader);
+    function.blocks[guard].insts = insts;
+    function.blocks[guard].term = term;
+    function.blocks[preheader].term = Terminator::Jump(inner);
+    function.blocks[entry].term.retarget(header, guard);
+}
+
+/// The block that jumps into the loop from outside, created if needed
+fn ensure_preheader(function: &mut Function, header: BlockId, body: &HashSet<BlockId>) -> Option<BlockId> {
+    let predecessors = function.predecessors();
+    let outside: Vec<BlockId> = predecessors[header].iter().copied().filter(|pred| !body.contains(pred)).collect();
+    if outside.is_empty() {
+        return None;
+    }
+    if let [entry] = outside[..] {
+        if function.blocks[entry].term == Terminator::Jump(header) {
+            return Some(entry);
+        }
+    }
+    let preheader = function.new_block();
+    function.blocks[preheader].term = Terminator::Jump(header);
+    for pred in outside {
+        function.blocks[pred].term.retarget(header, preheader);
+    }
+    Some(preheader)
+}
+
+fn hoist(function: &mut Function, header: BlockId, body: &HashSet<BlockId>, preheader: BlockId) -> usize {
+    // What is known about variables once the preheader has run
+    let states = fold::variable_states(function);
+    let mut entry_state = states[preheader].clone().unwrap_or_default();
+    for inst in &function.blocks[preheader].insts {
+        if let Op::Store(name, _) = &inst.op {
+            entry_state.insert(name.clone(), VarState::Assigned);
+        }
+    }
+    let stored = function.stored_variables(body);
+    let constants: HashMap<Temp, Const> = function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match (&inst.op, inst.dest) {
+            (Op::Const(value), Some(dest)) => Some((dest, value.clone())),
+            _ => None,
+        })
+        .collect();
+    let mut defined_in_loop: HashSet<Temp> =
+        body.iter().flat_map(|block| &function.blocks[*block].insts).filter_map(|inst| inst.dest).collect();
+
+    let order: Vec<BlockId> = function.reverse_postorder().into_iter().filter(|block| body.contains(block)).collect();
+    let mut hoisted = Vec::new();
+    let mut count = 0;
+    for block in order {
+        let mut barrier = block != header;
+        let insts = std::mem::take(&mut function.blocks[block].insts);
+        let mut kept = Vec::with_capacity(insts.len());
+        for mut inst in insts {
+            let invariant = inst.dest.is_some()
+                && inst.op.is_pure()
+                && !matches!(inst.op, Op::Const(_))
+                && inst.op.operands().iter().all(|temp| !defined_in_loop.contains(temp) || constants.contains_key(temp))
+                && !matches!(&inst.op, Op::Load(name) if stored.contains(name));
+            let fails = match &inst.op {
+                Op::Load(name) => !fold::definitely_assigned(&entry_state, name),
+                op => op.has_effect(),
+            };
+            if invariant && (!fails || !barrier) {
+                // Constants defined in the loop are duplicated rather than moved
+                for operand in inst.op.operands_mut() {
+                    if defined_in_loop.contains(operand) {
+                        let copy = function.new_temp();
+                        hoisted.push(Inst { dest: Some(copy), op: Op::Const(constants[operand].clone()) });
+                        *operand = copy;
+                    }
+                }
+                defined_in_loop.remove(&inst.dest.unwrap());
+                hoisted.push(inst);
+                count += 1;
+                continue;
+            }
+            if fails || matches!(inst.op, Op::Line(_)) {
+                barrier = true;
+            }
+            kept.push(inst);
+        }
+        function.blocks[block].insts = kept;
+    }
+    function.blocks[preheader].insts.extend(hoisted);
+    count
+}
@@ -0,0 +1,423 @@
+// AST to IR lowering
+//
+// Mirrors what `BytecodeCompiler` emits for the same code, so that running
+// the lowered program without any passes behaves exactly like `-O0`. Code
+// the lowering cannot express returns `Unsupported`, and that function (or
+// the top level) is compiled by the `BytecodeCompiler` instead.
+
+use super::{BinOp, BlockId, Builtin, Const, Function, Inst, Module, Op, Temp, Terminator, UnOp, Unit};
+use crate::ast::{
+    BinaryOperator, Expression, InterpolatedPart, Literal, MatchExpression, Pattern, Program, Statement,
+    UnaryOperator,
+};
+use crate::bytecode_compiler::BytecodeCompiler;
+use crate::error::CompilerError;
+use crate::vm::BytecodeInstruction;
+use std::collections::HashSet;
+
+/// Why a function was left to the bytecode compiler
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsupported(pub String);
+
+type Lowered<T> = Result<T, Unsupported>;
+
+pub fn lower_program(program: &Program) -> Result<Module, CompilerError> {
+    // Calls to C functions from `extern` blocks must not become builtins
+    let externs = BytecodeCompiler::extern_functions(program);
+    let python = BytecodeCompiler::python_modules(program);
+    let mut functions = Vec::new();
+    let mut top_level = Vec::new();
+    let mut pending_line = None;
+    for statement in &program.statements {
+        match statement {
+            // A line marker before a function belongs to the function, as in `BytecodeCompiler`
+            Statement::SourceLine(_) => {
+                if let Some(line) = pending_line.replace(statement.clone()) {
+                    top_level.push(line);
+                }
+            }
+            Statement::Function(_) => {
+                let mut unit = Vec::new();
+                unit.extend(pending_line.take());
+                unit.push(statement.clone());
+                functions.push(lower_function(&unit, &externs, &python)?);
+            }
+            _ => {
+                top_level.extend(pending_line.take());
+                top_level.push(statement.clone());
+            }
+        }
+    }
+    top_level.extend(pending_line.take());
+
+    let has_main = program.statements.iter().any(|statement| {
+        matches!(statement, Statement::Function(function) if function.name == "main")
+    });
+    let top_level = match Lowerer::top_level(&top_level, has_main, &externs) {
+        Ok(function) => Unit::Ir(function),
+        Err(_) => {
+            let mut instructions = fallback(&top_level, &externs, &python)?;
+            if has_main {
+                instructions.push(BytecodeInstruction::Call("main".to_string(), 0));
+            }
+            Unit::Bytecode(instructions)
+        }
+    };
+    Ok(Module { functions, top_level })
+}
+
+fn lower_function(statements: &[Statement], externs: &HashSet<String>, python: &HashSet<String>) -> Result<Unit, CompilerError> {
+    match Lowerer::function(statements, externs) {
+        Ok(function) => Ok(Unit::Ir(function)),
+        Err(_) => Ok(Unit::Bytecode(fallback(statements, externs, python)?)),
+    }
+}
+
+// Python modules only appear in the bytecode fallback, since the IR has no member access
+fn fallback(statements: &[Statement], externs: &HashSet<String>, python: &HashSet<String>) -> Result<Vec<BytecodeInstruction>, CompilerError> {
+    let program = Program { statements: statements.to_vec(), annotations: Vec::new() };
+    BytecodeCompiler::new()
+        .with_extern_functions(externs.clone())
+        .with_python_modules(python.clone())
+        .compile_module(&program)
+}
+
+struct Lowerer {
+    function: Function,
+    current: BlockId,
+    match_count: usize,
+    externs: HashSet<String>,
+}
+
+impl Lowerer {
+    fn new(name: Option<String>, params: Vec<String>, externs: &HashSet<String>) -> Self {
+        Self { function: Function::new(name, params), current: 0, match_count: 0, externs: externs.clone() }
+    }
+
+    fn top_level(statements: &[Statement], has_main: bool, externs: &HashSet<String>) -> Lowered<Function> {
+        let mut lowerer = Self::new(None, Vec::new(), externs);
+        for statement in statements {
+            match statement {
+                Statement::Return(_) => return unsupported("return outside a function"),
+                _ => lowerer.statement(statement)?,
+            }
+        }
+        if has_main {
+            lowerer.emit(Op::Call("main".to_string(), Vec::new()));
+        }
+        lowerer.terminate(Terminator::End);
+        Ok(lowerer.function)
+    }
+
+    fn function(statements: &[Statement], externs: &HashSet<String>) -> Lowered<Function> {
+        let (line, function) = match statements {
+            [Statement::SourceLine(line), Statement::Function(function)] => (Some(*line), function),
+            [Statement::Function(function)] => (None, function),
+            _ => return unsupported("not a function definition"),
+        };
+        let Expression::Block(body) = &*function.body else {
+            return unsupported("function body is not a block");
+        };
+        let params = function.parameters.iter().map(|param| param.name.clone()).collect();
+        let mut lowerer = Self::new(Some(function.name.clone()), params, externs);
+        if let Some(line) = line {
+            lowerer.emit_effect(Op::Line(line));
+        }
+        for statement in body {
+            lowerer.statement(statement)?;
+        }
+        let null = lowerer.constant(Const::Null);
+        lowerer.terminate(Terminator::Return(null));
+        Ok(lowerer.function)
+    }
+
+    fn emit(&mut self, op: Op) -> Temp {
+        let dest = self.function.new_temp();
+        self.function.blocks[self.current].insts.push(Inst { dest: Some(dest), op });
+        dest
+    }
+
+    fn emit_effect(&mut self, op: Op) {
+        self.function.blocks[self.current].insts.push(Inst { dest: None, op });
+    }
+
+    fn constant(&mut self, value: Const) -> Temp {
+        self.emit(Op::Const(value))
+    }
+
+    /// End the current block; code that follows goes into a fresh (possibly unreachable) block
+    fn terminate(&mut self, term: Terminator) {
+        self.function.blocks[self.current].term = term;
+        self.current = self.function.new_block();
+    }
+
+    fn switch_to(&mut self, block: BlockId) {
+        self.current = block;
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Lowered<()> {
+        match statement {
+            Statement::SourceLine(line) => self.emit_effect(Op::Line(*line)),
+            Statement::Let(let_stmt) => {
+                let value = self.expression(&let_stmt.value, true)?;
+                self.emit_effect(Op::Store(let_stmt.name.clone(), value));
+            }
+            Statement::AssignmentStatement { name, value } => {
+                let value = self.expression(value, true)?;
+                self.emit_effect(Op::Store(name.clone(), value));
+            }
+            Statement::Expression(Expression::Block(statements)) => {
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+            }
+            Statement::Expression(expression) => {
+                self.expression(expression, false)?;
+            }
+            Statement::Return(return_stmt) => {
+                let value = match &return_stmt.value {
+                    Some(value) => self.expression(value, true)?,
+                    None => self.constant(Const::Null),
+                };
+                self.terminate(Terminator::Return(value));
+            }
+            _ => return unsupported("statement"),
+        }
+        Ok(())
+    }
+
+    /// Lower `expression` and return the temp holding its value. When the
+    /// value is not `wanted`, a match does not join its arms' values.
+    fn expression(&mut self, expression: &Expression, wanted: bool) -> Lowered<Temp> {
+        match expression {
+            Expression::Literal(literal) => self.literal(literal),
+            Expression::Identifier(name) => Ok(self.emit(Op::Load(name.clone()))),
+            Expression::BinaryOp(bin_op) => {
+                let left = self.expression(&bin_op.left, true)?;
+                let right = self.expression(&bin_op.right, true)?;
+                let op = match bin_op.operator {
+                    BinaryOperator::Add => BinOp::Add,
+                    BinaryOperator::Subtract => BinOp::Sub,
+                    BinaryOperator::Multiply => BinOp::Mul,
+                    BinaryOperator::Divide => BinOp::Div,
+                    BinaryOperator::Modulo => BinOp::Mod,
+                    BinaryOperator::Equal => BinOp::Eq,
+                    BinaryOperator::NotEqual => BinOp::Ne,
+                    BinaryOperator::LessThan => BinOp::Lt,
+                    BinaryOperator::LessThanOrEqual => BinOp::Le,
+                    BinaryOperator::GreaterThan => BinOp::Gt,
+                    BinaryOperator::GreaterThanOrEqual => BinOp::Ge,
+                    BinaryOperator::And => BinOp::And,
+                    BinaryOperator::Or => BinOp::Or,
+                    _ => return unsupported("binary operator"),
+                };
+                Ok(self.emit(Op::Binary(op, left, right)))
+            }
+            Expression::UnaryOp(unary_op) => {
+                let operand = self.expression(&unary_op.operand, true)?;
+                let op = match unary_op.operator {
+                    UnaryOperator::Negate => UnOp::Neg,
+                    UnaryOperator::Not => UnOp::Not,
+                    _ => return unsupported("unary operator"),
+                };
+                Ok(self.emit(Op::Unary(op, operand)))
+            }
+            Expression::FunctionCall(function, args) => {
+                let Expression::Identifier(name) = &**function else {
+                    return unsupported("dynamic call");
+                };
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.expression(&arg.value, true)?);
+                }
+                if self.externs.contains(name) {
+                    return Ok(self.emit(Op::Call(name.clone(), values)));
+                }
+                match Builtin::lookup(name) {
+                    Some(builtin) if builtin.arity != values.len() => unsupported("builtin arity"),
+                    Some(builtin) if builtin.returns => Ok(self.emit(Op::Builtin(builtin, values))),
+                    Some(builtin) => {
+                        self.emit_effect(Op::Builtin(builtin, values));
+                        Ok(self.constant(Const::Null))
+                    }
+                    None if Builtin::is_unmodelled(name) => unsupported("builtin"),
+                    None => Ok(self.emit(Op::Call(name.clone(), values))),
+                }
+            }
+            Expression::Block(statements) => {
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                Ok(self.constant(Const::Null))
+            }
+            Expression::If(if_expr) => {
+                let condition = self.expression(&if_expr.condition, true)?;
+                let then_block = self.function.new_block();
+                let after = self.function.new_block();
+                let else_block = if if_expr.else_branch.is_some() { self.function.new_block() } else { after };
+                self.function.blocks[self.current].term = Terminator::Branch(condition, then_block, else_block);
+
+                self.switch_to(then_block);
+                self.expression(&if_expr.then_branch, false)?;
+                self.function.blocks[self.current].term = Terminator::Jump(after);
+                if let Some(else_branch) = &if_expr.else_branch {
+                    self.switch_to(else_block);
+                    self.expression(else_branch, false)?;
+                    self.function.blocks[self.current].term = Terminator::Jump(after);
+                }
+                // If expressions always produce null
+                self.switch_to(after);
+                Ok(self.constant(Const::Null))
+            }
+            Expression::While(while_expr) => {
+                let header = self.function.new_block();
+                let body = self.function.new_block();
+                let after = self.function.new_block();
+                self.function.blocks[self.current].term = Terminator::Jump(header);
+
+                self.switch_to(header);
+                let condition = self.expression(&while_expr.condition, true)?;
+                self.function.blocks[self.current].term = Terminator::Branch(condition, body, after);
+                self.switch_to(body);
+                self.expression(&while_expr.body, false)?;
+                self.function.blocks[self.current].term = Terminator::Jump(header);
+
+                self.switch_to(after);
+                Ok(self.constant(Const::Null))
+            }
+            Expression::ArrayAccess(access) => {
+                let array = self.expression(&access.array, true)?;
+                let index = self.expression(&access.index, true)?;
+                Ok(self.emit(Op::GetIndex(array, index)))
+            }
+            Expression::InterpolatedString(interpolated) => {
+                let concat = Builtin::lookup("concat").expect("concat is a builtin");
+                let mut result: Option<Temp> = None;
+                for part in &interpolated.parts {
+                    let value = match part {
+                        InterpolatedPart::String(text) => self.constant(Const::String(text.clone())),
+                        InterpolatedPart::Expr(expr) => {
+                            let value = self.expression(expr, true)?;
+                            self.emit(Op::Unary(UnOp::ToString, value))
+                        }
+                    };
+                    result = Some(match result {
+                        Some(left) => self.emit(Op::Builtin(concat, vec![left, value])),
+                        None => value,
+                    });
+                }
+                Ok(match result {
+                    Some(result) => result,
+                    None => self.constant(Const::String(String::new())),
+                })
+            }
+            Expression::Assignment(assign) => {
+                let value = self.expression(&assign.value, true)?;
+                self.emit_effect(Op::Store(assign.target.clone(), value));
+                Ok(value)
+            }
+            Expression::Match(match_expr) => self.match_expression(match_expr, wanted),
+            _ => unsupported("expression"),
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal) -> Lowered<Temp> {
+        Ok(match literal {
+            Literal::Array(elements) => {
+                let elements = elements.iter().map(scalar).collect::<Lowered<Vec<_>>>()?;
+                self.emit(Op::ArrayLiteral(elements))
+            }
+            other => {
+                let value = scalar(other)?;
+                self.constant(value)
+            }
+        })
+    }
+
+    fn match_expression(&mut self, match_expr: &MatchExpression, wanted: bool) -> Lowered<Temp> {
+        // Arms join through a hidden variable, as temps have no phi nodes
+        self.match_count += 1;
+        let result = format!("$match{}", self.match_count);
+        let subject = self.expression(&match_expr.expression, true)?;
+        let after = self.function.new_block();
+        for arm in &match_expr.arms {
+            let next_arm = match self.pattern_test(&arm.pattern, subject)? {
+                Some(test) => {
+                    let body = self.function.new_block();
+                    let next_arm = self.function.new_block();
+                    self.function.blocks[self.current].term = Terminator::Branch(test, body, next_arm);
+                    self.switch_to(body);
+                    Some(next_arm)
+                }
+                None => None,
+            };
+            if let Pattern::Identifier(name) = &arm.pattern {
+                self.emit_effect(Op::Store(name.clone(), subject));
+            }
+            let value = self.expression(&arm.body, wanted)?;
+            if wanted {
+                self.emit_effect(Op::Store(result.clone(), value));
+            }
+            self.function.blocks[self.current].term = Terminator::Jump(after);
+            // Arms after one that always matches are unreachable
+            self.current = match next_arm {
+                Some(next_arm) => next_arm,
+                None => self.function.new_block(),
+            };
+        }
+        // A match without a matching arm produces null
+        if wanted {
+            let null = self.constant(Const::Null);
+            self.emit_effect(Op::Store(result.clone(), null));
+        }
+        self.function.blocks[self.current].term = Terminator::Jump(after);
+        self.switch_to(after);
+        Ok(if wanted { self.emit(Op::Load(result)) } else { self.constant(Const::Null) })
+    }
+
+    /// A temp holding whether `subject` matches `pattern`, or `None` for patterns that always match
+    fn pattern_test(&mut self, pattern: &Pattern, subject: Temp) -> Lowered<Option<Temp>> {
+        match pattern {
+            Pattern::Wildcard | Pattern::Identifier(_) => Ok(None),
+            Pattern::Literal(literal) => {
+                let value = scalar(literal)?;
+                let value = self.constant(value);
+                Ok(Some(self.emit(Op::Binary(BinOp::Eq, subject, value))))
+            }
+            Pattern::Or(alternatives) => {
+                let mut result: Option<Temp> = None;
+                for alternative in alternatives {
+                    let test = match self.pattern_test(alternative, subject)? {
+                        Some(test) => test,
+                        None => self.constant(Const::Bool(true)),
+                    };
+                    result = Some(match result {
+                        Some(left) => self.emit(Op::Binary(BinOp::Or, left, test)),
+                        None => test,
+                    });
+                }
+                match result {
+                    Some(result) => Ok(Some(result)),
+                    None => unsupported("empty or-pattern"),
+                }
+            }
+            Pattern::Struct(..) | Pattern::Tuple(_) => unsupported("pattern"),
+        }
+    }
+}
+
+fn scalar(literal: &Literal) -> Lowered<Const> {
+    Ok(match literal {
+        Literal::Int(value) => Const::Int(*value),
+        Literal::Float(value) => Const::Float(*value),
+        Literal::String(value) => Const::String(value.clone()),
+        Literal::Bool(value) => Const::Bool(*value),
+        Literal::Char(value) => Const::String(value.to_string()),
+        Literal::Null => Const::Null,
+        Literal::Array(_) => return unsupported("nested array literal"),
+    })
+}
+
+fn unsupported<T>(what: &str) -> Lowered<T> {
+    Err(Unsupported(what.to_string()))
+}
@@ -0,0 +1,1066 @@
+// Mid-level IR
+//
+// A control-flow graph of basic blocks that sits between the AST and the
+// bytecode when optimizing (`-O1` and up). Every instruction result is a
+// numbered temporary that is assigned exactly once and whose definition
+// dominates its uses; named variables stay explicit `load`/`store`s, which is
+// what the VM executes. `lower` builds the graph, the pass modules rewrite it
+// and `codegen` turns it back into bytecode. Functions the lowering does not
+// understand are compiled by the `BytecodeCompiler` unchanged.
+
+pub mod codegen;
+pub mod cse;
+pub mod dce;
+pub mod fold;
+pub mod inline;
+pub mod licm;
+pub mod lower;
+
+use crate::ast::Program;
+use crate::error::CompilerError;
+use crate::vm::{BytecodeInstruction, VMValue};
+use std::collections::HashSet;
+use std::fmt;
+
+pub type Temp = usize;
+pub type BlockId = usize;
+
+#[derive(Debug, Clone)]
+pub enum Const {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    Null,
+}
+
+// Floats compare by bits so that the constant lattice is stable for NaN
+impl PartialEq for Const {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Const::Int(a), Const::Int(b)) => a == b,
+            (Const::Float(a), Const::Float(b)) => a.to_bits() == b.to_bits(),
+            (Const::Bool(a), Const::Bool(b)) => a == b,
+            (Const::String(a), Const::String(b)) => a == b,
+            (Const::Null, Const::Null) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Const {
+    pub fn to_value(&self) -> VMValue {
+        match self {
+            Const::Int(value) => VMValue::Int(*value),
+            Const::Float(value) => VMValue::Float(*value),
+            Const::Bool(value) => VMValue::Bool(*value),
+            Const::String(value) => VMValue::String(value.clone()),
+            Const::Null => VMValue::Null,
+        }
+    }
+
+    pub fn from_value(value: &VMValue) -> Option<Self> {
+        match value {
+            VMValue::Int(value) => Some(Const::Int(*value)),
+            VMValue::Float(value) => Some(Const::Float(*value)),
+            VMValue::Bool(value) => Some(Const::Bool(*value)),
+            VMValue::String(value) => Some(Const::String(value.clone())),
+            VMValue::Null => Some(Const::Null),
+            _ => None,
+        }
+    }
+
+    pub fn instruction(&self) -> BytecodeInstruction {
+        match self {
+            Const::Int(value) => BytecodeInstruction::PushInt(*value),
+            Const::Float(value) => BytecodeInstruction::PushFloat(*value),
+            Const::Bool(value) => BytecodeInstruction::PushBool(*value),
+            Const::String(value) => BytecodeInstruction::PushString(value.clone()),
+            Const::Null => BytecodeInstruction::PushNull,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    pub fn instruction(self) -> BytecodeInstruction {
+        match self {
+            BinOp::Add => BytecodeInstruction::Add,
+            BinOp::Sub => BytecodeInstruction::Sub,
+            BinOp::Mul => BytecodeInstruction::Mul,
+            BinOp::Div => BytecodeInstruction::Div,
+            BinOp::Mod => BytecodeInstruction::Mod,
+            BinOp::Eq => BytecodeInstruction::Eq,
+            BinOp::Ne => BytecodeInstruction::Ne,
+            BinOp::Lt => BytecodeInstruction::Lt,
+            BinOp::Le => BytecodeInstruction::Le,
+            BinOp::Gt => BytecodeInstruction::Gt,
+            BinOp::Ge => BytecodeInstruction::Ge,
+            BinOp::And => BytecodeInstruction::And,
+            BinOp::Or => BytecodeInstruction::Or,
+        }
+    }
+
+    /// Whether swapping the operands gives the same result, errors included
+    pub fn is_commutative(self) -> bool {
+        matches!(self, BinOp::Mul | BinOp::Eq | BinOp::Ne | BinOp::And | BinOp::Or)
+    }
+
+    /// Equality and truthiness are defined for every pair of values
+    pub fn can_fail(self) -> bool {
+        !matches!(self, BinOp::Eq | BinOp::Ne | BinOp::And | BinOp::Or)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::Div => "div",
+            BinOp::Mod => "mod",
+            BinOp::Eq => "eq",
+            BinOp::Ne => "ne",
+            BinOp::Lt => "lt",
+            BinOp::Le => "le",
+            BinOp::Gt => "gt",
+            BinOp::Ge => "ge",
+            BinOp::And => "and",
+            BinOp::Or => "or",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnOp {
+    Neg,
+    Not,
+    ToString,
+}
+
+impl UnOp {
+    pub fn instruction(self) -> BytecodeInstruction {
+        match self {
+            UnOp::Neg => BytecodeInstruction::Neg,
+            UnOp::Not => BytecodeInstruction::Not,
+            UnOp::ToString => BytecodeInstruction::ToString,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            UnOp::Neg => "neg",
+            UnOp::Not => "not",
+            UnOp::ToString => "to_string",
+        }
+    }
+}
+
+/// A builtin the IR can call: its bytecode, how many arguments it pops and
+/// whether it pushes a result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: usize,
+    pub returns: bool,
+    /// Same arguments always give the same result or the same error, with
+    /// no other effect. Builtins that allocate or read mutable containers
+    /// are not pure.
+    pub pure: bool,
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin { name: "print", arity: 1, returns: false, pure: false },
+    Builtin { name: "println", arity: 1, returns: false, pure: false },
+    Builtin { name: "read_line", arity: 0, returns: true, pure: false },
+    Builtin { name: "read_file", arity: 1, returns: true, pure: false },
+    Builtin { name: "write_file", arity: 2, returns: true, pure: false },
+    Builtin { name: "append_file", arity: 2, returns: true, pure: false },
+    Builtin { name: "file_exists", arity: 1, returns: true, pure: false },
+    Builtin { name: "abs", arity: 1, returns: true, pure: true },
+    Builtin { name: "sqrt", arity: 1, returns: true, pure: true },
+    Builtin { name: "sin", arity: 1, returns: true, pure: true },
+    Builtin { name: "cos", arity: 1, returns: true, pure: true },
+    Builtin { name: "tan", arity: 1, returns: true, pure: true },
+    Builtin { name: "floor", arity: 1, returns: true, pure: true },
+    Builtin { name: "ceil", arity: 1, returns: true, pure: true },
+    Builtin { name: "round", arity: 1, returns: true, pure: true },
+    Builtin { name: "pow", arity: 2, returns: true, pure: true },
+    Builtin { name: "min", arity: 2, returns: true, pure: true },
+    Builtin { name: "max", arity: 2, returns: true, pure: true },
+    Builtin { name: "len", arity: 1, returns: true, pure: false },
+    Builtin { name: "substring", arity: 3, returns: true, pure: true },
+    Builtin { name: "concat", arity: 2, returns: true, pure: true },
+    Builtin { name: "contains", arity: 2, returns: true, pure: true },
+    Builtin { name: "starts_with", arity: 2, returns: true, pure: true },
+    Builtin { name: "ends_with", arity: 2, returns: true, pure: true },
+    Builtin { name: "to_upper", arity: 1, returns: true, pure: true },
+    Builtin { name: "to_lower", arity: 1, returns: true, pure: true },
+    Builtin { name: "trim", arity: 1, returns: true, pure: true },
+    Builtin { name: "split", arity: 2, returns: true, pure: false },
+    Builtin { name: "join", arity: 2, returns: true, pure: false },
+    Builtin { name: "random", arity: 0, returns: true, pure: false },
+    Builtin { name: "random_int", arity: 2, returns: true, pure: false },
+    Builtin { name: "typeof", arity: 1, returns: true, pure: true },
+    Builtin { name: "time", arity: 0, returns: true, pure: false },
+    Builtin { name: "sleep", arity: 1, returns: true, pure: false },
+    Builtin { name: "dict_new", arity: 0, returns: true, pure: false },
+    Builtin { name: "dict_set", arity: 3, returns: true, pure: false },
+    Builtin { name: "dict_get", arity: 2, returns: true, pure: false },
+    Builtin { name: "dict_has", arity: 2, returns: true, pure: false },
+    Builtin { name: "dict_keys", arity: 1, returns: true, pure: false },
+    Builtin { name: "dict_size", arity: 1, returns: true, pure: false },
+    Builtin { name: "dict_clear", arity: 1, returns: true, pure: false },
+    Builtin { name: "array_push", arity: 2, returns: true, pure: false },
+    Builtin { name: "array_reverse", arity: 1, returns: true, pure: false },
+    Builtin { name: "array_sort", arity: 1, returns: true, pure: false },
+    Builtin { name: "array_slice", arity: 3, returns: true, pure: false },
+    Builtin { name: "json_parse", arity: 1, returns: true, pure: false },
+    Builtin { name: "json_stringify", arity: 1, returns: true, pure: false },
+];
+
+/// Builtins the bytecode compiler knows but the IR cannot model, because
+/// they leave several values on the stack or stop the program
+const UNMODELLED_BUILTINS: &[&str] = &[
+    "exit", "array_pop", "dict_remove", "array_filter", "array_map", "array_reduce", "array_find",
+    "try_catch", "throw_error",
+];
+
+impl Builtin {
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        BUILTINS.iter().find(|builtin| builtin.name == name).copied()
+    }
+
+    pub fn is_unmodelled(name: &str) -> bool {
+        UNMODELLED_BUILTINS.contains(&name)
+    }
+
+    pub fn instruction(&self) -> BytecodeInstruction {
+        match self.name {
+            "print" | "println" => BytecodeInstruction::Println,
+            "read_line" => BytecodeInstruction::ReadLine,
+            "read_file" => BytecodeInstruction::ReadFile,
+            "write_file" => BytecodeInstruction::WriteFile,
+            "append_file" => BytecodeInstruction::AppendFile,
+            "file_exists" => BytecodeInstruction::FileExists,
+            "abs" => BytecodeInstruction::Abs,
+            "sqrt" => BytecodeInstruction::Sqrt,
+            "sin" => BytecodeInstruction::Sin,
+            "cos" => BytecodeInstruction::Cos,
+            "tan" => BytecodeInstruction::Tan,
+            "floor" => BytecodeInstruction::Floor,
+            "ceil" => BytecodeInstruction::Ceil,
+            "round" => BytecodeInstruction::Round,
+            "pow" => BytecodeInstruction::Pow,
+            "min" => BytecodeInstruction::Min,
+            "max" => BytecodeInstruction::Max,
+            "len" => BytecodeInstruction::StringLen,
+            "substring" => BytecodeInstruction::Substring,
+            "concat" => BytecodeInstruction::StringConcat,
+            "contains" => BytecodeInstruction::StringContains,
+            "starts_with" => BytecodeInstruction::StringStartsWith,
+            "ends_with" => BytecodeInstruction::StringEndsWith,
+            "to_upper" => BytecodeInstruction::StringToUpper,
+            "to_lower" => BytecodeInstruction::StringToLower,
+            "trim" => BytecodeInstruction::StringTrim,
+            "split" => BytecodeInstruction::StringSplit,
+            "join" => BytecodeInstruction::StringJoin,
+            "random" => BytecodeInstruction::Random,
+            "random_int" => BytecodeInstruction::RandomInt,
+            "typeof" => BytecodeInstruction::TypeOf,
+            "time" => BytecodeInstruction::Time,
+            "sleep" => BytecodeInstruction::Sleep,
+            "dict_new" => BytecodeInstruction::DictNew,
+            "dict_set" => BytecodeInstruction::DictSet,
+            "dict_get" => BytecodeInstruction::DictGet,
+            "dict_has" => BytecodeInstruction::DictHas,
+            "dict_keys" => BytecodeInstruction::DictKeys,
+            "dict_size" => BytecodeInstruction::DictSize,
+            "dict_clear" => BytecodeInstruction::DictClear,
+            "array_push" => BytecodeInstruction::ArrayPush,
+            "array_reverse" => BytecodeInstruction::ArrayReverse,
+            "array_sort" => BytecodeInstruction::ArraySort,
+            "array_slice" => BytecodeInstruction::ArraySlice,
+            "json_parse" => BytecodeInstruction::JsonParse,
+            "json_stringify" => BytecodeInstruction::JsonStringify,
+            other => unreachable!("builtin '{}' has no instruction", other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Const(Const),
+    Load(String),
+    Store(String, Temp),
+    Binary(BinOp, Temp, Temp),
+    Unary(UnOp, Temp),
+    Builtin(Builtin, Vec<Temp>),
+    Call(String, Vec<Temp>),
+    ArrayLiteral(Vec<Const>),
+    GetIndex(Temp, Temp),
+    /// Source line marker, kept in place for coverage and heap profiles
+    Line(usize),
+}
+
+impl Op {
+    pub fn operands(&self) -> Vec<Temp> {
+        match self {
+            Op::Store(_, value) | Op::Unary(_, value) => vec![*value],
+            Op::Binary(_, left, right) | Op::GetIndex(left, right) => vec![*left, *right],
+            Op::Builtin(_, args) | Op::Call(_, args) => args.clone(),
+            Op::Const(_) | Op::Load(_) | Op::ArrayLiteral(_) | Op::Line(_) => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Temp> {
+        match self {
+            Op::Store(_, value) | Op::Unary(_, value) => vec![value],
+            Op::Binary(_, left, right) | Op::GetIndex(left, right) => vec![left, right],
+            Op::Builtin(_, args) | Op::Call(_, args) => args.iter_mut().collect(),
+            Op::Const(_) | Op::Load(_) | Op::ArrayLiteral(_) | Op::Line(_) => Vec::new(),
+        }
+    }
+
+    /// The result depends only on the operands (and for loads, the variable),
+    /// so equal instructions can share a result or move out of loops
+    pub fn is_pure(&self) -> bool {
+        match self {
+            Op::Const(_) | Op::Load(_) | Op::Binary(..) => true,
+            Op::Unary(op, _) => *op != UnOp::ToString,
+            Op::Builtin(builtin, _) => builtin.pure,
+            _ => false,
+        }
+    }
+
+    /// Whether running the instruction can be observed other than through
+    /// its result: output, calls, or a runtime error
+    pub fn has_effect(&self) -> bool {
+        match self {
+            Op::Const(_) | Op::ArrayLiteral(_) => false,
+            Op::Binary(op, ..) => op.can_fail(),
+            Op::Unary(op, _) => *op == UnOp::Neg,
+            Op::Load(_) | Op::Store(..) | Op::Line(_) => false,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inst {
+    pub dest: Option<Temp>,
+    pub op: Op,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    Branch(Temp, BlockId, BlockId),
+    Return(Temp),
+    /// End of the top-level code
+    End,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch(_, then_block, else_block) => vec![*then_block, *else_block],
+            Terminator::Return(_) | Terminator::End => Vec::new(),
+        }
+    }
+
+    pub fn operand(&self) -> Option<Temp> {
+        match self {
+            Terminator::Branch(condition, ..) | Terminator::Return(condition) => Some(*condition),
+            _ => None,
+        }
+    }
+
+    pub fn operand_mut(&mut self) -> Option<&mut Temp> {
+        match self {
+            Terminator::Branch(condition, ..) | Terminator::Return(condition) => Some(condition),
+            _ => None,
+        }
+    }
+
+    pub fn retarget(&mut self, from: BlockId, to: BlockId) {
+        match self {
+            Terminator::Jump(target) if *target == from => *target = to,
+            Terminator::Branch(_, then_block, else_block) => {
+                if *then_block == from {
+                    *then_block = to;
+                }
+                if *else_block == from {
+                    *else_block = to;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+/// One function, or the top-level code when `name` is `None`. Block 0 is the entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: Option<String>,
+    pub params: Vec<String>,
+    pub blocks: Vec<Block>,
+    pub temp_count: usize,
+}
+
+impl Function {
+    pub fn new(name: Option<String>, params: Vec<String>) -> Self {
+        Self {
+            name,
+            params,
+            blocks: vec![Block { insts: Vec::new(), term: Terminator::End }],
+            temp_count: 0,
+        }
+    }
+
+    pub fn new_temp(&mut self) -> Temp {
+        self.temp_count += 1;
+        self.temp_count - 1
+    }
+
+    pub fn new_block(&mut self) -> BlockId {
+        self.blocks.push(Block { insts: Vec::new(), term: Terminator::End });
+        self.blocks.len() - 1
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for (id, block) in self.blocks.iter().enumerate() {
+            for successor in block.term.successors() {
+                if !predecessors[successor].contains(&id) {
+                    predecessors[successor].push(id);
+                }
+            }
+        }
+        predecessors
+    }
+
+    /// Blocks reachable from the entry, in reverse postorder
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        // Iterative DFS with an explicit successor cursor per frame
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((block, next)) = stack.pop() {
+            let successors = self.blocks[block].term.successors();
+            if next < successors.len() {
+                stack.push((block, next + 1));
+                let successor = successors[next];
+                if !visited[successor] {
+                    visited[successor] = true;
+                    stack.push((successor, 0));
+                }
+            } else {
+                order.push(block);
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Immediate dominator of every reachable block (the entry is its own),
+    /// using the Cooper-Harvey-Kennedy iteration
+    pub fn dominators(&self) -> Vec<Option<BlockId>> {
+        let order = self.reverse_postorder();
+        let mut position = vec![usize::MAX; self.blocks.len()];
+        for (index, block) in order.iter().enumerate() {
+            position[*block] = index;
+        }
+        let predecessors = self.predecessors();
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        idom[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let mut new_idom: Option<BlockId> = None;
+                for &predecessor in &predecessors[block] {
+                    if idom[predecessor].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => predecessor,
+                        Some(current) => {
+                            let (mut a, mut b) = (predecessor, current);
+                            while a != b {
+                                while position[a] > position[b] {
+                                    a = idom[a].unwrap();
+                                }
+                                while position[b] > position[a] {
+                                    b = idom[b].unwrap();
+                                }
+                            }
+                            a
+                        }
+                    });
+                }
+                if new_idom.is_some() && idom[block] != new_idom {
+                    idom[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom
+    }
+
+    pub fn dominates(idom: &[Option<BlockId>], a: BlockId, mut b: BlockId) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+            match idom[b] {
+                Some(parent) if parent != b => b = parent,
+                _ => return false,
+            }
+        }
+    }
+
+    /// How many times each temp is used, terminators included
+    pub fn use_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.temp_count];
+        for block in &self.blocks {
+            for inst in &block.insts {
+                for operand in inst.op.operands() {
+                    counts[operand] += 1;
+                }
+            }
+            if let Some(operand) = block.term.operand() {
+                counts[operand] += 1;
+            }
+        }
+        counts
+    }
+
+    /// Replace every use of a temp by its entry in `replacements`, following chains
+    pub fn replace_uses(&mut self, replacements: &std::collections::HashMap<Temp, Temp>) {
+        if replacements.is_empty() {
+            return;
+        }
+        let resolve = |mut temp: Temp| {
+            while let Some(next) = replacements.get(&temp) {
+                temp = *next;
+            }
+            temp
+        };
+        for block in &mut self.blocks {
+            for inst in &mut block.insts {
+                for operand in inst.op.operands_mut() {
+                    *operand = resolve(*operand);
+                }
+            }
+            if let Some(operand) = block.term.operand_mut() {
+                *operand = resolve(*operand);
+            }
+        }
+    }
+
+    /// Variables stored anywhere in `blocks`
+    pub fn stored_variables(&self, blocks: &HashSet<BlockId>) -> HashSet<String> {
+        let mut stored = HashSet::new();
+        for &block in blocks {
+            for inst in &self.blocks[block].insts {
+                if let Op::Store(name, _) = &inst.op {
+                    stored.insert(name.clone());
+                }
+            }
+        }
+        stored
+    }
+
+    pub fn instruction_count(&self) -> usize {
+        self.blocks.iter().map(|block| block.insts.len() + 1).sum()
+    }
+}
+
+/// A compiled unit: optimizable IR, or bytecode from the `BytecodeCompiler`
+/// for code the lowering does not cover. Fallback bytecode for a function
+/// includes its `DefineFunction` and `EndFunction`; jump targets are
+/// relative to the start of the unit.
+#[derive(Debug, Clone)]
+pub enum Unit {
+    Ir(Function),
+    Bytecode(Vec<BytecodeInstruction>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Module {
+    /// Function definitions in source order
+    pub functions: Vec<Unit>,
+    /// The top-level statements, followed by the call to `main` if there is one
+    pub top_level: Unit,
+}
+
+impl Module {
+    pub fn ir_functions_mut(&mut self) -> impl Iterator<Item = &mut Function> {
+        self.functions.iter_mut().chain(std::iter::once(&mut self.top_level)).filter_map(|unit| match unit {
+            Unit::Ir(function) => Some(function),
+            Unit::Bytecode(_) => None,
+        })
+    }
+}
+
+/// Counts of what each pass changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PassStats {
+    pub constants_folded: usize,
+    pub instructions_removed: usize,
+    pub expressions_shared: usize,
+    pub instructions_hoisted: usize,
+    pub calls_inlined: usize,
+}
+
+/// Lower `program` and optimize it at `level` (1 or 2)
+pub fn optimize_program(program: &Program, level: u8) -> Result<(Module, PassStats), CompilerError> {
+    let mut module = lower::lower_program(program)?;
+    let mut stats = PassStats::default();
+    if level >= 2 {
+        stats.calls_inlined += inline::inline_calls(&mut module);
+    }
+    let rounds = if level >= 2 { 2 } else { 1 };
+    for function in module.ir_functions_mut() {
+        for _ in 0..rounds {
+            stats.constants_folded += fold::propagate_constants(function);
+            stats.instructions_removed += dce::eliminate_dead_code(function);
+            // Loop rotation needs the header's temps to be private, so it runs before CSE
+            if level >= 2 {
+                stats.instructions_hoisted += licm::hoist_loop_invariants(function);
+            }
+            stats.expressions_shared += cse::eliminate_common_subexpressions(function);
+            stats.instructions_removed += dce::eliminate_dead_code(function);
+        }
+    }
+    Ok((module, stats))
+}
+
+/// Compile `program` to bytecode. Level 0 uses the `BytecodeCompiler`
+/// directly; higher levels go through the IR.
+pub fn compile_program(program: &Program, level: u8) -> Result<Vec<BytecodeInstruction>, CompilerError> {
+    if level == 0 {
+        return crate::bytecode_compiler::BytecodeCompiler::new().compile_program(program);
+    }
+    let (module, _) = optimize_program(program, level)?;
+    Ok(codegen::generate(&module))
+}
+
+impl fmt::Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Const::Int(value) => write!(f, "{}", value),
+            Const::Float(value) => write!(f, "{:?}", value),
+            Const::Bool(value) => write!(f, "{}", value),
+            Const::String(value) => write!(f, "{:?}", value),
+            Const::Null => write!(f, "null"),
+        }
+    }
+}
+
+fn join_temps(temps: &[Temp]) -> String {
+    temps.iter().map(|temp| format!("%{}", temp)).collect::<Vec<_>>().join(", ")
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(dest) = self.dest {
+            write!(f, "%{} = ", dest)?;
+        }
+        match &self.op {
+            Op::Const(value) => write!(f, "const {}", value),
+            Op::Load(name) => write!(f, "load {}", name),
+            Op::Store(name, value) => write!(f, "store {}, %{}", name, value),
+            Op::Binary(op, left, right) => write!(f, "{} %{}, %{}", op.name(), left, right),
+            Op::Unary(op, value) => write!(f, "{} %{}", op.name(), value),
+            Op::Builtin(builtin, args) => write!(f, "builtin {}({})", builtin.name, join_temps(args)),
+            Op::Call(name, args) => write!(f, "call {}({})", name, join_temps(args)),
+            Op::ArrayLiteral(elements) => {
+                let elements: Vec<String> = elements.iter().map(|element| element.to_string()).collect();
+                write!(f, "array [{}]", elements.join(", "))
+            }
+            Op::GetIndex(array, index) => write!(f, "index %{}, %{}", array, index),
+            Op::Line(line) => write!(f, "line {}", line),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => writeln!(f, "fn {}({}) {{", name, self.params.join(", "))?,
+            None => writeln!(f, "top level {{")?,
+        }
+        for (id, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "b{}:", id)?;
+            for inst in &block.insts {
+                writeln!(f, "    {}", inst)?;
+            }
+            match &block.term {
+                Terminator::Jump(target) => writeln!(f, "    jump b{}", target)?,
+                Terminator::Branch(condition, then_block, else_block) => {
+                    writeln!(f, "    branch %{}, b{}, b{}", condition, then_block, else_block)?
+                }
+                Terminator::Return(value) => writeln!(f, "    return %{}", value)?,
+                Terminator::End => writeln!(f, "    end")?,
+            }
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for unit in self.functions.iter().chain(std::iter::once(&self.top_level)) {
+            match unit {
+                Unit::Ir(function) => writeln!(f, "{}", function)?,
+                Unit::Bytecode(instructions) => {
+                    writeln!(f, "bytecode ({} instructions, not lowered)", instructions.len())?;
+                    writeln!(f)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::test_framework::golden::{run_program_at, ProgramOutput};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn parse(source: &str) -> Program {
+        let tokens = Lexer::new(source, "test.nx".to_string()).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    fn optimize(source: &str) -> (Module, PassStats) {
+        optimize_program(&parse(source), 2).unwrap()
+    }
+
+    /// Run `source` at -O0 and -O2 and require identical output
+    fn assert_same_behaviour(source: &str) -> ProgramOutput {
+        let unoptimized = run_program_at(source, "test.nx", 0);
+        for level in [1, 2] {
+            let optimized = run_program_at(source, "test.nx", level);
+            assert_eq!(unoptimized, optimized, "-O{} changed the behaviour of:\n{}", level, source);
+        }
+        unoptimized
+    }
+
+    fn ir_function<'a>(module: &'a Module, name: Option<&str>) -> &'a Function {
+        let unit = match name {
+            None => &module.top_level,
+            Some(name) => module
+                .functions
+                .iter()
+                .find(|unit| matches!(unit, Unit::Ir(function) if function.name.as_deref() == Some(name)))
+                .unwrap(),
+        };
+        match unit {
+            Unit::Ir(function) => function,
+            Unit::Bytecode(_) => panic!("not lowered"),
+        }
+    }
+
+    fn count_ops(function: &Function, predicate: impl Fn(&Op) -> bool) -> usize {
+        function.blocks.iter().flat_map(|block| &block.insts).filter(|inst| predicate(&inst.op)).count()
+    }
+
+    #[test]
+    fn test_constant_propagation() {
+        let source = "let x = 2 * 3;\nlet y = x + 1;\nif y > 5 {\n    println(\"big \" + y);\n} else {\n    println(\"small\");\n}\n";
+        assert_eq!(assert_same_behaviour(source).stdout, "big 7\n");
+        let (module, stats) = optimize(source);
+        let top_level = ir_function(&module, None);
+        assert!(stats.constants_folded > 0);
+        assert_eq!(count_ops(top_level, |op| matches!(op, Op::Binary(..))), 0);
+        // The branch was decided at compile time, so only one block is left
+        assert_eq!(top_level.blocks.len(), 1);
+    }
+
+    #[test]
+    fn test_folding_leaves_failures_to_runtime() {
+        assert_same_behaviour("println(\"before\");\nlet x = 1 / 0;\nprintln(\"after\");\n");
+        assert_same_behaviour("let big = 9223372036854775807;\nprintln(big - 1);\n");
+        assert_same_behaviour("println(\"a\" - 1);\n");
+    }
+
+    #[test]
+    fn test_dead_code_elimination() {
+        let source = "let unused = 5 * 5;\nlet x = 1;\nx = 2;\nprintln(x);\nwhile false {\n    println(\"never\");\n}\n";
+        assert_eq!(assert_same_behaviour(source).stdout, "2\n");
+        let (module, _) = optimize(source);
+        let top_level = ir_function(&module, None);
+        assert_eq!(count_ops(top_level, |op| matches!(op, Op::Store(..))), 0);
+        assert_eq!(top_level.blocks.len(), 1);
+    }
+
+    #[test]
+    fn test_dead_loads_of_undefined_variables_stay() {
+        let output = assert_same_behaviour("fn main() {\n    let unused = missing;\n    println(\"unreachable\");\n}\n");
+        assert!(output.stderr.contains("Undefined variable: missing"));
+    }
+
+    #[test]
+    fn test_common_subexpressions() {
+        let source = "fn f(a: Int, b: Int) {\n    let x = a * b + 1;\n    let y = b * a + 1;\n    return x + y;\n}\nfn main() {\n    println(f(3, 4));\n}\n";
+        assert_eq!(assert_same_behaviour(source).stdout, "26\n");
+        let (module, stats) = optimize(source);
+        assert!(stats.expressions_shared > 0);
+        let f = ir_function(&module, Some("f"));
+        assert_eq!(count_ops(f, |op| matches!(op, Op::Binary(BinOp::Mul, ..))), 1);
+    }
+
+    #[test]
+    fn test_loads_are_not_shared_across_stores() {
+        assert_same_behaviour("let x = 1;\nlet a = x + 1;\nx = 10;\nlet b = x + 1;\nprintln(a + b);\n");
+    }
+
+    #[test]
+    fn test_loop_invariant_code_motion() {
+        let source = "fn scale(n: Int, count: Int) {\n    let total = 0;\n    let i = 0;\n    while i < count {\n        total = total + n * n;\n        i = i + 1;\n    }\n    return total;\n}\nfn main() {\n    println(scale(7, 3));\n    println(scale(7, 0));\n}\n";
+        assert_eq!(assert_same_behaviour(source).stdout, "147\n0\n");
+        let (module, stats) = optimize(source);
+        assert!(stats.instructions_hoisted > 0);
+        // `n * n` is computed once, in the preheader outside the loop
+        let scale = ir_function(&module, Some("scale"));
+        for (id, block) in scale.blocks.iter().enumerate() {
+            if block.insts.iter().any(|inst| matches!(inst.op, Op::Binary(BinOp::Mul, ..))) {
+                assert!(!in_cycle(scale, id), "{}", scale);
+            }
+        }
+    }
+
+    fn in_cycle(function: &Function, start: BlockId) -> bool {
+        let mut seen = HashSet::new();
+        let mut worklist = function.blocks[start].term.successors();
+        while let Some(block) = worklist.pop() {
+            if block == start {
+                return true;
+            }
+            if seen.insert(block) {
+                worklist.extend(function.blocks[block].term.successors());
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn test_hoisting_keeps_errors_in_place() {
+        // The loop never runs, so the division by zero must not happen
+        assert_same_behaviour("let d = 0;\nlet i = 0;\nwhile i < 0 {\n    println(10 / d);\n    i = i + 1;\n}\nprintln(\"done\");\n");
+        // The failing division comes after output in the first iteration
+        assert_same_behaviour("let d = 0;\nlet i = 0;\nwhile i < 3 {\n    println(i);\n    println(10 / d);\n    i = i + 1;\n}\n");
+        assert_same_behaviour("let i = 0;\nwhile i < 3 {\n    println(i);\n    println(later);\n    i = i + 1;\n}\n");
+    }
+
+    #[test]
+    fn test_inlining() {
+        let source = "fn square(x: Int) {\n    return x * x;\n}\nfn pick(c: Bool, a: Int, b: Int) {\n    if c {\n        return a;\n    }\n    return b;\n}\nfn main() {\n    let x = 1;\n    println(square(5) + x);\n    println(pick(true, 1, 2) + pick(false, 1, 2));\n}\n";
+        assert_eq!(assert_same_behaviour(source).stdout, "26\n3\n");
+        let (module, stats) = optimize(source);
+        assert!(stats.calls_inlined >= 3);
+        let main = ir_function(&module, Some("main"));
+        assert_eq!(count_ops(main, |op| matches!(op, Op::Call(..))), 0);
+        // Folding through the inlined bodies leaves only the printed constants
+        assert_eq!(count_ops(main, |op| matches!(op, Op::Binary(..))), 0);
+    }
+
+    #[test]
+    fn test_inlined_variables_stay_separate() {
+        assert_same_behaviour("fn bump(x: Int) {\n    let y = x + 1;\n    return y;\n}\nfn main() {\n    let x = 10;\n    let y = 20;\n    println(bump(x) + y);\n    println(x + y);\n}\n");
+        // Recursive and unknown callees are left alone
+        assert_same_behaviour("fn fact(n: Int) {\n    if n <= 1 {\n        return 1;\n    }\n    return n * fact(n - 1);\n}\nfn main() {\n    println(fact(10));\n    println(nothing(1));\n}\n");
+        // A callee reading a variable it never set fails in the callee, not the caller
+        assert_same_behaviour("fn leak() {\n    return x;\n}\nfn main() {\n    let x = 1;\n    println(leak());\n}\n");
+    }
+
+    #[test]
+    fn test_inlining_keeps_tail_calls() {
+        // `describe` is inlined into `outer` in tail position, so its returns become `outer`'s
+        let source = "fn add(a: Int, b: Int) {\n    return a + b;\n}\nfn sum_to(n: Int, total: Int) {\n    if n == 0 {\n        return total;\n    }\n    return sum_to(n - 1, add(total, n));\n}\nfn describe(n: Int) {\n    if n > 100 {\n        return sum_to(n, 0);\n    }\n    return 0;\n}\nfn outer(n: Int) {\n    return describe(n);\n}\nfn main() {\n    println(outer(50000));\n}\n";
+        assert_eq!(assert_same_behaviour(source).stdout, "1250025000\n");
+        let code = compile_program(&parse(source), 2).unwrap();
+        let tail_calls = code
+            .iter()
+            .filter(|instruction| matches!(instruction, BytecodeInstruction::TailCall(name, 2) if name == "sum_to"))
+            .count();
+        assert_eq!(tail_calls, 3);
+    }
+
+    #[test]
+    fn test_unsupported_code_falls_back_to_bytecode() {
+        let source = "fn main() {\n    let items = [3, 1, 2];\n    let last = array_pop(items);\n    println(last);\n}\n";
+        let (module, _) = optimize(source);
+        assert!(matches!(module.functions[0], Unit::Bytecode(_)));
+        assert_same_behaviour(source);
+    }
+
+    #[test]
+    fn test_match_and_interpolation() {
+        assert_same_behaviour(
+            "fn describe(n: Int) {\n    return match n {\n        0 => \"zero\",\n        1 | 2 => \"small\",\n        other => \"big {other}\",\n    };\n}\nfn main() {\n    let i = 0;\n    while i < 5 {\n        println(describe(i * 2));\n        i = i + 1;\n    }\n}\n",
+        );
+    }
+
+    #[test]
+    fn test_emitted_ir() {
+        let (module, _) = optimize("let x = 1 + 2;\nprintln(x);\n");
+        assert_eq!(module.to_string(), "top level {\nb0:\n    %3 = const 3\n    builtin println(%3)\n    end\n}\n\n");
+    }
+
+    /// Builds random but well-formed programs: every variable is assigned
+    /// before use and loops are bounded. A few divisions are left unguarded
+    /// so that runtime errors are compared too.
+    struct ProgramGenerator {
+        rng: StdRng,
+        functions: Vec<(String, usize)>,
+    }
+
+    impl ProgramGenerator {
+        fn expression(&mut self, variables: &[String], depth: usize) -> String {
+            if depth == 0 || self.rng.gen_ratio(1, 4) {
+                return if self.rng.gen_bool(0.6) && !variables.is_empty() {
+                    variables[self.rng.gen_range(0..variables.len())].clone()
+                } else {
+                    self.rng.gen_range(-3..10).to_string()
+                };
+            }
+            let left = self.expression(variables, depth - 1);
+            let right = self.expression(variables, depth - 1);
+            match self.rng.gen_range(0..40) {
+                0..=3 if !self.functions.is_empty() => {
+                    let (name, arity) = self.functions[self.rng.gen_range(0..self.functions.len())].clone();
+                    let args: Vec<String> = (0..arity).map(|_| self.expression(variables, depth - 1)).collect();
+                    format!("{}({})", name, args.join(", "))
+                }
+                4..=5 => format!("-({})", left),
+                6..=7 => format!("abs({})", left),
+                8..=9 => format!("max({}, {})", left, right),
+                10..=13 => format!("({} / (abs({}) + 1))", left, right),
+                14..=17 => format!("({} % (abs({}) + 2))", left, right),
+                18 => format!("({} / {})", left, right),
+                19..=25 => format!("({} - {})", left, right),
+                26..=31 => format!("({} * {})", left, right),
+                _ => format!("({} + {})", left, right),
+            }
+        }
+
+        fn condition(&mut self, variables: &[String]) -> String {
+            let left = self.expression(variables, 2);
+            let right = self.expression(variables, 1);
+            let comparison = ["==", "!=", "<", "<=", ">", ">="][self.rng.gen_range(0..6)];
+            match self.rng.gen_range(0..6) {
+                0 => format!("!({} {} {})", left, comparison, right),
+                1 => {
+                    let other = self.expression(variables, 1);
+                    format!("({} {} {}) && ({} > 0)", left, comparison, right, other)
+                }
+                2 => {
+                    let other = self.expression(variables, 1);
+                    format!("({} {} {}) || ({} == 1)", left, comparison, right, other)
+                }
+                _ => format!("{} {} {}", left, comparison, right),
+            }
+        }
+
+        /// Values are reduced after each assignment so they stay far from overflow
+        fn block(&mut self, variables: &mut Vec<String>, indent: usize, depth: usize, out: &mut String) {
+            let pad = "    ".repeat(indent);
+            for _ in 0..self.rng.gen_range(1..6) {
+                match self.rng.gen_range(0..7) {
+                    0 if depth > 0 => {
+                        let condition = self.condition(variables);
+                        out.push_str(&format!("{}if ({}) {{\n", pad, condition));
+                        self.block(&mut variables.clone(), indent + 1, depth - 1, out);
+                        out.push_str(&format!("{}}} else {{\n", pad));
+                        self.block(&mut variables.clone(), indent + 1, depth - 1, out);
+                        out.push_str(&format!("{}}}\n", pad));
+                    }
+                    1 if depth > 0 => {
+                        let counter = format!("i{}", indent);
+                        let limit = self.expression(variables, 1);
+                        out.push_str(&format!("{}let {} = 0;\n", pad, counter));
+                        out.push_str(&format!("{}while {} < min({}, 4) {{\n", pad, counter, limit));
+                        let mut inner = variables.clone();
+                        self.block(&mut inner, indent + 1, depth - 1, out);
+                        out.push_str(&format!("{}    {} = {} + 1;\n", pad, counter, counter));
+                        out.push_str(&format!("{}}}\n", pad));
+                    }
+                    2 | 3 => {
+                        let value = self.expression(variables, 3);
+                        out.push_str(&format!("{}println(\"p \" + {});\n", pad, value));
+                    }
+                    4 if !variables.is_empty() => {
+                        let target = variables[self.rng.gen_range(0..variables.len())].clone();
+                        let value = self.expression(variables, 3);
+                        out.push_str(&format!("{}{} = ({}) % 1000;\n", pad, target, value));
+                    }
+                    _ => {
+                        let name = format!("v{}", self.rng.gen_range(0..100));
+                        let value = self.expression(variables, 3);
+                        out.push_str(&format!("{}let {} = ({}) % 1000;\n", pad, name, value));
+                        if !variables.contains(&name) {
+                            variables.push(name);
+                        }
+                    }
+                }
+            }
+        }
+
+        fn program(seed: u64) -> String {
+            let mut generator = ProgramGenerator { rng: StdRng::seed_from_u64(seed), functions: Vec::new() };
+            let mut out = String::new();
+            for index in 0..generator.rng.gen_range(0..3) {
+                let params: Vec<String> = (0..generator.rng.gen_range(0..3)).map(|p| format!("p{}", p)).collect();
+                let typed: Vec<String> = params.iter().map(|param| format!("{}: Int", param)).collect();
+                out.push_str(&format!("fn f{}({}) {{\n", index, typed.join(", ")));
+                let mut variables = params.clone();
+                generator.block(&mut variables, 1, 1, &mut out);
+                let result = generator.expression(&variables, 2);
+                out.push_str(&format!("    return ({}) % 1000;\n}}\n", result));
+                generator.functions.push((format!("f{}", index), params.len()));
+            }
+            out.push_str("fn main() {\n");
+            generator.block(&mut Vec::new(), 1, 2, &mut out);
+            out.push_str("}\n");
+            out
+        }
+    }
+
+    #[test]
+    fn test_random_programs_behave_the_same() {
+        for seed in 0..400 {
+            assert_same_behaviour(&ProgramGenerator::program(seed));
+        }
+    }
+}
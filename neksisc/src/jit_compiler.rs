@@ -1,111 +1,1292 @@
-use std::collections::HashMap;
-use crate::vm::{VMValue, BytecodeInstruction};
+// JIT compilation of hot functions
+//
+// A function that is called often enough is compiled to native code with
+// Cranelift, specialized for the types of the arguments it was called with.
+// Only numeric code qualifies: Int, Float and Bool values, arithmetic,
+// comparisons, jumps and direct calls to other functions that qualify for
+// their argument types. The types of every local and stack slot are worked
+// out ahead of time, so compiled code needs no tags and no type checks; the
+// VM checks the argument types before each compiled call instead and keeps
+// interpreting when they are not the ones a version was compiled for.
+//
+// Compiled code has no side effects, so whenever it runs into something it
+// cannot finish the way the interpreter would (an Int overflow, a division
+// by zero, a comparison with NaN, recursion too deep for the native stack)
+// it bails out and the VM runs the whole call again in the interpreter,
+// which then fails or carries on exactly as it would without the JIT. A
+// version that keeps bailing out is thrown away.
+
+use crate::vm::{BytecodeInstruction, VMValue};
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{types, AbiParam, Block, InstBuilder, MemFlags, Signature, Type, UserFuncName, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// Interpreted calls before a function is compiled for the argument types of the next one
+pub const DEFAULT_HOT_THRESHOLD: usize = 50;
+// Versions kept per function, one for each combination of argument types
+const MAX_VERSIONS: usize = 4;
+// Bail-outs after which a version is thrown away
+const MAX_BAILOUTS: usize = 3;
+const MAX_PARAMS: usize = 8;
+// Nested compiled calls before bailing out, whatever the VM's own limit is
+const NATIVE_DEPTH_LIMIT: u64 = 2_000;
+// Rounds of the return type fixpoint before giving up on a batch
+const MAX_ROUNDS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JitType {
+    Int,
+    Float,
+    Bool,
+    Null,
+}
+
+impl JitType {
+    pub fn of(value: &VMValue) -> Option<JitType> {
+        match value {
+            VMValue::Int(_) => Some(JitType::Int),
+            VMValue::Float(_) => Some(JitType::Float),
+            VMValue::Bool(_) => Some(JitType::Bool),
+            VMValue::Null => Some(JitType::Null),
+            _ => None,
+        }
+    }
+
+    fn clif(self) -> Type {
+        match self {
+            JitType::Int => types::I64,
+            JitType::Float => types::F64,
+            JitType::Bool | JitType::Null => types::I8,
+        }
+    }
+
+    fn is_number(self) -> bool {
+        matches!(self, JitType::Int | JitType::Float)
+    }
+
+    // Values cross into compiled code as raw 64-bit words
+    fn to_word(value: &VMValue) -> i64 {
+        match value {
+            VMValue::Int(i) => *i,
+            VMValue::Float(f) => f.to_bits() as i64,
+            VMValue::Bool(b) => *b as i64,
+            _ => 0,
+        }
+    }
+
+    fn value(self, word: i64) -> VMValue {
+        match self {
+            JitType::Int => VMValue::Int(word),
+            JitType::Float => VMValue::Float(f64::from_bits(word as u64)),
+            JitType::Bool => VMValue::Bool(word != 0),
+            JitType::Null => VMValue::Null,
+        }
+    }
+}
+
+// A function and the argument types it is compiled for
+type Spec = (String, Vec<JitType>);
+
+// Shared by all compiled frames of one call from the interpreter
+#[repr(C)]
+struct JitContext {
+    depth: u64,
+    max_depth: u64,
+    bailed: u8,
+}
+
+const DEPTH_OFFSET: i32 = 0;
+const MAX_DEPTH_OFFSET: i32 = 8;
+const BAILED_OFFSET: i32 = 16;
+
+type Entry = unsafe extern "C" fn(*mut JitContext, *const i64) -> i64;
+
+struct Version {
+    params: Vec<JitType>,
+    result: JitType,
+    entry: Entry,
+    bailouts: usize,
+}
+
+impl Version {
+    fn accepts(&self, args: &[VMValue]) -> bool {
+        self.params.len() == args.len() && self.params.iter().zip(args).all(|(param, arg)| JitType::of(arg) == Some(*param))
+    }
+}
+
+struct FunctionState {
+    interpreted_calls: usize,
+    // A function with a loop can spend long enough in a single call that it
+    // is worth compiling straight away
+    has_loop: bool,
+    versions: Vec<Version>,
+}
+
+impl FunctionState {
+    fn new(instructions: &[BytecodeInstruction], (start, end, _): (usize, usize, usize)) -> Self {
+        let has_loop = instructions[start..end].iter().enumerate().any(|(index, instruction)| match instruction {
+            BytecodeInstruction::Jump(address)
+            | BytecodeInstruction::JumpIfFalse(address)
+            | BytecodeInstruction::JumpIfTrue(address) => *address <= start + index,
+            _ => false,
+        });
+        Self { interpreted_calls: 0, has_loop, versions: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JitStats {
+    // Function versions compiled to native code, including callees compiled along with them
+    pub compiled: usize,
+    // Versions that could not be compiled or were thrown away
+    pub rejected: usize,
+    // Calls from the interpreter that completed natively
+    pub native_calls: usize,
+    // Calls to a compiled function with argument types none of its versions accept
+    pub guard_failures: usize,
+    // Compiled calls that were run again in the interpreter
+    pub bailouts: usize,
+}
+
+extern "C" fn float_mod(a: f64, b: f64) -> f64 {
+    a % b
+}
 
 pub struct JITCompiler {
-    hot_functions: HashMap<String, usize>, // Function name -> call count
-    compiled_functions: HashMap<String, fn(&[VMValue]) -> VMValue>,
+    module: JITModule,
     hot_threshold: usize,
+    functions: HashMap<String, FunctionState>,
+    // Every version compiled so far, callees included, and its result type
+    compiled: HashMap<Spec, (FuncId, JitType)>,
+    rejected: HashSet<Spec>,
+    float_mod: FuncId,
+    stats: JitStats,
 }
 
 impl JITCompiler {
-    pub fn new() -> Self {
-        Self {
-            hot_functions: HashMap::new(),
-            compiled_functions: HashMap::new(),
-            hot_threshold: 10, // Compile after 10 calls
-        }
-    }
-    
-    pub fn track_function_call(&mut self, function_name: &str) -> bool {
-        let count = self.hot_functions.entry(function_name.to_string()).or_insert(0);
-        *count += 1;
-        
-        if *count >= self.hot_threshold && !self.compiled_functions.contains_key(function_name) {
-            self.compile_hot_function(function_name);
-            true
-        } else {
-            false
-        }
-    }
-    
-    fn compile_hot_function(&mut self, function_name: &str) {
-        // For now, create optimized versions of common functions
-        match function_name {
-            "fibonacci" => {
-                self.compiled_functions.insert(
-                    function_name.to_string(),
-                    |args| {
-                        if let Some(VMValue::Int(n)) = args.get(0) {
-                            VMValue::Int(Self::fast_fibonacci(*n))
-                        } else {
-                            VMValue::Null
+    pub fn new() -> Result<Self, String> {
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false").map_err(|e| e.to_string())?;
+        flags.set("is_pic", "false").map_err(|e| e.to_string())?;
+        flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+        let isa = cranelift_native::builder()
+            .map_err(|e| format!("JIT is not supported on this machine: {}", e))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| e.to_string())?;
+
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("neksis_float_mod", float_mod as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let mut signature = module.make_signature();
+        signature.params.push(AbiParam::new(types::F64));
+        signature.params.push(AbiParam::new(types::F64));
+        signature.returns.push(AbiParam::new(types::F64));
+        let float_mod = module
+            .declare_function("neksis_float_mod", Linkage::Import, &signature)
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            module,
+            hot_threshold: DEFAULT_HOT_THRESHOLD,
+            functions: HashMap::new(),
+            compiled: HashMap::new(),
+            rejected: HashSet::new(),
+            float_mod,
+            stats: JitStats::default(),
+        })
+    }
+
+    pub fn with_hot_threshold(mut self, calls: usize) -> Self {
+        self.hot_threshold = calls;
+        self
+    }
+
+    pub fn stats(&self) -> JitStats {
+        self.stats
+    }
+
+    /// Run a call to the user function `name` natively if it has a version
+    /// for these arguments, compiling one if the function has become hot.
+    /// `None` means the VM has to interpret the call.
+    pub(crate) fn call(
+        &mut self,
+        name: &str,
+        args: &[VMValue],
+        max_depth: usize,
+        instructions: &[BytecodeInstruction],
+        function_table: &HashMap<String, (usize, usize, usize)>,
+    ) -> Option<VMValue> {
+        if !self.functions.contains_key(name) {
+            let state = FunctionState::new(instructions, *function_table.get(name)?);
+            self.functions.insert(name.to_string(), state);
+        }
+        let state = self.functions.get_mut(name).unwrap();
+        if let Some(index) = state.versions.iter().position(|version| version.accepts(args)) {
+            return self.run(name, index, args, max_depth);
+        }
+
+        if !state.versions.is_empty() {
+            self.stats.guard_failures += 1;
+        }
+        state.interpreted_calls += 1;
+        let hot = state.interpreted_calls >= self.hot_threshold || state.has_loop;
+        if !hot || state.versions.len() >= MAX_VERSIONS || args.len() > MAX_PARAMS {
+            return None;
+        }
+        state.interpreted_calls = 0;
+        let params = args.iter().map(JitType::of).collect::<Option<Vec<_>>>()?;
+        let spec = (name.to_string(), params);
+        if self.rejected.contains(&spec) || self.compiled.contains_key(&spec) {
+            return None;
+        }
+        self.compile(spec, instructions, function_table);
+
+        let index = self.functions[name].versions.iter().position(|version| version.accepts(args))?;
+        self.run(name, index, args, max_depth)
+    }
+
+    fn run(&mut self, name: &str, index: usize, args: &[VMValue], max_depth: usize) -> Option<VMValue> {
+        let state = self.functions.get_mut(name)?;
+        let version = &mut state.versions[index];
+        let mut words = [0i64; MAX_PARAMS];
+        for (word, arg) in words.iter_mut().zip(args) {
+            *word = JitType::to_word(arg);
+        }
+        let mut context = JitContext {
+            depth: 0,
+            max_depth: (max_depth as u64).min(NATIVE_DEPTH_LIMIT),
+            bailed: 0,
+        };
+        // Safety: `entry` was compiled for exactly these argument types and
+        // reads one word per parameter
+        let word = unsafe { (version.entry)(&mut context, words.as_ptr()) };
+
+        if context.bailed != 0 {
+            self.stats.bailouts += 1;
+            version.bailouts += 1;
+            if version.bailouts >= MAX_BAILOUTS {
+                let version = state.versions.remove(index);
+                self.rejected.insert((name.to_string(), version.params));
+                self.stats.rejected += 1;
+            }
+            return None;
+        }
+        self.stats.native_calls += 1;
+        Some(version.result.value(word))
+    }
+
+    /// Compile `root` together with every version it calls that is not
+    /// compiled yet. Nothing is compiled if any of them does not qualify.
+    fn compile(
+        &mut self,
+        root: Spec,
+        instructions: &[BytecodeInstruction],
+        function_table: &HashMap<String, (usize, usize, usize)>,
+    ) {
+        let Some(analyses) = self.analyze_batch(&root, instructions, function_table) else {
+            self.rejected.insert(root);
+            self.stats.rejected += 1;
+            return;
+        };
+
+        let mut ids = HashMap::new();
+        for (spec, analysis) in &analyses {
+            let signature = self.signature(&spec.1, analysis.result.unwrap());
+            let Ok(id) = self.module.declare_anonymous_function(&signature) else {
+                self.rejected.insert(root);
+                return;
+            };
+            ids.insert(spec.clone(), (id, analysis.result.unwrap()));
+        }
+        let mut entries = Vec::new();
+        let mut context = self.module.make_context();
+        let mut builder_context = FunctionBuilderContext::new();
+        for (spec, analysis) in &analyses {
+            let (id, result) = ids[spec];
+            context.func.signature = self.signature(&spec.1, result);
+            context.func.name = UserFuncName::user(0, id.as_u32());
+            let (start, end, _) = function_table[&spec.0];
+            FunctionCodegen::build(
+                &mut self.module,
+                &mut context,
+                &mut builder_context,
+                Callees { batch: &ids, compiled: &self.compiled, float_mod: self.float_mod },
+                spec,
+                result,
+                &instructions[start..end],
+                start,
+                analysis,
+            );
+            if self.module.define_function(id, &mut context).is_err() {
+                self.module.clear_context(&mut context);
+                self.rejected.insert(root);
+                return;
+            }
+            self.module.clear_context(&mut context);
+
+            let entry = self.build_entry(&mut context, &mut builder_context, id, &spec.1, result);
+            let Some(entry) = entry else {
+                self.rejected.insert(root);
+                return;
+            };
+            entries.push((spec.clone(), entry, result));
+        }
+        if self.module.finalize_definitions().is_err() {
+            self.rejected.insert(root);
+            return;
+        }
+
+        for (spec, entry, result) in entries {
+            let pointer = self.module.get_finalized_function(entry);
+            // Safety: `build_entry` defined this function with the `Entry` signature
+            let entry = unsafe { std::mem::transmute::<*const u8, Entry>(pointer) };
+            let state = self
+                .functions
+                .entry(spec.0.clone())
+                .or_insert_with(|| FunctionState::new(instructions, function_table[&spec.0]));
+            if state.versions.len() < MAX_VERSIONS {
+                state.versions.push(Version { params: spec.1.clone(), result, entry, bailouts: 0 });
+            }
+            self.compiled.insert(spec.clone(), ids[&spec]);
+            self.stats.compiled += 1;
+        }
+    }
+
+    /// Type every version reachable from `root`, iterating until the result
+    /// types of recursive calls settle
+    fn analyze_batch(
+        &self,
+        root: &Spec,
+        instructions: &[BytecodeInstruction],
+        function_table: &HashMap<String, (usize, usize, usize)>,
+    ) -> Option<HashMap<Spec, Analysis>> {
+        let mut results: HashMap<Spec, Option<JitType>> = HashMap::from([(root.clone(), None)]);
+        let mut rejected: HashSet<Spec> = HashSet::new();
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            let mut analyses = HashMap::new();
+            let pending: Vec<Spec> = results.keys().filter(|spec| !rejected.contains(*spec)).cloned().collect();
+            for spec in pending {
+                let lookup = |callee: &Spec| -> Option<Option<JitType>> {
+                    if let Some((_, result)) = self.compiled.get(callee) {
+                        Some(Some(*result))
+                    } else if self.rejected.contains(callee) || rejected.contains(callee) {
+                        None
+                    } else {
+                        Some(results.get(callee).copied().flatten())
+                    }
+                };
+                match analyze(&spec, instructions, function_table, &lookup) {
+                    Ok(analysis) => {
+                        for callee in &analysis.callees {
+                            if !self.compiled.contains_key(callee) && !results.contains_key(callee) {
+                                results.insert(callee.clone(), None);
+                                changed = true;
+                            }
+                        }
+                        if results[&spec] != analysis.result {
+                            results.insert(spec.clone(), analysis.result);
+                            changed = true;
                         }
+                        analyses.insert(spec, analysis);
+                    }
+                    Err(_) => {
+                        rejected.insert(spec);
+                        changed = true;
+                    }
+                }
+            }
+            if rejected.contains(root) {
+                return None;
+            }
+            if changed {
+                continue;
+            }
+            // Settled: versions that never return, or still see values of
+            // unknown type, do not qualify, and neither does anything calling them
+            let incomplete: Vec<Spec> = analyses
+                .iter()
+                .filter(|(_, analysis)| analysis.result.is_none() || !analysis.is_complete())
+                .map(|(spec, _)| spec.clone())
+                .collect();
+            if incomplete.is_empty() {
+                return Some(analyses);
+            }
+            rejected.extend(incomplete);
+        }
+        None
+    }
+
+    fn signature(&self, params: &[JitType], result: JitType) -> Signature {
+        let mut signature = self.module.make_signature();
+        signature.params.push(AbiParam::new(self.module.target_config().pointer_type()));
+        signature.params.extend(params.iter().map(|param| AbiParam::new(param.clif())));
+        signature.returns.push(AbiParam::new(result.clif()));
+        signature
+    }
+
+    /// An `Entry` for `function`: unpacks the argument words, calls it and
+    /// packs its result into a word
+    fn build_entry(
+        &mut self,
+        context: &mut Context,
+        builder_context: &mut FunctionBuilderContext,
+        function: FuncId,
+        params: &[JitType],
+        result: JitType,
+    ) -> Option<FuncId> {
+        let pointer = self.module.target_config().pointer_type();
+        let mut signature = self.module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(pointer));
+        signature.returns.push(AbiParam::new(types::I64));
+        let id = self.module.declare_anonymous_function(&signature).ok()?;
+        context.func.signature = signature;
+        context.func.name = UserFuncName::user(0, id.as_u32());
+
+        let mut builder = FunctionBuilder::new(&mut context.func, builder_context);
+        let block = builder.create_block();
+        builder.append_block_params_for_function_params(block);
+        builder.switch_to_block(block);
+        let (jit_context, words) = (builder.block_params(block)[0], builder.block_params(block)[1]);
+        let mut args = vec![jit_context];
+        for (index, param) in params.iter().enumerate() {
+            let word = builder.ins().load(types::I64, MemFlags::trusted(), words, (index * 8) as i32);
+            args.push(match param {
+                JitType::Int => word,
+                JitType::Float => builder.ins().bitcast(types::F64, MemFlags::new(), word),
+                JitType::Bool | JitType::Null => builder.ins().ireduce(types::I8, word),
+            });
+        }
+        let callee = self.module.declare_func_in_func(function, builder.func);
+        let call = builder.ins().call(callee, &args);
+        let value = builder.inst_results(call)[0];
+        let word = match result {
+            JitType::Int => value,
+            JitType::Float => builder.ins().bitcast(types::I64, MemFlags::new(), value),
+            JitType::Bool | JitType::Null => builder.ins().uextend(types::I64, value),
+        };
+        builder.ins().return_(&[word]);
+        builder.seal_all_blocks();
+        builder.finalize();
+
+        let defined = self.module.define_function(id, context);
+        self.module.clear_context(context);
+        defined.ok().map(|_| id)
+    }
+}
+
+// Type analysis
+//
+// An abstract interpretation of a function's bytecode that records the
+// type of every stack slot and local before each instruction. `None` stands
+// for a value whose type is not known yet: the result of a call whose own
+// result type is still being worked out.
+
+type Slot = Option<JitType>;
+
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    stack: Vec<Slot>,
+    locals: BTreeMap<String, Slot>,
+}
+
+impl State {
+    fn pop(&mut self) -> Result<Slot, String> {
+        self.stack.pop().ok_or_else(|| "stack underflow".to_string())
+    }
+
+    fn top(&self, count: usize) -> Result<&[Slot], String> {
+        if count > self.stack.len() {
+            return Err("stack underflow".to_string());
+        }
+        Ok(&self.stack[self.stack.len() - count..])
+    }
+
+    /// The state at a join point; locals whose types disagree are dropped,
+    /// so reading them afterwards does not qualify
+    fn merge(&self, other: &State) -> Result<State, String> {
+        if self.stack.len() != other.stack.len() {
+            return Err("stack depths differ at a join".to_string());
+        }
+        let stack = self
+            .stack
+            .iter()
+            .zip(&other.stack)
+            .map(|(a, b)| join(*a, *b).ok_or_else(|| "a stack slot changes type".to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let locals = self
+            .locals
+            .iter()
+            .filter_map(|(name, a)| {
+                let b = other.locals.get(name)?;
+                Some((name.clone(), join(*a, *b)?))
+            })
+            .collect();
+        Ok(State { stack, locals })
+    }
+}
+
+fn join(a: Slot, b: Slot) -> Option<Slot> {
+    match (a, b) {
+        (None, other) | (other, None) => Some(other),
+        (Some(a), Some(b)) if a == b => Some(Some(a)),
+        _ => None,
+    }
+}
+
+struct Analysis {
+    // Indexed from the function's first instruction; `None` where unreachable
+    states: Vec<Option<State>>,
+    result: Option<JitType>,
+    callees: Vec<Spec>,
+}
+
+impl Analysis {
+    fn is_complete(&self) -> bool {
+        self.states
+            .iter()
+            .flatten()
+            .all(|state| state.stack.iter().chain(state.locals.values()).all(|slot| slot.is_some()))
+    }
+}
+
+fn arithmetic_result(left: Slot, right: Slot) -> Result<Slot, String> {
+    match (left, right) {
+        (Some(JitType::Int), Some(JitType::Int)) => Ok(Some(JitType::Int)),
+        (Some(a), Some(b)) if a.is_number() && b.is_number() => Ok(Some(JitType::Float)),
+        (Some(_), Some(_)) => Err("arithmetic on a non-numeric value".to_string()),
+        (left, right) => {
+            // Unknown so far, but it can only end up as a number
+            if left.is_some_and(|t| !t.is_number()) || right.is_some_and(|t| !t.is_number()) {
+                return Err("arithmetic on a non-numeric value".to_string());
+            }
+            Ok(None)
+        }
+    }
+}
+
+fn ordered_comparison(left: Slot, right: Slot) -> Result<(), String> {
+    match (left, right) {
+        (Some(a), Some(b)) if a.is_number() && b.is_number() => Ok(()),
+        (Some(JitType::Bool), Some(JitType::Bool)) => Ok(()),
+        (Some(_), Some(_)) => Err("values that cannot be compared".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Type the body of `spec`. `lookup` gives the result type of a callee
+/// version, `Some(None)` while it is not known yet, or `None` if the callee
+/// does not qualify.
+fn analyze(
+    spec: &Spec,
+    instructions: &[BytecodeInstruction],
+    function_table: &HashMap<String, (usize, usize, usize)>,
+    lookup: &dyn Fn(&Spec) -> Option<Option<JitType>>,
+) -> Result<Analysis, String> {
+    let &(start, end, param_count) = function_table.get(&spec.0).ok_or("not a user function")?;
+    if param_count != spec.1.len() {
+        return Err("argument count mismatch".to_string());
+    }
+    let code = &instructions[start..end];
+    let mut states: Vec<Option<State>> = vec![None; code.len()];
+    let entry = State { stack: spec.1.iter().map(|t| Some(*t)).collect(), locals: BTreeMap::new() };
+    if code.is_empty() {
+        return Err("empty function".to_string());
+    }
+    states[0] = Some(entry);
+    let mut result: Slot = None;
+    let mut callees: Vec<Spec> = Vec::new();
+    let mut work = vec![0];
+
+    while let Some(index) = work.pop() {
+        let mut state = states[index].clone().unwrap();
+        let mut successors: Vec<(usize, State)> = Vec::new();
+        let target = |address: usize| -> Result<usize, String> {
+            if address < start || address >= end {
+                return Err("jump out of the function".to_string());
+            }
+            Ok(address - start)
+        };
+        let mut falls_through = true;
+
+        match &code[index] {
+            BytecodeInstruction::PushInt(_) => state.stack.push(Some(JitType::Int)),
+            BytecodeInstruction::PushFloat(_) => state.stack.push(Some(JitType::Float)),
+            BytecodeInstruction::PushBool(_) => state.stack.push(Some(JitType::Bool)),
+            BytecodeInstruction::PushNull => state.stack.push(Some(JitType::Null)),
+            BytecodeInstruction::Pop => {
+                state.pop()?;
+            }
+            BytecodeInstruction::Dup => {
+                let top = state.top(1)?[0];
+                state.stack.push(top);
+            }
+            BytecodeInstruction::Swap => {
+                let len = state.top(2)?.len();
+                let at = state.stack.len() - len;
+                state.stack.swap(at, at + 1);
+            }
+            BytecodeInstruction::Load(name) => {
+                // A name that is not a local here would be read from the globals
+                let slot = *state.locals.get(name).ok_or_else(|| format!("reads global '{}'", name))?;
+                state.stack.push(slot);
+            }
+            BytecodeInstruction::Store(name) => {
+                let slot = state.pop()?;
+                state.locals.insert(name.clone(), slot);
+            }
+            BytecodeInstruction::Add
+            | BytecodeInstruction::Sub
+            | BytecodeInstruction::Mul
+            | BytecodeInstruction::Div
+            | BytecodeInstruction::Mod => {
+                let right = state.pop()?;
+                let left = state.pop()?;
+                state.stack.push(arithmetic_result(left, right)?);
+            }
+            BytecodeInstruction::Neg => {
+                let value = state.pop()?;
+                if value.is_some_and(|t| !t.is_number()) {
+                    return Err("negating a non-numeric value".to_string());
+                }
+                state.stack.push(value);
+            }
+            BytecodeInstruction::Eq | BytecodeInstruction::Ne => {
+                state.pop()?;
+                state.pop()?;
+                state.stack.push(Some(JitType::Bool));
+            }
+            BytecodeInstruction::Lt | BytecodeInstruction::Le | BytecodeInstruction::Gt | BytecodeInstruction::Ge => {
+                let right = state.pop()?;
+                let left = state.pop()?;
+                ordered_comparison(left, right)?;
+                state.stack.push(Some(JitType::Bool));
+            }
+            BytecodeInstruction::And | BytecodeInstruction::Or => {
+                state.pop()?;
+                state.pop()?;
+                state.stack.push(Some(JitType::Bool));
+            }
+            BytecodeInstruction::Not => {
+                state.pop()?;
+                state.stack.push(Some(JitType::Bool));
+            }
+            BytecodeInstruction::Line(_) => {}
+            BytecodeInstruction::Jump(address) => {
+                successors.push((target(*address)?, state.clone()));
+                falls_through = false;
+            }
+            BytecodeInstruction::JumpIfFalse(address) | BytecodeInstruction::JumpIfTrue(address) => {
+                state.pop()?;
+                successors.push((target(*address)?, state.clone()));
+            }
+            BytecodeInstruction::Call(name, arg_count) | BytecodeInstruction::TailCall(name, arg_count) => {
+                let &(_, _, params) = function_table.get(name).ok_or_else(|| format!("calls builtin '{}'", name))?;
+                if params != *arg_count || *arg_count > MAX_PARAMS {
+                    return Err(format!("unsupported call to '{}'", name));
+                }
+                let args = state.top(*arg_count)?.to_vec();
+                let is_self_tail_call = matches!(code[index], BytecodeInstruction::TailCall(..))
+                    && *name == spec.0
+                    && args.iter().zip(&spec.1).all(|(arg, param)| *arg == Some(*param));
+                if is_self_tail_call {
+                    // Starts the function over with the new arguments
+                    if state.stack.len() != *arg_count {
+                        return Err("values left under a tail call".to_string());
                     }
-                );
-            }
-            "factorial" => {
-                self.compiled_functions.insert(
-                    function_name.to_string(),
-                    |args| {
-                        if let Some(VMValue::Int(n)) = args.get(0) {
-                            VMValue::Int(Self::fast_factorial(*n))
-                        } else {
-                            VMValue::Null
+                    let restart = State { stack: args, locals: BTreeMap::new() };
+                    successors.push((0, restart));
+                    falls_through = false;
+                } else {
+                    state.stack.truncate(state.stack.len() - arg_count);
+                    let slot = match args.into_iter().collect::<Option<Vec<_>>>() {
+                        Some(types) => {
+                            let callee = (name.clone(), types);
+                            let result = lookup(&callee).ok_or_else(|| format!("calls '{}', which does not qualify", name))?;
+                            if !callees.contains(&callee) {
+                                callees.push(callee);
+                            }
+                            result
                         }
+                        None => None,
+                    };
+                    state.stack.push(slot);
+                }
+            }
+            BytecodeInstruction::Return => {
+                if state.stack.len() != 1 {
+                    return Err("values left under the result".to_string());
+                }
+                result = join(result, state.stack[0]).ok_or("returns values of different types")?;
+                falls_through = false;
+            }
+            other => return Err(format!("{:?} does not qualify", other)),
+        }
+
+        if falls_through {
+            if index + 1 >= code.len() {
+                return Err("runs past the end of the function".to_string());
+            }
+            successors.push((index + 1, state));
+        }
+        for (next, incoming) in successors {
+            let merged = match &states[next] {
+                None => incoming,
+                Some(existing) => existing.merge(&incoming)?,
+            };
+            if states[next].as_ref() != Some(&merged) {
+                states[next] = Some(merged);
+                work.push(next);
+            }
+        }
+    }
+
+    Ok(Analysis { states, result, callees })
+}
+
+// Code generation
+//
+// Every stack slot and local becomes a Cranelift variable per type it takes,
+// so the stack machine turns into plain SSA values, and every instruction
+// that starts a basic block gets a Cranelift block.
+
+struct Callees<'a> {
+    batch: &'a HashMap<Spec, (FuncId, JitType)>,
+    compiled: &'a HashMap<Spec, (FuncId, JitType)>,
+    float_mod: FuncId,
+}
+
+impl Callees<'_> {
+    fn get(&self, spec: &Spec) -> (FuncId, JitType) {
+        *self.batch.get(spec).or_else(|| self.compiled.get(spec)).expect("callee was analyzed")
+    }
+}
+
+// Where a value lives in the bytecode
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Place {
+    Stack(usize),
+    Local(String),
+}
+
+struct FunctionCodegen<'a> {
+    builder: FunctionBuilder<'a>,
+    module: &'a mut JITModule,
+    context: Value,
+    bail: Block,
+    variables: HashMap<(Place, JitType), Variable>,
+}
+
+impl<'a> FunctionCodegen<'a> {
+    #[allow(clippy::too_many_arguments)]
+    fn build(
+        module: &'a mut JITModule,
+        context: &'a mut Context,
+        builder_context: &'a mut FunctionBuilderContext,
+        callees: Callees,
+        spec: &Spec,
+        result: JitType,
+        code: &[BytecodeInstruction],
+        start: usize,
+        analysis: &Analysis,
+    ) {
+        let mut builder = FunctionBuilder::new(&mut context.func, builder_context);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        let bail = builder.create_block();
+        let body = builder.create_block();
+
+        // Leaders: the first instruction, jump targets and whatever follows a jump or return
+        let mut blocks: Vec<Option<Block>> = vec![None; code.len()];
+        blocks[0] = Some(builder.create_block());
+        for (index, instruction) in code.iter().enumerate() {
+            if analysis.states[index].is_none() {
+                continue;
+            }
+            let mut leaders = Vec::new();
+            match instruction {
+                BytecodeInstruction::Jump(address) => leaders.push(address - start),
+                BytecodeInstruction::JumpIfFalse(address) | BytecodeInstruction::JumpIfTrue(address) => {
+                    leaders.push(address - start);
+                    leaders.push(index + 1);
+                }
+                _ => {}
+            }
+            for leader in leaders {
+                if blocks[leader].is_none() {
+                    blocks[leader] = Some(builder.create_block());
+                }
+            }
+        }
+
+        builder.switch_to_block(entry);
+        let jit_context = builder.block_params(entry)[0];
+        let params = builder.block_params(entry)[1..].to_vec();
+        let mut codegen = FunctionCodegen { builder, module, context: jit_context, bail, variables: HashMap::new() };
+
+        // Bail out when nested too deep, otherwise count this frame
+        let depth = codegen.builder.ins().load(types::I64, MemFlags::trusted(), jit_context, DEPTH_OFFSET);
+        let max_depth = codegen.builder.ins().load(types::I64, MemFlags::trusted(), jit_context, MAX_DEPTH_OFFSET);
+        let too_deep = codegen.builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, depth, max_depth);
+        codegen.builder.ins().brif(too_deep, bail, &[], body, &[]);
+        codegen.builder.switch_to_block(body);
+        let depth = codegen.builder.ins().iadd_imm(depth, 1);
+        codegen.builder.ins().store(MemFlags::trusted(), depth, jit_context, DEPTH_OFFSET);
+        for (index, (value, param)) in params.iter().zip(&spec.1).enumerate() {
+            codegen.define(Place::Stack(index), *param, *value);
+        }
+        codegen.builder.ins().jump(blocks[0].unwrap(), &[]);
+
+        codegen.builder.switch_to_block(bail);
+        let one = codegen.builder.ins().iconst(types::I8, 1);
+        codegen.builder.ins().store(MemFlags::trusted(), one, jit_context, BAILED_OFFSET);
+        let zero = codegen.zero(result);
+        codegen.builder.ins().return_(&[zero]);
+
+        let mut terminated = true;
+        for (index, instruction) in code.iter().enumerate() {
+            let Some(state) = &analysis.states[index] else {
+                continue;
+            };
+            if let Some(block) = blocks[index] {
+                if !terminated {
+                    codegen.builder.ins().jump(block, &[]);
+                }
+                codegen.builder.switch_to_block(block);
+            }
+            let stack: Vec<JitType> = state.stack.iter().map(|slot| slot.expect("analysis is complete")).collect();
+            let locals: HashMap<&str, JitType> = state
+                .locals
+                .iter()
+                .map(|(name, slot)| (name.as_str(), slot.expect("analysis is complete")))
+                .collect();
+            let next_block = |target: usize| blocks[target].expect("jump targets are leaders");
+            terminated = codegen.instruction(instruction, &stack, &locals, spec, &callees, &|address| next_block(address - start), blocks.get(index + 1).copied().flatten(), blocks[0].unwrap());
+        }
+
+        codegen.builder.seal_all_blocks();
+        codegen.builder.finalize();
+    }
+
+    fn variable(&mut self, slot: Place, ty: JitType) -> Variable {
+        let next = self.variables.len();
+        let builder = &mut self.builder;
+        *self.variables.entry((slot, ty)).or_insert_with(|| {
+            let variable = Variable::from_u32(next as u32);
+            builder.declare_var(variable, ty.clif());
+            variable
+        })
+    }
+
+    fn define(&mut self, slot: Place, ty: JitType, value: Value) {
+        let variable = self.variable(slot, ty);
+        self.builder.def_var(variable, value);
+    }
+
+    fn use_slot(&mut self, slot: Place, ty: JitType) -> Value {
+        let variable = self.variable(slot, ty);
+        self.builder.use_var(variable)
+    }
+
+    fn zero(&mut self, ty: JitType) -> Value {
+        match ty {
+            JitType::Int => self.builder.ins().iconst(types::I64, 0),
+            JitType::Float => self.builder.ins().f64const(0.0),
+            JitType::Bool | JitType::Null => self.builder.ins().iconst(types::I8, 0),
+        }
+    }
+
+    fn bail_if(&mut self, condition: Value) {
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, self.bail, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn float_of(&mut self, value: Value, ty: JitType) -> Value {
+        match ty {
+            JitType::Int => self.builder.ins().fcvt_from_sint(types::F64, value),
+            _ => value,
+        }
+    }
+
+    // `VMValue::to_bool` as 0 or 1
+    fn truthy(&mut self, value: Value, ty: JitType) -> Value {
+        match ty {
+            JitType::Int => self.builder.ins().icmp_imm(IntCC::NotEqual, value, 0),
+            JitType::Float => {
+                let zero = self.builder.ins().f64const(0.0);
+                self.builder.ins().fcmp(FloatCC::NotEqual, value, zero)
+            }
+            JitType::Bool => value,
+            JitType::Null => self.builder.ins().iconst(types::I8, 0),
+        }
+    }
+
+    /// Emit one instruction; returns whether it ended the block
+    #[allow(clippy::too_many_arguments)]
+    fn instruction(
+        &mut self,
+        instruction: &BytecodeInstruction,
+        stack: &[JitType],
+        locals: &HashMap<&str, JitType>,
+        spec: &Spec,
+        callees: &Callees,
+        block_at: &dyn Fn(usize) -> Block,
+        fall_through: Option<Block>,
+        function_start: Block,
+    ) -> bool {
+        let len = stack.len();
+        let top = |depth: usize| stack[len - 1 - depth];
+        match instruction {
+            BytecodeInstruction::PushInt(value) => {
+                let value = self.builder.ins().iconst(types::I64, *value);
+                self.define(Place::Stack(len), JitType::Int, value);
+            }
+            BytecodeInstruction::PushFloat(value) => {
+                let value = self.builder.ins().f64const(*value);
+                self.define(Place::Stack(len), JitType::Float, value);
+            }
+            BytecodeInstruction::PushBool(value) => {
+                let value = self.builder.ins().iconst(types::I8, *value as i64);
+                self.define(Place::Stack(len), JitType::Bool, value);
+            }
+            BytecodeInstruction::PushNull => {
+                let value = self.builder.ins().iconst(types::I8, 0);
+                self.define(Place::Stack(len), JitType::Null, value);
+            }
+            BytecodeInstruction::Pop | BytecodeInstruction::Line(_) => {}
+            BytecodeInstruction::Dup => {
+                let value = self.use_slot(Place::Stack(len - 1), top(0));
+                self.define(Place::Stack(len), top(0), value);
+            }
+            BytecodeInstruction::Swap => {
+                let upper = self.use_slot(Place::Stack(len - 1), top(0));
+                let lower = self.use_slot(Place::Stack(len - 2), top(1));
+                self.define(Place::Stack(len - 2), top(0), upper);
+                self.define(Place::Stack(len - 1), top(1), lower);
+            }
+            BytecodeInstruction::Load(name) => {
+                let ty = locals[name.as_str()];
+                let value = self.use_slot(Place::Local(name.clone()), ty);
+                self.define(Place::Stack(len), ty, value);
+            }
+            BytecodeInstruction::Store(name) => {
+                let value = self.use_slot(Place::Stack(len - 1), top(0));
+                self.define(Place::Local(name.clone()), top(0), value);
+            }
+            BytecodeInstruction::Add
+            | BytecodeInstruction::Sub
+            | BytecodeInstruction::Mul
+            | BytecodeInstruction::Div
+            | BytecodeInstruction::Mod => {
+                let (left_type, right_type) = (top(1), top(0));
+                let left = self.use_slot(Place::Stack(len - 2), left_type);
+                let right = self.use_slot(Place::Stack(len - 1), right_type);
+                let (value, ty) = self.arithmetic(instruction, left, left_type, right, right_type, callees);
+                self.define(Place::Stack(len - 2), ty, value);
+            }
+            BytecodeInstruction::Neg => {
+                let value = self.use_slot(Place::Stack(len - 1), top(0));
+                let negated = if top(0) == JitType::Int {
+                    let overflows = self.builder.ins().icmp_imm(IntCC::Equal, value, i64::MIN);
+                    self.bail_if(overflows);
+                    self.builder.ins().ineg(value)
+                } else {
+                    self.builder.ins().fneg(value)
+                };
+                self.define(Place::Stack(len - 1), top(0), negated);
+            }
+            BytecodeInstruction::Eq
+            | BytecodeInstruction::Ne
+            | BytecodeInstruction::Lt
+            | BytecodeInstruction::Le
+            | BytecodeInstruction::Gt
+            | BytecodeInstruction::Ge => {
+                let (left_type, right_type) = (top(1), top(0));
+                let left = self.use_slot(Place::Stack(len - 2), left_type);
+                let right = self.use_slot(Place::Stack(len - 1), right_type);
+                let value = self.comparison(instruction, left, left_type, right, right_type);
+                self.define(Place::Stack(len - 2), JitType::Bool, value);
+            }
+            BytecodeInstruction::And | BytecodeInstruction::Or => {
+                let left = self.use_slot(Place::Stack(len - 2), top(1));
+                let right = self.use_slot(Place::Stack(len - 1), top(0));
+                let left = self.truthy(left, top(1));
+                let right = self.truthy(right, top(0));
+                let value = if matches!(instruction, BytecodeInstruction::And) {
+                    self.builder.ins().band(left, right)
+                } else {
+                    self.builder.ins().bor(left, right)
+                };
+                self.define(Place::Stack(len - 2), JitType::Bool, value);
+            }
+            BytecodeInstruction::Not => {
+                let value = self.use_slot(Place::Stack(len - 1), top(0));
+                let value = self.truthy(value, top(0));
+                let value = self.builder.ins().bxor_imm(value, 1);
+                self.define(Place::Stack(len - 1), JitType::Bool, value);
+            }
+            BytecodeInstruction::Jump(address) => {
+                self.builder.ins().jump(block_at(*address), &[]);
+                return true;
+            }
+            BytecodeInstruction::JumpIfFalse(address) | BytecodeInstruction::JumpIfTrue(address) => {
+                let value = self.use_slot(Place::Stack(len - 1), top(0));
+                let condition = self.truthy(value, top(0));
+                let (target, next) = (block_at(*address), fall_through.expect("a branch is followed by a leader"));
+                if matches!(instruction, BytecodeInstruction::JumpIfTrue(_)) {
+                    self.builder.ins().brif(condition, target, &[], next, &[]);
+                } else {
+                    self.builder.ins().brif(condition, next, &[], target, &[]);
+                }
+                return true;
+            }
+            BytecodeInstruction::Call(name, arg_count) | BytecodeInstruction::TailCall(name, arg_count) => {
+                let types = stack[len - arg_count..].to_vec();
+                let args: Vec<Value> = (len - arg_count..len)
+                    .zip(&types)
+                    .map(|(slot, ty)| self.use_slot(Place::Stack(slot), *ty))
+                    .collect();
+                if matches!(instruction, BytecodeInstruction::TailCall(..)) && *name == spec.0 && types == spec.1 {
+                    for (slot, (value, ty)) in args.into_iter().zip(&types).enumerate() {
+                        self.define(Place::Stack(slot), *ty, value);
                     }
-                );
+                    self.builder.ins().jump(function_start, &[]);
+                    return true;
+                }
+                let (id, result) = callees.get(&(name.clone(), types));
+                let callee = self.module.declare_func_in_func(id, self.builder.func);
+                let mut call_args = vec![self.context];
+                call_args.extend(args);
+                let call = self.builder.ins().call(callee, &call_args);
+                let value = self.builder.inst_results(call)[0];
+                let bailed = self.builder.ins().load(types::I8, MemFlags::trusted(), self.context, BAILED_OFFSET);
+                self.bail_if(bailed);
+                self.define(Place::Stack(len - arg_count), result, value);
+            }
+            BytecodeInstruction::Return => {
+                let value = self.use_slot(Place::Stack(len - 1), top(0));
+                let depth = self.builder.ins().load(types::I64, MemFlags::trusted(), self.context, DEPTH_OFFSET);
+                let depth = self.builder.ins().iadd_imm(depth, -1);
+                self.builder.ins().store(MemFlags::trusted(), depth, self.context, DEPTH_OFFSET);
+                self.builder.ins().return_(&[value]);
+                return true;
             }
+            other => unreachable!("{:?} was rejected by the analysis", other),
+        }
+        false
+    }
+
+    fn arithmetic(
+        &mut self,
+        instruction: &BytecodeInstruction,
+        left: Value,
+        left_type: JitType,
+        right: Value,
+        right_type: JitType,
+        callees: &Callees,
+    ) -> (Value, JitType) {
+        if left_type == JitType::Int && right_type == JitType::Int {
+            let value = match instruction {
+                BytecodeInstruction::Add | BytecodeInstruction::Sub | BytecodeInstruction::Mul => {
+                    let (value, overflows) = match instruction {
+                        BytecodeInstruction::Add => self.builder.ins().sadd_overflow(left, right),
+                        BytecodeInstruction::Sub => self.builder.ins().ssub_overflow(left, right),
+                        _ => self.builder.ins().smul_overflow(left, right),
+                    };
+                    self.bail_if(overflows);
+                    value
+                }
+                _ => {
+                    let by_zero = self.builder.ins().icmp_imm(IntCC::Equal, right, 0);
+                    self.bail_if(by_zero);
+                    let is_min = self.builder.ins().icmp_imm(IntCC::Equal, left, i64::MIN);
+                    let is_minus_one = self.builder.ins().icmp_imm(IntCC::Equal, right, -1);
+                    let overflows = self.builder.ins().band(is_min, is_minus_one);
+                    self.bail_if(overflows);
+                    if matches!(instruction, BytecodeInstruction::Div) {
+                        self.builder.ins().sdiv(left, right)
+                    } else {
+                        self.builder.ins().srem(left, right)
+                    }
+                }
+            };
+            return (value, JitType::Int);
+        }
+
+        let left = self.float_of(left, left_type);
+        let right = self.float_of(right, right_type);
+        let value = match instruction {
+            BytecodeInstruction::Add => self.builder.ins().fadd(left, right),
+            BytecodeInstruction::Sub => self.builder.ins().fsub(left, right),
+            BytecodeInstruction::Mul => self.builder.ins().fmul(left, right),
             _ => {
-                // Generic optimization - inline simple functions
-                println!("JIT: Optimizing function '{}'", function_name);
-            }
-        }
-    }
-    
-    pub fn try_execute_compiled(&self, function_name: &str, args: &[VMValue]) -> Option<VMValue> {
-        if let Some(compiled_fn) = self.compiled_functions.get(function_name) {
-            Some(compiled_fn(args))
-        } else {
-            None
-        }
-    }
-    
-    // Optimized mathematical functions
-    fn fast_fibonacci(n: i64) -> i64 {
-        if n <= 1 {
-            return n;
-        }
-        
-        let mut a = 0i64;
-        let mut b = 1i64;
-        
-        for _ in 2..=n {
-            let temp = a + b;
-            a = b;
-            b = temp;
-        }
-        
-        b
-    }
-    
-    fn fast_factorial(n: i64) -> i64 {
-        if n <= 1 {
-            return 1;
-        }
-        
-        let mut result = 1i64;
-        for i in 2..=n {
-            result *= i;
-        }
-        
-        result
-    }
-    
-    pub fn get_stats(&self) -> String {
-        format!(
-            "JIT Stats: {} hot functions tracked, {} compiled",
-            self.hot_functions.len(),
-            self.compiled_functions.len()
-        )
+                let zero = self.builder.ins().f64const(0.0);
+                let by_zero = self.builder.ins().fcmp(FloatCC::Equal, right, zero);
+                self.bail_if(by_zero);
+                if matches!(instruction, BytecodeInstruction::Div) {
+                    self.builder.ins().fdiv(left, right)
+                } else {
+                    let float_mod = self.module.declare_func_in_func(callees.float_mod, self.builder.func);
+                    let call = self.builder.ins().call(float_mod, &[left, right]);
+                    self.builder.inst_results(call)[0]
+                }
+            }
+        };
+        (value, JitType::Float)
+    }
+
+    // `VMValue`'s `PartialEq` and `PartialOrd`; an ordered comparison the
+    // interpreter would reject bails out
+    fn comparison(&mut self, instruction: &BytecodeInstruction, left: Value, left_type: JitType, right: Value, right_type: JitType) -> Value {
+        let ordered = !matches!(instruction, BytecodeInstruction::Eq | BytecodeInstruction::Ne);
+        match (left_type, right_type) {
+            (JitType::Int, JitType::Int) | (JitType::Bool, JitType::Bool) => {
+                let signed = left_type == JitType::Int;
+                let condition = match instruction {
+                    BytecodeInstruction::Eq => IntCC::Equal,
+                    BytecodeInstruction::Ne => IntCC::NotEqual,
+                    BytecodeInstruction::Lt if signed => IntCC::SignedLessThan,
+                    BytecodeInstruction::Le if signed => IntCC::SignedLessThanOrEqual,
+                    BytecodeInstruction::Gt if signed => IntCC::SignedGreaterThan,
+                    BytecodeInstruction::Ge if signed => IntCC::SignedGreaterThanOrEqual,
+                    BytecodeInstruction::Lt => IntCC::UnsignedLessThan,
+                    BytecodeInstruction::Le => IntCC::UnsignedLessThanOrEqual,
+                    BytecodeInstruction::Gt => IntCC::UnsignedGreaterThan,
+                    _ => IntCC::UnsignedGreaterThanOrEqual,
+                };
+                self.builder.ins().icmp(condition, left, right)
+            }
+            (a, b) if a.is_number() && b.is_number() => {
+                let left = self.float_of(left, a);
+                let right = self.float_of(right, b);
+                if ordered {
+                    let unordered = self.builder.ins().fcmp(FloatCC::Unordered, left, right);
+                    self.bail_if(unordered);
+                }
+                let condition = match instruction {
+                    BytecodeInstruction::Eq => FloatCC::Equal,
+                    BytecodeInstruction::Ne => FloatCC::NotEqual,
+                    BytecodeInstruction::Lt => FloatCC::LessThan,
+                    BytecodeInstruction::Le => FloatCC::LessThanOrEqual,
+                    BytecodeInstruction::Gt => FloatCC::GreaterThan,
+                    _ => FloatCC::GreaterThanOrEqual,
+                };
+                self.builder.ins().fcmp(condition, left, right)
+            }
+            // Different kinds of value are never equal; `Null == Null`
+            (a, b) => {
+                let equal = a == b;
+                let value = matches!(instruction, BytecodeInstruction::Eq) == equal;
+                self.builder.ins().iconst(types::I8, value as i64)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::test_framework::golden::{run_program_at, ProgramOutput};
+    use crate::vm::{OutputCapture, VM};
+
+    /// Run `source` with every function compiled on its first call and
+    /// require the same output as the interpreter
+    fn run_jit(source: &str) -> (ProgramOutput, JitStats) {
+        let tokens = Lexer::new(source, "test.nx".to_string()).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let capture = OutputCapture::new();
+        let mut vm = VM::new();
+        vm.set_output_capture(capture.clone());
+        vm.set_jit(JITCompiler::new().unwrap().with_hot_threshold(1));
+        vm.load_instructions(crate::ir::compile_program(&program, 0).unwrap());
+        let exit_code = match vm.run() {
+            Ok(()) => 0,
+            Err(message) => {
+                capture.stderr.lock().unwrap().push_str(&format!("error: {}\n", message));
+                1
+            }
+        };
+        let output = ProgramOutput { stdout: capture.stdout(), stderr: capture.stderr(), exit_code };
+        assert_eq!(output, run_program_at(source, "test.nx", 0), "the JIT changed the behaviour of:\n{}", source);
+        (output, vm.jit_stats().unwrap())
+    }
+
+    #[test]
+    fn test_recursive_function_runs_natively() {
+        let (output, stats) = run_jit(
+            "fn fib(n: Int) -> Int {\n    if n < 2 {\n        return n;\n    }\n    return fib(n - 1) + fib(n - 2);\n}\n\
+             fn main() {\n    println(fib(20));\n    println(fib(21));\n}\n",
+        );
+        assert_eq!(output.stdout, "6765\n10946\n");
+        assert_eq!(stats.compiled, 1);
+        assert_eq!(stats.native_calls, 2);
+        assert_eq!(stats.bailouts, 0);
+    }
+
+    #[test]
+    fn test_loops_floats_and_mutual_recursion() {
+        let (output, stats) = run_jit(
+            "fn is_even(n: Int) -> Bool {\n    if n == 0 {\n        return true;\n    }\n    return is_odd(n - 1);\n}\n\
+             fn is_odd(n: Int) -> Bool {\n    if n == 0 {\n        return false;\n    }\n    return is_even(n - 1);\n}\n\
+             fn series(terms: Int) -> Float {\n    let sum = 0.0;\n    let k = 1;\n    while k <= terms {\n        sum = sum + 1.0 / (k * k);\n        k = k + 1;\n    }\n    return sum;\n}\n\
+             fn main() {\n    println(is_even(1000));\n    println(is_odd(7));\n    println(series(1000));\n    println(7 % 3 + 7.5 % 2);\n}\n",
+        );
+        assert_eq!(output.stdout, "true\ntrue\n1.6439345666815615\n2.5\n");
+        assert_eq!(stats.compiled, 3);
+    }
+
+    #[test]
+    fn test_new_argument_types_get_their_own_version() {
+        let (output, stats) = run_jit(
+            "fn twice(x: Int) -> Int {\n    return x + x;\n}\n\
+             fn main() {\n    println(twice(2));\n    println(twice(2.5));\n    println(twice(\"ab\"));\n}\n",
+        );
+        assert_eq!(output.stdout, "4\n5\nabab\n");
+        assert_eq!(stats.compiled, 2);
+        assert_eq!(stats.native_calls, 2);
+        assert_eq!(stats.guard_failures, 2);
+    }
+
+    #[test]
+    fn test_bailouts_fall_back_to_the_interpreter() {
+        let (output, stats) = run_jit(
+            "fn ratio(a: Int, b: Int) -> Int {\n    return a / b;\n}\n\
+             fn less(a: Float, b: Float) -> Bool {\n    return a < b;\n}\n\
+             fn main() {\n    println(ratio(7, 2));\n    try {\n        println(ratio(1, 0));\n    } catch e {\n        println(e);\n    }\n    println(less(0.0 / 1.0, 1.0));\n    let big = pow(10.0, 400.0);\n    less(big - big, 1.0);\n}\n",
+        );
+        assert_eq!(output.stdout, "3\nDivision by zero\ntrue\n");
+        assert!(output.stderr.contains("Cannot compare values"));
+        assert_eq!(stats.bailouts, 2);
+    }
+
+    #[test]
+    fn test_deep_recursion_and_stack_overflow() {
+        let source = "fn depth(n: Int) -> Int {\n    if n == 0 {\n        return 0;\n    }\n    return 1 + depth(n - 1);\n}\n\
+                      fn main() {\n    println(depth(5000));\n    println(depth(20000));\n}\n";
+        let (output, stats) = run_jit(source);
+        assert_eq!(output.stdout, "5000\n");
+        assert!(output.stderr.contains("StackOverflow"));
+        assert!(stats.bailouts > 0);
+    }
+
+    #[test]
+    fn test_functions_with_side_effects_are_not_compiled() {
+        let (output, stats) = run_jit(
+            "fn shout(n: Int) -> Int {\n    println(n);\n    return n;\n}\n\
+             fn main() {\n    shout(1);\n    shout(2);\n}\n",
+        );
+        assert_eq!(output.stdout, "1\n2\n");
+        assert_eq!(stats.compiled, 0);
+        assert_eq!(stats.native_calls, 0);
     }
 }
@@ -36,6 +36,7 @@ pub enum Token {
     Class,
     Extends,
     Import,
+    Extern,
     As,
     
     // Ownership and borrowing keywords
@@ -197,6 +198,8 @@ impl<'a> Lexer<'a> {
         let start_line = self.line;
         let start_column = self.column;
         
+        // Words keep their spelling, so keywords can still be used where any name is allowed
+        let mut word = None;
         let token = match self.input.next().unwrap() {
             '(' => Token::LeftParen,
             ')' => Token::RightParen,
@@ -230,6 +233,17 @@ impl<'a> Lexer<'a> {
                 }
             }
             '@' => Token::At,
+            '#' => {
+                // Skip `#` comments, like `//` ones
+                for c in self.input.by_ref() {
+                    if c == '\n' {
+                        self.line += 1;
+                        self.column = 1;
+                        break;
+                    }
+                }
+                return self.next_token();
+            }
 
             '+' => Token::Plus,
             '-' => {
@@ -324,12 +338,16 @@ impl<'a> Lexer<'a> {
             '$' => Token::Dollar, // Add support for $ character
             '"' => self.read_string()?,
             '\'' => self.read_character()?,
-            c if c.is_alphabetic() || c == '_' => self.read_identifier_or_keyword(c)?,
+            c if c.is_alphabetic() || c == '_' => {
+                let (token, text) = self.read_identifier_or_keyword(c)?;
+                word = Some(text);
+                token
+            }
             c if c.is_digit(10) => self.read_number(c)?,
             c => return Err(format!("Unexpected character: {}", c)),
         };
         
-        let lexeme = self.get_lexeme(start_line, start_column);
+        let lexeme = word.unwrap_or_else(|| self.get_lexeme(start_line, start_column));
         
         Ok(Some(TokenInfo {
             token,
@@ -438,7 +456,7 @@ impl<'a> Lexer<'a> {
         }
     }
     
-    fn read_identifier_or_keyword(&mut self, first: char) -> Result<Token, String> {
+    fn read_identifier_or_keyword(&mut self, first: char) -> Result<(Token, String), String> {
         let mut identifier = String::from(first);
         
         while let Some(&c) = self.input.peek() {
@@ -449,7 +467,7 @@ impl<'a> Lexer<'a> {
             }
         }
         
-        Ok(match identifier.as_str() {
+        let token = match identifier.as_str() {
             "let" => Token::Let,
             "mut" => Token::Mut,
             "struct" => Token::Struct,
@@ -512,9 +530,11 @@ impl<'a> Lexer<'a> {
             "class" => Token::Class,
             "extends" => Token::Extends,
             "import" => Token::Import,
+            "extern" => Token::Extern,
             "as" => Token::As,
-            _ => Token::Identifier(identifier),
-        })
+            _ => Token::Identifier(identifier.clone()),
+        };
+        Ok((token, identifier))
     }
     
     fn read_number(&mut self, first: char) -> Result<Token, String> {
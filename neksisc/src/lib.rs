@@ -1,5 +1,6 @@
 // Modern Neksis 2025 Library
 pub mod ast;
+pub mod visit;
 pub mod lexer;
 pub mod parser;
 pub mod semantic;
@@ -10,11 +11,21 @@ pub mod repl;
 pub mod stdlib;
 pub mod optimizer;
 pub mod optimization_analysis;
+pub mod ir;
 pub mod vm;
+pub mod jit_compiler;
+pub mod wasm;
+pub mod transpile;
 pub mod bytecode_compiler;
+pub mod bytecode_file;
 pub mod package_manager;
 pub mod lsp;
 pub mod tests;
+pub mod test_framework;
+pub mod benchmark;
+pub mod coverage;
+pub mod profiler;
+pub mod heap_profiler;
 pub mod cli;
 pub mod formatter;
 pub mod linter;
@@ -59,9 +70,16 @@ pub mod test_oop_modules;
 // Advanced features
 pub mod type_inference;
 pub mod memory_profiler;
+pub mod memory_manager;
 pub mod borrow_checker;
 pub mod macro_system;
 pub mod ffi;
+pub mod bindgen;
+pub mod permissions;
+pub mod limits;
+pub mod engine;
+pub mod capi;
+pub mod python_module;
 pub mod concurrency;
 
 use crate::error::CompilerError;
@@ -77,6 +95,11 @@ pub struct Compiler {
 pub use crate::compiler::FastCompiler;
 pub use crate::compiler::CompilerOptions;
 
+// Hosting Neksis scripts in Rust programs
+pub use crate::engine::{Engine, FromValue, IntoValue};
+pub use crate::permissions::{Capability, Permissions};
+pub use crate::limits::{Limit, Limits};
+
 impl Compiler {
     pub fn new() -> Result<Self, CompilerError> {
         Ok(Self {
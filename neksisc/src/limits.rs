@@ -0,0 +1,303 @@
+// Resource Limits
+//
+// Bounds on what one run of a program may use, so a host can run code it does
+// not trust: instructions executed (fuel), heap bytes, call depth and wall
+// time. The budget starts when the VM starts running, or when a host calls
+// one of the program's functions, and callbacks from C share the budget of
+// the call they run in.
+//
+// Exceeding the call depth raises the catchable `StackOverflow` error, as it
+// always has. The other limits stop the program with an error naming the
+// limit that `try`/`catch` cannot handle. The heap is measured like the heap
+// profile, by walking every value reachable from the VM, whenever the values
+// created since the last walk could have taken it past the limit and at least
+// as often as there are values to walk.
+//
+// Fuel and time are checked between instructions, so neither can stop a
+// built-in that blocks, such as `sleep` or `read_line`, or a host function
+// before it returns; the run stops at the next instruction after it.
+
+use crate::heap_profiler::{self, format_bytes};
+use crate::vm::{BytecodeInstruction, VMValue, DEFAULT_MAX_CALL_DEPTH};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// The clock is read once per this many instructions
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+// The heap is measured at least this often, in instructions
+const MIN_MEASURE_INTERVAL: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Instructions,
+    Memory,
+    CallDepth,
+    Time,
+}
+
+impl Limit {
+    /// The name that starts the error message
+    pub fn error_name(self) -> &'static str {
+        match self {
+            Limit::Instructions => "InstructionLimitExceeded",
+            Limit::Memory => "MemoryLimitExceeded",
+            Limit::CallDepth => "StackOverflow",
+            Limit::Time => "TimeLimitExceeded",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    pub max_instructions: Option<u64>,
+    pub max_heap_bytes: Option<usize>,
+    pub max_call_depth: usize,
+    /// Checked between instructions, so a blocking built-in or host function
+    /// can overrun it by as long as it takes to return
+    pub max_wall_time: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_instructions: None,
+            max_heap_bytes: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_wall_time: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Whether instructions must run in the VM to be counted and timed, rather than as native code
+    pub fn needs_interpreter(&self) -> bool {
+        self.max_instructions.is_some() || self.max_wall_time.is_some()
+    }
+
+    pub(crate) fn message(&self, limit: Limit) -> String {
+        let detail = match limit {
+            Limit::Instructions => format!("ran more than {} instructions", self.max_instructions.unwrap_or_default()),
+            Limit::Memory => format!("heap grew past {}", format_bytes(self.max_heap_bytes.unwrap_or_default())),
+            Limit::CallDepth => format!("maximum call depth of {} exceeded", self.max_call_depth),
+            Limit::Time => format!("ran longer than {:?}", self.max_wall_time.unwrap_or_default()),
+        };
+        format!("{}: {}", limit.error_name(), detail)
+    }
+}
+
+/// `1048576`, `512K`, `64MB` or `1G`; the units are powers of 1024
+pub fn parse_bytes(text: &str) -> Result<usize, String> {
+    let upper = text.trim().to_ascii_uppercase();
+    let number = upper.trim_end_matches('B').trim_end_matches('I');
+    let (digits, unit) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        _ => (number, 1),
+    };
+    digits
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("'{}' is not a size such as 4096, 512K or 64MB", text))
+}
+
+/// `250ms`, `2s` or `1.5s`; a bare number is milliseconds
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let invalid = || format!("'{}' is not a duration such as 500ms or 2s", text);
+    let millis = text.strip_suffix("ms").unwrap_or(text);
+    if let Ok(millis) = millis.trim().parse::<u64>() {
+        return Ok(Duration::from_millis(millis));
+    }
+    let seconds = text.strip_suffix('s').ok_or_else(invalid)?;
+    seconds
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(invalid)
+}
+
+// How an instruction can add to the heap
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum HeapEffect {
+    Nothing,
+    // Leaves a value that may be newly allocated
+    Allocates,
+    // Adds an element of about this many bytes to the container it leaves
+    Grows(usize),
+}
+
+pub(crate) fn heap_effect(instruction: &BytecodeInstruction) -> HeapEffect {
+    match instruction {
+        BytecodeInstruction::ArrayPush => HeapEffect::Grows(std::mem::size_of::<VMValue>()),
+        BytecodeInstruction::DictSet | BytecodeInstruction::SetProperty(_) => {
+            HeapEffect::Grows(std::mem::size_of::<String>() + std::mem::size_of::<VMValue>())
+        }
+        // Results of built-ins and native functions
+        BytecodeInstruction::Call(..) | BytecodeInstruction::CallMethod(..) => HeapEffect::Allocates,
+        instruction if heap_profiler::always_allocates(instruction) => HeapEffect::Allocates,
+        _ => HeapEffect::Nothing,
+    }
+}
+
+// Bytes `value` may have just allocated. Containers are shared rather than
+// copied, so one referenced from anywhere but the stack is not new.
+fn new_bytes(value: &VMValue) -> usize {
+    match value {
+        VMValue::String(text) => text.capacity(),
+        VMValue::Array(items) if Rc::strong_count(items) == 1 => heap_profiler::allocation_size(value),
+        VMValue::Object(fields) if Rc::strong_count(fields) == 1 => heap_profiler::allocation_size(value),
+        _ => 0,
+    }
+}
+
+// What the current run has used so far
+pub(crate) struct Usage {
+    instructions: u64,
+    started: Instant,
+    // Heap bytes at the last measurement, and an estimate of what was added since
+    heap_measured: usize,
+    heap_added: usize,
+    until_measurement: usize,
+}
+
+impl Usage {
+    pub(crate) fn start() -> Self {
+        Self {
+            instructions: 0,
+            started: Instant::now(),
+            heap_measured: 0,
+            heap_added: 0,
+            until_measurement: MIN_MEASURE_INTERVAL,
+        }
+    }
+
+    /// Count one instruction against the fuel and time limits
+    pub(crate) fn step(&mut self, limits: &Limits) -> Result<(), Limit> {
+        self.instructions += 1;
+        if limits.max_instructions.is_some_and(|max| self.instructions > max) {
+            return Err(Limit::Instructions);
+        }
+        if let Some(max) = limits.max_wall_time {
+            if self.instructions.is_multiple_of(TIME_CHECK_INTERVAL) && self.started.elapsed() > max {
+                return Err(Limit::Time);
+            }
+        }
+        Ok(())
+    }
+
+    /// Account for an instruction with `effect` that left `result` on the
+    /// stack. Returns true when the heap has to be measured.
+    pub(crate) fn allocated(&mut self, limits: &Limits, effect: HeapEffect, result: Option<&VMValue>) -> bool {
+        let Some(max) = limits.max_heap_bytes else { return false };
+        self.heap_added += match (effect, result) {
+            (HeapEffect::Allocates, Some(value)) => new_bytes(value),
+            (HeapEffect::Grows(bytes), _) => bytes,
+            _ => 0,
+        };
+        self.until_measurement = self.until_measurement.saturating_sub(1);
+        self.until_measurement == 0 || self.heap_measured + self.heap_added > max
+    }
+
+    /// Record a measurement of `bytes` found by walking `values` values
+    pub(crate) fn measured(&mut self, limits: &Limits, bytes: usize, values: usize) -> Result<(), Limit> {
+        self.heap_measured = bytes;
+        self.heap_added = 0;
+        // Walking is proportional to the values walked, so it is spread over as many instructions
+        self.until_measurement = values.max(MIN_MEASURE_INTERVAL);
+        if limits.max_heap_bytes.is_some_and(|max| bytes > max) {
+            return Err(Limit::Memory);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_framework::golden::run_program;
+    use crate::vm::VM;
+
+    fn run_limited(source: &str, limits: Limits) -> (Result<(), String>, Option<Limit>, String) {
+        let tokens = crate::lexer::Lexer::new(source, "limits.nx".to_string()).tokenize().unwrap();
+        let program = crate::parser::Parser::new(tokens).parse().unwrap();
+        let capture = crate::vm::OutputCapture::new();
+        let mut vm = VM::new();
+        vm.set_output_capture(capture.clone());
+        vm.set_limits(limits);
+        vm.load_instructions(crate::ir::compile_program(&program, 0).unwrap());
+        let result = vm.run();
+        (result, vm.limit_exceeded(), capture.stdout())
+    }
+
+    #[test]
+    fn test_parse_sizes_and_durations() {
+        assert_eq!(parse_bytes("4096"), Ok(4096));
+        assert_eq!(parse_bytes("512K"), Ok(512 * 1024));
+        assert_eq!(parse_bytes("64MB"), Ok(64 << 20));
+        assert_eq!(parse_bytes("1GiB"), Ok(1 << 30));
+        assert_eq!(parse_bytes("lots").unwrap_err(), "'lots' is not a size such as 4096, 512K or 64MB");
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("750"), Ok(Duration::from_millis(750)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("soon").unwrap_err(), "'soon' is not a duration such as 500ms or 2s");
+    }
+
+    #[test]
+    fn test_instruction_limit_cannot_be_caught() {
+        let spin = "let i = 0;\ntry {\n    while true { i = i + 1; }\n} catch (e) {\n    print(\"caught\");\n}\n";
+        let limits = Limits { max_instructions: Some(10_000), ..Limits::default() };
+        let (result, limit, stdout) = run_limited(spin, limits);
+        assert_eq!(result.unwrap_err(), "InstructionLimitExceeded: ran more than 10000 instructions");
+        assert_eq!(limit, Some(Limit::Instructions));
+        assert_eq!(stdout, "");
+
+        let limits = Limits { max_instructions: Some(10_000), ..Limits::default() };
+        let (result, limit, stdout) = run_limited("let total = 0;\nlet i = 0;\nwhile i < 100 { total = total + i; i = i + 1; }\nprint(total);", limits);
+        assert!(result.is_ok() && limit.is_none());
+        assert_eq!(stdout, "4950\n");
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let grow = "let text = \"x\";\nwhile true { text = text + text; }\n";
+        let limits = Limits { max_heap_bytes: Some(1 << 20), ..Limits::default() };
+        let (result, limit, _) = run_limited(grow, limits);
+        assert_eq!(result.unwrap_err(), "MemoryLimitExceeded: heap grew past 1.0 MiB");
+        assert_eq!(limit, Some(Limit::Memory));
+
+        let push = "let items = [];\nwhile true { array_push(items, 1); }\n";
+        let limits = Limits { max_heap_bytes: Some(1 << 20), max_instructions: Some(10_000_000), ..Limits::default() };
+        assert_eq!(run_limited(push, limits).1, Some(Limit::Memory));
+
+        // Garbage does not count, only what is still reachable
+        let churn = "let i = 0;\nwhile i < 20000 { let s = \"abcdefghijklmnopqrstuvwxyz\" + i; i = i + 1; }\nprint(\"done\");";
+        let limits = Limits { max_heap_bytes: Some(64 * 1024), ..Limits::default() };
+        let (result, limit, stdout) = run_limited(churn, limits);
+        assert!(result.is_ok() && limit.is_none(), "{:?}", result);
+        assert_eq!(stdout, "done\n");
+    }
+
+    #[test]
+    fn test_time_and_call_depth_limits() {
+        let limits = Limits { max_wall_time: Some(Duration::from_millis(50)), ..Limits::default() };
+        let started = Instant::now();
+        let (result, limit, _) = run_limited("while true { }\n", limits);
+        assert_eq!(result.unwrap_err(), "TimeLimitExceeded: ran longer than 50ms");
+        assert_eq!(limit, Some(Limit::Time));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let recurse = "fn down(n: Int) -> Int { return 1 + down(n + 1); }\ndown(0);\n";
+        let limits = Limits { max_call_depth: 50, ..Limits::default() };
+        let (result, limit, _) = run_limited(recurse, limits);
+        assert!(result.unwrap_err().starts_with("StackOverflow: maximum call depth of 50 exceeded"));
+        assert_eq!(limit, Some(Limit::CallDepth));
+
+        // Without limits programs run as before
+        assert_eq!(run_program("print(1 + 1);", "plain.nx").stdout, "2\n");
+    }
+}
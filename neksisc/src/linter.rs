@@ -1,8 +1,23 @@
-use crate::ast::{Program, Statement, Expression, FunctionStatement};
+// Source linter
+//
+// Rules run over the parsed program, using the parser's line markers to place
+// issues. Severities can be overridden in the `lint` section of `nexus.json`,
+// `# neksis-lint: allow(rule)` comments silence a rule for one line, and rules
+// that know how to repair what they report attach a `LintFix`.
+
+use crate::ast::{
+    BinaryOperator, Expression, FunctionStatement, Literal, MatchArm, Parameter, Pattern, Program, Statement, Type,
+    UnaryOperator,
+};
 use crate::lexer::Lexer;
 use crate::parser::Parser;
 use crate::error::CompilerError;
-use std::collections::HashMap;
+use crate::package_manager::PackageManifest;
+use crate::visit::{self, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
 #[derive(Debug, Clone)]
 pub struct LintRule {
@@ -19,6 +34,63 @@ pub enum LintSeverity {
     Info,
 }
 
+impl LintSeverity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintSeverity::Error => "error",
+            LintSeverity::Warning => "warning",
+            LintSeverity::Info => "info",
+        }
+    }
+
+    /// The matching SARIF result level
+    fn sarif_level(&self) -> &'static str {
+        match self {
+            LintSeverity::Error => "error",
+            LintSeverity::Warning => "warning",
+            LintSeverity::Info => "note",
+        }
+    }
+}
+
+/// A rule's level in the `lint` section of `nexus.json`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleLevel {
+    Off,
+    Info,
+    Warning,
+    Error,
+}
+
+/// The `lint` section of `nexus.json`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LintConfig {
+    /// Longest line `long_line` accepts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_line_length: Option<usize>,
+    /// Level of each rule that differs from its default
+    pub rules: HashMap<String, RuleLevel>,
+}
+
+impl LintConfig {
+    /// The `lint` section of `dir/nexus.json`, or the defaults when there is none
+    pub fn load(dir: &Path) -> Result<Self, CompilerError> {
+        let manifest = PackageManifest::load(dir)?;
+        Ok(manifest.and_then(|manifest| manifest.lint).unwrap_or_default())
+    }
+}
+
+/// Replacing `length` characters of `line` from `column` with `replacement` repairs an issue
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintFix {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub replacement: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct LintIssue {
     pub rule: String,
@@ -27,6 +99,14 @@ pub struct LintIssue {
     pub line: usize,
     pub column: usize,
     pub suggestion: Option<String>,
+    pub fix: Option<LintFix>,
+}
+
+/// The issues found in one file
+#[derive(Debug, Clone)]
+pub struct FileLint {
+    pub file: String,
+    pub issues: Vec<LintIssue>,
 }
 
 pub struct Linter {
@@ -48,6 +128,25 @@ impl Linter {
         linter
     }
 
+    /// A linter with the severities and limits from a `lint` manifest section
+    pub fn from_config(config: &LintConfig) -> Result<Self, CompilerError> {
+        let mut linter = Self::new();
+        if let Some(length) = config.max_line_length {
+            linter.max_line_length = length;
+        }
+        for (name, level) in &config.rules {
+            let rule = linter.rules.get_mut(name)
+                .ok_or_else(|| CompilerError::config_error(&format!("Unknown lint rule '{}'", name)))?;
+            rule.enabled = *level != RuleLevel::Off;
+            rule.severity = match level {
+                RuleLevel::Error => LintSeverity::Error,
+                RuleLevel::Warning => LintSeverity::Warning,
+                RuleLevel::Info | RuleLevel::Off => LintSeverity::Info,
+            };
+        }
+        Ok(linter)
+    }
+
     fn register_default_rules(&mut self) {
         let default_rules = vec![
             LintRule {
@@ -86,6 +185,36 @@ impl Linter {
                 severity: LintSeverity::Info,
                 enabled: true,
             },
+            LintRule {
+                name: "shadowing".to_string(),
+                description: "Variable shadows an earlier declaration".to_string(),
+                severity: LintSeverity::Warning,
+                enabled: true,
+            },
+            LintRule {
+                name: "unused_parameter".to_string(),
+                description: "Function parameter is never used".to_string(),
+                severity: LintSeverity::Warning,
+                enabled: true,
+            },
+            LintRule {
+                name: "constant_condition".to_string(),
+                description: "Condition always has the same value".to_string(),
+                severity: LintSeverity::Warning,
+                enabled: true,
+            },
+            LintRule {
+                name: "empty_match_arm".to_string(),
+                description: "Match arm has an empty body".to_string(),
+                severity: LintSeverity::Warning,
+                enabled: true,
+            },
+            LintRule {
+                name: "assignment_in_condition".to_string(),
+                description: "Assignment used as a condition".to_string(),
+                severity: LintSeverity::Warning,
+                enabled: true,
+            },
         ];
 
         for rule in default_rules {
@@ -93,216 +222,735 @@ impl Linter {
         }
     }
 
+    /// Every rule, sorted by name
+    pub fn rules(&self) -> Vec<&LintRule> {
+        let mut rules: Vec<&LintRule> = self.rules.values().collect();
+        rules.sort_by(|a, b| a.name.cmp(&b.name));
+        rules
+    }
+
     pub fn lint_source(&self, source: &str, filename: &str) -> Result<Vec<LintIssue>, CompilerError> {
-        let mut issues = Vec::new();
-        
-        // Parse the source code
         let mut lexer = Lexer::new(source, filename.to_string());
         let tokens = lexer.tokenize()?;
-        let mut parser = Parser::new(tokens);
+        let mut parser = Parser::new(tokens).with_line_markers();
         let ast = parser.parse()?;
 
-        // Check line length
-        self.check_line_length(source, &mut issues);
-        
-        // Analyze AST for issues
-        self.analyze_program(&ast, source, &mut issues)?;
+        let mut checker = Checker::new(self, source);
+        self.check_line_length(source, &mut checker);
+        checker.visit_program(&ast);
 
+        let allowed = suppressions(source);
+        let mut issues: Vec<LintIssue> = checker.issues
+            .into_iter()
+            .filter(|issue| !allowed.get(&issue.line).is_some_and(|rules| rules.contains(&issue.rule)))
+            .collect();
+        issues.sort_by_key(|issue| (issue.line, issue.column));
         Ok(issues)
     }
 
-    fn check_line_length(&self, source: &str, issues: &mut Vec<LintIssue>) {
+    fn check_line_length(&self, source: &str, checker: &mut Checker) {
         for (line_num, line) in source.lines().enumerate() {
-            if line.len() > self.max_line_length {
-                issues.push(LintIssue {
-                    rule: "long_line".to_string(),
-                    message: format!("Line {} exceeds maximum length of {} characters", line_num + 1, self.max_line_length),
-                    severity: LintSeverity::Warning,
-                    line: line_num + 1,
-                    column: self.max_line_length + 1,
-                    suggestion: Some("Consider breaking the line into multiple lines".to_string()),
-                });
+            if line.chars().count() > self.max_line_length {
+                checker.report(
+                    "long_line",
+                    line_num + 1,
+                    self.max_line_length + 1,
+                    format!("Line {} exceeds maximum length of {} characters", line_num + 1, self.max_line_length),
+                    "Consider breaking the line into multiple lines",
+                    None,
+                );
             }
         }
     }
 
-    fn analyze_program(&self, program: &Program, _source: &str, issues: &mut Vec<LintIssue>) -> Result<(), CompilerError> {
-        let mut used_variables = HashMap::new();
-        let mut declared_functions = HashMap::new();
-        let mut called_functions = HashMap::new();
+    pub fn with_max_line_length(mut self, length: usize) -> Self {
+        self.max_line_length = length;
+        self
+    }
 
-        for statement in &program.statements {
-            self.analyze_statement(statement, &mut used_variables, &mut declared_functions, &mut called_functions, issues)?;
+    pub fn with_allow_unused_variables(mut self, allow: bool) -> Self {
+        self.allow_unused_variables = allow;
+        self
+    }
+
+    pub fn with_allow_unused_functions(mut self, allow: bool) -> Self {
+        self.allow_unused_functions = allow;
+        self
+    }
+
+    pub fn enable_rule(&mut self, rule_name: &str) {
+        if let Some(rule) = self.rules.get_mut(rule_name) {
+            rule.enabled = true;
         }
+    }
 
-        // Check for unused variables
-        if !self.allow_unused_variables {
-            for (var_name, _) in &used_variables {
-                if !used_variables.contains_key(var_name) {
-                    issues.push(LintIssue {
-                        rule: "unused_variable".to_string(),
-                        message: format!("Variable '{}' is declared but never used", var_name),
-                        severity: LintSeverity::Warning,
-                        line: 1, // TODO: Get actual line number
-                        column: 1,
-                        suggestion: Some("Remove the variable or use it in your code".to_string()),
-                    });
-                }
-            }
+    pub fn disable_rule(&mut self, rule_name: &str) {
+        if let Some(rule) = self.rules.get_mut(rule_name) {
+            rule.enabled = false;
         }
+    }
 
-        // Check for unused functions
-        if !self.allow_unused_functions {
-            for (func_name, _) in &declared_functions {
-                if !called_functions.contains_key(func_name) && func_name != "main" {
-                    issues.push(LintIssue {
-                        rule: "unused_function".to_string(),
-                        message: format!("Function '{}' is declared but never called", func_name),
-                        severity: LintSeverity::Warning,
-                        line: 1, // TODO: Get actual line number
-                        column: 1,
-                        suggestion: Some("Remove the function or call it from your code".to_string()),
-                    });
-                }
+    /// The results as a JSON document
+    pub fn render_json(&self, results: &[FileLint]) -> String {
+        let issues: Vec<_> = results
+            .iter()
+            .flat_map(|result| {
+                result.issues.iter().map(move |issue| {
+                    json!({
+                        "file": result.file,
+                        "rule": issue.rule,
+                        "severity": issue.severity.name(),
+                        "message": issue.message,
+                        "line": issue.line,
+                        "column": issue.column,
+                        "suggestion": issue.suggestion,
+                        "fixable": issue.fix.is_some(),
+                    })
+                })
+            })
+            .collect();
+        let document = json!({
+            "summary": {
+                "files": results.len(),
+                "issues": issues.len(),
+            },
+            "issues": issues,
+        });
+        serde_json::to_string_pretty(&document).unwrap_or_default()
+    }
+
+    /// The results as a SARIF 2.1.0 log for code-scanning dashboards
+    pub fn render_sarif(&self, results: &[FileLint]) -> String {
+        let rules: Vec<_> = self
+            .rules()
+            .into_iter()
+            .map(|rule| {
+                json!({
+                    "id": rule.name,
+                    "shortDescription": { "text": rule.description },
+                    "defaultConfiguration": {
+                        "enabled": rule.enabled,
+                        "level": rule.severity.sarif_level(),
+                    },
+                })
+            })
+            .collect();
+        let sarif_results: Vec<_> = results
+            .iter()
+            .flat_map(|result| {
+                result.issues.iter().map(move |issue| {
+                    json!({
+                        "ruleId": issue.rule,
+                        "level": issue.severity.sarif_level(),
+                        "message": { "text": issue.message },
+                        "locations": [{
+                            "physicalLocation": {
+                                "artifactLocation": { "uri": result.file },
+                                "region": { "startLine": issue.line, "startColumn": issue.column },
+                            },
+                        }],
+                    })
+                })
+            })
+            .collect();
+        let document = json!({
+            "version": "2.1.0",
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "neksis-lint",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": rules,
+                    },
+                },
+                "results": sarif_results,
+            }],
+        });
+        serde_json::to_string_pretty(&document).unwrap_or_default()
+    }
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Apply the fixes attached to `issues`, returning the new source and how many were applied.
+/// A fix that overlaps one further along its line is skipped.
+pub fn apply_fixes(source: &str, issues: &[LintIssue]) -> (String, usize) {
+    let mut fixes_by_line: HashMap<usize, Vec<&LintFix>> = HashMap::new();
+    for fix in issues.iter().filter_map(|issue| issue.fix.as_ref()) {
+        fixes_by_line.entry(fix.line).or_default().push(fix);
+    }
+
+    let mut applied = 0;
+    let mut output = String::with_capacity(source.len());
+    for (index, line) in source.split_inclusive('\n').enumerate() {
+        let Some(fixes) = fixes_by_line.get_mut(&(index + 1)) else {
+            output.push_str(line);
+            continue;
+        };
+        // Work from the end of the line so earlier columns stay valid
+        fixes.sort_by_key(|fix| std::cmp::Reverse(fix.column));
+        let mut chars: Vec<char> = line.chars().collect();
+        let mut limit = chars.len();
+        for fix in fixes.iter() {
+            let start = fix.column - 1;
+            let end = start + fix.length;
+            if end > limit {
+                continue;
             }
+            chars.splice(start..end, fix.replacement.chars());
+            limit = start;
+            applied += 1;
         }
+        output.extend(chars);
+    }
+    (output, applied)
+}
 
-        Ok(())
+/// Lines on which rules are allowed by `neksis-lint: allow(...)` comments. A comment
+/// after code applies to its own line, one on a line of its own to the next line of code.
+fn suppressions(source: &str) -> HashMap<usize, HashSet<String>> {
+    let mut allowed: HashMap<usize, HashSet<String>> = HashMap::new();
+    let mut pending = HashSet::new();
+    for (index, line) in source.lines().enumerate() {
+        let (code, comment) = match comment_start(line) {
+            Some(start) => (&line[..start], &line[start..]),
+            None => (line, ""),
+        };
+        let directive = comment
+            .trim_start_matches('#')
+            .trim_start_matches("//")
+            .trim()
+            .strip_prefix("neksis-lint:")
+            .and_then(|rest| rest.trim().strip_prefix("allow("))
+            .and_then(|rest| rest.split(')').next());
+        if let Some(rules) = directive {
+            pending.extend(rules.split(',').map(|rule| rule.trim().to_string()).filter(|rule| !rule.is_empty()));
+        }
+        if !code.trim().is_empty() && !pending.is_empty() {
+            allowed.entry(index + 1).or_default().extend(pending.drain());
+        }
     }
+    allowed
+}
 
-    fn analyze_statement(
-        &self,
-        statement: &Statement,
-        used_variables: &mut HashMap<String, bool>,
-        declared_functions: &mut HashMap<String, bool>,
-        called_functions: &mut HashMap<String, bool>,
-        issues: &mut Vec<LintIssue>,
-    ) -> Result<(), CompilerError> {
-        match statement {
-            Statement::Function(func) => {
-                declared_functions.insert(func.name.clone(), true);
-                self.analyze_function(func, used_variables, called_functions, issues)?;
-            }
-            Statement::Let(let_stmt) => {
-                used_variables.insert(let_stmt.name.clone(), false);
-                self.analyze_expression(&let_stmt.value, used_variables, called_functions)?;
-            }
-            Statement::Expression(expr) => {
-                self.analyze_expression(expr, used_variables, called_functions)?;
+/// Byte offset of the `#` or `//` comment on `line`, ignoring ones inside strings
+fn comment_start(line: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut previous_slash = false;
+    for (index, c) in line.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
             }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '#' => return Some(index),
+            '/' if previous_slash => return Some(index - 1),
             _ => {}
         }
-        Ok(())
+        previous_slash = c == '/';
     }
+    None
+}
 
-    fn analyze_function(
-        &self,
-        func: &FunctionStatement,
-        used_variables: &mut HashMap<String, bool>,
-        called_functions: &mut HashMap<String, bool>,
-        issues: &mut Vec<LintIssue>,
-    ) -> Result<(), CompilerError> {
-        // Check for empty function body
-        if let Expression::Block(statements) = &*func.body {
-            if statements.is_empty() {
-                issues.push(LintIssue {
-                    rule: "empty_function".to_string(),
-                    message: format!("Function '{}' has empty body", func.name),
-                    severity: LintSeverity::Info,
-                    line: 1, // TODO: Get actual line number
-                    column: 1,
-                    suggestion: Some("Add implementation or remove the function".to_string()),
-                });
+/// Byte offset of `word` in `text` at or after `from`, as a whole identifier
+fn find_word(text: &str, word: &str, from: usize) -> Option<usize> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    text.get(from..)?
+        .match_indices(word)
+        .map(|(offset, _)| from + offset)
+        .find(|&start| {
+            let before = text[..start].chars().next_back();
+            let after = text[start + word.len()..].chars().next();
+            !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+        })
+}
+
+/// The value of a condition that cannot change at run time
+fn constant_condition(expression: &Expression) -> Option<bool> {
+    let (left, operator, right) = match expression {
+        Expression::Literal(Literal::Bool(value)) => return Some(*value),
+        Expression::UnaryOp(unary) if unary.operator == UnaryOperator::Not => {
+            return constant_condition(&unary.operand).map(|value| !value);
+        }
+        Expression::BinaryOp(binary) => (&*binary.left, &binary.operator, &*binary.right),
+        Expression::BinaryOperation { left, operator, right } => (&**left, operator, &**right),
+        _ => return None,
+    };
+    match operator {
+        BinaryOperator::And => Some(constant_condition(left)? && constant_condition(right)?),
+        BinaryOperator::Or => Some(constant_condition(left)? || constant_condition(right)?),
+        _ => {
+            let (Expression::Literal(Literal::Int(a)), Expression::Literal(Literal::Int(b))) = (left, right) else {
+                return None;
+            };
+            match operator {
+                BinaryOperator::Equal => Some(a == b),
+                BinaryOperator::NotEqual => Some(a != b),
+                BinaryOperator::LessThan => Some(a < b),
+                BinaryOperator::LessThanOrEqual => Some(a <= b),
+                BinaryOperator::GreaterThan => Some(a > b),
+                BinaryOperator::GreaterThanOrEqual => Some(a >= b),
+                _ => None,
             }
+        }
+    }
+}
 
-            // Check for missing return statement
-            if func.return_type.is_some() {
-                let has_return = statements.iter().any(|stmt| matches!(stmt, Statement::Return(_)));
-                if !has_return {
-                    issues.push(LintIssue {
-                        rule: "missing_return".to_string(),
-                        message: format!("Function '{}' has return type but no return statement", func.name),
-                        severity: LintSeverity::Error,
-                        line: 1, // TODO: Get actual line number
-                        column: 1,
-                        suggestion: Some("Add a return statement or change return type to Void".to_string()),
-                    });
-                }
+/// Whether every path through `statements` ends in a `return` or `throw`
+fn always_returns(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match statement {
+        Statement::Return(_) | Statement::ReturnStatement { .. } => true,
+        Statement::Expression(expression) => exits(expression),
+        Statement::ExpressionStatement { expression } => exits(expression),
+        _ => false,
+    })
+}
+
+fn exits(expression: &Expression) -> bool {
+    match expression {
+        Expression::Return(_) | Expression::Throw(_) => true,
+        Expression::Block(statements) => always_returns(statements),
+        Expression::If(if_expr) => {
+            exits(&if_expr.then_branch) && if_expr.else_branch.as_deref().is_some_and(exits)
+        }
+        _ => false,
+    }
+}
+
+/// Whether a block holds no statements besides line markers
+fn is_empty_block(expression: &Expression) -> bool {
+    match expression {
+        Expression::Block(statements) => statements.iter().all(|s| matches!(s, Statement::SourceLine(_))),
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BindingKind {
+    Variable,
+    Parameter,
+    /// Bound by a match arm; never reported as unused
+    Pattern,
+}
+
+#[derive(Debug)]
+struct Binding {
+    name: String,
+    line: usize,
+    kind: BindingKind,
+    used: bool,
+}
+
+/// Walks a program once, tracking scopes and reporting issues as it goes
+struct Checker<'a> {
+    linter: &'a Linter,
+    lines: Vec<&'a str>,
+    /// Line of the statement being checked
+    line: usize,
+    scopes: Vec<Vec<Binding>>,
+    /// Functions that nothing outside the program calls, with their lines
+    functions: Vec<(String, usize)>,
+    called: HashSet<String>,
+    /// Names used before any declaration in scope, such as globals read by earlier functions
+    unresolved: HashSet<String>,
+    issues: Vec<LintIssue>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(linter: &'a Linter, source: &'a str) -> Self {
+        Self {
+            linter,
+            lines: source.lines().collect(),
+            line: 1,
+            scopes: Vec::new(),
+            functions: Vec::new(),
+            called: HashSet::new(),
+            unresolved: HashSet::new(),
+            issues: Vec::new(),
+        }
+    }
+
+    fn report(&mut self, rule: &str, line: usize, column: usize, message: String, suggestion: &str, fix: Option<LintFix>) {
+        let Some(config) = self.linter.rules.get(rule).filter(|config| config.enabled) else {
+            return;
+        };
+        self.issues.push(LintIssue {
+            rule: rule.to_string(),
+            message,
+            severity: config.severity.clone(),
+            line,
+            column,
+            suggestion: Some(suggestion.to_string()),
+            fix,
+        });
+    }
+
+    /// 1-based column of `word` on `line`, after `anchor` when one is given
+    fn column_of(&self, line: usize, anchor: Option<&str>, word: &str) -> Option<usize> {
+        let text = self.lines.get(line.checked_sub(1)?)?;
+        let from = match anchor {
+            Some(anchor) => find_word(text, anchor, 0)? + anchor.len(),
+            None => 0,
+        };
+        let start = find_word(text, word, from)?;
+        Some(text[..start].chars().count() + 1)
+    }
+
+    fn unused_functions(&mut self) {
+        for (name, line) in std::mem::take(&mut self.functions) {
+            if name == "main" || self.called.contains(&name) || self.unresolved.contains(&name) {
+                continue;
             }
+            let column = self.column_of(line, Some("fn"), &name).unwrap_or(1);
+            self.report(
+                "unused_function",
+                line,
+                column,
+                format!("Function '{}' is declared but never called", name),
+                "Remove the function or call it from your code",
+                None,
+            );
         }
+    }
 
-        // Analyze function body
-        self.analyze_expression(&func.body, used_variables, called_functions)?;
-        Ok(())
+    fn scoped_block(&mut self, statements: &[Statement]) {
+        self.scopes.push(Vec::new());
+        self.visit_block(statements);
+        self.pop_scope();
     }
 
-    fn analyze_expression(
-        &self,
-        expression: &Expression,
-        used_variables: &mut HashMap<String, bool>,
-        called_functions: &mut HashMap<String, bool>,
-    ) -> Result<(), CompilerError> {
-        match expression {
-            Expression::Identifier(name) => {
-                used_variables.insert(name.clone(), true);
+    fn let_binding(&mut self, name: &str, value: &Expression) {
+        self.visit_expression(value);
+        let line = self.line;
+        if !name.starts_with('_') {
+            let previous = self.scopes.iter().flatten().rev().find(|binding| binding.name == name).map(|b| b.line);
+            if let Some(previous_line) = previous {
+                let column = self.column_of(line, Some("let"), name).unwrap_or(1);
+                self.report(
+                    "shadowing",
+                    line,
+                    column,
+                    format!("Variable '{}' shadows the declaration on line {}", name, previous_line),
+                    "Rename one of the variables",
+                    None,
+                );
             }
-            Expression::FunctionCall(callee, _) => {
-                if let Expression::Identifier(func_name) = callee.as_ref() {
-                    called_functions.insert(func_name.clone(), true);
-                }
+        }
+        self.declare(name, BindingKind::Variable);
+    }
+
+    fn declare(&mut self, name: &str, kind: BindingKind) {
+        let line = self.line;
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(Binding { name: name.to_string(), line, kind, used: false });
+        }
+    }
+
+    fn use_name(&mut self, name: &str) {
+        match self.scopes.iter_mut().flatten().rev().find(|binding| binding.name == name) {
+            Some(binding) => binding.used = true,
+            None => {
+                self.unresolved.insert(name.to_string());
             }
-            Expression::BinaryOp(binary_op) => {
-                self.analyze_expression(&binary_op.left, used_variables, called_functions)?;
-                self.analyze_expression(&binary_op.right, used_variables, called_functions)?;
+        }
+    }
+
+    fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        let is_module = self.scopes.is_empty();
+        for binding in scope {
+            let used = binding.used || (is_module && self.unresolved.contains(&binding.name));
+            if used || binding.name.starts_with('_') {
+                continue;
             }
-            Expression::Block(statements) => {
-                for statement in statements {
-                    self.analyze_statement(statement, used_variables, &mut HashMap::new(), called_functions, &mut Vec::new())?;
+            match binding.kind {
+                BindingKind::Variable if !self.linter.allow_unused_variables => {
+                    let column = self.column_of(binding.line, Some("let"), &binding.name);
+                    self.report(
+                        "unused_variable",
+                        binding.line,
+                        column.unwrap_or(1),
+                        format!("Variable '{}' is declared but never used", binding.name),
+                        "Remove the variable, use it, or prefix its name with '_'",
+                        column.map(|column| rename_fix(binding.line, column, &binding.name)),
+                    );
                 }
+                BindingKind::Parameter => {
+                    let column = self.column_of(binding.line, Some("fn"), &binding.name)
+                        .or_else(|| self.column_of(binding.line, None, &binding.name));
+                    self.report(
+                        "unused_parameter",
+                        binding.line,
+                        column.unwrap_or(1),
+                        format!("Parameter '{}' is never used", binding.name),
+                        "Remove the parameter, use it, or prefix its name with '_'",
+                        column.map(|column| rename_fix(binding.line, column, &binding.name)),
+                    );
+                }
+                _ => {}
             }
-            _ => {}
         }
-        Ok(())
     }
 
-    pub fn with_max_line_length(mut self, length: usize) -> Self {
-        self.max_line_length = length;
-        self
+    fn with_parameters(&mut self, parameters: &[Parameter], body: &Expression) {
+        self.scopes.push(Vec::new());
+        for parameter in parameters {
+            self.declare(&parameter.name, BindingKind::Parameter);
+        }
+        self.visit_expression(body);
+        self.pop_scope();
     }
 
-    pub fn with_allow_unused_variables(mut self, allow: bool) -> Self {
-        self.allow_unused_variables = allow;
-        self
+    fn condition(&mut self, keyword: &str, condition: &Expression) {
+        self.visit_expression(condition);
+        let line = self.line;
+
+        if let Expression::Assignment(assignment) = condition {
+            let text = self.lines.get(line.wrapping_sub(1)).copied().unwrap_or("");
+            let fix = find_word(text, keyword, 0).and_then(|start| lone_equals(text, start)).map(|offset| LintFix {
+                line,
+                column: text[..offset].chars().count() + 1,
+                length: 1,
+                replacement: "==".to_string(),
+            });
+            self.report(
+                "assignment_in_condition",
+                line,
+                fix.as_ref().map_or(1, |fix| fix.column),
+                format!("Assignment to '{}' used as the {} condition", assignment.target, keyword),
+                "Use '==' to compare",
+                fix,
+            );
+            return;
+        }
+
+        // `while (true)` is the usual way to write a loop that exits with `break` or `return`
+        match constant_condition(condition) {
+            Some(true) if keyword == "while" => {}
+            Some(value) => {
+                let column = self.column_of(line, None, keyword).unwrap_or(1);
+                self.report(
+                    "constant_condition",
+                    line,
+                    column,
+                    format!("The {} condition is always {}", keyword, value),
+                    "Remove the condition or the branch it makes dead",
+                    None,
+                );
+            }
+            None => {}
+        }
     }
 
-    pub fn with_allow_unused_functions(mut self, allow: bool) -> Self {
-        self.allow_unused_functions = allow;
-        self
+}
+
+impl Visitor for Checker<'_> {
+    fn visit_program(&mut self, program: &Program) {
+        self.scopes.push(Vec::new());
+        visit::walk_program(self, program);
+        self.pop_scope();
+
+        if !self.linter.allow_unused_functions {
+            self.unused_functions();
+        }
     }
 
-    pub fn enable_rule(&mut self, rule_name: &str) {
-        if let Some(rule) = self.rules.get_mut(rule_name) {
-            rule.enabled = true;
+    fn visit_block(&mut self, statements: &[Statement]) {
+        let mut returned = false;
+        let mut reported = false;
+        for statement in statements {
+            if let Statement::SourceLine(line) = statement {
+                self.line = *line;
+                continue;
+            }
+            if returned && !reported {
+                reported = true;
+                self.report(
+                    "unreachable_code",
+                    self.line,
+                    1,
+                    "Code after return statement is unreachable".to_string(),
+                    "Remove the unreachable code",
+                    None,
+                );
+            }
+            self.visit_statement(statement);
+            returned |= matches!(
+                statement,
+                Statement::Return(_) | Statement::ReturnStatement { .. } | Statement::Expression(Expression::Return(_))
+            );
         }
     }
 
-    pub fn disable_rule(&mut self, rule_name: &str) {
-        if let Some(rule) = self.rules.get_mut(rule_name) {
-            rule.enabled = false;
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(let_stmt) => self.let_binding(&let_stmt.name, &let_stmt.value),
+            Statement::LetStatement { name, value, .. } => self.let_binding(name, value),
+            _ => visit::walk_statement(self, statement),
+        }
+    }
+
+    fn visit_function(&mut self, func: &FunctionStatement) {
+        let line = self.line;
+        // Annotated functions such as tests and benchmarks are called by the tooling
+        if func.annotations.is_empty() {
+            self.functions.push((func.name.clone(), line));
+        }
+
+        if let Expression::Block(statements) = &*func.body {
+            let column = self.column_of(line, None, "fn").unwrap_or(1);
+            if is_empty_block(&func.body) {
+                self.report(
+                    "empty_function",
+                    line,
+                    column,
+                    format!("Function '{}' has empty body", func.name),
+                    "Add implementation or remove the function",
+                    None,
+                );
+            }
+            let returns_value = !matches!(func.return_type, None | Some(Type::Void));
+            if returns_value && !always_returns(statements) {
+                self.report(
+                    "missing_return",
+                    line,
+                    column,
+                    format!("Function '{}' has return type but no return statement", func.name),
+                    "Add a return statement or change return type to Void",
+                    None,
+                );
+            }
+        }
+
+        self.with_parameters(&func.parameters, &func.body);
+    }
+
+    fn visit_match_arm(&mut self, arm: &MatchArm) {
+        if is_empty_block(&arm.body) {
+            let column = self.column_of(self.line, None, "match").unwrap_or(1);
+            self.report(
+                "empty_match_arm",
+                self.line,
+                column,
+                "Match arm has an empty body".to_string(),
+                "Handle the case, or make the intent explicit with a value such as null",
+                None,
+            );
+        }
+        self.scopes.push(Vec::new());
+        if let Pattern::Identifier(name) = &arm.pattern {
+            self.declare(name, BindingKind::Pattern);
         }
+        visit::walk_match_arm(self, arm);
+        self.pop_scope();
     }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier(name) => self.use_name(name),
+            Expression::FunctionCall(callee, arguments) => {
+                match callee.as_ref() {
+                    Expression::Identifier(name) => {
+                        self.called.insert(name.clone());
+                        // Calling a variable that holds a function uses it
+                        if let Some(binding) = self.scopes.iter_mut().flatten().rev().find(|b| &b.name == name) {
+                            binding.used = true;
+                        }
+                    }
+                    other => self.visit_expression(other),
+                }
+                for argument in arguments {
+                    self.visit_expression(&argument.value);
+                }
+            }
+            Expression::CallExpression { function, .. } => {
+                self.called.insert(function.clone());
+                visit::walk_expression(self, expression);
+            }
+            Expression::If(if_expr) => {
+                self.condition("if", &if_expr.condition);
+                self.visit_expression(&if_expr.then_branch);
+                if let Some(else_branch) = &if_expr.else_branch {
+                    self.visit_expression(else_branch);
+                }
+            }
+            Expression::IfExpression { condition, then_branch, else_branch } => {
+                self.condition("if", condition);
+                self.visit_expression(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.visit_expression(else_branch);
+                }
+            }
+            Expression::While(while_expr) => {
+                self.condition("while", &while_expr.condition);
+                self.visit_expression(&while_expr.body);
+            }
+            Expression::Block(statements) | Expression::BlockExpression { statements } => self.scoped_block(statements),
+            Expression::Let(let_stmt) => self.let_binding(&let_stmt.name, &let_stmt.value),
+            Expression::Lambda(lambda) => self.with_parameters(&lambda.parameters, &lambda.body),
+            Expression::ListComprehension(comprehension) => {
+                self.visit_expression(&comprehension.iterable);
+                self.scopes.push(Vec::new());
+                self.declare(&comprehension.iterator, BindingKind::Pattern);
+                if let Some(condition) = &comprehension.condition {
+                    self.visit_expression(condition);
+                }
+                self.visit_expression(&comprehension.element);
+                self.pop_scope();
+            }
+            _ => visit::walk_expression(self, expression),
+        }
+    }
+}
+
+/// A fix that prefixes `name` at `column` with `_`
+fn rename_fix(line: usize, column: usize, name: &str) -> LintFix {
+    LintFix {
+        line,
+        column,
+        length: name.chars().count(),
+        replacement: format!("_{}", name),
+    }
+}
+
+/// Byte offset of the first `=` after `from` that is not part of `==`, `!=`, `<=`, `>=` or `=>`
+fn lone_equals(text: &str, from: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    (from..bytes.len()).find(|&i| {
+        bytes[i] == b'='
+            && !matches!(bytes.get(i + 1), Some(b'=') | Some(b'>'))
+            && !matches!(i.checked_sub(1).map(|p| bytes[p]), Some(b'=') | Some(b'!') | Some(b'<') | Some(b'>'))
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn rules_of(issues: &[LintIssue]) -> Vec<&str> {
+        issues.iter().map(|issue| issue.rule.as_str()).collect()
+    }
+
     #[test]
     fn test_lint_unused_variable() {
         let linter = Linter::new();
         let source = "fn main() { let x = 42; }";
         let issues = linter.lint_source(source, "test.nx").unwrap();
-        
+
         assert!(!issues.is_empty());
         assert!(issues.iter().any(|issue| issue.rule == "unused_variable"));
     }
@@ -312,7 +960,7 @@ mod tests {
         let linter = Linter::new();
         let source = "fn main() -> Int { let x = 42; }";
         let issues = linter.lint_source(source, "test.nx").unwrap();
-        
+
         assert!(!issues.is_empty());
         assert!(issues.iter().any(|issue| issue.rule == "missing_return"));
     }
@@ -322,8 +970,109 @@ mod tests {
         let linter = Linter::new().with_max_line_length(10);
         let source = "fn main() { let very_long_variable_name = 42; }";
         let issues = linter.lint_source(source, "test.nx").unwrap();
-        
+
         assert!(!issues.is_empty());
         assert!(issues.iter().any(|issue| issue.rule == "long_line"));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_lint_new_rules() {
+        let linter = Linter::new();
+        let source = "\
+fn scale(value: Int, factor: Int) -> Int {
+    let result = value * 2;
+    if (true) {
+        let result = 1;
+        println(result);
+    }
+    let kind = match value {
+        0 => {},
+        _ => 1
+    };
+    println(kind);
+    return result;
+}
+let flag = false;
+while (flag = true) {
+    println(scale(1, 2));
+}
+";
+        let issues = linter.lint_source(source, "test.nx").unwrap();
+        let found: Vec<(&str, usize)> = issues.iter().map(|issue| (issue.rule.as_str(), issue.line)).collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("unused_parameter", 1),
+                ("constant_condition", 3),
+                ("shadowing", 4),
+                ("empty_match_arm", 7),
+                ("unused_variable", 14),
+                ("assignment_in_condition", 15),
+            ]
+        );
+        assert_eq!(issues[0].column, 22);
+    }
+
+    #[test]
+    fn test_suppression_comments() {
+        let linter = Linter::new();
+        let source = "\
+fn main() {
+    # neksis-lint: allow(unused_variable)
+    let a = 1;
+    let b = 2; // neksis-lint: allow(unused_variable, shadowing)
+    let c = 3;
+}
+";
+        let issues = linter.lint_source(source, "test.nx").unwrap();
+        assert_eq!(rules_of(&issues), vec!["unused_variable"]);
+        assert_eq!(issues[0].line, 5);
+    }
+
+    #[test]
+    fn test_config_overrides_severity() {
+        let config: LintConfig = serde_json::from_str(
+            r#"{ "max_line_length": 200, "rules": { "unused_variable": "error", "empty_function": "off" } }"#,
+        )
+        .unwrap();
+        let linter = Linter::from_config(&config).unwrap();
+        let issues = linter.lint_source("fn main() { let x = 1; }\nfn idle() {}\nidle();\n", "test.nx").unwrap();
+
+        assert_eq!(rules_of(&issues), vec!["unused_variable"]);
+        assert_eq!(issues[0].severity, LintSeverity::Error);
+
+        let unknown = LintConfig { rules: HashMap::from([("no_such_rule".to_string(), RuleLevel::Off)]), ..LintConfig::default() };
+        assert!(Linter::from_config(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_apply_fixes() {
+        let linter = Linter::new();
+        let source = "fn add(a: Int, b: Int) -> Int {\n    let unused = 1;\n    if (a = 2) {\n        return a;\n    }\n    return a;\n}\nprintln(add(1, 2));\n";
+        let issues = linter.lint_source(source, "test.nx").unwrap();
+        let (fixed, applied) = apply_fixes(source, &issues);
+
+        assert_eq!(applied, 3);
+        assert_eq!(
+            fixed,
+            "fn add(a: Int, _b: Int) -> Int {\n    let _unused = 1;\n    if (a == 2) {\n        return a;\n    }\n    return a;\n}\nprintln(add(1, 2));\n"
+        );
+        assert!(linter.lint_source(&fixed, "test.nx").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_render_sarif() {
+        let linter = Linter::new();
+        let issues = linter.lint_source("fn main() { let x = 1; }", "main.nx").unwrap();
+        let sarif: serde_json::Value =
+            serde_json::from_str(&linter.render_sarif(&[FileLint { file: "main.nx".to_string(), issues }])).unwrap();
+
+        let run = &sarif["runs"][0];
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(run["tool"]["driver"]["name"], "neksis-lint");
+        assert_eq!(run["results"][0]["ruleId"], "unused_variable");
+        assert_eq!(run["results"][0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "main.nx");
+        assert_eq!(run["results"][0]["locations"][0]["physicalLocation"]["region"]["startLine"], 1);
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use crate::vm::{ArrayRef, ObjectRef, VMValue};
 
 #[derive(Debug, Clone)]
 pub enum MemoryValue {
@@ -149,6 +151,230 @@ impl MemoryManager {
     }
 }
 
+// Cycle collection for VM arrays and objects
+//
+// Arrays and objects are reference counted, so most are freed as soon as the
+// last reference goes away. Counting alone cannot free containers that refer
+// to each other, so the collector keeps a weak reference to every container
+// the VM creates and periodically looks for groups that are only kept alive
+// from inside the group. Like `MemoryManager::collect_garbage` it decides
+// liveness from strong counts instead of scanning the VM's roots: references
+// held by other tracked containers are subtracted from each count, and a
+// container with no references left from outside, and no path from one that
+// has them, is garbage.
+
+/// Collections run after at least this many new containers, or as many as
+/// survived the previous collection if that is more
+const MIN_COLLECTION_THRESHOLD: usize = 1024;
+
+/// A weak handle to an array or object
+#[derive(Debug, Clone)]
+pub enum WeakContainer {
+    Array(Weak<RefCell<Vec<VMValue>>>),
+    Object(Weak<RefCell<HashMap<String, VMValue>>>),
+}
+
+impl WeakContainer {
+    pub fn from_value(value: &VMValue) -> Option<Self> {
+        match value {
+            VMValue::Array(items) => Some(WeakContainer::Array(Rc::downgrade(items))),
+            VMValue::Object(fields) => Some(WeakContainer::Object(Rc::downgrade(fields))),
+            _ => None,
+        }
+    }
+
+    pub fn upgrade(&self) -> Option<VMValue> {
+        match self {
+            WeakContainer::Array(items) => items.upgrade().map(VMValue::Array),
+            WeakContainer::Object(fields) => fields.upgrade().map(VMValue::Object),
+        }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        match self {
+            WeakContainer::Array(items) => items.strong_count() > 0,
+            WeakContainer::Object(fields) => fields.strong_count() > 0,
+        }
+    }
+}
+
+/// Address of the container behind `value`, used as its identity
+pub fn container_address(value: &VMValue) -> Option<usize> {
+    match value {
+        VMValue::Array(items) => Some(Rc::as_ptr(items) as *const () as usize),
+        VMValue::Object(fields) => Some(Rc::as_ptr(fields) as *const () as usize),
+        _ => None,
+    }
+}
+
+/// Call `visit` for every container directly referenced by `value`'s
+/// contents, including values captured by closures stored inside it
+pub fn for_each_child_container(value: &VMValue, visit: &mut dyn FnMut(&VMValue)) {
+    fn walk(value: &VMValue, visit: &mut dyn FnMut(&VMValue)) {
+        match value {
+            VMValue::Array(_) | VMValue::Object(_) => visit(value),
+            VMValue::Function(_, captured) => captured.iter().for_each(|inner| walk(inner, visit)),
+            _ => {}
+        }
+    }
+    match value {
+        VMValue::Array(items) => items.borrow().iter().for_each(|item| walk(item, visit)),
+        VMValue::Object(fields) => fields.borrow().values().for_each(|field| walk(field, visit)),
+        _ => {}
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CollectorStats {
+    pub collections: usize,
+    /// Containers freed by the collector rather than by reference counting
+    pub freed: usize,
+    /// Containers currently tracked, some of which may already be freed
+    pub tracked: usize,
+}
+
+pub struct CycleCollector {
+    tracked: Vec<WeakContainer>,
+    since_collection: usize,
+    threshold: usize,
+    collections: usize,
+    freed: usize,
+}
+
+impl CycleCollector {
+    pub fn new() -> Self {
+        Self {
+            tracked: Vec::new(),
+            since_collection: 0,
+            threshold: MIN_COLLECTION_THRESHOLD,
+            collections: 0,
+            freed: 0,
+        }
+    }
+
+    pub fn array(&mut self, items: Vec<VMValue>) -> VMValue {
+        let items: ArrayRef = Rc::new(RefCell::new(items));
+        self.track(&VMValue::Array(Rc::clone(&items)));
+        VMValue::Array(items)
+    }
+
+    pub fn object(&mut self, fields: HashMap<String, VMValue>) -> VMValue {
+        let fields: ObjectRef = Rc::new(RefCell::new(fields));
+        self.track(&VMValue::Object(Rc::clone(&fields)));
+        VMValue::Object(fields)
+    }
+
+    /// Start tracking a container created elsewhere, e.g. by an embedder
+    pub fn track(&mut self, value: &VMValue) {
+        if let Some(container) = WeakContainer::from_value(value) {
+            self.tracked.push(container);
+            self.since_collection += 1;
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.since_collection >= self.threshold
+    }
+
+    /// Free every tracked container that is only reachable through cycles,
+    /// returning how many were freed. Containers that are mutably borrowed
+    /// while this runs are treated as live.
+    pub fn collect(&mut self) -> usize {
+        // Containers whose count already reached zero are gone; the rest are held here
+        let nodes: Vec<VMValue> = self.tracked.iter().filter_map(WeakContainer::upgrade).collect();
+        let index: HashMap<usize, usize> = nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, node)| container_address(node).map(|address| (address, i)))
+            .collect();
+
+        // Every strong count includes the reference held by `nodes`
+        let mut outside: Vec<usize> = nodes.iter().map(|node| strong_count(node).saturating_sub(1)).collect();
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+        for (i, node) in nodes.iter().enumerate() {
+            if !can_borrow(node) {
+                outside[i] = usize::MAX;
+                continue;
+            }
+            for_each_child_container(node, &mut |child| {
+                if let Some(&j) = container_address(child).and_then(|address| index.get(&address)) {
+                    outside[j] = outside[j].saturating_sub(1);
+                    children[i].push(j);
+                }
+            });
+        }
+
+        // Anything referenced from outside the tracked containers is live, as is everything it reaches
+        let mut live = vec![false; nodes.len()];
+        let mut pending: Vec<usize> = (0..nodes.len()).filter(|&i| outside[i] > 0).collect();
+        while let Some(i) = pending.pop() {
+            if !live[i] {
+                live[i] = true;
+                pending.extend(children[i].iter().copied());
+            }
+        }
+
+        // The rest only keep each other alive; emptying them breaks the cycles
+        let mut freed = 0;
+        for (node, live) in nodes.iter().zip(&live) {
+            if !live {
+                clear(node);
+                freed += 1;
+            }
+        }
+        drop(nodes);
+
+        self.tracked.retain(WeakContainer::is_alive);
+        self.since_collection = 0;
+        self.threshold = self.tracked.len().max(MIN_COLLECTION_THRESHOLD);
+        self.collections += 1;
+        self.freed += freed;
+        freed
+    }
+
+    pub fn stats(&self) -> CollectorStats {
+        CollectorStats { collections: self.collections, freed: self.freed, tracked: self.tracked.len() }
+    }
+}
+
+impl Default for CycleCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn strong_count(container: &VMValue) -> usize {
+    match container {
+        VMValue::Array(items) => Rc::strong_count(items),
+        VMValue::Object(fields) => Rc::strong_count(fields),
+        _ => 0,
+    }
+}
+
+fn can_borrow(container: &VMValue) -> bool {
+    match container {
+        VMValue::Array(items) => items.try_borrow().is_ok(),
+        VMValue::Object(fields) => fields.try_borrow().is_ok(),
+        _ => false,
+    }
+}
+
+fn clear(container: &VMValue) {
+    // The contents are dropped after the borrow ends, since dropping them can
+    // free other containers in the same cycle
+    match container {
+        VMValue::Array(items) => {
+            let contents = items.try_borrow_mut().map(|mut items| std::mem::take(&mut *items));
+            drop(contents);
+        }
+        VMValue::Object(fields) => {
+            let contents = fields.try_borrow_mut().map(|mut fields| std::mem::take(&mut *fields));
+            drop(contents);
+        }
+        _ => {}
+    }
+}
+
 // Convert between our new memory system and the existing VM system
 impl From<crate::vm::VMValue> for VMValueCore {
     fn from(vm_value: crate::vm::VMValue) -> Self {
@@ -158,13 +384,13 @@ impl From<crate::vm::VMValue> for VMValueCore {
             crate::vm::VMValue::String(s) => VMValueCore::String(s),
             crate::vm::VMValue::Bool(b) => VMValueCore::Bool(b),
             crate::vm::VMValue::Array(arr) => {
-                let converted: Vec<MemoryValue> = arr.into_iter()
+                let converted: Vec<MemoryValue> = arr.borrow().iter().cloned()
                     .map(|v| MemoryValue::Owned(Box::new(VMValueCore::from(v))))
                     .collect();
                 VMValueCore::Array(converted)
             }
             crate::vm::VMValue::Object(map) => {
-                let converted: HashMap<String, MemoryValue> = map.into_iter()
+                let converted: HashMap<String, MemoryValue> = map.borrow().clone().into_iter()
                     .map(|(k, v)| (k, MemoryValue::Owned(Box::new(VMValueCore::from(v)))))
                     .collect();
                 VMValueCore::Object(converted)
@@ -188,7 +414,7 @@ impl From<VMValueCore> for crate::vm::VMValue {
                         _ => crate::vm::VMValue::Null,
                     })
                     .collect();
-                crate::vm::VMValue::Array(converted)
+                crate::vm::VMValue::array(converted)
             }
             VMValueCore::Object(map) => {
                 let converted: HashMap<String, crate::vm::VMValue> = map.into_iter()
@@ -197,9 +423,70 @@ impl From<VMValueCore> for crate::vm::VMValue {
                         _ => crate::vm::VMValue::Null,
                     }))
                     .collect();
-                crate::vm::VMValue::Object(converted)
+                crate::vm::VMValue::object(converted)
             }
             VMValueCore::Null => crate::vm::VMValue::Null,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collects_unreachable_cycles_only() {
+        let mut collector = CycleCollector::new();
+
+        // a <-> b, referenced only by each other once the locals are dropped
+        let a = collector.object(HashMap::new());
+        let b = collector.array(Vec::new());
+        if let (VMValue::Object(fields), VMValue::Array(items)) = (&a, &b) {
+            fields.borrow_mut().insert("peer".to_string(), b.clone());
+            items.borrow_mut().push(a.clone());
+        }
+        let garbage = [WeakContainer::from_value(&a).unwrap(), WeakContainer::from_value(&b).unwrap()];
+
+        // kept -> self, plus a child reachable only through the cycle, but kept is still in use
+        let kept = collector.array(Vec::new());
+        let child = collector.object(HashMap::new());
+        if let VMValue::Array(items) = &kept {
+            items.borrow_mut().push(kept.clone());
+            items.borrow_mut().push(child.clone());
+        }
+        let child_handle = WeakContainer::from_value(&child).unwrap();
+        drop((a, b, child));
+
+        assert_eq!(collector.collect(), 2);
+        assert!(garbage.iter().all(|container| !container.is_alive()));
+        assert!(child_handle.is_alive());
+        assert_eq!(kept.to_string(), "[[...], {}]");
+
+        drop(kept);
+        assert_eq!(collector.collect(), 2);
+        assert!(!child_handle.is_alive());
+        assert_eq!(collector.stats(), CollectorStats { collections: 2, freed: 4, tracked: 0 });
+    }
+
+    #[test]
+    fn test_structural_equality_of_cycles() {
+        // a = [0, a] and b = [0, b], two distinct cycles of the same shape
+        let cyclic = |first: i64| {
+            let value = VMValue::array(vec![VMValue::Int(first)]);
+            if let VMValue::Array(items) = &value {
+                items.borrow_mut().push(value.clone());
+            }
+            value
+        };
+        let (a, b, c) = (cyclic(0), cyclic(0), cyclic(1));
+        assert!(a.structurally_equal(&b));
+        assert!(!a.structurally_equal(&c));
+
+        // Break the cycles so the test does not leak them
+        for value in [&a, &b, &c] {
+            if let VMValue::Array(items) = value {
+                items.borrow_mut().clear();
+            }
+        }
+    }
+}
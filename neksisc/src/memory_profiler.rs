@@ -68,6 +68,11 @@ impl MemoryProfiler {
     }
 
     pub fn record_allocation(&self, address: usize, size: usize, allocation_site: &str) {
+        self.record_allocation_with_trace(address, size, allocation_site, self.capture_stack_trace());
+    }
+
+    /// Record an allocation whose stack trace is supplied by the caller, e.g. the VM's call stack
+    pub fn record_allocation_with_trace(&self, address: usize, size: usize, allocation_site: &str, stack_trace: Vec<String>) {
         if !self.enabled {
             return;
         }
@@ -76,7 +81,7 @@ impl MemoryProfiler {
             address,
             size,
             timestamp: Instant::now(),
-            stack_trace: self.capture_stack_trace(),
+            stack_trace,
             allocation_site: allocation_site.to_string(),
         };
 
@@ -85,8 +90,10 @@ impl MemoryProfiler {
             stats.allocation_count += 1;
             stats.current_allocations += 1;
             
-            if stats.allocated_bytes > stats.peak_allocated_bytes {
-                stats.peak_allocated_bytes = stats.allocated_bytes;
+            // The peak is of bytes live at once, not of everything ever allocated
+            let live_bytes = stats.allocated_bytes - stats.deallocated_bytes;
+            if live_bytes > stats.peak_allocated_bytes {
+                stats.peak_allocated_bytes = live_bytes;
             }
         }
 
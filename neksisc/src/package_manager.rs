@@ -3,6 +3,8 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
 use crate::error::CompilerError;
+use crate::formatter::FormatConfig;
+use crate::linter::LintConfig;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PackageManifest {
@@ -15,6 +17,26 @@ pub struct PackageManifest {
     pub dev_dependencies: HashMap<String, String>,
     pub scripts: HashMap<String, String>,
     pub entry_point: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<FormatConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lint: Option<LintConfig>,
+}
+
+impl PackageManifest {
+    /// The `nexus.json` in `dir`, if there is one
+    pub fn load(dir: &Path) -> Result<Option<Self>, CompilerError> {
+        let manifest_path = dir.join("nexus.json");
+        if !manifest_path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&manifest_path)
+            .map_err(|e| CompilerError::io_error(&format!("Failed to read manifest: {}", e)))?;
+        let manifest = serde_json::from_str(&content)
+            .map_err(|e| CompilerError::config_error(&format!("Failed to parse manifest: {}", e)))?;
+        Ok(Some(manifest))
+    }
 }
 
 #[derive(Debug)]
@@ -57,6 +79,8 @@ impl PackageManager {
             dev_dependencies: HashMap::new(),
             scripts: HashMap::new(),
             entry_point: Some("src/main.nx".to_string()),
+            format: None,
+            lint: None,
         };
         
         let manifest_content = serde_json::to_string_pretty(&manifest)
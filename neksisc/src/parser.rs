@@ -1,10 +1,13 @@
 use crate::ast::*;
 use crate::lexer::{Token, TokenInfo, InterpolatedPart as LexerInterpolatedPart};
 use crate::ast::InterpolatedPart;
+use crate::ffi::{CallingConvention, FFIParameter, FFISignature, FFIType, ParameterDirection};
+use std::collections::HashMap;
 
 pub struct Parser {
     tokens: Vec<TokenInfo>,
     current: usize,
+    track_lines: bool,
 }
 
 impl Parser {
@@ -12,19 +15,23 @@ impl Parser {
         Self {
             tokens,
             current: 0,
+            track_lines: false,
         }
     }
     
+    /// Precede every statement with a `Statement::SourceLine` marker, so what
+    /// is compiled from it can be mapped back to source lines
+    pub fn with_line_markers(mut self) -> Self {
+        self.track_lines = true;
+        self
+    }
+    
     pub fn parse(&mut self) -> Result<Program, String> {
         let mut statements = Vec::new();
         
         while !self.is_at_end() {
-            if let Some(statement) = self.parse_statement()? {
-                statements.push(statement);
-            } else {
-                if !self.is_at_end() {
-                    self.advance();
-                }
+            if !self.parse_tracked_statement(&mut statements)? && !self.is_at_end() {
+                self.advance();
             }
         }
         
@@ -50,6 +57,34 @@ impl Parser {
         }
     }
     
+    // Parse a statement into `statements`, preceded by its line marker when
+    // tracking lines. Returns false if nothing was parsed.
+    fn parse_tracked_statement(&mut self, statements: &mut Vec<Statement>) -> Result<bool, String> {
+        let line = self.statement_line();
+        match self.parse_statement()? {
+            Some(statement) => {
+                if self.track_lines {
+                    statements.push(Statement::SourceLine(line));
+                }
+                statements.push(statement);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    
+    // Line of the statement starting at the current token. Annotated
+    // functions report the line of their `fn` keyword.
+    fn statement_line(&self) -> usize {
+        let mut remaining = self.tokens[self.current.min(self.tokens.len())..].iter();
+        let token = if matches!(self.peek(), Token::At) {
+            remaining.find(|info| matches!(info.token, Token::Fn))
+        } else {
+            remaining.next()
+        };
+        token.map_or(0, |info| info.line)
+    }
+    
     fn peek_next(&self) -> &Token {
         if self.current + 1 >= self.tokens.len() {
             &Token::Eof
@@ -108,8 +143,7 @@ impl Parser {
         }
     }
     
-    // Parse Annotations - currently unused but kept for future annotation support
-    #[allow(dead_code)]
+    // Parse Annotations (`@name` or `@name("arg", 42)`), the leading '@' is already consumed
     fn parse_annotation(&mut self) -> Result<Annotation, String> {
         let _start_line = self.previous().line;
         let _start_column = self.previous().column;
@@ -129,11 +163,16 @@ impl Parser {
             
             if !self.check(&Token::RightParen) {
                 loop {
-                    if let Token::String(arg) = &self.peek() {
-                        args.push(arg.clone());
-                        self.advance();
-                    } else {
-                        return Err("Expected string argument in annotation".to_string());
+                    match self.peek().clone() {
+                        Token::String(arg) => {
+                            args.push(Expression::Literal(Literal::String(arg)));
+                            self.advance();
+                        }
+                        Token::Number(arg) => {
+                            args.push(Expression::Literal(Literal::Int(arg)));
+                            self.advance();
+                        }
+                        _ => return Err("Expected string or integer argument in annotation".to_string()),
                     }
                     
                     if !self.match_token(&Token::Comma) {
@@ -150,7 +189,7 @@ impl Parser {
         
         Ok(Annotation {
             name,
-            arguments: arguments.into_iter().map(|arg| Expression::Literal(Literal::String(arg))).collect(),
+            arguments,
             attached_to: None, // Will be set later when we know what follows
         })
     }
@@ -161,6 +200,19 @@ impl Parser {
             return Ok(Some(Statement::Let(self.parse_let_statement()?)));
         } else if self.match_token(&Token::Fn) {
             return Ok(Some(Statement::Function(self.parse_function_statement()?)));
+        } else if self.check(&Token::At) {
+            // Annotations such as `@test` attach to the function that follows them
+            let mut annotations = Vec::new();
+            while self.match_token(&Token::At) {
+                annotations.push(self.parse_annotation()?);
+            }
+            self.consume(&Token::Fn, "Expected 'fn' after annotations")?;
+            let mut function = self.parse_function_statement()?;
+            for annotation in &mut annotations {
+                annotation.attached_to = Some(function.name.clone());
+            }
+            function.annotations = annotations;
+            return Ok(Some(Statement::Function(function)));
         } else if self.match_token(&Token::Struct) {
             return Ok(Some(Statement::Struct(self.parse_struct_statement()?)));
         } else if self.match_token(&Token::Enum) {
@@ -170,6 +222,18 @@ impl Parser {
         } else if self.match_token(&Token::Use) {
             return Ok(Some(Statement::Use(self.parse_use_statement()?)));
         } else if self.match_token(&Token::Import) {
+            // import python "module" runs the module in the embedded interpreter
+            if matches!(self.peek(), Token::Identifier(word) if word == "python") && matches!(self.peek_next(), Token::String(_)) {
+                self.advance();
+                let module = self.parse_string_literal("Expected a Python module name")?;
+                let alias = if self.match_token(&Token::As) {
+                    Some(self.parse_identifier("Expected alias after 'as' in import")?)
+                } else {
+                    None
+                };
+                self.consume(&Token::Semicolon, "Expected ';' after import statement")?;
+                return Ok(Some(Statement::PythonImport(PythonImportStatement { module, alias })));
+            }
             // import module or import module as alias
             let module = if let Token::Identifier(name) = &self.peek() {
                 let name = name.clone();
@@ -226,6 +290,8 @@ impl Parser {
             }
             // For now, treat as UseStatement with path 'module.name'
             return Ok(Some(Statement::Use(UseStatement { path: format!("{}.{}", module, name), alias })));
+        } else if self.match_token(&Token::Extern) {
+            return Ok(Some(Statement::Extern(self.parse_extern_statement()?)));
         } else if self.match_token(&Token::Return) {
             return Ok(Some(Statement::Return(self.parse_return_statement()?)));
         } else if self.match_token(&Token::Module) {
@@ -255,8 +321,8 @@ impl Parser {
         // Try to parse as expression statement
         let expr = self.parse_expression();
         match &expr {
-            Ok(Expression::Block(_)) => {
-                // Block statements do not require a semicolon
+            Ok(Expression::Block(_)) | Ok(Expression::TryCatch(_)) => {
+                // Block statements and try-catch do not require a semicolon
                 Ok(Some(Statement::Expression(expr.unwrap())))
             },
             Ok(Expression::Assignment(assign_expr)) => {
@@ -472,7 +538,7 @@ impl Parser {
             parameters: parameters.clone(),
             return_type: return_type.clone(),
             body: Box::new(body),
-            annotations: Vec::new(), // TODO: Associate annotations
+            annotations: Vec::new(), // Filled in by parse_statement when annotations precede `fn`
             signature: FunctionSignature {
                 parameters: parameters,
                 return_type: return_type,
@@ -480,6 +546,146 @@ impl Parser {
         })
     }
     
+    // extern "C" lib "libc.so.6" { struct Div { quot: i32, rem: i32 } fn div(a: i32, b: i32) -> Div; }
+    fn parse_extern_statement(&mut self) -> Result<ExternStatement, String> {
+        let abi = self.parse_string_literal("Expected an ABI string such as \"C\" after 'extern'")?;
+        if abi != "C" {
+            return Err(format!("Unsupported ABI \"{}\" at line {}; only \"C\" is supported", abi, self.previous().line));
+        }
+        if !matches!(self.peek(), Token::Identifier(word) if word == "lib") {
+            return Err(format!("Expected 'lib' after extern \"C\" at line {}", self.previous().line));
+        }
+        self.advance();
+        let library = self.parse_string_literal("Expected a library path after 'lib'")?;
+        self.consume(&Token::LeftBrace, "Expected '{' after library path")?;
+
+        // Structs declared in the block can be passed and returned by value
+        let mut structs = HashMap::new();
+        let mut functions = Vec::new();
+        while !self.check(&Token::RightBrace) && !self.is_at_end() {
+            if self.match_token(&Token::Struct) {
+                let name = self.parse_identifier("Expected struct name")?;
+                self.consume(&Token::LeftBrace, "Expected '{' after struct name")?;
+                let mut fields = Vec::new();
+                while !self.check(&Token::RightBrace) && !self.is_at_end() {
+                    let field = self.parse_identifier("Expected field name")?;
+                    self.consume(&Token::Colon, "Expected ':' after field name")?;
+                    fields.push((field, self.parse_foreign_type(&structs)?));
+                    if !self.match_token(&Token::Comma) {
+                        break;
+                    }
+                }
+                self.consume(&Token::RightBrace, "Expected '}' after struct fields")?;
+                if fields.is_empty() {
+                    return Err(format!("Extern struct '{}' has no fields", name));
+                }
+                structs.insert(name, FFIType::structure(fields));
+            } else if self.match_token(&Token::Fn) {
+                let name = self.parse_identifier("Expected function name")?;
+                self.consume(&Token::LeftParen, "Expected '(' after function name")?;
+                let mut parameters = Vec::new();
+                while !self.check(&Token::RightParen) && !self.is_at_end() {
+                    let param = self.parse_identifier("Expected parameter name")?;
+                    self.consume(&Token::Colon, "Expected ':' after parameter name")?;
+                    parameters.push((param, self.parse_foreign_type(&structs)?));
+                    if !self.match_token(&Token::Comma) {
+                        break;
+                    }
+                }
+                self.consume(&Token::RightParen, "Expected ')' after parameters")?;
+                let return_type = if self.match_token(&Token::Arrow) {
+                    self.parse_foreign_type(&structs)?
+                } else {
+                    FFIType::Void
+                };
+                self.consume(&Token::Semicolon, "Expected ';' after extern function")?;
+                functions.push(ExternFunction { name, signature: Self::foreign_signature(parameters, return_type) });
+            } else {
+                return Err(format!("Expected 'fn' or 'struct' in extern block at line {}", self.previous().line));
+            }
+        }
+        self.consume(&Token::RightBrace, "Expected '}' after extern block")?;
+        Ok(ExternStatement { library, functions })
+    }
+
+    fn parse_foreign_type(&mut self, structs: &HashMap<String, FFIType>) -> Result<FFIType, String> {
+        if self.match_token(&Token::Star) {
+            return Ok(FFIType::Pointer(Box::new(self.parse_foreign_type(structs)?)));
+        }
+        if self.match_token(&Token::LeftBracket) {
+            let element = self.parse_foreign_type(structs)?;
+            self.consume(&Token::RightBracket, "Expected ']' after array element type")?;
+            return Ok(FFIType::Array(Box::new(element), 0));
+        }
+        if self.match_token(&Token::Fn) {
+            // A callback: fn(i32, i32) -> i32
+            self.consume(&Token::LeftParen, "Expected '(' after 'fn' in callback type")?;
+            let mut parameters = Vec::new();
+            while !self.check(&Token::RightParen) && !self.is_at_end() {
+                parameters.push((format!("arg{}", parameters.len()), self.parse_foreign_type(structs)?));
+                if !self.match_token(&Token::Comma) {
+                    break;
+                }
+            }
+            self.consume(&Token::RightParen, "Expected ')' after callback parameter types")?;
+            let return_type = if self.match_token(&Token::Arrow) {
+                self.parse_foreign_type(structs)?
+            } else {
+                FFIType::Void
+            };
+            return Ok(FFIType::Function(Box::new(Self::foreign_signature(parameters, return_type))));
+        }
+        let name = self.parse_identifier("Expected a C type")?;
+        Ok(match name.as_str() {
+            "i8" => FFIType::Int8,
+            "i16" => FFIType::Int16,
+            "i32" => FFIType::Int32,
+            "i64" | "isize" | "Int" => FFIType::Int64,
+            "u8" => FFIType::UInt8,
+            "u16" => FFIType::UInt16,
+            "u32" => FFIType::UInt32,
+            "u64" | "usize" => FFIType::UInt64,
+            "f32" => FFIType::Float32,
+            "f64" | "Float" => FFIType::Float64,
+            "bool" | "Bool" => FFIType::Bool,
+            "String" => FFIType::String,
+            "Pointer" => FFIType::Pointer(Box::new(FFIType::Void)),
+            "void" | "Void" => FFIType::Void,
+            _ => structs.get(&name).cloned().ok_or_else(|| format!("Unknown C type '{}' at line {}", name, self.previous().line))?,
+        })
+    }
+
+    fn foreign_signature(parameters: Vec<(String, FFIType)>, return_type: FFIType) -> FFISignature {
+        FFISignature {
+            return_type,
+            parameters: parameters
+                .into_iter()
+                .map(|(name, ffi_type)| FFIParameter { name, ffi_type, direction: ParameterDirection::In })
+                .collect(),
+            calling_convention: CallingConvention::C,
+        }
+    }
+
+    fn parse_identifier(&mut self, message: &str) -> Result<String, String> {
+        if let Token::Identifier(name) = self.peek() {
+            let name = name.clone();
+            self.advance();
+            Ok(name)
+        } else {
+            Err(format!("{} at line {}", message, self.previous().line))
+        }
+    }
+
+    fn parse_string_literal(&mut self, message: &str) -> Result<String, String> {
+        if let Token::String(value) = self.peek() {
+            let value = value.clone();
+            self.advance();
+            Ok(value)
+        } else {
+            Err(format!("{} at line {}", message, self.previous().line))
+        }
+    }
+
     fn parse_generic_type_params(&mut self) -> Result<Vec<String>, String> {
         self.consume(&Token::LeftAngle, "Expected '<' for generic type parameters")?;
         
@@ -777,6 +983,20 @@ impl Parser {
         })
     }
     
+    // Whether the `{` at the current token opens the fields of a `struct_name`
+    // literal: `Name { field: ... }`, or `Name {}` for type names
+    fn starts_struct_literal(&self, struct_name: &str) -> bool {
+        if !self.check(&Token::LeftBrace) {
+            return false;
+        }
+        let token_at = |offset: usize| self.tokens.get(self.current + offset).map(|info| &info.token);
+        match token_at(1) {
+            Some(Token::Identifier(_)) => matches!(token_at(2), Some(Token::Colon)),
+            Some(Token::RightBrace) => struct_name.starts_with(|c: char| c.is_uppercase()),
+            _ => false,
+        }
+    }
+    
     pub fn parse_struct_literal(&mut self, struct_name: String) -> Result<Expression, String> {
 
         self.consume(&Token::LeftBrace, "Expected '{' after struct name")?;
@@ -1216,6 +1436,14 @@ impl Parser {
                 }
             } else if self.match_token(&Token::LeftParen) {
                 expr = self.finish_call(expr)?;
+            } else if self.match_token(&Token::LeftBracket) {
+                // Indexing: expr[index]
+                let index = self.parse_expression()?;
+                self.consume(&Token::RightBracket, "Expected ']' after array index")?;
+                expr = Expression::ArrayAccess(ArrayAccessExpression {
+                    array: Box::new(expr),
+                    index: Box::new(index),
+                });
             } else if self.check(&Token::LeftBrace) {
                 // Only consume LeftBrace if this is a struct literal (i.e., previous expr is identifier)
                 if let Expression::Identifier(struct_name) = &expr {
@@ -1245,17 +1473,26 @@ impl Parser {
                     break;
                 }
             } else if self.match_token(&Token::Dot) {
-                // Member access: expr.member
-                if let Token::Identifier(member) = &self.peek() {
-                    let member = member.clone();
-                    self.advance();
-                    expr = Expression::MemberAccess(MemberAccess {
-                        object: Box::new(expr),
-                        member,
-                    });
-                } else {
+                // Member access: expr.member, where keywords are allowed so that
+                // Python attributes such as `os.path.join` can be named
+                let member = match self.peek() {
+                    Token::Identifier(member) => Some(member.clone()),
+                    Token::Eof => None,
+                    _ => {
+                        let lexeme = &self.tokens[self.current].lexeme;
+                        let is_word = lexeme.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                            && lexeme.chars().all(|c| c.is_alphanumeric() || c == '_');
+                        is_word.then(|| lexeme.clone())
+                    }
+                };
+                let Some(member) = member else {
                     return Err("Expected identifier after '.'".to_string());
-                }
+                };
+                self.advance();
+                expr = Expression::MemberAccess(MemberAccess {
+                    object: Box::new(expr),
+                    member,
+                });
             } else if self.match_token(&Token::ColonColon) {
                 // Handle module paths like std::io::print or enum variants like Color::Red
                 let next_name = if let Token::Identifier(name) = &self.peek() {
@@ -1359,6 +1596,7 @@ impl Parser {
             let mut _temp_parser = Parser {
                 tokens: self.tokens.clone(),
                 current: self.current,
+                track_lines: self.track_lines,
             };
             
             // Try to parse as expression first
@@ -1412,11 +1650,11 @@ impl Parser {
         } else if self.match_token(&Token::Match) {
             self.parse_match_expression()
         } else if self.match_token(&Token::Try) {
-            // Check if this is try-catch or just try
-            if self.check(&Token::Catch) {
-                self.parse_try_catch_expression()
-            } else {
+            // `try(expr)` on its own, otherwise `try { ... } catch e { ... }`
+            if self.check(&Token::LeftParen) {
                 self.parse_try_expression()
+            } else {
+                self.parse_try_catch_expression()
             }
         } else if self.match_token(&Token::Spawn) {
             self.parse_spawn_expression()
@@ -1456,6 +1694,9 @@ impl Parser {
             self.parse_lifetime_expression()
         } else if self.match_token(&Token::LeftBracket) {
             // Could be array literal, list comprehension, or slicing
+            if self.match_token(&Token::RightBracket) {
+                return Ok(Expression::Literal(Literal::Array(vec![])));
+            }
             let expr = self.parse_expression()?;
             if self.match_token(&Token::For) {
                 // List comprehension: [expr for x in xs if cond]
@@ -1504,9 +1745,6 @@ impl Parser {
                     end,
                     step,
                 }));
-            } else if self.check(&Token::RightBracket) {
-                self.advance();
-                return Ok(Expression::Literal(Literal::Array(vec![])));
             } else {
                 // Use the dedicated list literal parser
                 // We already have the first expression in 'expr', so we need to handle this specially
@@ -1530,8 +1768,9 @@ impl Parser {
             let name = name.clone();
             self.advance();
             
-            // Check if this is a struct literal
-            if self.check(&Token::LeftBrace) {
+            // Check if this is a struct literal rather than a block that follows
+            // a condition, as in `while i < n { ... }`
+            if self.starts_struct_literal(&name) {
                 self.parse_struct_literal(name)
             } else {
                 Ok(Expression::Identifier(name))
@@ -1631,12 +1870,8 @@ impl Parser {
             // Block expression
             let mut statements = Vec::new();
             while !self.check(&Token::RightBrace) && !self.is_at_end() {
-                if let Some(statement) = self.parse_statement()? {
-                    statements.push(statement);
-                } else {
-                    if !self.is_at_end() {
-                        self.advance();
-                    }
+                if !self.parse_tracked_statement(&mut statements)? && !self.is_at_end() {
+                    self.advance();
                 }
             }
             self.consume(&Token::RightBrace, "Expected '}' after try block")?;
@@ -1648,16 +1883,28 @@ impl Parser {
         
         self.consume(&Token::Catch, "Expected 'catch' after try block")?;
         
-        let _catch_block = if self.match_token(&Token::LeftBrace) {
+        // `catch e { ... }` or `catch (e) { ... }`
+        let parenthesized = self.check(&Token::LeftParen) && matches!(self.peek_next(), Token::Identifier(_));
+        if parenthesized {
+            self.advance();
+        }
+        let catch_variable = if let Token::Identifier(name) = &self.peek() {
+            let name = name.clone();
+            self.advance();
+            Some(name)
+        } else {
+            None
+        };
+        if parenthesized {
+            self.consume(&Token::RightParen, "Expected ')' after catch variable")?;
+        }
+        
+        let catch_block = if self.match_token(&Token::LeftBrace) {
             // Block expression
             let mut statements = Vec::new();
             while !self.check(&Token::RightBrace) && !self.is_at_end() {
-                if let Some(statement) = self.parse_statement()? {
-                    statements.push(statement);
-                } else {
-                    if !self.is_at_end() {
-                        self.advance();
-                    }
+                if !self.parse_tracked_statement(&mut statements)? && !self.is_at_end() {
+                    self.advance();
                 }
             }
             self.consume(&Token::RightBrace, "Expected '}' after catch block")?;
@@ -1667,20 +1914,20 @@ impl Parser {
             Box::new(self.parse_expression()?)
         };
         
-        Ok(*try_block)
+        Ok(Expression::TryCatch(TryCatchExpression {
+            try_block,
+            catch_variable,
+            catch_block,
+        }))
     }
     
     fn parse_block(&mut self) -> Result<Expression, String> {
         let mut statements = Vec::new();
         
         while !self.check(&Token::RightBrace) && !self.is_at_end() {
-            if let Some(statement) = self.parse_statement()? {
-                statements.push(statement);
-            } else {
-                // If no statement was parsed, advance past the current token
-                if !self.is_at_end() {
-                    self.advance();
-                }
+            // If no statement was parsed, advance past the current token
+            if !self.parse_tracked_statement(&mut statements)? && !self.is_at_end() {
+                self.advance();
             }
         }
         
@@ -1806,12 +2053,8 @@ impl Parser {
             // Block expression
             let mut statements = Vec::new();
             while !self.check(&Token::RightBrace) && !self.is_at_end() {
-                if let Some(statement) = self.parse_statement()? {
-                    statements.push(statement);
-                } else {
-                    if !self.is_at_end() {
-                        self.advance();
-                    }
+                if !self.parse_tracked_statement(&mut statements)? && !self.is_at_end() {
+                    self.advance();
                 }
             }
             self.consume(&Token::RightBrace, "Expected '}' after if block")?;
@@ -1836,12 +2079,8 @@ impl Parser {
                     // Block expression
                     let mut statements = Vec::new();
                     while !self.check(&Token::RightBrace) && !self.is_at_end() {
-                        if let Some(statement) = self.parse_statement()? {
-                            statements.push(statement);
-                        } else {
-                            if !self.is_at_end() {
-                                self.advance();
-                            }
+                        if !self.parse_tracked_statement(&mut statements)? && !self.is_at_end() {
+                            self.advance();
                         }
                     }
                     self.consume(&Token::RightBrace, "Expected '}' after if block")?;
@@ -1857,12 +2096,8 @@ impl Parser {
                         // Block expression
                         let mut statements = Vec::new();
                         while !self.check(&Token::RightBrace) && !self.is_at_end() {
-                            if let Some(statement) = self.parse_statement()? {
-                                statements.push(statement);
-                            } else {
-                                if !self.is_at_end() {
-                                    self.advance();
-                                }
+                            if !self.parse_tracked_statement(&mut statements)? && !self.is_at_end() {
+                                self.advance();
                             }
                         }
                         self.consume(&Token::RightBrace, "Expected '}' after else block")?;
@@ -1884,12 +2119,8 @@ impl Parser {
                 // Block expression
                 let mut statements = Vec::new();
                 while !self.check(&Token::RightBrace) && !self.is_at_end() {
-                    if let Some(statement) = self.parse_statement()? {
-                        statements.push(statement);
-                    } else {
-                        if !self.is_at_end() {
-                            self.advance();
-                        }
+                    if !self.parse_tracked_statement(&mut statements)? && !self.is_at_end() {
+                        self.advance();
                     }
                 }
                 self.consume(&Token::RightBrace, "Expected '}' after else block")?;
@@ -1925,7 +2156,7 @@ impl Parser {
         
         self.consume(&Token::RightBrace, "Expected '}' after match arms")?;
         
-        Ok(*value)
+        Ok(Expression::Match(MatchExpression { expression: value, arms }))
     }
     
     fn parse_match_arm(&mut self) -> Result<MatchArm, String> {
@@ -1957,7 +2188,32 @@ impl Parser {
         if let Token::Identifier(name) = &self.peek() {
             let name = name.clone();
             self.advance();
-            return Ok(Pattern::Identifier(name));
+            if !self.check(&Token::ColonColon) {
+                return Ok(Pattern::Identifier(name));
+            }
+            // An enum variant such as `Shape::Empty`, or `Shape::Circle(r)` with its payload
+            let mut path = name;
+            while self.match_token(&Token::ColonColon) {
+                if let Token::Identifier(segment) = &self.peek() {
+                    path = format!("{}::{}", path, segment);
+                    self.advance();
+                } else {
+                    return Err("Expected identifier after '::' in pattern".to_string());
+                }
+            }
+            let mut fields = Vec::new();
+            if self.match_token(&Token::LeftParen) {
+                if !self.check(&Token::RightParen) {
+                    loop {
+                        fields.push(self.parse_pattern()?);
+                        if !self.match_token(&Token::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(&Token::RightParen, "Expected ')' after variant patterns")?;
+            }
+            return Ok(Pattern::Struct(path, fields));
         }
         
         // Check for literal patterns
@@ -2002,9 +2258,7 @@ impl Parser {
         
         let mut body_statements = Vec::new();
         while !self.check(&Token::RightBrace) && !self.is_at_end() {
-            if let Some(statement) = self.parse_statement()? {
-                body_statements.push(statement);
-            }
+            self.parse_tracked_statement(&mut body_statements)?;
         }
         
         self.consume(&Token::RightBrace, "Expected '}' after loop body")?;
@@ -2029,12 +2283,8 @@ impl Parser {
             // Block expression
             let mut statements = Vec::new();
             while !self.check(&Token::RightBrace) && !self.is_at_end() {
-                if let Some(statement) = self.parse_statement()? {
-                    statements.push(statement);
-                } else {
-                    if !self.is_at_end() {
-                        self.advance();
-                    }
+                if !self.parse_tracked_statement(&mut statements)? && !self.is_at_end() {
+                    self.advance();
                 }
             }
             self.consume(&Token::RightBrace, "Expected '}' after while block")?;
@@ -2261,7 +2511,6 @@ impl Parser {
     
     // Missing parse methods
     fn parse_try_expression(&mut self) -> Result<Expression, String> {
-        self.consume(&Token::Try, "Expected 'try'")?;
         self.consume(&Token::LeftParen, "Expected '(' after 'try'")?;
         let expr = self.parse_expression()?;
         self.consume(&Token::RightParen, "Expected ')' after try expression")?;
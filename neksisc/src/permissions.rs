@@ -0,0 +1,304 @@
+// Permissions
+//
+// What a running program may do to the host. A VM starts with no permissions:
+// reading or writing files, exiting the process and loading native code
+// (`extern "C"` and `import python`) each fail with a catchable
+// `PermissionDenied` error until they are granted, either with the `--allow-*`
+// flags of `neksis run` or through the embedding APIs.
+//
+// Paths are resolved to absolute paths, following symbolic links, both when
+// they are granted and when they are used, so `..` and links cannot leave a
+// granted directory.
+
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Read,
+    Write,
+    Exit,
+    Ffi,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 4] = [
+        Capability::Read,
+        Capability::Write,
+        Capability::Exit,
+        Capability::Ffi,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Read => "read",
+            Capability::Write => "write",
+            Capability::Exit => "exit",
+            Capability::Ffi => "ffi",
+        }
+    }
+
+    // Whether grants can be limited to particular paths
+    fn is_scoped(self) -> bool {
+        matches!(self, Capability::Read | Capability::Write)
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        Capability::ALL
+            .into_iter()
+            .find(|capability| capability.name() == name)
+            .ok_or_else(|| format!("Unknown permission '{}'", name))
+    }
+}
+
+// The paths a capability is granted for
+#[derive(Debug, Clone, PartialEq)]
+enum Scope<T> {
+    Only(Vec<T>),
+    All,
+}
+
+impl<T> Default for Scope<T> {
+    fn default() -> Self {
+        Scope::Only(Vec::new())
+    }
+}
+
+impl<T> Scope<T> {
+    fn allows(&self, matches: impl Fn(&T) -> bool) -> bool {
+        match self {
+            Scope::All => true,
+            Scope::Only(grants) => grants.iter().any(matches),
+        }
+    }
+
+    fn add(&mut self, grant: T) {
+        if let Scope::Only(grants) = self {
+            grants.push(grant);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Permissions {
+    read: Scope<PathBuf>,
+    write: Scope<PathBuf>,
+    exit: bool,
+    ffi: bool,
+}
+
+impl Permissions {
+    /// Nothing is allowed
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Everything is allowed, as for a trusted program
+    pub fn all() -> Self {
+        let mut permissions = Self::none();
+        for capability in Capability::ALL {
+            permissions.allow_all(capability);
+        }
+        permissions
+    }
+
+    /// Grant `capability` for everything
+    pub fn allow_all(&mut self, capability: Capability) {
+        match capability {
+            Capability::Read => self.read = Scope::All,
+            Capability::Write => self.write = Scope::All,
+            Capability::Exit => self.exit = true,
+            Capability::Ffi => self.ffi = true,
+        }
+    }
+
+    /// Grant `capability` for one path and everything under it. Only `read`
+    /// and `write` can be limited this way.
+    pub fn allow(&mut self, capability: Capability, scope: &str) -> Result<(), String> {
+        match capability {
+            Capability::Read => self.read.add(resolve(Path::new(scope))),
+            Capability::Write => self.write.add(resolve(Path::new(scope))),
+            Capability::Exit | Capability::Ffi => {
+                return Err(format!("The {} permission cannot be limited to '{}'", capability, scope));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply a command-line flag such as `--allow-read`, `--allow-read=data,config`
+    /// or `--allow-all`. Returns false for flags that are not about permissions.
+    pub fn allow_flag(&mut self, flag: &str) -> Result<bool, String> {
+        if flag == "--allow-all" || flag == "-A" {
+            *self = Self::all();
+            return Ok(true);
+        }
+        let Some(rest) = flag.strip_prefix("--allow-") else {
+            return Ok(false);
+        };
+        let (name, scopes) = match rest.split_once('=') {
+            Some((name, scopes)) => (name, Some(scopes)),
+            None => (rest, None),
+        };
+        let Ok(capability) = name.parse::<Capability>() else {
+            return Ok(false);
+        };
+        match scopes {
+            None => self.allow_all(capability),
+            Some(_) if !capability.is_scoped() => {
+                return Err(format!("--allow-{} does not take a value", capability));
+            }
+            Some(scopes) => {
+                for scope in scopes.split(',').filter(|scope| !scope.is_empty()) {
+                    self.allow(capability, scope)?;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn check_read(&self, path: &str) -> Result<(), String> {
+        let resolved = resolve(Path::new(path));
+        if self.read.allows(|grant| resolved.starts_with(grant)) {
+            return Ok(());
+        }
+        Err(denied(&format!("read access to '{}'", path), Capability::Read))
+    }
+
+    pub fn check_write(&self, path: &str) -> Result<(), String> {
+        let resolved = resolve(Path::new(path));
+        if self.write.allows(|grant| resolved.starts_with(grant)) {
+            return Ok(());
+        }
+        Err(denied(&format!("write access to '{}'", path), Capability::Write))
+    }
+
+    pub fn check_exit(&self) -> Result<(), String> {
+        if self.exit {
+            return Ok(());
+        }
+        Err(denied("exit", Capability::Exit))
+    }
+
+    /// `what` names the library or Python module being loaded
+    pub fn check_ffi(&self, what: &str) -> Result<(), String> {
+        if self.ffi {
+            return Ok(());
+        }
+        Err(denied(&format!("loading {}", what), Capability::Ffi))
+    }
+}
+
+fn denied(action: &str, capability: Capability) -> String {
+    format!("PermissionDenied: {} (grant it with --allow-{})", action, capability)
+}
+
+// Make `path` absolute, resolving links and `..` for every part of it that exists
+fn resolve(path: &Path) -> PathBuf {
+    let absolute = std::env::current_dir().unwrap_or_default().join(path);
+    let mut resolved = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            component => {
+                resolved.push(component);
+                // Each step is resolved before the next `..` is applied to it
+                if let Ok(real) = resolved.canonicalize() {
+                    resolved = real;
+                }
+            }
+        }
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nothing_is_allowed_by_default() {
+        let permissions = Permissions::none();
+        assert_eq!(
+            permissions.check_read("notes.txt").unwrap_err(),
+            "PermissionDenied: read access to 'notes.txt' (grant it with --allow-read)"
+        );
+        assert!(permissions.check_write("notes.txt").is_err());
+        assert!(permissions.check_exit().is_err());
+        assert_eq!(
+            permissions.check_ffi("library 'libc.so.6'").unwrap_err(),
+            "PermissionDenied: loading library 'libc.so.6' (grant it with --allow-ffi)"
+        );
+
+        let permissions = Permissions::all();
+        assert!(permissions.check_read("/etc/hostname").is_ok());
+        assert!(permissions.check_write("/tmp/out.txt").is_ok());
+        assert!(permissions.check_exit().is_ok());
+    }
+
+    #[test]
+    fn test_paths_stay_inside_their_grant() {
+        let root = std::env::temp_dir().join(format!("neksis-permissions-{}", std::process::id()));
+        let data = root.join("data");
+        std::fs::create_dir_all(data.join("nested")).unwrap();
+        std::fs::create_dir_all(root.join("secret")).unwrap();
+
+        let mut permissions = Permissions::none();
+        permissions.allow_flag(&format!("--allow-read={}", data.display())).unwrap();
+        assert!(permissions.check_read(data.join("nested/a.txt").to_str().unwrap()).is_ok());
+        assert!(permissions.check_read(data.join("new/b.txt").to_str().unwrap()).is_ok());
+        assert!(permissions.check_read(data.join("../secret/key").to_str().unwrap()).is_err());
+        assert!(permissions.check_read(root.join("data-other").to_str().unwrap()).is_err());
+        assert!(permissions.check_write(data.join("a.txt").to_str().unwrap()).is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("secret"), data.join("link")).unwrap();
+            assert!(permissions.check_read(data.join("link/key").to_str().unwrap()).is_err());
+            // `link/..` is the parent of the link's target, not `data`
+            assert!(permissions.check_read(data.join("link/../data/x").to_str().unwrap()).is_ok());
+            assert!(permissions.check_read(data.join("link/../secret/key").to_str().unwrap()).is_err());
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut permissions = Permissions::none();
+        assert!(permissions.allow_flag("--allow-write=out,logs").unwrap());
+        assert!(permissions.allow_flag("--allow-exit").unwrap());
+        assert!(!permissions.allow_flag("--profile").unwrap());
+        // Network, environment and subprocess access have no permission yet
+        assert!(!permissions.allow_flag("--allow-net").unwrap());
+
+        assert!(permissions.check_write("out/result.txt").is_ok());
+        assert!(permissions.check_write("logs").is_ok());
+        assert_eq!(
+            permissions.check_write("src/main.nx").unwrap_err(),
+            "PermissionDenied: write access to 'src/main.nx' (grant it with --allow-write)"
+        );
+        assert!(permissions.check_read("out/result.txt").is_err());
+        assert!(permissions.check_exit().is_ok());
+        assert!(permissions.check_ffi("library 'libm.so.6'").is_err());
+
+        assert_eq!(permissions.allow_flag("--allow-exit=0").unwrap_err(), "--allow-exit does not take a value");
+        assert_eq!(
+            permissions.allow(Capability::Ffi, "libm.so.6").unwrap_err(),
+            "The ffi permission cannot be limited to 'libm.so.6'"
+        );
+        assert_eq!("net".parse::<Capability>().unwrap_err(), "Unknown permission 'net'");
+    }
+}
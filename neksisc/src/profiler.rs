@@ -0,0 +1,286 @@
+// Function Profiler for Neksis
+//
+// An instrumenting profiler driven by the VM: every call into and return from
+// a user-defined function is timed. The result gives per-function call counts
+// with inclusive and exclusive time, folded stacks for flamegraph tools and a
+// Chrome trace-event document (chrome://tracing, Perfetto).
+
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Name of the frame that covers top-level code outside any function
+pub const ROOT_FRAME: &str = "<module>";
+
+/// Trace events are capped so long-running programs cannot exhaust memory
+const MAX_TRACE_EVENTS: usize = 1_000_000;
+
+struct Frame {
+    name: String,
+    /// Folded stack up to and including this frame, e.g. `<module>;main;fib`
+    path: String,
+    started: Instant,
+    children: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionProfile {
+    pub name: String,
+    pub calls: u64,
+    /// Time spent in the function and its callees. Recursive calls are only
+    /// counted once, from the outermost frame.
+    pub inclusive: Duration,
+    /// Time spent in the function's own instructions and builtins
+    pub exclusive: Duration,
+}
+
+/// One completed call, in microseconds since profiling started
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub name: String,
+    pub start_us: f64,
+    pub duration_us: f64,
+}
+
+pub struct Profiler {
+    started: Instant,
+    frames: Vec<Frame>,
+    functions: HashMap<String, FunctionProfile>,
+    folded: HashMap<String, Duration>,
+    events: Vec<TraceEvent>,
+    dropped_events: usize,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let started = Instant::now();
+        Self {
+            started,
+            frames: vec![Frame {
+                name: ROOT_FRAME.to_string(),
+                path: ROOT_FRAME.to_string(),
+                started,
+                children: Duration::ZERO,
+            }],
+            functions: HashMap::new(),
+            folded: HashMap::new(),
+            events: Vec::new(),
+            dropped_events: 0,
+        }
+    }
+
+    /// Number of active frames, including the root frame
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn enter(&mut self, name: &str) {
+        let path = match self.frames.last() {
+            Some(parent) => format!("{};{}", parent.path, name),
+            None => name.to_string(),
+        };
+        self.functions
+            .entry(name.to_string())
+            .or_insert_with(|| FunctionProfile { name: name.to_string(), ..FunctionProfile::default() })
+            .calls += 1;
+        self.frames.push(Frame { name: name.to_string(), path, started: Instant::now(), children: Duration::ZERO });
+    }
+
+    pub fn exit(&mut self) {
+        // The root frame is only closed by `finish`
+        if self.frames.len() > 1 {
+            self.close_frame();
+        }
+    }
+
+    fn close_frame(&mut self) {
+        let Some(frame) = self.frames.pop() else { return };
+        let elapsed = frame.started.elapsed();
+        let exclusive = elapsed.saturating_sub(frame.children);
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += elapsed;
+        }
+
+        // The root frame only appears in stacks and the trace, not in the function table
+        if !self.frames.is_empty() {
+            let recursive = self.frames.iter().any(|outer| outer.name == frame.name);
+            let profile = self
+                .functions
+                .entry(frame.name.clone())
+                .or_insert_with(|| FunctionProfile { name: frame.name.clone(), ..FunctionProfile::default() });
+            profile.exclusive += exclusive;
+            if !recursive {
+                profile.inclusive += elapsed;
+            }
+        }
+        *self.folded.entry(frame.path).or_insert(Duration::ZERO) += exclusive;
+
+        if self.events.len() < MAX_TRACE_EVENTS {
+            self.events.push(TraceEvent {
+                name: frame.name,
+                start_us: frame.started.duration_since(self.started).as_secs_f64() * 1e6,
+                duration_us: elapsed.as_secs_f64() * 1e6,
+            });
+        } else {
+            self.dropped_events += 1;
+        }
+    }
+
+    /// Close every open frame, including any left open by an error or `exit()`
+    pub fn finish(mut self) -> Profile {
+        while !self.frames.is_empty() {
+            self.close_frame();
+        }
+        let mut functions: Vec<FunctionProfile> = self.functions.into_values().collect();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then_with(|| a.name.cmp(&b.name)));
+        // Chrome expects events ordered by start time
+        self.events.sort_by(|a, b| a.start_us.partial_cmp(&b.start_us).unwrap_or(std::cmp::Ordering::Equal));
+        Profile {
+            total: self.started.elapsed(),
+            functions,
+            folded: self.folded.into_iter().collect(),
+            events: self.events,
+            dropped_events: self.dropped_events,
+        }
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The finished profile of one program run
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub total: Duration,
+    /// Sorted by exclusive time, hottest first
+    pub functions: Vec<FunctionProfile>,
+    pub folded: BTreeMap<String, Duration>,
+    pub events: Vec<TraceEvent>,
+    pub dropped_events: usize,
+}
+
+impl Profile {
+    /// Folded stacks weighted by exclusive microseconds, one per line, as
+    /// consumed by `flamegraph.pl` and `inferno-flamegraph`
+    pub fn folded_stacks(&self) -> String {
+        let mut out = String::new();
+        for (stack, time) in &self.folded {
+            let micros = time.as_micros();
+            if micros > 0 {
+                out.push_str(&format!("{} {}\n", stack, micros));
+            }
+        }
+        out
+    }
+
+    /// The Chrome trace-event JSON format, using complete (`"ph": "X"`) events
+    pub fn chrome_trace(&self) -> String {
+        let events: Vec<_> = self
+            .events
+            .iter()
+            .map(|event| {
+                json!({
+                    "name": event.name,
+                    "cat": "function",
+                    "ph": "X",
+                    "ts": event.start_us,
+                    "dur": event.duration_us,
+                    "pid": 1,
+                    "tid": 1,
+                })
+            })
+            .collect();
+        let document = json!({
+            "traceEvents": events,
+            "displayTimeUnit": "ms",
+            "otherData": { "dropped_events": self.dropped_events },
+        });
+        serde_json::to_string(&document).unwrap_or_default()
+    }
+
+    /// The `limit` functions with the most exclusive time
+    pub fn hot_functions(&self, limit: usize) -> String {
+        let total = self.total.as_secs_f64().max(f64::EPSILON);
+        let width = self
+            .functions
+            .iter()
+            .take(limit)
+            .map(|function| function.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("Function".len())
+            + 2;
+
+        let mut out = format!(
+            "{:<width$}{:>10}{:>14}{:>8}{:>14}{:>8}\n",
+            "Function", "Calls", "Self", "Self%", "Total", "Total%",
+            width = width
+        );
+        for function in self.functions.iter().take(limit) {
+            out.push_str(&format!(
+                "{:<width$}{:>10}{:>14}{:>7.1}%{:>14}{:>7.1}%\n",
+                function.name,
+                function.calls,
+                format!("{:.3?}", function.exclusive),
+                function.exclusive.as_secs_f64() * 100.0 / total,
+                format!("{:.3?}", function.inclusive),
+                function.inclusive.as_secs_f64() * 100.0 / total,
+                width = width
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode_compiler::BytecodeCompiler;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::vm::{OutputCapture, VM};
+
+    #[test]
+    fn test_profile_recursive_program() {
+        let source = "fn fib(n: Int) -> Int {\n    if n <= 1 {\n        return n;\n    }\n    return fib(n - 1) + fib(n - 2);\n}\n\nfn main() {\n    println(fib(10));\n}\n";
+        let tokens = Lexer::new(source, "fib.nx".to_string()).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let instructions = BytecodeCompiler::new().compile_program(&program).unwrap();
+
+        let mut vm = VM::new();
+        vm.set_output_capture(OutputCapture::new());
+        vm.enable_profiling();
+        vm.load_instructions(instructions);
+        vm.run().unwrap();
+        let profile = vm.take_profile().unwrap();
+
+        let calls: HashMap<&str, u64> = profile.functions.iter().map(|f| (f.name.as_str(), f.calls)).collect();
+        assert_eq!(calls.get("fib"), Some(&177));
+        assert_eq!(calls.get("main"), Some(&1));
+
+        // Recursion must not inflate inclusive time beyond the whole run
+        let fib = profile.functions.iter().find(|f| f.name == "fib").unwrap();
+        let main = profile.functions.iter().find(|f| f.name == "main").unwrap();
+        assert!(fib.inclusive <= main.inclusive);
+        assert!(main.inclusive <= profile.total);
+
+        assert!(profile.folded.keys().any(|stack| stack == "<module>;main;fib;fib"));
+        assert_eq!(profile.events.len(), 177 + 1 + 1);
+        let trace: serde_json::Value = serde_json::from_str(&profile.chrome_trace()).unwrap();
+        assert_eq!(trace["traceEvents"][0]["name"], "<module>");
+        assert!(profile.hot_functions(5).lines().nth(1).is_some());
+    }
+
+    #[test]
+    fn test_unbalanced_frames_are_closed() {
+        let mut profiler = Profiler::new();
+        profiler.enter("outer");
+        profiler.enter("inner");
+        let profile = profiler.finish();
+        assert_eq!(profile.events.len(), 3);
+        assert_eq!(profile.functions.iter().map(|f| f.calls).sum::<u64>(), 2);
+    }
+}
@@ -0,0 +1,409 @@
+// The `neksis` Python Module
+//
+// Exposes the compiler and VM to Python: `compile` reports diagnostics, `run`
+// executes a program and returns its result with what it printed, and
+// `Engine` keeps VM state across runs and accepts Python callables as
+// built-ins. Programs can only use the host as far as `allow` grants, and
+// `limits` stops runaway ones with `LimitExceeded`.
+// `maturin develop` builds it with the `extension-module` feature
+// (see pyproject.toml); values cross the boundary through the conversions in
+// `ffi`.
+
+use crate::engine::{self, Diagnostic, Engine};
+use crate::error::CompilerError;
+use crate::ffi::{neksis_value, python_error, python_object};
+use crate::limits::Limits;
+use crate::memory_manager::CycleCollector;
+use crate::permissions::Capability;
+use crate::vm::{OutputCapture, VMValue};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyTuple};
+use std::rc::Rc;
+use std::time::Duration;
+
+// Raised when a program fails to compile or run
+create_exception!(neksis, NeksisError, PyException);
+// Raised when a program is stopped by one of its limits
+create_exception!(neksis, LimitExceeded, NeksisError);
+
+/// A problem found by `compile`
+#[pyclass(name = "Diagnostic", get_all)]
+#[derive(Clone)]
+struct PyDiagnostic {
+    severity: String,
+    message: String,
+    line: Option<usize>,
+    column: Option<usize>,
+}
+
+#[pymethods]
+impl PyDiagnostic {
+    fn __repr__(&self) -> String {
+        let position = match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{}:{}: ", line, column),
+            (Some(line), None) => format!("{}: ", line),
+            _ => String::new(),
+        };
+        format!("<Diagnostic {}{}: {}>", position, self.severity, self.message)
+    }
+}
+
+impl From<Diagnostic> for PyDiagnostic {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self {
+            severity: diagnostic.severity.name().to_string(),
+            message: diagnostic.message,
+            line: diagnostic.line,
+            column: diagnostic.column,
+        }
+    }
+}
+
+/// The value of a program's last expression, and everything it printed
+#[pyclass(get_all)]
+struct RunResult {
+    value: PyObject,
+    stdout: String,
+}
+
+#[pymethods]
+impl RunResult {
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        Ok(format!("RunResult(value={}, stdout={:?})", self.value.as_ref(py).repr()?, self.stdout))
+    }
+}
+
+/// A Neksis VM that keeps its variables, functions and imports between runs
+#[pyclass(name = "Engine", unsendable)]
+struct PyEngine {
+    engine: Engine,
+    capture: OutputCapture,
+}
+
+#[pymethods]
+impl PyEngine {
+    #[new]
+    #[pyo3(signature = (globals = None, allow = None, limits = None))]
+    fn new(globals: Option<&PyDict>, allow: Option<&PyDict>, limits: Option<&PyDict>) -> PyResult<Self> {
+        let capture = OutputCapture::new();
+        let mut engine = Engine::new();
+        engine.set_output_capture(capture.clone());
+        let mut py_engine = Self { engine, capture };
+        if let Some(globals) = globals {
+            py_engine.set_globals(globals)?;
+        }
+        if let Some(allow) = allow {
+            py_engine.allow_each(allow)?;
+        }
+        if let Some(limits) = limits {
+            py_engine.set_limits(Some(limits))?;
+        }
+        Ok(py_engine)
+    }
+
+    /// Let programs use `permission` ("read", "write", "exit" or "ffi") on
+    /// `scope`, or without limits when `scope` is None
+    #[pyo3(signature = (permission, scope = None))]
+    fn allow(&mut self, permission: &str, scope: Option<&str>) -> PyResult<()> {
+        let capability = permission.parse::<Capability>().map_err(PyValueError::new_err)?;
+        match scope {
+            Some(scope) => self.engine.allow(capability, scope).map_err(|e| PyValueError::new_err(e.message)),
+            None => {
+                self.engine.allow_all(capability);
+                Ok(())
+            }
+        }
+    }
+
+    /// Bound each later run by the limits in `limits`: `max_instructions`,
+    /// `max_heap_bytes`, `max_call_depth` and `max_wall_time` in seconds.
+    /// None removes every limit.
+    #[pyo3(signature = (limits = None))]
+    fn set_limits(&mut self, limits: Option<&PyDict>) -> PyResult<()> {
+        let mut bounds = Limits::default();
+        for (name, value) in limits.into_iter().flatten() {
+            match name.extract::<&str>()? {
+                "max_instructions" => bounds.max_instructions = value.extract()?,
+                "max_heap_bytes" => bounds.max_heap_bytes = value.extract()?,
+                "max_call_depth" => bounds.max_call_depth = value.extract()?,
+                "max_wall_time" => {
+                    bounds.max_wall_time = value.extract::<Option<f64>>()?
+                        .map(|seconds| Duration::try_from_secs_f64(seconds).map_err(|e| PyValueError::new_err(e.to_string())))
+                        .transpose()?;
+                }
+                other => return Err(PyValueError::new_err(format!("Unknown limit '{}'", other))),
+            }
+        }
+        self.engine.set_limits(bounds);
+        Ok(())
+    }
+
+    /// Run `source` after everything this engine has run before
+    fn run(&mut self, py: Python<'_>, source: &str) -> PyResult<RunResult> {
+        let result = self.engine.run(source);
+        let stdout = std::mem::take(&mut *self.capture.stdout.lock().unwrap());
+        self.capture.stderr.lock().unwrap().clear();
+        match result {
+            Ok(value) => Ok(RunResult { value: to_python(py, &value)?, stdout }),
+            Err(error) => {
+                // Keep what the program printed before it failed
+                let error = match self.engine.limit_exceeded() {
+                    Some(_) => LimitExceeded::new_err(error.message),
+                    None => neksis_error(&error),
+                };
+                error.value(py).setattr("stdout", stdout)?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Make `function` callable from Neksis as `name(...)`. An exception it
+    /// raises becomes a Neksis error that try/catch can handle.
+    fn register(&mut self, py: Python<'_>, name: &str, function: PyObject) -> PyResult<()> {
+        if !function.as_ref(py).is_callable() {
+            return Err(PyTypeError::new_err(format!("the function registered as '{}' is not callable", name)));
+        }
+        let name_for_errors = name.to_string();
+        self.engine.register_function(name, Rc::new(move |args: Vec<VMValue>| {
+            Python::with_gil(|py| {
+                let args = args.iter().map(|arg| python_object(py, arg)).collect::<Result<Vec<_>, _>>()?;
+                let result = function.call1(py, PyTuple::new(py, args)).map_err(|e| python_error(&e))?;
+                neksis_value(result.as_ref(py), &mut CycleCollector::new())
+                    .map_err(|e| format!("{} returned {}", name_for_errors, e))
+            })
+        }));
+        Ok(())
+    }
+
+    fn get_global(&self, py: Python<'_>, name: &str) -> PyResult<PyObject> {
+        match self.engine.global(name) {
+            Some(value) => to_python(py, &value),
+            None => Err(PyKeyError::new_err(name.to_string())),
+        }
+    }
+
+    fn set_global(&mut self, name: &str, value: &PyAny) -> PyResult<()> {
+        let value = neksis_value(value, &mut CycleCollector::new())
+            .map_err(|e| PyTypeError::new_err(format!("global '{}' is {}", name, e)))?;
+        self.engine.set_global(name, value);
+        Ok(())
+    }
+}
+
+impl PyEngine {
+    fn set_globals(&mut self, globals: &PyDict) -> PyResult<()> {
+        for (name, value) in globals {
+            self.set_global(name.extract()?, value)?;
+        }
+        Ok(())
+    }
+
+    // `{"read": True, "write": ["out", "logs"], "exit": True}`
+    fn allow_each(&mut self, allow: &PyDict) -> PyResult<()> {
+        for (permission, scopes) in allow {
+            let permission: &str = permission.extract()?;
+            if let Ok(all) = scopes.extract::<bool>() {
+                if all {
+                    self.allow(permission, None)?;
+                }
+            } else if let Ok(scope) = scopes.extract::<&str>() {
+                self.allow(permission, Some(scope))?;
+            } else {
+                for scope in scopes.extract::<Vec<&str>>()? {
+                    self.allow(permission, Some(scope))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compile `source` without running it and return what the compiler and
+/// linter found; an empty list means the program is clean
+#[pyfunction]
+fn compile(source: &str) -> Vec<PyDiagnostic> {
+    engine::check(source).into_iter().map(PyDiagnostic::from).collect()
+}
+
+/// Run `source` in a fresh engine whose globals start as `globals`, with the
+/// permissions in `allow` and the bounds in `limits`
+#[pyfunction]
+#[pyo3(signature = (source, globals = None, allow = None, limits = None))]
+fn run(
+    py: Python<'_>,
+    source: &str,
+    globals: Option<&PyDict>,
+    allow: Option<&PyDict>,
+    limits: Option<&PyDict>,
+) -> PyResult<RunResult> {
+    PyEngine::new(globals, allow, limits)?.run(py, source)
+}
+
+#[pymodule]
+fn neksis(py: Python<'_>, module: &PyModule) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(compile, module)?)?;
+    module.add_function(wrap_pyfunction!(run, module)?)?;
+    module.add_class::<PyEngine>()?;
+    module.add_class::<PyDiagnostic>()?;
+    module.add_class::<RunResult>()?;
+    module.add("NeksisError", py.get_type::<NeksisError>())?;
+    module.add("LimitExceeded", py.get_type::<LimitExceeded>())?;
+    Ok(())
+}
+
+fn to_python(py: Python<'_>, value: &VMValue) -> PyResult<PyObject> {
+    python_object(py, value).map_err(PyTypeError::new_err)
+}
+
+fn neksis_error(error: &CompilerError) -> PyErr {
+    NeksisError::new_err(error.message.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run `code` with the module importable as `neksis`
+    fn with_module(code: &str) {
+        Python::with_gil(|py| {
+            // A module can only be initialized once per process
+            let modules = py.import("sys").unwrap().getattr("modules").unwrap();
+            if !modules.contains("neksis").unwrap() {
+                modules.set_item("neksis", pyo3::wrap_pymodule!(neksis)(py)).unwrap();
+            }
+            if let Err(error) = py.run(code, None, None) {
+                error.print(py);
+                panic!("Python code failed: {}", error);
+            }
+        });
+    }
+
+    #[test]
+    fn test_python_run_and_compile() {
+        with_module(r#"
+import neksis
+result = neksis.run('let x = base * 2;\nprint("x is " + x);\nx + 1', globals={"base": 20})
+assert result.value == 41, result
+assert result.stdout == "x is 40\n", result
+assert neksis.run('print("no value");').value is None
+assert neksis.run('[1, 2.5, "three", null]').value == [1, 2.5, "three", None]
+
+assert neksis.compile('fn f(x: Int) -> Int { return x; }\nprint(f(1));') == []
+[error] = neksis.compile('let a = 1;\nfn f(x) { return x; }')
+assert (error.severity, error.line) == ("error", 2), error
+
+try:
+    neksis.run('print("before");\nlet y = nothing;')
+    raise AssertionError("an undefined variable should raise")
+except neksis.NeksisError as error:
+    assert str(error) == "Undefined variable: nothing", error
+    assert error.stdout == "before\n"
+
+try:
+    neksis.run("1", globals={"bad": 1j})
+    raise AssertionError("a complex global should be rejected")
+except TypeError as error:
+    assert str(error) == "global 'bad' is a Python complex, which has no Neksis equivalent", error
+"#);
+    }
+
+    #[test]
+    fn test_python_engine_state_and_callables() {
+        with_module(r#"
+import neksis
+engine = neksis.Engine()
+calls = []
+def lookup(key):
+    calls.append(key)
+    return {"a": [1, 2], "b": {"nested": True}}[key]
+engine.register("lookup", lookup)
+engine.register("shout", lambda text: text.upper() + "!")
+
+engine.run('fn total(items: Array) -> Int { let sum = 0; let i = 0; while i < len(items) { sum = sum + items[i]; i = i + 1; } return sum; }')
+engine.run('let count = total(lookup("a"));')
+assert engine.get_global("count") == 3
+assert engine.run('count * 10').value == 30
+assert engine.run('dict_get(lookup("b"), "nested")').value is True
+assert engine.run('print(shout("hi"));').stdout == "HI!\n"
+assert calls == ["a", "b"]
+
+# A Python exception is catchable from Neksis
+caught = engine.run('let m = "";\ntry { lookup("zzz"); } catch (e) { m = e; }\nm').value
+assert caught == "KeyError: 'zzz'", caught
+try:
+    engine.run('lookup("zzz")')
+    raise AssertionError("an uncaught Python exception should raise")
+except neksis.NeksisError as error:
+    assert str(error) == "KeyError: 'zzz'", error
+
+engine.set_global("scale", 1.5)
+assert engine.run('scale * 2').value == 3.0
+try:
+    engine.get_global("missing")
+    raise AssertionError("a missing global should raise KeyError")
+except KeyError:
+    pass
+try:
+    engine.register("nope", 42)
+    raise AssertionError("registering a non-callable should raise")
+except TypeError:
+    pass
+"#);
+    }
+
+    #[test]
+    fn test_python_permissions() {
+        with_module(r#"
+import neksis
+try:
+    neksis.run('file_exists("Cargo.toml")')
+    raise AssertionError("file access should be denied by default")
+except neksis.NeksisError as error:
+    assert str(error).startswith("PermissionDenied: read access to 'Cargo.toml'"), error
+
+assert neksis.run('file_exists("Cargo.toml")', allow={"read": "Cargo.toml"}).value is True
+assert neksis.run('file_exists("Cargo.toml")', allow={"read": True}).value is True
+
+engine = neksis.Engine(allow={"read": ["src"]})
+caught = engine.run('let m = "";\ntry { exit(2); } catch (e) { m = e; }\nm').value
+assert caught == "PermissionDenied: exit (grant it with --allow-exit)", caught
+engine.allow("read", "Cargo.toml")
+assert engine.run('file_exists("Cargo.toml")').value is True
+try:
+    engine.allow("disk")
+    raise AssertionError("an unknown permission should raise")
+except ValueError as error:
+    assert str(error) == "Unknown permission 'disk'", error
+"#);
+    }
+
+    #[test]
+    fn test_python_limits() {
+        with_module(r#"
+import neksis
+try:
+    neksis.run("let i = 0;\nwhile true { i = i + 1; }", limits={"max_instructions": 10000})
+    raise AssertionError("the loop should have been stopped")
+except neksis.LimitExceeded as error:
+    assert str(error) == "InstructionLimitExceeded: ran more than 10000 instructions", error
+    assert isinstance(error, neksis.NeksisError)
+
+engine = neksis.Engine(limits={"max_wall_time": 0.05})
+try:
+    engine.run('print("started");\nwhile true { }')
+    raise AssertionError("the loop should have timed out")
+except neksis.LimitExceeded as error:
+    assert str(error) == "TimeLimitExceeded: ran longer than 50ms", error
+    assert error.stdout == "started\n"
+engine.set_limits(None)
+assert engine.run("1 + 1").value == 2
+try:
+    engine.set_limits({"max_fuel": 1})
+    raise AssertionError("an unknown limit should raise")
+except ValueError as error:
+    assert str(error) == "Unknown limit 'max_fuel'", error
+"#);
+    }
+}
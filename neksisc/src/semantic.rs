@@ -5,9 +5,11 @@ use crate::ast::{
     RcExpression, ArcExpression, CellExpression, RefCellExpression, MallocExpression,
     FreeExpression, ReallocExpression, LifetimeExpression, MatchExpression, SpawnExpression,
     JoinExpression, ChannelExpression, TryExpression, TryCatchExpression, PipelineExpression,
-    ClassStatement, InterpolatedPart, CallArgument, UnaryOperator, BinaryOperator, BinaryOp
+    ClassStatement, InterpolatedPart, CallArgument, UnaryOperator, BinaryOperator, BinaryOp,
+    MatchArm, Pattern
 };
 use crate::error::CompilerError;
+use crate::visit::{self, Visitor};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -526,9 +528,11 @@ impl SemanticAnalyzer {
                 Ok(TypeValue::Void)
             }
             Expression::ReferenceExpression { target: _, borrow_type: _ } => {
+                self.analyze_children(expression)?;
                 Ok(TypeValue::Reference(Box::new(TypeValue::Unknown), BorrowType::Borrowed, None))
             }
             Expression::DereferenceExpression { target: _ } => {
+                self.analyze_children(expression)?;
                 Ok(TypeValue::Unknown)
             }
             Expression::Block(statements) => {
@@ -546,7 +550,8 @@ impl SemanticAnalyzer {
                 Ok(last_type)
             }
             Expression::StructLiteral(struct_lit) => {
-                // For now, just return the struct type
+                // For now, just check the field values and return the struct type
+                self.analyze_children(expression)?;
                 Ok(TypeValue::Struct(struct_lit.struct_name.clone()))
             }
             Expression::MemberAccess(member_access) => {
@@ -600,7 +605,10 @@ impl SemanticAnalyzer {
             // Concurrency
             Expression::Spawn(spawn_expr) => self.analyze_spawn_expression(spawn_expr),
             Expression::Join(join_expr) => self.analyze_join_expression(join_expr),
-            Expression::Channel(channel_expr) => self.analyze_channel_expression(channel_expr),
+            Expression::Channel(channel_expr) => {
+                self.analyze_children(expression)?;
+                self.analyze_channel_expression(channel_expr)
+            }
             // Error Handling
             Expression::Try(try_expr) => self.analyze_try_expression(try_expr),
             Expression::TryCatch(try_catch_expr) => self.analyze_try_catch_expression(try_catch_expr),
@@ -655,6 +663,9 @@ impl SemanticAnalyzer {
                 let param_types: Vec<TypeValue> = lambda_expr.parameters.iter().map(|p| {
                     self.convert_ast_type_to_type_value(&p.type_annotation).unwrap_or(TypeValue::Unknown)
                 }).collect();
+                for (param, param_type) in lambda_expr.parameters.iter().zip(&param_types) {
+                    self.variables.insert(param.name.clone(), param_type.clone());
+                }
                 // Analyze body type
                 let body_type = self.analyze_expression(&lambda_expr.body)?;
                 Ok(TypeValue::Function(param_types, Box::new(body_type)))
@@ -716,6 +727,13 @@ impl SemanticAnalyzer {
         }
     }
 
+    /// Analyzes the children of a node whose own type doesn't depend on them
+    fn analyze_children(&mut self, expression: &Expression) -> Result<(), CompilerError> {
+        let mut children = ChildAnalyzer { analyzer: self, result: Ok(()) };
+        visit::walk_expression(&mut children, expression);
+        children.result
+    }
+
     fn convert_ast_type_to_type_value(&self, ast_type: &Type) -> Result<TypeValue, CompilerError> {
         match ast_type {
             Type::Int => Ok(TypeValue::Int),
@@ -947,9 +965,15 @@ impl SemanticAnalyzer {
     // Pattern Matching
     fn analyze_match_expression(&mut self, match_expr: &MatchExpression) -> Result<TypeValue, CompilerError> {
         let value_type = self.analyze_expression(&match_expr.expression)?;
-        
+
+        let mut arms = ChildAnalyzer { analyzer: self, result: Ok(()) };
+        for arm in &match_expr.arms {
+            arms.visit_match_arm(arm);
+        }
+        arms.result?;
+
         // For now, just return the value type
-        // In a real implementation, we would analyze the match arms
+        // In a real implementation, we would unify the arm types
         Ok(value_type)
     }
 
@@ -990,6 +1014,9 @@ impl SemanticAnalyzer {
 
     fn analyze_try_catch_expression(&mut self, try_catch_expr: &TryCatchExpression) -> Result<TypeValue, CompilerError> {
         let try_type = self.analyze_expression(&try_catch_expr.try_block)?;
+        if let Some(name) = &try_catch_expr.catch_variable {
+            self.variables.insert(name.clone(), TypeValue::String);
+        }
         let _catch_type = self.analyze_expression(&try_catch_expr.catch_block)?;
         
         // For now, just return the try type
@@ -1056,4 +1083,31 @@ impl SemanticAnalyzer {
             Err(CompilerError::type_error(msg))
         }
     }
-} 
\ No newline at end of file
+}
+
+/// Runs the analyzer on each child node it is given, keeping the first error
+struct ChildAnalyzer<'a> {
+    analyzer: &'a mut SemanticAnalyzer,
+    result: Result<(), CompilerError>,
+}
+
+impl Visitor for ChildAnalyzer<'_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if self.result.is_ok() {
+            self.result = self.analyzer.analyze_statement(statement);
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if self.result.is_ok() {
+            self.result = self.analyzer.analyze_expression(expression).map(|_| ());
+        }
+    }
+
+    fn visit_match_arm(&mut self, arm: &MatchArm) {
+        if let Pattern::Identifier(name) = &arm.pattern {
+            self.analyzer.variables.insert(name.clone(), TypeValue::Unknown);
+        }
+        visit::walk_match_arm(self, arm);
+    }
+}
@@ -0,0 +1,124 @@
+// Line diffs for test failure messages
+//
+// Used by `assert_eq` and the golden-output runner to show which lines of an
+// expected value differ from the actual one.
+
+/// A single line of a diff between expected and actual text
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine {
+    Same(String),
+    Expected(String),
+    Actual(String),
+}
+
+/// Compute a line diff using the longest common subsequence of lines
+pub fn diff_lines(expected: &str, actual: &str) -> Vec<DiffLine> {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let (n, m) = (expected.len(), actual.len());
+
+    // lcs[i][j] is the LCS length of expected[i..] and actual[j..]
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if expected[i] == actual[j] {
+            lines.push(DiffLine::Same(expected[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            lines.push(DiffLine::Expected(expected[i].to_string()));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Actual(actual[j].to_string()));
+            j += 1;
+        }
+    }
+    lines.extend(expected[i..].iter().map(|line| DiffLine::Expected(line.to_string())));
+    lines.extend(actual[j..].iter().map(|line| DiffLine::Actual(line.to_string())));
+    lines
+}
+
+/// Render a diff with `-` for expected-only lines and `+` for actual-only lines
+pub fn render_diff(expected: &str, actual: &str) -> String {
+    let mut output = String::from("--- expected\n+++ actual\n");
+    for line in diff_lines(expected, actual) {
+        match line {
+            DiffLine::Same(text) => output.push_str(&format!("  {}\n", text)),
+            DiffLine::Expected(text) => output.push_str(&format!("- {}\n", text)),
+            DiffLine::Actual(text) => output.push_str(&format!("+ {}\n", text)),
+        }
+    }
+    output
+}
+
+/// Render a unified diff from `old` to `new` with `context` unchanged lines around each change
+pub fn render_unified_diff(old_name: &str, new_name: &str, old: &str, new: &str, context: usize) -> String {
+    let lines = diff_lines(old, new);
+    let changes: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, DiffLine::Same(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // The old and new line numbers each diff line starts at
+    let mut positions = Vec::with_capacity(lines.len());
+    let (mut old_line, mut new_line) = (1, 1);
+    for line in &lines {
+        positions.push((old_line, new_line));
+        match line {
+            DiffLine::Same(_) => {
+                old_line += 1;
+                new_line += 1;
+            }
+            DiffLine::Expected(_) => old_line += 1,
+            DiffLine::Actual(_) => new_line += 1,
+        }
+    }
+
+    let mut output = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let mut index = 0;
+    while index < changes.len() {
+        // Changes closer together than twice the context share a hunk
+        let start = changes[index].saturating_sub(context);
+        while index + 1 < changes.len() && changes[index + 1] - changes[index] - 1 <= 2 * context {
+            index += 1;
+        }
+        let end = (changes[index] + context + 1).min(lines.len());
+        let hunk = &lines[start..end];
+
+        let old_count = hunk.iter().filter(|line| !matches!(line, DiffLine::Actual(_))).count();
+        let new_count = hunk.iter().filter(|line| !matches!(line, DiffLine::Expected(_))).count();
+        let (old_start, new_start) = positions[start];
+        output.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            if old_count == 0 { old_start - 1 } else { old_start },
+            old_count,
+            if new_count == 0 { new_start - 1 } else { new_start },
+            new_count
+        ));
+        for line in hunk {
+            match line {
+                DiffLine::Same(text) => output.push_str(&format!(" {}\n", text)),
+                DiffLine::Expected(text) => output.push_str(&format!("-{}\n", text)),
+                DiffLine::Actual(text) => output.push_str(&format!("+{}\n", text)),
+            }
+        }
+        index += 1;
+    }
+    output
+}
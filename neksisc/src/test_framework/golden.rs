@@ -0,0 +1,294 @@
+// Golden Output Conformance Runner
+//
+// Runs `.nx` programs through the VM and compares their stdout, stderr and
+// exit code with a sibling `.expected` file (`foo.nx` -> `foo.expected`).
+// With `bless` enabled the expected files are rewritten from the current
+// output instead.
+
+use super::diff::render_diff;
+use crate::error::CompilerError;
+use crate::jit_compiler::JITCompiler;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::permissions::Permissions;
+use crate::vm::{OutputCapture, VM};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Everything a program produced when run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+impl ProgramOutput {
+    /// The `.expected` file format. A missing trailing newline on either
+    /// stream is normalised so the sections always start on their own line.
+    pub fn render(&self) -> String {
+        format!(
+            "exit code: {}\n--- stdout ---\n{}--- stderr ---\n{}",
+            self.exit_code,
+            with_trailing_newline(&self.stdout),
+            with_trailing_newline(&self.stderr)
+        )
+    }
+}
+
+fn with_trailing_newline(text: &str) -> String {
+    if text.is_empty() || text.ends_with('\n') {
+        text.to_string()
+    } else {
+        format!("{}\n", text)
+    }
+}
+
+/// Compile and run `source` the way `neksis run` does, capturing its output.
+/// Compile and runtime errors are reported on stderr with exit code 1.
+pub fn run_program(source: &str, file_name: &str) -> ProgramOutput {
+    run_program_at(source, file_name, 0)
+}
+
+/// `run_program` at optimization level `level`, see `ir::compile_program`
+pub fn run_program_at(source: &str, file_name: &str, level: u8) -> ProgramOutput {
+    run_program_with(source, file_name, level, None)
+}
+
+/// `run_program_at` with the JIT compiling functions after `jit_threshold` calls
+pub fn run_program_with(source: &str, file_name: &str, level: u8, jit_threshold: Option<usize>) -> ProgramOutput {
+    let capture = OutputCapture::new();
+    let compiled = (|| -> Result<_, CompilerError> {
+        let mut lexer = Lexer::new(source, file_name.to_string());
+        let tokens = lexer.tokenize()?;
+        let mut parser = Parser::new(tokens);
+        let ast = parser.parse()?;
+        crate::ir::compile_program(&ast, level)
+    })();
+
+    let exit_code = match compiled {
+        Err(e) => {
+            capture.stderr.lock().unwrap().push_str(&format!("error: {}\n", e.message));
+            1
+        }
+        Ok(instructions) => {
+            let mut vm = VM::new();
+            // Golden programs belong to the project, so they may use the host freely
+            vm.set_permissions(Permissions::all());
+            vm.set_output_capture(capture.clone());
+            if let Some(threshold) = jit_threshold {
+                match JITCompiler::new() {
+                    Ok(jit) => vm.set_jit(jit.with_hot_threshold(threshold)),
+                    Err(message) => capture.stderr.lock().unwrap().push_str(&format!("warning: {}\n", message)),
+                }
+            }
+            vm.load_instructions(instructions);
+            match vm.run() {
+                Ok(()) => vm.exit_code().unwrap_or(0),
+                Err(message) => {
+                    capture.stderr.lock().unwrap().push_str(&format!("error: {}\n", message));
+                    1
+                }
+            }
+        }
+    };
+
+    ProgramOutput {
+        stdout: capture.stdout(),
+        stderr: capture.stderr(),
+        exit_code,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoldenStatus {
+    Matched,
+    /// The rendered diff between the expected file and the actual output
+    Mismatched(String),
+    MissingExpected,
+    Blessed,
+    TimedOut,
+}
+
+#[derive(Debug, Clone)]
+pub struct GoldenResult {
+    pub file: PathBuf,
+    pub status: GoldenStatus,
+}
+
+impl GoldenResult {
+    pub fn passed(&self) -> bool {
+        matches!(self.status, GoldenStatus::Matched | GoldenStatus::Blessed)
+    }
+}
+
+pub struct GoldenRunner {
+    pub timeout: Duration,
+    pub bless: bool,
+    pub filter: Option<String>,
+    pub opt_level: u8,
+    // Run with the JIT, compiling functions after this many calls
+    pub jit_threshold: Option<usize>,
+}
+
+impl GoldenRunner {
+    pub fn new() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            bless: false,
+            filter: None,
+            opt_level: 0,
+            jit_threshold: None,
+        }
+    }
+
+    pub fn expected_path(program: &Path) -> PathBuf {
+        program.with_extension("expected")
+    }
+
+    /// Collect the `.nx` programs directly inside each directory in `paths`
+    pub fn collect_programs(&self, paths: &[PathBuf]) -> Result<Vec<PathBuf>, CompilerError> {
+        let mut programs = Vec::new();
+        for path in paths {
+            if path.is_file() {
+                programs.push(path.clone());
+                continue;
+            }
+            let entries = fs::read_dir(path)
+                .map_err(|e| CompilerError::io_error(&format!("Failed to read directory '{}': {}", path.display(), e)))?;
+            for entry in entries.flatten() {
+                let entry_path = entry.path();
+                if entry_path.is_file() && entry_path.extension().is_some_and(|ext| ext == "nx") {
+                    programs.push(entry_path);
+                }
+            }
+        }
+        if let Some(filter) = &self.filter {
+            programs.retain(|program| program.display().to_string().contains(filter.as_str()));
+        }
+        programs.sort();
+        Ok(programs)
+    }
+
+    pub fn check(&self, program: &Path) -> Result<GoldenResult, CompilerError> {
+        let source = fs::read_to_string(program)
+            .map_err(|e| CompilerError::io_error(&format!("Failed to read '{}': {}", program.display(), e)))?;
+        let file_name = program.display().to_string();
+
+        let (sender, receiver) = mpsc::channel();
+        let thread_name = file_name.clone();
+        let (level, jit_threshold) = (self.opt_level, self.jit_threshold);
+        thread::spawn(move || {
+            let _ = sender.send(run_program_with(&source, &thread_name, level, jit_threshold));
+        });
+        let actual = match receiver.recv_timeout(self.timeout) {
+            Ok(output) => output.render(),
+            Err(_) => return Ok(GoldenResult { file: program.to_path_buf(), status: GoldenStatus::TimedOut }),
+        };
+
+        let expected_path = Self::expected_path(program);
+        let status = if self.bless {
+            fs::write(&expected_path, &actual)
+                .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", expected_path.display(), e)))?;
+            GoldenStatus::Blessed
+        } else {
+            match fs::read_to_string(&expected_path) {
+                Ok(expected) if expected.replace("\r\n", "\n") == actual => GoldenStatus::Matched,
+                Ok(expected) => GoldenStatus::Mismatched(render_diff(&expected.replace("\r\n", "\n"), &actual)),
+                Err(_) => GoldenStatus::MissingExpected,
+            }
+        };
+
+        Ok(GoldenResult { file: program.to_path_buf(), status })
+    }
+
+    pub fn run(&self, paths: &[PathBuf]) -> Result<Vec<GoldenResult>, CompilerError> {
+        self.collect_programs(paths)?
+            .iter()
+            .map(|program| self.check(program))
+            .collect()
+    }
+}
+
+impl Default for GoldenRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bless_then_match_then_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("hello.nx");
+        fs::write(&program, "fn main() {\n    println(\"hello\");\n}\n").unwrap();
+
+        let mut runner = GoldenRunner::new();
+        runner.bless = true;
+        assert_eq!(runner.check(&program).unwrap().status, GoldenStatus::Blessed);
+        let expected = fs::read_to_string(GoldenRunner::expected_path(&program)).unwrap();
+        assert_eq!(expected, "exit code: 0\n--- stdout ---\nhello\n--- stderr ---\n");
+
+        runner.bless = false;
+        assert_eq!(runner.check(&program).unwrap().status, GoldenStatus::Matched);
+
+        fs::write(&program, "fn main() {\n    println(\"goodbye\");\n}\n").unwrap();
+        match runner.check(&program).unwrap().status {
+            GoldenStatus::Mismatched(diff) => {
+                assert!(diff.contains("- hello"));
+                assert!(diff.contains("+ goodbye"));
+            }
+            status => panic!("expected a mismatch, got {:?}", status),
+        }
+    }
+
+    #[test]
+    fn test_errors_and_exit_codes() {
+        let output = run_program("fn main() {\n    println(\"bye\");\n    exit(3);\n    println(\"unreachable\");\n}\n", "exit.nx");
+        assert_eq!(output.stdout, "bye\n");
+        assert_eq!(output.exit_code, 3);
+
+        let output = run_program("fn main() {\n    println(missing);\n}\n", "error.nx");
+        assert_eq!(output.exit_code, 1);
+        assert!(output.stderr.contains("Undefined variable: missing"));
+    }
+
+    fn check_repository_corpus(opt_level: u8, jit_threshold: Option<usize>) {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests");
+        let mut runner = GoldenRunner::new();
+        runner.opt_level = opt_level;
+        runner.jit_threshold = jit_threshold;
+        let failures: Vec<String> = runner
+            .run(&[corpus])
+            .unwrap()
+            .into_iter()
+            .filter(|result| !result.passed())
+            .map(|result| format!("{}: {:?}", result.file.display(), result.status))
+            .collect();
+        assert!(failures.is_empty(), "golden output mismatches:\n{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn test_repository_corpus() {
+        check_repository_corpus(0, None);
+    }
+
+    #[test]
+    fn test_repository_corpus_optimized() {
+        // The optimizer must not change what any program prints
+        check_repository_corpus(2, None);
+    }
+
+    #[test]
+    fn test_repository_corpus_jit() {
+        // Compiling every function on its first call must not change what any program prints either
+        check_repository_corpus(0, Some(1));
+        check_repository_corpus(2, Some(1));
+    }
+}
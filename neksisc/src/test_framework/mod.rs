@@ -0,0 +1,610 @@
+// User Test Framework for Neksis
+//
+// Discovers functions annotated with `@test` (and `@should_fail`) in a
+// project's `.nx` files and runs each one in a fresh VM, with name
+// filtering, a per-test timeout and a pool of parallel workers.
+
+pub mod diff;
+pub mod golden;
+pub mod reporters;
+
+use crate::ast::{Expression, FunctionStatement, Literal, Statement};
+use crate::bytecode_compiler::BytecodeCompiler;
+use crate::coverage::{CoverageCounters, CoverageReport, FileCoverage};
+use crate::error::CompilerError;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::permissions::Permissions;
+use crate::vm::{BytecodeInstruction, OutputCapture, VM};
+use reporters::TestReporter;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A single `@test` function found during discovery
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub name: String,
+    pub file: PathBuf,
+    pub line: usize,
+    pub should_fail: bool,
+    /// Substring the failure message must contain, from `@should_fail("...")`
+    pub expected_failure: Option<String>,
+    /// Why the test cannot run at all, reported as its failure
+    error: Option<String>,
+    program: Arc<Vec<BytecodeInstruction>>,
+}
+
+impl TestCase {
+    /// Qualified name used in output, e.g. `tests/math.nx::adds_numbers`
+    pub fn qualified_name(&self) -> String {
+        format!("{}::{}", self.file.display(), self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestStatus {
+    Passed,
+    Failed(String),
+    TimedOut,
+}
+
+/// The result of running one test case
+#[derive(Debug, Clone)]
+pub struct TestOutcome {
+    pub case: TestCase,
+    pub status: TestStatus,
+    pub duration: Duration,
+    /// Line of the statement whose error ended the test
+    pub failure_line: Option<usize>,
+    pub stdout: String,
+    pub stderr: String,
+    /// Hit counters for the test's file, when coverage is enabled
+    pub coverage: Option<CoverageCounters>,
+}
+
+impl TestOutcome {
+    pub fn passed(&self) -> bool {
+        self.status == TestStatus::Passed
+    }
+}
+
+/// A file that contains tests but could not be read, parsed or compiled
+#[derive(Debug, Clone)]
+pub struct LoadError {
+    pub file: PathBuf,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestRunConfig {
+    pub filter: Option<String>,
+    pub timeout: Duration,
+    pub jobs: usize,
+    pub coverage: bool,
+}
+
+impl Default for TestRunConfig {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            timeout: Duration::from_secs(10),
+            jobs: num_cpus::get(),
+            coverage: false,
+        }
+    }
+}
+
+/// Tests collected from a set of paths
+#[derive(Debug, Default)]
+pub struct Discovery {
+    pub cases: Vec<TestCase>,
+    pub load_errors: Vec<LoadError>,
+}
+
+#[derive(Debug)]
+pub struct TestReport {
+    pub outcomes: Vec<TestOutcome>,
+    pub load_errors: Vec<LoadError>,
+    pub filtered_out: usize,
+    pub duration: Duration,
+    pub coverage: Option<CoverageReport>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> usize {
+        self.outcomes.iter().filter(|outcome| outcome.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.outcomes.len() - self.passed() + self.load_errors.len()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed() == 0
+    }
+}
+
+pub struct TestRunner {
+    config: TestRunConfig,
+}
+
+impl TestRunner {
+    pub fn new(config: TestRunConfig) -> Self {
+        Self { config }
+    }
+
+    /// Find every `@test` function in the `.nx` files under `paths`
+    pub fn discover(&self, paths: &[PathBuf]) -> Result<Discovery, CompilerError> {
+        let files = find_source_files(paths)?;
+
+        let mut discovery = Discovery::default();
+        for file in files {
+            let source = fs::read_to_string(&file)
+                .map_err(|e| CompilerError::io_error(&format!("Failed to read '{}': {}", file.display(), e)))?;
+            // Only files that mention a test annotation are parsed, so unrelated
+            // scripts with syntax the compiler does not support yet are left alone
+            if !source.contains("@test") && !source.contains("@should_fail") {
+                continue;
+            }
+            match load_test_cases(&file, &source) {
+                Ok(cases) => discovery.cases.extend(cases),
+                Err(message) => discovery.load_errors.push(LoadError { file, message }),
+            }
+        }
+        Ok(discovery)
+    }
+
+    /// Run the discovered tests that match the name filter, streaming results to `reporter`
+    pub fn run(&self, discovery: Discovery, reporter: &mut dyn TestReporter) -> TestReport {
+        let started = Instant::now();
+        let total = discovery.cases.len();
+        let cases: Vec<TestCase> = discovery
+            .cases
+            .into_iter()
+            .filter(|case| self.matches_filter(case))
+            .collect();
+        let filtered_out = total - cases.len();
+        let case_count = cases.len();
+
+        reporter.run_started(case_count);
+
+        // Workers pull tests from a shared queue and send outcomes back to this
+        // thread, so reporters never have to be thread-safe
+        let queue: Arc<Mutex<VecDeque<(usize, TestCase)>>> =
+            Arc::new(Mutex::new(cases.into_iter().enumerate().collect()));
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..self.config.jobs.max(1).min(case_count.max(1)) {
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+            let timeout = self.config.timeout;
+            let coverage = self.config.coverage;
+            thread::spawn(move || loop {
+                let next = queue.lock().unwrap().pop_front();
+                let Some((index, case)) = next else { break };
+                if sender.send((index, run_test_case(case, timeout, coverage))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut outcomes = Vec::with_capacity(case_count);
+        for (index, outcome) in receiver {
+            reporter.test_finished(&outcome);
+            outcomes.push((index, outcome));
+        }
+        outcomes.sort_by_key(|(index, _)| *index);
+        let outcomes: Vec<TestOutcome> = outcomes.into_iter().map(|(_, outcome)| outcome).collect();
+        let coverage = if self.config.coverage { Some(collect_coverage(&outcomes)) } else { None };
+
+        let report = TestReport {
+            outcomes,
+            load_errors: discovery.load_errors,
+            filtered_out,
+            duration: started.elapsed(),
+            coverage,
+        };
+        reporter.run_finished(&report);
+        report
+    }
+
+    fn matches_filter(&self, case: &TestCase) -> bool {
+        match &self.config.filter {
+            Some(filter) => case.qualified_name().contains(filter.as_str()),
+            None => true,
+        }
+    }
+}
+
+// Merge the counters of every test in a file and map them back to its source
+fn collect_coverage(outcomes: &[TestOutcome]) -> CoverageReport {
+    let mut files: Vec<(&TestCase, CoverageCounters)> = Vec::new();
+    for outcome in outcomes {
+        let Some(counters) = &outcome.coverage else { continue };
+        match files.iter_mut().find(|(case, _)| case.file == outcome.case.file) {
+            Some((_, merged)) => merged.merge(counters),
+            None => files.push((&outcome.case, counters.clone())),
+        }
+    }
+    CoverageReport {
+        files: files
+            .into_iter()
+            .map(|(case, counters)| FileCoverage::from_counters(&case.file, &case.program, &counters))
+            .collect(),
+    }
+}
+
+/// Every `.nx` file under `paths`, sorted, with a leading `./` removed
+pub(crate) fn find_source_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, CompilerError> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect_source_files(path, &mut files)?;
+    }
+    let mut files: Vec<PathBuf> = files
+        .into_iter()
+        .map(|file| file.strip_prefix(".").map(Path::to_path_buf).unwrap_or(file))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn collect_source_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), CompilerError> {
+    if path.is_file() {
+        if path.extension().is_some_and(|ext| ext == "nx") {
+            files.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+
+    let entries = fs::read_dir(path)
+        .map_err(|e| CompilerError::io_error(&format!("Failed to read directory '{}': {}", path.display(), e)))?;
+    for entry in entries.flatten() {
+        let entry_path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Skip hidden directories and build output
+        if entry_path.is_dir() && (name.starts_with('.') || name == "target") {
+            continue;
+        }
+        collect_source_files(&entry_path, files)?;
+    }
+    Ok(())
+}
+
+/// A compiled source file together with its functions that carry one of the requested annotations
+pub(crate) struct AnnotatedProgram {
+    pub instructions: Arc<Vec<BytecodeInstruction>>,
+    /// Each matching function and the line its `fn` keyword is on
+    pub functions: Vec<(FunctionStatement, usize)>,
+}
+
+/// Compile `source` without calling `main` and pick out the functions annotated with any of `annotations`
+pub(crate) fn load_annotated_functions(file: &Path, source: &str, annotations: &[&str]) -> Result<AnnotatedProgram, String> {
+    let mut lexer = Lexer::new(source, file.display().to_string());
+    let tokens = lexer.tokenize()?;
+    // Each function is preceded by a marker with the line of its `fn` keyword
+    let mut parser = Parser::new(tokens).with_line_markers();
+    let program = parser.parse()?;
+    let mut compiler = BytecodeCompiler::new();
+    let instructions = compiler.compile_module(&program).map_err(|e| e.message)?;
+
+    let mut functions = Vec::new();
+    let mut line = 0;
+    for statement in program.statements {
+        match statement {
+            Statement::SourceLine(marker) => line = marker,
+            Statement::Function(function)
+                if function.annotations.iter().any(|a| annotations.contains(&a.name.as_str())) =>
+            {
+                functions.push((function, line));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(AnnotatedProgram {
+        instructions: Arc::new(instructions),
+        functions,
+    })
+}
+
+fn load_test_cases(file: &Path, source: &str) -> Result<Vec<TestCase>, String> {
+    let program = load_annotated_functions(file, source, &["test", "should_fail"])?;
+
+    let mut cases = Vec::new();
+    for (function, line) in &program.functions {
+        let (should_fail, expected_failure) = should_fail_annotation(function);
+        cases.push(TestCase {
+            name: function.name.clone(),
+            file: file.to_path_buf(),
+            line: *line,
+            should_fail,
+            expected_failure,
+            error: (!function.parameters.is_empty())
+                .then(|| format!("test function '{}' must not take parameters", function.name)),
+            program: Arc::clone(&program.instructions),
+        });
+    }
+    Ok(cases)
+}
+
+fn should_fail_annotation(function: &FunctionStatement) -> (bool, Option<String>) {
+    match function.annotations.iter().find(|a| a.name == "should_fail") {
+        Some(annotation) => {
+            let expected = annotation.arguments.first().and_then(|argument| match argument {
+                Expression::Literal(Literal::String(text)) => Some(text.clone()),
+                _ => None,
+            });
+            (true, expected)
+        }
+        None => (false, None),
+    }
+}
+
+fn run_test_case(case: TestCase, timeout: Duration, coverage: bool) -> TestOutcome {
+    if let Some(message) = &case.error {
+        let status = TestStatus::Failed(message.clone());
+        return TestOutcome {
+            case,
+            status,
+            duration: Duration::ZERO,
+            failure_line: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            coverage: None,
+        };
+    }
+
+    let mut instructions = (*case.program).clone();
+    instructions.push(BytecodeInstruction::Call(case.name.clone(), 0));
+    instructions.push(BytecodeInstruction::Pop);
+
+    let started = Instant::now();
+    let capture = OutputCapture::new();
+    let vm_capture = capture.clone();
+    let (sender, receiver) = mpsc::channel();
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let vm_interrupt = Arc::clone(&interrupt);
+    thread::spawn(move || {
+        let mut vm = VM::new();
+        vm.set_interrupt(vm_interrupt);
+        // Tests belong to the project, so they may use the host freely
+        vm.set_permissions(Permissions::all());
+        vm.set_output_capture(vm_capture);
+        if coverage {
+            vm.enable_coverage();
+        }
+        vm.load_instructions(instructions);
+        let result = match vm.run() {
+            Ok(()) => match vm.exit_code() {
+                Some(code) if code != 0 => Err((format!("test called exit({})", code), None)),
+                _ => Ok(()),
+            },
+            Err(message) => Err((message, vm.current_line())),
+        };
+        let _ = sender.send((result, vm.take_coverage()));
+    });
+    let (result, counters) = match receiver.recv_timeout(timeout) {
+        Ok((result, counters)) => (Ok(result), counters),
+        Err(error) => (Err(error), None),
+    };
+    let duration = started.elapsed();
+    if result.is_err() {
+        // Stop the test rather than leave it running on its thread
+        interrupt.store(true, Ordering::Relaxed);
+    }
+
+    let failure_line = match &result {
+        Ok(Err((_, line))) => *line,
+        _ => None,
+    };
+    let status = match result.map(|outcome| outcome.map_err(|(message, _)| message)) {
+        Err(_) => TestStatus::TimedOut,
+        Ok(Ok(())) if case.should_fail => TestStatus::Failed("test did not fail as expected".to_string()),
+        Ok(Ok(())) => TestStatus::Passed,
+        Ok(Err(message)) if case.should_fail => match &case.expected_failure {
+            Some(expected) if !message.contains(expected.as_str()) => TestStatus::Failed(format!(
+                "test failed with an unexpected message\n  expected to contain: {}\n  actual: {}",
+                expected, message
+            )),
+            _ => TestStatus::Passed,
+        },
+        Ok(Err(message)) => TestStatus::Failed(message),
+    };
+
+    TestOutcome {
+        case,
+        status,
+        duration,
+        failure_line,
+        stdout: capture.stdout(),
+        stderr: capture.stderr(),
+        coverage: counters,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writer that keeps reporter output for inspection
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn quiet_reporter() -> Box<dyn TestReporter> {
+        reporters::create_reporter(reporters::ReportFormat::Pretty, Box::new(std::io::sink()))
+    }
+
+    fn write_test_file(dir: &Path, name: &str, source: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_discover_and_run() {
+        let dir = tempfile::tempdir().unwrap();
+        write_test_file(
+            dir.path(),
+            "math_test.nx",
+            r#"
+fn add(a: Int, b: Int) -> Int {
+    return a + b;
+}
+
+@test
+fn adds_numbers() {
+    assert_eq(add(2, 3), 5);
+}
+
+@test
+fn wrong_sum() {
+    assert_eq(add(2, 2), 5);
+}
+
+@test
+@should_fail("assertion failed")
+fn expected_failure() {
+    assert(false);
+}
+"#,
+        );
+        write_test_file(dir.path(), "plain.nx", "fn main() { println(1); }");
+
+        let runner = TestRunner::new(TestRunConfig { jobs: 2, ..TestRunConfig::default() });
+        let discovery = runner.discover(&[dir.path().to_path_buf()]).unwrap();
+        assert_eq!(discovery.cases.len(), 3);
+        assert_eq!(discovery.cases[0].line, 7);
+
+        let report = runner.run(discovery, quiet_reporter().as_mut());
+        assert_eq!(report.passed(), 2);
+        assert_eq!(report.failed(), 1);
+        let failure = report.outcomes.iter().find(|o| o.case.name == "wrong_sum").unwrap();
+        assert!(matches!(&failure.status, TestStatus::Failed(message) if message.contains("left: 4")));
+    }
+
+    #[test]
+    fn test_filter_and_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        write_test_file(
+            dir.path(),
+            "loop_test.nx",
+            "@test\nfn spins() { while true { } }\n\n@test\nfn quick() { assert(true); }\n",
+        );
+
+        let runner = TestRunner::new(TestRunConfig {
+            filter: Some("spins".to_string()),
+            timeout: Duration::from_millis(50),
+            jobs: 1,
+            ..TestRunConfig::default()
+        });
+        let report = runner.run(runner.discover(&[dir.path().to_path_buf()]).unwrap(), quiet_reporter().as_mut());
+        assert_eq!(report.filtered_out, 1);
+        assert_eq!(report.outcomes[0].status, TestStatus::TimedOut);
+    }
+
+    #[test]
+    fn test_lines_and_parameterized_tests() {
+        let dir = tempfile::tempdir().unwrap();
+        write_test_file(
+            dir.path(),
+            "lines_test.nx",
+            "fn check() {\n    assert(false);\n}\n\n@test\nfn check() {\n    assert(true);\n}\n\n@test\nfn takes(n: Int) {\n    assert(n > 0);\n}\n",
+        );
+
+        let runner = TestRunner::new(TestRunConfig { jobs: 1, ..TestRunConfig::default() });
+        let discovery = runner.discover(&[dir.path().to_path_buf()]).unwrap();
+        assert!(discovery.load_errors.is_empty());
+        let lines: Vec<usize> = discovery.cases.iter().map(|case| case.line).collect();
+        assert_eq!(lines, [6, 11]);
+
+        let report = runner.run(discovery, quiet_reporter().as_mut());
+        assert_eq!(report.outcomes[0].status, TestStatus::Passed);
+        assert_eq!(
+            report.outcomes[1].status,
+            TestStatus::Failed("test function 'takes' must not take parameters".to_string())
+        );
+    }
+
+    #[test]
+    fn test_interrupt_stops_the_vm() {
+        let mut vm = VM::new();
+        vm.set_interrupt(Arc::new(AtomicBool::new(true)));
+        vm.load_instructions(vec![BytecodeInstruction::Jump(0)]);
+        assert_eq!(vm.run(), Err("Interrupted".to_string()));
+    }
+
+    #[test]
+    fn test_json_and_junit_reporters() {
+        let dir = tempfile::tempdir().unwrap();
+        write_test_file(
+            dir.path(),
+            "output_test.nx",
+            "@test\nfn noisy() {\n    println(\"to stdout\");\n    eprintln(\"to stderr\");\n    assert_eq(1, 2);\n}\n",
+        );
+        let runner = TestRunner::new(TestRunConfig::default());
+
+        let json_out = SharedBuffer::default();
+        let mut json_reporter = reporters::create_reporter(reporters::ReportFormat::Json, Box::new(json_out.clone()));
+        runner.run(runner.discover(&[dir.path().to_path_buf()]).unwrap(), json_reporter.as_mut());
+        let document: serde_json::Value = serde_json::from_str(&json_out.contents()).unwrap();
+        let test = &document["tests"][0];
+        assert_eq!(test["status"], "failed");
+        assert_eq!(test["line"], 2);
+        assert_eq!(test["failure_line"], 5);
+        assert_eq!(test["stdout"], "to stdout\n");
+        assert_eq!(test["stderr"], "to stderr\n");
+        assert_eq!(document["summary"]["failed"], 1);
+
+        let junit_out = SharedBuffer::default();
+        let mut junit_reporter = reporters::create_reporter(reporters::ReportFormat::Junit, Box::new(junit_out.clone()));
+        runner.run(runner.discover(&[dir.path().to_path_buf()]).unwrap(), junit_reporter.as_mut());
+        let xml = junit_out.contents();
+        assert!(xml.contains("<testcase name=\"noisy\""));
+        assert!(xml.contains("<failure message=\"assertion failed: left == right\" type=\"AssertionError\">"));
+        assert!(xml.contains(":5</failure>"));
+        assert!(xml.contains("<system-out>to stdout\n</system-out>"));
+        assert!(xml.contains("<system-err>to stderr\n</system-err>"));
+    }
+
+    #[test]
+    fn test_render_diff() {
+        let diff = diff::render_diff("a\nb\nc", "a\nx\nc");
+        assert!(diff.contains("- b"));
+        assert!(diff.contains("+ x"));
+        assert!(diff.contains("  c"));
+    }
+
+    #[test]
+    fn test_render_unified_diff() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\ntwo\n3\n4\n5\n6\n7\n8\nnine\n";
+        let diff = diff::render_unified_diff("a.nx", "a.nx (formatted)", old, new, 2);
+        assert_eq!(
+            diff,
+            "--- a.nx\n+++ a.nx (formatted)\n@@ -1,4 +1,4 @@\n 1\n-2\n+two\n 3\n 4\n@@ -7,3 +7,3 @@\n 7\n 8\n-9\n+nine\n"
+        );
+        assert_eq!(diff::render_unified_diff("a", "b", old, old, 2), "");
+    }
+}
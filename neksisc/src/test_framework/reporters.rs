@@ -0,0 +1,283 @@
+// Test Reporters
+//
+// Output formats for `neksis test --format pretty|json|junit`. Pretty output
+// streams results as tests finish; JSON and JUnit XML emit one document once
+// the run is complete so CI systems and dashboards can consume it.
+
+use super::{TestOutcome, TestReport, TestStatus};
+use serde_json::json;
+use std::io::Write;
+
+/// Receives test results as the runner produces them
+pub trait TestReporter {
+    fn run_started(&mut self, _test_count: usize) {}
+    fn test_finished(&mut self, _outcome: &TestOutcome) {}
+    fn run_finished(&mut self, report: &TestReport);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Pretty,
+    Json,
+    Junit,
+}
+
+impl ReportFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "pretty" => Some(ReportFormat::Pretty),
+            "json" => Some(ReportFormat::Json),
+            "junit" => Some(ReportFormat::Junit),
+            _ => None,
+        }
+    }
+}
+
+/// Create the reporter for `format`, writing to `out`
+pub fn create_reporter(format: ReportFormat, out: Box<dyn Write>) -> Box<dyn TestReporter> {
+    match format {
+        ReportFormat::Pretty => Box::new(PrettyReporter::new(out)),
+        ReportFormat::Json => Box::new(JsonReporter::new(out)),
+        ReportFormat::Junit => Box::new(JunitReporter::new(out)),
+    }
+}
+
+fn duration_ms(outcome: &TestOutcome) -> f64 {
+    outcome.duration.as_secs_f64() * 1000.0
+}
+
+fn failure_message(outcome: &TestOutcome) -> Option<String> {
+    match &outcome.status {
+        TestStatus::Passed => None,
+        TestStatus::Failed(message) => Some(message.clone()),
+        TestStatus::TimedOut => Some(format!("test timed out after {:.0}ms", duration_ms(outcome))),
+    }
+}
+
+// Where the test failed, or where it is declared if no statement failed
+fn location(outcome: &TestOutcome) -> String {
+    format!("{}:{}", outcome.case.file.display(), outcome.failure_line.unwrap_or(outcome.case.line))
+}
+
+/// Human-readable output with emoji, matching the rest of the CLI
+pub struct PrettyReporter {
+    out: Box<dyn Write>,
+}
+
+impl PrettyReporter {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self { out }
+    }
+}
+
+impl TestReporter for PrettyReporter {
+    fn run_started(&mut self, test_count: usize) {
+        let _ = writeln!(self.out, "🧪 Running {} neksis tests...", test_count);
+    }
+
+    fn test_finished(&mut self, outcome: &TestOutcome) {
+        let label = match outcome.status {
+            TestStatus::Passed => "✅ ok",
+            TestStatus::Failed(_) => "❌ FAILED",
+            TestStatus::TimedOut => "⏱️ TIMED OUT",
+        };
+        let _ = writeln!(self.out, "test {} ... {} ({:.1?})", outcome.case.qualified_name(), label, outcome.duration);
+    }
+
+    fn run_finished(&mut self, report: &TestReport) {
+        let failures: Vec<&TestOutcome> = report.outcomes.iter().filter(|outcome| !outcome.passed()).collect();
+
+        if !failures.is_empty() || !report.load_errors.is_empty() {
+            let _ = writeln!(self.out, "\nFailures:");
+            for error in &report.load_errors {
+                let _ = writeln!(self.out, "\n---- {} ----\n{}", error.file.display(), error.message);
+            }
+            for outcome in &failures {
+                let _ = writeln!(self.out, "\n---- {} ({}) ----", outcome.case.name, location(outcome));
+                if let Some(message) = failure_message(outcome) {
+                    let _ = writeln!(self.out, "{}", message);
+                }
+                if !outcome.stdout.is_empty() {
+                    let _ = writeln!(self.out, "---- stdout ----\n{}", outcome.stdout.trim_end());
+                }
+                if !outcome.stderr.is_empty() {
+                    let _ = writeln!(self.out, "---- stderr ----\n{}", outcome.stderr.trim_end());
+                }
+            }
+        }
+
+        let _ = writeln!(self.out, "\n=== Test Results ===");
+        let _ = writeln!(
+            self.out,
+            "{} {} passed; {} failed; {} filtered out; finished in {:.2}s",
+            if report.is_success() { "✅" } else { "❌" },
+            report.passed(),
+            report.failed(),
+            report.filtered_out,
+            report.duration.as_secs_f64()
+        );
+        let _ = self.out.flush();
+    }
+}
+
+/// A single JSON document describing the whole run
+pub struct JsonReporter {
+    out: Box<dyn Write>,
+}
+
+impl JsonReporter {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self { out }
+    }
+}
+
+impl TestReporter for JsonReporter {
+    fn run_finished(&mut self, report: &TestReport) {
+        let tests: Vec<_> = report
+            .outcomes
+            .iter()
+            .map(|outcome| {
+                json!({
+                    "name": outcome.case.name,
+                    "file": outcome.case.file.display().to_string(),
+                    "line": outcome.case.line,
+                    "failure_line": outcome.failure_line,
+                    "status": match outcome.status {
+                        TestStatus::Passed => "passed",
+                        TestStatus::Failed(_) => "failed",
+                        TestStatus::TimedOut => "timed_out",
+                    },
+                    "duration_ms": duration_ms(outcome),
+                    "message": failure_message(outcome),
+                    "stdout": outcome.stdout,
+                    "stderr": outcome.stderr,
+                })
+            })
+            .collect();
+        let load_errors: Vec<_> = report
+            .load_errors
+            .iter()
+            .map(|error| json!({ "file": error.file.display().to_string(), "message": error.message }))
+            .collect();
+        let document = json!({
+            "summary": {
+                "total": report.outcomes.len(),
+                "passed": report.passed(),
+                "failed": report.failed(),
+                "filtered_out": report.filtered_out,
+                "duration_ms": report.duration.as_secs_f64() * 1000.0,
+                "success": report.is_success(),
+            },
+            "tests": tests,
+            "load_errors": load_errors,
+        });
+        let _ = writeln!(self.out, "{}", serde_json::to_string_pretty(&document).unwrap_or_default());
+        let _ = self.out.flush();
+    }
+}
+
+/// JUnit XML with one `<testsuite>` per source file
+pub struct JunitReporter {
+    out: Box<dyn Write>,
+}
+
+impl JunitReporter {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self { out }
+    }
+}
+
+impl TestReporter for JunitReporter {
+    fn run_finished(&mut self, report: &TestReport) {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuites name=\"neksis\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+            report.outcomes.len() + report.load_errors.len(),
+            report.outcomes.len() - report.passed(),
+            report.load_errors.len(),
+            report.duration.as_secs_f64()
+        ));
+
+        // Group outcomes by file, keeping the order in which files were discovered
+        let mut files: Vec<&std::path::Path> = Vec::new();
+        for outcome in &report.outcomes {
+            if !files.contains(&outcome.case.file.as_path()) {
+                files.push(&outcome.case.file);
+            }
+        }
+
+        for file in files {
+            let outcomes: Vec<&TestOutcome> = report.outcomes.iter().filter(|o| o.case.file == file).collect();
+            let suite_name = escape_xml(&file.display().to_string());
+            xml.push_str(&format!(
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{:.3}\">\n",
+                suite_name,
+                outcomes.len(),
+                outcomes.iter().filter(|o| !o.passed()).count(),
+                outcomes.iter().map(|o| o.duration.as_secs_f64()).sum::<f64>()
+            ));
+            for outcome in outcomes {
+                xml.push_str(&format!(
+                    "    <testcase name=\"{}\" classname=\"{}\" file=\"{}\" line=\"{}\" time=\"{:.3}\">\n",
+                    escape_xml(&outcome.case.name),
+                    suite_name,
+                    suite_name,
+                    outcome.case.line,
+                    outcome.duration.as_secs_f64()
+                ));
+                if let Some(message) = failure_message(outcome) {
+                    let kind = if outcome.status == TestStatus::TimedOut { "Timeout" } else { "AssertionError" };
+                    xml.push_str(&format!(
+                        "      <failure message=\"{}\" type=\"{}\">{}\n  at {}</failure>\n",
+                        escape_xml(message.lines().next().unwrap_or("")),
+                        kind,
+                        escape_xml(&message),
+                        escape_xml(&location(outcome))
+                    ));
+                }
+                if !outcome.stdout.is_empty() {
+                    xml.push_str(&format!("      <system-out>{}</system-out>\n", escape_xml(&outcome.stdout)));
+                }
+                if !outcome.stderr.is_empty() {
+                    xml.push_str(&format!("      <system-err>{}</system-err>\n", escape_xml(&outcome.stderr)));
+                }
+                xml.push_str("    </testcase>\n");
+            }
+            xml.push_str("  </testsuite>\n");
+        }
+
+        // Files that could not be loaded are reported as errored suites
+        for error in &report.load_errors {
+            let name = escape_xml(&error.file.display().to_string());
+            xml.push_str(&format!("  <testsuite name=\"{}\" tests=\"1\" failures=\"0\" errors=\"1\" time=\"0\">\n", name));
+            xml.push_str(&format!("    <testcase name=\"load\" classname=\"{}\" file=\"{}\" time=\"0\">\n", name, name));
+            xml.push_str(&format!(
+                "      <error message=\"{}\" type=\"LoadError\">{}</error>\n",
+                escape_xml(error.message.lines().next().unwrap_or("")),
+                escape_xml(&error.message)
+            ));
+            xml.push_str("    </testcase>\n  </testsuite>\n");
+        }
+
+        xml.push_str("</testsuites>\n");
+        let _ = self.out.write_all(xml.as_bytes());
+        let _ = self.out.flush();
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than tab and newlines are not valid XML 1.0
+            c if (c as u32) < 0x20 && c != '\t' && c != '\n' && c != '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,1341 @@
+// Rust source emission for the transpiler
+//
+// A second walk over the AST that prints Rust with the inferred types. Every
+// expression becomes a `Code`: its text, how tightly it binds, and whether it
+// is an owned value, a borrow, or a place that must be cloned before it is
+// moved. Callers convert between these at the point of use: `.clone()` or
+// `.to_string()` where a value is stored, `&x` where a function borrows.
+
+use super::types::{self, Pass, Ty, Types, VarKind};
+use crate::ast::{
+    BinaryOp, BinaryOperator, CallArgument, EnumStatement, Expression, FunctionStatement, IfExpression,
+    InterpolatedPart, Literal, MatchExpression, Pattern, Program, Statement, StructStatement, UnaryOperator,
+};
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Prec {
+    Lowest,
+    Or,
+    And,
+    Compare,
+    Add,
+    Mul,
+    Cast,
+    Unary,
+    Postfix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Holding {
+    Owned,
+    /// A borrowed slice form: `&str`, `&[T]` or `&Struct`
+    Shared,
+    /// A `ref` binding to an owned value: `&String` or `&Vec<T>`
+    Ref,
+    /// `&mut Vec<T>`
+    Unique,
+}
+
+struct Code {
+    text: String,
+    prec: Prec,
+    holding: Holding,
+    /// A variable, field or element rather than a temporary
+    place: bool,
+}
+
+impl Code {
+    fn value(text: impl Into<String>, prec: Prec) -> Self {
+        Code { text: text.into(), prec, holding: Holding::Owned, place: false }
+    }
+
+    fn borrowed(text: impl Into<String>) -> Self {
+        Code { text: text.into(), prec: Prec::Postfix, holding: Holding::Shared, place: false }
+    }
+
+    fn at(&self, prec: Prec) -> String {
+        if self.prec < prec {
+            format!("({})", self.text)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+enum Part {
+    Text(String),
+    Arg(String, &'static str),
+}
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn", "else",
+    "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "macro",
+    "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static", "struct", "trait",
+    "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+pub fn ident(name: &str) -> String {
+    if matches!(name, "self" | "Self" | "super" | "crate") {
+        format!("{}_", name)
+    } else if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn path(name: &str) -> String {
+    name.split("::").map(ident).collect::<Vec<_>>().join("::")
+}
+
+fn float_text(value: f64) -> String {
+    format!("{:?}", value)
+}
+
+fn is_catch_all(pattern: &Pattern) -> bool {
+    matches!(pattern, Pattern::Wildcard | Pattern::Identifier(_))
+}
+
+/// Whether an Int expression has a type Rust can see, rather than defaulting to `i32`
+fn anchored(expression: &Expression) -> bool {
+    match expression {
+        Expression::Literal(_) => false,
+        Expression::BinaryOp(op) => anchored(&op.left) || anchored(&op.right),
+        Expression::UnaryOp(op) => anchored(&op.operand),
+        _ => true,
+    }
+}
+
+fn real_statements(statements: &[Statement]) -> Vec<&Statement> {
+    statements.iter().filter(|statement| !matches!(statement, Statement::SourceLine(_))).collect()
+}
+
+/// Whether control never reaches the end of a statement
+fn diverges(statement: &Statement) -> bool {
+    match statement {
+        Statement::Return(_) => true,
+        Statement::Expression(expression) => expression_diverges(expression),
+        _ => false,
+    }
+}
+
+fn expression_diverges(expression: &Expression) -> bool {
+    match expression {
+        Expression::If(IfExpression { then_branch, else_branch: Some(else_branch), .. }) => {
+            expression_diverges(then_branch) && expression_diverges(else_branch)
+        }
+        Expression::Loop(_) => true,
+        Expression::While(w) => matches!(*w.condition, Expression::Literal(Literal::Bool(true))),
+        Expression::FunctionCall(callee, _) => matches!(&**callee, Expression::Identifier(name) if name == "exit"),
+        Expression::Block(statements) => real_statements(statements).last().is_some_and(|last| diverges(last)),
+        _ => false,
+    }
+}
+
+pub fn emit(program: &Program, types: &Types, library: bool) -> Result<String, String> {
+    let mut emitter = Emitter {
+        types,
+        library,
+        out: String::new(),
+        indent: 0,
+        function: None,
+        ret: Ty::Unit,
+        rename_main: false,
+        helpers: BTreeSet::new(),
+        bindings: HashMap::new(),
+        suffix: false,
+    };
+    emitter.program(program)?;
+    Ok(emitter.out)
+}
+
+struct Emitter<'a> {
+    types: &'a Types,
+    library: bool,
+    out: String,
+    indent: usize,
+    function: Option<String>,
+    ret: Ty,
+    /// The program's `main` becomes `neksis_main` when the Rust `main` has other work to do
+    rename_main: bool,
+    helpers: BTreeSet<&'static str>,
+    /// How match bindings hold their value, decided by their pattern
+    bindings: HashMap<usize, Holding>,
+    /// Give the next numeric literal an explicit type suffix
+    suffix: bool,
+}
+
+impl<'a> Emitter<'a> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn error(&self, message: String) -> String {
+        match &self.function {
+            Some(name) => format!("function '{}' {}", name, message),
+            None => format!("the top level {}", message),
+        }
+    }
+
+    fn unsupported(&self, what: &str) -> String {
+        self.error(format!("uses {}, which the Rust target does not support", what))
+    }
+
+    fn vis(&self) -> &'static str {
+        if self.library {
+            "pub "
+        } else {
+            ""
+        }
+    }
+
+    fn function_name(&self, name: &str) -> String {
+        if self.rename_main && name == "main" {
+            "neksis_main".to_string()
+        } else {
+            ident(name)
+        }
+    }
+
+    fn of(&self, expression: &Expression) -> Result<Ty, String> {
+        let ty = self.types.of(expression);
+        if contains_var(&ty) {
+            return Err(self.error("has an expression whose type the transpiler cannot infer".to_string()));
+        }
+        Ok(ty)
+    }
+
+    fn holding(&self, id: usize) -> Holding {
+        let var = self.types.var(id);
+        match var.kind {
+            VarKind::Local => Holding::Owned,
+            VarKind::Param => match self.types.pass(id) {
+                Pass::Value => Holding::Owned,
+                Pass::Ref => Holding::Shared,
+                Pass::RefMut => Holding::Unique,
+            },
+            VarKind::Binding => self.bindings.get(&id).copied().unwrap_or(Holding::Owned),
+        }
+    }
+
+    fn program(&mut self, program: &Program) -> Result<(), String> {
+        let top: Vec<&Statement> = program
+            .statements
+            .iter()
+            .filter(|statement| {
+                !matches!(
+                    statement,
+                    Statement::Function(_) | Statement::Struct(_) | Statement::Enum(_) | Statement::SourceLine(_)
+                )
+            })
+            .collect();
+        let main = self.types.functions.get("main");
+        if self.library && !top.is_empty() {
+            return Err("the top level has statements, which a library cannot run; move them into a function".to_string());
+        }
+        if let Some(main) = main.filter(|_| !self.library) {
+            if !main.params.is_empty() {
+                self.function = Some("main".to_string());
+                return Err(self.unsupported("parameters on `main`"));
+            }
+            self.rename_main = !top.is_empty() || self.types.resolve(&main.ret) != Ty::Unit;
+        }
+
+        let mut first = true;
+        for statement in &program.statements {
+            if !matches!(statement, Statement::Function(_) | Statement::Struct(_) | Statement::Enum(_)) {
+                continue;
+            }
+            if let Statement::Function(function) = statement {
+                if self.types.functions[&function.name].definition != types::key(function) {
+                    continue;
+                }
+            }
+            if !first {
+                self.out.push('\n');
+            }
+            first = false;
+            match statement {
+                Statement::Struct(item) => self.struct_item(item),
+                Statement::Enum(item) => self.enum_item(item),
+                Statement::Function(function) => self.function_item(function)?,
+                _ => {}
+            }
+        }
+
+        if !self.library && (main.is_none() || self.rename_main) {
+            if !first {
+                self.out.push('\n');
+            }
+            self.function = None;
+            self.ret = Ty::Unit;
+            if top.is_empty() && !self.rename_main {
+                self.line("fn main() {}");
+                return self.write_helpers();
+            }
+            self.line("fn main() {");
+            self.indent += 1;
+            for statement in top {
+                self.statement(statement)?;
+            }
+            if self.rename_main {
+                self.line("neksis_main();");
+            }
+            self.indent -= 1;
+            self.line("}");
+        }
+        self.write_helpers()
+    }
+
+    fn write_helpers(&mut self) -> Result<(), String> {
+        for helper in std::mem::take(&mut self.helpers) {
+            self.out.push('\n');
+            self.out.push_str(match helper {
+                "format_list" => {
+                    "fn format_list<T: std::fmt::Display>(items: &[T]) -> String {\n    \
+                     let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();\n    \
+                     format!(\"[{}]\", items.join(\", \"))\n}\n"
+                }
+                "read_line" => {
+                    "fn read_line() -> String {\n    \
+                     let mut line = String::new();\n    \
+                     std::io::stdin().read_line(&mut line).expect(\"failed to read from stdin\");\n    \
+                     line.trim_end_matches(['\\r', '\\n']).to_string()\n}\n"
+                }
+                _ => {
+                    "fn append_file(path: &str, content: &str) {\n    \
+                     use std::io::Write;\n    \
+                     let mut file = std::fs::OpenOptions::new()\n        \
+                     .create(true)\n        \
+                     .append(true)\n        \
+                     .open(path)\n        \
+                     .expect(\"failed to open file\");\n    \
+                     file.write_all(content.as_bytes()).expect(\"failed to write file\");\n}\n"
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn struct_item(&mut self, item: &StructStatement) {
+        let vis = self.vis();
+        self.line("#[derive(Debug, Clone, PartialEq)]");
+        self.line(&format!("{}struct {} {{", vis, ident(&item.name)));
+        for (name, ty) in &self.types.structs[&item.name] {
+            let ty = self.types.resolve(ty);
+            self.line(&format!("    {}{}: {},", vis, ident(name), ty.rust()));
+        }
+        self.line("}");
+    }
+
+    fn enum_item(&mut self, item: &EnumStatement) {
+        self.line("#[derive(Debug, Clone, PartialEq)]");
+        self.line(&format!("{}enum {} {{", self.vis(), ident(&item.name)));
+        for (name, payload) in &self.types.enums[&item.name] {
+            if payload.is_empty() {
+                self.line(&format!("    {},", ident(name)));
+            } else {
+                let payload: Vec<String> = payload.iter().map(|ty| self.types.resolve(ty).rust()).collect();
+                self.line(&format!("    {}({}),", ident(name), payload.join(", ")));
+            }
+        }
+        self.line("}");
+    }
+
+    fn function_item(&mut self, function: &FunctionStatement) -> Result<(), String> {
+        let signature = &self.types.functions[&function.name];
+        self.function = Some(function.name.clone());
+        self.ret = self.types.resolve(&signature.ret);
+        let mut params = Vec::new();
+        for &id in &signature.params {
+            let var = self.types.var(id);
+            let ty = self.types.var_ty(id);
+            let name = ident(&var.name);
+            params.push(match self.types.pass(id) {
+                Pass::Value if var.reassigned => format!("mut {}: {}", name, ty.rust()),
+                Pass::Value => format!("{}: {}", name, ty.rust()),
+                Pass::Ref => match &ty {
+                    Ty::Str => format!("{}: &str", name),
+                    Ty::Vec(element) => format!("{}: &[{}]", name, element.rust()),
+                    other => format!("{}: &{}", name, other.rust()),
+                },
+                Pass::RefMut => format!("{}: &mut {}", name, ty.rust()),
+            });
+        }
+        let ret = if self.ret == Ty::Unit { String::new() } else { format!(" -> {}", self.ret.rust()) };
+        let name = self.function_name(&function.name);
+        self.line(&format!("{}fn {}({}){} {{", self.vis(), name, params.join(", "), ret));
+        self.indent += 1;
+        let statements = match &*function.body {
+            Expression::Block(statements) => real_statements(statements),
+            _ => Vec::new(),
+        };
+        self.body(&statements)?;
+        self.indent -= 1;
+        self.line("}");
+        Ok(())
+    }
+
+    /// A function body, whose final `return` becomes its tail expression
+    fn body(&mut self, statements: &[&Statement]) -> Result<(), String> {
+        let Some((last, init)) = statements.split_last() else {
+            if self.ret != Ty::Unit {
+                return Err(self.error("can reach its end without returning a value".to_string()));
+            }
+            return Ok(());
+        };
+        for statement in init {
+            self.statement(statement)?;
+        }
+        match last {
+            Statement::Return(ret) => match &ret.value {
+                Some(value) if self.ret != Ty::Unit => {
+                    let ret = self.ret.clone();
+                    let code = self.returned(value, &ret)?;
+                    self.line(&code.text);
+                }
+                Some(value) => self.expression_statement(value)?,
+                None => {}
+            },
+            other => {
+                self.statement(other)?;
+                if self.ret != Ty::Unit && !diverges(other) {
+                    return Err(self.error("can reach its end without returning a value".to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, body: &Expression) -> Result<(), String> {
+        self.indent += 1;
+        let result = match body {
+            Expression::Block(statements) => {
+                real_statements(statements).into_iter().try_for_each(|statement| self.statement(statement))
+            }
+            other => self.expression_statement(other),
+        };
+        self.indent -= 1;
+        result
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::SourceLine(_) => Ok(()),
+            Statement::Let(let_statement) => {
+                let id = self.types.site(let_statement);
+                let var = self.types.var(id);
+                let ty = self.types.var_ty(id);
+                let mutable = if var.reassigned || var.mutated { "mut " } else { "" };
+                let annotation = match &*let_statement.value {
+                    Expression::Literal(Literal::Array(items)) if items.is_empty() => format!(": {}", ty.rust()),
+                    value if ty == Ty::Int && !anchored(value) => ": i64".to_string(),
+                    _ => String::new(),
+                };
+                let code = self.owned(&let_statement.value, &ty)?;
+                let text = format!("let {}{}{} = {};", mutable, ident(&let_statement.name), annotation, code.text);
+                self.line(&text);
+                Ok(())
+            }
+            Statement::AssignmentStatement { name, value } => {
+                let id = self.types.site(statement);
+                if types::in_place_update(name, value).is_some() {
+                    return self.expression_statement(value);
+                }
+                let ty = self.types.var_ty(id);
+                let text = match self.compound(name, &ty, value)? {
+                    Some(text) => text,
+                    None => format!("{} = {};", ident(name), self.owned(value, &ty)?.text),
+                };
+                self.line(&text);
+                Ok(())
+            }
+            Statement::Expression(expression) => self.expression_statement(expression),
+            Statement::Return(ret) => {
+                match &ret.value {
+                    Some(value) if self.ret != Ty::Unit => {
+                        let ret = self.ret.clone();
+                        let code = self.returned(value, &ret)?;
+                        self.line(&format!("return {};", code.text));
+                    }
+                    Some(value) => {
+                        self.expression_statement(value)?;
+                        self.line("return;");
+                    }
+                    None => self.line("return;"),
+                }
+                Ok(())
+            }
+            _ => Err(self.unsupported("this statement")),
+        }
+    }
+
+    /// `x = x + y` as `x += y`
+    fn compound(&mut self, name: &str, ty: &Ty, value: &Expression) -> Result<Option<String>, String> {
+        let Expression::BinaryOp(BinaryOp { left, operator, right }) = value else {
+            return Ok(None);
+        };
+        if !matches!(&**left, Expression::Identifier(left) if left == name) || self.of(value)? != *ty {
+            return Ok(None);
+        }
+        let symbol = match types::normalize(operator) {
+            Some(BinaryOperator::Add) => "+=",
+            Some(BinaryOperator::Subtract) => "-=",
+            Some(BinaryOperator::Multiply) => "*=",
+            Some(BinaryOperator::Divide) => "/=",
+            Some(BinaryOperator::Modulo) => "%=",
+            _ => return Ok(None),
+        };
+        let right_ty = self.of(right)?;
+        let text = match ty {
+            Ty::Int | Ty::Float => self.number(right, ty)?.text,
+            Ty::Str if right_ty == Ty::Str => self.borrowed(right)?,
+            Ty::Str => format!("&{}.to_string()", self.expr(right)?.at(Prec::Postfix)),
+            _ => return Ok(None),
+        };
+        Ok(Some(format!("{} {} {};", ident(name), symbol, text)))
+    }
+
+    fn expression_statement(&mut self, expression: &Expression) -> Result<(), String> {
+        match expression {
+            Expression::If(if_expression) => self.if_chain(if_expression, ""),
+            Expression::While(while_expression) => {
+                if matches!(*while_expression.condition, Expression::Literal(Literal::Bool(true))) {
+                    self.line("loop {");
+                } else {
+                    let condition = self.condition(&while_expression.condition)?;
+                    self.line(&format!("while {} {{", condition.text));
+                }
+                self.block(&while_expression.body)?;
+                self.line("}");
+                Ok(())
+            }
+            Expression::Loop(loop_expression) => {
+                self.line("loop {");
+                self.block(&loop_expression.body)?;
+                self.line("}");
+                Ok(())
+            }
+            Expression::Block(_) => {
+                self.line("{");
+                self.block(expression)?;
+                self.line("}");
+                Ok(())
+            }
+            Expression::Match(matched) => self.match_statement(matched),
+            Expression::FunctionCall(callee, args) => {
+                let code = self.call(callee, args, true)?;
+                self.line(&format!("{};", code.text));
+                Ok(())
+            }
+            other => {
+                let code = self.expr(other)?;
+                self.line(&format!("{};", code.text));
+                Ok(())
+            }
+        }
+    }
+
+    fn if_chain(&mut self, if_expression: &IfExpression, prefix: &str) -> Result<(), String> {
+        let condition = self.condition(&if_expression.condition)?;
+        self.line(&format!("{}if {} {{", prefix, condition.text));
+        self.block(&if_expression.then_branch)?;
+        match if_expression.else_branch.as_deref() {
+            Some(Expression::If(inner)) => return self.if_chain(inner, "} else "),
+            Some(other) => {
+                self.line("} else {");
+                self.block(other)?;
+            }
+            None => {}
+        }
+        self.line("}");
+        Ok(())
+    }
+
+    /// A condition, with neksis truthiness spelled out for non-Bool values
+    fn condition(&mut self, expression: &Expression) -> Result<Code, String> {
+        let ty = self.of(expression)?;
+        let code = self.expr(expression)?;
+        Ok(match ty {
+            Ty::Bool => code,
+            Ty::Int => Code::value(format!("{} != 0", code.at(Prec::Add)), Prec::Compare),
+            Ty::Float => Code::value(format!("{} != 0.0", code.at(Prec::Add)), Prec::Compare),
+            Ty::Str => Code::value(format!("!{}.is_empty()", code.at(Prec::Postfix)), Prec::Unary),
+            Ty::Unit => Code::value("false", Prec::Postfix),
+            other => return Err(self.unsupported(&format!("a {} as a condition", other))),
+        })
+    }
+
+    fn subject(&mut self, expression: &Expression) -> Result<String, String> {
+        let ty = self.of(expression)?;
+        let code = self.expr(expression)?;
+        Ok(match (&ty, code.holding) {
+            (Ty::Str, Holding::Shared) => code.text,
+            (Ty::Str, _) => format!("{}.as_str()", code.at(Prec::Postfix)),
+            (Ty::Named(_), Holding::Shared | Holding::Ref | Holding::Unique) => format!("*{}", code.at(Prec::Unary)),
+            _ => code.text,
+        })
+    }
+
+    fn exhaustive(&self, matched: &MatchExpression, subject: &Ty) -> bool {
+        if matched.arms.iter().any(|arm| is_catch_all(&arm.pattern)) {
+            return true;
+        }
+        match subject {
+            Ty::Named(name) => self.types.enums.get(name).is_some_and(|variants| {
+                variants.iter().all(|(variant, _)| {
+                    matched.arms.iter().any(|arm| match &arm.pattern {
+                        Pattern::Struct(path, fields) => {
+                            path.rsplit_once("::").is_some_and(|(_, name)| name == variant)
+                                && fields.iter().all(is_catch_all)
+                        }
+                        _ => false,
+                    })
+                })
+            }),
+            Ty::Bool => [true, false].iter().all(|value| {
+                matched.arms.iter().any(|arm| arm.pattern == Pattern::Literal(Literal::Bool(*value)))
+            }),
+            _ => false,
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, top_str: bool) -> Result<String, String> {
+        Ok(match pattern {
+            Pattern::Wildcard => "_".to_string(),
+            Pattern::Identifier(name) => {
+                let id = self.types.site(pattern);
+                if self.types.var_ty(id).is_copy() {
+                    self.bindings.insert(id, Holding::Owned);
+                    ident(name)
+                } else if top_str {
+                    self.bindings.insert(id, Holding::Shared);
+                    ident(name)
+                } else {
+                    self.bindings.insert(id, Holding::Ref);
+                    format!("ref {}", ident(name))
+                }
+            }
+            Pattern::Literal(Literal::Int(value)) => value.to_string(),
+            Pattern::Literal(Literal::Bool(value)) => value.to_string(),
+            Pattern::Literal(Literal::String(value)) => format!("{:?}", value),
+            Pattern::Literal(Literal::Char(value)) => format!("{:?}", value),
+            Pattern::Struct(name, fields) if fields.is_empty() => path(name),
+            Pattern::Struct(name, fields) => {
+                let mut parts = Vec::new();
+                for field in fields {
+                    parts.push(self.pattern(field, false)?);
+                }
+                format!("{}({})", path(name), parts.join(", "))
+            }
+            _ => return Err(self.unsupported("this pattern")),
+        })
+    }
+
+    fn match_statement(&mut self, matched: &MatchExpression) -> Result<(), String> {
+        let subject_ty = self.of(&matched.expression)?;
+        let subject = self.subject(&matched.expression)?;
+        self.line(&format!("match {} {{", subject));
+        self.indent += 1;
+        for arm in &matched.arms {
+            let pattern = self.pattern(&arm.pattern, subject_ty == Ty::Str)?;
+            match &*arm.body {
+                body @ (Expression::Block(_)
+                | Expression::If(_)
+                | Expression::While(_)
+                | Expression::Loop(_)
+                | Expression::Match(_)) => {
+                    self.line(&format!("{} => {{", pattern));
+                    match body {
+                        Expression::Block(_) => self.block(body)?,
+                        other => {
+                            self.indent += 1;
+                            self.expression_statement(other)?;
+                            self.indent -= 1;
+                        }
+                    }
+                    self.line("}");
+                }
+                Expression::FunctionCall(callee, args) => {
+                    let code = self.call(callee, args, true)?;
+                    if self.of(&arm.body)? == Ty::Unit || self.mutates_in_place(callee) {
+                        self.line(&format!("{} => {},", pattern, code.text));
+                    } else {
+                        self.line(&format!("{} => {{ {}; }}", pattern, code.text));
+                    }
+                }
+                body => {
+                    let code = self.expr(body)?;
+                    self.line(&format!("{} => {{ {}; }}", pattern, code.text));
+                }
+            }
+        }
+        if !self.exhaustive(matched, &subject_ty) {
+            self.line("_ => {}");
+        }
+        self.indent -= 1;
+        self.line("}");
+        Ok(())
+    }
+
+    fn match_value(&mut self, expression: &Expression, matched: &MatchExpression) -> Result<Code, String> {
+        let result = self.of(expression)?;
+        let subject_ty = self.of(&matched.expression)?;
+        if !self.exhaustive(matched, &subject_ty) {
+            return Err(self.error("uses a match without a `_` arm as a value".to_string()));
+        }
+        let subject = self.subject(&matched.expression)?;
+        let inner = "    ".repeat(self.indent + 1);
+        let mut text = format!("match {} {{\n", subject);
+        self.indent += 1;
+        for arm in &matched.arms {
+            let pattern = self.pattern(&arm.pattern, subject_ty == Ty::Str)?;
+            if matches!(*arm.body, Expression::Block(_)) {
+                self.indent -= 1;
+                return Err(self.unsupported("block arms in a match that produces a value"));
+            }
+            let code = self.owned(&arm.body, &result)?;
+            text.push_str(&format!("{}{} => {},\n", inner, pattern, code.text));
+        }
+        self.indent -= 1;
+        text.push_str(&"    ".repeat(self.indent));
+        text.push('}');
+        Ok(Code::value(text, Prec::Lowest))
+    }
+
+    fn mutates_in_place(&self, callee: &Expression) -> bool {
+        matches!(callee, Expression::Identifier(name)
+            if matches!(name.as_str(), "array_push" | "array_reverse" | "array_sort")
+                && !self.types.functions.contains_key(name))
+    }
+
+    fn expr(&mut self, expression: &Expression) -> Result<Code, String> {
+        Ok(match expression {
+            Expression::Literal(literal) => self.literal(expression, literal)?,
+            Expression::Identifier(name) => {
+                let id = self.types.resolved(expression).ok_or_else(|| self.error(format!("uses `{}` oddly", name)))?;
+                Code { text: ident(name), prec: Prec::Postfix, holding: self.holding(id), place: true }
+            }
+            Expression::BinaryOp(op) => self.binary(expression, op)?,
+            Expression::UnaryOp(op) => match op.operator {
+                UnaryOperator::Negate => {
+                    let operand = self.expr(&op.operand)?;
+                    Code::value(format!("-{}", operand.at(Prec::Unary)), Prec::Unary)
+                }
+                _ => {
+                    let operand = self.condition(&op.operand)?;
+                    Code::value(format!("!{}", operand.at(Prec::Unary)), Prec::Unary)
+                }
+            },
+            Expression::FunctionCall(callee, args) => self.call(callee, args, false)?,
+            Expression::Match(matched) => self.match_value(expression, matched)?,
+            Expression::StructLiteral(literal) => {
+                let fields = self.types.structs[&literal.struct_name].clone();
+                let mut parts = Vec::new();
+                for (name, value) in &literal.fields {
+                    let ty = fields.iter().find(|(field, _)| field == name).map(|(_, ty)| self.types.resolve(ty));
+                    let code = self.owned(value, &ty.unwrap_or(Ty::Unit))?;
+                    if code.text == ident(name) {
+                        parts.push(code.text);
+                    } else {
+                        parts.push(format!("{}: {}", ident(name), code.text));
+                    }
+                }
+                if parts.is_empty() {
+                    Code::value(format!("{} {{}}", ident(&literal.struct_name)), Prec::Postfix)
+                } else {
+                    Code::value(format!("{} {{ {} }}", ident(&literal.struct_name), parts.join(", ")), Prec::Postfix)
+                }
+            }
+            Expression::MemberAccess(access) => {
+                let object = self.expr(&access.object)?;
+                Code {
+                    text: format!("{}.{}", object.at(Prec::Postfix), ident(&access.member)),
+                    prec: Prec::Postfix,
+                    holding: Holding::Owned,
+                    place: true,
+                }
+            }
+            Expression::EnumVariantAccess { enum_name, variant_name } => {
+                Code::value(format!("{}::{}", ident(enum_name), ident(variant_name)), Prec::Postfix)
+            }
+            Expression::ArrayAccess(access) => {
+                let array = self.expr(&access.array)?;
+                let index = self.index(&access.index)?;
+                Code {
+                    text: format!("{}[{}]", array.at(Prec::Postfix), index),
+                    prec: Prec::Postfix,
+                    holding: Holding::Owned,
+                    place: true,
+                }
+            }
+            Expression::InterpolatedString(_) => {
+                let mut parts = Vec::new();
+                self.parts(expression, &mut parts)?;
+                self.format_code(parts)
+            }
+            Expression::If(_) | Expression::While(_) | Expression::Loop(_) | Expression::Block(_) => {
+                return Err(self.unsupported("`if` and loops as values"));
+            }
+            other => return Err(self.unsupported(types::describe(other))),
+        })
+    }
+
+    fn literal(&mut self, expression: &Expression, literal: &Literal) -> Result<Code, String> {
+        let suffix = std::mem::take(&mut self.suffix);
+        Ok(match literal {
+            Literal::Int(value) => {
+                Code::value(format!("{}{}", value, if suffix { "_i64" } else { "" }), Prec::Postfix)
+            }
+            Literal::Float(value) => {
+                Code::value(format!("{}{}", float_text(*value), if suffix { "_f64" } else { "" }), Prec::Postfix)
+            }
+            Literal::Bool(value) => Code::value(value.to_string(), Prec::Postfix),
+            Literal::Char(value) => Code::value(format!("{:?}", value), Prec::Postfix),
+            Literal::String(value) => Code::borrowed(format!("{:?}", value)),
+            Literal::Null => Code::value("()", Prec::Postfix),
+            Literal::Array(items) => {
+                let Ty::Vec(element) = self.of(expression)? else {
+                    return Err(self.error("has an array literal of an unexpected type".to_string()));
+                };
+                if items.is_empty() {
+                    return Ok(Code::value("Vec::new()", Prec::Postfix));
+                }
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|item| match (item, &*element) {
+                        (Literal::Int(value), Ty::Float) => float_text(*value as f64),
+                        (Literal::Int(value), _) => value.to_string(),
+                        (Literal::Float(value), _) => float_text(*value),
+                        (Literal::String(value), _) => format!("{:?}.to_string()", value),
+                        (Literal::Char(value), _) => format!("{:?}", value),
+                        (Literal::Bool(value), _) => value.to_string(),
+                        _ => "()".to_string(),
+                    })
+                    .collect();
+                Code::value(format!("vec![{}]", items.join(", ")), Prec::Postfix)
+            }
+        })
+    }
+
+    fn index(&mut self, index: &Expression) -> Result<String, String> {
+        match index {
+            Expression::Literal(Literal::Int(value)) if *value >= 0 => Ok(value.to_string()),
+            other => Ok(format!("{} as usize", self.expr(other)?.at(Prec::Cast))),
+        }
+    }
+
+    /// A number, converted to Float when `target` is Float
+    fn number(&mut self, expression: &Expression, target: &Ty) -> Result<Code, String> {
+        let ty = self.of(expression)?;
+        if *target == Ty::Float && ty == Ty::Int {
+            if let Expression::Literal(Literal::Int(value)) = expression {
+                self.suffix = false;
+                return Ok(Code::value(float_text(*value as f64), Prec::Postfix));
+            }
+            let code = self.expr(expression)?;
+            return Ok(Code::value(format!("{} as f64", code.at(Prec::Cast)), Prec::Cast));
+        }
+        self.expr(expression)
+    }
+
+    /// A method receiver, whose numeric literals need an explicit type
+    fn receiver(&mut self, expression: &Expression, target: &Ty) -> Result<String, String> {
+        let ty = self.of(expression)?;
+        if ty.is_number() && !anchored(expression) {
+            self.suffix = true;
+        }
+        let code = self.number(expression, target)?;
+        self.suffix = false;
+        if matches!(expression, Expression::Literal(Literal::Int(_))) && *target == Ty::Float {
+            return Ok(format!("{}_f64", code.text));
+        }
+        Ok(code.at(Prec::Postfix))
+    }
+
+    fn binary(&mut self, expression: &Expression, op: &BinaryOp) -> Result<Code, String> {
+        let operator = types::normalize(&op.operator).ok_or_else(|| self.unsupported("compound assignment operators"))?;
+        let ty = self.of(expression)?;
+        let (symbol, prec) = match operator {
+            BinaryOperator::And => {
+                let left = self.condition(&op.left)?;
+                let right = self.condition(&op.right)?;
+                return Ok(Code::value(format!("{} && {}", left.at(Prec::And), right.at(Prec::Compare)), Prec::And));
+            }
+            BinaryOperator::Or => {
+                let left = self.condition(&op.left)?;
+                let right = self.condition(&op.right)?;
+                return Ok(Code::value(format!("{} || {}", left.at(Prec::Or), right.at(Prec::And)), Prec::Or));
+            }
+            BinaryOperator::Equal => return self.compare(&op.left, &op.right, "=="),
+            BinaryOperator::NotEqual => return self.compare(&op.left, &op.right, "!="),
+            BinaryOperator::LessThan => return self.compare(&op.left, &op.right, "<"),
+            BinaryOperator::LessThanOrEqual => return self.compare(&op.left, &op.right, "<="),
+            BinaryOperator::GreaterThan => return self.compare(&op.left, &op.right, ">"),
+            BinaryOperator::GreaterThanOrEqual => return self.compare(&op.left, &op.right, ">="),
+            BinaryOperator::Add if ty == Ty::Str => {
+                let mut parts = Vec::new();
+                self.parts(expression, &mut parts)?;
+                return Ok(self.format_code(parts));
+            }
+            BinaryOperator::Add => ("+", Prec::Add),
+            BinaryOperator::Subtract => ("-", Prec::Add),
+            BinaryOperator::Multiply => ("*", Prec::Mul),
+            BinaryOperator::Divide => ("/", Prec::Mul),
+            _ => ("%", Prec::Mul),
+        };
+        if ty == Ty::Int && !anchored(expression) {
+            self.suffix = true;
+        }
+        let left = self.number(&op.left, &ty)?;
+        let right = self.number(&op.right, &ty)?;
+        let right_prec = if prec == Prec::Add { Prec::Mul } else { Prec::Cast };
+        Ok(Code::value(format!("{} {} {}", left.at(prec), symbol, right.at(right_prec)), prec))
+    }
+
+    /// Both sides of a comparison, made comparable
+    fn operands(&mut self, left: &Expression, right: &Expression) -> Result<(Code, Code), String> {
+        let (left_ty, right_ty) = (self.of(left)?, self.of(right)?);
+        if left_ty.is_number() && right_ty.is_number() {
+            let target = if left_ty == Ty::Float || right_ty == Ty::Float { Ty::Float } else { Ty::Int };
+            return Ok((self.number(left, &target)?, self.number(right, &target)?));
+        }
+        let (l, r) = (self.expr(left)?, self.expr(right)?);
+        if left_ty.is_copy() || (l.holding == Holding::Owned && r.holding == Holding::Owned) {
+            return Ok((l, r));
+        }
+        if left_ty == Ty::Str {
+            // Compare as `&str` on both sides
+            let as_str = |code: Code| match code.holding {
+                Holding::Shared => code,
+                _ => Code::value(format!("{}.as_str()", code.at(Prec::Postfix)), Prec::Postfix),
+            };
+            return Ok((as_str(l), as_str(r)));
+        }
+        let deref = |code: Code, other: &Code| {
+            if code.holding != Holding::Owned && other.holding == Holding::Owned {
+                Code::value(format!("*{}", code.at(Prec::Unary)), Prec::Unary)
+            } else {
+                code
+            }
+        };
+        let left_code = deref(l, &r);
+        let right_code = deref(r, &left_code);
+        Ok((left_code, right_code))
+    }
+
+    fn compare(&mut self, left: &Expression, right: &Expression, symbol: &str) -> Result<Code, String> {
+        let (l, r) = self.operands(left, right)?;
+        // `x as f64 < y` would start a generic argument list
+        let side = |code: &Code| if code.prec == Prec::Cast { format!("({})", code.text) } else { code.at(Prec::Add) };
+        Ok(Code::value(format!("{} {} {}", side(&l), symbol, side(&r)), Prec::Compare))
+    }
+
+    /// The pieces of a string built with `+`, `concat` or interpolation
+    fn parts(&mut self, expression: &Expression, out: &mut Vec<Part>) -> Result<(), String> {
+        match expression {
+            Expression::BinaryOp(op)
+                if types::normalize(&op.operator) == Some(BinaryOperator::Add) && self.of(expression)? == Ty::Str =>
+            {
+                self.parts(&op.left, out)?;
+                self.parts(&op.right, out)?;
+            }
+            Expression::FunctionCall(callee, args)
+                if matches!(&**callee, Expression::Identifier(name) if name == "concat")
+                    && !self.types.functions.contains_key("concat") =>
+            {
+                for arg in args {
+                    self.parts(&arg.value, out)?;
+                }
+            }
+            Expression::InterpolatedString(interpolated) => {
+                for part in &interpolated.parts {
+                    match part {
+                        InterpolatedPart::String(text) => out.push(Part::Text(text.clone())),
+                        InterpolatedPart::Expr(expression) => self.parts(expression, out)?,
+                    }
+                }
+            }
+            Expression::Literal(Literal::String(text)) => out.push(Part::Text(text.clone())),
+            Expression::Literal(Literal::Int(value)) => out.push(Part::Text(value.to_string())),
+            Expression::Literal(Literal::Float(value)) => out.push(Part::Text(value.to_string())),
+            Expression::Literal(Literal::Bool(value)) => out.push(Part::Text(value.to_string())),
+            Expression::Literal(Literal::Char(value)) => out.push(Part::Text(value.to_string())),
+            Expression::Literal(Literal::Null) => out.push(Part::Text("null".to_string())),
+            other => {
+                let part = self.display(other)?;
+                out.push(part);
+            }
+        }
+        Ok(())
+    }
+
+    /// How a value prints: as the VM prints it for scalars and arrays, with `{:?}` for
+    /// structs and enums
+    fn display(&mut self, expression: &Expression) -> Result<Part, String> {
+        let ty = self.of(expression)?;
+        if ty == Ty::Unit {
+            return Ok(match expression {
+                Expression::FunctionCall(callee, args) => {
+                    let code = self.call(callee, args, true)?;
+                    Part::Arg(format!("{{ {}; \"null\" }}", code.text), "")
+                }
+                _ => Part::Text("null".to_string()),
+            });
+        }
+        let code = self.expr(expression)?;
+        Ok(match &ty {
+            Ty::Vec(element) if !matches!(**element, Ty::Named(_) | Ty::Unit) => Part::Arg(self.list(&code, element), ""),
+            Ty::Vec(_) | Ty::Named(_) => Part::Arg(code.text, ":?"),
+            _ => Part::Arg(code.text, ""),
+        })
+    }
+
+    fn list(&mut self, code: &Code, element: &Ty) -> String {
+        self.helpers.insert("format_list");
+        if let Ty::Vec(inner) = element {
+            let row = Code { text: "row".to_string(), prec: Prec::Postfix, holding: Holding::Ref, place: true };
+            let rows = self.list(&row, inner);
+            return format!(
+                "format_list(&{}.iter().map(|row| {}).collect::<Vec<_>>())",
+                code.at(Prec::Postfix),
+                rows
+            );
+        }
+        match code.holding {
+            Holding::Owned => format!("format_list(&{})", code.at(Prec::Unary)),
+            _ => format!("format_list({})", code.text),
+        }
+    }
+
+    fn format_args(&self, parts: Vec<Part>) -> String {
+        let mut template = String::new();
+        let mut args = Vec::new();
+        for part in parts {
+            match part {
+                Part::Text(text) => template.push_str(&text.replace('{', "{{").replace('}', "}}")),
+                Part::Arg(code, spec) => {
+                    let inline = code.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+                        && code.chars().all(|c| c.is_alphanumeric() || c == '_')
+                        && !matches!(code.as_str(), "true" | "false");
+                    if inline {
+                        template.push_str(&format!("{{{}{}}}", code, spec));
+                    } else {
+                        template.push_str(&format!("{{{}}}", spec));
+                        args.push(code);
+                    }
+                }
+            }
+        }
+        let mut text = format!("{:?}", template);
+        for arg in args {
+            text.push_str(", ");
+            text.push_str(&arg);
+        }
+        text
+    }
+
+    fn format_code(&self, parts: Vec<Part>) -> Code {
+        if parts.iter().all(|part| matches!(part, Part::Text(_))) {
+            let text: String = parts.into_iter().map(|part| if let Part::Text(text) = part { text } else { String::new() }).collect();
+            return Code::borrowed(format!("{:?}", text));
+        }
+        Code::value(format!("format!({})", self.format_args(parts)), Prec::Postfix)
+    }
+
+    /// A value to store: borrows become owned and places are cloned
+    fn owned(&mut self, expression: &Expression, target: &Ty) -> Result<Code, String> {
+        let ty = self.of(expression)?;
+        if ty.is_number() {
+            return self.number(expression, target);
+        }
+        let code = self.expr(expression)?;
+        Ok(self.own(code, &ty))
+    }
+
+    /// A returned value, where a local can move out instead of being cloned
+    fn returned(&mut self, expression: &Expression, target: &Ty) -> Result<Code, String> {
+        if let Some(id) = self.types.resolved(expression) {
+            if self.holding(id) == Holding::Owned && !self.types.var_ty(id).is_copy() {
+                return Ok(Code::value(ident(&self.types.var(id).name), Prec::Postfix));
+            }
+        }
+        self.owned(expression, target)
+    }
+
+    fn own(&self, code: Code, ty: &Ty) -> Code {
+        if ty.is_copy() {
+            return code;
+        }
+        match code.holding {
+            Holding::Owned if code.place => Code::value(format!("{}.clone()", code.at(Prec::Postfix)), Prec::Postfix),
+            Holding::Owned => code,
+            _ => {
+                let method = match ty {
+                    Ty::Str => "to_string",
+                    Ty::Vec(_) => "to_vec",
+                    _ => "clone",
+                };
+                Code::value(format!("{}.{}()", code.at(Prec::Postfix), method), Prec::Postfix)
+            }
+        }
+    }
+
+    /// An argument for a parameter passed by shared reference
+    fn borrowed(&mut self, expression: &Expression) -> Result<String, String> {
+        let code = self.expr(expression)?;
+        Ok(match code.holding {
+            Holding::Owned => format!("&{}", code.at(Prec::Unary)),
+            _ => code.text,
+        })
+    }
+
+    fn borrowed_mut(&mut self, expression: &Expression) -> Result<String, String> {
+        let code = self.expr(expression)?;
+        match code.holding {
+            Holding::Owned => Ok(format!("&mut {}", code.at(Prec::Unary))),
+            Holding::Unique => Ok(code.text),
+            _ => Err(self.error("changes a borrowed array in place".to_string())),
+        }
+    }
+
+    /// A `&str` for generic std APIs, which do not convert `&String` themselves
+    fn str_arg(&mut self, expression: &Expression) -> Result<String, String> {
+        let code = self.expr(expression)?;
+        Ok(if matches!(expression, Expression::Literal(_)) { code.text } else { format!("&{}[..]", code.at(Prec::Postfix)) })
+    }
+
+    fn call(&mut self, callee: &Expression, args: &[CallArgument], statement: bool) -> Result<Code, String> {
+        let Expression::Identifier(name) = callee else {
+            return Err(self.unsupported("calls through expressions"));
+        };
+        if let Some((enum_name, variant_name)) = name.rsplit_once("::") {
+            let payload = self.types.enums[enum_name]
+                .iter()
+                .find(|(variant, _)| variant == variant_name)
+                .map(|(_, payload)| payload.clone())
+                .unwrap_or_default();
+            let mut values = Vec::new();
+            for (arg, ty) in args.iter().zip(&payload) {
+                let ty = self.types.resolve(ty);
+                values.push(self.owned(&arg.value, &ty)?.text);
+            }
+            return Ok(Code::value(format!("{}({})", path(name), values.join(", ")), Prec::Postfix));
+        }
+        if let Some(signature) = self.types.functions.get(name) {
+            let mut values = Vec::new();
+            for (&param, arg) in signature.params.iter().zip(args) {
+                let ty = self.types.var_ty(param);
+                values.push(match self.types.pass(param) {
+                    Pass::Value => self.owned(&arg.value, &ty)?.text,
+                    Pass::Ref => self.borrowed(&arg.value)?,
+                    Pass::RefMut => self.borrowed_mut(&arg.value)?,
+                });
+            }
+            return Ok(Code::value(format!("{}({})", self.function_name(name), values.join(", ")), Prec::Postfix));
+        }
+        self.builtin(name, args, statement)
+    }
+
+    fn builtin(&mut self, name: &str, args: &[CallArgument], statement: bool) -> Result<Code, String> {
+        let arg = |index: usize| &args[index].value;
+        let postfix = |code: Code| code.at(Prec::Postfix);
+        Ok(match name {
+            "print" | "println" | "eprint" | "eprintln" => {
+                let mut parts = Vec::new();
+                self.parts(arg(0), &mut parts)?;
+                if matches!(name, "println" | "eprintln") && parts.iter().all(|part| matches!(part, Part::Text(text) if text.is_empty())) {
+                    return Ok(Code::value(format!("{}!()", name), Prec::Postfix));
+                }
+                Code::value(format!("{}!({})", name, self.format_args(parts)), Prec::Postfix)
+            }
+            "assert" => {
+                let condition = self.condition(arg(0))?.text;
+                if args.len() == 2 {
+                    let mut parts = Vec::new();
+                    self.parts(arg(1), &mut parts)?;
+                    Code::value(format!("assert!({}, {})", condition, self.format_args(parts)), Prec::Postfix)
+                } else {
+                    Code::value(format!("assert!({})", condition), Prec::Postfix)
+                }
+            }
+            "assert_eq" | "assert_ne" => {
+                let (left, right) = self.operands(arg(0), arg(1))?;
+                Code::value(format!("{}!({}, {})", name, left.text, right.text), Prec::Postfix)
+            }
+            "abs" => {
+                let ty = self.of(arg(0))?;
+                Code::value(format!("{}.abs()", self.receiver(arg(0), &ty)?), Prec::Postfix)
+            }
+            "sqrt" | "sin" | "cos" | "tan" => {
+                Code::value(format!("{}.{}()", self.receiver(arg(0), &Ty::Float)?, name), Prec::Postfix)
+            }
+            "floor" | "ceil" | "round" => {
+                if self.of(arg(0))? == Ty::Int {
+                    self.expr(arg(0))?
+                } else {
+                    Code::value(format!("{}.{}() as i64", self.receiver(arg(0), &Ty::Float)?, name), Prec::Cast)
+                }
+            }
+            "pow" => {
+                if self.of(arg(0))? == Ty::Int && self.of(arg(1))? == Ty::Int {
+                    let exponent = match arg(1) {
+                        Expression::Literal(Literal::Int(value)) => value.to_string(),
+                        other => format!("{} as u32", self.expr(other)?.at(Prec::Cast)),
+                    };
+                    Code::value(format!("{}.pow({})", self.receiver(arg(0), &Ty::Int)?, exponent), Prec::Postfix)
+                } else {
+                    let base = self.receiver(arg(0), &Ty::Float)?;
+                    let exponent = self.number(arg(1), &Ty::Float)?.text;
+                    Code::value(format!("{}.powf({})", base, exponent), Prec::Postfix)
+                }
+            }
+            "min" | "max" => {
+                let target = if self.of(arg(0))? == Ty::Int && self.of(arg(1))? == Ty::Int { Ty::Int } else { Ty::Float };
+                let left = self.receiver(arg(0), &target)?;
+                let right = self.number(arg(1), &target)?.text;
+                Code::value(format!("{}.{}({})", left, name, right), Prec::Postfix)
+            }
+            "len" => Code::value(format!("{}.len() as i64", postfix(self.expr(arg(0))?)), Prec::Cast),
+            "substring" => {
+                let text = postfix(self.expr(arg(0))?);
+                let (start, count) = (arg(1), arg(2));
+                let range = match (start, count) {
+                    (Expression::Literal(Literal::Int(a)), Expression::Literal(Literal::Int(b))) if *a >= 0 && *b >= 0 => {
+                        format!("{}..{}", a, a + b)
+                    }
+                    (Expression::Literal(_) | Expression::Identifier(_), _) => {
+                        let start = self.expr(start)?;
+                        let count = self.expr(count)?;
+                        format!("{} as usize..({} + {}) as usize", start.at(Prec::Cast), start.at(Prec::Add), count.at(Prec::Mul))
+                    }
+                    _ => {
+                        let start = self.expr(start)?;
+                        let count = self.expr(count)?;
+                        return Ok(Code::value(
+                            format!(
+                                "{{ let start = {} as usize; {}[start..start + {} as usize].to_string() }}",
+                                start.at(Prec::Cast),
+                                text,
+                                count.at(Prec::Cast)
+                            ),
+                            Prec::Postfix,
+                        ));
+                    }
+                };
+                Code::value(format!("{}[{}].to_string()", text, range), Prec::Postfix)
+            }
+            "concat" => {
+                let mut parts = Vec::new();
+                for arg in args {
+                    self.parts(&arg.value, &mut parts)?;
+                }
+                self.format_code(parts)
+            }
+            "contains" | "starts_with" | "ends_with" => {
+                let text = postfix(self.expr(arg(0))?);
+                let pattern = self.borrowed(arg(1))?;
+                Code::value(format!("{}.{}({})", text, name, pattern), Prec::Postfix)
+            }
+            "to_upper" => Code::value(format!("{}.to_uppercase()", postfix(self.expr(arg(0))?)), Prec::Postfix),
+            "to_lower" => Code::value(format!("{}.to_lowercase()", postfix(self.expr(arg(0))?)), Prec::Postfix),
+            "trim" => Code::value(format!("{}.trim().to_string()", postfix(self.expr(arg(0))?)), Prec::Postfix),
+            "split" => {
+                let text = postfix(self.expr(arg(0))?);
+                let separator = self.borrowed(arg(1))?;
+                Code::value(
+                    format!("{}.split({}).map(String::from).collect::<Vec<String>>()", text, separator),
+                    Prec::Postfix,
+                )
+            }
+            "join" => {
+                let items = postfix(self.expr(arg(0))?);
+                let separator = self.str_arg(arg(1))?;
+                Code::value(format!("{}.join({})", items, separator), Prec::Postfix)
+            }
+            "typeof" => {
+                let name = self.of(arg(0))?.type_name();
+                match arg(0) {
+                    Expression::FunctionCall(callee, args) => {
+                        let code = self.call(callee, args, true)?;
+                        Code::borrowed(format!("{{ {}; {:?} }}", code.text, name))
+                    }
+                    _ => Code::borrowed(format!("{:?}", name)),
+                }
+            }
+            "array_push" | "array_reverse" | "array_sort" => {
+                let Ty::Vec(element) = self.of(arg(0))? else {
+                    return Err(self.error(format!("calls `{}` on something other than an array", name)));
+                };
+                let operation = match name {
+                    "array_push" => format!("push({})", self.owned(arg(1), &element)?.text),
+                    "array_reverse" => "reverse()".to_string(),
+                    _ => match &*element {
+                        Ty::Float => "sort_by(|a, b| a.total_cmp(b))".to_string(),
+                        Ty::Int | Ty::Str | Ty::Bool | Ty::Char => "sort()".to_string(),
+                        other => return Err(self.unsupported(&format!("sorting arrays of {}", other))),
+                    },
+                };
+                let array = self.expr(arg(0))?;
+                if !array.place {
+                    Code::value(format!("{{ let mut items = {}; items.{}; items }}", array.text, operation), Prec::Postfix)
+                } else if statement {
+                    Code::value(format!("{}.{}", array.at(Prec::Postfix), operation), Prec::Postfix)
+                } else {
+                    let array = array.at(Prec::Postfix);
+                    Code::value(format!("{{ {}.{}; {}.clone() }}", array, operation, array), Prec::Postfix)
+                }
+            }
+            "array_slice" => {
+                let array = postfix(self.expr(arg(0))?);
+                let start = self.index(arg(1))?;
+                let end = self.index(arg(2))?;
+                Code::value(format!("{}[{}..{}].to_vec()", array, start, end), Prec::Postfix)
+            }
+            "read_line" => {
+                self.helpers.insert("read_line");
+                Code::value("read_line()", Prec::Postfix)
+            }
+            "read_file" => {
+                let path = self.borrowed(arg(0))?;
+                Code::value(format!("std::fs::read_to_string({}).expect(\"failed to read file\")", path), Prec::Postfix)
+            }
+            "write_file" => {
+                let path = self.borrowed(arg(0))?;
+                let content = self.borrowed(arg(1))?;
+                Code::value(format!("std::fs::write({}, {}).expect(\"failed to write file\")", path, content), Prec::Postfix)
+            }
+            "append_file" => {
+                self.helpers.insert("append_file");
+                let path = self.borrowed(arg(0))?;
+                let content = self.borrowed(arg(1))?;
+                Code::value(format!("append_file({}, {})", path, content), Prec::Postfix)
+            }
+            "file_exists" => {
+                let path = self.borrowed(arg(0))?;
+                Code::value(format!("std::path::Path::new({}).exists()", path), Prec::Postfix)
+            }
+            "exit" => {
+                let code = match arg(0) {
+                    Expression::Literal(Literal::Int(value)) => value.to_string(),
+                    other => format!("{} as i32", self.expr(other)?.at(Prec::Cast)),
+                };
+                Code::value(format!("std::process::exit({})", code), Prec::Postfix)
+            }
+            other => return Err(self.unsupported(&format!("the builtin '{}'", other))),
+        })
+    }
+}
+
+fn contains_var(ty: &Ty) -> bool {
+    match ty {
+        Ty::Var(_) => true,
+        Ty::Vec(element) => contains_var(element),
+        _ => false,
+    }
+}
@@ -0,0 +1,332 @@
+// Rust transpiler
+//
+// `neksis transpile --to rust` turns a program into a cargo crate of plain
+// Rust. Types are inferred over the whole program first (types.rs), then
+// emit.rs prints structs and enums as derived items, functions with borrowed
+// parameters where their bodies allow it, matches as Rust patterns and
+// builtins as calls into std. A program that uses something without a Rust
+// equivalent is rejected with the function and construct, rather than
+// producing a crate that does not compile.
+
+mod emit;
+mod types;
+
+use crate::ast::Program;
+use crate::error::CompilerError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct RustTranspiler {
+    library: bool,
+}
+
+impl RustTranspiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emit a library crate: items are `pub` and there is no `main`
+    pub fn library(mut self, library: bool) -> Self {
+        self.library = library;
+        self
+    }
+
+    pub fn transpile(&self, program: &Program) -> Result<String, CompilerError> {
+        let types = types::infer(program).map_err(|message| CompilerError::codegen_error("rust", &message))?;
+        emit::emit(program, &types, self.library).map_err(|message| CompilerError::codegen_error("rust", &message))
+    }
+
+    /// Write a cargo crate for the program into `dir` and return the path of its source file
+    pub fn write_crate(&self, program: &Program, name: &str, dir: &Path) -> Result<PathBuf, CompilerError> {
+        let source = self.transpile(program)?;
+        let src = dir.join("src");
+        fs::create_dir_all(&src)
+            .map_err(|e| CompilerError::io_error(&format!("Failed to create '{}': {}", src.display(), e)))?;
+        let manifest = format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n",
+            package_name(name)
+        );
+        let manifest_path = dir.join("Cargo.toml");
+        fs::write(&manifest_path, manifest)
+            .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", manifest_path.display(), e)))?;
+        let file = src.join(if self.library { "lib.rs" } else { "main.rs" });
+        fs::write(&file, source)
+            .map_err(|e| CompilerError::io_error(&format!("Failed to write '{}': {}", file.display(), e)))?;
+        Ok(file)
+    }
+}
+
+/// A cargo package name derived from a file name
+fn package_name(name: &str) -> String {
+    let mut package: String =
+        name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' }).collect();
+    package = package.trim_matches('-').to_string();
+    if package.is_empty() || package.starts_with(|c: char| c.is_ascii_digit()) {
+        package = format!("neksis-{}", package);
+    }
+    package
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::test_framework::golden::{run_program_at, ProgramOutput};
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn have_rustc() -> bool {
+        let found = Command::new("rustc").arg("--version").output().is_ok();
+        if !found {
+            eprintln!("skipping: no rustc");
+        }
+        found
+    }
+
+    fn parse(source: &str) -> Program {
+        let tokens = Lexer::new(source, "test.nx".to_string()).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    fn transpile(source: &str) -> Result<String, CompilerError> {
+        RustTranspiler::new().transpile(&parse(source))
+    }
+
+    // Compile the transpiled program with rustc and run it
+    fn run_rust(source: &str) -> ProgramOutput {
+        static BUILDS: AtomicUsize = AtomicUsize::new(0);
+        let rust = transpile(source).unwrap();
+        let base = std::env::temp_dir().join(format!(
+            "neksis-rust-test-{}-{}",
+            std::process::id(),
+            BUILDS.fetch_add(1, Ordering::SeqCst)
+        ));
+        let file = base.with_extension("rs");
+        fs::write(&file, &rust).unwrap();
+        let build = Command::new("rustc")
+            .args(["--edition", "2021", "-O", "-A", "warnings", "-o"])
+            .arg(&base)
+            .arg(&file)
+            .output()
+            .unwrap();
+        let _ = fs::remove_file(&file);
+        assert!(build.status.success(), "rustc rejected:\n{}\n{}", rust, String::from_utf8_lossy(&build.stderr));
+        let output = Command::new(&base).output().unwrap();
+        let _ = fs::remove_file(&base);
+        ProgramOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::new(),
+            exit_code: output.status.code().unwrap_or(-1),
+        }
+    }
+
+    fn assert_same_as_vm(source: &str) {
+        let expected = run_program_at(source, "test.nx", 0);
+        let actual = run_rust(source);
+        assert_eq!(actual.exit_code, expected.exit_code);
+        assert_eq!(actual.stdout, expected.stdout);
+    }
+
+    const SHAPES: &str = r#"
+        enum Shape { Circle(Float), Rect(Float, Float), Empty }
+        struct Point { x: Int, y: Int }
+        fn area(s: Shape) -> Float {
+            return match s {
+                Shape::Circle(r) => 3.0 * r * r,
+                Shape::Rect(w, h) => w * h,
+                Shape::Empty => 0.0
+            };
+        }
+        fn main() {
+            let p = Point { x: 1, y: 2 };
+            println(p.x + p.y);
+            println(area(Shape::Circle(2.0)));
+            println(area(Shape::Rect(2, 3.5)));
+            println(area(Shape::Empty));
+            println(p);
+        }
+    "#;
+
+    #[test]
+    fn test_structs_enums_and_match() {
+        let rust = transpile(SHAPES).unwrap();
+        assert!(rust.contains("#[derive(Debug, Clone, PartialEq)]\nenum Shape {\n    Circle(f64),\n    Rect(f64, f64),\n    Empty,\n}"));
+        assert!(rust.contains("struct Point {\n    x: i64,\n    y: i64,\n}"));
+        assert!(rust.contains("fn area(s: &Shape) -> f64 {\n    match *s {\n        Shape::Circle(r) => 3.0 * r * r,"));
+        assert!(rust.contains("area(&Shape::Rect(2.0, 3.5))"));
+        if have_rustc() {
+            assert_eq!(run_rust(SHAPES).stdout, "3\n12\n7\n0\nPoint { x: 1, y: 2 }\n");
+        }
+    }
+
+    #[test]
+    fn test_parameters_borrow_unless_owned() {
+        let rust = transpile(r#"
+            fn total(xs: Array) -> Int {
+                let sum = 0;
+                let i = 0;
+                while i < len(xs) {
+                    sum = sum + xs[i];
+                    i = i + 1;
+                }
+                return sum;
+            }
+            fn fill(xs: Array, n: Int) {
+                let i = 0;
+                while (i < n) {
+                    array_push(xs, i * i);
+                    i = i + 1;
+                }
+            }
+            fn greet(name: String) -> String {
+                return "hello " + name;
+            }
+            fn shout(name: String) -> String {
+                name = to_upper(name);
+                return name + "!";
+            }
+        "#)
+        .unwrap();
+        assert!(rust.contains("fn total(xs: &[i64]) -> i64 {\n    let mut sum: i64 = 0;"));
+        assert!(rust.contains("        sum += xs[i as usize];"));
+        assert!(rust.contains("fn fill(xs: &mut Vec<i64>, n: i64) {"));
+        assert!(rust.contains("        xs.push(i * i);"));
+        assert!(rust.contains("fn greet(name: &str) -> String {\n    format!(\"hello {name}\")\n}"));
+        assert!(rust.contains("fn shout(mut name: String) -> String {\n    name = name.to_uppercase();"));
+    }
+
+    #[test]
+    fn test_values_and_builtins() {
+        if !have_rustc() {
+            return;
+        }
+        assert_same_as_vm(r#"
+            fn fib(n: Int) -> Int {
+                if n < 2 {
+                    return n;
+                }
+                return fib(n - 1) + fib(n - 2);
+            }
+            fn label(n: Int) -> String {
+                return match n {
+                    0 => "zero",
+                    1 => "one",
+                    _ => "many"
+                };
+            }
+            fn main() {
+                println(0.1 + 0.2);
+                println(1.0 / 3.0);
+                println(-7 / 2);
+                println(7 % -3);
+                println(split("a,b,,c", ","));
+                println(to_upper("MiXed") + trim("  x  ") + substring("hello", 1, 3));
+                let items = [5, 3, 9, 1];
+                println(array_sort(items));
+                println(typeof(items) + " " + typeof(1.5));
+                println("x" + 1 + 2.5);
+                println(round(-2.5));
+                println(max(3, 4.5));
+                println(pow(2, 10));
+                println(sqrt(16));
+                let total = 0;
+                total = total + 0.5;
+                println(total);
+                println("fib(15) = {fib(15)}");
+                println(label(0) + label(1) + label(7));
+                let words = [];
+                array_push(words, "a");
+                array_push(words, "b");
+                println(len(words));
+                let flag = contains("haystack", "st");
+                if flag && !starts_with("abc", "b") {
+                    println("yes");
+                }
+                exit(3);
+            }
+        "#);
+    }
+
+    #[test]
+    fn test_main_is_wrapped_when_needed() {
+        // Top-level statements run before `main`, which keeps its return value
+        let rust = transpile("println(\"first\");\nfn main() -> Int {\n    println(\"second\");\n    return 0;\n}\n").unwrap();
+        assert!(rust.contains("fn neksis_main() -> i64 {"));
+        assert!(rust.contains("fn main() {\n    println!(\"first\");\n    neksis_main();\n}"));
+        let rust = transpile("fn main() {\n    println(\"only\");\n}\n").unwrap();
+        assert_eq!(rust, "fn main() {\n    println!(\"only\");\n}\n");
+    }
+
+    #[test]
+    fn test_library_mode() {
+        let program = parse("struct Pair { a: Int, b: Int }\nfn sum(p: Pair) -> Int {\n    return p.a + p.b;\n}\n");
+        let rust = RustTranspiler::new().library(true).transpile(&program).unwrap();
+        assert!(rust.contains("pub struct Pair {\n    pub a: i64,\n    pub b: i64,\n}"));
+        assert!(rust.contains("pub fn sum(p: &Pair) -> i64 {\n    p.a + p.b\n}"));
+        assert!(!rust.contains("fn main"));
+        let error = RustTranspiler::new().library(true).transpile(&parse("println(1);")).unwrap_err();
+        assert!(error.to_string().contains("a library cannot run"), "{}", error);
+    }
+
+    #[test]
+    fn test_unsupported_features_name_the_function() {
+        let error = transpile("fn risky() {\n    try {\n        println(1);\n    } catch (e) {\n        println(e);\n    }\n}\n")
+            .unwrap_err();
+        assert!(error.to_string().contains("function 'risky' uses try/catch"), "{}", error);
+        let error = transpile("let limit = 3;\nfn f() -> Int {\n    return limit;\n}\n").unwrap_err();
+        assert!(error.to_string().contains("the global variable `limit`"), "{}", error);
+        let error = transpile("fn f(n: Int) -> Int {\n    if n > 0 {\n        return 1;\n    }\n}\n").unwrap_err();
+        assert!(error.to_string().contains("can reach its end without returning a value"), "{}", error);
+    }
+
+    #[test]
+    fn test_write_crate() {
+        let dir = std::env::temp_dir().join(format!("neksis-transpile-crate-{}", std::process::id()));
+        let file = RustTranspiler::new().write_crate(&parse("println(1);"), "Hello World", &dir).unwrap();
+        assert_eq!(file, dir.join("src").join("main.rs"));
+        let manifest = fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        assert!(manifest.starts_with("[package]\nname = \"hello-world\"\n"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_repository_corpus_rust() {
+        if !have_rustc() {
+            return;
+        }
+        // Every golden program that transpiles and succeeds in the VM must print the same in Rust
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests");
+        let mut programs: Vec<_> = fs::read_dir(&corpus)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "nx"))
+            .collect();
+        programs.sort();
+        let failures: Vec<String> = std::thread::scope(|scope| {
+            let workers: Vec<_> = programs
+                .chunks(programs.len().div_ceil(4).max(1))
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .filter_map(|path| {
+                                let source = fs::read_to_string(path).unwrap();
+                                let expected = run_program_at(&source, "test.nx", 0);
+                                if expected.exit_code != 0 || transpile(&source).is_err() {
+                                    return None;
+                                }
+                                let actual = run_rust(&source);
+                                (actual.stdout != expected.stdout || actual.exit_code != 0)
+                                    .then(|| format!("{}:\n{}", path.display(), actual.render()))
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+        });
+        assert!(failures.is_empty(), "Rust output mismatches:\n{}", failures.join("\n"));
+    }
+}
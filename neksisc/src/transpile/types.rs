@@ -0,0 +1,1084 @@
+// Type inference for the Rust transpiler
+//
+// Rust needs a type for every variable and return value, while neksis only
+// asks for them on parameters. The types come from unification over the whole
+// program, walked again until nothing changes: an operation whose result
+// depends on its operands (`+` on strings or numbers, most builtins) waits
+// until they are known. A variable that holds both Ints and Floats becomes a
+// Float, as the VM's arithmetic would make it. The walk also records which
+// variables are reassigned or changed in place, which decides `let mut` and
+// how each parameter is passed.
+
+use crate::ast::{
+    BinaryOperator, CallArgument, Expression, InterpolatedPart, Literal, MatchExpression, Pattern,
+    Program, Statement, Type, UnaryOperator,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ty {
+    Int,
+    Float,
+    Bool,
+    Str,
+    Char,
+    Unit,
+    Vec(Box<Ty>),
+    Named(String),
+    Var(usize),
+}
+
+impl Ty {
+    pub fn is_copy(&self) -> bool {
+        matches!(self, Ty::Int | Ty::Float | Ty::Bool | Ty::Char | Ty::Unit)
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Ty::Int | Ty::Float)
+    }
+
+    /// The owned Rust type
+    pub fn rust(&self) -> String {
+        match self {
+            Ty::Int => "i64".to_string(),
+            Ty::Float => "f64".to_string(),
+            Ty::Bool => "bool".to_string(),
+            Ty::Str => "String".to_string(),
+            Ty::Char => "char".to_string(),
+            Ty::Unit => "()".to_string(),
+            Ty::Vec(element) => format!("Vec<{}>", element.rust()),
+            Ty::Named(name) => name.clone(),
+            Ty::Var(_) => "_".to_string(),
+        }
+    }
+
+    /// What `typeof` reports for a value of this type
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Ty::Int => "int",
+            Ty::Float => "float",
+            Ty::Bool => "bool",
+            Ty::Str => "string",
+            Ty::Char => "char",
+            Ty::Unit => "null",
+            Ty::Vec(_) => "array",
+            Ty::Named(_) | Ty::Var(_) => "object",
+        }
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::Int => write!(f, "Int"),
+            Ty::Float => write!(f, "Float"),
+            Ty::Bool => write!(f, "Bool"),
+            Ty::Str => write!(f, "String"),
+            Ty::Char => write!(f, "Char"),
+            Ty::Unit => write!(f, "null"),
+            Ty::Vec(element) => write!(f, "Array<{}>", element),
+            Ty::Named(name) => write!(f, "{}", name),
+            Ty::Var(_) => write!(f, "an unknown type"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VarKind {
+    Local,
+    Param,
+    /// A name bound by a match pattern
+    Binding,
+}
+
+/// How a parameter is passed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    Value,
+    Ref,
+    RefMut,
+}
+
+#[derive(Debug, Clone)]
+pub struct Var {
+    pub name: String,
+    pub kind: VarKind,
+    pub reassigned: bool,
+    /// Changed in place by `array_push` and friends, or lent to a `&mut` parameter
+    pub mutated: bool,
+    ty: Ty,
+    owner: Option<String>,
+    /// The let statement of a local whose type comes from its values
+    inferred_at: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub params: Vec<usize>,
+    pub ret: Ty,
+    /// Address of the definition that wins when a name is defined twice
+    pub definition: usize,
+}
+
+pub struct Types {
+    pub structs: HashMap<String, Vec<(String, Ty)>>,
+    pub enums: HashMap<String, Vec<(String, Vec<Ty>)>>,
+    pub functions: HashMap<String, Signature>,
+    vars: Vec<Var>,
+    bindings: Vec<Option<Ty>>,
+    /// Let statements, match patterns and assignments to the variable they introduce or change
+    sites: HashMap<usize, usize>,
+    /// Identifier expressions to the variable they name
+    resolved: HashMap<usize, usize>,
+    /// Type variables of expressions whose type is found later in the walk
+    nodes: HashMap<usize, Ty>,
+    exprs: HashMap<usize, Ty>,
+    scopes: Vec<HashMap<String, usize>>,
+    globals: HashSet<String>,
+    function: Option<String>,
+    changed: bool,
+    /// Let statements whose variable turned out to hold Floats as well as Ints
+    widened: HashSet<usize>,
+    restart: bool,
+}
+
+/// The address of an AST node, which identifies it across walks
+pub fn key<T>(node: &T) -> usize {
+    node as *const T as usize
+}
+
+pub const SUPPORTED_BUILTINS: &[&str] = &[
+    "print", "println", "eprint", "eprintln", "assert", "assert_eq", "assert_ne", "abs", "sqrt", "sin", "cos",
+    "tan", "floor", "ceil", "round", "pow", "min", "max", "len", "substring", "concat", "contains",
+    "starts_with", "ends_with", "to_upper", "to_lower", "trim", "split", "join", "typeof", "array_push",
+    "array_reverse", "array_sort", "array_slice", "read_line", "read_file", "write_file", "append_file",
+    "file_exists", "exit",
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    SUPPORTED_BUILTINS.contains(&name)
+}
+
+fn arity(name: &str) -> (usize, usize) {
+    match name {
+        "read_line" => (0, 0),
+        "assert" => (1, 2),
+        "assert_eq" | "assert_ne" | "pow" | "min" | "max" | "concat" | "contains" | "starts_with"
+        | "ends_with" | "split" | "join" | "array_push" | "write_file" | "append_file" => (2, 2),
+        "substring" | "array_slice" => (3, 3),
+        _ => (1, 1),
+    }
+}
+
+pub fn infer(program: &Program) -> Result<Types, String> {
+    let mut widened = HashSet::new();
+    loop {
+        let types = infer_with(program, widened)?;
+        if !types.restart {
+            return Ok(types);
+        }
+        widened = types.widened;
+    }
+}
+
+/// Infer types, starting the variables declared at `widened` as Floats
+fn infer_with(program: &Program, widened: HashSet<usize>) -> Result<Types, String> {
+    let mut types = Types {
+        structs: HashMap::new(),
+        enums: HashMap::new(),
+        functions: HashMap::new(),
+        vars: Vec::new(),
+        bindings: Vec::new(),
+        sites: HashMap::new(),
+        resolved: HashMap::new(),
+        nodes: HashMap::new(),
+        exprs: HashMap::new(),
+        scopes: Vec::new(),
+        globals: HashSet::new(),
+        function: None,
+        changed: false,
+        widened,
+        restart: false,
+    };
+    types.declare_items(program)?;
+    for _ in 0..8 {
+        let settled = types.settle(program);
+        // A widened variable can make later code look mistyped, so its errors wait for the restart
+        if types.restart {
+            return Ok(types);
+        }
+        settled?;
+        if !types.apply_defaults()? {
+            return Ok(types);
+        }
+    }
+    Err("the program's types did not settle".to_string())
+}
+
+impl Types {
+    pub fn var(&self, id: usize) -> &Var {
+        &self.vars[id]
+    }
+
+    pub fn var_ty(&self, id: usize) -> Ty {
+        self.resolve(&self.vars[id].ty)
+    }
+
+    /// The variable introduced by a let statement or pattern, or changed by an assignment
+    pub fn site<T>(&self, node: &T) -> usize {
+        self.sites[&key(node)]
+    }
+
+    /// The variable an identifier expression refers to
+    pub fn resolved(&self, expression: &Expression) -> Option<usize> {
+        self.resolved.get(&key(expression)).copied()
+    }
+
+    pub fn of(&self, expression: &Expression) -> Ty {
+        match self.exprs.get(&key(expression)) {
+            Some(ty) => self.resolve(ty),
+            None => Ty::Unit,
+        }
+    }
+
+    pub fn pass(&self, id: usize) -> Pass {
+        let var = &self.vars[id];
+        if self.var_ty(id).is_copy() || var.reassigned {
+            Pass::Value
+        } else if var.mutated {
+            Pass::RefMut
+        } else {
+            Pass::Ref
+        }
+    }
+
+    pub fn resolve(&self, ty: &Ty) -> Ty {
+        match ty {
+            Ty::Var(index) => match &self.bindings[*index] {
+                Some(bound) => self.resolve(bound),
+                None => ty.clone(),
+            },
+            Ty::Vec(element) => Ty::Vec(Box::new(self.resolve(element))),
+            other => other.clone(),
+        }
+    }
+
+    fn shallow(&self, ty: &Ty) -> Ty {
+        match ty {
+            Ty::Var(index) => match &self.bindings[*index] {
+                Some(bound) => self.shallow(bound),
+                None => ty.clone(),
+            },
+            other => other.clone(),
+        }
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.bindings.push(None);
+        Ty::Var(self.bindings.len() - 1)
+    }
+
+    fn node(&mut self, expression: &Expression) -> Ty {
+        if let Some(ty) = self.nodes.get(&key(expression)) {
+            return ty.clone();
+        }
+        let ty = self.fresh();
+        self.nodes.insert(key(expression), ty.clone());
+        ty
+    }
+
+    fn bind(&mut self, index: usize, ty: Ty) {
+        self.bindings[index] = Some(ty);
+        self.changed = true;
+    }
+
+    fn occurs(&self, index: usize, ty: &Ty) -> bool {
+        match self.shallow(ty) {
+            Ty::Var(other) => other == index,
+            Ty::Vec(element) => self.occurs(index, &element),
+            _ => false,
+        }
+    }
+
+    fn unify(&mut self, a: &Ty, b: &Ty) -> Result<(), String> {
+        let (a, b) = (self.shallow(a), self.shallow(b));
+        match (&a, &b) {
+            (Ty::Var(x), Ty::Var(y)) if x == y => Ok(()),
+            (Ty::Var(x), other) | (other, Ty::Var(x)) => {
+                if self.occurs(*x, other) {
+                    return Err(format!("needs a value of type {} to contain itself", other));
+                }
+                self.bind(*x, other.clone());
+                Ok(())
+            }
+            (Ty::Vec(x), Ty::Vec(y)) => self.unify(x, y),
+            _ if a == b => Ok(()),
+            _ => Err(format!("mixes {} and {}", a, b)),
+        }
+    }
+
+    /// Unify `target` with a value flowing into it, where an Int may flow into a Float
+    fn flow(&mut self, target: &Ty, value: &Ty) -> Result<(), String> {
+        let (target, value) = (self.shallow(target), self.shallow(value));
+        match (&target, &value) {
+            (Ty::Float, Ty::Int) => Ok(()),
+            _ => self.unify(&target, &value),
+        }
+    }
+
+    fn error(&self, message: String) -> String {
+        match &self.function {
+            Some(name) => format!("function '{}' {}", name, message),
+            None => format!("the top level {}", message),
+        }
+    }
+
+    fn unsupported(&self, what: &str) -> String {
+        self.error(format!("uses {}, which the Rust target does not support", what))
+    }
+
+    fn new_var(&mut self, name: &str, ty: Ty, kind: VarKind) -> usize {
+        self.vars.push(Var {
+            name: name.to_string(),
+            kind,
+            reassigned: false,
+            mutated: false,
+            ty,
+            owner: self.function.clone(),
+            inferred_at: None,
+        });
+        self.vars.len() - 1
+    }
+
+    fn declare(&mut self, name: &str, id: usize) {
+        self.scopes.last_mut().expect("scope").insert(name.to_string(), id);
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).copied())
+    }
+
+    fn mark_reassigned(&mut self, id: usize) {
+        if !self.vars[id].reassigned {
+            self.vars[id].reassigned = true;
+            self.changed = true;
+        }
+    }
+
+    fn mark_mutated(&mut self, argument: &Expression) {
+        if let Some(id) = self.resolved(argument) {
+            if !self.vars[id].mutated {
+                self.vars[id].mutated = true;
+                self.changed = true;
+            }
+        }
+    }
+
+    fn convert(&mut self, ty: &Type) -> Result<Ty, String> {
+        Ok(match ty {
+            Type::Int => Ty::Int,
+            Type::Float => Ty::Float,
+            Type::Bool => Ty::Bool,
+            Type::String => Ty::Str,
+            Type::Char => Ty::Char,
+            Type::Void => Ty::Unit,
+            Type::Array(element, _) | Type::Slice(element) => Ty::Vec(Box::new(self.convert(element)?)),
+            Type::GenericType(name, args) | Type::Generic(name, args)
+                if matches!(name.as_str(), "Array" | "List" | "Vec") && args.len() == 1 =>
+            {
+                Ty::Vec(Box::new(self.convert(&args[0])?))
+            }
+            Type::Struct(name) | Type::Enum(name) => match name.as_str() {
+                "Array" | "List" | "Vec" => Ty::Vec(Box::new(self.fresh())),
+                _ if self.structs.contains_key(name) || self.enums.contains_key(name) => Ty::Named(name.clone()),
+                _ => return Err(self.unsupported(&format!("the type '{}'", name))),
+            },
+            other => return Err(self.unsupported(&format!("the type {:?}", other))),
+        })
+    }
+
+    fn declare_items(&mut self, program: &Program) -> Result<(), String> {
+        for statement in &program.statements {
+            match statement {
+                Statement::Struct(item) => {
+                    self.structs.insert(item.name.clone(), Vec::new());
+                }
+                Statement::Enum(item) => {
+                    self.enums.insert(item.name.clone(), Vec::new());
+                }
+                _ => {}
+            }
+        }
+        for statement in &program.statements {
+            match statement {
+                Statement::Struct(item) => {
+                    let mut fields = Vec::new();
+                    for field in &item.fields {
+                        fields.push((field.name.clone(), self.convert(&field.field_type)?));
+                    }
+                    self.structs.insert(item.name.clone(), fields);
+                }
+                Statement::Enum(item) => {
+                    let mut variants = Vec::new();
+                    for variant in &item.variants {
+                        let mut payload = Vec::new();
+                        for field in &variant.fields {
+                            payload.push(self.convert(&field.field_type)?);
+                        }
+                        variants.push((variant.name.clone(), payload));
+                    }
+                    self.enums.insert(item.name.clone(), variants);
+                }
+                _ => {}
+            }
+        }
+        for statement in &program.statements {
+            if let Statement::Function(function) = statement {
+                self.function = Some(function.name.clone());
+                let mut params = Vec::new();
+                for param in &function.parameters {
+                    if param.default_value.is_some() {
+                        return Err(self.unsupported("default parameter values"));
+                    }
+                    let ty = self.convert(&param.type_annotation)?;
+                    params.push(self.new_var(&param.name, ty, VarKind::Param));
+                }
+                let ret = match &function.return_type {
+                    Some(ty) => self.convert(ty)?,
+                    None => self.fresh(),
+                };
+                self.functions
+                    .insert(function.name.clone(), Signature { params, ret, definition: key(function) });
+            }
+        }
+        self.function = None;
+        Ok(())
+    }
+
+    /// Walk the program until no type or flag changes
+    fn settle(&mut self, program: &Program) -> Result<(), String> {
+        for _ in 0..64 {
+            self.changed = false;
+            self.exprs.clear();
+            self.walk(program)?;
+            if !self.changed || self.restart {
+                return Ok(());
+            }
+        }
+        Err("the program's types did not settle".to_string())
+    }
+
+    fn walk(&mut self, program: &Program) -> Result<(), String> {
+        self.function = None;
+        self.scopes = vec![HashMap::new()];
+        for statement in &program.statements {
+            match statement {
+                Statement::Function(_) | Statement::Struct(_) | Statement::Enum(_) => {}
+                other => self.statement(other)?,
+            }
+        }
+        self.globals = self.scopes[0].keys().cloned().collect();
+        for statement in &program.statements {
+            let Statement::Function(function) = statement else { continue };
+            let signature = self.functions[&function.name].clone();
+            if signature.definition != key(function) {
+                continue;
+            }
+            self.function = Some(function.name.clone());
+            let params = function.parameters.iter().map(|param| param.name.clone()).zip(signature.params);
+            self.scopes = vec![params.collect()];
+            self.branch(&function.body)?;
+        }
+        self.function = None;
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), String> {
+        self.scopes.push(HashMap::new());
+        let result = statements.iter().try_for_each(|statement| self.statement(statement));
+        self.scopes.pop();
+        result
+    }
+
+    /// The body of a function, loop or branch
+    fn branch(&mut self, body: &Expression) -> Result<(), String> {
+        match body {
+            Expression::Block(statements) => self.statements(statements),
+            other => self.expression(other, false).map(|_| ()),
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::SourceLine(_) => {}
+            Statement::Let(let_statement) => {
+                let value = self.expression(&let_statement.value, true)?;
+                let id = match self.sites.get(&key(let_statement)) {
+                    Some(id) => *id,
+                    None => {
+                        let site = key(let_statement);
+                        let ty = match &let_statement.type_annotation {
+                            Some(ty) => self.convert(ty)?,
+                            None if self.widened.contains(&site) => Ty::Float,
+                            None => self.fresh(),
+                        };
+                        let id = self.new_var(&let_statement.name, ty, VarKind::Local);
+                        if let_statement.type_annotation.is_none() {
+                            self.vars[id].inferred_at = Some(site);
+                        }
+                        self.sites.insert(site, id);
+                        id
+                    }
+                };
+                let target = self.vars[id].ty.clone();
+                self.assign(id, &target, &value)?;
+                self.declare(&let_statement.name, id);
+            }
+            Statement::AssignmentStatement { name, value } => {
+                let Some(id) = self.lookup(name) else {
+                    return Err(self.undefined(name));
+                };
+                if self.vars[id].kind == VarKind::Binding {
+                    return Err(self.unsupported("assignments to match bindings"));
+                }
+                self.sites.insert(key(statement), id);
+                let value_ty = self.expression(value, true)?;
+                if in_place_update(name, value).is_none() {
+                    let target = self.vars[id].ty.clone();
+                    self.assign(id, &target, &value_ty)?;
+                    self.mark_reassigned(id);
+                }
+            }
+            Statement::Expression(expression) => {
+                self.expression(expression, false)?;
+            }
+            Statement::Return(ret) => {
+                let Some(function) = self.function.clone() else {
+                    return Err(self.unsupported("`return` outside a function"));
+                };
+                let value = match &ret.value {
+                    Some(value) => self.expression(value, true)?,
+                    None => Ty::Unit,
+                };
+                let target = self.functions[&function].ret.clone();
+                self.flow(&target, &value).map_err(|message| self.error(format!("returns a value that {}", message)))?;
+            }
+            Statement::Function(_) | Statement::Struct(_) | Statement::Enum(_) => {
+                return Err(self.unsupported("nested declarations"));
+            }
+            Statement::Extern(_) => return Err(self.unsupported("extern blocks")),
+            Statement::PythonImport(_) => return Err(self.unsupported("Python imports")),
+            _ => return Err(self.unsupported("this statement")),
+        }
+        Ok(())
+    }
+
+    /// A value stored into variable `id`. An unannotated Int variable that is given a Float
+    /// becomes a Float, which means inferring everything again.
+    fn assign(&mut self, id: usize, target: &Ty, value: &Ty) -> Result<(), String> {
+        let (target_head, value_head) = (self.shallow(target), self.shallow(value));
+        if let (Ty::Int, Ty::Float, Some(site)) = (&target_head, &value_head, self.vars[id].inferred_at) {
+            self.widened.insert(site);
+            self.restart = true;
+            return Ok(());
+        }
+        let name = self.vars[id].name.clone();
+        self.flow(target, value).map_err(|message| self.error(format!("stores a value in `{}` that {}", name, message)))
+    }
+
+    fn undefined(&self, name: &str) -> String {
+        if self.function.is_some() && self.globals.contains(name) {
+            self.unsupported(&format!("the global variable `{}`", name))
+        } else {
+            self.error(format!("uses the undefined variable `{}`", name))
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression, value: bool) -> Result<Ty, String> {
+        let ty = self.expression_type(expression, value)?;
+        self.exprs.insert(key(expression), ty.clone());
+        Ok(ty)
+    }
+
+    fn expression_type(&mut self, expression: &Expression, value: bool) -> Result<Ty, String> {
+        Ok(match expression {
+            Expression::Literal(Literal::Array(items)) => {
+                let element = self.node(expression);
+                for item in items {
+                    let ty = self.literal(item)?;
+                    self.flow(&element, &ty).map_err(|message| self.error(format!("has an array that {}", message)))?;
+                }
+                Ty::Vec(Box::new(element))
+            }
+            Expression::Literal(literal) => self.literal(literal)?,
+            Expression::Identifier(name) => {
+                let Some(id) = self.lookup(name) else {
+                    if self.functions.contains_key(name) {
+                        return Err(self.unsupported("functions as values"));
+                    }
+                    return Err(self.undefined(name));
+                };
+                self.resolved.insert(key(expression), id);
+                self.vars[id].ty.clone()
+            }
+            Expression::BinaryOp(op) => self.binary(expression, &op.operator, &op.left, &op.right)?,
+            Expression::UnaryOp(op) => {
+                let operand = self.expression(&op.operand, true)?;
+                match op.operator {
+                    UnaryOperator::Not => Ty::Bool,
+                    UnaryOperator::Negate => match self.shallow(&operand) {
+                        Ty::Int | Ty::Float | Ty::Var(_) => operand,
+                        other => return Err(self.error(format!("negates a {}", other))),
+                    },
+                    _ => return Err(self.unsupported("this unary operator")),
+                }
+            }
+            Expression::FunctionCall(callee, args) => self.call(expression, callee, args)?,
+            Expression::If(if_expression) => {
+                self.expression(&if_expression.condition, true)?;
+                self.branch(&if_expression.then_branch)?;
+                if let Some(else_branch) = &if_expression.else_branch {
+                    self.branch(else_branch)?;
+                }
+                Ty::Unit
+            }
+            Expression::While(while_expression) => {
+                self.expression(&while_expression.condition, true)?;
+                self.branch(&while_expression.body)?;
+                Ty::Unit
+            }
+            Expression::Loop(loop_expression) => {
+                self.branch(&loop_expression.body)?;
+                Ty::Unit
+            }
+            Expression::Block(statements) => {
+                self.statements(statements)?;
+                Ty::Unit
+            }
+            Expression::Match(match_expression) => self.match_expression(expression, match_expression, value)?,
+            Expression::StructLiteral(literal) => {
+                let Some(fields) = self.structs.get(&literal.struct_name).cloned() else {
+                    return Err(self.error(format!("uses the undefined struct `{}`", literal.struct_name)));
+                };
+                for (name, value) in &literal.fields {
+                    let value = self.expression(value, true)?;
+                    let Some((_, field)) = fields.iter().find(|(field, _)| field == name) else {
+                        return Err(self.error(format!("sets `{}`, which `{}` does not have", name, literal.struct_name)));
+                    };
+                    self.flow(field, &value)
+                        .map_err(|message| self.error(format!("sets field `{}` to a value that {}", name, message)))?;
+                }
+                if let Some((missing, _)) = fields.iter().find(|(field, _)| !literal.fields.iter().any(|(name, _)| name == field)) {
+                    return Err(self.error(format!("leaves field `{}` of `{}` unset", missing, literal.struct_name)));
+                }
+                Ty::Named(literal.struct_name.clone())
+            }
+            Expression::MemberAccess(access) => {
+                let object = self.expression(&access.object, true)?;
+                match self.shallow(&object) {
+                    Ty::Named(name) if self.structs.contains_key(&name) => {
+                        match self.structs[&name].iter().find(|(field, _)| *field == access.member) {
+                            Some((_, ty)) => ty.clone(),
+                            None => return Err(self.error(format!("reads `{}`, which `{}` does not have", access.member, name))),
+                        }
+                    }
+                    Ty::Var(_) => self.node(expression),
+                    other => return Err(self.error(format!("reads field `{}` of a {}", access.member, other))),
+                }
+            }
+            Expression::EnumVariantAccess { enum_name, variant_name } => {
+                let payload = self.variant(enum_name, variant_name)?;
+                if !payload.is_empty() {
+                    return Err(self.error(format!("uses `{}::{}` without its {} values", enum_name, variant_name, payload.len())));
+                }
+                Ty::Named(enum_name.clone())
+            }
+            Expression::ArrayAccess(access) => {
+                let array = self.expression(&access.array, true)?;
+                let index = self.expression(&access.index, true)?;
+                self.unify(&index, &Ty::Int).map_err(|_| self.error("indexes an array with a non-Int value".to_string()))?;
+                match self.shallow(&array) {
+                    Ty::Vec(element) => *element,
+                    Ty::Var(_) => self.node(expression),
+                    Ty::Str => return Err(self.unsupported("indexing into strings")),
+                    other => return Err(self.error(format!("indexes into a {}", other))),
+                }
+            }
+            Expression::InterpolatedString(interpolated) => {
+                for part in &interpolated.parts {
+                    if let InterpolatedPart::Expr(part) = part {
+                        self.expression(part, true)?;
+                    }
+                }
+                Ty::Str
+            }
+            other => return Err(self.unsupported(describe(other))),
+        })
+    }
+
+    fn literal(&mut self, literal: &Literal) -> Result<Ty, String> {
+        Ok(match literal {
+            Literal::Int(_) => Ty::Int,
+            Literal::Float(_) => Ty::Float,
+            Literal::Bool(_) => Ty::Bool,
+            Literal::String(_) => Ty::Str,
+            Literal::Char(_) => Ty::Char,
+            Literal::Null => Ty::Unit,
+            Literal::Array(_) => return Err(self.unsupported("nested array literals")),
+        })
+    }
+
+    fn variant(&self, enum_name: &str, variant_name: &str) -> Result<Vec<Ty>, String> {
+        let Some(variants) = self.enums.get(enum_name) else {
+            return Err(self.error(format!("uses the undefined enum `{}`", enum_name)));
+        };
+        match variants.iter().find(|(name, _)| name == variant_name) {
+            Some((_, payload)) => Ok(payload.clone()),
+            None => Err(self.error(format!("uses `{}::{}`, which is not a variant", enum_name, variant_name))),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        expression: &Expression,
+        operator: &BinaryOperator,
+        left: &Expression,
+        right: &Expression,
+    ) -> Result<Ty, String> {
+        let l = self.expression(left, true)?;
+        let r = self.expression(right, true)?;
+        let (lh, rh) = (self.shallow(&l), self.shallow(&r));
+        let known = !matches!(lh, Ty::Var(_)) && !matches!(rh, Ty::Var(_));
+        let Some(operator) = normalize(operator) else {
+            return Err(self.unsupported("compound assignment operators"));
+        };
+        match operator {
+            BinaryOperator::And | BinaryOperator::Or => Ok(Ty::Bool),
+            BinaryOperator::Equal | BinaryOperator::NotEqual => {
+                if !known {
+                    self.unify(&l, &r).map_err(|message| self.error(format!("compares values that {}", message)))?;
+                } else if lh != rh && !(lh.is_number() && rh.is_number()) {
+                    self.unify(&l, &r).map_err(|_| self.error(format!("compares a {} with a {}", lh, rh)))?;
+                }
+                Ok(Ty::Bool)
+            }
+            BinaryOperator::LessThan
+            | BinaryOperator::LessThanOrEqual
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanOrEqual => {
+                if known {
+                    let ordered = (lh.is_number() && rh.is_number())
+                        || (lh == rh && matches!(lh, Ty::Str | Ty::Bool | Ty::Char));
+                    if !ordered {
+                        return Err(self.error(format!("orders a {} against a {}", lh, rh)));
+                    }
+                }
+                Ok(Ty::Bool)
+            }
+            _ => {
+                let result = self.node(expression);
+                if known {
+                    let ty = match (&operator, &lh, &rh) {
+                        (BinaryOperator::Add, Ty::Str, Ty::Str | Ty::Int | Ty::Float)
+                        | (BinaryOperator::Add, Ty::Int | Ty::Float, Ty::Str) => Ty::Str,
+                        (_, Ty::Int, Ty::Int) => Ty::Int,
+                        (_, a, b) if a.is_number() && b.is_number() => Ty::Float,
+                        _ => return Err(self.error(format!("applies {:?} to a {} and a {}", operator, lh, rh))),
+                    };
+                    self.unify(&result, &ty).map_err(|message| self.error(format!("has arithmetic that {}", message)))?;
+                }
+                Ok(result)
+            }
+        }
+    }
+
+    fn call(&mut self, expression: &Expression, callee: &Expression, args: &[CallArgument]) -> Result<Ty, String> {
+        let Expression::Identifier(name) = callee else {
+            return Err(self.unsupported("calls through expressions"));
+        };
+        if args.iter().any(|arg| arg.name.is_some()) {
+            return Err(self.unsupported("keyword arguments"));
+        }
+        let mut tys = Vec::new();
+        for arg in args {
+            tys.push(self.expression(&arg.value, true)?);
+        }
+        if let Some((enum_name, variant_name)) = name.rsplit_once("::") {
+            let payload = self.variant(enum_name, variant_name)?;
+            if payload.len() != tys.len() {
+                return Err(self.error(format!("passes {} values to `{}`, which holds {}", tys.len(), name, payload.len())));
+            }
+            for (target, value) in payload.iter().zip(&tys) {
+                self.flow(target, value).map_err(|message| self.error(format!("builds `{}` from a value that {}", name, message)))?;
+            }
+            return Ok(Ty::Named(enum_name.to_string()));
+        }
+        if let Some(signature) = self.functions.get(name).cloned() {
+            if signature.params.len() != tys.len() {
+                return Err(self.error(format!(
+                    "calls `{}` with {} arguments, but it takes {}",
+                    name,
+                    tys.len(),
+                    signature.params.len()
+                )));
+            }
+            for ((param, ty), arg) in signature.params.iter().zip(&tys).zip(args) {
+                let target = self.vars[*param].ty.clone();
+                let param_name = self.vars[*param].name.clone();
+                self.flow(&target, ty).map_err(|message| {
+                    self.error(format!("passes `{}` of `{}` a value that {}", param_name, name, message))
+                })?;
+                if self.vars[*param].mutated {
+                    self.mark_mutated(&arg.value);
+                }
+            }
+            return Ok(signature.ret);
+        }
+        if is_builtin(name) {
+            return self.builtin(expression, name, args, &tys);
+        }
+        if crate::ir::Builtin::lookup(name).is_some() || crate::ir::Builtin::is_unmodelled(name) {
+            return Err(self.unsupported(&format!("the builtin '{}'", name)));
+        }
+        Err(self.error(format!("calls the undefined function `{}`", name)))
+    }
+
+    fn builtin(&mut self, expression: &Expression, name: &str, args: &[CallArgument], tys: &[Ty]) -> Result<Ty, String> {
+        let (min, max) = arity(name);
+        if tys.len() < min || tys.len() > max {
+            return Err(self.error(format!("calls `{}` with {} arguments", name, tys.len())));
+        }
+        if matches!(name, "array_push" | "array_reverse" | "array_sort") {
+            self.mark_mutated(&args[0].value);
+        }
+        let heads: Vec<Ty> = tys.iter().map(|ty| self.shallow(ty)).collect();
+        let mismatch = |types: &Types| {
+            let got: Vec<String> = heads.iter().map(|ty| ty.to_string()).collect();
+            types.error(format!("calls `{}` with {}", name, got.join(", ")))
+        };
+        // Results that do not depend on the arguments
+        let fixed = match name {
+            "print" | "println" | "eprint" | "eprintln" | "assert" | "write_file" | "append_file" | "exit" => Some(Ty::Unit),
+            "assert_eq" | "assert_ne" => {
+                if !(heads[0].is_number() && heads[1].is_number()) {
+                    self.unify(&tys[0], &tys[1]).map_err(|_| mismatch(self))?;
+                }
+                Some(Ty::Unit)
+            }
+            "len" => Some(Ty::Int),
+            "typeof" | "read_line" | "read_file" | "substring" | "concat" | "to_upper" | "to_lower" | "trim" | "join" => {
+                Some(Ty::Str)
+            }
+            "contains" | "starts_with" | "ends_with" | "file_exists" => Some(Ty::Bool),
+            "split" => Some(Ty::Vec(Box::new(Ty::Str))),
+            _ => None,
+        };
+        let expected: &[Ty] = match name {
+            "substring" => &[Ty::Str, Ty::Int, Ty::Int],
+            "concat" | "contains" | "starts_with" | "ends_with" | "split" | "write_file" | "append_file" => &[Ty::Str, Ty::Str],
+            "to_upper" | "to_lower" | "trim" | "read_file" | "file_exists" => &[Ty::Str],
+            "exit" => &[Ty::Int],
+            _ => &[],
+        };
+        for (ty, want) in tys.iter().zip(expected) {
+            self.unify(ty, want).map_err(|_| mismatch(self))?;
+        }
+        if let Some(ty) = fixed {
+            match name {
+                "len" if !matches!(heads[0], Ty::Str | Ty::Vec(_) | Ty::Var(_)) => return Err(mismatch(self)),
+                "join" => {
+                    self.unify(&tys[0], &Ty::Vec(Box::new(Ty::Str))).map_err(|_| mismatch(self))?;
+                    self.unify(&tys[1], &Ty::Str).map_err(|_| mismatch(self))?;
+                }
+                _ => {}
+            }
+            return Ok(ty);
+        }
+        let result = self.node(expression);
+        if matches!(name, "array_push" | "array_reverse" | "array_sort" | "array_slice") {
+            let element = match &heads[0] {
+                Ty::Vec(element) => (**element).clone(),
+                Ty::Var(_) => {
+                    let element = self.fresh();
+                    self.unify(&tys[0], &Ty::Vec(Box::new(element.clone())))?;
+                    element
+                }
+                _ => return Err(mismatch(self)),
+            };
+            match name {
+                "array_push" => self.flow(&element, &tys[1]).map_err(|message| self.error(format!("pushes a value that {}", message)))?,
+                "array_slice" => {
+                    self.unify(&tys[1], &Ty::Int).map_err(|_| mismatch(self))?;
+                    self.unify(&tys[2], &Ty::Int).map_err(|_| mismatch(self))?;
+                }
+                _ => {}
+            }
+            self.unify(&result, &tys[0]).map_err(|_| mismatch(self))?;
+            return Ok(result);
+        }
+        if heads.iter().any(|ty| matches!(ty, Ty::Var(_))) {
+            return Ok(result);
+        }
+        if !heads.iter().all(Ty::is_number) {
+            return Err(mismatch(self));
+        }
+        let all_int = heads.iter().all(|ty| *ty == Ty::Int);
+        let ty = match name {
+            "abs" => heads[0].clone(),
+            "sqrt" | "sin" | "cos" | "tan" => Ty::Float,
+            "floor" | "ceil" | "round" => Ty::Int,
+            "pow" | "min" | "max" if all_int => Ty::Int,
+            _ => Ty::Float,
+        };
+        self.unify(&result, &ty).map_err(|message| self.error(format!("calls `{}`, which {}", name, message)))?;
+        Ok(result)
+    }
+
+    fn match_expression(&mut self, expression: &Expression, matched: &MatchExpression, value: bool) -> Result<Ty, String> {
+        let subject = self.expression(&matched.expression, true)?;
+        let result = self.node(expression);
+        for arm in &matched.arms {
+            if arm.guard.is_some() {
+                return Err(self.unsupported("match guards"));
+            }
+            self.scopes.push(HashMap::new());
+            let outcome = self.pattern(&arm.pattern, &subject).and_then(|()| match &*arm.body {
+                Expression::Block(statements) => self.statements(statements).map(|()| Ty::Unit),
+                body => self.expression(body, value),
+            });
+            self.scopes.pop();
+            let ty = outcome?;
+            if value {
+                self.flow(&result, &ty).map_err(|message| self.error(format!("has match arms that {}", message)))?;
+            }
+        }
+        Ok(if value { result } else { Ty::Unit })
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, subject: &Ty) -> Result<(), String> {
+        match pattern {
+            Pattern::Wildcard => Ok(()),
+            Pattern::Identifier(name) => {
+                let id = match self.sites.get(&key(pattern)) {
+                    Some(id) => *id,
+                    None => {
+                        let ty = self.fresh();
+                        let id = self.new_var(name, ty, VarKind::Binding);
+                        self.sites.insert(key(pattern), id);
+                        id
+                    }
+                };
+                let ty = self.vars[id].ty.clone();
+                self.unify(&ty, subject)?;
+                self.declare(name, id);
+                Ok(())
+            }
+            Pattern::Literal(Literal::Float(_)) => Err(self.unsupported("Float patterns")),
+            Pattern::Literal(literal) => {
+                let ty = self.literal(literal)?;
+                let head = self.shallow(subject);
+                self.unify(subject, &ty).map_err(|_| self.error(format!("matches a {} against a {} pattern", head, ty)))
+            }
+            Pattern::Struct(path, fields) => {
+                let Some((enum_name, variant_name)) = path.rsplit_once("::") else {
+                    return Err(self.unsupported("struct patterns"));
+                };
+                let payload = self.variant(enum_name, variant_name)?;
+                let head = self.shallow(subject);
+                self.unify(subject, &Ty::Named(enum_name.to_string()))
+                    .map_err(|_| self.error(format!("matches a {} against `{}`", head, path)))?;
+                if payload.len() != fields.len() {
+                    return Err(self.error(format!("matches `{}` with {} patterns, but it holds {}", path, fields.len(), payload.len())));
+                }
+                fields.iter().zip(&payload).try_for_each(|(field, ty)| self.pattern(field, ty))
+            }
+            _ => Err(self.unsupported("this pattern")),
+        }
+    }
+
+    /// Settle what nothing constrains: arrays that are never filled hold Ints and functions
+    /// that never return a value return `()`. Returns whether anything changed.
+    fn apply_defaults(&mut self) -> Result<bool, String> {
+        let mut open = Vec::new();
+        for signature in self.functions.values() {
+            open.push(signature.ret.clone());
+        }
+        for var in &self.vars {
+            open.push(var.ty.clone());
+        }
+        for fields in self.structs.values() {
+            open.extend(fields.iter().map(|(_, ty)| ty.clone()));
+        }
+        for variants in self.enums.values() {
+            open.extend(variants.iter().flat_map(|(_, payload)| payload.iter().cloned()));
+        }
+        let returns = self.functions.len();
+        let mut changed = false;
+        for (index, ty) in open.into_iter().enumerate() {
+            match self.shallow(&ty) {
+                Ty::Var(var) if index < returns => {
+                    self.bind(var, Ty::Unit);
+                    changed = true;
+                }
+                Ty::Vec(element) => {
+                    if let Ty::Var(var) = self.shallow(&element) {
+                        self.bind(var, Ty::Int);
+                        changed = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !changed {
+            if let Some(var) = self.vars.iter().find(|var| matches!(self.shallow(&var.ty), Ty::Var(_))) {
+                let name = var.name.clone();
+                self.function = var.owner.clone();
+                return Err(self.error(format!("needs a type for `{}` that the transpiler cannot infer", name)));
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// `xs = array_push(xs, v)` and friends, which the VM performs in place
+pub fn in_place_update<'a>(name: &str, value: &'a Expression) -> Option<&'a str> {
+    let Expression::FunctionCall(callee, args) = value else { return None };
+    let Expression::Identifier(function) = &**callee else { return None };
+    let target = matches!(args.first().map(|arg| &arg.value), Some(Expression::Identifier(first)) if first == name);
+    (target && matches!(function.as_str(), "array_push" | "array_reverse" | "array_sort")).then_some(function.as_str())
+}
+
+pub fn normalize(operator: &BinaryOperator) -> Option<BinaryOperator> {
+    use BinaryOperator::*;
+    Some(match operator {
+        Sub => Subtract,
+        Mul => Multiply,
+        Div => Divide,
+        Eq => Equal,
+        Ne => NotEqual,
+        Lt => LessThan,
+        Le => LessThanOrEqual,
+        Gt => GreaterThan,
+        Ge => GreaterThanOrEqual,
+        Assign | AddAssign | SubtractAssign | MultiplyAssign | DivideAssign => return None,
+        other => other.clone(),
+    })
+}
+
+pub fn describe(expression: &Expression) -> &'static str {
+    match expression {
+        Expression::TryCatch(_) | Expression::Try(_) => "try/catch",
+        Expression::Throw(_) => "throw",
+        Expression::Lambda(_) => "closures",
+        Expression::DictLiteral(_) => "dictionary literals",
+        Expression::SetLiteral(_) => "set literals",
+        Expression::ListComprehension(_) => "list comprehensions",
+        Expression::Spawn(_) | Expression::Join(_) | Expression::Channel(_) => "threads",
+        Expression::Slice(_) => "slices",
+        Expression::Pipeline(_) => "pipelines",
+        Expression::Assignment(_) => "assignments inside expressions",
+        Expression::Return(_) => "`return` inside an expression",
+        _ => "this expression",
+    }
+}
@@ -1,5 +1,9 @@
-use crate::ast::{Expression, Statement, Type, Literal, UnaryOperator, BinaryOp};
+use crate::ast::{
+    Expression, Statement, Type, Literal, UnaryOperator, BinaryOp, FunctionStatement, LetStatement, MatchArm,
+    Parameter, Pattern,
+};
 use crate::error::CompilerError;
+use crate::visit::{self, Visitor};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
@@ -145,14 +149,47 @@ impl TypeInferrer {
             Statement::ExpressionStatement { expression } => {
                 self.infer_expression(expression)?;
             }
+            Statement::Let(let_stmt) => self.infer_let(let_stmt)?,
+            Statement::Function(FunctionStatement { name, parameters, return_type, body, .. }) => {
+                let param_types = parameters.iter().map(|param| param.type_annotation.clone()).collect();
+                let func_type = Type::Function(param_types, Box::new(return_type.clone().unwrap_or(Type::Void)));
+                self.context.declare_function(name, func_type);
+                self.infer_scoped(parameters, body)?;
+            }
             _ => {
-                // For other statement types, do basic checking
-                return Ok(());
+                // For other statement types, check the expressions they contain
+                let mut children = ChildInferrer { inferrer: self, result: Ok(()) };
+                visit::walk_statement(&mut children, statement);
+                return children.result;
+            }
+        }
+        Ok(())
+    }
+
+    fn infer_let(&mut self, let_stmt: &LetStatement) -> Result<(), CompilerError> {
+        let inferred_type = self.infer_expression(&let_stmt.value)?;
+        if let Some(annotated_type) = &let_stmt.type_annotation {
+            if !self.types_compatible(&inferred_type, annotated_type) {
+                return Err(CompilerError::type_error(
+                    &format!("Type mismatch: expected {}, got {}", annotated_type, inferred_type)
+                ));
             }
         }
+        self.context.declare_variable(&let_stmt.name, inferred_type);
         Ok(())
     }
 
+    /// Infers `body` in a new scope holding `parameters`
+    fn infer_scoped(&mut self, parameters: &[Parameter], body: &Expression) -> Result<Type, CompilerError> {
+        self.context.enter_scope();
+        for param in parameters {
+            self.context.declare_variable(&param.name, param.type_annotation.clone());
+        }
+        let body_type = self.infer_expression(body);
+        self.context.exit_scope();
+        body_type
+    }
+
     fn infer_expression(&mut self, expression: &Expression) -> Result<Type, CompilerError> {
         match expression {
             Expression::Literal(literal) => {
@@ -168,6 +205,7 @@ impl TypeInferrer {
             }
             Expression::Identifier(name) => {
                 self.context.get_variable_type(name)
+                    .or_else(|| self.context.get_function_type(name))
                     .ok_or_else(|| CompilerError::type_error(&format!("Undefined variable: {}", name)))
             }
 
@@ -267,9 +305,23 @@ impl TypeInferrer {
                     Ok(Type::Void)
                 }
             }
+            Expression::Block(statements) => {
+                self.context.enter_scope();
+                let result = statements.iter().try_for_each(|stmt| self.infer_statement(stmt));
+                self.context.exit_scope();
+                result.map(|_| Type::Any)
+            }
+            Expression::Let(let_stmt) => self.infer_let(let_stmt).map(|_| Type::Void),
+            Expression::Lambda(lambda) => {
+                let body_type = self.infer_scoped(&lambda.parameters, &lambda.body)?;
+                let param_types = lambda.parameters.iter().map(|param| param.type_annotation.clone()).collect();
+                Ok(Type::Function(param_types, Box::new(body_type)))
+            }
             _ => {
-                // For other expression types, return a default type
-                Ok(Type::Any)
+                // For other expression types, check the children and return a default type
+                let mut children = ChildInferrer { inferrer: self, result: Ok(()) };
+                visit::walk_expression(&mut children, expression);
+                children.result.map(|_| Type::Any)
             }
         }
     }
@@ -287,4 +339,57 @@ impl Default for TypeInferrer {
     fn default() -> Self {
         Self::new()
     }
-} 
\ No newline at end of file
+}
+
+/// Runs inference on each child node it is given, keeping the first error
+struct ChildInferrer<'a> {
+    inferrer: &'a mut TypeInferrer,
+    result: Result<(), CompilerError>,
+}
+
+impl Visitor for ChildInferrer<'_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if self.result.is_ok() {
+            self.result = self.inferrer.infer_statement(statement);
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if self.result.is_ok() {
+            self.result = self.inferrer.infer_expression(expression).map(|_| ());
+        }
+    }
+
+    fn visit_function(&mut self, function: &FunctionStatement) {
+        if self.result.is_ok() {
+            self.result = self.inferrer.infer_scoped(&function.parameters, &function.body).map(|_| ());
+        }
+    }
+
+    fn visit_match_arm(&mut self, arm: &MatchArm) {
+        if let Pattern::Identifier(name) = &arm.pattern {
+            self.inferrer.context.declare_variable(name, Type::Any);
+        }
+        visit::walk_match_arm(self, arm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn infer(source: &str) -> Result<(), CompilerError> {
+        let tokens = Lexer::new(source, "test.nx".to_string()).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        TypeInferrer::new().infer_program(&program.statements)
+    }
+
+    #[test]
+    fn test_infers_nested_nodes() {
+        assert!(infer("fn twice(x: Int) -> Int { return x + x; }\nlet y = twice(2);\nwhile (y < 10) { let z = y; }").is_ok());
+        assert!(infer("while (true) { let z = missing; }").is_err());
+        assert!(infer("fn f() { if (true) { return 1 + missing; } }").is_err());
+    }
+}
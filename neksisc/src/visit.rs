@@ -0,0 +1,1119 @@
+// Traversal of the classic AST
+//
+// `Visitor` walks a tree by reference, `VisitorMut` walks it in place and `Fold`
+// rebuilds it by value. Every method defaults to the matching `walk_*` function,
+// which visits all of the node's children, so a pass overrides only the nodes it
+// cares about and calls `walk_*` from its override to keep descending.
+//
+// The walks match every variant without a catch-all arm, so a new AST node has to
+// be given a traversal here before anything compiles.
+//
+// `MatchArm::expression` and `FunctionStatement::signature` are copies the parser
+// keeps next to `body` and `parameters`. Only the originals are visited; the mutable
+// walks refresh the copies afterwards when they were still in sync.
+
+use crate::ast::{
+    Annotation, Expression, FunctionStatement, InterpolatedPart, Literal, MatchArm, Parameter, Pattern, Program, Statement,
+};
+
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+
+    /// A statement list: the program, a block, or a module body
+    fn visit_block(&mut self, statements: &[Statement]) {
+        walk_block(self, statements)
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement)
+    }
+
+    fn visit_function(&mut self, function: &FunctionStatement) {
+        walk_function(self, function)
+    }
+
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        walk_parameter(self, parameter)
+    }
+
+    fn visit_annotation(&mut self, annotation: &Annotation) {
+        walk_annotation(self, annotation)
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression)
+    }
+
+    fn visit_match_arm(&mut self, arm: &MatchArm) {
+        walk_match_arm(self, arm)
+    }
+
+    fn visit_pattern(&mut self, _pattern: &Pattern) {}
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for annotation in &program.annotations {
+        visitor.visit_annotation(annotation);
+    }
+    visitor.visit_block(&program.statements);
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, statements: &[Statement]) {
+    for statement in statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::Expression(expression) => visitor.visit_expression(expression),
+        Statement::Let(let_stmt) => visitor.visit_expression(&let_stmt.value),
+        Statement::Return(return_stmt) => {
+            if let Some(value) = &return_stmt.value {
+                visitor.visit_expression(value);
+            }
+        }
+        Statement::Function(function) => visitor.visit_function(function),
+        Statement::Struct(struct_stmt) => {
+            for annotation in &struct_stmt.annotations {
+                visitor.visit_annotation(annotation);
+            }
+        }
+        Statement::Enum(enum_stmt) => {
+            for annotation in &enum_stmt.annotations {
+                visitor.visit_annotation(annotation);
+            }
+        }
+        Statement::Trait(trait_stmt) => {
+            for annotation in &trait_stmt.annotations {
+                visitor.visit_annotation(annotation);
+            }
+            for method in &trait_stmt.methods {
+                visitor.visit_function(method);
+            }
+        }
+        Statement::Impl(impl_stmt) => {
+            for method in &impl_stmt.methods {
+                visitor.visit_function(method);
+            }
+        }
+        Statement::Module(module) => visitor.visit_block(&module.statements),
+        Statement::GenericFunction(function) => {
+            for annotation in &function.annotations {
+                visitor.visit_annotation(annotation);
+            }
+            for parameter in &function.parameters {
+                visitor.visit_parameter(parameter);
+            }
+            visitor.visit_expression(&function.body);
+        }
+        Statement::Class(class) => {
+            for annotation in &class.annotations {
+                visitor.visit_annotation(annotation);
+            }
+            for method in &class.methods {
+                visitor.visit_function(method);
+            }
+        }
+        Statement::LetStatement { value, .. } | Statement::AssignmentStatement { value, .. } => {
+            visitor.visit_expression(value)
+        }
+        Statement::FunctionStatement { parameters, body, .. } => {
+            for parameter in parameters {
+                visitor.visit_parameter(parameter);
+            }
+            visitor.visit_expression(body);
+        }
+        Statement::ReturnStatement { value } => {
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+        Statement::ExpressionStatement { expression } => visitor.visit_expression(expression),
+        Statement::Use(_) | Statement::Extern(_) | Statement::PythonImport(_) | Statement::Move(_) | Statement::Drop(_) | Statement::SourceLine(_) => {}
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, function: &FunctionStatement) {
+    for annotation in &function.annotations {
+        visitor.visit_annotation(annotation);
+    }
+    for parameter in &function.parameters {
+        visitor.visit_parameter(parameter);
+    }
+    visitor.visit_expression(&function.body);
+}
+
+pub fn walk_parameter<V: Visitor + ?Sized>(visitor: &mut V, parameter: &Parameter) {
+    if let Some(default) = &parameter.default_value {
+        visitor.visit_expression(default);
+    }
+}
+
+pub fn walk_annotation<V: Visitor + ?Sized>(visitor: &mut V, annotation: &Annotation) {
+    for argument in &annotation.arguments {
+        visitor.visit_expression(argument);
+    }
+}
+
+pub fn walk_match_arm<V: Visitor + ?Sized>(visitor: &mut V, arm: &MatchArm) {
+    visitor.visit_pattern(&arm.pattern);
+    if let Some(guard) = &arm.guard {
+        visitor.visit_expression(guard);
+    }
+    if arm.expression != arm.body {
+        visitor.visit_expression(&arm.expression);
+    }
+    visitor.visit_expression(&arm.body);
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match expression {
+        Expression::Literal(_)
+        | Expression::Identifier(_)
+        | Expression::Move(_)
+        | Expression::Drop(_)
+        | Expression::EnumVariantAccess { .. } => {}
+        Expression::BinaryOp(binary) => {
+            visitor.visit_expression(&binary.left);
+            visitor.visit_expression(&binary.right);
+        }
+        Expression::BinaryOperation { left, right, .. } | Expression::BinaryExpression { left, right, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Expression::UnaryOp(unary) => visitor.visit_expression(&unary.operand),
+        Expression::UnaryExpression { operand, .. } => visitor.visit_expression(operand),
+        Expression::FunctionCall(callee, arguments) => {
+            visitor.visit_expression(callee);
+            for argument in arguments {
+                visitor.visit_expression(&argument.value);
+            }
+        }
+        Expression::CallExpression { arguments, .. } | Expression::BuiltinFunction { arguments, .. } => {
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Expression::If(if_expr) => {
+            visitor.visit_expression(&if_expr.condition);
+            visitor.visit_expression(&if_expr.then_branch);
+            if let Some(else_branch) = &if_expr.else_branch {
+                visitor.visit_expression(else_branch);
+            }
+        }
+        Expression::IfExpression { condition, then_branch, else_branch } => {
+            visitor.visit_expression(condition);
+            visitor.visit_expression(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_expression(else_branch);
+            }
+        }
+        Expression::While(while_expr) => {
+            visitor.visit_expression(&while_expr.condition);
+            visitor.visit_expression(&while_expr.body);
+        }
+        Expression::Loop(loop_expr) => visitor.visit_expression(&loop_expr.body),
+        Expression::Block(statements) | Expression::BlockExpression { statements } => visitor.visit_block(statements),
+        Expression::Return(value) => {
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+        Expression::Let(let_stmt) => visitor.visit_expression(&let_stmt.value),
+        Expression::Assignment(assignment) => visitor.visit_expression(&assignment.value),
+        Expression::Malloc(malloc) => visitor.visit_expression(&malloc.size),
+        Expression::Free(free) => visitor.visit_expression(&free.pointer),
+        Expression::Realloc(realloc) => {
+            visitor.visit_expression(&realloc.pointer);
+            visitor.visit_expression(&realloc.new_size);
+        }
+        Expression::TryCatch(try_catch) => {
+            visitor.visit_expression(&try_catch.try_block);
+            visitor.visit_expression(&try_catch.catch_block);
+        }
+        Expression::Borrow(borrow) => visitor.visit_expression(&borrow.expression),
+        Expression::BorrowMut(borrow) => visitor.visit_expression(&borrow.expression),
+        Expression::Clone(clone) => visitor.visit_expression(&clone.expression),
+        Expression::StructLiteral(literal) => {
+            for (_, value) in &literal.fields {
+                visitor.visit_expression(value);
+            }
+        }
+        Expression::MemberAccess(access) => visitor.visit_expression(&access.object),
+        Expression::ArrayAccess(access) => {
+            visitor.visit_expression(&access.array);
+            visitor.visit_expression(&access.index);
+        }
+        Expression::Box(inner) => visitor.visit_expression(&inner.value),
+        Expression::Rc(inner) => visitor.visit_expression(&inner.value),
+        Expression::Arc(inner) => visitor.visit_expression(&inner.value),
+        Expression::Cell(inner) => visitor.visit_expression(&inner.value),
+        Expression::RefCell(inner) => visitor.visit_expression(&inner.value),
+        Expression::Lifetime(lifetime) => visitor.visit_expression(&lifetime.expression),
+        Expression::Match(match_expr) => {
+            visitor.visit_expression(&match_expr.expression);
+            for arm in &match_expr.arms {
+                visitor.visit_match_arm(arm);
+            }
+        }
+        Expression::Spawn(spawn) => visitor.visit_expression(&spawn.expression),
+        Expression::Join(join) => visitor.visit_expression(&join.handle),
+        Expression::Channel(channel) => {
+            if let Some(capacity) = &channel.capacity {
+                visitor.visit_expression(capacity);
+            }
+        }
+        Expression::Try(try_expr) => visitor.visit_expression(&try_expr.expression),
+        Expression::Pipeline(pipeline) => {
+            for stage in &pipeline.stages {
+                visitor.visit_expression(stage);
+            }
+        }
+        Expression::Throw(throw) => visitor.visit_expression(&throw.value),
+        Expression::Lambda(lambda) => {
+            for parameter in &lambda.parameters {
+                visitor.visit_parameter(parameter);
+            }
+            visitor.visit_expression(&lambda.body);
+        }
+        Expression::DictLiteral(dict) => {
+            for (key, value) in &dict.entries {
+                visitor.visit_expression(key);
+                visitor.visit_expression(value);
+            }
+        }
+        Expression::SetLiteral(set) => {
+            for element in &set.elements {
+                visitor.visit_expression(element);
+            }
+        }
+        Expression::InterpolatedString(interpolated) => {
+            for part in &interpolated.parts {
+                if let InterpolatedPart::Expr(expr) = part {
+                    visitor.visit_expression(expr);
+                }
+            }
+        }
+        Expression::ListComprehension(comprehension) => {
+            visitor.visit_expression(&comprehension.iterable);
+            if let Some(condition) = &comprehension.condition {
+                visitor.visit_expression(condition);
+            }
+            visitor.visit_expression(&comprehension.element);
+        }
+        Expression::Slice(slice) => {
+            visitor.visit_expression(&slice.collection);
+            for bound in [&slice.start, &slice.end, &slice.step].into_iter().flatten() {
+                visitor.visit_expression(bound);
+            }
+        }
+        Expression::ReferenceExpression { target, .. } | Expression::DereferenceExpression { target } => {
+            visitor.visit_expression(target)
+        }
+    }
+}
+
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program)
+    }
+
+    /// A statement list: the program, a block, or a module body
+    fn visit_block_mut(&mut self, statements: &mut Vec<Statement>) {
+        walk_block_mut(self, statements)
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement)
+    }
+
+    fn visit_function_mut(&mut self, function: &mut FunctionStatement) {
+        walk_function_mut(self, function)
+    }
+
+    fn visit_parameter_mut(&mut self, parameter: &mut Parameter) {
+        walk_parameter_mut(self, parameter)
+    }
+
+    fn visit_annotation_mut(&mut self, annotation: &mut Annotation) {
+        walk_annotation_mut(self, annotation)
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression)
+    }
+
+    fn visit_match_arm_mut(&mut self, arm: &mut MatchArm) {
+        walk_match_arm_mut(self, arm)
+    }
+
+    fn visit_pattern_mut(&mut self, _pattern: &mut Pattern) {}
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for annotation in &mut program.annotations {
+        visitor.visit_annotation_mut(annotation);
+    }
+    visitor.visit_block_mut(&mut program.statements);
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statements: &mut Vec<Statement>) {
+    for statement in statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::Expression(expression) => visitor.visit_expression_mut(expression),
+        Statement::Let(let_stmt) => visitor.visit_expression_mut(&mut let_stmt.value),
+        Statement::Return(return_stmt) => {
+            if let Some(value) = &mut return_stmt.value {
+                visitor.visit_expression_mut(value);
+            }
+        }
+        Statement::Function(function) => visitor.visit_function_mut(function),
+        Statement::Struct(struct_stmt) => {
+            for annotation in &mut struct_stmt.annotations {
+                visitor.visit_annotation_mut(annotation);
+            }
+        }
+        Statement::Enum(enum_stmt) => {
+            for annotation in &mut enum_stmt.annotations {
+                visitor.visit_annotation_mut(annotation);
+            }
+        }
+        Statement::Trait(trait_stmt) => {
+            for annotation in &mut trait_stmt.annotations {
+                visitor.visit_annotation_mut(annotation);
+            }
+            for method in &mut trait_stmt.methods {
+                visitor.visit_function_mut(method);
+            }
+        }
+        Statement::Impl(impl_stmt) => {
+            for method in &mut impl_stmt.methods {
+                visitor.visit_function_mut(method);
+            }
+        }
+        Statement::Module(module) => visitor.visit_block_mut(&mut module.statements),
+        Statement::GenericFunction(function) => {
+            for annotation in &mut function.annotations {
+                visitor.visit_annotation_mut(annotation);
+            }
+            for parameter in &mut function.parameters {
+                visitor.visit_parameter_mut(parameter);
+            }
+            visitor.visit_expression_mut(&mut function.body);
+        }
+        Statement::Class(class) => {
+            for annotation in &mut class.annotations {
+                visitor.visit_annotation_mut(annotation);
+            }
+            for method in &mut class.methods {
+                visitor.visit_function_mut(method);
+            }
+        }
+        Statement::LetStatement { value, .. } | Statement::AssignmentStatement { value, .. } => {
+            visitor.visit_expression_mut(value)
+        }
+        Statement::FunctionStatement { parameters, body, .. } => {
+            for parameter in parameters {
+                visitor.visit_parameter_mut(parameter);
+            }
+            visitor.visit_expression_mut(body);
+        }
+        Statement::ReturnStatement { value } => {
+            if let Some(value) = value {
+                visitor.visit_expression_mut(value);
+            }
+        }
+        Statement::ExpressionStatement { expression } => visitor.visit_expression_mut(expression),
+        Statement::Use(_) | Statement::Extern(_) | Statement::PythonImport(_) | Statement::Move(_) | Statement::Drop(_) | Statement::SourceLine(_) => {}
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(visitor: &mut V, function: &mut FunctionStatement) {
+    let synced = function.signature.parameters == function.parameters;
+    for annotation in &mut function.annotations {
+        visitor.visit_annotation_mut(annotation);
+    }
+    for parameter in &mut function.parameters {
+        visitor.visit_parameter_mut(parameter);
+    }
+    visitor.visit_expression_mut(&mut function.body);
+    if synced {
+        function.signature.parameters = function.parameters.clone();
+    }
+}
+
+pub fn walk_parameter_mut<V: VisitorMut + ?Sized>(visitor: &mut V, parameter: &mut Parameter) {
+    if let Some(default) = &mut parameter.default_value {
+        visitor.visit_expression_mut(default);
+    }
+}
+
+pub fn walk_annotation_mut<V: VisitorMut + ?Sized>(visitor: &mut V, annotation: &mut Annotation) {
+    for argument in &mut annotation.arguments {
+        visitor.visit_expression_mut(argument);
+    }
+}
+
+pub fn walk_match_arm_mut<V: VisitorMut + ?Sized>(visitor: &mut V, arm: &mut MatchArm) {
+    let synced = arm.expression == arm.body;
+    visitor.visit_pattern_mut(&mut arm.pattern);
+    if let Some(guard) = &mut arm.guard {
+        visitor.visit_expression_mut(guard);
+    }
+    if !synced {
+        visitor.visit_expression_mut(&mut arm.expression);
+    }
+    visitor.visit_expression_mut(&mut arm.body);
+    if synced {
+        arm.expression = arm.body.clone();
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::Literal(_)
+        | Expression::Identifier(_)
+        | Expression::Move(_)
+        | Expression::Drop(_)
+        | Expression::EnumVariantAccess { .. } => {}
+        Expression::BinaryOp(binary) => {
+            visitor.visit_expression_mut(&mut binary.left);
+            visitor.visit_expression_mut(&mut binary.right);
+        }
+        Expression::BinaryOperation { left, right, .. } | Expression::BinaryExpression { left, right, .. } => {
+            visitor.visit_expression_mut(left);
+            visitor.visit_expression_mut(right);
+        }
+        Expression::UnaryOp(unary) => visitor.visit_expression_mut(&mut unary.operand),
+        Expression::UnaryExpression { operand, .. } => visitor.visit_expression_mut(operand),
+        Expression::FunctionCall(callee, arguments) => {
+            visitor.visit_expression_mut(callee);
+            for argument in arguments {
+                visitor.visit_expression_mut(&mut argument.value);
+            }
+        }
+        Expression::CallExpression { arguments, .. } | Expression::BuiltinFunction { arguments, .. } => {
+            for argument in arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        }
+        Expression::If(if_expr) => {
+            visitor.visit_expression_mut(&mut if_expr.condition);
+            visitor.visit_expression_mut(&mut if_expr.then_branch);
+            if let Some(else_branch) = &mut if_expr.else_branch {
+                visitor.visit_expression_mut(else_branch);
+            }
+        }
+        Expression::IfExpression { condition, then_branch, else_branch } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_expression_mut(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_expression_mut(else_branch);
+            }
+        }
+        Expression::While(while_expr) => {
+            visitor.visit_expression_mut(&mut while_expr.condition);
+            visitor.visit_expression_mut(&mut while_expr.body);
+        }
+        Expression::Loop(loop_expr) => visitor.visit_expression_mut(&mut loop_expr.body),
+        Expression::Block(statements) | Expression::BlockExpression { statements } => {
+            visitor.visit_block_mut(statements)
+        }
+        Expression::Return(value) => {
+            if let Some(value) = value {
+                visitor.visit_expression_mut(value);
+            }
+        }
+        Expression::Let(let_stmt) => visitor.visit_expression_mut(&mut let_stmt.value),
+        Expression::Assignment(assignment) => visitor.visit_expression_mut(&mut assignment.value),
+        Expression::Malloc(malloc) => visitor.visit_expression_mut(&mut malloc.size),
+        Expression::Free(free) => visitor.visit_expression_mut(&mut free.pointer),
+        Expression::Realloc(realloc) => {
+            visitor.visit_expression_mut(&mut realloc.pointer);
+            visitor.visit_expression_mut(&mut realloc.new_size);
+        }
+        Expression::TryCatch(try_catch) => {
+            visitor.visit_expression_mut(&mut try_catch.try_block);
+            visitor.visit_expression_mut(&mut try_catch.catch_block);
+        }
+        Expression::Borrow(borrow) => visitor.visit_expression_mut(&mut borrow.expression),
+        Expression::BorrowMut(borrow) => visitor.visit_expression_mut(&mut borrow.expression),
+        Expression::Clone(clone) => visitor.visit_expression_mut(&mut clone.expression),
+        Expression::StructLiteral(literal) => {
+            for (_, value) in &mut literal.fields {
+                visitor.visit_expression_mut(value);
+            }
+        }
+        Expression::MemberAccess(access) => visitor.visit_expression_mut(&mut access.object),
+        Expression::ArrayAccess(access) => {
+            visitor.visit_expression_mut(&mut access.array);
+            visitor.visit_expression_mut(&mut access.index);
+        }
+        Expression::Box(inner) => visitor.visit_expression_mut(&mut inner.value),
+        Expression::Rc(inner) => visitor.visit_expression_mut(&mut inner.value),
+        Expression::Arc(inner) => visitor.visit_expression_mut(&mut inner.value),
+        Expression::Cell(inner) => visitor.visit_expression_mut(&mut inner.value),
+        Expression::RefCell(inner) => visitor.visit_expression_mut(&mut inner.value),
+        Expression::Lifetime(lifetime) => visitor.visit_expression_mut(&mut lifetime.expression),
+        Expression::Match(match_expr) => {
+            visitor.visit_expression_mut(&mut match_expr.expression);
+            for arm in &mut match_expr.arms {
+                visitor.visit_match_arm_mut(arm);
+            }
+        }
+        Expression::Spawn(spawn) => visitor.visit_expression_mut(&mut spawn.expression),
+        Expression::Join(join) => visitor.visit_expression_mut(&mut join.handle),
+        Expression::Channel(channel) => {
+            if let Some(capacity) = &mut channel.capacity {
+                visitor.visit_expression_mut(capacity);
+            }
+        }
+        Expression::Try(try_expr) => visitor.visit_expression_mut(&mut try_expr.expression),
+        Expression::Pipeline(pipeline) => {
+            for stage in &mut pipeline.stages {
+                visitor.visit_expression_mut(stage);
+            }
+        }
+        Expression::Throw(throw) => visitor.visit_expression_mut(&mut throw.value),
+        Expression::Lambda(lambda) => {
+            for parameter in &mut lambda.parameters {
+                visitor.visit_parameter_mut(parameter);
+            }
+            visitor.visit_expression_mut(&mut lambda.body);
+        }
+        Expression::DictLiteral(dict) => {
+            for (key, value) in &mut dict.entries {
+                visitor.visit_expression_mut(key);
+                visitor.visit_expression_mut(value);
+            }
+        }
+        Expression::SetLiteral(set) => {
+            for element in &mut set.elements {
+                visitor.visit_expression_mut(element);
+            }
+        }
+        Expression::InterpolatedString(interpolated) => {
+            for part in &mut interpolated.parts {
+                if let InterpolatedPart::Expr(expr) = part {
+                    visitor.visit_expression_mut(expr);
+                }
+            }
+        }
+        Expression::ListComprehension(comprehension) => {
+            visitor.visit_expression_mut(&mut comprehension.iterable);
+            if let Some(condition) = &mut comprehension.condition {
+                visitor.visit_expression_mut(condition);
+            }
+            visitor.visit_expression_mut(&mut comprehension.element);
+        }
+        Expression::Slice(slice) => {
+            visitor.visit_expression_mut(&mut slice.collection);
+            for bound in [&mut slice.start, &mut slice.end, &mut slice.step].into_iter().flatten() {
+                visitor.visit_expression_mut(bound);
+            }
+        }
+        Expression::ReferenceExpression { target, .. } | Expression::DereferenceExpression { target } => {
+            visitor.visit_expression_mut(target)
+        }
+    }
+}
+
+pub trait Fold {
+    fn fold_program(&mut self, program: Program) -> Program {
+        fold_program_children(self, program)
+    }
+
+    /// A statement list: the program, a block, or a module body. Returning fewer or
+    /// more statements than were passed in removes or inserts code.
+    fn fold_block(&mut self, statements: Vec<Statement>) -> Vec<Statement> {
+        statements.into_iter().map(|statement| self.fold_statement(statement)).collect()
+    }
+
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        fold_statement_children(self, statement)
+    }
+
+    fn fold_function(&mut self, function: FunctionStatement) -> FunctionStatement {
+        fold_function_children(self, function)
+    }
+
+    fn fold_parameter(&mut self, parameter: Parameter) -> Parameter {
+        fold_parameter_children(self, parameter)
+    }
+
+    fn fold_annotation(&mut self, annotation: Annotation) -> Annotation {
+        fold_annotation_children(self, annotation)
+    }
+
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        fold_expression_children(self, expression)
+    }
+
+    fn fold_match_arm(&mut self, arm: MatchArm) -> MatchArm {
+        fold_match_arm_children(self, arm)
+    }
+
+    fn fold_pattern(&mut self, pattern: Pattern) -> Pattern {
+        pattern
+    }
+}
+
+/// Folds a boxed expression in place, reusing its allocation
+fn fold_boxed<F: Fold + ?Sized>(folder: &mut F, mut expression: Box<Expression>) -> Box<Expression> {
+    let taken = std::mem::replace(&mut *expression, Expression::Literal(Literal::Null));
+    *expression = folder.fold_expression(taken);
+    expression
+}
+
+fn fold_optional<F: Fold + ?Sized>(folder: &mut F, expression: Option<Box<Expression>>) -> Option<Box<Expression>> {
+    expression.map(|expression| fold_boxed(folder, expression))
+}
+
+fn fold_expressions<F: Fold + ?Sized>(folder: &mut F, expressions: Vec<Expression>) -> Vec<Expression> {
+    expressions.into_iter().map(|expression| folder.fold_expression(expression)).collect()
+}
+
+fn fold_annotations<F: Fold + ?Sized>(folder: &mut F, annotations: Vec<Annotation>) -> Vec<Annotation> {
+    annotations.into_iter().map(|annotation| folder.fold_annotation(annotation)).collect()
+}
+
+fn fold_parameters<F: Fold + ?Sized>(folder: &mut F, parameters: Vec<Parameter>) -> Vec<Parameter> {
+    parameters.into_iter().map(|parameter| folder.fold_parameter(parameter)).collect()
+}
+
+fn fold_functions<F: Fold + ?Sized>(folder: &mut F, functions: Vec<FunctionStatement>) -> Vec<FunctionStatement> {
+    functions.into_iter().map(|function| folder.fold_function(function)).collect()
+}
+
+pub fn fold_program_children<F: Fold + ?Sized>(folder: &mut F, program: Program) -> Program {
+    Program {
+        annotations: fold_annotations(folder, program.annotations),
+        statements: folder.fold_block(program.statements),
+    }
+}
+
+pub fn fold_statement_children<F: Fold + ?Sized>(folder: &mut F, statement: Statement) -> Statement {
+    match statement {
+        Statement::Expression(expression) => Statement::Expression(folder.fold_expression(expression)),
+        Statement::Let(mut let_stmt) => {
+            let_stmt.value = fold_boxed(folder, let_stmt.value);
+            Statement::Let(let_stmt)
+        }
+        Statement::Return(mut return_stmt) => {
+            return_stmt.value = fold_optional(folder, return_stmt.value);
+            Statement::Return(return_stmt)
+        }
+        Statement::Function(function) => Statement::Function(folder.fold_function(function)),
+        Statement::Struct(mut struct_stmt) => {
+            struct_stmt.annotations = fold_annotations(folder, struct_stmt.annotations);
+            Statement::Struct(struct_stmt)
+        }
+        Statement::Enum(mut enum_stmt) => {
+            enum_stmt.annotations = fold_annotations(folder, enum_stmt.annotations);
+            Statement::Enum(enum_stmt)
+        }
+        Statement::Trait(mut trait_stmt) => {
+            trait_stmt.annotations = fold_annotations(folder, trait_stmt.annotations);
+            trait_stmt.methods = fold_functions(folder, trait_stmt.methods);
+            Statement::Trait(trait_stmt)
+        }
+        Statement::Impl(mut impl_stmt) => {
+            impl_stmt.methods = fold_functions(folder, impl_stmt.methods);
+            Statement::Impl(impl_stmt)
+        }
+        Statement::Module(mut module) => {
+            module.statements = folder.fold_block(module.statements);
+            Statement::Module(module)
+        }
+        Statement::GenericFunction(mut function) => {
+            function.annotations = fold_annotations(folder, function.annotations);
+            function.parameters = fold_parameters(folder, function.parameters);
+            function.body = fold_boxed(folder, function.body);
+            Statement::GenericFunction(function)
+        }
+        Statement::Class(mut class) => {
+            class.annotations = fold_annotations(folder, class.annotations);
+            class.methods = fold_functions(folder, class.methods);
+            Statement::Class(class)
+        }
+        Statement::LetStatement { name, value, var_type } => {
+            Statement::LetStatement { name, value: fold_boxed(folder, value), var_type }
+        }
+        Statement::AssignmentStatement { name, value } => {
+            Statement::AssignmentStatement { name, value: fold_boxed(folder, value) }
+        }
+        Statement::FunctionStatement { name, parameters, return_type, body } => Statement::FunctionStatement {
+            name,
+            parameters: fold_parameters(folder, parameters),
+            return_type,
+            body: fold_boxed(folder, body),
+        },
+        Statement::ReturnStatement { value } => Statement::ReturnStatement { value: fold_optional(folder, value) },
+        Statement::ExpressionStatement { expression } => {
+            Statement::ExpressionStatement { expression: fold_boxed(folder, expression) }
+        }
+        statement @ (Statement::Use(_) | Statement::Extern(_) | Statement::PythonImport(_) | Statement::Move(_) | Statement::Drop(_) | Statement::SourceLine(_)) => {
+            statement
+        }
+    }
+}
+
+pub fn fold_function_children<F: Fold + ?Sized>(folder: &mut F, mut function: FunctionStatement) -> FunctionStatement {
+    let synced = function.signature.parameters == function.parameters;
+    function.annotations = fold_annotations(folder, function.annotations);
+    function.parameters = fold_parameters(folder, function.parameters);
+    function.body = fold_boxed(folder, function.body);
+    if synced {
+        function.signature.parameters = function.parameters.clone();
+    }
+    function
+}
+
+pub fn fold_parameter_children<F: Fold + ?Sized>(folder: &mut F, mut parameter: Parameter) -> Parameter {
+    parameter.default_value = fold_optional(folder, parameter.default_value);
+    parameter
+}
+
+pub fn fold_annotation_children<F: Fold + ?Sized>(folder: &mut F, mut annotation: Annotation) -> Annotation {
+    annotation.arguments = fold_expressions(folder, annotation.arguments);
+    annotation
+}
+
+pub fn fold_match_arm_children<F: Fold + ?Sized>(folder: &mut F, mut arm: MatchArm) -> MatchArm {
+    let synced = arm.expression == arm.body;
+    arm.pattern = folder.fold_pattern(arm.pattern);
+    arm.guard = fold_optional(folder, arm.guard);
+    arm.body = fold_boxed(folder, arm.body);
+    arm.expression = if synced { arm.body.clone() } else { fold_boxed(folder, arm.expression) };
+    arm
+}
+
+pub fn fold_expression_children<F: Fold + ?Sized>(folder: &mut F, expression: Expression) -> Expression {
+    match expression {
+        expression @ (Expression::Literal(_)
+        | Expression::Identifier(_)
+        | Expression::Move(_)
+        | Expression::Drop(_)
+        | Expression::EnumVariantAccess { .. }) => expression,
+        Expression::BinaryOp(mut binary) => {
+            binary.left = fold_boxed(folder, binary.left);
+            binary.right = fold_boxed(folder, binary.right);
+            Expression::BinaryOp(binary)
+        }
+        Expression::BinaryOperation { left, operator, right } => Expression::BinaryOperation {
+            left: fold_boxed(folder, left),
+            operator,
+            right: fold_boxed(folder, right),
+        },
+        Expression::BinaryExpression { left, operator, right } => Expression::BinaryExpression {
+            left: fold_boxed(folder, left),
+            operator,
+            right: fold_boxed(folder, right),
+        },
+        Expression::UnaryOp(mut unary) => {
+            unary.operand = fold_boxed(folder, unary.operand);
+            Expression::UnaryOp(unary)
+        }
+        Expression::UnaryExpression { operator, operand } => {
+            Expression::UnaryExpression { operator, operand: fold_boxed(folder, operand) }
+        }
+        Expression::FunctionCall(callee, arguments) => {
+            let callee = fold_boxed(folder, callee);
+            let arguments = arguments
+                .into_iter()
+                .map(|mut argument| {
+                    argument.value = folder.fold_expression(argument.value);
+                    argument
+                })
+                .collect();
+            Expression::FunctionCall(callee, arguments)
+        }
+        Expression::CallExpression { function, arguments } => {
+            Expression::CallExpression { function, arguments: fold_expressions(folder, arguments) }
+        }
+        Expression::BuiltinFunction { name, arguments } => {
+            Expression::BuiltinFunction { name, arguments: fold_expressions(folder, arguments) }
+        }
+        Expression::If(mut if_expr) => {
+            if_expr.condition = fold_boxed(folder, if_expr.condition);
+            if_expr.then_branch = fold_boxed(folder, if_expr.then_branch);
+            if_expr.else_branch = fold_optional(folder, if_expr.else_branch);
+            Expression::If(if_expr)
+        }
+        Expression::IfExpression { condition, then_branch, else_branch } => Expression::IfExpression {
+            condition: fold_boxed(folder, condition),
+            then_branch: fold_boxed(folder, then_branch),
+            else_branch: fold_optional(folder, else_branch),
+        },
+        Expression::While(mut while_expr) => {
+            while_expr.condition = fold_boxed(folder, while_expr.condition);
+            while_expr.body = fold_boxed(folder, while_expr.body);
+            Expression::While(while_expr)
+        }
+        Expression::Loop(mut loop_expr) => {
+            loop_expr.body = fold_boxed(folder, loop_expr.body);
+            Expression::Loop(loop_expr)
+        }
+        Expression::Block(statements) => Expression::Block(folder.fold_block(statements)),
+        Expression::BlockExpression { statements } => {
+            Expression::BlockExpression { statements: folder.fold_block(statements) }
+        }
+        Expression::Return(value) => Expression::Return(fold_optional(folder, value)),
+        Expression::Let(mut let_stmt) => {
+            let_stmt.value = fold_boxed(folder, let_stmt.value);
+            Expression::Let(let_stmt)
+        }
+        Expression::Assignment(mut assignment) => {
+            assignment.value = fold_boxed(folder, assignment.value);
+            Expression::Assignment(assignment)
+        }
+        Expression::Malloc(mut malloc) => {
+            malloc.size = fold_boxed(folder, malloc.size);
+            Expression::Malloc(malloc)
+        }
+        Expression::Free(mut free) => {
+            free.pointer = fold_boxed(folder, free.pointer);
+            Expression::Free(free)
+        }
+        Expression::Realloc(mut realloc) => {
+            realloc.pointer = fold_boxed(folder, realloc.pointer);
+            realloc.new_size = fold_boxed(folder, realloc.new_size);
+            Expression::Realloc(realloc)
+        }
+        Expression::TryCatch(mut try_catch) => {
+            try_catch.try_block = fold_boxed(folder, try_catch.try_block);
+            try_catch.catch_block = fold_boxed(folder, try_catch.catch_block);
+            Expression::TryCatch(try_catch)
+        }
+        Expression::Borrow(mut borrow) => {
+            borrow.expression = fold_boxed(folder, borrow.expression);
+            Expression::Borrow(borrow)
+        }
+        Expression::BorrowMut(mut borrow) => {
+            borrow.expression = fold_boxed(folder, borrow.expression);
+            Expression::BorrowMut(borrow)
+        }
+        Expression::Clone(mut clone) => {
+            clone.expression = fold_boxed(folder, clone.expression);
+            Expression::Clone(clone)
+        }
+        Expression::StructLiteral(mut literal) => {
+            literal.fields = literal
+                .fields
+                .into_iter()
+                .map(|(name, value)| (name, folder.fold_expression(value)))
+                .collect();
+            Expression::StructLiteral(literal)
+        }
+        Expression::MemberAccess(mut access) => {
+            access.object = fold_boxed(folder, access.object);
+            Expression::MemberAccess(access)
+        }
+        Expression::ArrayAccess(mut access) => {
+            access.array = fold_boxed(folder, access.array);
+            access.index = fold_boxed(folder, access.index);
+            Expression::ArrayAccess(access)
+        }
+        Expression::Box(mut inner) => {
+            inner.value = fold_boxed(folder, inner.value);
+            Expression::Box(inner)
+        }
+        Expression::Rc(mut inner) => {
+            inner.value = fold_boxed(folder, inner.value);
+            Expression::Rc(inner)
+        }
+        Expression::Arc(mut inner) => {
+            inner.value = fold_boxed(folder, inner.value);
+            Expression::Arc(inner)
+        }
+        Expression::Cell(mut inner) => {
+            inner.value = fold_boxed(folder, inner.value);
+            Expression::Cell(inner)
+        }
+        Expression::RefCell(mut inner) => {
+            inner.value = fold_boxed(folder, inner.value);
+            Expression::RefCell(inner)
+        }
+        Expression::Lifetime(mut lifetime) => {
+            lifetime.expression = fold_boxed(folder, lifetime.expression);
+            Expression::Lifetime(lifetime)
+        }
+        Expression::Match(mut match_expr) => {
+            match_expr.expression = fold_boxed(folder, match_expr.expression);
+            match_expr.arms = match_expr.arms.into_iter().map(|arm| folder.fold_match_arm(arm)).collect();
+            Expression::Match(match_expr)
+        }
+        Expression::Spawn(mut spawn) => {
+            spawn.expression = fold_boxed(folder, spawn.expression);
+            Expression::Spawn(spawn)
+        }
+        Expression::Join(mut join) => {
+            join.handle = fold_boxed(folder, join.handle);
+            Expression::Join(join)
+        }
+        Expression::Channel(mut channel) => {
+            channel.capacity = fold_optional(folder, channel.capacity);
+            Expression::Channel(channel)
+        }
+        Expression::Try(mut try_expr) => {
+            try_expr.expression = fold_boxed(folder, try_expr.expression);
+            Expression::Try(try_expr)
+        }
+        Expression::Pipeline(mut pipeline) => {
+            pipeline.stages = fold_expressions(folder, pipeline.stages);
+            Expression::Pipeline(pipeline)
+        }
+        Expression::Throw(mut throw) => {
+            throw.value = fold_boxed(folder, throw.value);
+            Expression::Throw(throw)
+        }
+        Expression::Lambda(mut lambda) => {
+            lambda.parameters = fold_parameters(folder, lambda.parameters);
+            lambda.body = fold_boxed(folder, lambda.body);
+            Expression::Lambda(lambda)
+        }
+        Expression::DictLiteral(mut dict) => {
+            dict.entries = dict
+                .entries
+                .into_iter()
+                .map(|(key, value)| (folder.fold_expression(key), folder.fold_expression(value)))
+                .collect();
+            Expression::DictLiteral(dict)
+        }
+        Expression::SetLiteral(mut set) => {
+            set.elements = fold_expressions(folder, set.elements);
+            Expression::SetLiteral(set)
+        }
+        Expression::InterpolatedString(mut interpolated) => {
+            interpolated.parts = interpolated
+                .parts
+                .into_iter()
+                .map(|part| match part {
+                    InterpolatedPart::Expr(expr) => InterpolatedPart::Expr(folder.fold_expression(expr)),
+                    text => text,
+                })
+                .collect();
+            Expression::InterpolatedString(interpolated)
+        }
+        Expression::ListComprehension(mut comprehension) => {
+            comprehension.iterable = fold_boxed(folder, comprehension.iterable);
+            comprehension.condition = fold_optional(folder, comprehension.condition);
+            comprehension.element = fold_boxed(folder, comprehension.element);
+            Expression::ListComprehension(comprehension)
+        }
+        Expression::Slice(mut slice) => {
+            slice.collection = fold_boxed(folder, slice.collection);
+            slice.start = fold_optional(folder, slice.start);
+            slice.end = fold_optional(folder, slice.end);
+            slice.step = fold_optional(folder, slice.step);
+            Expression::Slice(slice)
+        }
+        Expression::ReferenceExpression { target, borrow_type } => {
+            Expression::ReferenceExpression { target: fold_boxed(folder, target), borrow_type }
+        }
+        Expression::DereferenceExpression { target } => {
+            Expression::DereferenceExpression { target: fold_boxed(folder, target) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::MatchExpression;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Program {
+        let tokens = Lexer::new(source, "test.nx".to_string()).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    #[derive(Default)]
+    struct Identifiers(Vec<String>);
+
+    impl Visitor for Identifiers {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::Identifier(name) = expression {
+                self.0.push(name.clone());
+            }
+            walk_expression(self, expression);
+        }
+    }
+
+    #[test]
+    fn test_visitor_reaches_nested_nodes() {
+        let program = parse(
+            "fn main() {
+                let f = fn(x) => x + a;
+                let s = \"total: {b}\";
+                let m = match c { 1 => d, _ => e };
+                println(g[h]);
+            }",
+        );
+        let mut identifiers = Identifiers::default();
+        identifiers.visit_program(&program);
+        assert_eq!(identifiers.0, vec!["x", "a", "b", "c", "d", "e", "println", "g", "h"]);
+    }
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            if let Expression::Identifier(name) = expression {
+                name.make_ascii_uppercase();
+            }
+            walk_expression_mut(self, expression);
+        }
+    }
+
+    #[test]
+    fn test_visitor_mut_keeps_match_arm_copies_in_sync() {
+        let mut program = parse("let y = match x { 1 => a, _ => b };");
+        Rename.visit_program_mut(&mut program);
+
+        let mut identifiers = Identifiers::default();
+        identifiers.visit_program(&program);
+        assert_eq!(identifiers.0, vec!["X", "A", "B"]);
+
+        let Statement::Let(let_stmt) = &program.statements[0] else { panic!("expected a let") };
+        let Expression::Match(MatchExpression { arms, .. }) = &*let_stmt.value else { panic!("expected a match") };
+        assert!(arms.iter().all(|arm| arm.expression == arm.body));
+    }
+
+    /// Replaces every integer literal with its double
+    struct Double;
+
+    impl Fold for Double {
+        fn fold_expression(&mut self, expression: Expression) -> Expression {
+            match expression {
+                Expression::Literal(Literal::Int(n)) => Expression::Literal(Literal::Int(n * 2)),
+                other => fold_expression_children(self, other),
+            }
+        }
+
+        fn fold_block(&mut self, statements: Vec<Statement>) -> Vec<Statement> {
+            // Drop expression statements that are bare literals
+            statements
+                .into_iter()
+                .filter(|statement| !matches!(statement, Statement::Expression(Expression::Literal(_))))
+                .map(|statement| self.fold_statement(statement))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_fold_rebuilds_the_tree() {
+        let program = parse("fn f() { 7; return 1 + [2]; } let g = fn(x) => x * 3;");
+        let folded = Double.fold_program(program);
+        assert_eq!(folded, parse("fn f() { return 2 + [2]; } let g = fn(x) => x * 6;"));
+    }
+}
@@ -1,4 +1,26 @@
+use crate::coverage::CoverageCounters;
+use crate::ffi::{FFICallback, FFIContext, FFISignature, FFIType, FFIValue, PythonInterop};
+use crate::profiler::{Profile, Profiler};
+use crate::heap_profiler::{self, HeapProfiler, HeapReport};
+use crate::jit_compiler::{JITCompiler, JitStats};
+use crate::memory_manager::{CollectorStats, CycleCollector};
+use crate::limits::{self, HeapEffect, Limit, Limits, Usage};
+use crate::permissions::Permissions;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Arrays and objects are shared, mutable containers: copying a value copies
+/// the reference, so every holder sees the same contents
+pub type ArrayRef = Rc<RefCell<Vec<VMValue>>>;
+pub type ObjectRef = Rc<RefCell<HashMap<String, VMValue>>>;
+
+/// A native function registered by an embedder, called with the arguments a
+/// program passed to it
+pub type HostFunction = Rc<dyn Fn(Vec<VMValue>) -> Result<VMValue, String>>;
 
 #[derive(Clone, Debug)]
 pub enum VMValue {
@@ -9,35 +31,135 @@ pub enum VMValue {
     Null,
     Function(String, Vec<VMValue>),
     BuiltinFunction(String),
-    Object(HashMap<String, VMValue>),
-    Array(Vec<VMValue>),
+    Object(ObjectRef),
+    Array(ArrayRef),
 }
 
 impl VMValue {
+    // A new array that is not tracked by any VM's cycle collector
+    pub fn array(items: Vec<VMValue>) -> Self {
+        VMValue::Array(Rc::new(RefCell::new(items)))
+    }
+
+    // A new object that is not tracked by any VM's cycle collector
+    pub fn object(fields: HashMap<String, VMValue>) -> Self {
+        VMValue::Object(Rc::new(RefCell::new(fields)))
+    }
+
     pub fn to_string(&self) -> String {
+        let mut out = String::new();
+        self.write_to(&mut out, &mut Vec::new());
+        out
+    }
+
+    // `open` holds the containers currently being printed, so a container
+    // that contains itself prints as `[...]` or `{...}` instead of recursing forever
+    fn write_to(&self, out: &mut String, open: &mut Vec<usize>) {
         match self {
-            VMValue::Int(i) => i.to_string(),
-            VMValue::Float(f) => f.to_string(),
-            VMValue::String(s) => s.clone(),
-            VMValue::Bool(b) => b.to_string(),
-            VMValue::Null => "null".to_string(),
-            VMValue::Function(name, _) => format!("<function {}>", name),
-            VMValue::BuiltinFunction(name) => format!("<builtin {}>", name),
+            VMValue::Int(i) => out.push_str(&i.to_string()),
+            VMValue::Float(f) => out.push_str(&f.to_string()),
+            VMValue::String(s) => out.push_str(s),
+            VMValue::Bool(b) => out.push_str(&b.to_string()),
+            VMValue::Null => out.push_str("null"),
+            VMValue::Function(name, _) => out.push_str(&format!("<function {}>", name)),
+            VMValue::BuiltinFunction(name) => out.push_str(&format!("<builtin {}>", name)),
             VMValue::Object(map) => {
-                let entries: Vec<String> = map.iter()
-                    .map(|(k, v)| format!("{}: {}", k, v.to_string()))
-                    .collect();
-                format!("{{{}}}", entries.join(", "))
+                let address = Rc::as_ptr(map) as usize;
+                if open.contains(&address) {
+                    out.push_str("{...}");
+                    return;
+                }
+                open.push(address);
+                out.push('{');
+                for (index, (k, v)) in map.borrow().iter().enumerate() {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    out.push_str(k);
+                    out.push_str(": ");
+                    v.write_to(out, open);
+                }
+                out.push('}');
+                open.pop();
             },
             VMValue::Array(arr) => {
-                let elements: Vec<String> = arr.iter()
-                    .map(|v| v.to_string())
-                    .collect();
-                format!("[{}]", elements.join(", "))
+                let address = Rc::as_ptr(arr) as usize;
+                if open.contains(&address) {
+                    out.push_str("[...]");
+                    return;
+                }
+                open.push(address);
+                out.push('[');
+                for (index, v) in arr.borrow().iter().enumerate() {
+                    if index > 0 {
+                        out.push_str(", ");
+                    }
+                    v.write_to(out, open);
+                }
+                out.push(']');
+                open.pop();
             },
         }
     }
 
+    // Deep equality used by assertions; unlike `==` this also compares the
+    // contents of distinct arrays and objects
+    pub fn structurally_equal(&self, other: &VMValue) -> bool {
+        self.equal_in(other, &mut Vec::new())
+    }
+
+    // `open` holds the pairs of containers currently being compared. A pair
+    // met again inside itself is treated as equal, so cyclic values compare
+    // by their shape instead of recursing forever
+    fn equal_in(&self, other: &VMValue, open: &mut Vec<(usize, usize)>) -> bool {
+        match (self, other) {
+            (VMValue::Array(a), VMValue::Array(b)) => {
+                if Rc::ptr_eq(a, b) {
+                    return true;
+                }
+                let pair = (Rc::as_ptr(a) as usize, Rc::as_ptr(b) as usize);
+                if open.contains(&pair) {
+                    return true;
+                }
+                open.push(pair);
+                let (a, b) = (a.borrow(), b.borrow());
+                let equal = a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| x.equal_in(y, open));
+                open.pop();
+                equal
+            }
+            (VMValue::Object(a), VMValue::Object(b)) => {
+                if Rc::ptr_eq(a, b) {
+                    return true;
+                }
+                let pair = (Rc::as_ptr(a) as usize, Rc::as_ptr(b) as usize);
+                if open.contains(&pair) {
+                    return true;
+                }
+                open.push(pair);
+                let (a, b) = (a.borrow(), b.borrow());
+                let equal = a.len() == b.len() && a.iter().all(|(k, v)| b.get(k).is_some_and(|w| v.equal_in(w, open)));
+                open.pop();
+                equal
+            }
+            _ => self == other,
+        }
+    }
+
+    // The name `typeof` reports
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            VMValue::Int(_) => "int",
+            VMValue::Float(_) => "float",
+            VMValue::String(_) => "string",
+            VMValue::Bool(_) => "bool",
+            VMValue::Array(_) => "array",
+            VMValue::Null => "null",
+            VMValue::Function(_, _) => "function",
+            VMValue::BuiltinFunction(_) => "builtin_function",
+            VMValue::Object(_) => "object",
+        }
+    }
+
     pub fn to_bool(&self) -> bool {
         match self {
             VMValue::Int(i) => *i != 0,
@@ -77,12 +199,16 @@ impl PartialEq for VMValue {
             (VMValue::Null, VMValue::Null) => true,
             (VMValue::Int(a), VMValue::Float(b)) => (*a as f64) == *b,
             (VMValue::Float(a), VMValue::Int(b)) => *a == (*b as f64),
+            // Containers compare by identity
+            (VMValue::Array(a), VMValue::Array(b)) => Rc::ptr_eq(a, b),
+            (VMValue::Object(a), VMValue::Object(b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
 }
 
-#[derive(Clone, Debug)]
+// Serializable so compiled programs can be saved, see `bytecode_file`
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum BytecodeInstruction {
     // Stack operations
     PushInt(i64),
@@ -126,11 +252,26 @@ pub enum BytecodeInstruction {
     JumpIfFalse(usize),
     JumpIfTrue(usize),
     
+    // Source position of the statement that follows
+    Line(usize),
+    
     // Function operations
     Call(String, usize),
+    // A call in tail position: a user function reuses the current frame,
+    // anything else is called normally and the `Return` after it runs
+    TailCall(String, usize),
     Return,
     DefineFunction(String, usize),
     EndFunction,
+    // Load a C function from a shared library: library path, name, signature
+    ExternFunction(String, String, FFISignature),
+    // Import a Python module: module name, alias
+    ImportPython(String, Option<String>),
+    // Call a function in an imported Python module: module binding, attribute
+    // path, and the name of each argument taken from the stack (None for positional)
+    CallPython(String, String, Vec<Option<String>>),
+    // Push an attribute of an imported Python module
+    LoadPython(String, String),
     
     // Built-in functions
     Print,
@@ -156,7 +297,10 @@ pub enum BytecodeInstruction {
     
     // Error handling
     Throw(String),
+    // Start a protected region whose errors jump to the given catch address
+    // with the error message on the stack
     Try(usize),
+    EndTry,
     Catch,
     Finally,
     
@@ -228,17 +372,80 @@ pub enum BytecodeInstruction {
     ThrowError,
 }
 
+/// Shared buffers that receive a VM's stdout and stderr instead of the process streams
+#[derive(Clone, Debug, Default)]
+pub struct OutputCapture {
+    pub stdout: Arc<Mutex<String>>,
+    pub stderr: Arc<Mutex<String>>,
+}
+
+impl OutputCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stdout(&self) -> String {
+        self.stdout.lock().unwrap().clone()
+    }
+
+    pub fn stderr(&self) -> String {
+        self.stderr.lock().unwrap().clone()
+    }
+}
+
+// Calls deeper than this fail with a `StackOverflow` error unless `set_max_call_depth` says otherwise
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
+// A user function call in progress
+pub(crate) struct CallFrame {
+    pub(crate) return_ip: usize,
+    pub(crate) function: String,
+}
+
+// An active `try` block and the state to unwind to when an error reaches it
+struct Handler {
+    catch_ip: usize,
+    stack_len: usize,
+    call_depth: usize,
+    scope_depth: usize,
+}
+
 pub struct VM {
     stack: Vec<VMValue>,
     locals: HashMap<String, VMValue>,
     globals: HashMap<String, VMValue>,
     instructions: Vec<BytecodeInstruction>,
     instruction_pointer: usize,
-    call_stack: Vec<usize>,
+    call_stack: Vec<CallFrame>,
+    handlers: Vec<Handler>,
+    limits: Limits,
+    // Counts against `limits` while a run is in progress
+    usage: Option<Usage>,
+    // The limit that stopped the latest run, if one did
+    limit_exceeded: Option<Limit>,
     function_table: HashMap<String, (usize, usize, usize)>,
     error: Option<String>,
     in_function_definition: bool,
     scope_stack: Vec<HashMap<String, VMValue>>, // Stack of local variable scopes
+    interrupt: Option<Arc<AtomicBool>>,
+    output_capture: Option<OutputCapture>,
+    exit_code: Option<i32>,
+    coverage: Option<CoverageCounters>,
+    profiler: Option<Profiler>,
+    heap_profiler: Option<HeapProfiler>,
+    jit: Option<JITCompiler>,
+    collector: CycleCollector,
+    // Libraries opened by `extern` blocks, and the library each C function comes from
+    ffi: FFIContext,
+    extern_functions: HashMap<String, String>,
+    // An error raised by a Neksis callback while C code was running
+    callback_error: Option<String>,
+    // Modules bound by `import python`
+    python: PythonInterop,
+    // Functions registered by the host, callable like built-ins
+    host_functions: HashMap<String, HostFunction>,
+    // What the program may do to the host, nothing unless granted
+    permissions: Permissions,
 }
 
 impl VM {
@@ -250,17 +457,267 @@ impl VM {
             instructions: Vec::new(),
             instruction_pointer: 0,
             call_stack: Vec::new(),
+            handlers: Vec::new(),
+            limits: Limits::default(),
+            usage: None,
+            limit_exceeded: None,
             function_table: HashMap::new(),
             error: None,
             in_function_definition: false,
             scope_stack: Vec::new(),
+            interrupt: None,
+            output_capture: None,
+            exit_code: None,
+            coverage: None,
+            profiler: None,
+            heap_profiler: None,
+            jit: None,
+            collector: CycleCollector::new(),
+            ffi: FFIContext::new(),
+            extern_functions: HashMap::new(),
+            callback_error: None,
+            python: PythonInterop::default(),
+            host_functions: HashMap::new(),
+            permissions: Permissions::none(),
+        }
+    }
+
+    // The line of the statement at the current instruction, from the nearest
+    // `Line` marker before it in the same function. After `run` fails this
+    // is the statement that failed.
+    pub fn current_line(&self) -> Option<usize> {
+        let end = (self.instruction_pointer + 1).min(self.instructions.len());
+        self.instructions[..end].iter().rev()
+            .take_while(|instruction| !matches!(instruction, BytecodeInstruction::DefineFunction(..)))
+            .find_map(|instruction| match instruction {
+                BytecodeInstruction::Line(line) => Some(*line),
+                _ => None,
+            })
+    }
+
+    // Calls nested deeper than `depth` fail with a catchable `StackOverflow` error
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.limits.max_call_depth = depth;
+    }
+
+    // Bound the instructions, heap, call depth and time of each run, see `limits`
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    // The limit that stopped the latest run or call, if one did
+    pub fn limit_exceeded(&self) -> Option<Limit> {
+        self.limit_exceeded
+    }
+
+    // Grant the program access to the host, see `permissions`
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = permissions;
+    }
+
+    pub fn permissions_mut(&mut self) -> &mut Permissions {
+        &mut self.permissions
+    }
+
+    // The code passed to `exit()`, if the program called it
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    // Call a user-defined function after the program has been loaded and run,
+    // returning the value it produced
+    pub fn call(&mut self, name: &str, args: Vec<VMValue>) -> Result<VMValue, String> {
+        if self.usage.is_none() {
+            self.limit_exceeded = None;
+        }
+        let (start, _end, param_count) = self.function_table.get(name).cloned()
+            .ok_or_else(|| format!("Undefined function: {}", name))?;
+        if args.len() != param_count {
+            return Err(format!("Function {} expects {} arguments, got {}", name, param_count, args.len()));
+        }
+
+        let stack_depth = self.stack.len();
+        let call_depth = self.call_stack.len();
+        let scope_depth = self.scope_stack.len();
+        // `try` blocks of an interrupted run must not catch errors from this call
+        let handlers = std::mem::take(&mut self.handlers);
+        self.stack.extend(args);
+        // Returning to the end of the instruction stream stops `run` once the call completes
+        let result = self.enter_function(name, start, self.instructions.len()).and_then(|_| self.run());
+        let value = if self.stack.len() > stack_depth { self.stack.pop() } else { None };
+
+        // Unwind whatever an error or `exit()` left behind
+        self.stack.truncate(stack_depth);
+        self.call_stack.truncate(call_depth);
+        self.handlers = handlers;
+        while self.scope_stack.len() > scope_depth {
+            self.pop_scope();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            // The root frame sits below the frame of every active call
+            while profiler.depth() > call_depth + 1 {
+                profiler.exit();
+            }
+        }
+        self.instruction_pointer = self.instructions.len();
+
+        result.map(|_| value.unwrap_or(VMValue::Null))
+    }
+
+    // Make `function` callable from programs as `name(...)`. User functions
+    // and built-ins with the same name take precedence
+    pub fn register_function(&mut self, name: &str, function: HostFunction) {
+        self.host_functions.insert(name.to_string(), function);
+    }
+
+    // A top-level variable or global, as the program's top level sees it
+    pub fn global(&self, name: &str) -> Option<VMValue> {
+        self.locals.get(name).or_else(|| self.globals.get(name)).cloned()
+    }
+
+    // Define a global that the program and every function in it can read
+    pub fn set_global(&mut self, name: &str, value: VMValue) {
+        self.locals.remove(name);
+        self.globals.insert(name.to_string(), value);
+    }
+
+    pub fn remove_global(&mut self, name: &str) -> Option<VMValue> {
+        self.locals.remove(name).or_else(|| self.globals.remove(name))
+    }
+
+    // Start counting executed `Line` markers and conditional jump outcomes
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(CoverageCounters::default());
+    }
+
+    pub fn take_coverage(&mut self) -> Option<CoverageCounters> {
+        self.coverage.take()
+    }
+
+    // Time every user function call from now on
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    // Stop profiling and return what was recorded
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profiler.take().map(Profiler::finish)
+    }
+
+    // Record every string, array and object the program allocates from now on
+    pub fn enable_heap_profiling(&mut self) {
+        let mut heap_profiler = HeapProfiler::new();
+        heap_profiler.index_sites(&self.instructions);
+        self.heap_profiler = Some(heap_profiler);
+    }
+
+    // Stop heap profiling and report what was allocated and what is still live
+    pub fn take_heap_profile(&mut self) -> Option<HeapReport> {
+        let heap_profiler = self.heap_profiler.take()?;
+        let scopes = self.scope_stack.iter().flat_map(|scope| scope.values());
+        let roots = self.stack.iter().chain(self.locals.values()).chain(self.globals.values()).chain(scopes);
+        Some(heap_profiler.finish(roots))
+    }
+
+    // Free arrays and objects that are only reachable through reference
+    // cycles, returning how many were freed
+    pub fn collect_garbage(&mut self) -> usize {
+        self.collector.collect()
+    }
+
+    pub fn collector_stats(&self) -> CollectorStats {
+        self.collector.stats()
+    }
+
+    // Compile hot numeric functions to native code, see `jit_compiler`
+    pub fn enable_jit(&mut self) -> Result<(), String> {
+        self.set_jit(JITCompiler::new()?);
+        Ok(())
+    }
+
+    pub fn set_jit(&mut self, jit: JITCompiler) {
+        self.jit = Some(jit);
+    }
+
+    pub fn jit_stats(&self) -> Option<JitStats> {
+        self.jit.as_ref().map(JITCompiler::stats)
+    }
+
+    // Redirect print output into `capture` instead of the process stdout/stderr
+    pub fn set_output_capture(&mut self, capture: OutputCapture) {
+        self.output_capture = Some(capture);
+    }
+
+    fn write_stdout(&self, text: &str) {
+        match &self.output_capture {
+            Some(capture) => capture.stdout.lock().unwrap().push_str(text),
+            None => print!("{}", text),
+        }
+    }
+
+    fn write_stderr(&self, text: &str) {
+        match &self.output_capture {
+            Some(capture) => capture.stderr.lock().unwrap().push_str(text),
+            None => eprint!("{}", text),
         }
     }
 
+    // Stop running with an error once `flag` is set, e.g. by another thread
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        self.interrupt = Some(flag);
+    }
+
     pub fn load_instructions(&mut self, instructions: Vec<BytecodeInstruction>) {
         self.instructions = instructions;
         self.instruction_pointer = 0;
         self.build_function_table();
+        if let Some(heap_profiler) = &mut self.heap_profiler {
+            heap_profiler.index_sites(&self.instructions);
+        }
+    }
+
+    // Append `instructions` to the loaded program and run just them. Variables,
+    // functions and imports from earlier runs stay in place, so a host can feed
+    // a program to the VM one piece at a time
+    pub fn run_appended(&mut self, instructions: Vec<BytecodeInstruction>) -> Result<(), String> {
+        let offset = self.instructions.len();
+        self.instructions.extend(instructions.into_iter().map(|instruction| relocate(instruction, offset)));
+        self.build_function_table();
+        if let Some(heap_profiler) = &mut self.heap_profiler {
+            heap_profiler.index_sites(&self.instructions);
+        }
+        self.instruction_pointer = offset;
+        self.exit_code = None;
+
+        let result = self.run();
+
+        // Unwind whatever an error or `exit()` left behind before the next piece
+        self.stack.clear();
+        self.call_stack.clear();
+        self.handlers.clear();
+        while !self.scope_stack.is_empty() {
+            self.pop_scope();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            while profiler.depth() > 1 {
+                profiler.exit();
+            }
+        }
+        self.in_function_definition = false;
+        self.instruction_pointer = self.instructions.len();
+        result
+    }
+
+    /// Run straight-line `instructions` in a fresh VM and return the value left on top of the stack
+    pub fn evaluate(instructions: Vec<BytecodeInstruction>) -> Result<Option<VMValue>, String> {
+        let mut vm = VM::new();
+        vm.load_instructions(instructions);
+        vm.run()?;
+        Ok(vm.stack.pop())
     }
 
     fn build_function_table(&mut self) {
@@ -291,7 +748,57 @@ impl VM {
     }
 
     pub fn run(&mut self) -> Result<(), String> {
+        // Callbacks from C run nested inside a run and share its budget
+        let outermost = self.usage.is_none();
+        if outermost {
+            self.usage = Some(Usage::start());
+            self.limit_exceeded = None;
+        }
+        let result = self.run_with_handlers();
+        if outermost {
+            self.usage = None;
+        }
+        result
+    }
+
+    fn run_with_handlers(&mut self) -> Result<(), String> {
+        loop {
+            let error = match self.execute() {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+            // An exceeded limit stops the program however many `try` blocks are active
+            if self.limit_exceeded.is_some() {
+                return Err(error);
+            }
+            let Some(handler) = self.handlers.pop() else {
+                if error.starts_with(Limit::CallDepth.error_name()) {
+                    self.limit_exceeded = Some(Limit::CallDepth);
+                }
+                return Err(error);
+            };
+            // Drop every frame and value above the `try` block, then continue in its catch block
+            self.stack.truncate(handler.stack_len);
+            self.call_stack.truncate(handler.call_depth);
+            while self.scope_stack.len() > handler.scope_depth {
+                self.pop_scope();
+            }
+            if let Some(profiler) = &mut self.profiler {
+                while profiler.depth() > handler.call_depth + 1 {
+                    profiler.exit();
+                }
+            }
+            self.in_function_definition = false;
+            self.stack.push(VMValue::String(error));
+            self.instruction_pointer = handler.catch_ip;
+        }
+    }
+
+    fn execute(&mut self) -> Result<(), String> {
         while self.instruction_pointer < self.instructions.len() {
+            if self.interrupt.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+                return Err("Interrupted".to_string());
+            }
             let instruction = self.instructions[self.instruction_pointer].clone();
             
             // Skip instructions that are part of function definitions
@@ -301,6 +808,14 @@ impl VM {
                 self.instruction_pointer += 1;
                 continue;
             }
+            if let Some(Err(limit)) = self.usage.as_mut().map(|usage| usage.step(&self.limits)) {
+                return Err(self.exceeded(limit));
+            }
+            let heap_effect = match self.limits.max_heap_bytes {
+                Some(_) => limits::heap_effect(&instruction),
+                None => HeapEffect::Nothing,
+            };
+            let fresh_value = self.heap_profiler.is_some() && heap_profiler::always_allocates(&instruction);
             
             match instruction {
                 BytecodeInstruction::PushInt(value) => {
@@ -322,6 +837,9 @@ impl VM {
                     if let Some(value) = self.locals.get(&name)
                         .or_else(|| self.globals.get(&name)) {
                         self.stack.push(value.clone());
+                    } else if self.function_table.contains_key(&name) {
+                        // A function used as a value, e.g. a callback for a C function
+                        self.stack.push(VMValue::Function(name, Vec::new()));
                     } else if name == "print" || name == "println" || name == "read_line" {
                         self.stack.push(VMValue::BuiltinFunction(name));
                     } else {
@@ -597,9 +1115,18 @@ impl VM {
                     self.instruction_pointer = offset;
                     continue;
                 }
+                BytecodeInstruction::Line(_) => {
+                    if let Some(coverage) = &mut self.coverage {
+                        coverage.record_line(self.instruction_pointer);
+                    }
+                }
                 BytecodeInstruction::JumpIfFalse(offset) => {
                     if let Some(condition) = self.stack.pop() {
-                        if !condition.to_bool() {
+                        let jumps = !condition.to_bool();
+                        if let Some(coverage) = &mut self.coverage {
+                            coverage.record_branch(self.instruction_pointer, jumps);
+                        }
+                        if jumps {
                             self.instruction_pointer = offset;
                             continue;
                         }
@@ -609,7 +1136,11 @@ impl VM {
                 }
                 BytecodeInstruction::JumpIfTrue(offset) => {
                     if let Some(condition) = self.stack.pop() {
-                        if condition.to_bool() {
+                        let jumps = condition.to_bool();
+                        if let Some(coverage) = &mut self.coverage {
+                            coverage.record_branch(self.instruction_pointer, jumps);
+                        }
+                        if jumps {
                             self.instruction_pointer = offset;
                             continue;
                         }
@@ -634,26 +1165,58 @@ impl VM {
                                 return Err(format!("Function {} expects {} arguments, got {}", name, param_count, arg_count));
                             }
                             
-                            // Create new scope for function
-                            self.push_scope();
-                            
-                            // Save return address
-                            let return_ip = self.instruction_pointer + 1;
-                            self.call_stack.push(return_ip);
-                            // Jump to function (parameters will be handled by Store instructions in the function)
-                            self.instruction_pointer = start;
-                            continue;
+                            if let Some(result) = self.call_compiled(&name, arg_count) {
+                                self.stack.push(result);
+                            } else {
+                                // Jump to function (parameters will be handled by Store instructions in the function)
+                                self.enter_function(&name, start, self.instruction_pointer + 1)?;
+                                continue;
+                            }
                         } else {
                             // Try built-in function
                             self.call_builtin_function(&name, arg_count)?;
                         }
                     }
                 }
+                BytecodeInstruction::TailCall(name, arg_count) => {
+                    if let Some((start, _end, param_count)) = self.function_table.get(&name).cloned() {
+                        if arg_count != param_count {
+                            return Err(format!("Function {} expects {} arguments, got {}", name, param_count, arg_count));
+                        }
+                        if let Some(result) = self.call_compiled(&name, arg_count) {
+                            // The `Return` that follows hands the result to the caller
+                            self.stack.push(result);
+                        } else {
+                            let depth = self.call_stack.len();
+                            match self.call_stack.last_mut() {
+                                Some(frame) => {
+                                    // The callee takes over this frame and returns straight to its caller
+                                    frame.function = name.clone();
+                                    self.locals.clear();
+                                    self.drop_handlers_above(depth - 1);
+                                    if let Some(profiler) = &mut self.profiler {
+                                        profiler.exit();
+                                        profiler.enter(&name);
+                                    }
+                                    self.instruction_pointer = start;
+                                }
+                                None => self.enter_function(&name, start, self.instruction_pointer + 1)?,
+                            }
+                            continue;
+                        }
+                    } else {
+                        self.call_builtin_function(&name, arg_count)?;
+                    }
+                }
                 BytecodeInstruction::Return => {
-                    if let Some(return_ip) = self.call_stack.pop() {
+                    if let Some(frame) = self.call_stack.pop() {
                         // Pop the function scope
                         self.pop_scope();
-                        self.instruction_pointer = return_ip;
+                        self.drop_handlers_above(self.call_stack.len());
+                        if let Some(profiler) = &mut self.profiler {
+                            profiler.exit();
+                        }
+                        self.instruction_pointer = frame.return_ip;
                         continue;
                     } else {
                         return Err("Return without function call".to_string());
@@ -663,16 +1226,34 @@ impl VM {
                     // End of function definition
                     self.in_function_definition = false;
                 }
+                BytecodeInstruction::ExternFunction(library, name, signature) => {
+                    self.permissions.check_ffi(&format!("library '{}'", library))?;
+                    self.declare_extern(&library, &name, signature)?;
+                }
+                BytecodeInstruction::ImportPython(module, alias) => {
+                    self.permissions.check_ffi(&format!("Python module '{}'", module))?;
+                    self.python.import(&module, alias.as_deref())?;
+                }
+                BytecodeInstruction::CallPython(module, path, names) => {
+                    let values = self.stack.split_off(self.stack.len().checked_sub(names.len()).ok_or("Stack underflow")?);
+                    let args: Vec<_> = names.into_iter().zip(values).collect();
+                    let result = self.python.call_python_function(&module, &path, &args, &mut self.collector)?;
+                    self.stack.push(result);
+                }
+                BytecodeInstruction::LoadPython(module, path) => {
+                    let value = self.python.get_attribute(&module, &path, &mut self.collector)?;
+                    self.stack.push(value);
+                }
                 BytecodeInstruction::Print => {
                     if let Some(value) = self.stack.pop() {
-                        print!("{}", value.to_string());
+                        self.write_stdout(&value.to_string());
                     } else {
                         return Err("Stack underflow".to_string());
                     }
                 }
                 BytecodeInstruction::Println => {
                     if let Some(value) = self.stack.pop() {
-                        println!("{}", value.to_string());
+                        self.write_stdout(&format!("{}\n", value.to_string()));
                     } else {
                         return Err("Stack underflow".to_string());
                     }
@@ -684,6 +1265,7 @@ impl VM {
                 }
                 BytecodeInstruction::ReadFile => {
                     if let Some(VMValue::String(path)) = self.stack.pop() {
+                        self.permissions.check_read(&path)?;
                         match std::fs::read_to_string(&path) {
                             Ok(content) => self.stack.push(VMValue::String(content)),
                             Err(e) => return Err(format!("Failed to read file '{}': {}", path, e)),
@@ -694,6 +1276,7 @@ impl VM {
                 }
                 BytecodeInstruction::WriteFile => {
                     if let (Some(VMValue::String(content)), Some(VMValue::String(path))) = (self.stack.pop(), self.stack.pop()) {
+                        self.permissions.check_write(&path)?;
                         match std::fs::write(&path, &content) {
                             Ok(_) => self.stack.push(VMValue::Null),
                             Err(e) => return Err(format!("Failed to write file '{}': {}", path, e)),
@@ -704,6 +1287,7 @@ impl VM {
                 }
                 BytecodeInstruction::AppendFile => {
                     if let (Some(VMValue::String(content)), Some(VMValue::String(path))) = (self.stack.pop(), self.stack.pop()) {
+                        self.permissions.check_write(&path)?;
                         use std::fs::OpenOptions;
                         use std::io::Write;
                         
@@ -722,6 +1306,7 @@ impl VM {
                 }
                 BytecodeInstruction::FileExists => {
                     if let Some(VMValue::String(path)) = self.stack.pop() {
+                        self.permissions.check_read(&path)?;
                         let exists = std::path::Path::new(&path).exists();
                         self.stack.push(VMValue::Bool(exists));
                     } else {
@@ -919,8 +1504,16 @@ impl VM {
                     self.error = Some(message);
                     return Ok(());
                 }
-                BytecodeInstruction::Try(_offset) => {
-                    // Try block - just continue execution
+                BytecodeInstruction::Try(catch_ip) => {
+                    self.handlers.push(Handler {
+                        catch_ip,
+                        stack_len: self.stack.len(),
+                        call_depth: self.call_stack.len(),
+                        scope_depth: self.scope_stack.len(),
+                    });
+                }
+                BytecodeInstruction::EndTry => {
+                    self.handlers.pop();
                 }
                 BytecodeInstruction::Catch => {
                     // Catch block - clear error and continue
@@ -930,11 +1523,12 @@ impl VM {
                     // Finally block - always execute
                 }
                 BytecodeInstruction::NewObject => {
-                    self.stack.push(VMValue::Object(HashMap::new()));
+                    let object = self.collector.object(HashMap::new());
+                    self.stack.push(object);
                 }
                 BytecodeInstruction::GetProperty(name) => {
-                    if let Some(VMValue::Object(mut props)) = self.stack.pop() {
-                        let value = props.remove(&name)
+                    if let Some(VMValue::Object(props)) = self.stack.pop() {
+                        let value = props.borrow().get(&name).cloned()
                             .unwrap_or(VMValue::Null);
                         self.stack.push(value);
                     } else {
@@ -942,8 +1536,8 @@ impl VM {
                     }
                 }
                 BytecodeInstruction::SetProperty(name) => {
-                    if let (Some(value), Some(VMValue::Object(mut props))) = (self.stack.pop(), self.stack.pop()) {
-                        props.insert(name, value);
+                    if let (Some(value), Some(VMValue::Object(props))) = (self.stack.pop(), self.stack.pop()) {
+                        props.borrow_mut().insert(name, value);
                         self.stack.push(VMValue::Object(props));
                     } else {
                         return Err("Cannot set property on non-object".to_string());
@@ -954,16 +1548,14 @@ impl VM {
                     self.call_function(&name, arg_count)?;
                 }
                 BytecodeInstruction::NewArray => {
-                    self.stack.push(VMValue::Array(Vec::new()));
+                    let array = self.collector.array(Vec::new());
+                    self.stack.push(array);
                 }
                 BytecodeInstruction::GetIndex => {
-                    if let (Some(index), Some(VMValue::Array(mut arr))) = (self.stack.pop(), self.stack.pop()) {
+                    if let (Some(index), Some(VMValue::Array(arr))) = (self.stack.pop(), self.stack.pop()) {
                         if let VMValue::Int(i) = index {
-                            if i >= 0 && i < arr.len() as i64 {
-                                self.stack.push(arr.remove(i as usize));
-                            } else {
-                                self.stack.push(VMValue::Null);
-                            }
+                            let element = usize::try_from(i).ok().and_then(|i| arr.borrow().get(i).cloned());
+                            self.stack.push(element.unwrap_or(VMValue::Null));
                         } else {
                             return Err("Invalid array index".to_string());
                         }
@@ -972,11 +1564,13 @@ impl VM {
                     }
                 }
                 BytecodeInstruction::SetIndex => {
-                    if let (Some(value), Some(index), Some(VMValue::Array(mut arr))) = (self.stack.pop(), self.stack.pop(), self.stack.pop()) {
+                    if let (Some(value), Some(index), Some(VMValue::Array(arr))) = (self.stack.pop(), self.stack.pop(), self.stack.pop()) {
                         if let VMValue::Int(i) = index {
-                            if i >= 0 && i < arr.len() as i64 {
-                                arr[i as usize] = value;
+                            let mut items = arr.borrow_mut();
+                            if let Some(slot) = usize::try_from(i).ok().and_then(|i| items.get_mut(i)) {
+                                *slot = value;
                             }
+                            drop(items);
                             self.stack.push(VMValue::Array(arr));
                         } else {
                             return Err("Invalid array index".to_string());
@@ -1028,10 +1622,11 @@ impl VM {
                     }
                 }
                 BytecodeInstruction::StringLen => {
-                    if let Some(VMValue::String(s)) = self.stack.pop() {
-                        self.stack.push(VMValue::Int(s.len() as i64));
-                    } else {
-                        return Err("len expects a string".to_string());
+                    match self.stack.pop() {
+                        Some(VMValue::String(s)) => self.stack.push(VMValue::Int(s.len() as i64)),
+                        Some(VMValue::Array(arr)) => self.stack.push(VMValue::Int(arr.borrow().len() as i64)),
+                        Some(VMValue::Object(dict)) => self.stack.push(VMValue::Int(dict.borrow().len() as i64)),
+                        _ => return Err("len expects a string, array or object".to_string()),
                     }
                 }
                 BytecodeInstruction::Substring => {
@@ -1108,7 +1703,8 @@ impl VM {
                         let parts: Vec<VMValue> = s.split(&delimiter)
                             .map(|part| VMValue::String(part.to_string()))
                             .collect();
-                        self.stack.push(VMValue::Array(parts));
+                        let parts = self.collector.array(parts);
+                        self.stack.push(parts);
                     } else {
                         return Err("split expects string arguments".to_string());
                     }
@@ -1116,7 +1712,7 @@ impl VM {
                 BytecodeInstruction::StringJoin => {
                     if let (Some(VMValue::String(delimiter)), Some(VMValue::Array(arr))) = 
                         (self.stack.pop(), self.stack.pop()) {
-                        let strings: Result<Vec<String>, String> = arr.iter().map(|item| {
+                        let strings: Result<Vec<String>, String> = arr.borrow().iter().map(|item| {
                             match item {
                                 VMValue::String(s) => Ok(s.clone()),
                                 VMValue::Int(i) => Ok(i.to_string()),
@@ -1171,18 +1767,7 @@ impl VM {
                 }
                 BytecodeInstruction::TypeOf => {
                     if let Some(value) = self.stack.pop() {
-                        let type_name = match value {
-                            VMValue::Int(_) => "int",
-                            VMValue::Float(_) => "float",
-                            VMValue::String(_) => "string",
-                            VMValue::Bool(_) => "bool",
-                            VMValue::Array(_) => "array",
-                            VMValue::Null => "null",
-                            VMValue::Function(_, _) => "function",
-                            VMValue::BuiltinFunction(_) => "builtin_function",
-                            VMValue::Object(_) => "object",
-                        };
-                        self.stack.push(VMValue::String(type_name.to_string()));
+                        self.stack.push(VMValue::String(value.type_name().to_string()));
                     } else {
                         return Err("Stack underflow for typeof".to_string());
                     }
@@ -1217,20 +1802,23 @@ impl VM {
                     } else {
                         0
                     };
+                    self.permissions.check_exit()?;
                     
-                    std::process::exit(exit_code);
+                    // Stop here and let the host decide what exiting means
+                    self.exit_code = Some(exit_code);
+                    return Ok(());
                 }
                 
                 // Advanced Data Structures - HashMap/Dictionary
                 BytecodeInstruction::DictNew => {
-                    let dict = HashMap::new();
-                    self.stack.push(VMValue::Object(dict));
+                    let dict = self.collector.object(HashMap::new());
+                    self.stack.push(dict);
                 }
                 BytecodeInstruction::DictSet => {
-                    if let (Some(value), Some(key), Some(VMValue::Object(mut dict))) = 
+                    if let (Some(value), Some(key), Some(VMValue::Object(dict))) = 
                         (self.stack.pop(), self.stack.pop(), self.stack.pop()) {
                         let key_str = key.to_string();
-                        dict.insert(key_str, value);
+                        dict.borrow_mut().insert(key_str, value);
                         self.stack.push(VMValue::Object(dict));
                     } else {
                         return Err("Invalid arguments for dict_set".to_string());
@@ -1240,7 +1828,7 @@ impl VM {
                     if let (Some(key), Some(VMValue::Object(dict))) = 
                         (self.stack.pop(), self.stack.pop()) {
                         let key_str = key.to_string();
-                        let value = dict.get(&key_str).cloned().unwrap_or(VMValue::Null);
+                        let value = dict.borrow().get(&key_str).cloned().unwrap_or(VMValue::Null);
                         self.stack.push(value);
                     } else {
                         return Err("Invalid arguments for dict_get".to_string());
@@ -1250,7 +1838,7 @@ impl VM {
                     if let (Some(key), Some(VMValue::Object(dict))) = 
                         (self.stack.pop(), self.stack.pop()) {
                         let key_str = key.to_string();
-                        let has_key = dict.contains_key(&key_str);
+                        let has_key = dict.borrow().contains_key(&key_str);
                         self.stack.push(VMValue::Bool(has_key));
                     } else {
                         return Err("Invalid arguments for dict_has".to_string());
@@ -1258,26 +1846,27 @@ impl VM {
                 }
                 BytecodeInstruction::DictKeys => {
                     if let Some(VMValue::Object(dict)) = self.stack.pop() {
-                        let keys: Vec<VMValue> = dict.keys()
+                        let keys: Vec<VMValue> = dict.borrow().keys()
                             .map(|k| VMValue::String(k.clone()))
                             .collect();
-                        self.stack.push(VMValue::Array(keys));
+                        let keys = self.collector.array(keys);
+                        self.stack.push(keys);
                     } else {
                         return Err("Invalid argument for dict_keys".to_string());
                     }
                 }
                 BytecodeInstruction::DictSize => {
                     if let Some(VMValue::Object(dict)) = self.stack.pop() {
-                        self.stack.push(VMValue::Int(dict.len() as i64));
+                        self.stack.push(VMValue::Int(dict.borrow().len() as i64));
                     } else {
                         return Err("Invalid argument for dict_size".to_string());
                     }
                 }
                 BytecodeInstruction::DictRemove => {
-                    if let (Some(key), Some(VMValue::Object(mut dict))) = 
+                    if let (Some(key), Some(VMValue::Object(dict))) = 
                         (self.stack.pop(), self.stack.pop()) {
                         let key_str = key.to_string();
-                        let removed = dict.remove(&key_str).unwrap_or(VMValue::Null);
+                        let removed = dict.borrow_mut().remove(&key_str).unwrap_or(VMValue::Null);
                         self.stack.push(VMValue::Object(dict));
                         self.stack.push(removed);
                     } else {
@@ -1285,8 +1874,9 @@ impl VM {
                     }
                 }
                 BytecodeInstruction::DictClear => {
-                    if let Some(VMValue::Object(mut dict)) = self.stack.pop() {
-                        dict.clear();
+                    if let Some(VMValue::Object(dict)) = self.stack.pop() {
+                        let cleared = std::mem::take(&mut *dict.borrow_mut());
+                        drop(cleared);
                         self.stack.push(VMValue::Object(dict));
                     } else {
                         return Err("Invalid argument for dict_clear".to_string());
@@ -1295,17 +1885,17 @@ impl VM {
                 
                 // Advanced Array functions
                 BytecodeInstruction::ArrayPush => {
-                    if let (Some(value), Some(VMValue::Array(mut arr))) = 
+                    if let (Some(value), Some(VMValue::Array(arr))) = 
                         (self.stack.pop(), self.stack.pop()) {
-                        arr.push(value);
+                        arr.borrow_mut().push(value);
                         self.stack.push(VMValue::Array(arr));
                     } else {
                         return Err("Invalid arguments for array_push".to_string());
                     }
                 }
                 BytecodeInstruction::ArrayPop => {
-                    if let Some(VMValue::Array(mut arr)) = self.stack.pop() {
-                        let popped = arr.pop().unwrap_or(VMValue::Null);
+                    if let Some(VMValue::Array(arr)) = self.stack.pop() {
+                        let popped = arr.borrow_mut().pop().unwrap_or(VMValue::Null);
                         self.stack.push(VMValue::Array(arr));
                         self.stack.push(popped);
                     } else {
@@ -1313,16 +1903,16 @@ impl VM {
                     }
                 }
                 BytecodeInstruction::ArrayReverse => {
-                    if let Some(VMValue::Array(mut arr)) = self.stack.pop() {
-                        arr.reverse();
+                    if let Some(VMValue::Array(arr)) = self.stack.pop() {
+                        arr.borrow_mut().reverse();
                         self.stack.push(VMValue::Array(arr));
                     } else {
                         return Err("Invalid argument for array_reverse".to_string());
                     }
                 }
                 BytecodeInstruction::ArraySort => {
-                    if let Some(VMValue::Array(mut arr)) = self.stack.pop() {
-                        arr.sort_by(|a, b| {
+                    if let Some(VMValue::Array(arr)) = self.stack.pop() {
+                        arr.borrow_mut().sort_by(|a, b| {
                             match (a, b) {
                                 (VMValue::Int(a), VMValue::Int(b)) => a.cmp(b),
                                 (VMValue::Float(a), VMValue::Float(b)) => a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal),
@@ -1339,14 +1929,17 @@ impl VM {
                     if let (Some(end), Some(start), Some(VMValue::Array(arr))) = 
                         (self.stack.pop(), self.stack.pop(), self.stack.pop()) {
                         if let (VMValue::Int(start), VMValue::Int(end)) = (start, end) {
+                            let items = arr.borrow();
                             let start_idx = start.max(0) as usize;
-                            let end_idx = (end.max(0) as usize).min(arr.len());
-                            if start_idx <= end_idx && start_idx < arr.len() {
-                                let slice = arr[start_idx..end_idx].to_vec();
-                                self.stack.push(VMValue::Array(slice));
+                            let end_idx = (end.max(0) as usize).min(items.len());
+                            let slice = if start_idx <= end_idx && start_idx < items.len() {
+                                items[start_idx..end_idx].to_vec()
                             } else {
-                                self.stack.push(VMValue::Array(vec![]));
-                            }
+                                Vec::new()
+                            };
+                            drop(items);
+                            let slice = self.collector.array(slice);
+                            self.stack.push(slice);
                         } else {
                             return Err("Array slice indices must be integers".to_string());
                         }
@@ -1378,7 +1971,7 @@ impl VM {
                     // For now, just return the first element or null
                     // TODO: Implement proper reduction with callback functions
                     if let Some(VMValue::Array(arr)) = self.stack.pop() {
-                        let result = arr.first().cloned().unwrap_or(VMValue::Null);
+                        let result = arr.borrow().first().cloned().unwrap_or(VMValue::Null);
                         self.stack.push(result);
                     } else {
                         return Err("Invalid argument for array_reduce".to_string());
@@ -1388,7 +1981,7 @@ impl VM {
                     // For now, just return the first element or null
                     // TODO: Implement proper finding with callback functions
                     if let Some(VMValue::Array(arr)) = self.stack.pop() {
-                        let result = arr.first().cloned().unwrap_or(VMValue::Null);
+                        let result = arr.borrow().first().cloned().unwrap_or(VMValue::Null);
                         self.stack.push(result);
                     } else {
                         return Err("Invalid argument for array_find".to_string());
@@ -1401,11 +1994,11 @@ impl VM {
                         // Simple JSON parsing - for demo purposes
                         // TODO: Implement proper JSON parsing
                         if json_str.starts_with('{') && json_str.ends_with('}') {
-                            let dict = HashMap::new();
-                            self.stack.push(VMValue::Object(dict));
+                            let dict = self.collector.object(HashMap::new());
+                            self.stack.push(dict);
                         } else if json_str.starts_with('[') && json_str.ends_with(']') {
-                            let arr = Vec::new();
-                            self.stack.push(VMValue::Array(arr));
+                            let arr = self.collector.array(Vec::new());
+                            self.stack.push(arr);
                         } else {
                             self.stack.push(VMValue::String(json_str));
                         }
@@ -1417,7 +2010,7 @@ impl VM {
                     if let Some(value) = self.stack.pop() {
                         let json_str = match value {
                             VMValue::Object(dict) => {
-                                let entries: Vec<String> = dict.iter()
+                                let entries: Vec<String> = dict.borrow().iter()
                                     .map(|(k, v)| format!("\"{}\":{}", k, match v {
                                         VMValue::String(s) => format!("\"{}\"", s),
                                         VMValue::Int(i) => i.to_string(),
@@ -1430,7 +2023,7 @@ impl VM {
                                 format!("{{{}}}", entries.join(","))
                             }
                             VMValue::Array(arr) => {
-                                let elements: Vec<String> = arr.iter()
+                                let elements: Vec<String> = arr.borrow().iter()
                                     .map(|v| match v {
                                         VMValue::String(s) => format!("\"{}\"", s),
                                         VMValue::Int(i) => i.to_string(),
@@ -1469,6 +2062,30 @@ impl VM {
                     }
                 }
             }
+
+            if let Some(heap_profiler) = &mut self.heap_profiler {
+                if let Some(value) = self.stack.last() {
+                    heap_profiler.record_result(self.instruction_pointer, fresh_value, value, &self.call_stack);
+                }
+                if heap_profiler.wants_collection() {
+                    let scopes = self.scope_stack.iter().flat_map(|scope| scope.values());
+                    heap_profiler.collect(self.stack.iter().chain(self.locals.values()).chain(self.globals.values()).chain(scopes));
+                }
+            }
+            if let Some(usage) = &mut self.usage {
+                if usage.allocated(&self.limits, heap_effect, self.stack.last()) {
+                    let scopes = self.scope_stack.iter().flat_map(|scope| scope.values());
+                    let roots = self.stack.iter().chain(self.locals.values()).chain(self.globals.values()).chain(scopes);
+                    let (bytes, values) = heap_profiler::measure(roots);
+                    if let Err(limit) = usage.measured(&self.limits, bytes, values) {
+                        return Err(self.exceeded(limit));
+                    }
+                }
+            }
+            // Between instructions no container is borrowed, so cycles can be collected safely
+            if self.collector.should_collect() {
+                self.collector.collect();
+            }
             
             self.instruction_pointer += 1;
         }
@@ -1483,7 +2100,7 @@ impl VM {
                     return Err("print expects 1 argument".to_string());
                 }
                 if let Some(value) = self.stack.pop() {
-                    print!("{}", value.to_string());
+                    self.write_stdout(&value.to_string());
                 } else {
                     return Err("Stack underflow".to_string());
                 }
@@ -1493,11 +2110,23 @@ impl VM {
                     return Err("println expects 1 argument".to_string());
                 }
                 if let Some(value) = self.stack.pop() {
-                    println!("{}", value.to_string());
+                    self.write_stdout(&format!("{}\n", value.to_string()));
                 } else {
                     return Err("Stack underflow".to_string());
                 }
             }
+            "eprint" | "eprintln" => {
+                if arg_count != 1 {
+                    return Err(format!("{} expects 1 argument", name));
+                }
+                let value = self.stack.pop().ok_or("Stack underflow")?;
+                if name == "eprintln" {
+                    self.write_stderr(&format!("{}\n", value.to_string()));
+                } else {
+                    self.write_stderr(&value.to_string());
+                }
+                self.stack.push(VMValue::Null);
+            }
             "read_line" => {
                 if arg_count != 0 {
                     return Err("read_line expects 0 arguments".to_string());
@@ -1511,6 +2140,7 @@ impl VM {
                     return Err("read_file expects 1 argument".to_string());
                 }
                 if let Some(VMValue::String(path)) = self.stack.pop() {
+                    self.permissions.check_read(&path)?;
                     match std::fs::read_to_string(&path) {
                         Ok(content) => self.stack.push(VMValue::String(content)),
                         Err(e) => return Err(format!("Failed to read file '{}': {}", path, e)),
@@ -1524,6 +2154,7 @@ impl VM {
                     return Err("write_file expects 2 arguments".to_string());
                 }
                 if let (Some(VMValue::String(content)), Some(VMValue::String(path))) = (self.stack.pop(), self.stack.pop()) {
+                    self.permissions.check_write(&path)?;
                     match std::fs::write(&path, &content) {
                         Ok(_) => self.stack.push(VMValue::Null),
                         Err(e) => return Err(format!("Failed to write file '{}': {}", path, e)),
@@ -1537,6 +2168,7 @@ impl VM {
                     return Err("append_file expects 2 arguments".to_string());
                 }
                 if let (Some(VMValue::String(content)), Some(VMValue::String(path))) = (self.stack.pop(), self.stack.pop()) {
+                    self.permissions.check_write(&path)?;
                     use std::fs::OpenOptions;
                     use std::io::Write;
                     
@@ -1558,12 +2190,43 @@ impl VM {
                     return Err("file_exists expects 1 argument".to_string());
                 }
                 if let Some(VMValue::String(path)) = self.stack.pop() {
+                    self.permissions.check_read(&path)?;
                     let exists = std::path::Path::new(&path).exists();
                     self.stack.push(VMValue::Bool(exists));
                 } else {
                     return Err("file_exists expects a string path".to_string());
                 }
             }
+            "assert" => {
+                if arg_count != 1 && arg_count != 2 {
+                    return Err("assert expects 1 or 2 arguments".to_string());
+                }
+                let message = if arg_count == 2 { self.stack.pop() } else { None };
+                let condition = self.stack.pop().ok_or("Stack underflow")?;
+                if !condition.to_bool() {
+                    return Err(match message {
+                        Some(message) => format!("assertion failed: {}", message.to_string()),
+                        None => "assertion failed".to_string(),
+                    });
+                }
+                self.stack.push(VMValue::Null);
+            }
+            "assert_eq" | "assert_ne" => {
+                if arg_count != 2 {
+                    return Err(format!("{} expects 2 arguments", name));
+                }
+                let (right, left) = match (self.stack.pop(), self.stack.pop()) {
+                    (Some(right), Some(left)) => (right, left),
+                    _ => return Err("Stack underflow".to_string()),
+                };
+                let expect_equal = name == "assert_eq";
+                if left.structurally_equal(&right) != expect_equal {
+                    return Err(Self::assertion_failure_message(&left, &right, expect_equal));
+                }
+                self.stack.push(VMValue::Null);
+            }
+            _ if self.host_functions.contains_key(name) => return self.call_host(name, arg_count),
+            _ if self.extern_functions.contains_key(name) => return self.call_extern(name, arg_count),
             _ => {
                 return Err(format!("Unknown built-in function: {}", name));
             }
@@ -1571,25 +2234,236 @@ impl VM {
         Ok(())
     }
 
+    fn call_host(&mut self, name: &str, arg_count: usize) -> Result<(), String> {
+        let function = Rc::clone(&self.host_functions[name]);
+        let args = self.stack.split_off(self.stack.len().checked_sub(arg_count).ok_or("Stack underflow")?);
+        let result = function(args)?;
+        self.collector.track(&result);
+        self.stack.push(result);
+        Ok(())
+    }
+
+    // Open `library` if needed and look up `name` in it, so calls to `name` go to C
+    fn declare_extern(&mut self, library: &str, name: &str, signature: FFISignature) -> Result<(), String> {
+        if !self.ffi.libraries.contains_key(library) {
+            self.ffi.load_library(library, library).map_err(|e| e.message)?;
+        }
+        self.ffi.declare_function(library, name, signature).map_err(|e| e.message)?;
+        self.extern_functions.insert(name.to_string(), library.to_string());
+        Ok(())
+    }
+
+    // Call a C function with the arguments on top of the stack
+    fn call_extern(&mut self, name: &str, arg_count: usize) -> Result<(), String> {
+        let function = &self.ffi.libraries[&self.extern_functions[name]].functions[name];
+        let (address, signature) = (function.symbol.ok_or_else(|| format!("Function {} was not loaded", name))?, function.signature.clone());
+        if arg_count != signature.parameters.len() {
+            return Err(format!("Function {} expects {} arguments, got {}", name, signature.parameters.len(), arg_count));
+        }
+        let values = self.stack.split_off(self.stack.len().checked_sub(arg_count).ok_or("Stack underflow")?);
+        let mut args = Vec::new();
+        for (index, (value, param)) in values.iter().zip(&signature.parameters).enumerate() {
+            let arg = self.foreign_value(value, &param.ffi_type)
+                .map_err(|e| format!("{}: argument {} ({}): {}", name, index + 1, param.name, e))?;
+            args.push(arg);
+        }
+
+        let result = unsafe { crate::ffi::call_raw(address, &signature, &mut args) };
+        if let Some(error) = self.callback_error.take() {
+            return Err(error);
+        }
+        let result = result.map_err(|e| format!("{}: {}", name, e))?;
+
+        // C may have changed the contents of arrays passed by pointer
+        for ((value, arg), param) in values.iter().zip(args).zip(&signature.parameters) {
+            if let (VMValue::Array(items), FFIValue::Array(updated), FFIType::Array(..)) = (value, arg, &param.ffi_type) {
+                let updated = updated.into_iter().map(|item| self.native_value(item)).collect();
+                *items.borrow_mut() = updated;
+            }
+        }
+        let result = self.native_value(result);
+        self.stack.push(result);
+        if self.exit_code.is_some() {
+            // A callback called `exit()`: stop once the C function has returned
+            self.instruction_pointer = self.instructions.len();
+        }
+        Ok(())
+    }
+
+    // A function pointer C can call to run the Neksis function `name`
+    fn callback(&mut self, name: &str, signature: &FFISignature) -> FFICallback {
+        let vm: *mut VM = self;
+        let name = name.to_string();
+        let return_type = signature.return_type.clone();
+        FFICallback(Rc::new(move |args| {
+            // Callbacks only run inside `call_extern`, while the VM waits for C to return
+            let vm = unsafe { &mut *vm };
+            if vm.callback_error.is_some() || vm.exit_code.is_some() {
+                return FFIValue::Void;
+            }
+            let args = args.into_iter().map(|arg| vm.native_value(arg)).collect();
+            // The callback runs nested inside the instruction that called C
+            let instruction_pointer = vm.instruction_pointer;
+            let result = vm.call(&name, args);
+            vm.instruction_pointer = instruction_pointer;
+            match result.and_then(|value| {
+                vm.foreign_value(&value, &return_type).map_err(|e| format!("callback {} returned the wrong type: {}", name, e))
+            }) {
+                Ok(value) => value,
+                Err(error) => {
+                    vm.callback_error = Some(error);
+                    FFIValue::Void
+                }
+            }
+        }))
+    }
+
+    fn foreign_value(&mut self, value: &VMValue, ty: &FFIType) -> Result<FFIValue, String> {
+        Ok(match (ty, value) {
+            (FFIType::Void, _) => FFIValue::Void,
+            (FFIType::Float32 | FFIType::Float64, VMValue::Float(f)) => FFIValue::Float64(*f),
+            // Integers, floats and pointer addresses; the call checks the range
+            (_, VMValue::Int(n)) if ty.is_scalar() && *ty != FFIType::Bool => FFIValue::Int64(*n),
+            (FFIType::Bool, VMValue::Bool(b)) => FFIValue::Bool(*b),
+            (FFIType::String | FFIType::Pointer(_), VMValue::String(s)) => FFIValue::String(s.clone()),
+            (FFIType::String | FFIType::Pointer(_) | FFIType::Function(_), VMValue::Null) => {
+                FFIValue::Pointer(std::ptr::null_mut())
+            }
+            (FFIType::Array(element, _), VMValue::Array(items)) => FFIValue::Array(
+                items.borrow().iter().map(|item| self.foreign_value(item, element)).collect::<Result<_, _>>()?,
+            ),
+            (FFIType::Struct(fields), VMValue::Object(object)) => {
+                let object = object.borrow();
+                let mut values = HashMap::new();
+                for field in fields {
+                    let field_value = object.get(&field.name).ok_or_else(|| format!("missing field '{}'", field.name))?;
+                    let field_value = self.foreign_value(field_value, &field.ffi_type)
+                        .map_err(|e| format!("field '{}': {}", field.name, e))?;
+                    values.insert(field.name.clone(), field_value);
+                }
+                FFIValue::Struct(values)
+            }
+            (FFIType::Function(signature), VMValue::Function(name, _)) => FFIValue::Callback(self.callback(name, signature)),
+            _ => return Err(format!("expected {}, got {}", ty, value.type_name())),
+        })
+    }
+
+    fn native_value(&mut self, value: FFIValue) -> VMValue {
+        match value {
+            FFIValue::Int8(v) => VMValue::Int(v as i64),
+            FFIValue::Int16(v) => VMValue::Int(v as i64),
+            FFIValue::Int32(v) => VMValue::Int(v as i64),
+            FFIValue::Int64(v) => VMValue::Int(v),
+            FFIValue::UInt8(v) => VMValue::Int(v as i64),
+            FFIValue::UInt16(v) => VMValue::Int(v as i64),
+            FFIValue::UInt32(v) => VMValue::Int(v as i64),
+            FFIValue::UInt64(v) => VMValue::Int(v as i64),
+            FFIValue::Float32(v) => VMValue::Float(v as f64),
+            FFIValue::Float64(v) => VMValue::Float(v),
+            FFIValue::Bool(b) => VMValue::Bool(b),
+            FFIValue::String(s) => VMValue::String(s),
+            FFIValue::Pointer(p) if p.is_null() => VMValue::Null,
+            FFIValue::Pointer(p) => VMValue::Int(p as i64),
+            FFIValue::Array(items) => {
+                let items = items.into_iter().map(|item| self.native_value(item)).collect();
+                self.collector.array(items)
+            }
+            FFIValue::Struct(fields) => {
+                let fields = fields.into_iter().map(|(name, field)| (name, self.native_value(field))).collect();
+                self.collector.object(fields)
+            }
+            FFIValue::Void | FFIValue::Callback(_) => VMValue::Null,
+        }
+    }
+
+    fn assertion_failure_message(left: &VMValue, right: &VMValue, expect_equal: bool) -> String {
+        let operator = if expect_equal { "==" } else { "!=" };
+        let mut message = format!(
+            "assertion failed: left {} right\n  left: {}\n right: {}",
+            operator,
+            left.to_string(),
+            right.to_string()
+        );
+        // Multi-line strings are much easier to compare as a line diff
+        if let (true, VMValue::String(l), VMValue::String(r)) = (expect_equal, left, right) {
+            if l.contains('\n') || r.contains('\n') {
+                message.push('\n');
+                message.push_str(&crate::test_framework::diff::render_diff(l, r));
+            }
+        }
+        message
+    }
+
     fn call_user_function(&mut self, name: &str, arg_count: usize) -> Result<(), String> {
         if let Some((start, _end, param_count)) = self.function_table.get(name).cloned() {
             if arg_count != param_count {
                 return Err(format!("Function {} expects {} arguments, got {}", name, param_count, arg_count));
             }
             
-            // Create new scope for function
-            self.push_scope();
-            
-            // Save return address
-            self.call_stack.push(self.instruction_pointer + 1);
             // Jump to function (parameters will be handled by Store instructions)
-            self.instruction_pointer = start;
+            self.enter_function(name, start, self.instruction_pointer + 1)?;
         } else {
             return Err(format!("Undefined function: {}", name));
         }
         Ok(())
     }
 
+    // The result of calling `name` with the arguments on top of the stack,
+    // if the JIT can run the call natively; the arguments are consumed then
+    fn call_compiled(&mut self, name: &str, arg_count: usize) -> Option<VMValue> {
+        // Profilers and coverage need to see every call and line, and limits every instruction
+        if self.profiler.is_some() || self.coverage.is_some() || self.heap_profiler.is_some() || self.limits.needs_interpreter() {
+            return None;
+        }
+        let jit = self.jit.as_mut()?;
+        let args_start = self.stack.len().checked_sub(arg_count)?;
+        let max_depth = self.limits.max_call_depth.saturating_sub(self.call_stack.len());
+        let result = jit.call(name, &self.stack[args_start..], max_depth, &self.instructions, &self.function_table)?;
+        self.stack.truncate(args_start);
+        Some(result)
+    }
+
+    // Push a frame for `name` and jump to its first instruction
+    fn enter_function(&mut self, name: &str, start: usize, return_ip: usize) -> Result<(), String> {
+        if self.call_stack.len() >= self.limits.max_call_depth {
+            return Err(self.stack_overflow(name));
+        }
+        self.push_scope();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(name);
+        }
+        self.call_stack.push(CallFrame { return_ip, function: name.to_string() });
+        self.instruction_pointer = start;
+        Ok(())
+    }
+
+    // `try` blocks of frames that have returned can no longer catch anything
+    fn drop_handlers_above(&mut self, call_depth: usize) {
+        while self.handlers.last().is_some_and(|handler| handler.call_depth > call_depth) {
+            self.handlers.pop();
+        }
+    }
+
+    fn stack_overflow(&self, callee: &str) -> String {
+        let chain: Vec<&str> = self
+            .call_stack
+            .iter()
+            .map(|frame| frame.function.as_str())
+            .chain(std::iter::once(callee))
+            .collect();
+        format!(
+            "{}\ncall chain: {}",
+            self.limits.message(Limit::CallDepth),
+            summarize_call_chain(&chain)
+        )
+    }
+
+    // Record that `limit` stopped the program and describe it
+    fn exceeded(&mut self, limit: Limit) -> String {
+        self.limit_exceeded = Some(limit);
+        self.limits.message(limit)
+    }
+
     fn call_function(&mut self, name: &str, arg_count: usize) -> Result<(), String> {
         // This is a placeholder for method calls
         // For now, just call as a regular function
@@ -1597,10 +2471,8 @@ impl VM {
     }
     
     fn push_scope(&mut self) {
-        // Save current locals to scope stack
-        self.scope_stack.push(self.locals.clone());
-        // Clear current locals for new scope
-        self.locals.clear();
+        // Save current locals to scope stack, leaving them empty for the new scope
+        self.scope_stack.push(std::mem::take(&mut self.locals));
     }
     
     fn pop_scope(&mut self) {
@@ -1609,4 +2481,48 @@ impl VM {
             self.locals = previous_locals;
         }
     }
-} 
\ No newline at end of file
+} 
+// `instruction` moved `offset` places further along the instruction stream
+fn relocate(instruction: BytecodeInstruction, offset: usize) -> BytecodeInstruction {
+    match instruction {
+        BytecodeInstruction::Jump(target) => BytecodeInstruction::Jump(target + offset),
+        BytecodeInstruction::JumpIfFalse(target) => BytecodeInstruction::JumpIfFalse(target + offset),
+        BytecodeInstruction::JumpIfTrue(target) => BytecodeInstruction::JumpIfTrue(target + offset),
+        BytecodeInstruction::Try(target) => BytecodeInstruction::Try(target + offset),
+        other => other,
+    }
+}
+
+// Outermost call first, with repeated calls and short repeating cycles collapsed,
+// e.g. `main -> walk (x9999)` or `main -> (is_even -> is_odd) (x5000)`
+fn summarize_call_chain(chain: &[&str]) -> String {
+    const MAX_CYCLE: usize = 4;
+    const MAX_PARTS: usize = 16;
+    let mut parts = Vec::new();
+    let mut i = 0;
+    while i < chain.len() {
+        // The cycle length that covers the most calls from here
+        let (length, repeats) = (1..=MAX_CYCLE.min(chain.len() - i))
+            .map(|length| {
+                let cycle = &chain[i..i + length];
+                let repeats = chain[i..].chunks(length).take_while(|chunk| *chunk == cycle).count();
+                (length, repeats)
+            })
+            .max_by_key(|&(length, repeats)| (if repeats > 1 { length * repeats } else { 0 }, usize::MAX - length))
+            .unwrap();
+        if repeats > 1 {
+            let cycle = chain[i..i + length].join(" -> ");
+            let cycle = if length > 1 { format!("({})", cycle) } else { cycle };
+            parts.push(format!("{} (x{})", cycle, repeats));
+            i += length * repeats;
+        } else {
+            parts.push(chain[i].to_string());
+            i += 1;
+        }
+    }
+    if parts.len() > MAX_PARTS {
+        let hidden = parts.len() - MAX_PARTS;
+        parts.splice(MAX_PARTS / 2..MAX_PARTS / 2 + hidden, [format!("... {} more ...", hidden)]);
+    }
+    parts.join(" -> ")
+}
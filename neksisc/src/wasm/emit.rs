@@ -0,0 +1,910 @@
+// WebAssembly code generation
+//
+// Compiles the IR of the top level and every function it can reach. Each
+// IR temp and variable becomes a typed local, each function a WebAssembly
+// function, and the control-flow graph is turned back into structured
+// `block`/`loop`/`if` code with the dominator-tree method from Ramsey's
+// "Beyond Relooper": a block that several earlier blocks jump to ends an
+// enclosing `block`, and a loop header starts a `loop`.
+
+use super::encoder::{FuncType, Instr::*, ValType};
+use super::runtime::{Body, Helper, Runtime};
+use super::types::{self, Rule, Type, UnitTypes};
+use crate::error::CompilerError;
+use crate::ir::{self, BinOp, BlockId, Builtin, Const, Function, Op, Temp, Terminator, UnOp, Unit};
+use crate::vm::BytecodeInstruction;
+use std::collections::{HashMap, HashSet};
+
+fn unsupported(what: &str) -> CompilerError {
+    CompilerError::codegen_error("wasm", &format!("{} uses a construct the wasm32-wasi target does not support", what))
+}
+
+struct Callee {
+    index: u32,
+    params: usize,
+    result: Type,
+}
+
+/// Compile `module` to a WASI command module
+pub fn emit_module(module: &ir::Module, max_call_depth: usize) -> Result<Vec<u8>, CompilerError> {
+    let top_level = match &module.top_level {
+        Unit::Ir(function) => function,
+        Unit::Bytecode(_) => return Err(unsupported("the top level")),
+    };
+    // The VM runs the last definition of a name
+    let mut definitions: HashMap<&str, &Unit> = HashMap::new();
+    for unit in &module.functions {
+        let name = match unit {
+            Unit::Ir(function) => function.name.as_deref().unwrap_or_default(),
+            Unit::Bytecode(code) => match code.first() {
+                Some(BytecodeInstruction::DefineFunction(name, _)) => name.as_str(),
+                _ => continue,
+            },
+        };
+        definitions.insert(name, unit);
+    }
+
+    // Only the functions the top level can reach are compiled
+    let mut units = vec![top_level];
+    let mut callees: HashMap<String, usize> = HashMap::new();
+    let mut next = 0;
+    while next < units.len() {
+        let function = units[next];
+        next += 1;
+        let reachable = types::reachable_blocks(function);
+        for (_, block) in function.blocks.iter().enumerate().filter(|(id, _)| reachable[*id]) {
+            for inst in &block.insts {
+                let Op::Call(name, _) = &inst.op else { continue };
+                if callees.contains_key(name) {
+                    continue;
+                }
+                match definitions.get(name.as_str()) {
+                    Some(Unit::Ir(callee)) => {
+                        callees.insert(name.clone(), units.len());
+                        units.push(callee);
+                    }
+                    Some(Unit::Bytecode(_)) => return Err(unsupported(&format!("function '{}'", name))),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    let unit_types = types::infer(&units, &callees)?;
+    let mut runtime = Runtime::new();
+    let indices: Vec<u32> = unit_types
+        .iter()
+        .enumerate()
+        .map(|(unit, types)| {
+            let params: Vec<ValType> = types.params.iter().map(Type::val_type).collect();
+            let results = if unit == 0 { Vec::new() } else { vec![types.result.val_type()] };
+            runtime.module.declare_function(FuncType::new(&params, &results))
+        })
+        .collect();
+    let callees: HashMap<String, Callee> = callees
+        .into_iter()
+        .map(|(name, unit)| {
+            let callee = Callee { index: indices[unit], params: units[unit].params.len(), result: unit_types[unit].result.clone() };
+            (name, callee)
+        })
+        .collect();
+
+    for (unit, function) in units.iter().enumerate() {
+        let emitter = FunctionEmitter::new(&mut runtime, function, &unit_types[unit], &callees, max_call_depth)?;
+        let (locals, code) = emitter.emit()?;
+        runtime.module.define_function(indices[unit], locals, code);
+    }
+    Ok(runtime.finish(indices[0]).encode())
+}
+
+/// An enclosing construct a `br` can target, innermost last
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frame {
+    IfThenElse,
+    LoopHeadedBy(BlockId),
+    BlockFollowedBy(BlockId),
+}
+
+struct FunctionEmitter<'a> {
+    runtime: &'a mut Runtime,
+    function: &'a Function,
+    types: &'a UnitTypes,
+    callees: &'a HashMap<String, Callee>,
+    max_call_depth: usize,
+    context: String,
+    body: Body,
+    temps: Vec<u32>,
+    variables: HashMap<String, u32>,
+    /// Set once a variable that may be read before it is stored has been stored
+    flags: HashMap<String, u32>,
+    assigned_on_entry: Vec<HashSet<String>>,
+    rpo_index: Vec<usize>,
+    children: Vec<Vec<BlockId>>,
+    merge: Vec<bool>,
+    loop_header: Vec<bool>,
+    frames: Vec<Frame>,
+}
+
+impl<'a> FunctionEmitter<'a> {
+    fn new(
+        runtime: &'a mut Runtime,
+        function: &'a Function,
+        types: &'a UnitTypes,
+        callees: &'a HashMap<String, Callee>,
+        max_call_depth: usize,
+    ) -> Result<Self, CompilerError> {
+        let context = match &function.name {
+            Some(name) => format!("function '{}'", name),
+            None => "the top level".to_string(),
+        };
+        let mut body = Body::new(function.params.len());
+        let temps = types.temps.iter().map(|ty| body.local(ty.val_type())).collect();
+        let mut variables: HashMap<String, u32> =
+            function.params.iter().enumerate().map(|(index, name)| (name.clone(), index as u32)).collect();
+        let mut names: Vec<&String> = types.variables.keys().filter(|name| !variables.contains_key(*name)).collect();
+        names.sort();
+        for name in names {
+            variables.insert(name.clone(), body.local(types.variables[name].val_type()));
+        }
+
+        let order = function.reverse_postorder();
+        let mut rpo_index = vec![usize::MAX; function.blocks.len()];
+        for (index, &block) in order.iter().enumerate() {
+            rpo_index[block] = index;
+        }
+        let idom = function.dominators();
+        let predecessors = function.predecessors();
+        let mut children = vec![Vec::new(); function.blocks.len()];
+        let mut merge = vec![false; function.blocks.len()];
+        let mut loop_header = vec![false; function.blocks.len()];
+        for &block in &order {
+            if block != 0 {
+                children[idom[block].expect("reachable blocks have a dominator")].push(block);
+            }
+            let mut forward = 0;
+            for &predecessor in predecessors[block].iter().filter(|&&p| rpo_index[p] != usize::MAX) {
+                if rpo_index[predecessor] < rpo_index[block] {
+                    forward += 1;
+                } else if Function::dominates(&idom, block, predecessor) {
+                    loop_header[block] = true;
+                } else {
+                    return Err(CompilerError::codegen_error(
+                        "wasm",
+                        &format!("{} has control flow the wasm32-wasi target cannot structure", context),
+                    ));
+                }
+            }
+            merge[block] = forward >= 2;
+        }
+        // The latest merge child must end the outermost block
+        for list in &mut children {
+            list.sort_by_key(|&child| std::cmp::Reverse(rpo_index[child]));
+        }
+
+        let mut emitter = Self {
+            runtime,
+            function,
+            types,
+            callees,
+            max_call_depth,
+            context,
+            body,
+            temps,
+            variables,
+            flags: HashMap::new(),
+            assigned_on_entry: Vec::new(),
+            rpo_index,
+            children,
+            merge,
+            loop_header,
+            frames: Vec::new(),
+        };
+        emitter.definite_assignment(&order, &predecessors);
+        Ok(emitter)
+    }
+
+    /// Find the variables stored on every path to each block, and give a flag
+    /// to those a load may read before any store
+    fn definite_assignment(&mut self, order: &[BlockId], predecessors: &[Vec<BlockId>]) {
+        let params: HashSet<String> = self.function.params.iter().cloned().collect();
+        let mut on_exit: Vec<Option<HashSet<String>>> = vec![None; self.function.blocks.len()];
+        let mut on_entry: Vec<HashSet<String>> = vec![HashSet::new(); self.function.blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order {
+                let mut assigned = if block == 0 {
+                    params.clone()
+                } else {
+                    let mut incoming = predecessors[block].iter().filter_map(|&p| on_exit[p].as_ref());
+                    let Some(first) = incoming.next() else { continue };
+                    let mut assigned = first.clone();
+                    for other in incoming {
+                        assigned.retain(|name| other.contains(name));
+                    }
+                    assigned
+                };
+                on_entry[block] = assigned.clone();
+                for inst in &self.function.blocks[block].insts {
+                    if let Op::Store(name, _) = &inst.op {
+                        assigned.insert(name.clone());
+                    }
+                }
+                if on_exit[block].as_ref() != Some(&assigned) {
+                    on_exit[block] = Some(assigned);
+                    changed = true;
+                }
+            }
+        }
+
+        for &block in order {
+            let mut assigned = on_entry[block].clone();
+            for inst in &self.function.blocks[block].insts {
+                match &inst.op {
+                    Op::Load(name)
+                        if self.variables.contains_key(name)
+                            && !assigned.contains(name)
+                            && !self.flags.contains_key(name) =>
+                    {
+                        let flag = self.body.local(ValType::I32);
+                        self.flags.insert(name.clone(), flag);
+                    }
+                    Op::Store(name, _) => {
+                        assigned.insert(name.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.assigned_on_entry = on_entry;
+    }
+
+    fn emit(mut self) -> Result<(Vec<ValType>, Vec<crate::wasm::encoder::Instr>), CompilerError> {
+        if self.function.name.is_some() {
+            // Calls nested deeper than the limit fail like the VM's
+            let depth = self.runtime.depth;
+            self.body.emit([GlobalGet(depth), I32Const(self.max_call_depth as i32), I32GeU, If]);
+            let message = format!("StackOverflow: maximum call depth of {} exceeded", self.max_call_depth);
+            self.runtime.fail(&mut self.body, &message);
+            self.body.emit([End, GlobalGet(depth), I32Const(1), I32Add, GlobalSet(depth)]);
+        }
+        self.do_tree(0)?;
+        Ok(self.body.finish())
+    }
+
+    fn do_tree(&mut self, block: BlockId) -> Result<(), CompilerError> {
+        let merges: Vec<BlockId> = self.children[block].iter().copied().filter(|&child| self.merge[child]).collect();
+        if self.loop_header[block] {
+            self.body.emit([Loop]);
+            self.frames.push(Frame::LoopHeadedBy(block));
+            self.node_within(block, &merges)?;
+            self.frames.pop();
+            self.body.emit([End]);
+            Ok(())
+        } else {
+            self.node_within(block, &merges)
+        }
+    }
+
+    fn node_within(&mut self, block: BlockId, merges: &[BlockId]) -> Result<(), CompilerError> {
+        if let Some((&follower, rest)) = merges.split_first() {
+            self.body.emit([Block]);
+            self.frames.push(Frame::BlockFollowedBy(follower));
+            self.node_within(block, rest)?;
+            self.frames.pop();
+            self.body.emit([End]);
+            return self.do_tree(follower);
+        }
+        let tail_call = self.tail_call(block);
+        self.block_code(block, tail_call)?;
+        match self.function.blocks[block].term {
+            Terminator::Jump(target) => self.do_branch(block, target),
+            Terminator::Branch(_, then_block, else_block) if then_block == else_block => self.do_branch(block, then_block),
+            Terminator::Branch(condition, then_block, else_block) => {
+                self.get(condition);
+                let ty = self.ty(condition);
+                self.runtime.truthy(&mut self.body, &ty);
+                self.body.emit([If]);
+                self.frames.push(Frame::IfThenElse);
+                self.do_branch(block, then_block)?;
+                self.body.emit([Else]);
+                self.do_branch(block, else_block)?;
+                self.frames.pop();
+                self.body.emit([End]);
+                Ok(())
+            }
+            // The call already returned
+            Terminator::Return(_) if tail_call.is_some() => Ok(()),
+            Terminator::Return(value) => {
+                self.ret(Some(value));
+                Ok(())
+            }
+            Terminator::End => {
+                self.ret(None);
+                Ok(())
+            }
+        }
+    }
+
+    fn do_branch(&mut self, from: BlockId, to: BlockId) -> Result<(), CompilerError> {
+        if self.rpo_index[to] <= self.rpo_index[from] || self.merge[to] {
+            let depth = self
+                .frames
+                .iter()
+                .rev()
+                .position(|frame| matches!(frame, Frame::LoopHeadedBy(target) | Frame::BlockFollowedBy(target) if *target == to))
+                .expect("a branch target encloses the branch");
+            self.body.emit([Br(depth as u32)]);
+            Ok(())
+        } else {
+            self.do_tree(to)
+        }
+    }
+
+    fn ret(&mut self, value: Option<Temp>) {
+        match (&self.function.name, value) {
+            (Some(_), Some(value)) => {
+                let depth = self.runtime.depth;
+                self.body.emit([GlobalGet(depth), I32Const(1), I32Sub, GlobalSet(depth)]);
+                self.get(value);
+                self.body.emit([Return]);
+            }
+            (None, _) => self.body.emit([Return]),
+            // Functions always end in a return
+            (Some(_), None) => self.body.emit([Unreachable]),
+        }
+    }
+
+    /// The call whose result `block` returns as is, which the VM runs in the
+    /// caller's frame; a `return_call` keeps the call depth the same
+    fn tail_call(&self, block: BlockId) -> Option<usize> {
+        let Terminator::Return(value) = self.function.blocks[block].term else { return None };
+        self.function.name.as_ref()?;
+        let insts = &self.function.blocks[block].insts;
+        let (index, inst) = insts.iter().enumerate().rev().find(|(_, inst)| !matches!(inst.op, Op::Line(_)))?;
+        match &inst.op {
+            Op::Call(name, args) if inst.dest == Some(value) => {
+                let callee = self.callees.get(name)?;
+                (callee.params == args.len() && callee.result == self.types.result).then_some(index)
+            }
+            _ => None,
+        }
+    }
+
+    fn ty(&self, temp: Temp) -> Type {
+        self.types.temps[temp].clone()
+    }
+
+    fn get(&mut self, temp: Temp) {
+        self.body.emit([LocalGet(self.temps[temp])]);
+    }
+
+    /// Push `temp` as a Float
+    fn get_float(&mut self, temp: Temp) {
+        self.get(temp);
+        if self.ty(temp) == Type::Int {
+            self.body.emit([F64ConvertI64S]);
+        }
+    }
+
+    /// Store the value on top of the stack in `dest`, or drop it
+    fn set(&mut self, dest: Option<Temp>) {
+        match dest {
+            Some(dest) => self.body.emit([LocalSet(self.temps[dest])]),
+            None => self.body.emit([Drop]),
+        }
+    }
+
+    fn fail(&mut self, message: &str) {
+        self.runtime.fail(&mut self.body, message);
+    }
+
+    /// Raise the error message on top of the stack
+    fn fail_with_message(&mut self) {
+        let fail = self.runtime.call(Helper::Fail);
+        self.body.emit([fail, Unreachable]);
+    }
+
+    fn call(&mut self, helper: Helper) {
+        let call = self.runtime.call(helper);
+        self.body.emit([call]);
+    }
+
+    /// Whether to compile an operation with `rule`; failing operations are
+    /// compiled to their error instead
+    fn check(&mut self, rule: Rule, args: &[Temp], dest: Option<Temp>) -> bool {
+        let result = match rule {
+            Rule::Fails(message) => {
+                self.fail(message);
+                return false;
+            }
+            Rule::Is(ty) => ty,
+            Rule::SameAs(arg) => self.ty(args[arg]),
+            Rule::ElementOf(arg) => self.ty(args[arg]).element().clone(),
+        };
+        match dest {
+            // An operand that is never produced, e.g. the result of a call that always fails
+            Some(dest) if self.types.temps[dest] != result => {
+                self.body.emit([Unreachable]);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn block_code(&mut self, block: BlockId, tail_call: Option<usize>) -> Result<(), CompilerError> {
+        let mut assigned = self.assigned_on_entry[block].clone();
+        for (index, inst) in self.function.blocks[block].insts.iter().enumerate() {
+            let dest = inst.dest;
+            match &inst.op {
+                Op::Const(value) => {
+                    self.constant(value);
+                    self.set(dest);
+                }
+                Op::Load(name) => match self.variables.get(name).copied() {
+                    Some(local) => {
+                        if !assigned.contains(name) {
+                            let flag = self.flags[name];
+                            self.body.emit([LocalGet(flag), I32Eqz, If]);
+                            self.fail(&format!("Undefined variable: {}", name));
+                            self.body.emit([End]);
+                        }
+                        self.body.emit([LocalGet(local)]);
+                        self.set(dest);
+                    }
+                    None if matches!(name.as_str(), "print" | "println" | "read_line") => {
+                        return Err(CompilerError::codegen_error(
+                            "wasm",
+                            &format!("{} uses the builtin '{}' as a value, which the wasm32-wasi target does not support", self.context, name),
+                        ));
+                    }
+                    None => self.fail(&format!("Undefined variable: {}", name)),
+                },
+                Op::Store(name, value) => {
+                    self.get(*value);
+                    self.body.emit([LocalSet(self.variables[name])]);
+                    if let Some(&flag) = self.flags.get(name) {
+                        self.body.emit([I32Const(1), LocalSet(flag)]);
+                    }
+                    assigned.insert(name.clone());
+                }
+                Op::Binary(op, left, right) => self.binary(*op, *left, *right, dest),
+                Op::Unary(op, operand) => self.unary(*op, *operand, dest),
+                Op::Builtin(builtin, args) => self.builtin(builtin, args, dest)?,
+                Op::Call(name, args) => self.call_function(name, args, dest, tail_call == Some(index)),
+                Op::ArrayLiteral(values) => self.array_literal(values, dest),
+                Op::GetIndex(array, index) => {
+                    let rule = types::index_rule(self.ty(*array).head(), self.ty(*index).head());
+                    if self.check(rule, &[*array, *index], dest) {
+                        let element = self.ty(*array).element().clone();
+                        self.get(*array);
+                        self.get(*index);
+                        self.call(Helper::IndexAddr);
+                        Runtime::load(&mut self.body, &element, 0);
+                        self.set(dest);
+                    }
+                }
+                Op::Line(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn constant(&mut self, value: &Const) {
+        let instr = match value {
+            Const::Int(value) => I64Const(*value),
+            Const::Float(value) => F64Const(*value),
+            Const::Bool(value) => I32Const(*value as i32),
+            Const::String(value) => I32Const(self.runtime.string(value)),
+            Const::Null => I32Const(0),
+        };
+        self.body.emit([instr]);
+    }
+
+    fn binary(&mut self, op: BinOp, left: Temp, right: Temp, dest: Option<Temp>) {
+        let (left_type, right_type) = (self.ty(left), self.ty(right));
+        let rule = types::binary_rule(op, left_type.head(), right_type.head());
+        if !self.check(rule.clone(), &[left, right], dest) {
+            return;
+        }
+        let ints = left_type == Type::Int && right_type == Type::Int;
+        match op {
+            BinOp::Add if rule == Rule::Is(Type::Str) => {
+                self.get(left);
+                self.runtime.stringify(&mut self.body, &left_type);
+                self.get(right);
+                self.runtime.stringify(&mut self.body, &right_type);
+                self.call(Helper::Concat);
+            }
+            BinOp::Add | BinOp::Sub | BinOp::Mul if ints => {
+                self.get(left);
+                self.get(right);
+                self.body.emit([match op {
+                    BinOp::Add => I64Add,
+                    BinOp::Sub => I64Sub,
+                    _ => I64Mul,
+                }]);
+            }
+            BinOp::Add | BinOp::Sub | BinOp::Mul => {
+                self.get_float(left);
+                self.get_float(right);
+                self.body.emit([match op {
+                    BinOp::Add => F64Add,
+                    BinOp::Sub => F64Sub,
+                    _ => F64Mul,
+                }]);
+            }
+            BinOp::Div | BinOp::Mod if ints => {
+                self.get(right);
+                self.body.emit([I64Eqz, If]);
+                self.fail("Division by zero");
+                self.body.emit([End]);
+                if op == BinOp::Mod {
+                    self.get(left);
+                    self.get(right);
+                    self.body.emit([I64RemS]);
+                } else {
+                    // i64::MIN / -1 wraps instead of trapping
+                    self.body.emit([I64Const(0)]);
+                    self.get(left);
+                    self.body.emit([I64Sub]);
+                    self.get(left);
+                    self.get(right);
+                    self.body.emit([I64Const(1)]);
+                    self.get(right);
+                    self.body.emit([I64Const(-1), I64Ne, Select, I64DivS]);
+                    self.get(right);
+                    self.body.emit([I64Const(-1), I64Eq, Select]);
+                }
+            }
+            BinOp::Div | BinOp::Mod => {
+                let divisor = self.body.local(ValType::F64);
+                self.get_float(right);
+                self.body.emit([LocalTee(divisor), F64Const(0.0), F64Eq, If]);
+                self.fail("Division by zero");
+                self.body.emit([End]);
+                self.get_float(left);
+                self.body.emit([LocalGet(divisor)]);
+                if op == BinOp::Div {
+                    self.body.emit([F64Div]);
+                } else {
+                    self.call(Helper::FloatMod);
+                }
+            }
+            BinOp::Eq | BinOp::Ne => {
+                let (a, b) = (self.temps[left], self.temps[right]);
+                self.runtime.equal(&mut self.body, (a, &left_type), (b, &right_type), false);
+                if op == BinOp::Ne {
+                    self.body.emit([I32Eqz]);
+                }
+            }
+            BinOp::And | BinOp::Or => {
+                self.get(left);
+                self.runtime.truthy(&mut self.body, &left_type);
+                self.get(right);
+                self.runtime.truthy(&mut self.body, &right_type);
+                self.body.emit([if op == BinOp::And { I32And } else { I32Or }]);
+            }
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => self.compare(op, left, right),
+        }
+        self.set(dest);
+    }
+
+    fn compare(&mut self, op: BinOp, left: Temp, right: Temp) {
+        let index = |op| match op {
+            BinOp::Lt => 0,
+            BinOp::Le => 1,
+            BinOp::Gt => 2,
+            _ => 3,
+        };
+        match (self.ty(left), self.ty(right)) {
+            (Type::Int, Type::Int) => {
+                self.get(left);
+                self.get(right);
+                self.body.emit([[I64LtS, I64LeS, I64GtS, I64GeS][index(op)]]);
+            }
+            (Type::Str, Type::Str) => {
+                self.get(left);
+                self.get(right);
+                self.call(Helper::StrCmp);
+                let (bound, compare) = [(0, I32LtS), (1, I32LtS), (0, I32GtS), (0, I32GeS)][index(op)];
+                self.body.emit([I32Const(bound), compare]);
+            }
+            (Type::Bool, Type::Bool) => {
+                self.get(left);
+                self.get(right);
+                self.body.emit([[I32LtU, I32LeU, I32GtU, I32GeU][index(op)]]);
+            }
+            _ => {
+                // NaN has no order, which the VM reports as an error
+                let (a, b) = (self.body.local(ValType::F64), self.body.local(ValType::F64));
+                self.get_float(left);
+                self.body.emit([LocalSet(a)]);
+                self.get_float(right);
+                self.body.emit([LocalSet(b), LocalGet(a), LocalGet(a), F64Ne, LocalGet(b), LocalGet(b), F64Ne, I32Or, If]);
+                self.fail("Cannot compare values");
+                self.body.emit([End, LocalGet(a), LocalGet(b), [F64Lt, F64Le, F64Gt, F64Ge][index(op)]]);
+            }
+        }
+    }
+
+    fn unary(&mut self, op: UnOp, operand: Temp, dest: Option<Temp>) {
+        let ty = self.ty(operand);
+        if !self.check(types::unary_rule(op, ty.head()), &[operand], dest) {
+            return;
+        }
+        match op {
+            UnOp::Neg if ty == Type::Int => {
+                self.body.emit([I64Const(0)]);
+                self.get(operand);
+                self.body.emit([I64Sub]);
+            }
+            UnOp::Neg => {
+                self.get(operand);
+                self.body.emit([F64Neg]);
+            }
+            UnOp::Not => {
+                self.get(operand);
+                self.runtime.truthy(&mut self.body, &ty);
+                self.body.emit([I32Eqz]);
+            }
+            UnOp::ToString => {
+                self.get(operand);
+                self.runtime.stringify(&mut self.body, &ty);
+            }
+        }
+        self.set(dest);
+    }
+
+    fn builtin(&mut self, builtin: &Builtin, args: &[Temp], dest: Option<Temp>) -> Result<(), CompilerError> {
+        let arg_types: Vec<Type> = args.iter().map(|&arg| self.ty(arg)).collect();
+        let heads: Vec<_> = arg_types.iter().map(Type::head).collect();
+        let rule = types::builtin_rule(builtin.name, &heads).map_err(|what| {
+            CompilerError::codegen_error(
+                "wasm",
+                &format!("{} uses {}, which the wasm32-wasi target does not support", self.context, what),
+            )
+        })?;
+        if !self.check(rule, args, dest) {
+            return Ok(());
+        }
+        let first = arg_types.first().cloned().unwrap_or(Type::Null);
+        match builtin.name {
+            "print" | "println" => {
+                self.get(args[0]);
+                self.runtime.stringify(&mut self.body, &first);
+                self.runtime.write(&mut self.body, 1, true);
+                if dest.is_some() {
+                    self.body.emit([I32Const(0)]);
+                    self.set(dest);
+                }
+                return Ok(());
+            }
+            "abs" if first == Type::Int => {
+                self.body.emit([I64Const(0)]);
+                self.get(args[0]);
+                self.body.emit([I64Sub]);
+                self.get(args[0]);
+                self.get(args[0]);
+                self.body.emit([I64Const(0), I64LtS, Select]);
+            }
+            "abs" => {
+                self.get(args[0]);
+                self.body.emit([F64Abs]);
+            }
+            "sqrt" => {
+                self.get(args[0]);
+                if first == Type::Int {
+                    self.body.emit([I64Const(0), I64LtS, If]);
+                } else {
+                    self.body.emit([F64Const(0.0), F64Lt, If]);
+                }
+                self.fail("sqrt of negative number");
+                self.body.emit([End]);
+                self.get_float(args[0]);
+                self.body.emit([F64Sqrt]);
+            }
+            "floor" | "ceil" | "round" => {
+                self.get(args[0]);
+                if first == Type::Float {
+                    match builtin.name {
+                        "floor" => self.body.emit([F64Floor]),
+                        "ceil" => self.body.emit([F64Ceil]),
+                        _ => self.call(Helper::Round),
+                    }
+                    // Saturates like Rust's `as i64`
+                    self.body.emit([I64TruncSatF64S]);
+                }
+            }
+            "pow" => {
+                self.get(args[0]);
+                self.get(args[1]);
+                self.call(Helper::PowInt);
+            }
+            "min" | "max" if arg_types.iter().all(|ty| *ty == Type::Int) => {
+                self.get(args[0]);
+                self.get(args[1]);
+                self.get(args[0]);
+                self.get(args[1]);
+                self.body.emit([if builtin.name == "min" { I64LtS } else { I64GtS }, Select]);
+            }
+            "min" | "max" => {
+                // Like f64::min and f64::max, a NaN operand is ignored
+                let (a, b) = (self.body.local(ValType::F64), self.body.local(ValType::F64));
+                self.get_float(args[0]);
+                self.body.emit([LocalSet(a)]);
+                self.get_float(args[1]);
+                self.body.emit([LocalSet(b), LocalGet(a), LocalGet(b)]);
+                self.body.emit([if builtin.name == "min" { F64Min } else { F64Max }]);
+                self.body.emit([LocalGet(b), LocalGet(a), LocalGet(a), F64Eq, Select]);
+                self.body.emit([LocalGet(a), LocalGet(b), LocalGet(b), F64Eq, Select]);
+            }
+            "len" => {
+                self.get(args[0]);
+                self.body.emit([I32Load(0), I64ExtendI32U]);
+            }
+            "typeof" => {
+                let name = self.runtime.string(first.type_name());
+                self.body.emit([I32Const(name)]);
+            }
+            "contains" => {
+                self.get(args[0]);
+                self.get(args[1]);
+                self.body.emit([I32Const(0)]);
+                self.call(Helper::Find);
+                self.body.emit([I32Const(0), I32GeS]);
+            }
+            "join" if matches!(first.element(), Type::Array(_)) => {
+                self.get(args[0]);
+                self.body.emit([I32Load(0), If]);
+                self.fail("join can only handle primitive types");
+                let empty = self.runtime.string("");
+                self.body.emit([End, I32Const(empty)]);
+            }
+            "array_push" => {
+                let element = first.element().clone();
+                self.get(args[0]);
+                self.call(Helper::ArrayReserve);
+                self.get(args[1]);
+                Runtime::store(&mut self.body, &element, 0);
+                self.get(args[0]);
+            }
+            name => {
+                let helper = match name {
+                    "substring" => Helper::Substring,
+                    "concat" => Helper::Concat,
+                    "starts_with" => Helper::StartsWith,
+                    "ends_with" => Helper::EndsWith,
+                    "to_upper" => Helper::ChangeCase(true),
+                    "to_lower" => Helper::ChangeCase(false),
+                    "trim" => Helper::Trim,
+                    "split" => Helper::Split,
+                    "join" => Helper::Join(first.element().clone()),
+                    "array_reverse" => Helper::ArrayReverse,
+                    "array_sort" => Helper::Sort(first.element().clone()),
+                    "array_slice" => Helper::ArraySlice,
+                    other => unreachable!("builtin_rule accepted '{}'", other),
+                };
+                for &arg in args {
+                    self.get(arg);
+                }
+                self.call(helper);
+            }
+        }
+        self.set(dest);
+        Ok(())
+    }
+
+    fn call_function(&mut self, name: &str, args: &[Temp], dest: Option<Temp>, tail: bool) {
+        let Some(callee) = self.callees.get(name) else {
+            return self.called_builtin(name, args, dest);
+        };
+        if callee.params != args.len() {
+            let message = format!("Function {} expects {} arguments, got {}", name, callee.params, args.len());
+            return self.fail(&message);
+        }
+        if dest.is_some_and(|dest| self.types.temps[dest] != callee.result) {
+            return self.body.emit([Unreachable]);
+        }
+        let index = callee.index;
+        if tail {
+            let depth = self.runtime.depth;
+            self.body.emit([GlobalGet(depth), I32Const(1), I32Sub, GlobalSet(depth)]);
+        }
+        for &arg in args {
+            self.get(arg);
+        }
+        if tail {
+            return self.body.emit([ReturnCall(index)]);
+        }
+        self.body.emit([Call(index)]);
+        self.set(dest);
+    }
+
+    /// The builtins the VM runs for calls to names that are not functions
+    fn called_builtin(&mut self, name: &str, args: &[Temp], dest: Option<Temp>) {
+        if !types::called_builtin(name, args.len()) {
+            let message = match name {
+                "eprint" | "eprintln" => format!("{} expects 1 argument", name),
+                "assert" => "assert expects 1 or 2 arguments".to_string(),
+                "assert_eq" | "assert_ne" => format!("{} expects 2 arguments", name),
+                _ => format!("Unknown built-in function: {}", name),
+            };
+            return self.fail(&message);
+        }
+        if !self.check(Rule::Is(Type::Null), args, dest) {
+            return;
+        }
+        match name {
+            "eprint" | "eprintln" => {
+                let ty = self.ty(args[0]);
+                self.get(args[0]);
+                self.runtime.stringify(&mut self.body, &ty);
+                self.runtime.write(&mut self.body, 2, name == "eprintln");
+            }
+            "assert" => {
+                let ty = self.ty(args[0]);
+                self.get(args[0]);
+                self.runtime.truthy(&mut self.body, &ty);
+                self.body.emit([I32Eqz, If]);
+                match args.get(1) {
+                    Some(&message) => {
+                        let prefix = self.runtime.string("assertion failed: ");
+                        let ty = self.ty(message);
+                        self.body.emit([I32Const(prefix)]);
+                        self.get(message);
+                        self.runtime.stringify(&mut self.body, &ty);
+                        self.call(Helper::Concat);
+                        self.fail_with_message();
+                    }
+                    None => self.fail("assertion failed"),
+                }
+                self.body.emit([End]);
+            }
+            _ => {
+                let expect_equal = name == "assert_eq";
+                let (left, right) = (args[0], args[1]);
+                let (left_type, right_type) = (self.ty(left), self.ty(right));
+                let (a, b) = (self.temps[left], self.temps[right]);
+                self.runtime.equal(&mut self.body, (a, &left_type), (b, &right_type), true);
+                if expect_equal {
+                    self.body.emit([I32Eqz]);
+                }
+                self.body.emit([If]);
+                let operator = if expect_equal { "==" } else { "!=" };
+                let heading = self.runtime.string(&format!("assertion failed: left {} right\n  left: ", operator));
+                let separator = self.runtime.string("\n right: ");
+                self.body.emit([I32Const(heading)]);
+                self.get(left);
+                self.runtime.stringify(&mut self.body, &left_type);
+                self.call(Helper::Concat);
+                self.body.emit([I32Const(separator)]);
+                self.call(Helper::Concat);
+                self.get(right);
+                self.runtime.stringify(&mut self.body, &right_type);
+                self.call(Helper::Concat);
+                self.fail_with_message();
+                self.body.emit([End]);
+            }
+        }
+        self.body.emit([I32Const(0)]);
+        self.set(dest);
+    }
+
+    fn array_literal(&mut self, values: &[Const], dest: Option<Temp>) {
+        let Some(dest) = dest else { return };
+        let Type::Array(element) = self.ty(dest) else {
+            return self.body.emit([Unreachable]);
+        };
+        let array = self.temps[dest];
+        self.body.emit([I32Const(values.len() as i32)]);
+        self.call(Helper::ArrayNew);
+        self.body.emit([LocalSet(array)]);
+        for (index, value) in values.iter().enumerate() {
+            self.body.emit([LocalGet(array), I32Load(8)]);
+            self.constant(value);
+            Runtime::store(&mut self.body, &element, index as u32 * 8);
+        }
+        self.body.emit([LocalGet(array), I32Const(values.len() as i32), I32Store(0)]);
+    }
+}
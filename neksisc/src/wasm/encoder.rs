@@ -0,0 +1,587 @@
+// WebAssembly binary encoder
+//
+// Builds a module in memory and writes it in the binary format: LEB128
+// integers, length-prefixed sections and vectors, and one opcode per
+// `Instr`. Only what the backend needs is modelled: functions with locals,
+// function imports, one linear memory, mutable i32 globals, exports and
+// active data segments.
+
+use std::collections::HashMap;
+
+const MAGIC: &[u8] = b"\0asm";
+const VERSION: &[u8] = &[1, 0, 0, 0];
+pub const PAGE_SIZE: u32 = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F64,
+}
+
+impl ValType {
+    fn byte(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+            ValType::F64 => 0x7c,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl FuncType {
+    pub fn new(params: &[ValType], results: &[ValType]) -> Self {
+        Self { params: params.to_vec(), results: results.to_vec() }
+    }
+}
+
+/// The instructions the backend emits. Memory accesses carry their static
+/// offset and use natural alignment; blocks never take or return values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instr {
+    Unreachable,
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    ReturnCall(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Load(u32),
+    I64Load(u32),
+    F64Load(u32),
+    I32Load8U(u32),
+    I32Store(u32),
+    I64Store(u32),
+    F64Store(u32),
+    I32Store8(u32),
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32LtU,
+    I32GtS,
+    I32GtU,
+    I32LeS,
+    I32LeU,
+    I32GeS,
+    I32GeU,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64LtU,
+    I64GtS,
+    I64GtU,
+    I64LeS,
+    I64GeS,
+    I64GeU,
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Gt,
+    F64Le,
+    F64Ge,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32DivU,
+    I32And,
+    I32Or,
+    I32Xor,
+    I32Shl,
+    I32ShrS,
+    I32ShrU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64DivU,
+    I64RemS,
+    I64RemU,
+    I64ShrU,
+    F64Abs,
+    F64Neg,
+    F64Ceil,
+    F64Floor,
+    F64Trunc,
+    F64Nearest,
+    F64Sqrt,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    F64Min,
+    F64Max,
+    F64Copysign,
+    I32WrapI64,
+    I64ExtendI32U,
+    F64ConvertI64S,
+    F64ConvertI64U,
+    I64TruncF64U,
+    I64TruncSatF64S,
+    I64ReinterpretF64,
+    MemoryCopy,
+}
+
+/// A defined function: its type, the locals after the parameters and the
+/// body without the final `end`
+#[derive(Debug, Clone, Default)]
+pub struct Function {
+    pub type_index: u32,
+    pub locals: Vec<ValType>,
+    pub body: Vec<Instr>,
+}
+
+#[derive(Debug, Clone)]
+struct Import {
+    module: String,
+    name: String,
+    type_index: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ExportKind {
+    Func,
+    Memory,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    types: Vec<FuncType>,
+    type_indices: HashMap<FuncType, u32>,
+    imports: Vec<Import>,
+    functions: Vec<Function>,
+    /// Initial values of the mutable i32 globals
+    globals: Vec<i32>,
+    exports: Vec<(String, ExportKind, u32)>,
+    data: Vec<(u32, Vec<u8>)>,
+    memory_pages: Option<u32>,
+}
+
+impl Module {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn type_index(&mut self, ty: FuncType) -> u32 {
+        if let Some(&index) = self.type_indices.get(&ty) {
+            return index;
+        }
+        let index = self.types.len() as u32;
+        self.types.push(ty.clone());
+        self.type_indices.insert(ty, index);
+        index
+    }
+
+    /// Import a function; imports must be added before any function is declared
+    pub fn import_function(&mut self, module: &str, name: &str, ty: FuncType) -> u32 {
+        assert!(self.functions.is_empty(), "imports come before defined functions");
+        let type_index = self.type_index(ty);
+        self.imports.push(Import { module: module.to_string(), name: name.to_string(), type_index });
+        self.imports.len() as u32 - 1
+    }
+
+    /// Reserve a function index; the body is filled in with `define`
+    pub fn declare_function(&mut self, ty: FuncType) -> u32 {
+        let type_index = self.type_index(ty);
+        self.functions.push(Function { type_index, ..Function::default() });
+        (self.imports.len() + self.functions.len() - 1) as u32
+    }
+
+    pub fn define_function(&mut self, index: u32, locals: Vec<ValType>, body: Vec<Instr>) {
+        let function = &mut self.functions[index as usize - self.imports.len()];
+        function.locals = locals;
+        function.body = body;
+    }
+
+    pub fn function_type(&self, index: u32) -> &FuncType {
+        let type_index = match (index as usize).checked_sub(self.imports.len()) {
+            Some(defined) => self.functions[defined].type_index,
+            None => self.imports[index as usize].type_index,
+        };
+        &self.types[type_index as usize]
+    }
+
+    pub fn add_global(&mut self, initial: i32) -> u32 {
+        self.globals.push(initial);
+        self.globals.len() as u32 - 1
+    }
+
+    pub fn set_global(&mut self, index: u32, initial: i32) {
+        self.globals[index as usize] = initial;
+    }
+
+    pub fn set_memory(&mut self, pages: u32) {
+        self.memory_pages = Some(pages);
+    }
+
+    pub fn add_data(&mut self, offset: u32, bytes: Vec<u8>) {
+        self.data.push((offset, bytes));
+    }
+
+    pub fn export(&mut self, name: &str, kind: ExportKind, index: u32) {
+        self.exports.push((name.to_string(), kind, index));
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(VERSION);
+
+        section(&mut out, 1, self.types.len(), |body| {
+            for ty in &self.types {
+                body.push(0x60);
+                val_types(body, &ty.params);
+                val_types(body, &ty.results);
+            }
+        });
+        section(&mut out, 2, self.imports.len(), |body| {
+            for import in &self.imports {
+                name(body, &import.module);
+                name(body, &import.name);
+                body.push(0x00);
+                uleb(body, import.type_index as u64);
+            }
+        });
+        section(&mut out, 3, self.functions.len(), |body| {
+            for function in &self.functions {
+                uleb(body, function.type_index as u64);
+            }
+        });
+        if let Some(pages) = self.memory_pages {
+            section(&mut out, 5, 1, |body| {
+                body.push(0x00);
+                uleb(body, pages as u64);
+            });
+        }
+        section(&mut out, 6, self.globals.len(), |body| {
+            for &initial in &self.globals {
+                body.push(ValType::I32.byte());
+                body.push(0x01);
+                encode_instr(body, Instr::I32Const(initial));
+                body.push(0x0b);
+            }
+        });
+        section(&mut out, 7, self.exports.len(), |body| {
+            for (export, kind, index) in &self.exports {
+                name(body, export);
+                body.push(match kind {
+                    ExportKind::Func => 0x00,
+                    ExportKind::Memory => 0x02,
+                });
+                uleb(body, *index as u64);
+            }
+        });
+        section(&mut out, 10, self.functions.len(), |body| {
+            for function in &self.functions {
+                let mut code = Vec::new();
+                // Runs of equal local types are declared together
+                let mut runs: Vec<(u32, ValType)> = Vec::new();
+                for &local in &function.locals {
+                    match runs.last_mut() {
+                        Some((count, ty)) if *ty == local => *count += 1,
+                        _ => runs.push((1, local)),
+                    }
+                }
+                uleb(&mut code, runs.len() as u64);
+                for (count, ty) in runs {
+                    uleb(&mut code, count as u64);
+                    code.push(ty.byte());
+                }
+                for &instr in &function.body {
+                    encode_instr(&mut code, instr);
+                }
+                code.push(0x0b);
+                uleb(body, code.len() as u64);
+                body.extend_from_slice(&code);
+            }
+        });
+        section(&mut out, 11, self.data.len(), |body| {
+            for (offset, bytes) in &self.data {
+                body.push(0x00);
+                encode_instr(body, Instr::I32Const(*offset as i32));
+                body.push(0x0b);
+                uleb(body, bytes.len() as u64);
+                body.extend_from_slice(bytes);
+            }
+        });
+        out
+    }
+}
+
+/// Append section `id` holding `count` entries, unless it would be empty
+fn section(out: &mut Vec<u8>, id: u8, count: usize, entries: impl FnOnce(&mut Vec<u8>)) {
+    if count == 0 {
+        return;
+    }
+    let mut body = Vec::new();
+    uleb(&mut body, count as u64);
+    entries(&mut body);
+    out.push(id);
+    uleb(out, body.len() as u64);
+    out.extend_from_slice(&body);
+}
+
+fn val_types(out: &mut Vec<u8>, types: &[ValType]) {
+    uleb(out, types.len() as u64);
+    out.extend(types.iter().map(|ty| ty.byte()));
+}
+
+fn name(out: &mut Vec<u8>, text: &str) {
+    uleb(out, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+pub fn uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn memarg(out: &mut Vec<u8>, align: u32, offset: u32) {
+    uleb(out, align as u64);
+    uleb(out, offset as u64);
+}
+
+fn encode_instr(out: &mut Vec<u8>, instr: Instr) {
+    use Instr::*;
+    // Blocks are all of the empty block type
+    const EMPTY: u8 = 0x40;
+    match instr {
+        Unreachable => out.push(0x00),
+        Block => out.extend_from_slice(&[0x02, EMPTY]),
+        Loop => out.extend_from_slice(&[0x03, EMPTY]),
+        If => out.extend_from_slice(&[0x04, EMPTY]),
+        Else => out.push(0x05),
+        End => out.push(0x0b),
+        Br(depth) => {
+            out.push(0x0c);
+            uleb(out, depth as u64);
+        }
+        BrIf(depth) => {
+            out.push(0x0d);
+            uleb(out, depth as u64);
+        }
+        Return => out.push(0x0f),
+        Call(index) => {
+            out.push(0x10);
+            uleb(out, index as u64);
+        }
+        ReturnCall(index) => {
+            out.push(0x12);
+            uleb(out, index as u64);
+        }
+        Drop => out.push(0x1a),
+        Select => out.push(0x1b),
+        LocalGet(index) | LocalSet(index) | LocalTee(index) | GlobalGet(index) | GlobalSet(index) => {
+            out.push(match instr {
+                LocalGet(_) => 0x20,
+                LocalSet(_) => 0x21,
+                LocalTee(_) => 0x22,
+                GlobalGet(_) => 0x23,
+                _ => 0x24,
+            });
+            uleb(out, index as u64);
+        }
+        I32Load(offset) => {
+            out.push(0x28);
+            memarg(out, 2, offset);
+        }
+        I64Load(offset) => {
+            out.push(0x29);
+            memarg(out, 3, offset);
+        }
+        F64Load(offset) => {
+            out.push(0x2b);
+            memarg(out, 3, offset);
+        }
+        I32Load8U(offset) => {
+            out.push(0x2d);
+            memarg(out, 0, offset);
+        }
+        I32Store(offset) => {
+            out.push(0x36);
+            memarg(out, 2, offset);
+        }
+        I64Store(offset) => {
+            out.push(0x37);
+            memarg(out, 3, offset);
+        }
+        F64Store(offset) => {
+            out.push(0x39);
+            memarg(out, 3, offset);
+        }
+        I32Store8(offset) => {
+            out.push(0x3a);
+            memarg(out, 0, offset);
+        }
+        MemorySize => out.extend_from_slice(&[0x3f, 0x00]),
+        MemoryGrow => out.extend_from_slice(&[0x40, 0x00]),
+        I32Const(value) => {
+            out.push(0x41);
+            sleb(out, value as i64);
+        }
+        I64Const(value) => {
+            out.push(0x42);
+            sleb(out, value);
+        }
+        F64Const(value) => {
+            out.push(0x44);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        I32Eqz => out.push(0x45),
+        I32Eq => out.push(0x46),
+        I32Ne => out.push(0x47),
+        I32LtS => out.push(0x48),
+        I32LtU => out.push(0x49),
+        I32GtS => out.push(0x4a),
+        I32GtU => out.push(0x4b),
+        I32LeS => out.push(0x4c),
+        I32LeU => out.push(0x4d),
+        I32GeS => out.push(0x4e),
+        I32GeU => out.push(0x4f),
+        I64Eqz => out.push(0x50),
+        I64Eq => out.push(0x51),
+        I64Ne => out.push(0x52),
+        I64LtS => out.push(0x53),
+        I64LtU => out.push(0x54),
+        I64GtS => out.push(0x55),
+        I64GtU => out.push(0x56),
+        I64LeS => out.push(0x57),
+        I64GeS => out.push(0x59),
+        I64GeU => out.push(0x5a),
+        F64Eq => out.push(0x61),
+        F64Ne => out.push(0x62),
+        F64Lt => out.push(0x63),
+        F64Gt => out.push(0x64),
+        F64Le => out.push(0x65),
+        F64Ge => out.push(0x66),
+        I32Add => out.push(0x6a),
+        I32Sub => out.push(0x6b),
+        I32Mul => out.push(0x6c),
+        I32DivU => out.push(0x6e),
+        I32And => out.push(0x71),
+        I32Or => out.push(0x72),
+        I32Xor => out.push(0x73),
+        I32Shl => out.push(0x74),
+        I32ShrS => out.push(0x75),
+        I32ShrU => out.push(0x76),
+        I64Add => out.push(0x7c),
+        I64Sub => out.push(0x7d),
+        I64Mul => out.push(0x7e),
+        I64DivS => out.push(0x7f),
+        I64DivU => out.push(0x80),
+        I64RemS => out.push(0x81),
+        I64RemU => out.push(0x82),
+        I64ShrU => out.push(0x88),
+        F64Abs => out.push(0x99),
+        F64Neg => out.push(0x9a),
+        F64Ceil => out.push(0x9b),
+        F64Floor => out.push(0x9c),
+        F64Trunc => out.push(0x9d),
+        F64Nearest => out.push(0x9e),
+        F64Sqrt => out.push(0x9f),
+        F64Add => out.push(0xa0),
+        F64Sub => out.push(0xa1),
+        F64Mul => out.push(0xa2),
+        F64Div => out.push(0xa3),
+        F64Min => out.push(0xa4),
+        F64Max => out.push(0xa5),
+        F64Copysign => out.push(0xa6),
+        I32WrapI64 => out.push(0xa7),
+        I64ExtendI32U => out.push(0xad),
+        F64ConvertI64S => out.push(0xb9),
+        F64ConvertI64U => out.push(0xba),
+        I64TruncF64U => out.push(0xb1),
+        I64TruncSatF64S => out.extend_from_slice(&[0xfc, 0x06]),
+        I64ReinterpretF64 => out.push(0xbd),
+        MemoryCopy => out.extend_from_slice(&[0xfc, 0x0a, 0x00, 0x00]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leb128() {
+        let encode = |value: i64| {
+            let mut out = Vec::new();
+            sleb(&mut out, value);
+            out
+        };
+        assert_eq!(encode(0), vec![0x00]);
+        assert_eq!(encode(63), vec![0x3f]);
+        assert_eq!(encode(64), vec![0xc0, 0x00]);
+        assert_eq!(encode(-1), vec![0x7f]);
+        assert_eq!(encode(-65), vec![0xbf, 0x7f]);
+        assert_eq!(encode(i64::MIN).len(), 10);
+
+        let mut out = Vec::new();
+        uleb(&mut out, 624485);
+        assert_eq!(out, vec![0xe5, 0x8e, 0x26]);
+    }
+
+    #[test]
+    fn test_empty_module() {
+        assert_eq!(Module::new().encode(), b"\0asm\x01\0\0\0".to_vec());
+    }
+
+    #[test]
+    fn test_function_and_export() {
+        let mut module = Module::new();
+        let add = module.declare_function(FuncType::new(&[ValType::I64, ValType::I64], &[ValType::I64]));
+        module.define_function(add, Vec::new(), vec![Instr::LocalGet(0), Instr::LocalGet(1), Instr::I64Add]);
+        module.export("add", ExportKind::Func, add);
+        let bytes = module.encode();
+        // type, function, export and code sections
+        let expected: &[u8] = &[
+            0x01, 0x07, 0x01, 0x60, 0x02, 0x7e, 0x7e, 0x01, 0x7e,
+            0x03, 0x02, 0x01, 0x00,
+            0x07, 0x07, 0x01, 0x03, b'a', b'd', b'd', 0x00, 0x00,
+            0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x7c, 0x0b,
+        ];
+        assert_eq!(&bytes[8..], expected);
+    }
+}
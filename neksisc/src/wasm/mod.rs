@@ -0,0 +1,340 @@
+// WebAssembly backend
+//
+// Compiles a program's IR straight to a WebAssembly binary for the
+// `wasm32-wasi` target, with no external toolchain: `neksis build --target
+// wasm32-wasi app.nx` writes `app.wasm`, which any WASI runtime can run
+// (`wasmtime app.wasm`). Output goes through `fd_write` and errors end the
+// program with exit code 1, as in the VM. Every value has one static type,
+// so programs that mix types in a variable or array are rejected at build
+// time. With the `wasm-backend` feature, `WASMRuntime` runs modules in
+// process with wasmtime.
+
+pub mod encoder;
+mod emit;
+mod runtime;
+mod types;
+
+use crate::ast::Program;
+use crate::error::CompilerError;
+use crate::vm::DEFAULT_MAX_CALL_DEPTH;
+
+pub struct WASMCompiler {
+    opt_level: u8,
+    max_call_depth: usize,
+}
+
+impl Default for WASMCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WASMCompiler {
+    pub fn new() -> Self {
+        Self { opt_level: 0, max_call_depth: DEFAULT_MAX_CALL_DEPTH }
+    }
+
+    // Optimize the IR at `level` first, as `-O<level>` does
+    pub fn with_optimization(mut self, level: u8) -> Self {
+        self.opt_level = level;
+        self
+    }
+
+    // Calls nested deeper than `depth` fail with a `StackOverflow` error, like `--max-stack`
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    pub fn compile_to_wasm(&self, program: &Program) -> Result<Vec<u8>, CompilerError> {
+        let module = if self.opt_level == 0 {
+            crate::ir::lower::lower_program(program)?
+        } else {
+            crate::ir::optimize_program(program, self.opt_level)?.0
+        };
+        emit::emit_module(&module, self.max_call_depth)
+    }
+}
+
+pub fn compile_to_wasm(program: &Program) -> Result<Vec<u8>, CompilerError> {
+    WASMCompiler::new().compile_to_wasm(program)
+}
+
+/// What a WASI program wrote and how it exited
+#[derive(Debug, Clone, PartialEq)]
+pub struct WasmOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+/// Runs WASI command modules with wasmtime, capturing their output
+#[cfg(feature = "wasm-backend")]
+pub struct WASMRuntime {
+    engine: wasmtime::Engine,
+}
+
+#[cfg(feature = "wasm-backend")]
+impl WASMRuntime {
+    // Deep recursion needs more than wasmtime's default 512 KiB of stack
+    const WASM_STACK: usize = 256 << 20;
+
+    pub fn new() -> Result<Self, CompilerError> {
+        let mut config = wasmtime::Config::new();
+        config.max_wasm_stack(Self::WASM_STACK);
+        let engine = wasmtime::Engine::new(&config)
+            .map_err(|e| CompilerError::runtime_error(&format!("Failed to create the WebAssembly engine: {}", e)))?;
+        Ok(Self { engine })
+    }
+
+    /// Run the `_start` function of `wasm_bytes`. Traps are errors; a
+    /// program that fails through `proc_exit` is not.
+    pub fn run(&self, wasm_bytes: &[u8]) -> Result<WasmOutput, CompilerError> {
+        use wasmtime_wasi::pipe::MemoryOutputPipe;
+        use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+
+        let module = wasmtime::Module::new(&self.engine, wasm_bytes)
+            .map_err(|e| CompilerError::runtime_error(&format!("Failed to load WASM module: {}", e)))?;
+        let stdout = MemoryOutputPipe::new(usize::MAX);
+        let stderr = MemoryOutputPipe::new(usize::MAX);
+        let wasi = wasmtime_wasi::WasiCtxBuilder::new().stdout(stdout.clone()).stderr(stderr.clone()).build_p1();
+        let mut linker: wasmtime::Linker<WasiP1Ctx> = wasmtime::Linker::new(&self.engine);
+        preview1::add_to_linker_sync(&mut linker, |ctx| ctx)
+            .map_err(|e| CompilerError::runtime_error(&format!("Failed to link WASI: {}", e)))?;
+        let engine = self.engine.clone();
+
+        // The wasm stack lives on the native one, so run on a thread that has room for it
+        let result = std::thread::Builder::new()
+            .stack_size(Self::WASM_STACK * 2)
+            .spawn(move || -> Result<i32, String> {
+                let mut store = wasmtime::Store::new(&engine, wasi);
+                let instance = linker.instantiate(&mut store, &module).map_err(|e| e.to_string())?;
+                let start = instance.get_typed_func::<(), ()>(&mut store, "_start").map_err(|e| e.to_string())?;
+                match start.call(&mut store, ()) {
+                    Ok(()) => Ok(0),
+                    Err(e) => match e.downcast_ref::<wasmtime_wasi::I32Exit>() {
+                        Some(exit) => Ok(exit.0),
+                        None => Err(format!("{:?}", e)),
+                    },
+                }
+            })
+            .map_err(|e| CompilerError::runtime_error(&format!("Failed to start the WebAssembly thread: {}", e)))?
+            .join()
+            .map_err(|_| CompilerError::runtime_error("The WebAssembly thread panicked"))?;
+        let exit_code = result.map_err(|e| CompilerError::runtime_error(&format!("WebAssembly trap: {}", e)))?;
+        Ok(WasmOutput {
+            stdout: String::from_utf8_lossy(&stdout.contents()).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr.contents()).into_owned(),
+            exit_code,
+        })
+    }
+}
+
+#[cfg(feature = "wasm-backend")]
+pub fn create_wasm_runtime() -> Result<WASMRuntime, CompilerError> {
+    WASMRuntime::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn compile(source: &str, level: u8) -> Result<Vec<u8>, CompilerError> {
+        let tokens = Lexer::new(source, "test.nx".to_string()).tokenize().unwrap();
+        let ast = Parser::new(tokens).with_line_markers().parse().unwrap();
+        WASMCompiler::new().with_optimization(level).compile_to_wasm(&ast)
+    }
+
+    fn validate(wasm: &[u8]) {
+        let mut validator = wasmparser::Validator::new();
+        if let Err(e) = validator.validate_all(wasm) {
+            panic!("invalid module: {} at offset {:#x}", e.message(), e.offset());
+        }
+    }
+
+    const PROGRAM: &str = r#"
+        fn fib(n: Int) -> Int {
+            if n < 2 {
+                return n;
+            }
+            return fib(n - 1) + fib(n - 2);
+        }
+        fn describe(x: Int) -> String {
+            let label = match x {
+                0 => "zero",
+                1 => "one",
+                _ => "many"
+            };
+            return label;
+        }
+        fn main() {
+            let total = 0;
+            let i = 0;
+            while i < 10 {
+                total = total + i;
+                i = i + 1;
+            }
+            println(total);
+            println(fib(15));
+            println(describe(1) + " " + describe(7));
+            let items = [5, 3, 9, 1];
+            array_push(items, 4);
+            println(array_sort(items));
+            println(split("a,b,,c", ","));
+            println(0.1 + 0.2);
+            println(1.0 / 3.0);
+            println(to_upper("MiXed") + trim("  x  ") + substring("hello", 1, 3));
+            println(max(3, 4.5));
+            println(typeof(items) + " " + typeof(1.5) + " " + len("abc"));
+            assert_eq(fib(10), 55);
+        }
+    "#;
+
+    #[test]
+    fn test_modules_validate() {
+        for level in [0, 1, 2] {
+            validate(&compile(PROGRAM, level).unwrap());
+        }
+        validate(&compile("println(\"hi\");\n", 0).unwrap());
+        validate(&compile("fn f() {\n    return undefined_name;\n}\nfn main() {\n    f(1);\n    missing(2);\n}\n", 0).unwrap());
+    }
+
+    #[test]
+    fn test_unsupported_programs() {
+        let error = compile("fn main() {\n    let x = 1;\n    x = \"one\";\n}\n", 0).unwrap_err();
+        assert!(error.message.contains("variable 'x' in function 'main' would hold both Int and String values"), "{}", error.message);
+        let error = compile("fn main() {\n    let items = [1, \"two\"];\n}\n", 0).unwrap_err();
+        assert!(error.message.contains("would hold both"), "{}", error.message);
+        let error = compile("fn main() {\n    println(sin(1.0));\n}\n", 0).unwrap_err();
+        assert!(error.message.contains("uses the builtin 'sin', which the wasm32-wasi target does not support"), "{}", error.message);
+        let error = compile("fn main() {\n    println(pow(2.0, 0.5));\n}\n", 0).unwrap_err();
+        assert!(error.message.contains("pow of a Float"), "{}", error.message);
+        let error = compile("fn main() {\n    let x = 1;\n    println(match x { 1 => \"one\" });\n}\n", 0).unwrap_err();
+        assert!(
+            error.message.contains("the match expression on line 3 in function 'main' would hold both String and Null values"),
+            "{}",
+            error.message
+        );
+    }
+
+    #[cfg(feature = "wasm-backend")]
+    mod run {
+        use super::*;
+        use crate::test_framework::golden::run_program_at;
+
+        fn run_wasm(source: &str, level: u8) -> WasmOutput {
+            let wasm = compile(source, level).unwrap();
+            validate(&wasm);
+            WASMRuntime::new().unwrap().run(&wasm).unwrap()
+        }
+
+        fn assert_same_as_vm(source: &str) {
+            for level in [0, 2] {
+                let expected = run_program_at(source, "test.nx", level);
+                let actual = run_wasm(source, level);
+                assert_eq!(
+                    (actual.stdout.as_str(), actual.stderr.as_str(), actual.exit_code),
+                    (expected.stdout.as_str(), expected.stderr.as_str(), expected.exit_code),
+                    "wasm output differs at -O{}",
+                    level
+                );
+            }
+        }
+
+        #[test]
+        fn test_values_and_builtins() {
+            assert_same_as_vm(PROGRAM);
+            assert_same_as_vm(r#"
+                fn main() {
+                    println(1e21 * 10.0);
+                    println(0.000001234);
+                    println(2.0);
+                    println(-0.0);
+                    println(123456.789);
+                    println(-7 / 2);
+                    println(7 % -3);
+                    println(7.5 % 2.0);
+                    println(round(-2.5));
+                    println(floor(2.7) + ceil(2.1));
+                    println(sqrt(16));
+                    println(pow(3, 4));
+                    println(min(2, 1) + abs(-5));
+                    println(contains("haystack", "st") == starts_with("abc", "ab"));
+                    println(ends_with("abc", "bc"));
+                    println(split("abc", ""));
+                    println(array_reverse([1, 2, 3]));
+                    println(array_slice([1, 2, 3, 4], 1, 3));
+                    println(["b", "a"] == ["b", "a"]);
+                    println("a" < "b");
+                    println(!true || 0);
+                    let words = ["pear", "apple", "fig"];
+                    println(array_sort(words));
+                    let empty = [];
+                    println(len(empty));
+                    eprintln("to stderr");
+                }
+            "#);
+        }
+
+        #[test]
+        fn test_errors_and_calls() {
+            assert_same_as_vm(r#"
+                fn deep(n: Int) -> Int {
+                    if n == 0 {
+                        return 0;
+                    }
+                    return 1 + deep(n - 1);
+                }
+                fn main() {
+                    println(deep(5000));
+                    println(10 / 0);
+                }
+            "#);
+            assert_same_as_vm("fn add(a: Int, b: Int) -> Int {\n    return a + b;\n}\nfn main() {\n    println(add(1));\n}\n");
+            assert_same_as_vm("fn main() {\n    assert_eq([1, 2], [1, 3]);\n}\n");
+            assert_same_as_vm("fn main() {\n    assert(1 > 2, \"numbers\");\n}\n");
+            assert_same_as_vm("fn main() {\n    if false {\n        let x = 1;\n    }\n    println(x);\n}\n");
+            assert_same_as_vm("fn main() {\n    println(substring(\"abc\", 2, 5));\n}\n");
+        }
+
+        #[test]
+        fn test_repository_corpus_wasm() {
+            // Golden programs the target accepts must print the same as in the VM
+            let corpus = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests");
+            let mut programs: Vec<_> = std::fs::read_dir(&corpus)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == "nx"))
+                .collect();
+            programs.sort();
+            let runtime = WASMRuntime::new().unwrap();
+            let mut failures = Vec::new();
+            for path in programs {
+                let source = std::fs::read_to_string(&path).unwrap();
+                let Ok(tokens) = Lexer::new(&source, "test.nx".to_string()).tokenize() else { continue };
+                let Ok(ast) = Parser::new(tokens).parse() else { continue };
+                let Ok(wasm) = WASMCompiler::new().with_optimization(2).compile_to_wasm(&ast) else { continue };
+                validate(&wasm);
+                let expected = run_program_at(&source, "test.nx", 2);
+                let actual = runtime.run(&wasm).unwrap();
+                if (actual.stdout.as_str(), actual.stderr.as_str(), actual.exit_code)
+                    != (expected.stdout.as_str(), expected.stderr.as_str(), expected.exit_code)
+                {
+                    failures.push(format!("{}:\n{}{}", path.display(), actual.stdout, actual.stderr));
+                }
+            }
+            assert!(failures.is_empty(), "wasm output mismatches:\n{}", failures.join("\n"));
+        }
+
+        #[test]
+        fn test_stack_overflow() {
+            let source = "fn forever(n: Int) -> Int {\n    return forever(n + 1) + 1;\n}\nfn main() {\n    forever(0);\n}\n";
+            let wasm = compile(source, 0).unwrap();
+            let output = WASMRuntime::new().unwrap().run(&wasm).unwrap();
+            assert_eq!(output.exit_code, 1);
+            assert_eq!(output.stderr, "error: StackOverflow: maximum call depth of 10000 exceeded\n");
+        }
+    }
+}
@@ -0,0 +1,905 @@
+// Runtime support for WebAssembly builds
+//
+// Helper functions written directly in WebAssembly and added to a module
+// only when the program needs them. Linear memory is laid out as
+//
+//   0..16      iovec and byte count for `fd_write`
+//   16..400    scratch space for formatting numbers
+//   400..      string constants, then the table of powers of ten
+//   heap       a bump allocator that grows memory and never frees
+//
+// A string is a pointer to its byte length followed by its UTF-8 bytes. An
+// array is a pointer to a 12 byte header (length, capacity, pointer to the
+// elements); every element takes an 8 byte slot, so arrays are shared by
+// reference like the VM's. Errors are written to stderr as `error: ...`
+// and end the program with exit code 1.
+
+use std::collections::HashMap;
+
+use super::encoder::{ExportKind, FuncType, Instr, Instr::*, Module, ValType, PAGE_SIZE};
+use super::types::Type;
+
+const IOVEC: i32 = 0;
+const WRITTEN: u32 = 8;
+const DIGITS: i32 = 16;
+const SCRATCH: i32 = 40;
+const SCRATCH_END: i32 = 400;
+const DATA_START: u32 = 400;
+const POWERS_OF_TEN: usize = 309;
+
+/// A function body under construction
+pub struct Body {
+    params: u32,
+    locals: Vec<ValType>,
+    pub code: Vec<Instr>,
+}
+
+impl Body {
+    pub fn new(params: usize) -> Self {
+        Self { params: params as u32, locals: Vec::new(), code: Vec::new() }
+    }
+
+    pub fn local(&mut self, ty: ValType) -> u32 {
+        self.locals.push(ty);
+        self.params + self.locals.len() as u32 - 1
+    }
+
+    pub fn emit(&mut self, code: impl IntoIterator<Item = Instr>) {
+        self.code.extend(code);
+    }
+
+    pub fn finish(mut self) -> (Vec<ValType>, Vec<Instr>) {
+        // Every path already returns; this keeps the validator from expecting a result
+        self.code.push(Unreachable);
+        (self.locals, self.code)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Helper {
+    Alloc,
+    StrAlloc,
+    Write,
+    Fail,
+    Concat,
+    StrEq,
+    StrCmp,
+    MemEq,
+    Find,
+    Slice,
+    Substring,
+    StartsWith,
+    EndsWith,
+    ChangeCase(bool),
+    Trim,
+    Split,
+    IntToStr,
+    FloatToStr,
+    MulPow10,
+    Round,
+    FloatMod,
+    PowInt,
+    ArrayNew,
+    ArrayReserve,
+    ArrayReverse,
+    ArraySlice,
+    IndexAddr,
+    Sort(Type),
+    Join(Type),
+    ArrayToStr(Type),
+    DeepEq(Type),
+}
+
+impl Helper {
+    fn signature(&self) -> FuncType {
+        use ValType::*;
+        match self {
+            Helper::Alloc | Helper::StrAlloc | Helper::ChangeCase(_) | Helper::Trim | Helper::ArrayNew => {
+                FuncType::new(&[I32], &[I32])
+            }
+            Helper::ArrayReserve | Helper::ArrayReverse | Helper::Sort(_) | Helper::ArrayToStr(_) => FuncType::new(&[I32], &[I32]),
+            Helper::Write => FuncType::new(&[I32, I32], &[]),
+            Helper::Fail => FuncType::new(&[I32], &[]),
+            Helper::Concat | Helper::StrEq | Helper::StrCmp | Helper::StartsWith | Helper::EndsWith | Helper::Split => {
+                FuncType::new(&[I32, I32], &[I32])
+            }
+            Helper::Join(_) | Helper::DeepEq(_) => FuncType::new(&[I32, I32], &[I32]),
+            Helper::MemEq | Helper::Find | Helper::Slice => FuncType::new(&[I32, I32, I32], &[I32]),
+            Helper::Substring | Helper::ArraySlice => FuncType::new(&[I32, I64, I64], &[I32]),
+            Helper::IntToStr => FuncType::new(&[I64], &[I32]),
+            Helper::FloatToStr => FuncType::new(&[F64], &[I32]),
+            Helper::MulPow10 => FuncType::new(&[F64, I32], &[F64]),
+            Helper::Round => FuncType::new(&[F64], &[F64]),
+            Helper::FloatMod => FuncType::new(&[F64, F64], &[F64]),
+            Helper::PowInt => FuncType::new(&[I64, I64], &[I64]),
+            Helper::IndexAddr => FuncType::new(&[I32, I64], &[I32]),
+        }
+    }
+}
+
+/// The module being built, with the helpers and constants added so far
+pub struct Runtime {
+    pub module: Module,
+    fd_write: u32,
+    proc_exit: u32,
+    heap: u32,
+    pub depth: u32,
+    helpers: HashMap<Helper, u32>,
+    pending: Vec<(Helper, u32)>,
+    strings: HashMap<String, i32>,
+    data: Vec<u8>,
+    powers_of_ten: Option<i32>,
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        use ValType::I32;
+        let mut module = Module::new();
+        let fd_write = module.import_function("wasi_snapshot_preview1", "fd_write", FuncType::new(&[I32, I32, I32, I32], &[I32]));
+        let proc_exit = module.import_function("wasi_snapshot_preview1", "proc_exit", FuncType::new(&[I32], &[]));
+        let heap = module.add_global(0);
+        let depth = module.add_global(0);
+        Self {
+            module,
+            fd_write,
+            proc_exit,
+            heap,
+            depth,
+            helpers: HashMap::new(),
+            pending: Vec::new(),
+            strings: HashMap::new(),
+            data: Vec::new(),
+            powers_of_ten: None,
+        }
+    }
+
+    /// The address of a string constant
+    pub fn string(&mut self, text: &str) -> i32 {
+        if let Some(&address) = self.strings.get(text) {
+            return address;
+        }
+        self.align_data(4);
+        let address = (DATA_START as usize + self.data.len()) as i32;
+        self.data.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(text.as_bytes());
+        self.strings.insert(text.to_string(), address);
+        address
+    }
+
+    fn align_data(&mut self, alignment: usize) {
+        while !self.data.len().is_multiple_of(alignment) {
+            self.data.push(0);
+        }
+    }
+
+    /// The index of `helper`, adding it to the module on first use
+    pub fn helper(&mut self, helper: Helper) -> u32 {
+        if let Some(&index) = self.helpers.get(&helper) {
+            return index;
+        }
+        let index = self.module.declare_function(helper.signature());
+        self.helpers.insert(helper.clone(), index);
+        self.pending.push((helper, index));
+        index
+    }
+
+    pub fn call(&mut self, helper: Helper) -> Instr {
+        Call(self.helper(helper))
+    }
+
+    /// Raise `message`; the code after it is unreachable
+    pub fn fail(&mut self, body: &mut Body, message: &str) {
+        let message = self.string(message);
+        let fail = self.call(Helper::Fail);
+        body.emit([I32Const(message), fail, Unreachable]);
+    }
+
+    /// Write the string on top of the stack to `fd`, with a newline if `newline`
+    pub fn write(&mut self, body: &mut Body, fd: i32, newline: bool) {
+        let text = body.local(ValType::I32);
+        let write = self.call(Helper::Write);
+        body.emit([LocalSet(text), I32Const(fd), LocalGet(text), write]);
+        if newline {
+            let newline = self.string("\n");
+            body.emit([I32Const(fd), I32Const(newline), write]);
+        }
+    }
+
+    /// Replace the value of type `ty` on top of the stack with its string form
+    pub fn stringify(&mut self, body: &mut Body, ty: &Type) {
+        match ty {
+            Type::Int => body.emit([self.call(Helper::IntToStr)]),
+            Type::Float => body.emit([self.call(Helper::FloatToStr)]),
+            Type::Bool => {
+                let (yes, no) = (self.string("true"), self.string("false"));
+                let flag = body.local(ValType::I32);
+                body.emit([LocalSet(flag), I32Const(yes), I32Const(no), LocalGet(flag), Select]);
+            }
+            Type::Str => {}
+            Type::Null => {
+                let null = self.string("null");
+                body.emit([Drop, I32Const(null)]);
+            }
+            Type::Array(element) => body.emit([self.call(Helper::ArrayToStr((**element).clone()))]),
+        }
+    }
+
+    /// Replace the value of type `ty` on top of the stack with its truth value
+    pub fn truthy(&mut self, body: &mut Body, ty: &Type) {
+        match ty {
+            Type::Int => body.emit([I64Const(0), I64Ne]),
+            Type::Float => body.emit([F64Const(0.0), F64Ne]),
+            Type::Bool => {}
+            Type::Str => body.emit([I32Load(0), I32Const(0), I32Ne]),
+            Type::Null => body.emit([Drop, I32Const(0)]),
+            Type::Array(_) => body.emit([Drop, I32Const(1)]),
+        }
+    }
+
+    /// Push whether the values in locals `left` and `right` are equal: `==`,
+    /// or with `structural` the deep comparison assertions use
+    pub fn equal(&mut self, body: &mut Body, left: (u32, &Type), right: (u32, &Type), structural: bool) {
+        let ((a, left), (b, right)) = (left, right);
+        match (left, right) {
+            (Type::Int, Type::Int) => body.emit([LocalGet(a), LocalGet(b), I64Eq]),
+            (Type::Float, Type::Float) => body.emit([LocalGet(a), LocalGet(b), F64Eq]),
+            (Type::Int, Type::Float) => body.emit([LocalGet(a), F64ConvertI64S, LocalGet(b), F64Eq]),
+            (Type::Float, Type::Int) => body.emit([LocalGet(a), LocalGet(b), F64ConvertI64S, F64Eq]),
+            (Type::Str, Type::Str) => body.emit([LocalGet(a), LocalGet(b), self.call(Helper::StrEq)]),
+            (Type::Bool, Type::Bool) => body.emit([LocalGet(a), LocalGet(b), I32Eq]),
+            (Type::Null, Type::Null) => body.emit([I32Const(1)]),
+            (Type::Array(_), Type::Array(_)) if structural && left == right => {
+                body.emit([LocalGet(a), LocalGet(b), self.call(Helper::DeepEq(left.clone()))]);
+            }
+            // Containers compare by identity
+            (Type::Array(_), Type::Array(_)) if left == right => body.emit([LocalGet(a), LocalGet(b), I32Eq]),
+            _ => body.emit([I32Const(0)]),
+        }
+    }
+
+    /// Load an element of type `ty` from the slot address on top of the stack
+    pub fn load(body: &mut Body, ty: &Type, offset: u32) {
+        body.emit([match ty {
+            Type::Int => I64Load(offset),
+            Type::Float => F64Load(offset),
+            _ => I32Load(offset),
+        }]);
+    }
+
+    /// Store the value on top of the stack into the slot address below it
+    pub fn store(body: &mut Body, ty: &Type, offset: u32) {
+        body.emit([match ty {
+            Type::Int => I64Store(offset),
+            Type::Float => F64Store(offset),
+            _ => I32Store(offset),
+        }]);
+    }
+
+    /// Export `start` as the WASI entry point and return the finished module
+    pub fn finish(mut self, start: u32) -> Module {
+        while let Some((helper, index)) = self.pending.pop() {
+            let (locals, code) = self.build(&helper).finish();
+            self.module.define_function(index, locals, code);
+        }
+        self.align_data(8);
+        let heap_start = DATA_START + self.data.len() as u32;
+        self.module.set_global(self.heap, heap_start as i32);
+        self.module.set_memory(heap_start / PAGE_SIZE + 1);
+        if !self.data.is_empty() {
+            self.module.add_data(DATA_START, std::mem::take(&mut self.data));
+        }
+        self.module.export("memory", ExportKind::Memory, 0);
+        self.module.export("_start", ExportKind::Func, start);
+        self.module
+    }
+
+    fn powers_of_ten(&mut self) -> i32 {
+        if let Some(address) = self.powers_of_ten {
+            return address;
+        }
+        self.align_data(8);
+        let address = (DATA_START as usize + self.data.len()) as i32;
+        for exponent in 0..POWERS_OF_TEN {
+            let power: f64 = format!("1e{}", exponent).parse().expect("a valid float");
+            self.data.extend_from_slice(&power.to_le_bytes());
+        }
+        self.powers_of_ten = Some(address);
+        address
+    }
+
+    fn build(&mut self, helper: &Helper) -> Body {
+        match helper {
+            Helper::Alloc => self.alloc(),
+            Helper::StrAlloc => {
+                let mut body = Body::new(1);
+                let string = body.local(ValType::I32);
+                body.emit([LocalGet(0), I32Const(4), I32Add, self.call(Helper::Alloc), LocalTee(string)]);
+                body.emit([LocalGet(0), I32Store(0), LocalGet(string), Return]);
+                body
+            }
+            Helper::Write => self.write_fd(),
+            Helper::Fail => {
+                let mut body = Body::new(1);
+                let (prefix, newline) = (self.string("error: "), self.string("\n"));
+                let write = self.call(Helper::Write);
+                body.emit([I32Const(2), I32Const(prefix), write, I32Const(2), LocalGet(0), write]);
+                body.emit([I32Const(2), I32Const(newline), write, I32Const(1), Call(self.proc_exit), Unreachable]);
+                body
+            }
+            Helper::Concat => self.concat(),
+            Helper::StrEq => {
+                let mut body = Body::new(2);
+                body.emit([LocalGet(0), LocalGet(1), I32Eq, If, I32Const(1), Return, End]);
+                body.emit([LocalGet(0), I32Load(0), LocalGet(1), I32Load(0), I32Ne, If, I32Const(0), Return, End]);
+                body.emit([LocalGet(0), I32Const(4), I32Add, LocalGet(1), I32Const(4), I32Add, LocalGet(0), I32Load(0)]);
+                body.emit([self.call(Helper::MemEq), Return]);
+                body
+            }
+            Helper::StrCmp => self.str_cmp(),
+            Helper::MemEq => mem_eq(),
+            Helper::Find => self.find(),
+            Helper::Slice => {
+                // The bytes from..to of a string, unchecked
+                let mut body = Body::new(3);
+                let result = body.local(ValType::I32);
+                body.emit([LocalGet(2), LocalGet(1), I32Sub, self.call(Helper::StrAlloc), LocalSet(result)]);
+                body.emit([LocalGet(result), I32Const(4), I32Add, LocalGet(0), I32Const(4), I32Add, LocalGet(1), I32Add]);
+                body.emit([LocalGet(2), LocalGet(1), I32Sub, MemoryCopy, LocalGet(result), Return]);
+                body
+            }
+            Helper::Substring => self.substring(),
+            Helper::StartsWith | Helper::EndsWith => {
+                let mut body = Body::new(2);
+                let (len, prefix_len) = (body.local(ValType::I32), body.local(ValType::I32));
+                body.emit([LocalGet(0), I32Load(0), LocalSet(len), LocalGet(1), I32Load(0), LocalSet(prefix_len)]);
+                body.emit([LocalGet(prefix_len), LocalGet(len), I32GtU, If, I32Const(0), Return, End]);
+                body.emit([LocalGet(0), I32Const(4), I32Add]);
+                if *helper == Helper::EndsWith {
+                    body.emit([LocalGet(len), I32Add, LocalGet(prefix_len), I32Sub]);
+                }
+                body.emit([LocalGet(1), I32Const(4), I32Add, LocalGet(prefix_len), self.call(Helper::MemEq), Return]);
+                body
+            }
+            Helper::ChangeCase(upper) => self.change_case(*upper),
+            Helper::Trim => self.trim(),
+            Helper::Split => self.split(),
+            Helper::IntToStr => self.int_to_str(),
+            Helper::FloatToStr => self.float_to_str(),
+            Helper::MulPow10 => self.mul_pow10(),
+            Helper::Round => {
+                // Half away from zero, like Rust's `round`
+                let mut body = Body::new(1);
+                let truncated = body.local(ValType::F64);
+                body.emit([LocalGet(0), F64Trunc, LocalSet(truncated)]);
+                body.emit([LocalGet(0), LocalGet(truncated), F64Sub, F64Abs, F64Const(0.5), F64Ge, If]);
+                body.emit([LocalGet(truncated), F64Const(1.0), LocalGet(0), F64Copysign, F64Add, Return, End]);
+                body.emit([LocalGet(truncated), Return]);
+                body
+            }
+            Helper::FloatMod => float_mod(),
+            Helper::PowInt => self.pow_int(),
+            Helper::ArrayNew => {
+                let mut body = Body::new(1);
+                let array = body.local(ValType::I32);
+                let alloc = self.call(Helper::Alloc);
+                body.emit([I32Const(12), alloc, LocalTee(array), I32Const(0), I32Store(0)]);
+                body.emit([LocalGet(array), LocalGet(0), I32Store(4)]);
+                body.emit([LocalGet(array), LocalGet(0), I32Const(8), I32Mul, alloc, I32Store(8), LocalGet(array), Return]);
+                body
+            }
+            Helper::ArrayReserve => self.array_reserve(),
+            Helper::ArrayReverse => array_reverse(),
+            Helper::ArraySlice => self.array_slice(),
+            Helper::IndexAddr => {
+                let mut body = Body::new(2);
+                body.emit([LocalGet(1), LocalGet(0), I32Load(0), I64ExtendI32U, I64GeU, If]);
+                self.fail(&mut body, "Array index out of bounds");
+                body.emit([End, LocalGet(0), I32Load(8), LocalGet(1), I32WrapI64, I32Const(8), I32Mul, I32Add, Return]);
+                body
+            }
+            Helper::Sort(element) => self.sort(element),
+            Helper::Join(element) => self.join(element),
+            Helper::ArrayToStr(element) => {
+                let mut body = Body::new(1);
+                let (open, close, separator) = (self.string("["), self.string("]"), self.string(", "));
+                let concat = self.call(Helper::Concat);
+                body.emit([I32Const(open), LocalGet(0), I32Const(separator), self.call(Helper::Join(element.clone()))]);
+                body.emit([concat, I32Const(close), concat, Return]);
+                body
+            }
+            Helper::DeepEq(array) => self.deep_eq(array),
+        }
+    }
+
+    fn alloc(&mut self) -> Body {
+        let mut body = Body::new(1);
+        let (start, end) = (body.local(ValType::I32), body.local(ValType::I32));
+        body.emit([GlobalGet(self.heap), I32Const(7), I32Add, I32Const(-8), I32And, LocalTee(start)]);
+        body.emit([LocalGet(0), I32Add, LocalTee(end), LocalGet(start), I32LtU, If]);
+        self.fail(&mut body, "out of memory");
+        body.emit([End]);
+        body.emit([LocalGet(end), MemorySize, I32Const(16), I32Shl, I32GtU, If]);
+        // Grow by the missing bytes, rounded up to whole pages
+        body.emit([LocalGet(end), MemorySize, I32Const(16), I32Shl, I32Sub, I32Const(PAGE_SIZE as i32 - 1), I32Add]);
+        body.emit([I32Const(16), I32ShrU, MemoryGrow, I32Const(-1), I32Eq, If]);
+        self.fail(&mut body, "out of memory");
+        body.emit([End, End]);
+        body.emit([LocalGet(end), GlobalSet(self.heap), LocalGet(start), Return]);
+        body
+    }
+
+    fn write_fd(&mut self) -> Body {
+        // fd_write may write less than asked for, so loop until everything is out
+        let mut body = Body::new(2);
+        let (data, len) = (body.local(ValType::I32), body.local(ValType::I32));
+        body.emit([LocalGet(1), I32Const(4), I32Add, LocalSet(data), LocalGet(1), I32Load(0), LocalSet(len)]);
+        body.emit([Block, Loop, LocalGet(len), I32Eqz, BrIf(1)]);
+        body.emit([I32Const(IOVEC), LocalGet(data), I32Store(0), I32Const(IOVEC), LocalGet(len), I32Store(4)]);
+        body.emit([LocalGet(0), I32Const(IOVEC), I32Const(1), I32Const(WRITTEN as i32), Call(self.fd_write), BrIf(1)]);
+        body.emit([LocalGet(data), I32Const(0), I32Load(WRITTEN), I32Add, LocalSet(data)]);
+        body.emit([LocalGet(len), I32Const(0), I32Load(WRITTEN), I32Sub, LocalSet(len), Br(0), End, End, Return]);
+        body
+    }
+
+    fn concat(&mut self) -> Body {
+        let mut body = Body::new(2);
+        let (left, right, result) = (body.local(ValType::I32), body.local(ValType::I32), body.local(ValType::I32));
+        body.emit([LocalGet(0), I32Load(0), LocalSet(left), LocalGet(1), I32Load(0), LocalSet(right)]);
+        body.emit([LocalGet(left), LocalGet(right), I32Add, self.call(Helper::StrAlloc), LocalSet(result)]);
+        body.emit([LocalGet(result), I32Const(4), I32Add, LocalGet(0), I32Const(4), I32Add, LocalGet(left), MemoryCopy]);
+        body.emit([LocalGet(result), I32Const(4), I32Add, LocalGet(left), I32Add]);
+        body.emit([LocalGet(1), I32Const(4), I32Add, LocalGet(right), MemoryCopy, LocalGet(result), Return]);
+        body
+    }
+
+    fn str_cmp(&mut self) -> Body {
+        // -1, 0 or 1 comparing bytes, then lengths
+        let mut body = Body::new(2);
+        let (left, right, len, index, a, b) = (
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+        );
+        body.emit([LocalGet(0), I32Load(0), LocalSet(left), LocalGet(1), I32Load(0), LocalSet(right)]);
+        body.emit([LocalGet(left), LocalGet(right), LocalGet(left), LocalGet(right), I32LtU, Select, LocalSet(len)]);
+        body.emit([Block, Loop, LocalGet(index), LocalGet(len), I32GeU, BrIf(1)]);
+        body.emit([LocalGet(0), LocalGet(index), I32Add, I32Load8U(4), LocalSet(a)]);
+        body.emit([LocalGet(1), LocalGet(index), I32Add, I32Load8U(4), LocalSet(b)]);
+        body.emit([LocalGet(a), LocalGet(b), I32Ne, If, I32Const(-1), I32Const(1), LocalGet(a), LocalGet(b), I32LtU, Select, Return, End]);
+        body.emit([LocalGet(index), I32Const(1), I32Add, LocalSet(index), Br(0), End, End]);
+        body.emit([LocalGet(left), LocalGet(right), I32GtU, LocalGet(left), LocalGet(right), I32LtU, I32Sub, Return]);
+        body
+    }
+
+    fn find(&mut self) -> Body {
+        // The first index from `from` where `needle` occurs in `haystack`, or -1
+        let mut body = Body::new(3);
+        let (len, needle_len) = (body.local(ValType::I32), body.local(ValType::I32));
+        body.emit([LocalGet(0), I32Load(0), LocalSet(len), LocalGet(1), I32Load(0), LocalTee(needle_len)]);
+        body.emit([I32Eqz, If, LocalGet(2), Return, End]);
+        body.emit([Block, Loop, LocalGet(2), LocalGet(needle_len), I32Add, LocalGet(len), I32GtU, BrIf(1)]);
+        body.emit([LocalGet(0), I32Const(4), I32Add, LocalGet(2), I32Add, LocalGet(1), I32Const(4), I32Add, LocalGet(needle_len)]);
+        body.emit([self.call(Helper::MemEq), If, LocalGet(2), Return, End]);
+        body.emit([LocalGet(2), I32Const(1), I32Add, LocalSet(2), Br(0), End, End, I32Const(-1), Return]);
+        body
+    }
+
+    fn substring(&mut self) -> Body {
+        // Byte offsets, checked the way the VM checks them
+        let mut body = Body::new(3);
+        let (len, end) = (body.local(ValType::I64), body.local(ValType::I64));
+        body.emit([LocalGet(0), I32Load(0), I64ExtendI32U, LocalSet(len), LocalGet(1), LocalGet(2), I64Add, LocalSet(end)]);
+        body.emit([LocalGet(1), LocalGet(len), I64GeU, LocalGet(end), LocalGet(len), I64GtU, I32Or]);
+        body.emit([LocalGet(end), LocalGet(1), I64LtU, I32Or, If]);
+        self.fail(&mut body, "substring indices out of bounds");
+        body.emit([End, LocalGet(0), LocalGet(1), I32WrapI64, LocalGet(end), I32WrapI64, self.call(Helper::Slice), Return]);
+        body
+    }
+
+    fn change_case(&mut self, upper: bool) -> Body {
+        let mut body = Body::new(1);
+        let (result, index, byte) = (body.local(ValType::I32), body.local(ValType::I32), body.local(ValType::I32));
+        let (from, to) = if upper { (b'a', b'A') } else { (b'A', b'a') };
+        body.emit([LocalGet(0), I32Const(0), LocalGet(0), I32Load(0), self.call(Helper::Slice), LocalSet(result)]);
+        body.emit([Block, Loop, LocalGet(index), LocalGet(result), I32Load(0), I32GeU, BrIf(1)]);
+        body.emit([LocalGet(result), LocalGet(index), I32Add, I32Load8U(4), LocalTee(byte)]);
+        body.emit([I32Const(from as i32), I32Sub, I32Const(26), I32LtU, If]);
+        body.emit([LocalGet(result), LocalGet(index), I32Add, LocalGet(byte), I32Const(to as i32 - from as i32), I32Add, I32Store8(4), End]);
+        body.emit([LocalGet(index), I32Const(1), I32Add, LocalSet(index), Br(0), End, End, LocalGet(result), Return]);
+        body
+    }
+
+    fn trim(&mut self) -> Body {
+        let mut body = Body::new(1);
+        let (start, end) = (body.local(ValType::I32), body.local(ValType::I32));
+        // ASCII whitespace: space and \t \n \v \f \r
+        let is_space = |body: &mut Body, index: u32, offset: u32| {
+            body.emit([LocalGet(0), LocalGet(index), I32Add, I32Load8U(offset), LocalTee(index + 2)]);
+            body.emit([I32Const(32), I32Eq, LocalGet(index + 2), I32Const(9), I32Sub, I32Const(5), I32LtU, I32Or]);
+        };
+        let byte = body.local(ValType::I32);
+        let _ = body.local(ValType::I32);
+        debug_assert_eq!(byte, start + 2);
+        body.emit([LocalGet(0), I32Load(0), LocalSet(end)]);
+        body.emit([Block, Loop, LocalGet(start), LocalGet(end), I32GeU, BrIf(1)]);
+        is_space(&mut body, start, 4);
+        body.emit([I32Eqz, BrIf(1), LocalGet(start), I32Const(1), I32Add, LocalSet(start), Br(0), End, End]);
+        body.emit([Block, Loop, LocalGet(end), LocalGet(start), I32LeU, BrIf(1)]);
+        is_space(&mut body, end, 3);
+        body.emit([I32Eqz, BrIf(1), LocalGet(end), I32Const(1), I32Sub, LocalSet(end), Br(0), End, End]);
+        body.emit([LocalGet(0), LocalGet(start), LocalGet(end), self.call(Helper::Slice), Return]);
+        body
+    }
+
+    fn split(&mut self) -> Body {
+        let mut body = Body::new(2);
+        let (parts, len, start, found, next) = (
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+        );
+        let (slice, reserve, find) = (self.call(Helper::Slice), self.call(Helper::ArrayReserve), self.call(Helper::Find));
+        let empty = self.string("");
+        let push = |body: &mut Body, value: &[Instr]| {
+            body.emit([LocalGet(parts), reserve]);
+            body.emit(value.iter().copied());
+            body.emit([I32Store(0)]);
+        };
+        body.emit([I32Const(4), self.call(Helper::ArrayNew), LocalSet(parts), LocalGet(0), I32Load(0), LocalSet(len)]);
+        body.emit([LocalGet(1), I32Load(0), I32Eqz, If]);
+        // An empty separator splits around every character, as Rust does
+        push(&mut body, &[I32Const(empty)]);
+        body.emit([Block, Loop, LocalGet(start), LocalGet(len), I32GeU, BrIf(1)]);
+        body.emit([LocalGet(start), I32Const(1), I32Add, LocalSet(next)]);
+        body.emit([Block, Loop, LocalGet(next), LocalGet(len), I32GeU, BrIf(1)]);
+        body.emit([LocalGet(0), LocalGet(next), I32Add, I32Load8U(4), I32Const(0xc0), I32And, I32Const(0x80), I32Ne, BrIf(1)]);
+        body.emit([LocalGet(next), I32Const(1), I32Add, LocalSet(next), Br(0), End, End]);
+        push(&mut body, &[LocalGet(0), LocalGet(start), LocalGet(next), slice]);
+        body.emit([LocalGet(next), LocalSet(start), Br(0), End, End]);
+        push(&mut body, &[I32Const(empty)]);
+        body.emit([Else]);
+        body.emit([Block, Loop, LocalGet(0), LocalGet(1), LocalGet(start), find, LocalTee(found), I32Const(0), I32LtS, BrIf(1)]);
+        push(&mut body, &[LocalGet(0), LocalGet(start), LocalGet(found), slice]);
+        body.emit([LocalGet(found), LocalGet(1), I32Load(0), I32Add, LocalSet(start), Br(0), End, End]);
+        push(&mut body, &[LocalGet(0), LocalGet(start), LocalGet(len), slice]);
+        body.emit([End, LocalGet(parts), Return]);
+        body
+    }
+
+    fn int_to_str(&mut self) -> Body {
+        // Digits are written backwards into the scratch space, then copied out
+        let mut body = Body::new(1);
+        let (magnitude, position, len) = (body.local(ValType::I64), body.local(ValType::I32), body.local(ValType::I32));
+        let result = body.local(ValType::I32);
+        body.emit([I64Const(0), LocalGet(0), I64Sub, LocalGet(0), LocalGet(0), I64Const(0), I64LtS, Select, LocalSet(magnitude)]);
+        body.emit([I32Const(SCRATCH_END), LocalSet(position)]);
+        body.emit([Loop, LocalGet(position), I32Const(1), I32Sub, LocalTee(position)]);
+        body.emit([LocalGet(magnitude), I64Const(10), I64RemU, I32WrapI64, I32Const(b'0' as i32), I32Add, I32Store8(0)]);
+        body.emit([LocalGet(magnitude), I64Const(10), I64DivU, LocalTee(magnitude), I64Const(0), I64Ne, BrIf(0), End]);
+        body.emit([LocalGet(0), I64Const(0), I64LtS, If, LocalGet(position), I32Const(1), I32Sub, LocalTee(position)]);
+        body.emit([I32Const(b'-' as i32), I32Store8(0), End]);
+        body.emit([I32Const(SCRATCH_END), LocalGet(position), I32Sub, LocalTee(len), self.call(Helper::StrAlloc), LocalTee(result)]);
+        body.emit([I32Const(4), I32Add, LocalGet(position), LocalGet(len), MemoryCopy, LocalGet(result), Return]);
+        body
+    }
+
+    fn mul_pow10(&mut self) -> Body {
+        // x * 10^n, in two steps when 10^n alone would overflow
+        let mut body = Body::new(2);
+        let table = self.powers_of_ten();
+        body.emit([LocalGet(1), I32Const(300), I32GtS, If]);
+        body.emit([LocalGet(0), F64Const(1e300), F64Mul, LocalSet(0), LocalGet(1), I32Const(300), I32Sub, LocalSet(1), End]);
+        body.emit([LocalGet(1), I32Const(-300), I32LtS, If]);
+        body.emit([LocalGet(0), F64Const(1e300), F64Div, LocalSet(0), LocalGet(1), I32Const(300), I32Add, LocalSet(1), End]);
+        body.emit([LocalGet(1), I32Const(0), I32GeS, If]);
+        body.emit([LocalGet(0), LocalGet(1), I32Const(8), I32Mul, F64Load(table as u32), F64Mul, Return, End]);
+        body.emit([LocalGet(0), I32Const(0), LocalGet(1), I32Sub, I32Const(8), I32Mul, F64Load(table as u32), F64Div, Return]);
+        body
+    }
+
+    fn float_to_str(&mut self) -> Body {
+        // Like Rust's `Display` for f64: the fewest significant digits that
+        // read back as the same value, never in exponent notation. Digits
+        // come from scaling by powers of ten, which is exact for the values
+        // programs usually print.
+        let mut body = Body::new(1);
+        let (negative, exponent, precision, scale) =
+            (body.local(ValType::I32), body.local(ValType::I32), body.local(ValType::I32), body.local(ValType::I32));
+        let (scaled, digits_value) = (body.local(ValType::F64), body.local(ValType::I64));
+        let (count, out, index, integer_digits) =
+            (body.local(ValType::I32), body.local(ValType::I32), body.local(ValType::I32), body.local(ValType::I32));
+        let (candidate_exponent, result) = (body.local(ValType::I32), body.local(ValType::I32));
+        let mul_pow10 = self.call(Helper::MulPow10);
+        let table = self.powers_of_ten();
+        let (nan, inf, neg_inf, zero, neg_zero) =
+            (self.string("NaN"), self.string("inf"), self.string("-inf"), self.string("0"), self.string("-0"));
+
+        body.emit([LocalGet(0), LocalGet(0), F64Ne, If, I32Const(nan), Return, End]);
+        body.emit([LocalGet(0), F64Const(f64::INFINITY), F64Eq, If, I32Const(inf), Return, End]);
+        body.emit([LocalGet(0), F64Const(f64::NEG_INFINITY), F64Eq, If, I32Const(neg_inf), Return, End]);
+        body.emit([LocalGet(0), I64ReinterpretF64, I64Const(0), I64LtS, LocalSet(negative)]);
+        body.emit([LocalGet(0), F64Abs, LocalSet(0)]);
+        body.emit([LocalGet(0), F64Const(0.0), F64Eq, If, I32Const(neg_zero), I32Const(zero), LocalGet(negative), Select, Return, End]);
+
+        // Estimate floor(log10(x)) from the binary exponent, then correct it
+        body.emit([LocalGet(0), I64ReinterpretF64, I64Const(52), I64ShrU, I32WrapI64, I32Const(0x7ff), I32And]);
+        body.emit([I32Const(1023), I32Sub, I32Const(78913), I32Mul, I32Const(18), I32ShrS, LocalSet(exponent)]);
+        body.emit([Block, Loop, LocalGet(0), I32Const(0), LocalGet(exponent), I32Sub, mul_pow10, F64Const(10.0), F64Lt, BrIf(1)]);
+        body.emit([LocalGet(exponent), I32Const(1), I32Add, LocalSet(exponent), Br(0), End, End]);
+        body.emit([Block, Loop, LocalGet(0), I32Const(0), LocalGet(exponent), I32Sub, mul_pow10, F64Const(1.0), F64Ge, BrIf(1)]);
+        body.emit([LocalGet(exponent), I32Const(1), I32Sub, LocalSet(exponent), Br(0), End, End]);
+
+        // Round to 1, 2, ... 17 significant digits until the digits read back exactly
+        body.emit([I32Const(0), LocalSet(precision), Block, Loop]);
+        body.emit([LocalGet(precision), I32Const(1), I32Add, LocalSet(precision), LocalGet(exponent), LocalSet(candidate_exponent)]);
+        body.emit([LocalGet(precision), I32Const(1), I32Sub, LocalGet(exponent), I32Sub, LocalSet(scale)]);
+        body.emit([LocalGet(0), LocalGet(scale), mul_pow10, F64Nearest, LocalTee(scaled)]);
+        body.emit([I32Const(table), LocalGet(precision), I32Const(8), I32Mul, I32Add, F64Load(0), F64Ge, If]);
+        // Rounding carried into a new leading digit
+        body.emit([LocalGet(scaled), F64Const(10.0), F64Div, LocalSet(scaled), LocalGet(scale), I32Const(1), I32Sub, LocalSet(scale)]);
+        body.emit([LocalGet(exponent), I32Const(1), I32Add, LocalSet(candidate_exponent), End]);
+        body.emit([LocalGet(scaled), I32Const(0), LocalGet(scale), I32Sub, mul_pow10, LocalGet(0), F64Eq, BrIf(1)]);
+        body.emit([LocalGet(precision), I32Const(17), I32LtU, BrIf(0), End, End]);
+
+        // The digits, most significant first, without trailing zeros
+        body.emit([LocalGet(scaled), I64TruncF64U, LocalSet(digits_value), LocalGet(precision), LocalSet(index)]);
+        body.emit([Block, Loop, LocalGet(index), I32Eqz, BrIf(1), LocalGet(index), I32Const(1), I32Sub, LocalTee(index)]);
+        body.emit([LocalGet(digits_value), I64Const(10), I64RemU, I32WrapI64, I32Const(b'0' as i32), I32Add, I32Store8(DIGITS as u32)]);
+        body.emit([LocalGet(digits_value), I64Const(10), I64DivU, LocalSet(digits_value), Br(0), End, End]);
+        body.emit([LocalGet(precision), LocalSet(count)]);
+        body.emit([Block, Loop, LocalGet(count), I32Const(1), I32LeU, BrIf(1)]);
+        body.emit([LocalGet(count), I32Const(1), I32Sub, I32Load8U(DIGITS as u32), I32Const(b'0' as i32), I32Ne, BrIf(1)]);
+        body.emit([LocalGet(count), I32Const(1), I32Sub, LocalSet(count), Br(0), End, End]);
+
+        let put_byte = |body: &mut Body, byte: u8| {
+            body.emit([LocalGet(out), I32Const(byte as i32), I32Store8(0), LocalGet(out), I32Const(1), I32Add, LocalSet(out)]);
+        };
+        let put_zeros = |body: &mut Body, count: &[Instr]| {
+            body.emit(count.iter().copied());
+            body.emit([LocalSet(index), Block, Loop, LocalGet(index), I32Const(0), I32LeS, BrIf(1)]);
+            body.emit([LocalGet(out), I32Const(b'0' as i32), I32Store8(0), LocalGet(out), I32Const(1), I32Add, LocalSet(out)]);
+            body.emit([LocalGet(index), I32Const(1), I32Sub, LocalSet(index), Br(0), End, End]);
+        };
+        let put_digits = |body: &mut Body, from: &[Instr], to: &[Instr]| {
+            body.emit([LocalGet(out), I32Const(DIGITS)]);
+            body.emit(from.iter().copied());
+            body.emit([I32Add]);
+            body.emit(to.iter().copied());
+            body.emit(from.iter().copied());
+            body.emit([I32Sub, LocalTee(index), MemoryCopy, LocalGet(out), LocalGet(index), I32Add, LocalSet(out)]);
+        };
+
+        body.emit([I32Const(SCRATCH), LocalSet(out), LocalGet(negative), If]);
+        put_byte(&mut body, b'-');
+        body.emit([End, LocalGet(candidate_exponent), I32Const(0), I32LtS, If]);
+        put_byte(&mut body, b'0');
+        put_byte(&mut body, b'.');
+        put_zeros(&mut body, &[I32Const(-1), LocalGet(candidate_exponent), I32Sub]);
+        put_digits(&mut body, &[I32Const(0)], &[LocalGet(count)]);
+        body.emit([Else, LocalGet(candidate_exponent), I32Const(1), I32Add, LocalSet(integer_digits)]);
+        body.emit([LocalGet(count), LocalGet(integer_digits), I32LeU, If]);
+        put_digits(&mut body, &[I32Const(0)], &[LocalGet(count)]);
+        put_zeros(&mut body, &[LocalGet(integer_digits), LocalGet(count), I32Sub]);
+        body.emit([Else]);
+        put_digits(&mut body, &[I32Const(0)], &[LocalGet(integer_digits)]);
+        put_byte(&mut body, b'.');
+        put_digits(&mut body, &[LocalGet(integer_digits)], &[LocalGet(count)]);
+        body.emit([End, End]);
+
+        body.emit([LocalGet(out), I32Const(SCRATCH), I32Sub, LocalTee(count), self.call(Helper::StrAlloc), LocalTee(result)]);
+        body.emit([I32Const(4), I32Add, I32Const(SCRATCH), LocalGet(count), MemoryCopy, LocalGet(result), Return]);
+        body
+    }
+
+    fn pow_int(&mut self) -> Body {
+        // Exponentiation by squaring with wrapping multiplication
+        let mut body = Body::new(2);
+        let (result, exponent) = (body.local(ValType::I64), body.local(ValType::I32));
+        body.emit([LocalGet(1), I64Const(0), I64LtS, If]);
+        self.fail(&mut body, "pow with a negative exponent is not supported on the wasm32-wasi target");
+        body.emit([End, I64Const(1), LocalSet(result), LocalGet(1), I32WrapI64, LocalSet(exponent)]);
+        body.emit([Block, Loop, LocalGet(exponent), I32Eqz, BrIf(1)]);
+        body.emit([LocalGet(exponent), I32Const(1), I32And, If, LocalGet(result), LocalGet(0), I64Mul, LocalSet(result), End]);
+        body.emit([LocalGet(0), LocalGet(0), I64Mul, LocalSet(0), LocalGet(exponent), I32Const(1), I32ShrU, LocalSet(exponent)]);
+        body.emit([Br(0), End, End, LocalGet(result), Return]);
+        body
+    }
+
+    fn array_reserve(&mut self) -> Body {
+        // Append an element slot, growing the storage by doubling; returns the slot's address
+        let mut body = Body::new(1);
+        let (len, capacity, items) = (body.local(ValType::I32), body.local(ValType::I32), body.local(ValType::I32));
+        body.emit([LocalGet(0), I32Load(0), LocalTee(len), LocalGet(0), I32Load(4), LocalTee(capacity), I32Eq, If]);
+        body.emit([I32Const(4), LocalGet(capacity), I32Const(2), I32Mul, LocalTee(capacity), LocalGet(capacity), I32Const(4), I32LtU, Select]);
+        body.emit([LocalSet(capacity), LocalGet(capacity), I32Const(8), I32Mul, self.call(Helper::Alloc), LocalTee(items)]);
+        body.emit([LocalGet(0), I32Load(8), LocalGet(len), I32Const(8), I32Mul, MemoryCopy]);
+        body.emit([LocalGet(0), LocalGet(items), I32Store(8), LocalGet(0), LocalGet(capacity), I32Store(4), End]);
+        body.emit([LocalGet(0), LocalGet(len), I32Const(1), I32Add, I32Store(0)]);
+        body.emit([LocalGet(0), I32Load(8), LocalGet(len), I32Const(8), I32Mul, I32Add, Return]);
+        body
+    }
+
+    fn array_slice(&mut self) -> Body {
+        // Negative bounds count as 0 and the end is clamped to the length, as in the VM
+        let mut body = Body::new(3);
+        let (len, count, result) = (body.local(ValType::I64), body.local(ValType::I32), body.local(ValType::I32));
+        body.emit([LocalGet(0), I32Load(0), I64ExtendI32U, LocalSet(len)]);
+        body.emit([LocalGet(1), I64Const(0), LocalGet(1), I64Const(0), I64GtS, Select, LocalSet(1)]);
+        body.emit([LocalGet(2), I64Const(0), LocalGet(2), I64Const(0), I64GtS, Select, LocalSet(2)]);
+        body.emit([LocalGet(2), LocalGet(len), LocalGet(2), LocalGet(len), I64LtS, Select, LocalSet(2)]);
+        body.emit([LocalGet(1), LocalGet(2), I64LeS, LocalGet(1), LocalGet(len), I64LtS, I32And, If]);
+        body.emit([LocalGet(2), LocalGet(1), I64Sub, I32WrapI64, LocalSet(count), End]);
+        body.emit([LocalGet(count), self.call(Helper::ArrayNew), LocalTee(result), I32Load(8)]);
+        body.emit([LocalGet(0), I32Load(8), LocalGet(1), I32WrapI64, I32Const(8), I32Mul, I32Add]);
+        body.emit([LocalGet(count), I32Const(8), I32Mul, MemoryCopy]);
+        body.emit([LocalGet(result), LocalGet(count), I32Store(0), LocalGet(result), Return]);
+        body
+    }
+
+    fn sort(&mut self, element: &Type) -> Body {
+        // A bottom-up merge sort, stable like Rust's `sort_by`; it takes an
+        // element from the right run only when it is strictly smaller
+        let mut body = Body::new(1);
+        let i32_local = |body: &mut Body| body.local(ValType::I32);
+        let (len, source, target, width, start, middle, end, left, right, out) = (
+            i32_local(&mut body),
+            i32_local(&mut body),
+            i32_local(&mut body),
+            i32_local(&mut body),
+            i32_local(&mut body),
+            i32_local(&mut body),
+            i32_local(&mut body),
+            i32_local(&mut body),
+            i32_local(&mut body),
+            i32_local(&mut body),
+        );
+        let items = body.local(ValType::I32);
+        let slot = |index: u32| [LocalGet(source), LocalGet(index), I32Const(8), I32Mul, I32Add];
+        body.emit([LocalGet(0), I32Load(0), LocalTee(len), I32Const(2), I32LtU, If, LocalGet(0), Return, End]);
+        body.emit([LocalGet(0), I32Load(8), LocalTee(items), LocalSet(source)]);
+        body.emit([LocalGet(len), I32Const(8), I32Mul, self.call(Helper::Alloc), LocalSet(target), I32Const(1), LocalSet(width)]);
+        body.emit([Block, Loop, LocalGet(width), LocalGet(len), I32GeU, BrIf(1), I32Const(0), LocalSet(start)]);
+        body.emit([Block, Loop, LocalGet(start), LocalGet(len), I32GeU, BrIf(1)]);
+        let min_len = |body: &mut Body, value: &[Instr], into: u32| {
+            body.emit(value.iter().copied());
+            body.emit([LocalSet(into), LocalGet(into), LocalGet(len), LocalGet(into), LocalGet(len), I32LtU, Select, LocalSet(into)]);
+        };
+        min_len(&mut body, &[LocalGet(start), LocalGet(width), I32Add], middle);
+        min_len(&mut body, &[LocalGet(middle), LocalGet(width), I32Add], end);
+        body.emit([LocalGet(start), LocalTee(left), LocalSet(out), LocalGet(middle), LocalSet(right)]);
+        body.emit([Block, Loop, LocalGet(left), LocalGet(middle), I32GeU, LocalGet(right), LocalGet(end), I32GeU, I32Or, BrIf(1)]);
+        // right < left?
+        body.emit(slot(right));
+        Runtime::load(&mut body, element, 0);
+        body.emit(slot(left));
+        Runtime::load(&mut body, element, 0);
+        match element {
+            Type::Int => body.emit([I64LtS]),
+            Type::Float => body.emit([F64Lt]),
+            Type::Str => body.emit([self.call(Helper::StrCmp), I32Const(0), I32LtS]),
+            _ => body.emit([Drop, Drop, I32Const(0)]),
+        }
+        body.emit([If, LocalGet(target), LocalGet(out), I32Const(8), I32Mul, I32Add]);
+        body.emit(slot(right));
+        body.emit([I64Load(0), I64Store(0), LocalGet(right), I32Const(1), I32Add, LocalSet(right)]);
+        body.emit([Else, LocalGet(target), LocalGet(out), I32Const(8), I32Mul, I32Add]);
+        body.emit(slot(left));
+        body.emit([I64Load(0), I64Store(0), LocalGet(left), I32Const(1), I32Add, LocalSet(left), End]);
+        body.emit([LocalGet(out), I32Const(1), I32Add, LocalSet(out), Br(0), End, End]);
+        // Copy whichever run has elements left
+        let rest = |body: &mut Body, from: u32, to: u32| {
+            body.emit([LocalGet(target), LocalGet(out), I32Const(8), I32Mul, I32Add]);
+            body.emit(slot(from));
+            body.emit([LocalGet(to), LocalGet(from), I32Sub, I32Const(8), I32Mul, MemoryCopy]);
+            body.emit([LocalGet(out), LocalGet(to), LocalGet(from), I32Sub, I32Add, LocalSet(out)]);
+        };
+        rest(&mut body, left, middle);
+        rest(&mut body, right, end);
+        body.emit([LocalGet(start), LocalGet(width), I32Const(2), I32Mul, I32Add, LocalSet(start), Br(0), End, End]);
+        body.emit([LocalGet(source), LocalGet(target), LocalSet(source), LocalSet(target)]);
+        body.emit([LocalGet(width), I32Const(2), I32Mul, LocalSet(width), Br(0), End, End]);
+        body.emit([LocalGet(source), LocalGet(items), I32Ne, If]);
+        body.emit([LocalGet(items), LocalGet(source), LocalGet(len), I32Const(8), I32Mul, MemoryCopy, End]);
+        body.emit([LocalGet(0), Return]);
+        body
+    }
+
+    fn join(&mut self, element: &Type) -> Body {
+        // Format every element first, so the result is allocated once
+        let mut body = Body::new(2);
+        let (len, parts, index, total, result, out, part) = (
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+            body.local(ValType::I32),
+        );
+        let empty = self.string("");
+        body.emit([LocalGet(0), I32Load(0), LocalTee(len), I32Eqz, If, I32Const(empty), Return, End]);
+        body.emit([LocalGet(len), I32Const(4), I32Mul, self.call(Helper::Alloc), LocalSet(parts)]);
+        body.emit([LocalGet(len), I32Const(1), I32Sub, LocalGet(1), I32Load(0), I32Mul, LocalSet(total)]);
+        body.emit([Block, Loop, LocalGet(index), LocalGet(len), I32GeU, BrIf(1)]);
+        body.emit([LocalGet(parts), LocalGet(index), I32Const(4), I32Mul, I32Add]);
+        body.emit([LocalGet(0), I32Load(8), LocalGet(index), I32Const(8), I32Mul, I32Add]);
+        Runtime::load(&mut body, element, 0);
+        self.stringify(&mut body, element);
+        body.emit([LocalTee(part), I32Store(0), LocalGet(total), LocalGet(part), I32Load(0), I32Add, LocalSet(total)]);
+        body.emit([LocalGet(index), I32Const(1), I32Add, LocalSet(index), Br(0), End, End]);
+        body.emit([LocalGet(total), self.call(Helper::StrAlloc), LocalTee(result), I32Const(4), I32Add, LocalSet(out)]);
+        body.emit([I32Const(0), LocalSet(index), Block, Loop, LocalGet(index), LocalGet(len), I32GeU, BrIf(1)]);
+        body.emit([LocalGet(index), If, LocalGet(out), LocalGet(1), I32Const(4), I32Add, LocalGet(1), I32Load(0), MemoryCopy]);
+        body.emit([LocalGet(out), LocalGet(1), I32Load(0), I32Add, LocalSet(out), End]);
+        body.emit([LocalGet(parts), LocalGet(index), I32Const(4), I32Mul, I32Add, I32Load(0), LocalSet(part)]);
+        body.emit([LocalGet(out), LocalGet(part), I32Const(4), I32Add, LocalGet(part), I32Load(0), MemoryCopy]);
+        body.emit([LocalGet(out), LocalGet(part), I32Load(0), I32Add, LocalSet(out)]);
+        body.emit([LocalGet(index), I32Const(1), I32Add, LocalSet(index), Br(0), End, End, LocalGet(result), Return]);
+        body
+    }
+
+    fn deep_eq(&mut self, array: &Type) -> Body {
+        let element = array.element();
+        let mut body = Body::new(2);
+        let index = body.local(ValType::I32);
+        let (a, b) = (body.local(element.val_type()), body.local(element.val_type()));
+        body.emit([LocalGet(0), LocalGet(1), I32Eq, If, I32Const(1), Return, End]);
+        body.emit([LocalGet(0), I32Load(0), LocalGet(1), I32Load(0), I32Ne, If, I32Const(0), Return, End]);
+        body.emit([Block, Loop, LocalGet(index), LocalGet(0), I32Load(0), I32GeU, BrIf(1)]);
+        for (array, local) in [(0, a), (1, b)] {
+            body.emit([LocalGet(array), I32Load(8), LocalGet(index), I32Const(8), I32Mul, I32Add]);
+            Runtime::load(&mut body, element, 0);
+            body.emit([LocalSet(local)]);
+        }
+        self.equal(&mut body, (a, element), (b, element), true);
+        body.emit([I32Eqz, If, I32Const(0), Return, End]);
+        body.emit([LocalGet(index), I32Const(1), I32Add, LocalSet(index), Br(0), End, End, I32Const(1), Return]);
+        body
+    }
+}
+
+fn mem_eq() -> Body {
+    let mut body = Body::new(3);
+    let index = body.local(ValType::I32);
+    body.emit([Block, Loop, LocalGet(index), LocalGet(2), I32GeU, BrIf(1)]);
+    body.emit([LocalGet(0), LocalGet(index), I32Add, I32Load8U(0), LocalGet(1), LocalGet(index), I32Add, I32Load8U(0)]);
+    body.emit([I32Ne, If, I32Const(0), Return, End]);
+    body.emit([LocalGet(index), I32Const(1), I32Add, LocalSet(index), Br(0), End, End, I32Const(1), Return]);
+    body
+}
+
+fn array_reverse() -> Body {
+    let mut body = Body::new(1);
+    let (low, high, swap) = (body.local(ValType::I32), body.local(ValType::I32), body.local(ValType::I64));
+    body.emit([LocalGet(0), I32Load(8), LocalTee(low), LocalGet(0), I32Load(0), I32Const(8), I32Mul, I32Add, I32Const(8), I32Sub, LocalSet(high)]);
+    body.emit([Block, Loop, LocalGet(low), LocalGet(high), I32GeS, BrIf(1)]);
+    body.emit([LocalGet(low), I64Load(0), LocalSet(swap), LocalGet(low), LocalGet(high), I64Load(0), I64Store(0)]);
+    body.emit([LocalGet(high), LocalGet(swap), I64Store(0)]);
+    body.emit([LocalGet(low), I32Const(8), I32Add, LocalSet(low), LocalGet(high), I32Const(8), I32Sub, LocalSet(high), Br(0), End, End]);
+    body.emit([LocalGet(0), Return]);
+    body
+}
+
+fn float_mod() -> Body {
+    // fmod by repeated subtraction of the divisor scaled by powers of two;
+    // every step is exact, so the result matches Rust's `%`
+    let mut body = Body::new(2);
+    let (rest, divisor, step) = (body.local(ValType::F64), body.local(ValType::F64), body.local(ValType::F64));
+    body.emit([LocalGet(0), LocalGet(0), F64Ne, LocalGet(1), LocalGet(1), F64Ne, I32Or]);
+    body.emit([LocalGet(0), F64Abs, F64Const(f64::INFINITY), F64Eq, I32Or, If, F64Const(f64::NAN), Return, End]);
+    body.emit([LocalGet(0), F64Abs, LocalTee(rest), LocalGet(1), F64Abs, LocalTee(divisor), F64Lt]);
+    body.emit([LocalGet(divisor), F64Const(f64::INFINITY), F64Eq, I32Or, If, LocalGet(0), Return, End]);
+    body.emit([LocalGet(divisor), LocalSet(step)]);
+    body.emit([Block, Loop, LocalGet(step), F64Const(2.0), F64Mul, LocalGet(rest), F64Gt, BrIf(1)]);
+    body.emit([LocalGet(step), F64Const(2.0), F64Mul, LocalSet(step), Br(0), End, End]);
+    body.emit([Block, Loop, LocalGet(step), LocalGet(divisor), F64Lt, BrIf(1)]);
+    body.emit([LocalGet(rest), LocalGet(step), F64Ge, If, LocalGet(rest), LocalGet(step), F64Sub, LocalSet(rest), End]);
+    body.emit([LocalGet(step), F64Const(0.5), F64Mul, LocalSet(step), Br(0), End, End]);
+    body.emit([LocalGet(rest), LocalGet(0), F64Copysign, Return]);
+    body
+}
@@ -0,0 +1,631 @@
+// Static types for the WebAssembly backend
+//
+// WebAssembly locals and parameters have fixed machine types, so every IR
+// temp, variable, parameter and return value gets one neksis type, found by
+// unification over the whole program. Operations whose result depends on
+// their operands' types wait until those are known and are revisited until
+// nothing changes. Operand types the VM would reject at run time are not a
+// compile error: the operation is compiled to raise the VM's error. Values
+// that need two different types (an Int variable later assigned a Float, an
+// array holding both Strings and Ints) are.
+
+use crate::error::CompilerError;
+use crate::ir::{BinOp, Const, Function, Op, Terminator, UnOp};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use super::encoder::ValType;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Str,
+    Null,
+    Array(Box<Type>),
+}
+
+impl Type {
+    pub fn val_type(&self) -> ValType {
+        match self {
+            Type::Int => ValType::I64,
+            Type::Float => ValType::F64,
+            Type::Bool | Type::Str | Type::Null | Type::Array(_) => ValType::I32,
+        }
+    }
+
+    pub fn head(&self) -> Head {
+        match self {
+            Type::Int => Head::Int,
+            Type::Float => Head::Float,
+            Type::Bool => Head::Bool,
+            Type::Str => Head::Str,
+            Type::Null => Head::Null,
+            Type::Array(_) => Head::Array,
+        }
+    }
+
+    pub fn element(&self) -> &Type {
+        match self {
+            Type::Array(element) => element,
+            other => unreachable!("{} has no elements", other),
+        }
+    }
+
+    /// What `typeof` returns for values of this type
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+            Type::Str => "string",
+            Type::Null => "null",
+            Type::Array(_) => "array",
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Float => write!(f, "Float"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Str => write!(f, "String"),
+            Type::Null => write!(f, "Null"),
+            Type::Array(element) => write!(f, "Array<{}>", element),
+        }
+    }
+}
+
+/// The outermost constructor of a type, which is all most rules look at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Head {
+    Int,
+    Float,
+    Bool,
+    Str,
+    Null,
+    Array,
+}
+
+impl Head {
+    fn is_number(self) -> bool {
+        matches!(self, Head::Int | Head::Float)
+    }
+}
+
+/// The type of an operation's result
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    Is(Type),
+    /// The type of argument `n`
+    SameAs(usize),
+    /// The element type of the array in argument `n`
+    ElementOf(usize),
+    /// The operation raises this error when it runs
+    Fails(&'static str),
+}
+
+const ARITHMETIC_ERROR: &str = "Cannot perform arithmetic on non-numeric value";
+
+pub fn binary_rule(op: BinOp, left: Head, right: Head) -> Rule {
+    use Head::*;
+    match op {
+        BinOp::Add => match (left, right) {
+            (Int, Int) => Rule::Is(Type::Int),
+            (Str, Str) | (Str, Int) | (Int, Str) | (Str, Float) | (Float, Str) => Rule::Is(Type::Str),
+            (a, b) if a.is_number() && b.is_number() => Rule::Is(Type::Float),
+            _ => Rule::Fails(ARITHMETIC_ERROR),
+        },
+        BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod => match (left, right) {
+            (Int, Int) => Rule::Is(Type::Int),
+            (a, b) if a.is_number() && b.is_number() => Rule::Is(Type::Float),
+            _ => Rule::Fails(ARITHMETIC_ERROR),
+        },
+        BinOp::Eq | BinOp::Ne | BinOp::And | BinOp::Or => Rule::Is(Type::Bool),
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => match (left, right) {
+            (Str, Str) | (Bool, Bool) => Rule::Is(Type::Bool),
+            (a, b) if a.is_number() && b.is_number() => Rule::Is(Type::Bool),
+            _ => Rule::Fails("Cannot compare values"),
+        },
+    }
+}
+
+pub fn unary_rule(op: UnOp, operand: Head) -> Rule {
+    match (op, operand) {
+        (UnOp::Neg, Head::Int | Head::Float) => Rule::SameAs(0),
+        (UnOp::Neg, _) => Rule::Fails("Cannot negate non-numeric value"),
+        (UnOp::Not, _) => Rule::Is(Type::Bool),
+        (UnOp::ToString, _) => Rule::Is(Type::Str),
+    }
+}
+
+/// The rule for builtin `name`, or an error for builtins this target cannot compile
+pub fn builtin_rule(name: &str, args: &[Head]) -> Result<Rule, String> {
+    use Head::*;
+    let numbers = args.iter().all(|arg| arg.is_number());
+    Ok(match (name, args) {
+        ("print" | "println" | "eprint" | "eprintln" | "assert" | "assert_eq" | "assert_ne", _) => Rule::Is(Type::Null),
+        ("abs", [Int | Float]) => Rule::SameAs(0),
+        ("abs", _) => Rule::Fails("abs expects a numeric value"),
+        ("sqrt", [Int | Float]) => Rule::Is(Type::Float),
+        ("sqrt", _) => Rule::Fails("sqrt expects a numeric value"),
+        ("floor" | "ceil" | "round", [Int | Float]) => Rule::Is(Type::Int),
+        ("floor", _) => Rule::Fails("floor expects a numeric value"),
+        ("ceil", _) => Rule::Fails("ceil expects a numeric value"),
+        ("round", _) => Rule::Fails("round expects a numeric value"),
+        ("pow", [Int, Int]) => Rule::Is(Type::Int),
+        ("pow", _) if numbers => return Err("pow of a Float".to_string()),
+        ("pow", _) => Rule::Fails("pow expects numeric values"),
+        ("min" | "max", [Int, Int]) => Rule::Is(Type::Int),
+        ("min" | "max", _) if numbers => Rule::Is(Type::Float),
+        ("min", _) => Rule::Fails("min expects numeric values"),
+        ("max", _) => Rule::Fails("max expects numeric values"),
+        ("len", [Str | Array]) => Rule::Is(Type::Int),
+        ("len", _) => Rule::Fails("len expects a string, array or object"),
+        ("substring", [Str, Int, Int]) => Rule::Is(Type::Str),
+        ("substring", _) => Rule::Fails("substring expects string, int, int"),
+        ("concat", [Str, Str]) => Rule::Is(Type::Str),
+        ("concat", _) => Rule::Fails("concat expects string arguments"),
+        ("contains" | "starts_with" | "ends_with", [Str, Str]) => Rule::Is(Type::Bool),
+        ("contains", _) => Rule::Fails("contains expects string arguments"),
+        ("starts_with", _) => Rule::Fails("starts_with expects string arguments"),
+        ("ends_with", _) => Rule::Fails("ends_with expects string arguments"),
+        ("to_upper" | "to_lower" | "trim", [Str]) => Rule::Is(Type::Str),
+        ("to_upper", _) => Rule::Fails("to_upper expects a string"),
+        ("to_lower", _) => Rule::Fails("to_lower expects a string"),
+        ("trim", _) => Rule::Fails("trim expects a string"),
+        ("split", [Str, Str]) => Rule::Is(Type::Array(Box::new(Type::Str))),
+        ("split", _) => Rule::Fails("split expects string arguments"),
+        ("join", [Array, Str]) => Rule::Is(Type::Str),
+        ("join", _) => Rule::Fails("join expects array and string arguments"),
+        ("typeof", _) => Rule::Is(Type::Str),
+        ("array_push", [Array, _]) | ("array_reverse" | "array_sort", [Array]) => Rule::SameAs(0),
+        ("array_push", _) => Rule::Fails("Invalid arguments for array_push"),
+        ("array_reverse", _) => Rule::Fails("Invalid argument for array_reverse"),
+        ("array_sort", _) => Rule::Fails("Invalid argument for array_sort"),
+        ("array_slice", [Array, Int, Int]) => Rule::SameAs(0),
+        ("array_slice", [Array, _, _]) => Rule::Fails("Array slice indices must be integers"),
+        ("array_slice", _) => Rule::Fails("Invalid arguments for array_slice"),
+        (other, _) => return Err(format!("the builtin '{}'", other)),
+    })
+}
+
+pub fn index_rule(array: Head, index: Head) -> Rule {
+    match (array, index) {
+        (Head::Array, Head::Int) => Rule::ElementOf(0),
+        (Head::Array, _) => Rule::Fails("Invalid array index"),
+        _ => Rule::Fails("Invalid array access"),
+    }
+}
+
+/// The builtins reached through `Op::Call` rather than `Op::Builtin`, with their arities
+pub fn called_builtin(name: &str, args: usize) -> bool {
+    matches!((name, args), ("eprint" | "eprintln", 1) | ("assert", 1 | 2) | ("assert_eq" | "assert_ne", 2))
+}
+
+/// The blocks of `function` reachable from its entry
+pub fn reachable_blocks(function: &Function) -> Vec<bool> {
+    let mut reachable = vec![false; function.blocks.len()];
+    let mut work = vec![0];
+    while let Some(block) = work.pop() {
+        if !std::mem::replace(&mut reachable[block], true) {
+            work.extend(function.blocks[block].term.successors());
+        }
+    }
+    reachable
+}
+
+// The source line in effect where each block starts, from the last `Line`
+// marker on the way there; 0 where none is known
+fn entry_lines(function: &Function) -> Vec<usize> {
+    let mut lines = vec![0; function.blocks.len()];
+    let mut seen = vec![false; function.blocks.len()];
+    let mut work = VecDeque::from([0]);
+    seen[0] = true;
+    while let Some(block) = work.pop_front() {
+        let mut line = lines[block];
+        for inst in &function.blocks[block].insts {
+            if let Op::Line(marker) = inst.op {
+                line = marker;
+            }
+        }
+        for successor in function.blocks[block].term.successors() {
+            if !std::mem::replace(&mut seen[successor], true) {
+                lines[successor] = line;
+                work.push_back(successor);
+            }
+        }
+    }
+    lines
+}
+
+// How messages refer to variable `name`. Lowering and inlining add hidden
+// variables, which are described by the code they come from instead.
+fn describe_variable(name: &str, line: usize, context: &str) -> String {
+    let place = match line {
+        0 => format!("in {}", context),
+        line => format!("on line {} in {}", line, context),
+    };
+    if name.starts_with("$match") {
+        format!("the match expression {}", place)
+    } else if name.starts_with("$ret$") {
+        format!("the result of a call inlined {}", place)
+    } else if let Some((original, _)) = name.split_once('$') {
+        // Inlined variables are renamed `name$N`
+        format!("variable '{}' of a call inlined {}", original, place)
+    } else {
+        format!("variable '{}' in {}", name, context)
+    }
+}
+
+/// The types found for one function or the top level
+#[derive(Debug, Clone)]
+pub struct UnitTypes {
+    pub params: Vec<Type>,
+    pub result: Type,
+    pub temps: Vec<Type>,
+    pub variables: HashMap<String, Type>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Term {
+    Unknown,
+    Int,
+    Float,
+    Bool,
+    Str,
+    Null,
+    Array(usize),
+}
+
+/// Union-find over type variables
+#[derive(Default)]
+struct Table {
+    terms: Vec<Term>,
+    parent: Vec<usize>,
+    /// Bumped whenever an unknown gets bound, to detect the fixed point
+    changes: usize,
+}
+
+impl Table {
+    fn fresh(&mut self, term: Term) -> usize {
+        self.terms.push(term);
+        self.parent.push(self.parent.len());
+        self.parent.len() - 1
+    }
+
+    fn of(&mut self, ty: &Type) -> usize {
+        let term = match ty {
+            Type::Int => Term::Int,
+            Type::Float => Term::Float,
+            Type::Bool => Term::Bool,
+            Type::Str => Term::Str,
+            Type::Null => Term::Null,
+            Type::Array(element) => Term::Array(self.of(element)),
+        };
+        self.fresh(term)
+    }
+
+    fn find(&mut self, var: usize) -> usize {
+        let mut root = var;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut current = var;
+        while self.parent[current] != root {
+            current = std::mem::replace(&mut self.parent[current], root);
+        }
+        root
+    }
+
+    fn term(&mut self, var: usize) -> Term {
+        let root = self.find(var);
+        self.terms[root]
+    }
+
+    fn head(&mut self, var: usize) -> Option<Head> {
+        Some(match self.term(var) {
+            Term::Unknown => return None,
+            Term::Int => Head::Int,
+            Term::Float => Head::Float,
+            Term::Bool => Head::Bool,
+            Term::Str => Head::Str,
+            Term::Null => Head::Null,
+            Term::Array(_) => Head::Array,
+        })
+    }
+
+    fn occurs(&mut self, var: usize, inside: usize) -> bool {
+        let inside = self.find(inside);
+        if inside == var {
+            return true;
+        }
+        match self.terms[inside] {
+            Term::Array(element) => self.occurs(var, element),
+            _ => false,
+        }
+    }
+
+    fn unify(&mut self, a: usize, b: usize) -> Result<(), ()> {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return Ok(());
+        }
+        match (self.terms[a], self.terms[b]) {
+            (Term::Unknown, _) if !self.occurs(a, b) => {
+                self.parent[a] = b;
+                self.changes += 1;
+            }
+            (_, Term::Unknown) if !self.occurs(b, a) => {
+                self.parent[b] = a;
+                self.changes += 1;
+            }
+            (Term::Array(x), Term::Array(y)) => {
+                self.parent[a] = b;
+                self.unify(x, y)?;
+            }
+            (x, y) if x == y && x != Term::Unknown => self.parent[a] = b,
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    fn resolve(&mut self, var: usize) -> Type {
+        match self.term(var) {
+            // Nothing ever constrains it, e.g. the elements of an array that stays empty
+            Term::Unknown | Term::Null => Type::Null,
+            Term::Int => Type::Int,
+            Term::Float => Type::Float,
+            Term::Bool => Type::Bool,
+            Term::Str => Type::Str,
+            Term::Array(element) => Type::Array(Box::new(self.resolve(element))),
+        }
+    }
+
+    fn describe(&mut self, var: usize) -> String {
+        match self.term(var) {
+            Term::Unknown => "?".to_string(),
+            Term::Array(element) => format!("Array<{}>", self.describe(element)),
+            _ => self.resolve(var).to_string(),
+        }
+    }
+}
+
+struct UnitVars {
+    name: String,
+    params: Vec<usize>,
+    result: usize,
+    temps: Vec<usize>,
+    variables: HashMap<String, usize>,
+    reachable: Vec<bool>,
+    lines: Vec<usize>,
+}
+
+struct Inference<'a> {
+    table: Table,
+    units: Vec<UnitVars>,
+    functions: &'a [&'a Function],
+    callees: &'a HashMap<String, usize>,
+}
+
+/// Type `functions`, where `callees` maps each function name to its index.
+/// Only reachable code is looked at.
+pub fn infer(functions: &[&Function], callees: &HashMap<String, usize>) -> Result<Vec<UnitTypes>, CompilerError> {
+    let mut table = Table::default();
+    let mut units = Vec::new();
+    for function in functions {
+        let name = match &function.name {
+            Some(name) => format!("function '{}'", name),
+            None => "the top level".to_string(),
+        };
+        let mut variables = HashMap::new();
+        let params = function
+            .params
+            .iter()
+            .map(|param| *variables.entry(param.clone()).or_insert_with(|| table.fresh(Term::Unknown)))
+            .collect();
+        for block in &function.blocks {
+            for inst in &block.insts {
+                if let Op::Store(name, _) = &inst.op {
+                    variables.entry(name.clone()).or_insert_with(|| table.fresh(Term::Unknown));
+                }
+            }
+        }
+        let result = table.fresh(if function.name.is_some() { Term::Unknown } else { Term::Null });
+        let temps = (0..function.temp_count).map(|_| table.fresh(Term::Unknown)).collect();
+        units.push(UnitVars {
+            name,
+            params,
+            result,
+            temps,
+            variables,
+            reachable: reachable_blocks(function),
+            lines: entry_lines(function),
+        });
+    }
+
+    let mut inference = Inference { table, units, functions, callees };
+    loop {
+        let before = inference.table.changes;
+        for unit in 0..functions.len() {
+            inference.unit(unit)?;
+        }
+        if inference.table.changes == before {
+            break;
+        }
+    }
+
+    let Inference { mut table, units, .. } = inference;
+    Ok(units
+        .into_iter()
+        .map(|unit| UnitTypes {
+            params: unit.params.iter().map(|&var| table.resolve(var)).collect(),
+            result: table.resolve(unit.result),
+            temps: unit.temps.iter().map(|&var| table.resolve(var)).collect(),
+            variables: unit.variables.iter().map(|(name, &var)| (name.clone(), table.resolve(var))).collect(),
+        })
+        .collect())
+}
+
+impl Inference<'_> {
+    fn unify(&mut self, a: usize, b: usize, what: impl FnOnce() -> String) -> Result<(), CompilerError> {
+        let (first, second) = (self.table.describe(a), self.table.describe(b));
+        self.table.unify(a, b).map_err(|_| {
+            CompilerError::codegen_error(
+                "wasm",
+                &format!(
+                    "{} would hold both {} and {} values; on the wasm32-wasi target every variable, parameter, return value and array keeps a single type",
+                    what(),
+                    first,
+                    second
+                ),
+            )
+        })
+    }
+
+    fn unit(&mut self, unit: usize) -> Result<(), CompilerError> {
+        let function = self.functions[unit];
+        let context = self.units[unit].name.clone();
+        for (block_id, block) in function.blocks.iter().enumerate() {
+            if !self.units[unit].reachable[block_id] {
+                continue;
+            }
+            let mut line = self.units[unit].lines[block_id];
+            for inst in &block.insts {
+                let dest = inst.dest.map(|dest| self.units[unit].temps[dest]);
+                let temp = |temp: usize| self.units[unit].temps[temp];
+                match &inst.op {
+                    Op::Const(value) => {
+                        let ty = const_type(value);
+                        let var = self.table.of(&ty);
+                        self.unify(dest.unwrap(), var, || format!("a constant in {}", context))?;
+                    }
+                    Op::Load(name) => {
+                        // Names that are never stored raise "Undefined variable" instead
+                        if let Some(&var) = self.units[unit].variables.get(name) {
+                            self.unify(dest.unwrap(), var, || describe_variable(name, line, &context))?;
+                        }
+                    }
+                    Op::Store(name, value) => {
+                        let var = self.units[unit].variables[name];
+                        let value = temp(*value);
+                        self.unify(var, value, || describe_variable(name, line, &context))?;
+                    }
+                    Op::Binary(op, left, right) => {
+                        let (left, right) = (temp(*left), temp(*right));
+                        if let (Some(l), Some(r)) = (self.table.head(left), self.table.head(right)) {
+                            self.apply(binary_rule(*op, l, r), dest, &[left, right], &context)?;
+                        }
+                    }
+                    Op::Unary(op, operand) => {
+                        let operand = temp(*operand);
+                        if let Some(head) = self.table.head(operand) {
+                            self.apply(unary_rule(*op, head), dest, &[operand], &context)?;
+                        }
+                    }
+                    Op::Builtin(builtin, args) => {
+                        let args: Vec<usize> = args.iter().map(|&arg| temp(arg)).collect();
+                        self.builtin(builtin.name, dest, &args, &context)?;
+                    }
+                    Op::Call(name, args) => {
+                        let args: Vec<usize> = args.iter().map(|&arg| temp(arg)).collect();
+                        match self.callees.get(name) {
+                            Some(&callee) if self.functions[callee].params.len() == args.len() => {
+                                for (index, &arg) in args.iter().enumerate() {
+                                    let param = self.units[callee].params[index];
+                                    let param_name = &self.functions[callee].params[index];
+                                    self.unify(param, arg, || format!("parameter '{}' of function '{}'", param_name, name))?;
+                                }
+                                if let Some(dest) = dest {
+                                    let result = self.units[callee].result;
+                                    self.unify(dest, result, || format!("the result of function '{}'", name))?;
+                                }
+                            }
+                            // Wrong arity fails at run time
+                            Some(_) => {}
+                            None if called_builtin(name, args.len()) => self.builtin(name, dest, &args, &context)?,
+                            None => {}
+                        }
+                    }
+                    Op::ArrayLiteral(elements) => {
+                        let dest = dest.unwrap();
+                        let element = match self.table.term(dest) {
+                            Term::Array(element) => element,
+                            _ => {
+                                let element = self.table.fresh(Term::Unknown);
+                                let array = self.table.fresh(Term::Array(element));
+                                self.unify(dest, array, || format!("an array in {}", context))?;
+                                element
+                            }
+                        };
+                        for value in elements {
+                            let var = self.table.of(&const_type(value));
+                            self.unify(element, var, || format!("an array literal in {}", context))?;
+                        }
+                    }
+                    Op::GetIndex(array, index) => {
+                        let (array, index) = (temp(*array), temp(*index));
+                        if let (Some(a), Some(i)) = (self.table.head(array), self.table.head(index)) {
+                            self.apply(index_rule(a, i), dest, &[array, index], &context)?;
+                        }
+                    }
+                    Op::Line(marker) => line = *marker,
+                }
+            }
+            if let Terminator::Return(value) = block.term {
+                let (result, value) = (self.units[unit].result, self.units[unit].temps[value]);
+                self.unify(result, value, || format!("the result of {}", context))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn builtin(&mut self, name: &str, dest: Option<usize>, args: &[usize], context: &str) -> Result<(), CompilerError> {
+        let mut heads = Vec::new();
+        for &arg in args {
+            match self.table.head(arg) {
+                Some(head) => heads.push(head),
+                None if matches!(name, "print" | "println" | "eprint" | "eprintln" | "typeof" | "assert" | "assert_eq" | "assert_ne") => {
+                    heads.push(Head::Null)
+                }
+                None => return Ok(()),
+            }
+        }
+        let rule = builtin_rule(name, &heads).map_err(|what| {
+            CompilerError::codegen_error("wasm", &format!("{} uses {}, which the wasm32-wasi target does not support", context, what))
+        })?;
+        if name == "array_push" && matches!(rule, Rule::SameAs(_)) {
+            let Term::Array(element) = self.table.term(args[0]) else { unreachable!("checked by the rule") };
+            self.unify(element, args[1], || format!("an array in {}", context))?;
+        }
+        self.apply(rule, dest, args, context)
+    }
+
+    fn apply(&mut self, rule: Rule, dest: Option<usize>, args: &[usize], context: &str) -> Result<(), CompilerError> {
+        let Some(dest) = dest else { return Ok(()) };
+        let result = match rule {
+            Rule::Is(ty) => self.table.of(&ty),
+            Rule::SameAs(arg) => args[arg],
+            Rule::ElementOf(arg) => match self.table.term(args[arg]) {
+                Term::Array(element) => element,
+                _ => unreachable!("checked by the rule"),
+            },
+            // The result is never produced
+            Rule::Fails(_) => return Ok(()),
+        };
+        self.unify(dest, result, || format!("a value in {}", context))
+    }
+}
+
+pub fn const_type(value: &Const) -> Type {
+    match value {
+        Const::Int(_) => Type::Int,
+        Const::Float(_) => Type::Float,
+        Const::Bool(_) => Type::Bool,
+        Const::String(_) => Type::Str,
+        Const::Null => Type::Null,
+    }
+}
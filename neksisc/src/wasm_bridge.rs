@@ -68,66 +68,29 @@ impl WasmBridge {
     pub fn convert_neksis_to_rust(&self, neksis_code: &str) -> Result<String, CompilerError> {
         let mut rust_code = String::new();
         rust_code.push_str("use wasm_bindgen::prelude::*;\n\n");
+        rust_code.push_str(&self.exported_rust(neksis_code)?);
+        Ok(rust_code)
+    }
 
-        // Convert basic Neksis syntax to Rust
-        let lines: Vec<&str> = neksis_code.lines().collect();
-        for line in lines {
-            let trimmed = line.trim();
-            if trimmed.is_empty() || trimmed.starts_with("//") {
-                continue;
-            }
+    /// Transpile a program as a library and export each of its functions to JavaScript
+    fn exported_rust(&self, neksis_code: &str) -> Result<String, CompilerError> {
+        let mut lexer = crate::lexer::Lexer::new(neksis_code, "<wasm>".to_string());
+        let tokens = lexer.tokenize()?;
+        let mut parser = crate::parser::Parser::new(tokens);
+        let program = parser.parse()?;
+        let library = crate::transpile::RustTranspiler::new().library(true).transpile(&program)?;
 
-            if trimmed.starts_with("fn ") {
-                // Convert function declaration
-                let func_def = self.convert_function_to_rust(trimmed);
-                rust_code.push_str(&format!("{}\n", func_def));
-            } else if trimmed.starts_with("let ") {
-                // Convert let declaration
-                let var_def = self.convert_variable_to_rust(trimmed);
-                rust_code.push_str(&format!("{}\n", var_def));
-            } else if trimmed.starts_with("print(") {
-                // Convert print statement
-                let print_stmt = self.convert_print_to_rust(trimmed);
-                rust_code.push_str(&format!("{}\n", print_stmt));
+        let mut rust_code = String::new();
+        for line in library.lines() {
+            if line.starts_with("pub fn ") {
+                rust_code.push_str("#[wasm_bindgen]\n");
             }
+            rust_code.push_str(line);
+            rust_code.push('\n');
         }
-
         Ok(rust_code)
     }
 
-    fn convert_function_to_rust(&self, neksis_func: &str) -> String {
-        // Convert "fn add(a: Int, b: Int) -> Int { a + b }" to Rust
-        let mut rust_func = neksis_func.to_string();
-        
-        // Replace fn with pub fn
-        rust_func = rust_func.replace("fn ", "pub fn ");
-        
-        // Replace type annotations
-        rust_func = rust_func.replace(": Int", ": i32");
-        rust_func = rust_func.replace(": Float", ": f64");
-        rust_func = rust_func.replace(": Bool", ": bool");
-        rust_func = rust_func.replace(": String", ": String");
-        rust_func = rust_func.replace(" -> Int", " -> i32");
-        rust_func = rust_func.replace(" -> Float", " -> f64");
-        rust_func = rust_func.replace(" -> Bool", " -> bool");
-        rust_func = rust_func.replace(" -> String", " -> String");
-        
-        // Add #[wasm_bindgen] attribute
-        let func_name = rust_func.split('(').next().unwrap_or("").replace("pub fn ", "");
-        format!("#[wasm_bindgen]\n{}", rust_func)
-    }
-
-    fn convert_variable_to_rust(&self, neksis_var: &str) -> String {
-        // Convert "let x = 42;" to "let x = 42;"
-        neksis_var.replace("let ", "let ")
-    }
-
-    fn convert_print_to_rust(&self, neksis_print: &str) -> String {
-        // Convert print statement to web_sys::console::log_1
-        let content = neksis_print.replace("print(", "").replace(");", "");
-        format!("web_sys::console::log_1(&wasm_bindgen::JsValue::from_str({}));", content)
-    }
-
     fn create_rust_project(&self, project_name: &str, rust_code: &str) -> Result<(), CompilerError> {
         // Create Cargo.toml
         let cargo_toml = format!(
@@ -241,17 +204,7 @@ if __name__ == "__main__":
         let mut bindings = String::new();
         bindings.push_str("use wasm_bindgen::prelude::*;\n");
         bindings.push_str("use web_sys::console;\n\n");
-
-        // Parse Neksis file to find functions
-        let lines: Vec<&str> = neksis_content.lines().collect();
-        for line in lines {
-            let trimmed = line.trim();
-            if trimmed.starts_with("fn ") {
-                // Extract function name and convert to Rust
-                let rust_func = self.convert_function_to_rust(trimmed);
-                bindings.push_str(&format!("{}\n", rust_func));
-            }
-        }
+        bindings.push_str(&self.exported_rust(&neksis_content)?);
 
         Ok(bindings)
     }
@@ -374,9 +327,8 @@ mod tests {
     #[test]
     fn test_neksis_to_rust_conversion() {
         let bridge = WasmBridge::new();
-        let neksis_code = "fn add(a: Int, b: Int) -> Int { a + b }";
+        let neksis_code = "fn add(a: Int, b: Int) -> Int { return a + b; }";
         let rust_code = bridge.convert_neksis_to_rust(neksis_code).unwrap();
-        assert!(rust_code.contains("pub fn add"));
-        assert!(rust_code.contains("i32"));
+        assert!(rust_code.contains("#[wasm_bindgen]\npub fn add(a: i64, b: i64) -> i64"));
     }
 } 
\ No newline at end of file
@@ -0,0 +1,145 @@
+/* Exercises the C API declared in include/neksis.h; see the Makefile. */
+
+#include <ctype.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "neksis.h"
+
+static int failures = 0;
+
+#define CHECK(condition)                                                        \
+    do {                                                                        \
+        if (!(condition)) {                                                     \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,    \
+                    #condition);                                                \
+            failures++;                                                         \
+        }                                                                       \
+    } while (0)
+
+static NeksisValue int_value(int64_t n) {
+    NeksisValue value = {.tag = NEKSIS_TYPE_INT, .data = {.int_value = n}};
+    return value;
+}
+
+static NeksisValue string_value(const char *s) {
+    NeksisValue value = {.tag = NEKSIS_TYPE_STRING, .data = {.string_value = s}};
+    return value;
+}
+
+/* sum(...) adds up its int arguments and counts its calls in user_data */
+static int sum(void *user_data, const NeksisValue *args, size_t argc, NeksisValue *result) {
+    int *calls = user_data;
+    int64_t total = 0;
+    (*calls)++;
+    for (size_t i = 0; i < argc; i++) {
+        if (args[i].tag != NEKSIS_TYPE_INT) {
+            *result = string_value("sum expects ints");
+            return NEKSIS_ERROR;
+        }
+        total += args[i].data.int_value;
+    }
+    *result = int_value(total);
+    return NEKSIS_OK;
+}
+
+/* shout(text) returns its argument in capitals */
+static int shout(void *user_data, const NeksisValue *args, size_t argc, NeksisValue *result) {
+    static char buffer[256];
+    (void)user_data;
+    if (argc != 1 || args[0].tag != NEKSIS_TYPE_STRING) {
+        return NEKSIS_ERROR;
+    }
+    snprintf(buffer, sizeof buffer, "%s!", args[0].data.string_value);
+    for (char *c = buffer; *c; c++) {
+        *c = (char)toupper((unsigned char)*c);
+    }
+    *result = string_value(buffer);
+    return NEKSIS_OK;
+}
+
+static int load_file(NeksisEngine *engine, const char *path) {
+    unsigned char data[65536];
+    FILE *file = fopen(path, "rb");
+    if (!file) {
+        perror(path);
+        return NEKSIS_ERROR;
+    }
+    size_t length = fread(data, 1, sizeof data, file);
+    fclose(file);
+    return neksis_load_bytecode(engine, data, length);
+}
+
+int main(int argc, char **argv) {
+    NeksisEngine *engine = neksis_engine_new();
+    NeksisValue result;
+    int calls = 0;
+
+    CHECK(neksis_last_error(engine) == NULL);
+    CHECK(neksis_register(engine, "sum", sum, &calls) == NEKSIS_OK);
+    CHECK(neksis_register(engine, "shout", shout, NULL) == NEKSIS_OK);
+    CHECK(neksis_load_source(engine,
+        "fn describe(n: Int, unit: String) -> String { return \"\" + sum(n, n, 2) + \" \" + unit; }\n"
+        "fn greet(name: String) -> String { return shout(\"hello \" + name); }\n"
+        "fn guarded() -> String {\n"
+        "    let message = \"\";\n"
+        "    try { sum(1, true); } catch (e) { message = e; }\n"
+        "    return message;\n"
+        "}\n") == NEKSIS_OK);
+
+    NeksisValue args[] = {int_value(20), string_value("apples")};
+    CHECK(neksis_call(engine, "describe", args, 2, &result) == NEKSIS_OK);
+    CHECK(result.tag == NEKSIS_TYPE_STRING && strcmp(result.data.string_value, "42 apples") == 0);
+    CHECK(calls == 1);
+
+    NeksisValue name = string_value("c");
+    CHECK(neksis_call(engine, "greet", &name, 1, &result) == NEKSIS_OK);
+    CHECK(result.tag == NEKSIS_TYPE_STRING && strcmp(result.data.string_value, "HELLO C!") == 0);
+
+    /* An error from a callback can be caught by the script */
+    CHECK(neksis_call(engine, "guarded", NULL, 0, &result) == NEKSIS_OK);
+    CHECK(result.tag == NEKSIS_TYPE_STRING && strcmp(result.data.string_value, "sum expects ints") == 0);
+
+    /* Failures leave a message behind */
+    CHECK(neksis_call(engine, "missing", NULL, 0, &result) == NEKSIS_ERROR);
+    CHECK(strcmp(neksis_last_error(engine), "Undefined function: missing") == 0);
+    CHECK(neksis_call(engine, "describe", args, 1, &result) == NEKSIS_ERROR);
+    CHECK(strcmp(neksis_last_error(engine), "Function describe expects 2 arguments, got 1") == 0);
+    CHECK(neksis_load_source(engine, "let = ;") == NEKSIS_ERROR);
+    CHECK(strcmp(neksis_last_error(engine), "Expected variable name") == 0);
+
+    /* Scripts cannot touch the host until it is allowed */
+    CHECK(neksis_load_source(engine,
+        "fn contents(path: String) -> String { return read_file(path); }") == NEKSIS_OK);
+    NeksisValue source_path = string_value("geometry.nx");
+    CHECK(neksis_call(engine, "contents", &source_path, 1, &result) == NEKSIS_ERROR);
+    CHECK(strcmp(neksis_last_error(engine),
+                 "PermissionDenied: read access to 'geometry.nx' (grant it with --allow-read)") == 0);
+    CHECK(neksis_allow(engine, "read", ".") == NEKSIS_OK);
+    CHECK(neksis_call(engine, "contents", &source_path, 1, &result) == NEKSIS_OK);
+    CHECK(result.tag == NEKSIS_TYPE_STRING && strncmp(result.data.string_value, "// Compiled", 11) == 0);
+    CHECK(neksis_allow(engine, "disk", NULL) == NEKSIS_ERROR);
+
+    /* A runaway script is stopped, and the engine stays usable */
+    NeksisLimits limits = {.max_instructions = 100000, .max_wall_time_ms = 1000};
+    CHECK(neksis_set_limits(engine, &limits) == NEKSIS_OK);
+    CHECK(neksis_load_source(engine, "let spins = 0;\nwhile true { spins = spins + 1; }\n") == NEKSIS_LIMIT_EXCEEDED);
+    CHECK(strcmp(neksis_last_error(engine), "InstructionLimitExceeded: ran more than 100000 instructions") == 0);
+    CHECK(neksis_call(engine, "describe", args, 2, &result) == NEKSIS_OK);
+    CHECK(neksis_set_limits(engine, NULL) == NEKSIS_OK);
+
+    if (argc > 1) {
+        NeksisValue sides[] = {{.tag = NEKSIS_TYPE_FLOAT, .data = {.float_value = 2.5}}, int_value(4)};
+        CHECK(load_file(engine, argv[1]) == NEKSIS_OK);
+        CHECK(neksis_call(engine, "area", sides, 2, &result) == NEKSIS_OK);
+        CHECK(result.tag == NEKSIS_TYPE_FLOAT && result.data.float_value == 10.0);
+    }
+
+    neksis_engine_free(engine);
+    if (failures) {
+        fprintf(stderr, "%d C API checks failed\n", failures);
+        return 1;
+    }
+    printf("All C API checks passed\n");
+    return 0;
+}